@@ -0,0 +1,59 @@
+// A demo that renders the preset scenes in a browser.
+// It exports plain functions instead of using `wasm-bindgen`, and `wasm_demo.html` calls them.
+// See the README for how to build and serve it.
+
+use std::sync::Mutex;
+
+use try_ray_tracing::geometry::Float;
+use try_ray_tracing::scenes::{self, SceneParams, SCENE_NAMES};
+use try_ray_tracing::{Filter, RenderSettings, Renderer};
+
+/// The RGBA pixels of the last rendered image, which the page reads from the linear memory.
+static IMAGE: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+
+/// Returns the number of the preset scenes, which can be passed to `render` as `scene_index`.
+#[no_mangle]
+pub extern "C" fn num_scenes() -> u32 {
+    SCENE_NAMES.len() as u32
+}
+
+/// Renders a preset scene and returns the pointer to its RGBA pixels,
+/// which stay valid until the next call, or the null pointer if the scene cannot be built.
+#[no_mangle]
+pub extern "C" fn render(
+    scene_index: u32,
+    width: u32,
+    height: u32,
+    num_samples_per_pixel: u32,
+    seed: u32,
+) -> *const u8 {
+    let (width, height) = (width as usize, height as usize);
+    let Some(name) = SCENE_NAMES.get(scene_index as usize) else {
+        return std::ptr::null();
+    };
+    let aspect_ratio = width as Float / height as Float;
+    let Some(scene) = scenes::by_name(name, aspect_ratio, &SceneParams::default()) else {
+        return std::ptr::null();
+    };
+    let renderer = Renderer::new(RenderSettings {
+        image_width: width,
+        image_height: height,
+        num_samples_per_pixel: num_samples_per_pixel as usize,
+        max_diffusion_depth: 50,
+        roulette: vec![],
+        num_threads: 0,
+        low_priority: false,
+        check_samples: false,
+        collect_stats: false,
+        path_guiding: false,
+        adaptive_samples: 0,
+        seed: Some(seed as u64),
+        filter: Filter::Box,
+        filter_sampling: false,
+        show_progress: true,
+    });
+    let film = renderer.render(&scene);
+    let mut image = IMAGE.lock().unwrap();
+    *image = film.to_rgba8();
+    image.as_ptr()
+}
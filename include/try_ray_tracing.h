@@ -0,0 +1,69 @@
+/* The C API of try_ray_tracing, which is exported with the `ffi` feature (see src/ffi.rs). */
+#ifndef TRY_RAY_TRACING_H
+#define TRY_RAY_TRACING_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef enum TrtStatus {
+    TRT_OK = 0,
+    TRT_NULL_POINTER = 1,
+    TRT_INVALID_ARGUMENT = 2,
+    TRT_UNKNOWN_SCENE = 3,
+    TRT_BUFFER_TOO_SMALL = 4,
+    /* A bug of the renderer; the handles passed to the call should not be used any more. */
+    TRT_PANIC = 5,
+    TRT_INVALID_SCENE = 6,
+} TrtStatus;
+
+typedef struct TrtRenderSettings {
+    uint32_t image_width;
+    /* At least 2. */
+    uint32_t image_height;
+    uint32_t num_samples_per_pixel;
+    int32_t max_diffusion_depth;
+    /* The number of worker threads, where 0 renders on the calling thread. */
+    uint32_t num_threads;
+    /* Whether `seed` is used, which makes the image reproducible. */
+    bool has_seed;
+    uint64_t seed;
+} TrtRenderSettings;
+
+typedef struct TrtScene TrtScene;
+typedef struct TrtRenderer TrtRenderer;
+
+/* Returns a static description of `status`. */
+const char *trt_status_message(TrtStatus status);
+
+/* Builds a preset scene such as "three-spheres"; free it by `trt_scene_free`. */
+TrtStatus trt_scene_new_preset(const char *name, double aspect_ratio, TrtScene **out_scene);
+/*
+ * Builds the scene described by `json` in the format of scene files; free it by `trt_scene_free`.
+ * Returns TRT_INVALID_SCENE for scenes that cannot be parsed or are invalid, e.g. with a negative radius.
+ */
+TrtStatus trt_scene_new_json(const char *json, double aspect_ratio, TrtScene **out_scene);
+void trt_scene_free(TrtScene *scene);
+
+/* Creates a renderer of images of at most 1 << 26 pixels; free it by `trt_renderer_free`. */
+TrtStatus trt_renderer_new(const TrtRenderSettings *settings, TrtRenderer **out_renderer);
+void trt_renderer_free(TrtRenderer *renderer);
+
+/*
+ * Render into `buffer`, whose length is at least 4 * width * height elements,
+ * in row-major order from the top-left pixel. The handles may be shared by threads rendering concurrently.
+ */
+/* Gamma-corrected RGBA bytes. */
+TrtStatus trt_render_rgba8(const TrtRenderer *renderer, const TrtScene *scene, uint8_t *buffer, size_t buffer_len);
+/* Linear RGBA floats. */
+TrtStatus trt_render_rgba_f32(const TrtRenderer *renderer, const TrtScene *scene, float *buffer, size_t buffer_len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,59 @@
+"""A smoke test of the Python bindings, which builds the shared library unless `TRY_RAY_TRACING_LIB` is set:
+
+    $ python3 -m unittest discover python
+"""
+
+import os
+import subprocess
+import unittest
+
+import try_ray_tracing as trt
+
+
+def setUpModule():
+    if "TRY_RAY_TRACING_LIB" not in os.environ:
+        root = os.path.dirname(os.path.dirname(os.path.abspath(__file__)))
+        subprocess.run(
+            ["cargo", "rustc", "--release", "--lib", "--features", "ffi", "--crate-type", "cdylib"],
+            cwd=root,
+            check=True,
+        )
+
+
+class RenderTest(unittest.TestCase):
+    def setUp(self):
+        self.renderer = trt.Renderer(8, 6, samples_per_pixel=2, max_depth=5, threads=1, seed=1)
+
+    def test_render_scripted_scene(self):
+        gray = trt.Lambertian((0.5, 0.5, 0.5))
+        scene = trt.Scene(trt.Camera(look_from=(0, 0, 1), look_at=(0, 0, 0), vertical_fov=60), [trt.Sphere((0, 0, 0), 0.5, gray)])
+        image = self.renderer.render_rgba8(scene)
+        self.assertEqual((6, 8, 4), image.shape)
+        self.assertEqual(255, image[:, :, 3].min())
+        # The sphere in the middle is darker than the sky at the top-left corner.
+        self.assertLess(image[3, 4, 2], image[0, 0, 2])
+        hdr = self.renderer.render_rgba_f32(scene)
+        self.assertEqual((6, 8, 4), hdr.shape)
+        self.assertGreater(hdr.max(), 0.0)
+
+    def test_render_preset(self):
+        image = self.renderer.render_rgba8(trt.Scene.preset("three-spheres"))
+        self.assertEqual((6, 8, 4), image.shape)
+        with self.assertRaises(trt.TrtError):
+            self.renderer.render_rgba8(trt.Scene.preset("no-such-scene"))
+
+    def test_invalid_scene(self):
+        gray = trt.Lambertian((0.5, 0.5, 0.5))
+        scene = trt.Scene(trt.Camera(look_from=(0, 0, 1), look_at=(0, 0, 0), vertical_fov=60), [trt.Sphere((0, 0, 0), -0.5, gray)])
+        with self.assertRaises(trt.TrtError):
+            self.renderer.render_rgba8(scene)
+
+    def test_invalid_settings(self):
+        with self.assertRaises(trt.TrtError):
+            trt.Renderer(0, 6)
+        with self.assertRaises(trt.TrtError):
+            trt.Renderer(1 << 16, 1 << 16)
+
+
+if __name__ == "__main__":
+    unittest.main()
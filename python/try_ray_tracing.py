@@ -0,0 +1,330 @@
+"""Python bindings of try_ray_tracing over its C API (see include/try_ray_tracing.h), returning numpy arrays.
+
+Build the shared library first, which is found in `target/release` of the repository
+or at the path given by the environment variable `TRY_RAY_TRACING_LIB`:
+
+    $ cargo rustc --release --lib --features ffi --crate-type cdylib
+
+Scenes are either the presets of `--scene` or built from cameras, materials, and objects,
+which are sent to the renderer in the format of scene files:
+
+    import try_ray_tracing as trt
+
+    ground = trt.Lambertian((0.5, 0.5, 0.5))
+    scene = trt.Scene(
+        trt.Camera(look_from=(0, 1, 3), look_at=(0, 0.5, 0), vertical_fov=40),
+        [trt.Sphere((0, -1000, 0), 1000, ground), trt.Sphere((0, 0.5, 0), 0.5, trt.Metal((0.8, 0.6, 0.2), 0.1))],
+    )
+    image = trt.Renderer(320, 180, samples_per_pixel=50, seed=1).render_rgba8(scene)  # of shape (180, 320, 4)
+"""
+
+import ctypes
+import json
+import os
+import sys
+
+import numpy as np
+
+__all__ = [
+    "TrtError",
+    "Camera",
+    "Lambertian",
+    "Metal",
+    "Glass",
+    "DiffuseLight",
+    "Sphere",
+    "Cylinder",
+    "Quad",
+    "Scene",
+    "Renderer",
+]
+
+
+class TrtError(Exception):
+    """The error for the statuses of the C API other than `TRT_OK`."""
+
+    def __init__(self, status):
+        super().__init__(_library().trt_status_message(status).decode())
+        self.status = status
+
+
+class _RenderSettings(ctypes.Structure):
+    _fields_ = [
+        ("image_width", ctypes.c_uint32),
+        ("image_height", ctypes.c_uint32),
+        ("num_samples_per_pixel", ctypes.c_uint32),
+        ("max_diffusion_depth", ctypes.c_int32),
+        ("num_threads", ctypes.c_uint32),
+        ("has_seed", ctypes.c_bool),
+        ("seed", ctypes.c_uint64),
+    ]
+
+
+_LIBRARY = None
+
+
+def _library():
+    """Loads the shared library once and declares the signatures of its functions."""
+    global _LIBRARY
+    if _LIBRARY is not None:
+        return _LIBRARY
+    path = os.environ.get("TRY_RAY_TRACING_LIB")
+    if path is None:
+        if sys.platform == "win32":
+            name = "try_ray_tracing.dll"
+        elif sys.platform == "darwin":
+            name = "libtry_ray_tracing.dylib"
+        else:
+            name = "libtry_ray_tracing.so"
+        root = os.path.dirname(os.path.dirname(os.path.abspath(__file__)))
+        path = os.path.join(root, "target", "release", name)
+    library = ctypes.CDLL(path)
+    handle = ctypes.c_void_p
+    status = ctypes.c_int
+    library.trt_status_message.argtypes = [status]
+    library.trt_status_message.restype = ctypes.c_char_p
+    for new_scene in [library.trt_scene_new_preset, library.trt_scene_new_json]:
+        new_scene.argtypes = [ctypes.c_char_p, ctypes.c_double, ctypes.POINTER(handle)]
+        new_scene.restype = status
+    library.trt_scene_free.argtypes = [handle]
+    library.trt_scene_free.restype = None
+    library.trt_renderer_new.argtypes = [ctypes.POINTER(_RenderSettings), ctypes.POINTER(handle)]
+    library.trt_renderer_new.restype = status
+    library.trt_renderer_free.argtypes = [handle]
+    library.trt_renderer_free.restype = None
+    library.trt_render_rgba8.argtypes = [handle, handle, ctypes.POINTER(ctypes.c_uint8), ctypes.c_size_t]
+    library.trt_render_rgba8.restype = status
+    library.trt_render_rgba_f32.argtypes = [handle, handle, ctypes.POINTER(ctypes.c_float), ctypes.c_size_t]
+    library.trt_render_rgba_f32.restype = status
+    _LIBRARY = library
+    return library
+
+
+def _check(status):
+    if status != 0:
+        raise TrtError(status)
+
+
+def _triple(values):
+    x, y, z = values
+    return [float(x), float(y), float(z)]
+
+
+class Camera:
+    """A perspective camera, or an orthographic one whose viewport is `orthographic` high."""
+
+    def __init__(self, look_from, look_at, vertical_fov, orthographic=None):
+        self.look_from = look_from
+        self.look_at = look_at
+        self.vertical_fov = vertical_fov
+        self.orthographic = orthographic
+
+    def to_json(self):
+        camera = {
+            "look_from": _triple(self.look_from),
+            "look_at": _triple(self.look_at),
+            "vertical_fov": float(self.vertical_fov),
+        }
+        if self.orthographic is not None:
+            camera["orthographic"] = float(self.orthographic)
+        return camera
+
+
+class Material:
+    """The base class of materials, which objects may share."""
+
+    def to_json(self):
+        raise NotImplementedError
+
+
+class Lambertian(Material):
+    def __init__(self, albedo):
+        self.albedo = albedo
+
+    def to_json(self):
+        return {"type": "lambertian", "albedo": _triple(self.albedo)}
+
+
+class Metal(Material):
+    def __init__(self, albedo, fuzz=0.0):
+        self.albedo = albedo
+        self.fuzz = fuzz
+
+    def to_json(self):
+        return {"type": "metal", "albedo": _triple(self.albedo), "fuzz": float(self.fuzz)}
+
+
+class Glass(Material):
+    """A glass whose `priority` decides which one is inside where glasses overlap."""
+
+    def __init__(self, eta, albedo=(1.0, 1.0, 1.0), priority=0):
+        self.eta = eta
+        self.albedo = albedo
+        self.priority = priority
+
+    def to_json(self):
+        return {"type": "glass", "eta": float(self.eta), "albedo": _triple(self.albedo), "priority": int(self.priority)}
+
+
+class DiffuseLight(Material):
+    def __init__(self, emit):
+        self.emit = emit
+
+    def to_json(self):
+        return {"type": "light", "emit": _triple(self.emit)}
+
+
+class Sphere:
+    def __init__(self, center, radius, material):
+        self.center = center
+        self.radius = radius
+        self.material = material
+
+    def to_json(self, material_index):
+        return {
+            "type": "sphere",
+            "center": _triple(self.center),
+            "radius": float(self.radius),
+            "material": material_index(self.material),
+        }
+
+
+class Cylinder:
+    """A cylinder from `base` to `base + axis`."""
+
+    def __init__(self, base, axis, radius, material):
+        self.base = base
+        self.axis = axis
+        self.radius = radius
+        self.material = material
+
+    def to_json(self, material_index):
+        return {
+            "type": "cylinder",
+            "base": _triple(self.base),
+            "axis": _triple(self.axis),
+            "radius": float(self.radius),
+            "material": material_index(self.material),
+        }
+
+
+class Quad:
+    """The parallelogram spanned by `u` and `v` from the corner `q`."""
+
+    def __init__(self, q, u, v, material):
+        self.q = q
+        self.u = u
+        self.v = v
+        self.material = material
+
+    def to_json(self, material_index):
+        return {
+            "type": "quad",
+            "q": _triple(self.q),
+            "u": _triple(self.u),
+            "v": _triple(self.v),
+            "material": material_index(self.material),
+        }
+
+
+class Scene:
+    """A scene of `objects` seen by `camera`, where `background` is `"sky"` or an RGB triple.
+
+    The native scene is built for each aspect ratio it is rendered at and kept until the scene is collected,
+    so the attributes should not be changed after the first render.
+    """
+
+    def __init__(self, camera, objects, background="sky"):
+        self.camera = camera
+        self.objects = list(objects)
+        self.background = background
+        self._preset = None
+        self._handles = {}
+
+    @classmethod
+    def preset(cls, name):
+        """The preset scene `name` of `--scene`, e.g. `"three-spheres"`."""
+        scene = cls(None, [])
+        scene._preset = name
+        return scene
+
+    def to_json(self):
+        """Returns the scene in the format of scene files, which `--scene-file` also reads."""
+        if self._preset is not None:
+            raise ValueError("preset scenes cannot be written as scene files")
+        materials = []
+        indices = {}
+
+        def material_index(material):
+            # Shared materials are written once so that the scene file stays as small as the scene.
+            if id(material) not in indices:
+                indices[id(material)] = len(materials)
+                materials.append(material)
+            return indices[id(material)]
+
+        objects = [obj.to_json(material_index) for obj in self.objects]
+        background = self.background if isinstance(self.background, str) else _triple(self.background)
+        return {
+            "camera": self.camera.to_json(),
+            "background": background,
+            "materials": [material.to_json() for material in materials],
+            "objects": objects,
+        }
+
+    def _handle(self, aspect_ratio):
+        if aspect_ratio not in self._handles:
+            library = _library()
+            handle = ctypes.c_void_p()
+            if self._preset is not None:
+                status = library.trt_scene_new_preset(self._preset.encode(), aspect_ratio, ctypes.byref(handle))
+            else:
+                text = json.dumps(self.to_json()).encode()
+                status = library.trt_scene_new_json(text, aspect_ratio, ctypes.byref(handle))
+            _check(status)
+            self._handles[aspect_ratio] = handle
+        return self._handles[aspect_ratio]
+
+    def __del__(self):
+        for handle in getattr(self, "_handles", {}).values():
+            _library().trt_scene_free(handle)
+
+
+class Renderer:
+    """A renderer of images `width` by `height`, where `threads=0` renders on the calling thread
+    and `seed` makes the images reproducible."""
+
+    def __init__(self, width, height, samples_per_pixel=100, max_depth=10, threads=None, seed=None):
+        settings = _RenderSettings(
+            image_width=width,
+            image_height=height,
+            num_samples_per_pixel=samples_per_pixel,
+            max_diffusion_depth=max_depth,
+            num_threads=(os.cpu_count() or 1) if threads is None else threads,
+            has_seed=seed is not None,
+            seed=0 if seed is None else seed,
+        )
+        self.width = width
+        self.height = height
+        self._handle = ctypes.c_void_p()
+        _check(_library().trt_renderer_new(ctypes.byref(settings), ctypes.byref(self._handle)))
+
+    def render_rgba8(self, scene):
+        """Renders `scene` as gamma-corrected RGBA bytes of shape `(height, width, 4)`."""
+        image = np.empty((self.height, self.width, 4), dtype=np.uint8)
+        buffer = image.ctypes.data_as(ctypes.POINTER(ctypes.c_uint8))
+        _check(_library().trt_render_rgba8(self._handle, self._scene(scene), buffer, image.size))
+        return image
+
+    def render_rgba_f32(self, scene):
+        """Renders `scene` as linear RGBA floats of shape `(height, width, 4)`, e.g. for HDR textures."""
+        image = np.empty((self.height, self.width, 4), dtype=np.float32)
+        buffer = image.ctypes.data_as(ctypes.POINTER(ctypes.c_float))
+        _check(_library().trt_render_rgba_f32(self._handle, self._scene(scene), buffer, image.size))
+        return image
+
+    def _scene(self, scene):
+        return scene._handle(self.width / self.height)
+
+    def __del__(self):
+        if getattr(self, "_handle", None):
+            _library().trt_renderer_free(self._handle)
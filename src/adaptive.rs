@@ -0,0 +1,161 @@
+use std::sync::Arc;
+
+use crate::film::Film;
+use crate::geometry::{Float, UnitVec3};
+use crate::hittable_object::Hittable;
+use crate::render::RAY_T;
+use crate::scenes::Scene;
+
+/// The relative difference of the distances to the surfaces seen at neighboring pixels
+/// beyond which they are taken as different surfaces, e.g. an object in front of a wall.
+const DEPTH_TOLERANCE: Float = 0.1;
+
+/// The cosine of the angle between the normals of neighboring pixels beyond which
+/// they are taken as different surfaces, e.g. the faces on both sides of the edge of a box.
+const NORMAL_TOLERANCE: Float = 0.9;
+
+/// The difference of a gamma-corrected component between neighboring pixels
+/// beyond which they are taken as a high-contrast edge, e.g. the boundary of a shadow.
+const CONTRAST_THRESHOLD: Float = 0.2;
+
+/// The type for what the ray through the center of a pixel hits first.
+struct PrimaryHit {
+    /// The address of the material, which tells the objects apart.
+    material: usize,
+    t: Float,
+    normal: UnitVec3,
+}
+impl PrimaryHit {
+    fn is_same_surface(&self, other: &Self) -> bool {
+        self.material == other.material
+            && (self.t - other.t).abs() <= DEPTH_TOLERANCE * self.t.min(other.t)
+            && self.normal.inner_product(&other.normal) >= NORMAL_TOLERANCE
+    }
+}
+
+/// The type for the pixels on edges, i.e., geometric silhouettes and creases
+/// and high-contrast edges in the image, which get extra samples for antialiasing.
+pub struct EdgeMask {
+    width: usize,
+    /// Whether each pixel is on an edge in row-major order from the top left.
+    edges: Vec<bool>,
+}
+impl EdgeMask {
+    /// Finds the edges of `scene` seen through the rays through the centers of the pixels,
+    /// which are exact since cameras have no depth of field, and those between the pixels of `film`
+    /// rendered so far. Both pixels on the sides of an edge are on the edge.
+    pub fn find(scene: &Scene, film: &Film) -> Self {
+        let colors: Vec<[Float; 3]> = (0..film.height)
+            .flat_map(|y| (0..film.width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let color = film.pixel(x, y).gamma_correct();
+                [color.r, color.g, color.b].map(|c| c.clamp(0., 1.))
+            })
+            .collect();
+        Self::find_with(scene, film.width, film.height, |index, other| {
+            colors[index]
+                .iter()
+                .zip(colors[other].iter())
+                .any(|(c, other_c)| (c - other_c).abs() > CONTRAST_THRESHOLD)
+        })
+    }
+
+    /// Finds only the geometric edges of `scene` in the image of `width` × `height` pixels.
+    pub fn find_geometric(scene: &Scene, width: usize, height: usize) -> Self {
+        Self::find_with(scene, width, height, |_, _| false)
+    }
+
+    /// Finds the geometric edges and those between the pixels of the indices for which
+    /// `is_contrast_edge` holds.
+    fn find_with(
+        scene: &Scene,
+        width: usize,
+        height: usize,
+        is_contrast_edge: impl Fn(usize, usize) -> bool,
+    ) -> Self {
+        let hits: Vec<Option<PrimaryHit>> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let u = x as Float / (width - 1).max(1) as Float;
+                let v = (height - 1 - y) as Float / (height - 1).max(1) as Float;
+                let ray = scene.camera.get_ray(u, v);
+                let (hit, material) = scene.world.hit(&ray, &RAY_T)?;
+                Some(PrimaryHit {
+                    material: Arc::as_ptr(&material) as *const () as usize,
+                    t: hit.t,
+                    normal: hit.surface_normal,
+                })
+            })
+            .collect();
+        let differ = |index: usize, other: usize| {
+            let is_geometric_edge = match (&hits[index], &hits[other]) {
+                (Some(hit), Some(other_hit)) => !hit.is_same_surface(other_hit),
+                (None, None) => false,
+                _ => true,
+            };
+            is_geometric_edge || is_contrast_edge(index, other)
+        };
+        let mut edges = vec![false; width * height];
+        for y in 0..height {
+            for x in 0..width {
+                let index = y * width + x;
+                let neighbors = [(x + 1 < width, index + 1), (y + 1 < height, index + width)];
+                for (exists, other) in neighbors {
+                    if exists && differ(index, other) {
+                        edges[index] = true;
+                        edges[other] = true;
+                    }
+                }
+            }
+        }
+        EdgeMask { width, edges }
+    }
+
+    /// Returns whether each pixel of the `y`-th scan line from the top is on an edge.
+    pub fn row(&self, y: usize) -> &[bool] {
+        &self.edges[y * self.width..(y + 1) * self.width]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::scenes;
+
+    #[test]
+    fn edge_mask_tests() {
+        // The gray sphere of the furnace fills the middle of the image.
+        let scene = scenes::furnace(1.);
+        let (width, height) = (32, 32);
+        let mut film = Film::new(width, height);
+        let edges = EdgeMask::find(&scene, &film);
+        let row = edges.row(height / 2);
+        // The silhouette of the sphere crosses the middle scan line twice.
+        let crossings: Vec<usize> = (0..width).filter(|&x| row[x]).collect();
+        assert!(!crossings.is_empty());
+        assert!(crossings
+            .iter()
+            .all(|&x| x < width / 2 - 4 || x > width / 2 + 4));
+        assert!(!row[0] && !row[width / 2] && !row[width - 1]);
+        let num_edges = edges.edges.iter().filter(|&&is_edge| is_edge).count();
+        assert!(num_edges < width * height / 4, "{}", num_edges);
+
+        // A bright pixel in the middle of the sphere makes an edge around it.
+        let white = Color {
+            r: 1.,
+            g: 1.,
+            b: 1.,
+        };
+        film.add_samples(width / 2, height / 2, &white, 1);
+        let edges = EdgeMask::find(&scene, &film);
+        assert!(edges.row(height / 2)[width / 2]);
+        assert!(edges.row(height / 2)[width / 2 + 1]);
+        assert!(edges.row(height / 2 - 1)[width / 2]);
+        assert!(!edges.row(height / 2 - 1)[width / 2 + 1]);
+        // The bright pixel is not a geometric edge.
+        let geometric_edges = EdgeMask::find_geometric(&scene, width, height);
+        assert!(!geometric_edges.row(height / 2)[width / 2]);
+        assert_eq!(row, geometric_edges.row(height / 2));
+    }
+}
@@ -0,0 +1,257 @@
+use crate::camera::{Camera, CameraPose};
+use crate::geometry::{Float, Point3, Vec3};
+use crate::hittable_object::{Hittable, Transform, Transformed};
+use crate::scenes::Scene;
+
+/// The trait for values that can be interpolated linearly.
+pub trait Lerp {
+    /// Returns `self` if `t` is 0 and `other` if `t` is 1.
+    fn lerp(&self, other: &Self, t: Float) -> Self;
+}
+impl Lerp for Float {
+    fn lerp(&self, other: &Self, t: Float) -> Self {
+        (1. - t) * self + t * other
+    }
+}
+impl Lerp for Vec3 {
+    fn lerp(&self, other: &Self, t: Float) -> Self {
+        Vec3 {
+            x: self.x.lerp(&other.x, t),
+            y: self.y.lerp(&other.y, t),
+            z: self.z.lerp(&other.z, t),
+        }
+    }
+}
+impl Lerp for Point3 {
+    fn lerp(&self, other: &Self, t: Float) -> Self {
+        Point3 {
+            x: self.x.lerp(&other.x, t),
+            y: self.y.lerp(&other.y, t),
+            z: self.z.lerp(&other.z, t),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keyframe<T> {
+    /// The time in seconds.
+    pub time: Float,
+    pub value: T,
+}
+
+/// The type for values that change over time.
+/// Values between keyframes are interpolated linearly,
+/// and values before the first (resp. after the last) keyframe are the first (resp. last) one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Track<T> {
+    keyframes: Vec<Keyframe<T>>,
+}
+impl<T: Lerp + Clone> Track<T> {
+    /// Creates a track from keyframes, which need not be sorted.
+    /// Panics if `keyframes` is empty.
+    pub fn new(mut keyframes: Vec<Keyframe<T>>) -> Self {
+        assert!(!keyframes.is_empty(), "a track needs at least one keyframe");
+        keyframes.sort_by(|k1, k2| k1.time.total_cmp(&k2.time));
+        Self { keyframes }
+    }
+
+    pub fn value_at(&self, time: Float) -> T {
+        let first = &self.keyframes[0];
+        if time <= first.time {
+            return first.value.clone();
+        }
+        for window in self.keyframes.windows(2) {
+            let (k1, k2) = (&window[0], &window[1]);
+            if time <= k2.time {
+                let t = (time - k1.time) / (k2.time - k1.time);
+                return k1.value.lerp(&k2.value, t);
+            }
+        }
+        self.keyframes[self.keyframes.len() - 1].value.clone()
+    }
+}
+
+impl Lerp for CameraPose {
+    fn lerp(&self, other: &Self, t: Float) -> Self {
+        CameraPose {
+            look_from: self.look_from.lerp(&other.look_from, t),
+            look_at: self.look_at.lerp(&other.look_at, t),
+            vertical_fov_degree: self.vertical_fov_degree.lerp(&other.vertical_fov_degree, t),
+        }
+    }
+}
+
+/// The type for the placement of objects relative to where they are defined in scenes:
+/// scaling and rotation around a pivot, followed by translation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjectTransform {
+    pub translation: Vec3,
+    /// The angles of the rotations around the x, y, and z axes in degrees,
+    /// which are applied in this order.
+    pub rotation_degree: Vec3,
+    pub scale: Float,
+}
+impl Default for ObjectTransform {
+    fn default() -> Self {
+        let zero = Vec3 {
+            x: 0.,
+            y: 0.,
+            z: 0.,
+        };
+        ObjectTransform {
+            translation: zero.clone(),
+            rotation_degree: zero,
+            scale: 1.,
+        }
+    }
+}
+impl Lerp for ObjectTransform {
+    fn lerp(&self, other: &Self, t: Float) -> Self {
+        ObjectTransform {
+            translation: self.translation.lerp(&other.translation, t),
+            rotation_degree: self.rotation_degree.lerp(&other.rotation_degree, t),
+            scale: self.scale.lerp(&other.scale, t),
+        }
+    }
+}
+
+/// The type for keyframed transformations of an object.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjectAnimation {
+    /// The index of the object in the scene.
+    pub index: usize,
+    /// The point around which the object is scaled and rotated.
+    pub pivot: Point3,
+    pub transform: Track<ObjectTransform>,
+}
+impl ObjectAnimation {
+    /// Returns the transformation of the object at `time`.
+    pub fn transform_at(&self, time: Float) -> Transform {
+        let transform = self.transform.value_at(time);
+        Transform::new(
+            self.pivot.clone(),
+            transform.translation,
+            transform.rotation_degree,
+            transform.scale,
+        )
+    }
+}
+
+/// The type for time-varying parameters of scenes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Animation {
+    /// The keyframes of the camera; the camera of the scene is used as is if `None`.
+    pub camera: Option<Track<CameraPose>>,
+    pub objects: Vec<ObjectAnimation>,
+}
+impl Animation {
+    /// Returns the camera at `time` for images of `aspect_ratio`, or `None` if the camera stays still.
+    pub fn camera_at(&self, time: Float, aspect_ratio: Float) -> Option<Camera> {
+        let camera = self.camera.as_ref()?;
+        Some(Camera::new(camera.value_at(time), aspect_ratio))
+    }
+
+    /// Returns the animation of the `index`-th object of scenes if it moves.
+    pub fn object(&self, index: usize) -> Option<&ObjectAnimation> {
+        self.objects
+            .iter()
+            .find(|animation| animation.index == index)
+    }
+
+    /// Moves the camera and the objects of `scene` to their places at `time`.
+    pub fn apply(&self, scene: Scene, time: Float, aspect_ratio: Float) -> Scene {
+        let mut scene = scene;
+        if let Some(camera) = self.camera_at(time, aspect_ratio) {
+            scene.camera = camera;
+        }
+        let members = std::mem::take(&mut scene.world.members);
+        scene.world.members = members
+            .into_iter()
+            .enumerate()
+            .map(|(index, object)| match self.object(index) {
+                None => object,
+                Some(animation) => {
+                    let transform = animation.transform.value_at(time);
+                    Box::new(Transformed::new(
+                        object,
+                        animation.pivot.clone(),
+                        transform.translation,
+                        transform.rotation_degree,
+                        transform.scale,
+                    )) as Box<dyn Hittable>
+                }
+            })
+            .collect();
+        scene
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn track_tests() {
+        let track = Track::new(vec![
+            Keyframe {
+                time: 1.,
+                value: 10.,
+            },
+            Keyframe {
+                time: 0.,
+                value: 0.,
+            },
+            Keyframe {
+                time: 3.,
+                value: 0.,
+            },
+        ]);
+        assert_eq!(0., track.value_at(-1.));
+        assert_eq!(5., track.value_at(0.5));
+        assert_eq!(10., track.value_at(1.));
+        assert_eq!(5., track.value_at(2.));
+        assert_eq!(0., track.value_at(4.));
+    }
+
+    #[test]
+    fn object_transform_track_tests() {
+        let track = Track::new(vec![
+            Keyframe {
+                time: 0.,
+                value: ObjectTransform::default(),
+            },
+            Keyframe {
+                time: 2.,
+                value: ObjectTransform {
+                    translation: Vec3 {
+                        x: 2.,
+                        y: 0.,
+                        z: 0.,
+                    },
+                    rotation_degree: Vec3 {
+                        x: 0.,
+                        y: 90.,
+                        z: 0.,
+                    },
+                    scale: 3.,
+                },
+            },
+        ]);
+        assert_eq!(
+            ObjectTransform {
+                translation: Vec3 {
+                    x: 1.,
+                    y: 0.,
+                    z: 0.,
+                },
+                rotation_degree: Vec3 {
+                    x: 0.,
+                    y: 45.,
+                    z: 0.,
+                },
+                scale: 2.,
+            },
+            track.value_at(1.)
+        );
+    }
+}
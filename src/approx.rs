@@ -0,0 +1,120 @@
+use crate::color::Color;
+use crate::geometry::{Float, Point3, Ray, UnitVec3, Vec3};
+use crate::hittable_object::HitRecord;
+
+/// The tolerance used by `assert_approx_eq!` unless specified otherwise.
+#[cfg(not(feature = "f32"))]
+pub const DEFAULT_EPSILON: Float = 1e-9;
+#[cfg(feature = "f32")]
+pub const DEFAULT_EPSILON: Float = 1e-4;
+
+/// The trait for values that can be compared up to rounding errors.
+pub trait ApproxEq {
+    /// Checks that every component of the values differs by at most `epsilon`.
+    fn approx_eq(&self, other: &Self, epsilon: Float) -> bool;
+}
+impl ApproxEq for Float {
+    fn approx_eq(&self, other: &Self, epsilon: Float) -> bool {
+        // The equality is for comparing infinities.
+        self == other || (self - other).abs() <= epsilon
+    }
+}
+impl ApproxEq for Vec3 {
+    fn approx_eq(&self, other: &Self, epsilon: Float) -> bool {
+        self.x.approx_eq(&other.x, epsilon)
+            && self.y.approx_eq(&other.y, epsilon)
+            && self.z.approx_eq(&other.z, epsilon)
+    }
+}
+impl ApproxEq for Point3 {
+    fn approx_eq(&self, other: &Self, epsilon: Float) -> bool {
+        self.x.approx_eq(&other.x, epsilon)
+            && self.y.approx_eq(&other.y, epsilon)
+            && self.z.approx_eq(&other.z, epsilon)
+    }
+}
+impl ApproxEq for UnitVec3 {
+    fn approx_eq(&self, other: &Self, epsilon: Float) -> bool {
+        self.inject().approx_eq(&other.inject(), epsilon)
+    }
+}
+impl ApproxEq for Color {
+    fn approx_eq(&self, other: &Self, epsilon: Float) -> bool {
+        self.r.approx_eq(&other.r, epsilon)
+            && self.g.approx_eq(&other.g, epsilon)
+            && self.b.approx_eq(&other.b, epsilon)
+    }
+}
+impl ApproxEq for Ray {
+    fn approx_eq(&self, other: &Self, epsilon: Float) -> bool {
+        self.origin.approx_eq(&other.origin, epsilon)
+            && self.direction.approx_eq(&other.direction, epsilon)
+    }
+}
+impl ApproxEq for HitRecord {
+    fn approx_eq(&self, other: &Self, epsilon: Float) -> bool {
+        self.t.approx_eq(&other.t, epsilon)
+            && self
+                .surface_normal
+                .approx_eq(&other.surface_normal, epsilon)
+            && self.u.approx_eq(&other.u, epsilon)
+            && self.v.approx_eq(&other.v, epsilon)
+    }
+}
+
+/// Asserts that two values are equal up to `epsilon` (`DEFAULT_EPSILON` if omitted) by `ApproxEq`.
+#[cfg(test)]
+macro_rules! assert_approx_eq {
+    ($expected:expr, $actual:expr) => {
+        $crate::approx::assert_approx_eq!($expected, $actual, $crate::approx::DEFAULT_EPSILON)
+    };
+    ($expected:expr, $actual:expr, $epsilon:expr) => {{
+        let (expected, actual) = (&$expected, &$actual);
+        assert!(
+            $crate::approx::ApproxEq::approx_eq(expected, actual, $epsilon),
+            "assertion failed: `expected ≈ actual`\n expected: {:?}\n   actual: {:?}",
+            expected,
+            actual
+        );
+    }};
+}
+#[cfg(test)]
+pub(crate) use assert_approx_eq;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn approx_eq_tests() {
+        assert!(0.1.approx_eq(&(0.3 - 0.2), DEFAULT_EPSILON));
+        assert!(!1.0.approx_eq(&1.1, DEFAULT_EPSILON));
+        assert!(Float::INFINITY.approx_eq(&Float::INFINITY, DEFAULT_EPSILON));
+        assert!(!Float::NAN.approx_eq(&Float::NAN, DEFAULT_EPSILON));
+        assert_approx_eq!(
+            Vec3 {
+                x: 0.6,
+                y: 0.,
+                z: 0.8,
+            },
+            Vec3 {
+                x: 0.6 - DEFAULT_EPSILON / 2.,
+                y: 0.,
+                z: 0.8 + DEFAULT_EPSILON / 2.,
+            }
+        );
+        assert!(!Color {
+            r: 0.5,
+            g: 0.5,
+            b: 0.5,
+        }
+        .approx_eq(
+            &Color {
+                r: 0.5,
+                g: 0.6,
+                b: 0.5,
+            },
+            0.01
+        ));
+    }
+}
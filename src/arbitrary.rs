@@ -0,0 +1,49 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::geometry::{Float, Point3, UnitVec3, Vec3};
+
+/// The number of random inputs each property is checked for.
+pub const NUM_CASES: usize = 1000;
+
+/// Calls `check` with `NUM_CASES` random number generators for property-based tests.
+/// The generators have fixed seeds so that failures are reproducible,
+/// and the index of the case is reported if `check` panics.
+pub fn for_all_cases(check: impl Fn(&mut StdRng)) {
+    for case in 0..NUM_CASES {
+        let mut rng = StdRng::seed_from_u64(case as u64);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| check(&mut rng)));
+        if let Err(e) = result {
+            eprintln!("the property fails for case #{}", case);
+            std::panic::resume_unwind(e);
+        }
+    }
+}
+
+/// Returns a vector whose components are in [-`scale`, `scale`).
+pub fn vec3(rng: &mut StdRng, scale: Float) -> Vec3 {
+    Vec3 {
+        x: rng.gen_range(-scale..scale),
+        y: rng.gen_range(-scale..scale),
+        z: rng.gen_range(-scale..scale),
+    }
+}
+
+pub fn point3(rng: &mut StdRng, scale: Float) -> Point3 {
+    let v = vec3(rng, scale);
+    Point3 {
+        x: v.x,
+        y: v.y,
+        z: v.z,
+    }
+}
+
+pub fn unit_vec3(rng: &mut StdRng) -> UnitVec3 {
+    loop {
+        let v = vec3(rng, 1.);
+        // Avoids directions dominated by rounding errors.
+        if v.length_squared() > 1e-4 {
+            return v.unit_vector();
+        }
+    }
+}
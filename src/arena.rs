@@ -0,0 +1,234 @@
+use std::sync::OnceLock;
+
+use crate::bvh::{union, TopLevelBvh};
+use crate::geometry::{Point3, Ray};
+use crate::hittable_object::{Cylinder, HitRecord, Hittable, Quad, SharedMaterial, Sphere};
+use crate::interval::Interval;
+use crate::json::Json;
+use crate::light::Light;
+use crate::scene_file::{tagged, MaterialTable};
+
+/// The type for the objects stored in `Arena`s, where the common shapes are stored in place
+/// and the others are boxed.
+pub enum ArenaObject {
+    Sphere(Sphere),
+    Quad(Quad),
+    Cylinder(Cylinder),
+    Other(Box<dyn Hittable>),
+}
+impl ArenaObject {
+    fn as_hittable(&self) -> &dyn Hittable {
+        match self {
+            ArenaObject::Sphere(sphere) => sphere,
+            ArenaObject::Quad(quad) => quad,
+            ArenaObject::Cylinder(cylinder) => cylinder,
+            ArenaObject::Other(object) => object.as_ref(),
+        }
+    }
+}
+impl From<Sphere> for ArenaObject {
+    fn from(sphere: Sphere) -> Self {
+        ArenaObject::Sphere(sphere)
+    }
+}
+impl From<Quad> for ArenaObject {
+    fn from(quad: Quad) -> Self {
+        ArenaObject::Quad(quad)
+    }
+}
+impl From<Cylinder> for ArenaObject {
+    fn from(cylinder: Cylinder) -> Self {
+        ArenaObject::Cylinder(cylinder)
+    }
+}
+impl From<Box<dyn Hittable>> for ArenaObject {
+    fn from(object: Box<dyn Hittable>) -> Self {
+        ArenaObject::Other(object)
+    }
+}
+
+/// The type for the indices of the objects in an `Arena`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ObjectId(usize);
+
+/// The type for lists of objects built at load time and stored contiguously, e.g. the many spheres
+/// of a preset scene. Unlike `HittableList`, the spheres, quads, and cylinders are neither boxed
+/// nor called through vtables, so that rays test them one after another in memory.
+/// The objects are referred to by their `ObjectId`s and are never removed.
+#[derive(Default)]
+pub struct Arena {
+    objects: Vec<ArenaObject>,
+    /// The BVH over the objects, which is built when rays are first traced.
+    bvh: OnceLock<TopLevelBvh>,
+}
+impl Arena {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `object` to the end of the arena and returns its index.
+    pub fn add(&mut self, object: impl Into<ArenaObject>) -> ObjectId {
+        self.objects.push(object.into());
+        self.bvh = OnceLock::new();
+        ObjectId(self.objects.len() - 1)
+    }
+
+    pub fn get(&self, id: ObjectId) -> &dyn Hittable {
+        self.objects[id.0].as_hittable()
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+}
+impl<T: Into<ArenaObject>> FromIterator<T> for Arena {
+    fn from_iter<I: IntoIterator<Item = T>>(objects: I) -> Self {
+        Arena {
+            objects: objects.into_iter().map(Into::into).collect(),
+            bvh: OnceLock::new(),
+        }
+    }
+}
+impl Hittable for Arena {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<(HitRecord, SharedMaterial)> {
+        let bvh = self.bvh.get_or_init(|| {
+            TopLevelBvh::from_bounds(
+                self.objects
+                    .iter()
+                    .map(|object| object.as_hittable().bounding_box()),
+            )
+        });
+        // Dispatched statically except for the boxed objects.
+        bvh.hit_members(ray, ray_t, |index, ray_t| match &self.objects[index] {
+            ArenaObject::Sphere(sphere) => sphere.hit(ray, ray_t),
+            ArenaObject::Quad(quad) => quad.hit(ray, ray_t),
+            ArenaObject::Cylinder(cylinder) => cylinder.hit(ray, ray_t),
+            ArenaObject::Other(object) => object.hit(ray, ray_t),
+        })
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        for (index, object) in self.objects.iter().enumerate() {
+            for problem in object.as_hittable().validate() {
+                problems.push(format!("object #{}: {}", index, problem));
+            }
+        }
+        problems
+    }
+
+    fn memory_size(&self) -> usize {
+        let slots_size = self.objects.capacity() * std::mem::size_of::<ArenaObject>();
+        // The objects in place are counted by the slots, and only what they own is added.
+        let owned_size: usize = self
+            .objects
+            .iter()
+            .map(|object| {
+                let hittable = object.as_hittable();
+                match object {
+                    ArenaObject::Other(_) => hittable.memory_size(),
+                    _ => hittable.memory_size() - std::mem::size_of_val(hittable),
+                }
+            })
+            .sum();
+        let bvh_size = self.bvh.get().map_or(0, TopLevelBvh::heap_size);
+        std::mem::size_of_val(self) + slots_size + owned_size + bvh_size
+    }
+
+    fn bounding_box(&self) -> Option<(Point3, Point3)> {
+        let mut boxes = self
+            .objects
+            .iter()
+            .map(|object| object.as_hittable().bounding_box());
+        let first = boxes.next()??;
+        boxes.try_fold(first, |bounds, object| Some(union(&bounds, &object?)))
+    }
+
+    fn collect_lights(&self, lights: &mut Vec<Light>) {
+        for object in self.objects.iter() {
+            object.as_hittable().collect_lights(lights);
+        }
+    }
+
+    /// Writes the arena as a list, which scene files read back as `HittableList`.
+    fn to_json(&self, materials: &mut MaterialTable) -> Option<Json> {
+        let members = self
+            .objects
+            .iter()
+            .map(|object| object.as_hittable().to_json(materials))
+            .collect::<Option<_>>()?;
+        Some(tagged("list", vec![("members", Json::Array(members))]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Attenuation;
+    use crate::geometry::{Float, Point3, Vec3};
+    use crate::hittable_object::{HittableList, Lambertian};
+    use std::sync::Arc;
+
+    #[test]
+    fn arena_tests() {
+        let material: SharedMaterial = Arc::new(Lambertian {
+            albedo: Attenuation {
+                r: 0.5,
+                g: 0.5,
+                b: 0.5,
+            },
+        });
+        let sphere = |z: Float| Sphere {
+            center: Point3 { x: 0., y: 0., z },
+            radius: 1.,
+            material: material.clone(),
+        };
+        let mut arena: Arena = [sphere(-10.), sphere(-5.)].into_iter().collect();
+        let boxed: Box<dyn Hittable> = Box::new(HittableList {
+            members: vec![Box::new(sphere(-3.))],
+        });
+        let id = arena.add(boxed);
+        assert_eq!(3, arena.len());
+        assert!(arena.get(id).as_node().is_none());
+
+        // The nearest object is hit regardless of the order, including the boxed one.
+        let ray = Ray {
+            origin: Point3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            direction: Vec3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            }
+            .unit_vector(),
+        };
+        let (hit, _) = arena
+            .hit(
+                &ray,
+                &Interval {
+                    min: 0.01,
+                    max: Float::INFINITY,
+                },
+            )
+            .unwrap();
+        assert_eq!(2., hit.t);
+
+        let mut invalid = Arena::new();
+        invalid.add(Sphere {
+            radius: -1.,
+            ..sphere(0.)
+        });
+        assert_eq!(1, invalid.validate().len());
+        assert!(invalid.validate()[0].starts_with("object #0: "));
+        assert!(arena.memory_size() > 3 * std::mem::size_of::<ArenaObject>());
+        let json = arena.to_json(&mut MaterialTable::default()).unwrap();
+        assert!(matches!(json.get("members"), Some(Json::Array(members)) if members.len() == 3));
+    }
+}
@@ -0,0 +1,201 @@
+use crate::color::Color;
+use crate::film::Film;
+use crate::geometry::{Float, Point3, UnitVec3};
+use crate::hittable_object::Mesh;
+
+/// The type for the points on meshes seen by the texels of their textures, where the light is baked.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SurfacePoint {
+    pub point: Point3,
+    /// The normal on the front side of the triangle, i.e., the side from which its corners
+    /// go counterclockwise, which is the side the light is baked for.
+    pub normal: UnitVec3,
+}
+
+/// Returns the points on `mesh` at the centers of the texels of a `width` × `height` texture
+/// laid over its texture coordinates, in row-major order from the top left, where `v` goes up.
+/// The texels outside all the triangles are `None`, and those in several triangles take the first.
+/// Returns `None` if the mesh has no texture coordinates.
+pub fn surface_points(
+    mesh: &Mesh,
+    width: usize,
+    height: usize,
+) -> Option<Vec<Option<SurfacePoint>>> {
+    let texture_coordinates = mesh.texture_coordinates()?;
+    let mut points = vec![None; width * height];
+    for (triangle, &[(u0, v0), (u1, v1), (u2, v2)]) in
+        mesh.triangles().iter().zip(texture_coordinates)
+    {
+        let determinant = (u1 - u0) * (v2 - v0) - (u2 - u0) * (v1 - v0);
+        if determinant.abs() < 1e-12 {
+            continue;
+        }
+        let [a, b, c] = triangle.map(|vertex| &mesh.vertices()[vertex]);
+        let normal = (b - a).cross_product(&(c - a));
+        if !(normal.length() > 0. && normal.is_finite()) {
+            // The light of degenerate triangles is not baked as they have no sides.
+            continue;
+        }
+        let normal = normal.unit_vector();
+        // Only the texels whose centers are in the box around the triangle are tested.
+        let texels = |min: Float, max: Float, size: usize| {
+            let first = (min * size as Float - 0.5).ceil().max(0.) as usize;
+            let last = (max * size as Float - 0.5).floor().min(size as Float - 1.);
+            first..(last + 1.).max(0.) as usize
+        };
+        let (u_min, u_max) = (u0.min(u1).min(u2), u0.max(u1).max(u2));
+        let (v_min, v_max) = (v0.min(v1).min(v2), v0.max(v1).max(v2));
+        for row in texels(v_min, v_max, height) {
+            let y = height - 1 - row;
+            let v = (row as Float + 0.5) / height as Float;
+            for x in texels(u_min, u_max, width) {
+                if points[y * width + x].is_some() {
+                    continue;
+                }
+                let u = (x as Float + 0.5) / width as Float;
+                let s = ((u - u0) * (v2 - v0) - (u2 - u0) * (v - v0)) / determinant;
+                let t = ((u1 - u0) * (v - v0) - (u - u0) * (v1 - v0)) / determinant;
+                // The texels on the edges shared by triangles are kept despite rounding errors.
+                let tolerance = 1e-6;
+                if s < -tolerance || t < -tolerance || s + t > 1. + tolerance {
+                    continue;
+                }
+                points[y * width + x] = Some(SurfacePoint {
+                    point: a + &(&((b - a) * s) + &((c - a) * t)),
+                    normal: normal.clone(),
+                });
+            }
+        }
+    }
+    Some(points)
+}
+
+/// Returns `film` with the texels not covered by `points` filled with the average of
+/// their filled neighbors, growing the filled texels by one texel for each of `iterations`,
+/// so that the texture filtered across the seams of the layout does not pick up black.
+pub fn dilate(film: &Film, points: &[Option<SurfacePoint>], iterations: usize) -> Film {
+    let (width, height) = (film.width, film.height);
+    let mut colors: Vec<Option<Color>> = (0..width * height)
+        .map(|index| {
+            points[index]
+                .as_ref()
+                .map(|_| film.pixel(index % width, index / width))
+        })
+        .collect();
+    for _ in 0..iterations {
+        let last = colors.clone();
+        for y in 0..height {
+            for x in 0..width {
+                if last[y * width + x].is_some() {
+                    continue;
+                }
+                let neighbors: Vec<&Color> = (y.saturating_sub(1)..(y + 2).min(height))
+                    .flat_map(|ny| {
+                        (x.saturating_sub(1)..(x + 2).min(width)).map(move |nx| (nx, ny))
+                    })
+                    .filter_map(|(nx, ny)| last[ny * width + nx].as_ref())
+                    .collect();
+                if let Some((first, rest)) = neighbors.split_first() {
+                    let sum = rest
+                        .iter()
+                        .fold((*first).clone(), |sum, color| sum.add(color));
+                    colors[y * width + x] = Some(sum.scale(1. / neighbors.len() as Float));
+                }
+            }
+        }
+    }
+    let mut dilated = Film::new(width, height);
+    for (index, color) in colors.iter().enumerate() {
+        if let Some(color) = color {
+            dilated.set_pixel(index % width, index / width, color);
+        }
+    }
+    dilated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx::assert_approx_eq;
+    use crate::color::Attenuation;
+    use crate::hittable_object::Lambertian;
+    use std::sync::Arc;
+
+    /// The square of side 2 on the xy-plane facing +z, whose left triangle covers
+    /// the lower left half of the texture if `full` is not set.
+    fn square(full: bool) -> Mesh {
+        let point = |x, y| Point3 { x, y, z: 0. };
+        let vertices = vec![point(0., 0.), point(2., 0.), point(2., 2.), point(0., 2.)];
+        let mut triangles = vec![[0, 1, 3]];
+        let mut texture_coordinates = vec![[(0., 0.), (1., 0.), (0., 1.)]];
+        if full {
+            triangles.push([1, 2, 3]);
+            texture_coordinates.push([(1., 0.), (1., 1.), (0., 1.)]);
+        }
+        let material = Arc::new(Lambertian {
+            albedo: Attenuation {
+                r: 0.5,
+                g: 0.5,
+                b: 0.5,
+            },
+        });
+        Mesh::new(vertices, triangles, material).with_texture_coordinates(texture_coordinates)
+    }
+
+    #[test]
+    fn surface_points_tests() {
+        let points = surface_points(&square(true), 4, 4).unwrap();
+        assert!(points.iter().all(Option::is_some));
+        // The top-left texel is at the top left of the square.
+        let SurfacePoint { point, normal } = points[0].as_ref().unwrap();
+        assert_approx_eq!(0.25, point.x);
+        assert_approx_eq!(1.75, point.y);
+        assert_approx_eq!(0., point.z);
+        assert_approx_eq!(1., normal.inject().z);
+        let point = &points[2 * 4 + 3].as_ref().unwrap().point;
+        assert_approx_eq!(1.75, point.x);
+        assert_approx_eq!(0.75, point.y);
+
+        // The texels above the diagonal are outside the triangle.
+        let points = surface_points(&square(false), 4, 4).unwrap();
+        let covered: Vec<bool> = points.iter().map(Option::is_some).collect();
+        #[rustfmt::skip]
+        let expected = vec![
+            true, false, false, false,
+            true, true, false, false,
+            true, true, true, false,
+            true, true, true, true,
+        ];
+        assert_eq!(expected, covered);
+
+        let material = square(true).material().clone();
+        let vertices = square(true).vertices().to_vec();
+        assert_eq!(
+            None,
+            surface_points(&Mesh::new(vertices, vec![], material), 4, 4)
+        );
+    }
+
+    #[test]
+    fn dilate_tests() {
+        let points = surface_points(&square(false), 4, 4).unwrap();
+        let mut film = Film::new(4, 4);
+        let white = Color {
+            r: 1.,
+            g: 1.,
+            b: 1.,
+        };
+        for (index, point) in points.iter().enumerate() {
+            if point.is_some() {
+                film.set_pixel(index % 4, index / 4, &white);
+            }
+        }
+        // The texels next to the triangle are filled first, and the corner farthest from it last.
+        let dilated = dilate(&film, &points, 1);
+        assert_eq!(white, dilated.pixel(1, 0));
+        assert_eq!(white, dilated.pixel(3, 2));
+        assert_eq!(0., dilated.pixel(3, 0).r);
+        let dilated = dilate(&film, &points, 2);
+        assert_eq!(white, dilated.pixel(3, 0));
+    }
+}
@@ -0,0 +1,231 @@
+use crate::color::Color;
+use crate::film::Film;
+use crate::geometry::Float;
+
+/// The type for the glow around bright parts of images, which real lenses and eyes scatter
+/// into their surroundings. It is applied to the linear colors of films, i.e., before tone mapping,
+/// so that only what is brighter than white glows.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bloom {
+    /// The luminance above which pixels glow.
+    pub threshold: Float,
+    /// The brightness of the glow relative to the excess of the pixels over `threshold`,
+    /// where `0` turns it off.
+    pub intensity: Float,
+    /// The number of levels of the Gaussian pyramid, each of which doubles the reach of the glow.
+    pub levels: usize,
+}
+impl Default for Bloom {
+    fn default() -> Self {
+        Bloom {
+            threshold: 1.,
+            intensity: 0.,
+            levels: 5,
+        }
+    }
+}
+impl Bloom {
+    /// Returns an error message if the parameters are out of range.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.threshold.is_finite() && self.threshold >= 0.) {
+            return Err("the bloom threshold must be non-negative".to_string());
+        }
+        if !(self.intensity.is_finite() && self.intensity >= 0.) {
+            return Err("the bloom intensity must be non-negative".to_string());
+        }
+        if !(1..=MAX_LEVELS).contains(&self.levels) {
+            return Err(format!("the bloom levels must be in 1..={}", MAX_LEVELS));
+        }
+        Ok(())
+    }
+
+    /// Adds the glow to the pixels of `film`, which keeps its records such as invalid samples.
+    pub fn apply(&self, film: &mut Film) {
+        if self.intensity == 0. {
+            return;
+        }
+        let mut level = Layer::bright_pass(film, self.threshold);
+        let mut glow = Layer::black(film.width, film.height);
+        let mut num_levels = 0;
+        // The levels stop early once they shrink to a single pixel.
+        while num_levels < self.levels && (level.width, level.height) != (1, 1) {
+            num_levels += 1;
+            level = level.blur().downsample();
+            glow.add_upsampled(&level.blur(), 1 << num_levels);
+        }
+        let weight = self.intensity / num_levels.max(1) as Float;
+        for y in 0..film.height {
+            for x in 0..film.width {
+                let glow = &glow.pixels[y * film.width + x];
+                let color = film.pixel(x, y).add(&glow.scale(weight));
+                film.set_pixel(x, y, &color);
+            }
+        }
+    }
+}
+
+/// The maximum of `Bloom::levels`, beyond which the glow reaches farther than any image.
+const MAX_LEVELS: usize = 12;
+
+/// Returns the luminance of `color` by the weights of Rec. 709.
+fn luminance(color: &Color) -> Float {
+    0.2126 * color.r + 0.7152 * color.g + 0.0722 * color.b
+}
+
+/// The type for the levels of the pyramid, where `(0, 0)` is the top-left pixel.
+struct Layer {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+impl Layer {
+    fn black(width: usize, height: usize) -> Self {
+        let black = Color {
+            r: 0.,
+            g: 0.,
+            b: 0.,
+        };
+        Layer {
+            width,
+            height,
+            pixels: vec![black; width * height],
+        }
+    }
+
+    /// Returns the excess of the pixels of `film` over the luminance `threshold`, keeping their hues.
+    fn bright_pass(film: &Film, threshold: Float) -> Self {
+        let mut layer = Layer::black(film.width, film.height);
+        for y in 0..film.height {
+            for x in 0..film.width {
+                let color = film.pixel(x, y);
+                let luminance = luminance(&color);
+                if luminance > threshold {
+                    let excess = color.scale((luminance - threshold) / luminance);
+                    layer.pixels[y * film.width + x] = excess;
+                }
+            }
+        }
+        layer
+    }
+
+    /// Returns the pixel, where the pixels beyond the edges are those on the edges.
+    fn pixel(&self, x: isize, y: isize) -> &Color {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        &self.pixels[y * self.width + x]
+    }
+
+    /// Returns the layer blurred by the binomial kernel of 5 taps in both directions,
+    /// which approximates a Gaussian of the standard deviation of 1 pixel.
+    fn blur(&self) -> Self {
+        const KERNEL: [Float; 5] = [1. / 16., 4. / 16., 6. / 16., 4. / 16., 1. / 16.];
+        let convolve = |layer: &Layer, (dx, dy): (isize, isize)| {
+            let mut blurred = Layer::black(layer.width, layer.height);
+            for y in 0..layer.height {
+                for x in 0..layer.width {
+                    let mut sum = Color {
+                        r: 0.,
+                        g: 0.,
+                        b: 0.,
+                    };
+                    for (k, weight) in (-2..=2).zip(KERNEL) {
+                        let pixel = layer.pixel(x as isize + k * dx, y as isize + k * dy);
+                        sum = sum.add(&pixel.scale(weight));
+                    }
+                    blurred.pixels[y * layer.width + x] = sum;
+                }
+            }
+            blurred
+        };
+        convolve(&convolve(self, (1, 0)), (0, 1))
+    }
+
+    /// Returns the layer of half the size, whose pixels are the averages of 2 × 2 pixels.
+    fn downsample(&self) -> Self {
+        let mut layer = Layer::black(self.width.div_ceil(2), self.height.div_ceil(2));
+        for y in 0..layer.height {
+            for x in 0..layer.width {
+                let (x2, y2) = (2 * x as isize, 2 * y as isize);
+                let sum = self
+                    .pixel(x2, y2)
+                    .add(self.pixel(x2 + 1, y2))
+                    .add(self.pixel(x2, y2 + 1))
+                    .add(self.pixel(x2 + 1, y2 + 1));
+                layer.pixels[y * layer.width + x] = sum.scale(0.25);
+            }
+        }
+        layer
+    }
+
+    /// Adds `smaller`, which is `factor` times smaller, enlarged by bilinear interpolation.
+    fn add_upsampled(&mut self, smaller: &Layer, factor: usize) {
+        let factor = factor as Float;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let sx = (x as Float + 0.5) / factor - 0.5;
+                let sy = (y as Float + 0.5) / factor - 0.5;
+                let (x0, y0) = (sx.floor(), sy.floor());
+                let (fx, fy) = (sx - x0, sy - y0);
+                let (x0, y0) = (x0 as isize, y0 as isize);
+                let top = smaller.pixel(x0, y0).blend(fx, smaller.pixel(x0 + 1, y0));
+                let bottom = smaller
+                    .pixel(x0, y0 + 1)
+                    .blend(fx, smaller.pixel(x0 + 1, y0 + 1));
+                let index = y * self.width + x;
+                self.pixels[index] = self.pixels[index].add(&top.blend(fy, &bottom));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bloom_tests() {
+        let mut film = Film::new(32, 32);
+        let dim = Color {
+            r: 0.5,
+            g: 0.5,
+            b: 0.5,
+        };
+        let bright = Color {
+            r: 20.,
+            g: 10.,
+            b: 5.,
+        };
+        for y in 0..32 {
+            for x in 0..32 {
+                let color = if (x, y) == (16, 16) { &bright } else { &dim };
+                film.add_samples(x, y, color, 1);
+            }
+        }
+        // Nothing glows below the threshold.
+        let mut dim_film = Film::new(1, 1);
+        dim_film.add_samples(0, 0, &dim, 1);
+        let bloom = Bloom {
+            intensity: 1.,
+            ..Bloom::default()
+        };
+        bloom.validate().unwrap();
+        bloom.apply(&mut dim_film);
+        assert_eq!(dim, dim_film.pixel(0, 0));
+
+        bloom.apply(&mut film);
+        let near = film.pixel(18, 16);
+        let far = film.pixel(28, 16);
+        assert!(near.r > far.r && far.r > dim.r, "{:?}, {:?}", near, far);
+        // The glow keeps the hue of the bright pixel.
+        assert!(near.r - dim.r > near.g - dim.g && near.g - dim.g > near.b - dim.b);
+        // The glow is symmetric around the bright pixel up to the pixel grid of the pyramid.
+        assert!((film.pixel(14, 16).r - near.r).abs() < 0.5 * (near.r - dim.r));
+
+        assert!(Bloom {
+            levels: 0,
+            ..Bloom::default()
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -0,0 +1,391 @@
+use crate::geometry::{Float, Point3, Ray};
+use crate::hittable_object::{HitRecord, HittableList, SharedMaterial};
+use crate::interval::Interval;
+
+/// Returns the smallest box containing both `a` and `b`, where boxes are given by their minimum
+/// and maximum corners.
+pub fn union(a: &(Point3, Point3), b: &(Point3, Point3)) -> (Point3, Point3) {
+    (a.0.min(&b.0), a.1.max(&b.1))
+}
+
+/// Checks that `ray` passes through the box `bounds` in `ray_t` by the slab method.
+pub(crate) fn hits_bounds(bounds: &(Point3, Point3), ray: &Ray, ray_t: &Interval) -> bool {
+    let (min, max) = bounds;
+    let direction = ray.direction.inject();
+    let mut ray_t = ray_t.clone();
+    for axis in 0..3 {
+        let inverse = 1. / direction[axis];
+        let t0 = (min[axis] - ray.origin[axis]) * inverse;
+        let t1 = (max[axis] - ray.origin[axis]) * inverse;
+        // `Float::max` and `Float::min` ignore the NaNs of rays parallel to the slab.
+        ray_t.min = ray_t.min.max(t0.min(t1));
+        // The far side is pushed slightly away so that rounding errors do not make rays miss
+        // flat boxes, e.g. those of axis-aligned triangles, which they hit exactly.
+        ray_t.max = ray_t.max.min(t0.max(t1) * (1. + 4. * Float::EPSILON));
+        if ray_t.min > ray_t.max {
+            return false;
+        }
+    }
+    true
+}
+
+/// The maximum number of primitives in a leaf of `Bvh`s.
+const MAX_LEAF_SIZE: usize = 4;
+
+/// The maximum depth of `Bvh`s, which halving the primitives at every node never reaches.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Clone, Debug)]
+enum BvhNode {
+    /// The primitives of `Bvh::primitives[start..end]`.
+    Leaf { start: usize, end: usize },
+    /// The children split along `axis`.
+    Inner {
+        left: usize,
+        right: usize,
+        axis: usize,
+    },
+}
+
+/// The type for bounding volume hierarchies over primitives referred to by their indices,
+/// e.g. the triangles of a mesh or the members of a world, which let rays skip most of them.
+#[derive(Clone, Debug)]
+pub struct Bvh {
+    /// The indices of the primitives, where those in each leaf are consecutive.
+    primitives: Vec<usize>,
+    /// The nodes with their bounding boxes, where the root is the last one.
+    nodes: Vec<((Point3, Point3), BvhNode)>,
+}
+impl Bvh {
+    /// Builds the hierarchy of the primitives given by their indices and bounding boxes,
+    /// splitting them at the median along the longest axis of their centers like `LightTree`.
+    pub fn new(primitives: impl IntoIterator<Item = (usize, (Point3, Point3))>) -> Self {
+        let (indices, bounds): (Vec<usize>, Vec<(Point3, Point3)>) = primitives.into_iter().unzip();
+        let mut order: Vec<usize> = (0..bounds.len()).collect();
+        let mut nodes = vec![];
+        if !order.is_empty() {
+            build(&bounds, &mut order, 0, &mut nodes);
+        }
+        Bvh {
+            primitives: order
+                .into_iter()
+                .map(|position| indices[position])
+                .collect(),
+            nodes,
+        }
+    }
+
+    /// Updates the bounding boxes of the nodes from `bounds(index)` of the primitives, e.g. after
+    /// they have moved, keeping the tree as it is. This is much cheaper than building another one,
+    /// while rays traverse it more slowly as the primitives get farther from where they were.
+    pub fn refit(&mut self, bounds: impl Fn(usize) -> (Point3, Point3)) {
+        // The children precede their parents, and thus they are refitted first.
+        for index in 0..self.nodes.len() {
+            let node_bounds = match &self.nodes[index].1 {
+                BvhNode::Leaf { start, end } => {
+                    let primitives = &self.primitives[*start..*end];
+                    primitives[1..]
+                        .iter()
+                        .fold(bounds(primitives[0]), |node_bounds, &primitive| {
+                            union(&node_bounds, &bounds(primitive))
+                        })
+                }
+                BvhNode::Inner { left, right, .. } => {
+                    union(&self.nodes[*left].0, &self.nodes[*right].0)
+                }
+            };
+            self.nodes[index].0 = node_bounds;
+        }
+    }
+
+    /// Returns the number of bytes of the nodes and the indices, which are outside the struct itself.
+    pub fn heap_size(&self) -> usize {
+        self.primitives.capacity() * std::mem::size_of::<usize>()
+            + self.nodes.capacity() * std::mem::size_of::<((Point3, Point3), BvhNode)>()
+    }
+
+    /// Returns the box bounding all the primitives, or `None` if there are none.
+    pub fn bounds(&self) -> Option<&(Point3, Point3)> {
+        self.nodes.last().map(|(bounds, _)| bounds)
+    }
+
+    /// Calls `hit(index, ray_t)` for the primitives whose boxes `ray` passes through in `ray_t`,
+    /// roughly from near to far, where `hit` returns the distance to the primitive if it hits it,
+    /// which narrows `ray_t` for the rest.
+    pub fn traverse(
+        &self,
+        ray: &Ray,
+        ray_t: &Interval,
+        mut hit: impl FnMut(usize, &Interval) -> Option<Float>,
+    ) {
+        let Some(root) = self.nodes.len().checked_sub(1) else {
+            return;
+        };
+        let direction = ray.direction.inject();
+        let mut ray_t = ray_t.clone();
+        // The stack is on the call stack since every ray traverses it.
+        let mut stack = [0; MAX_DEPTH + 1];
+        stack[0] = root;
+        let mut len = 1;
+        while len > 0 {
+            len -= 1;
+            let (bounds, node) = &self.nodes[stack[len]];
+            if !hits_bounds(bounds, ray, &ray_t) {
+                continue;
+            }
+            match node {
+                BvhNode::Leaf { start, end } => {
+                    for &index in &self.primitives[*start..*end] {
+                        if let Some(t) = hit(index, &ray_t) {
+                            ray_t.max = t;
+                        }
+                    }
+                }
+                BvhNode::Inner { left, right, axis } => {
+                    // The far child is pushed first so that the near one is visited first.
+                    let (near, far) = if direction[*axis] < 0. {
+                        (right, left)
+                    } else {
+                        (left, right)
+                    };
+                    stack[len] = *far;
+                    stack[len + 1] = *near;
+                    len += 2;
+                }
+            }
+        }
+    }
+}
+
+/// Adds the nodes for the primitives at `order`, i.e., the positions in `bounds`, which start
+/// at `start` in the leaves, and returns the index of the root of them.
+fn build(
+    bounds: &[(Point3, Point3)],
+    order: &mut [usize],
+    start: usize,
+    nodes: &mut Vec<((Point3, Point3), BvhNode)>,
+) -> usize {
+    let node_bounds = order[1..]
+        .iter()
+        .fold(bounds[order[0]].clone(), |node_bounds, &position| {
+            union(&node_bounds, &bounds[position])
+        });
+    let node = if order.len() <= MAX_LEAF_SIZE {
+        BvhNode::Leaf {
+            start,
+            end: start + order.len(),
+        }
+    } else {
+        let center = |position: &usize| {
+            let (min, max) = &bounds[*position];
+            min + &((max - min) * 0.5)
+        };
+        let (min, max) = order.iter().map(center).fold(
+            (center(&order[0]), center(&order[0])),
+            |(min, max), center| (min.min(&center), max.max(&center)),
+        );
+        let extent = max - min;
+        let axis = (0..3)
+            .max_by(|&a, &b| extent[a].total_cmp(&extent[b]))
+            .unwrap_or(0);
+        order.sort_by(|a, b| center(a)[axis].total_cmp(&center(b)[axis]));
+        let middle = order.len() / 2;
+        let (left, right) = order.split_at_mut(middle);
+        let left = build(bounds, left, start, nodes);
+        let right = build(bounds, right, start + middle, nodes);
+        BvhNode::Inner { left, right, axis }
+    };
+    nodes.push((node_bounds, node));
+    nodes.len() - 1
+}
+
+/// The type for the top level of the acceleration of worlds, i.e., a BVH over their members
+/// by the bounding boxes of them in the world. Meshes have their own bottom-level BVHs over
+/// their triangles in their object spaces, and thus moving or animating members, which wraps them
+/// in transformations, rebuilds or refits only this small hierarchy instead of those of the meshes.
+#[derive(Clone, Debug)]
+pub struct TopLevelBvh {
+    bvh: Bvh,
+    /// The number of the objects, including the unbounded ones.
+    num_objects: usize,
+    /// The members without bounding boxes, e.g. volumes, which are tested with every ray.
+    unbounded: Vec<usize>,
+}
+impl TopLevelBvh {
+    pub fn new(world: &HittableList) -> Self {
+        Self::from_bounds(world.members.iter().map(|member| member.bounding_box()))
+    }
+
+    /// Builds the hierarchy over the objects of the bounding boxes `boxes` in the order of them,
+    /// where `None` means that the object is unbounded.
+    pub fn from_bounds(boxes: impl IntoIterator<Item = Option<(Point3, Point3)>>) -> Self {
+        let mut bounded = vec![];
+        let mut unbounded = vec![];
+        let mut num_objects = 0;
+        for (index, bounds) in boxes.into_iter().enumerate() {
+            num_objects += 1;
+            match bounds {
+                Some(bounds) => bounded.push((index, bounds)),
+                None => unbounded.push(index),
+            }
+        }
+        TopLevelBvh {
+            bvh: Bvh::new(bounded),
+            num_objects,
+            unbounded,
+        }
+    }
+
+    /// Refits the hierarchy to the members of `world` moved since it was built, e.g. by animations,
+    /// or builds another one if members have been added or removed, or have gained or lost bounds.
+    pub fn refit(&mut self, world: &HittableList) {
+        let boxes: Vec<Option<(Point3, Point3)>> = world
+            .members
+            .iter()
+            .map(|member| member.bounding_box())
+            .collect();
+        let unbounded: Vec<usize> = (0..boxes.len())
+            .filter(|&index| boxes[index].is_none())
+            .collect();
+        if boxes.len() != self.num_objects || unbounded != self.unbounded {
+            *self = Self::from_bounds(boxes);
+            return;
+        }
+        self.bvh.refit(|index| {
+            boxes[index]
+                .clone()
+                .expect("the members in the hierarchy have bounds")
+        });
+    }
+
+    /// Returns the number of bytes of the hierarchy outside the struct itself.
+    pub fn heap_size(&self) -> usize {
+        self.bvh.heap_size() + self.unbounded.capacity() * std::mem::size_of::<usize>()
+    }
+
+    /// Returns the nearest hit as `HittableList::hit` does, where `hit_member(index, ray_t)`
+    /// tests the `index`-th member, e.g. while timing it.
+    pub fn hit_members(
+        &self,
+        ray: &Ray,
+        ray_t: &Interval,
+        mut hit_member: impl FnMut(usize, &Interval) -> Option<(HitRecord, SharedMaterial)>,
+    ) -> Option<(HitRecord, SharedMaterial)> {
+        let mut maybe_nearest: Option<(HitRecord, SharedMaterial)> = None;
+        let mut ray_t = ray_t.clone();
+        for &index in self.unbounded.iter() {
+            if let Some(pair) = hit_member(index, &ray_t) {
+                ray_t.max = pair.0.t;
+                maybe_nearest = Some(pair);
+            }
+        }
+        self.bvh.traverse(ray, &ray_t, |index, ray_t| {
+            let pair = hit_member(index, ray_t)?;
+            let t = pair.0.t;
+            maybe_nearest = Some(pair);
+            Some(t)
+        });
+        maybe_nearest
+    }
+
+    /// Returns the nearest hit of `ray` with the members of `world`, which the hierarchy is built for.
+    pub fn hit(
+        &self,
+        world: &HittableList,
+        ray: &Ray,
+        ray_t: &Interval,
+    ) -> Option<(HitRecord, SharedMaterial)> {
+        self.hit_members(ray, ray_t, |index, ray_t| {
+            world.members[index].hit(ray, ray_t)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arbitrary::{self, for_all_cases};
+    use crate::color::Attenuation;
+    use crate::geometry::Vec3;
+    use crate::hittable_object::{Hittable, Lambertian, Sphere, Transformed};
+    use rand::Rng;
+    use std::sync::Arc;
+
+    #[test]
+    fn top_level_bvh_tests() {
+        let material: SharedMaterial = Arc::new(Lambertian {
+            albedo: Attenuation {
+                r: 0.5,
+                g: 0.5,
+                b: 0.5,
+            },
+        });
+        for_all_cases(|rng| {
+            let num_members = rng.gen_range(0..20);
+            let members: Vec<Box<dyn Hittable>> = (0..num_members)
+                .map(|_| {
+                    let sphere = Box::new(Sphere {
+                        center: arbitrary::point3(rng, 5.),
+                        radius: rng.gen_range(0.1..1.),
+                        material: material.clone(),
+                    });
+                    if rng.gen_bool(0.3) {
+                        Box::new(Transformed::new(
+                            sphere,
+                            arbitrary::point3(rng, 5.),
+                            arbitrary::vec3(rng, 2.),
+                            arbitrary::vec3(rng, 180.),
+                            rng.gen_range(0.5..2.),
+                        )) as Box<dyn Hittable>
+                    } else {
+                        sphere
+                    }
+                })
+                .collect();
+            let world = HittableList { members };
+            let bvh = TopLevelBvh::new(&world);
+            let ray = Ray {
+                origin: arbitrary::point3(rng, 8.),
+                direction: arbitrary::unit_vec3(rng),
+            };
+            let ray_t = Interval {
+                min: 0.01,
+                max: Float::INFINITY,
+            };
+            let expected = world.hit(&ray, &ray_t).map(|(hit, _)| hit.t);
+            let got = bvh.hit(&world, &ray, &ray_t).map(|(hit, _)| hit.t);
+            assert_eq!(expected, got);
+
+            // The hierarchy refitted to the moved members finds the same hits as another one.
+            let offset = arbitrary::vec3(rng, 3.);
+            let moved = HittableList {
+                members: world
+                    .members
+                    .into_iter()
+                    .map(|member| {
+                        Box::new(Transformed::new(
+                            member,
+                            Point3 {
+                                x: 0.,
+                                y: 0.,
+                                z: 0.,
+                            },
+                            offset.clone(),
+                            Vec3 {
+                                x: 0.,
+                                y: 0.,
+                                z: 0.,
+                            },
+                            1.,
+                        )) as Box<dyn Hittable>
+                    })
+                    .collect(),
+            };
+            let mut refitted = bvh;
+            refitted.refit(&moved);
+            let expected = moved.hit(&ray, &ray_t).map(|(hit, _)| hit.t);
+            let got = refitted.hit(&moved, &ray, &ray_t).map(|(hit, _)| hit.t);
+            assert_eq!(expected, got);
+        });
+    }
+}
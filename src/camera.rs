@@ -1,50 +1,367 @@
-use crate::geometry::{Point3, Ray, UnitVec3, Vec3};
+use crate::geometry::{Float, Point3, Ray, Vec3};
+
+/// The type for the placement of cameras.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CameraPose {
+    pub look_from: Point3,
+    pub look_at: Point3,
+    pub vertical_fov_degree: Float,
+}
+impl CameraPose {
+    /// Returns the pose moved around the vertical axis through `look_at` by `degree`,
+    /// counterclockwise when seen from above.
+    pub fn orbit(&self, degree: Float) -> Self {
+        let (sin, cos) = degree.to_radians().sin_cos();
+        let offset = &self.look_from - &self.look_at;
+        CameraPose {
+            look_from: &self.look_at
+                + Vec3 {
+                    x: cos * offset.x + sin * offset.z,
+                    y: offset.y,
+                    z: -sin * offset.x + cos * offset.z,
+                },
+            look_at: self.look_at.clone(),
+            vertical_fov_degree: self.vertical_fov_degree,
+        }
+    }
+}
+
+/// The type for how cameras project scenes onto images.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Projection {
+    /// Rays spread from the position of the camera by the field of view.
+    Perspective,
+    /// Rays go in parallel to the view direction through a viewport of the height in world units,
+    /// e.g. for top-down maps where sizes do not shrink with the distance.
+    Orthographic { viewport_height: Float },
+    /// Rays spread from the position of the camera in all directions but straight behind,
+    /// which are laid out by the stereographic projection from that direction, so that the field of view
+    /// can be up to 360 degrees, e.g. for "little planet" images looking straight down at the ground.
+    Stereographic,
+}
 
 pub struct Camera {
+    pose: CameraPose,
+    projection: Projection,
     origin: Point3,
+    look_in: Vec3,
     lower_left_corner: Point3,
     horizontal: Vec3,
     vertical: Vec3,
 }
 impl Camera {
-    pub fn new(
-        origin: Point3,
-        look_in: UnitVec3,
+    /// Creates a perspective camera whose view-up vector is the y-axis.
+    pub fn new(pose: CameraPose, aspect_ratio: Float) -> Self {
+        Self::with_projection(pose, Projection::Perspective, aspect_ratio)
+    }
+
+    /// Creates a camera projecting the scene as `projection`, whose view-up vector is the y-axis.
+    /// The field of view of `pose` is ignored by orthographic cameras.
+    pub fn with_projection(pose: CameraPose, projection: Projection, aspect_ratio: Float) -> Self {
+        let view_up = Vec3 {
+            x: 0.,
+            y: 1.,
+            z: 0.,
+        };
+        Self::oriented(pose, projection, view_up, aspect_ratio)
+    }
+
+    /// Creates a perspective camera whose images are upright toward `view_up`,
+    /// e.g. for looking straight up or down, where the y-axis cannot be the view-up vector.
+    pub fn with_view_up(pose: CameraPose, view_up: Vec3, aspect_ratio: Float) -> Self {
+        Self::oriented(pose, Projection::Perspective, view_up, aspect_ratio)
+    }
+
+    /// Creates a stereographic camera at `look_from` looking straight down with the field of view of
+    /// `vertical_fov_degree` (less than 360), whose images are upright toward `forward` projected
+    /// onto the ground, so that the ground around the camera is a "little planet" in the middle
+    /// of the image and the sky surrounds it.
+    pub fn little_planet(
+        look_from: Point3,
+        forward: &Vec3,
+        vertical_fov_degree: Float,
+        aspect_ratio: Float,
+    ) -> Self {
+        let look_at = &look_from
+            - Vec3 {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            };
+        let pose = CameraPose {
+            look_from,
+            look_at,
+            vertical_fov_degree,
+        };
+        let view_up = Vec3 {
+            x: forward.x,
+            y: 0.,
+            z: forward.z,
+        };
+        Self::oriented(pose, Projection::Stereographic, view_up, aspect_ratio)
+    }
+
+    fn oriented(
+        pose: CameraPose,
+        projection: Projection,
         view_up: Vec3,
-        vertical_fov_radian: f64,
-        aspect_ratio: f64,
+        aspect_ratio: Float,
     ) -> Self {
-        let viewport_height: f64 = (vertical_fov_radian / 2.).tan();
-        let viewport_width: f64 = viewport_height * aspect_ratio;
+        let origin = pose.look_from.clone();
+        // Normalizes without `UnitVec3` so that degenerate poses result in NaNs reported by
+        // `validate` instead of failing the debug assertion.
+        let normalize = |v: Vec3| &v / v.length();
+        let look_in = normalize(&pose.look_at - &origin);
+        let viewport_height: Float = match projection {
+            Projection::Perspective => {
+                let vertical_fov_radian = pose.vertical_fov_degree.to_radians();
+                2. * (vertical_fov_radian / 2.).tan()
+            }
+            Projection::Orthographic { viewport_height } => viewport_height,
+            // The stereographic projection puts the directions at the angle of θ from the view direction
+            // at the distance of 2 tan(θ / 2) from the center of the viewport.
+            Projection::Stereographic => {
+                let vertical_fov_radian = pose.vertical_fov_degree.to_radians();
+                4. * (vertical_fov_radian / 4.).tan()
+            }
+        };
+        let viewport_width: Float = viewport_height * aspect_ratio;
 
-        let w = look_in.inject().scale(-1.).unit_vector();
-        let u = view_up.cross_product(&w.inject()).unit_vector();
-        let v = w.inject().cross_product(&u.inject()).unit_vector();
+        let w = -&look_in;
+        let u = normalize(view_up.cross_product(&w));
+        let v = normalize(w.cross_product(&u));
 
-        let horizontal = u.inject().scale(viewport_width);
-        let vertical = v.inject().scale(viewport_height);
+        let horizontal = &u * viewport_width;
+        let vertical = &v * viewport_height;
 
-        let lower_left_corner = origin
-            .add(&horizontal.scale(-0.5))
-            .add(&vertical.scale(-0.5))
-            .add(&look_in.inject());
+        // The viewport of orthographic cameras goes through the camera itself.
+        let lower_left_corner = match projection {
+            Projection::Perspective | Projection::Stereographic => {
+                &origin - &horizontal * 0.5 - &vertical * 0.5 + &look_in
+            }
+            Projection::Orthographic { .. } => &origin - &horizontal * 0.5 - &vertical * 0.5,
+        };
 
         Self {
+            pose,
+            projection,
             origin,
+            look_in,
             lower_left_corner,
             horizontal,
             vertical,
         }
     }
 
-    pub fn get_ray(&self, u: f64, v: f64) -> Ray {
-        let origin = self.origin.clone();
-        let direction = self
-            .lower_left_corner
-            .add(&self.horizontal.scale(u))
-            .add(&self.vertical.scale(v))
-            .subtract(&origin)
-            .unit_vector();
-        Ray { origin, direction }
+    /// Returns a camera at `pose` projecting as `projection`, which keeps the view-up vector
+    /// and the aspect ratio of this camera.
+    pub fn reposed(&self, pose: CameraPose, projection: Projection) -> Self {
+        Self::oriented(pose, projection, self.vertical.clone(), self.aspect_ratio())
+    }
+
+    pub fn pose(&self) -> &CameraPose {
+        &self.pose
+    }
+
+    /// Returns the ratio of the width of the images to their height.
+    pub fn aspect_ratio(&self) -> Float {
+        self.horizontal.length() / self.vertical.length()
+    }
+
+    pub fn projection(&self) -> &Projection {
+        &self.projection
+    }
+
+    /// Returns the problems of the camera, e.g. NaNs caused by a zero-length look direction
+    /// or by a look direction parallel to the view-up vector.
+    pub fn validate(&self) -> Vec<String> {
+        let is_finite = self.origin.is_finite()
+            && self.lower_left_corner.is_finite()
+            && self.horizontal.is_finite()
+            && self.vertical.is_finite();
+        if !self.origin.is_finite() {
+            vec![format!("origin must be finite, but is {:?}", self.origin)]
+        } else if !is_finite
+            || self.horizontal.length_squared() == 0.
+            || self.vertical.length_squared() == 0.
+        {
+            vec![
+                "the look direction must be non-zero and not parallel to the view-up vector, \
+                  and the field of view must be in (0, 180) degrees, or (0, 360) for stereographic ones"
+                    .to_string(),
+            ]
+        } else {
+            vec![]
+        }
+    }
+
+    /// Returns the coordinates `(u, v)` for which `get_ray` goes through `point`,
+    /// or `None` if the point is not in front of the camera.
+    pub fn project(&self, point: &Point3) -> Option<(Float, Float)> {
+        let on_viewport = match self.projection {
+            Projection::Perspective => {
+                let offset = point - &self.origin;
+                let along = offset.inner_product(&self.look_in);
+                if along <= 0. {
+                    return None;
+                }
+                &self.origin + &offset / along
+            }
+            Projection::Orthographic { .. } => point.clone(),
+            Projection::Stereographic => {
+                let direction = point - &self.origin;
+                let direction = &direction / direction.length();
+                let cos = direction.inner_product(&self.look_in);
+                // The direction straight behind the camera is at infinity.
+                if cos <= -1. {
+                    return None;
+                }
+                let sideways = &direction - &self.look_in * cos;
+                &self.origin + &self.look_in + &sideways * (2. / (1. + cos))
+            }
+        };
+        // The offset along the view direction is orthogonal to both edges of the viewport.
+        let offset = &on_viewport - &self.lower_left_corner;
+        Some((
+            offset.inner_product(&self.horizontal) / self.horizontal.length_squared(),
+            offset.inner_product(&self.vertical) / self.vertical.length_squared(),
+        ))
+    }
+
+    pub fn get_ray(&self, u: Float, v: Float) -> Ray {
+        let point = &self.lower_left_corner + &self.horizontal * u + &self.vertical * v;
+        match self.projection {
+            Projection::Perspective => {
+                let origin = self.origin.clone();
+                let direction = (point - &origin).unit_vector();
+                Ray { origin, direction }
+            }
+            Projection::Orthographic { .. } => Ray {
+                origin: point,
+                direction: self.look_in.unit_vector(),
+            },
+            Projection::Stereographic => {
+                let sideways = &point - &self.origin - &self.look_in;
+                let distance = sideways.length();
+                let direction = if distance > 0. {
+                    let angle = 2. * (distance / 2.).atan();
+                    &self.look_in * angle.cos() + &sideways * (angle.sin() / distance)
+                } else {
+                    self.look_in.clone()
+                };
+                Ray {
+                    origin: self.origin.clone(),
+                    direction: direction.unit_vector(),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx::assert_approx_eq;
+
+    #[test]
+    fn orbit_tests() {
+        let pose = CameraPose {
+            look_from: Point3 {
+                x: 0.,
+                y: 1.,
+                z: 2.,
+            },
+            look_at: Point3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            },
+            vertical_fov_degree: 40.,
+        };
+        let orbited = pose.orbit(90.);
+        // A quarter turn counterclockwise seen from above moves the camera from +z to +x.
+        let expected = Point3 {
+            x: 3.,
+            y: 1.,
+            z: -1.,
+        };
+        assert_approx_eq!(expected, orbited.look_from);
+        assert_eq!(pose.look_at, orbited.look_at);
+        assert_approx_eq!(pose.look_from, pose.orbit(360.).look_from);
+    }
+
+    #[test]
+    fn project_tests() {
+        let pose = CameraPose {
+            look_from: Point3 {
+                x: 1.,
+                y: 2.,
+                z: 3.,
+            },
+            look_at: Point3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            },
+            vertical_fov_degree: 50.,
+        };
+        let projections = [
+            Projection::Perspective,
+            Projection::Orthographic {
+                viewport_height: 4.,
+            },
+            Projection::Stereographic,
+        ];
+        for projection in projections {
+            let camera = Camera::with_projection(pose.clone(), projection, 1.5);
+            // The points along the rays are projected back to the coordinates of the rays.
+            for (u, v) in [(0.5, 0.5), (0., 1.), (0.2, 0.7), (1.3, -0.1)] {
+                let point = camera.get_ray(u, v).at(3.);
+                let (projected_u, projected_v) = camera.project(&point).unwrap();
+                assert_approx_eq!(u, projected_u);
+                assert_approx_eq!(v, projected_v);
+            }
+        }
+        let camera = Camera::new(pose.clone(), 1.5);
+        assert_eq!(None, camera.project(&camera.get_ray(0.5, 0.5).at(-1.)));
+    }
+
+    #[test]
+    fn little_planet_tests() {
+        let look_from = Point3 {
+            x: 1.,
+            y: 2.,
+            z: 3.,
+        };
+        let forward = Vec3 {
+            x: 0.,
+            y: -0.5,
+            z: -1.,
+        };
+        let camera = Camera::little_planet(look_from.clone(), &forward, 300., 1.);
+        assert!(camera.validate().is_empty());
+        let down = Vec3 {
+            x: 0.,
+            y: -1.,
+            z: 0.,
+        };
+        let ray = camera.get_ray(0.5, 0.5);
+        assert_approx_eq!(look_from, ray.origin);
+        assert_approx_eq!(down, ray.direction.inject());
+        // The top edge is 150 degrees away from straight down toward the forward direction.
+        let (sin, cos) = (150. as Float).to_radians().sin_cos();
+        let expected = Vec3 {
+            x: 0.,
+            y: -cos,
+            z: -sin,
+        };
+        assert_approx_eq!(expected, camera.get_ray(0.5, 1.).direction.inject());
+        // Only the direction straight up cannot be projected.
+        let up = Point3 {
+            x: 1.,
+            y: 3.,
+            z: 3.,
+        };
+        assert_eq!(None, camera.project(&up));
     }
 }
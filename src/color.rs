@@ -1,19 +1,48 @@
-// All fields are in [0, 1].
+use std::io::{self, Write};
+
+use crate::geometry::Float;
+use crate::interval::Interval;
+
+/// The type for linear RGB quantities of light, which is used under the names of its two roles:
+/// `Color` for amounts of light, e.g. radiance, which may be brighter than white,
+/// and `Attenuation` for the fractions of light passed on, e.g. albedos, which are in [0, 1].
+/// They are a single type so that light is attenuated, added, and textured without conversions.
 #[derive(Clone, Debug, PartialEq)]
-pub struct Color {
-    pub r: f64,
-    pub g: f64,
-    pub b: f64,
+pub struct Spectrum {
+    pub r: Float,
+    pub g: Float,
+    pub b: Float,
 }
-impl Color {
-    pub fn write(&self) {
-        let ir = (255.999 * self.r) as u8;
-        let ig = (255.999 * self.g) as u8;
-        let ib = (255.999 * self.b) as u8;
-        println!("{} {} {}", ir, ig, ib);
+
+/// An amount of light, e.g. the radiance along a ray or a pixel of a film.
+pub type Color = Spectrum;
+
+/// A fraction of light passed on, e.g. by a surface, in [0, 1] for each channel.
+pub type Attenuation = Spectrum;
+
+impl Spectrum {
+    /// Checks that all the fields are finite and non-negative.
+    pub fn is_valid(&self) -> bool {
+        [self.r, self.g, self.b]
+            .iter()
+            .all(|c| c.is_finite() && *c >= 0.)
+    }
+
+    /// Converts the color into 8-bit values, clamping out-of-range components.
+    pub fn to_bytes(&self) -> [u8; 3] {
+        let intensity = Interval {
+            min: 0.,
+            max: 0.999,
+        };
+        [self.r, self.g, self.b].map(|c| (256. * intensity.clamp(c)) as u8)
+    }
+
+    pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        let [ir, ig, ib] = self.to_bytes();
+        writeln!(out, "{} {} {}", ir, ig, ib)
     }
 
-    pub fn blend(&self, t: f64, other: &Self) -> Self {
+    pub fn blend(&self, t: Float, other: &Self) -> Self {
         Self {
             r: (1. - t) * self.r + t * other.r,
             g: (1. - t) * self.g + t * other.g,
@@ -21,8 +50,15 @@ impl Color {
         }
     }
 
-    #[allow(dead_code)]
-    pub fn scale(&self, t: f64) -> Self {
+    pub fn add(&self, other: &Self) -> Self {
+        Self {
+            r: self.r + other.r,
+            g: self.g + other.g,
+            b: self.b + other.b,
+        }
+    }
+
+    pub fn scale(&self, t: Float) -> Self {
         Self {
             r: self.r * t,
             g: self.g * t,
@@ -38,28 +74,19 @@ impl Color {
         }
     }
 
-    pub fn average(colors: &Vec<Self>) -> Self {
-        let mut r: f64 = 0.;
-        let mut g: f64 = 0.;
-        let mut b: f64 = 0.;
-        for color in colors.iter() {
-            r += color.r;
-            g += color.g;
-            b += color.b;
-        }
-        let num = colors.len() as f64;
+    /// Performs Gamma Correction.
+    pub fn gamma_correct(&self) -> Self {
         Self {
-            r: r / num,
-            g: g / num,
-            b: b / num,
+            r: self.r.sqrt(),
+            g: self.g.sqrt(),
+            b: self.b.sqrt(),
         }
     }
-}
 
-// All fields are in [0, 1].
-#[derive(Clone, Debug, PartialEq)]
-pub struct Attenuation {
-    pub r: f64,
-    pub g: f64,
-    pub b: f64,
+    /// Checks that all the fields are in [0, 1], as those of attenuations must be.
+    pub fn is_attenuation(&self) -> bool {
+        [self.r, self.g, self.b]
+            .iter()
+            .all(|c| (0. ..=1.).contains(c))
+    }
 }
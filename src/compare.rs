@@ -0,0 +1,670 @@
+extern crate png;
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::color::Color;
+use crate::error::RenderError;
+use crate::geometry::{consts, Float};
+
+/// The type for images as they are displayed, e.g. renders read from files for comparison,
+/// whose colors are gamma-corrected values in [0, 1] stored row by row from the top.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+}
+impl Image {
+    /// Loads a PNG image if the file name ends with `.png`, or a PPM image (`P3` or `P6`) otherwise.
+    /// The alpha channel is ignored.
+    pub fn load(path: &Path) -> Result<Self, RenderError> {
+        let mut file = File::open(path).map_err(|e| RenderError::io(path, e))?;
+        let is_png = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("png"));
+        if is_png {
+            return read_png(file).map_err(|e| match e {
+                png::DecodingError::IoError(e) => RenderError::io(path, e),
+                e => RenderError::SceneParse {
+                    path: path.to_path_buf(),
+                    message: e.to_string(),
+                },
+            });
+        }
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes)
+            .map_err(|e| RenderError::io(path, e))?;
+        parse_ppm(&bytes).map_err(|message| RenderError::SceneParse {
+            path: path.to_path_buf(),
+            message,
+        })
+    }
+
+    /// Returns the image as RGB bytes in row-major order.
+    pub fn to_rgb8(&self) -> Vec<u8> {
+        self.pixels.iter().flat_map(Color::to_bytes).collect()
+    }
+
+    /// Writes the image in the plain PPM format.
+    pub fn write_ppm(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "P3")?;
+        writeln!(out, "{} {}", self.width, self.height)?;
+        writeln!(out, "255")?;
+        for pixel in self.pixels.iter() {
+            pixel.write(out)?;
+        }
+        out.flush()
+    }
+
+    /// Writes the image in the PNG format.
+    pub fn write_png(&self, out: &mut impl Write) -> Result<(), png::EncodingError> {
+        let mut encoder = png::Encoder::new(out, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.to_rgb8())?;
+        writer.finish()
+    }
+}
+
+fn read_png(file: File) -> Result<Image, png::DecodingError> {
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data)?;
+    let num_channels = info.color_type.samples();
+    let decode = |byte: u8| byte as Float / 255.;
+    let pixels = data[..info.buffer_size()]
+        .chunks(num_channels)
+        .map(|pixel| match pixel {
+            [gray] | [gray, _] => {
+                let c = decode(*gray);
+                Color { r: c, g: c, b: c }
+            }
+            [r, g, b, ..] => Color {
+                r: decode(*r),
+                g: decode(*g),
+                b: decode(*b),
+            },
+            _ => unreachable!("PNG images have 1 to 4 channels"),
+        })
+        .collect();
+    Ok(Image {
+        width: info.width as usize,
+        height: info.height as usize,
+        pixels,
+    })
+}
+
+/// Parses a PPM image in the plain (`P3`) or the raw (`P6`) format, whose maximum value is at most 255
+/// for the raw format.
+pub fn parse_ppm(bytes: &[u8]) -> Result<Image, String> {
+    // The header is whitespace-separated tokens, where `#` starts a comment up to the end of the line.
+    let mut position = 0;
+    let mut next_token = || {
+        loop {
+            match bytes.get(position) {
+                Some(b'#') => {
+                    while bytes.get(position).is_some_and(|&b| b != b'\n') {
+                        position += 1;
+                    }
+                }
+                Some(b) if b.is_ascii_whitespace() => position += 1,
+                _ => break,
+            }
+        }
+        let start = position;
+        while bytes
+            .get(position)
+            .is_some_and(|b| !b.is_ascii_whitespace())
+        {
+            position += 1;
+        }
+        std::str::from_utf8(&bytes[start..position])
+            .ok()
+            .filter(|token| !token.is_empty())
+            .map(|token| (token.to_string(), position))
+    };
+    let magic = next_token().ok_or("empty file")?.0;
+    if magic != "P3" && magic != "P6" {
+        return Err("not a PPM image (expected P3 or P6)".to_string());
+    }
+    let mut number = |name: &str| -> Result<(usize, usize), String> {
+        let (token, end) = next_token().ok_or_else(|| format!("missing {}", name))?;
+        let value = token
+            .parse()
+            .map_err(|_| format!("invalid {}: {}", name, token))?;
+        Ok((value, end))
+    };
+    let (width, _) = number("width")?;
+    let (height, _) = number("height")?;
+    let (max_value, header_end) = number("maximum value")?;
+    if max_value == 0 || max_value > u16::MAX as usize {
+        return Err(format!("invalid maximum value: {}", max_value));
+    }
+    let num_values = width * height * 3;
+    let values: Vec<usize> = if magic == "P3" {
+        let values = (0..num_values)
+            .map(|_| number("pixel value").map(|(value, _)| value))
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(value) = values.iter().find(|&&value| value > max_value) {
+            return Err(format!(
+                "pixel value {} exceeds the maximum value {}",
+                value, max_value
+            ));
+        }
+        values
+    } else {
+        if max_value > u8::MAX as usize {
+            return Err("raw PPM images with 16-bit values are not supported".to_string());
+        }
+        // A single whitespace character separates the header from the pixels.
+        let data = bytes.get(header_end + 1..).unwrap_or(&[]);
+        if data.len() < num_values {
+            return Err(format!(
+                "expected {} bytes of pixels, but the file has {}",
+                num_values,
+                data.len()
+            ));
+        }
+        data[..num_values].iter().map(|&b| b as usize).collect()
+    };
+    let decode = |value: usize| value as Float / max_value as Float;
+    let pixels = values
+        .chunks(3)
+        .map(|rgb| Color {
+            r: decode(rgb[0]),
+            g: decode(rgb[1]),
+            b: decode(rgb[2]),
+        })
+        .collect();
+    Ok(Image {
+        width,
+        height,
+        pixels,
+    })
+}
+
+/// The pixels per degree of visual angle assumed by FLIP by default,
+/// i.e., a 0.7 m wide 4K monitor seen from 0.7 m away.
+pub const DEFAULT_PIXELS_PER_DEGREE: Float = 67.;
+
+/// The type for the differences between two images.
+pub struct Comparison {
+    pub width: usize,
+    pub height: usize,
+    /// The root mean square error of the displayed values of all the components.
+    pub rmse: Float,
+    /// The peak signal-to-noise ratio in decibels, which is infinite for identical images.
+    pub psnr: Float,
+    /// The mean of `flip_errors`.
+    pub flip: Float,
+    /// The FLIP error of each pixel in [0, 1] in row-major order, which estimates
+    /// how noticeable the difference is when flipping between the images.
+    pub flip_errors: Vec<Float>,
+}
+impl Comparison {
+    /// Returns the image of `flip_errors`, from black for no error through purple and orange
+    /// to pale yellow for the largest error, as in the magma color map used by FLIP.
+    pub fn heatmap(&self) -> Image {
+        Image {
+            width: self.width,
+            height: self.height,
+            pixels: self.flip_errors.iter().map(|&e| magma(e)).collect(),
+        }
+    }
+}
+
+/// Compares `test` with `reference`, which must be of the same size, where FLIP assumes that
+/// the images are viewed with `pixels_per_degree` pixels per degree of visual angle.
+pub fn compare(
+    reference: &Image,
+    test: &Image,
+    pixels_per_degree: Float,
+) -> Result<Comparison, String> {
+    if (reference.width, reference.height) != (test.width, test.height) {
+        return Err(format!(
+            "the images have different sizes: {}x{} and {}x{}",
+            reference.width, reference.height, test.width, test.height
+        ));
+    }
+    if reference.pixels.is_empty() {
+        return Err("the images are empty".to_string());
+    }
+    let squared_error: Float = reference
+        .pixels
+        .iter()
+        .zip(test.pixels.iter())
+        .map(|(r, t)| (r.r - t.r).powi(2) + (r.g - t.g).powi(2) + (r.b - t.b).powi(2))
+        .sum();
+    let rmse = (squared_error / (3 * reference.pixels.len()) as Float).sqrt();
+    let psnr = -20. * rmse.log10();
+    let flip_errors = flip(reference, test, pixels_per_degree);
+    let flip = flip_errors.iter().sum::<Float>() / flip_errors.len() as Float;
+    Ok(Comparison {
+        width: reference.width,
+        height: reference.height,
+        rmse,
+        psnr,
+        flip,
+        flip_errors,
+    })
+}
+
+// The parameters of FLIP; see "FLIP: A Difference Evaluator for Alternating Images"
+// by Andersson et al. (2020).
+/// The exponents compressing the color and the feature differences.
+const FLIP_COLOR_EXPONENT: Float = 0.7;
+const FLIP_FEATURE_EXPONENT: Float = 0.5;
+/// The fraction of the maximum color difference mapped to `FLIP_COLOR_ERROR_THRESHOLD`,
+/// above which color differences are compressed into the rest of [0, 1].
+const FLIP_COLOR_DIFFERENCE_THRESHOLD: Float = 0.4;
+const FLIP_COLOR_ERROR_THRESHOLD: Float = 0.95;
+/// The width in degrees of the edges and the points found by the feature detection.
+const FLIP_FEATURE_WIDTH: Float = 0.082;
+
+/// Returns the FLIP error of each pixel, comparing the colors filtered by the contrast sensitivity
+/// of human vision and weighting them up by the differences of the edges and the points.
+fn flip(reference: &Image, test: &Image, pixels_per_degree: Float) -> Vec<Float> {
+    let (width, height) = (reference.width, reference.height);
+    let max_color_difference = hyab(
+        &hunt_adjust(linear_to_lab([0., 1., 0.])),
+        &hunt_adjust(linear_to_lab([0., 0., 1.])),
+    )
+    .powf(FLIP_COLOR_EXPONENT);
+    let filters = SpatialFilters::new(pixels_per_degree);
+    let (edge_kernel, point_kernel) = feature_kernels(pixels_per_degree);
+    let prepare = |image: &Image| {
+        let ycxcz: Vec<[Float; 3]> = image
+            .pixels
+            .iter()
+            .map(|pixel| {
+                xyz_to_ycxcz(linear_to_xyz(
+                    [pixel.r, pixel.g, pixel.b].map(srgb_to_linear),
+                ))
+            })
+            .collect();
+        let filtered: Vec<[Float; 3]> = filters
+            .apply(&ycxcz, width, height)
+            .into_iter()
+            .map(|c| {
+                let linear = xyz_to_linear(ycxcz_to_xyz(c)).map(|c| c.clamp(0., 1.));
+                hunt_adjust(linear_to_lab(linear))
+            })
+            .collect();
+        // The features are found in the achromatic channel normalized to [0, 1].
+        let luminance: Vec<Float> = ycxcz.iter().map(|c| (c[0] + 16.) / 116.).collect();
+        let edges = gradient_magnitude(&luminance, width, height, &edge_kernel);
+        let points = gradient_magnitude(&luminance, width, height, &point_kernel);
+        (filtered, edges, points)
+    };
+    let (reference_colors, reference_edges, reference_points) = prepare(reference);
+    let (test_colors, test_edges, test_points) = prepare(test);
+    (0..width * height)
+        .map(|index| {
+            let color_difference =
+                hyab(&reference_colors[index], &test_colors[index]).powf(FLIP_COLOR_EXPONENT);
+            let threshold = FLIP_COLOR_DIFFERENCE_THRESHOLD * max_color_difference;
+            let color_error = if color_difference < threshold {
+                FLIP_COLOR_ERROR_THRESHOLD / threshold * color_difference
+            } else {
+                FLIP_COLOR_ERROR_THRESHOLD
+                    + (color_difference - threshold) / (max_color_difference - threshold)
+                        * (1. - FLIP_COLOR_ERROR_THRESHOLD)
+            };
+            let feature_difference = (reference_edges[index] - test_edges[index])
+                .abs()
+                .max((reference_points[index] - test_points[index]).abs());
+            let feature_error = (feature_difference / consts::SQRT_2).powf(FLIP_FEATURE_EXPONENT);
+            color_error.powf(1. - feature_error).clamp(0., 1.)
+        })
+        .collect()
+}
+
+/// The type for the filters modeling the contrast sensitivity of human vision
+/// for the achromatic, the red-green, and the blue-yellow channels.
+struct SpatialFilters {
+    radius: usize,
+    /// The normalized square kernels of the channels in row-major order.
+    kernels: [Vec<Float>; 3],
+}
+impl SpatialFilters {
+    fn new(pixels_per_degree: Float) -> Self {
+        // The weights and the widths of the two Gaussians of each channel.
+        let parameters: [(Float, Float, Float, Float); 3] = [
+            (1., 0.0047, 0., 1e-5),
+            (1., 0.0053, 0., 1e-5),
+            (34.1, 0.04, 13.5, 0.025),
+        ];
+        let max_width = 0.04;
+        let radius = (3. * (max_width / (2. * consts::PI * consts::PI)).sqrt() * pixels_per_degree)
+            .ceil() as usize;
+        let pi_squared = consts::PI * consts::PI;
+        let kernels = parameters.map(|(a1, b1, a2, b2)| {
+            let kernel = square_kernel(radius, |x, y| {
+                let z = (x * x + y * y) / (pixels_per_degree * pixels_per_degree);
+                a1 * (consts::PI / b1).sqrt() * (-pi_squared * z / b1).exp()
+                    + a2 * (consts::PI / b2).sqrt() * (-pi_squared * z / b2).exp()
+            });
+            let sum: Float = kernel.iter().sum();
+            kernel.into_iter().map(|w| w / sum).collect()
+        });
+        SpatialFilters { radius, kernels }
+    }
+
+    fn apply(&self, image: &[[Float; 3]], width: usize, height: usize) -> Vec<[Float; 3]> {
+        let mut filtered = vec![[0.; 3]; image.len()];
+        for (channel, kernel) in self.kernels.iter().enumerate() {
+            let values: Vec<Float> = image.iter().map(|c| c[channel]).collect();
+            let values = convolve(&values, width, height, kernel, self.radius);
+            for (pixel, value) in filtered.iter_mut().zip(values) {
+                pixel[channel] = value;
+            }
+        }
+        filtered
+    }
+}
+
+/// Returns the kernels of the first and the second derivatives of Gaussians along x,
+/// which find edges and points respectively, where the positive and the negative weights of each
+/// sum to 1 and -1.
+fn feature_kernels(pixels_per_degree: Float) -> (Vec<Float>, Vec<Float>) {
+    let sigma = 0.5 * FLIP_FEATURE_WIDTH * pixels_per_degree;
+    let radius = (3. * sigma).ceil() as usize;
+    let gaussian = |x: Float, y: Float| (-(x * x + y * y) / (2. * sigma * sigma)).exp();
+    let normalize = |kernel: Vec<Float>| {
+        let positive: Float = kernel.iter().filter(|&&w| w > 0.).sum();
+        let negative: Float = -kernel.iter().filter(|&&w| w < 0.).sum::<Float>();
+        kernel
+            .into_iter()
+            .map(|w| if w > 0. { w / positive } else { w / negative })
+            .collect()
+    };
+    let edge = normalize(square_kernel(radius, |x, y| -x * gaussian(x, y)));
+    let point = normalize(square_kernel(radius, |x, y| {
+        (x * x / (sigma * sigma) - 1.) * gaussian(x, y)
+    }));
+    (edge, point)
+}
+
+/// Returns the kernel of the side `2 * radius + 1` whose weight at `(x, y)` from the center is `weight`.
+fn square_kernel(radius: usize, weight: impl Fn(Float, Float) -> Float) -> Vec<Float> {
+    let side = 2 * radius + 1;
+    (0..side * side)
+        .map(|index| {
+            let x = (index % side) as Float - radius as Float;
+            let y = (index / side) as Float - radius as Float;
+            weight(x, y)
+        })
+        .collect()
+}
+
+/// Convolves `values` with the square `kernel` of `radius`, extending the borders of the image.
+fn convolve(
+    values: &[Float],
+    width: usize,
+    height: usize,
+    kernel: &[Float],
+    radius: usize,
+) -> Vec<Float> {
+    let side = 2 * radius + 1;
+    let clamp = |i: usize, n: usize| i.saturating_sub(radius).min(n - 1);
+    (0..width * height)
+        .map(|index| {
+            let (x, y) = (index % width, index / width);
+            let mut sum = 0.;
+            for ky in 0..side {
+                let row = clamp(y + ky, height) * width;
+                for kx in 0..side {
+                    sum += kernel[ky * side + kx] * values[row + clamp(x + kx, width)];
+                }
+            }
+            sum
+        })
+        .collect()
+}
+
+/// Returns the magnitudes of the responses to `kernel` along x and to its transpose along y.
+fn gradient_magnitude(
+    values: &[Float],
+    width: usize,
+    height: usize,
+    kernel: &[Float],
+) -> Vec<Float> {
+    let side = (kernel.len() as Float).sqrt() as usize;
+    let radius = side / 2;
+    let transposed: Vec<Float> = (0..kernel.len())
+        .map(|index| kernel[(index % side) * side + index / side])
+        .collect();
+    let along_x = convolve(values, width, height, kernel, radius);
+    let along_y = convolve(values, width, height, &transposed, radius);
+    along_x
+        .iter()
+        .zip(along_y.iter())
+        .map(|(x, y)| (x * x + y * y).sqrt())
+        .collect()
+}
+
+fn srgb_to_linear(c: Float) -> Float {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_xyz([r, g, b]: [Float; 3]) -> [Float; 3] {
+    [
+        0.4124564 * r + 0.3575761 * g + 0.1804375 * b,
+        0.2126729 * r + 0.7151522 * g + 0.072175 * b,
+        0.0193339 * r + 0.119192 * g + 0.9503041 * b,
+    ]
+}
+
+fn xyz_to_linear([x, y, z]: [Float; 3]) -> [Float; 3] {
+    [
+        3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        -0.969266 * x + 1.8760108 * y + 0.041556 * z,
+        0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+    ]
+}
+
+/// Returns the XYZ of the white point (D65), i.e., of the linear RGB (1, 1, 1).
+fn white_point() -> [Float; 3] {
+    linear_to_xyz([1., 1., 1.])
+}
+
+/// Converts XYZ into the opponent color space YCxCz, which is linear unlike L*a*b*
+/// so that it can be filtered.
+fn xyz_to_ycxcz([x, y, z]: [Float; 3]) -> [Float; 3] {
+    let [xn, yn, zn] = white_point();
+    [
+        116. * y / yn - 16.,
+        500. * (x / xn - y / yn),
+        200. * (y / yn - z / zn),
+    ]
+}
+
+fn ycxcz_to_xyz([l, cx, cz]: [Float; 3]) -> [Float; 3] {
+    let [xn, yn, zn] = white_point();
+    let y = (l + 16.) / 116.;
+    [(cx / 500. + y) * xn, y * yn, (y - cz / 200.) * zn]
+}
+
+fn linear_to_lab(rgb: [Float; 3]) -> [Float; 3] {
+    let [x, y, z] = linear_to_xyz(rgb);
+    let [xn, yn, zn] = white_point();
+    let delta: Float = 6. / 29.;
+    let f = |t: Float| {
+        if t > delta.powi(3) {
+            t.cbrt()
+        } else {
+            t / (3. * delta * delta) + 4. / 29.
+        }
+    };
+    let (fx, fy, fz) = (f(x / xn), f(y / yn), f(z / zn));
+    [116. * fy - 16., 500. * (fx - fy), 200. * (fy - fz)]
+}
+
+/// Scales the chroma by the lightness as the Hunt effect, which makes dark colors look less colorful.
+fn hunt_adjust([l, a, b]: [Float; 3]) -> [Float; 3] {
+    [l, 0.01 * l * a, 0.01 * l * b]
+}
+
+/// The HyAB color difference, which suits large differences better than the Euclidean distance.
+fn hyab(lab1: &[Float; 3], lab2: &[Float; 3]) -> Float {
+    let (dl, da, db) = (lab1[0] - lab2[0], lab1[1] - lab2[1], lab1[2] - lab2[2]);
+    dl.abs() + (da * da + db * db).sqrt()
+}
+
+/// Returns the color of `t` in [0, 1] interpolated between the key colors of the magma color map.
+fn magma(t: Float) -> Color {
+    const KEYS: [[Float; 3]; 5] = [
+        [0., 0., 4.],
+        [81., 18., 124.],
+        [183., 55., 121.],
+        [252., 137., 97.],
+        [252., 253., 191.],
+    ];
+    let position = t.clamp(0., 1.) * (KEYS.len() - 1) as Float;
+    let index = (position as usize).min(KEYS.len() - 2);
+    let fraction = position - index as Float;
+    let [r, g, b] = [0, 1, 2].map(|channel| {
+        let (from, to) = (KEYS[index][channel], KEYS[index + 1][channel]);
+        (from + (to - from) * fraction) / 255.
+    });
+    Color { r, g, b }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx::assert_approx_eq;
+
+    fn gradient_image(width: usize, height: usize, shade: impl Fn(usize, usize) -> Float) -> Image {
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let c = shade(x, y);
+                Color { r: c, g: c, b: c }
+            })
+            .collect();
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    #[test]
+    fn parse_ppm_tests() {
+        let plain = b"P3\n# a comment\n2 1\n255\n255 0 0  0 0 255\n";
+        let image = parse_ppm(plain).unwrap();
+        assert_eq!((2, 1), (image.width, image.height));
+        assert_eq!(
+            vec![
+                Color {
+                    r: 1.,
+                    g: 0.,
+                    b: 0.
+                },
+                Color {
+                    r: 0.,
+                    g: 0.,
+                    b: 1.
+                },
+            ],
+            image.pixels
+        );
+        let mut raw = b"P6 2 1 255\n".to_vec();
+        raw.extend([255, 0, 0, 0, 0, 255]);
+        assert_eq!(image, parse_ppm(&raw).unwrap());
+
+        // The images written by `write_ppm` are read back.
+        let mut written = vec![];
+        image.write_ppm(&mut written).unwrap();
+        assert_eq!(image, parse_ppm(&written).unwrap());
+
+        assert!(parse_ppm(b"P5 1 1 255\n\0").is_err());
+        assert_eq!(
+            Err("missing pixel value".to_string()),
+            parse_ppm(b"P3 2 1 255 0 0 0").map(|_| ())
+        );
+        assert_eq!(
+            Err("pixel value 300 exceeds the maximum value 255".to_string()),
+            parse_ppm(b"P3 1 1 255 300 0 0").map(|_| ())
+        );
+        assert!(parse_ppm(b"P6 2 1 255\n\0\0\0").is_err());
+    }
+
+    #[test]
+    fn compare_tests() {
+        let reference = gradient_image(32, 16, |x, _| x as Float / 31.);
+        let comparison = compare(&reference, &reference, DEFAULT_PIXELS_PER_DEGREE).unwrap();
+        assert_eq!(0., comparison.rmse);
+        assert_eq!(Float::INFINITY, comparison.psnr);
+        assert_approx_eq!(0., comparison.flip, 1e-6);
+
+        // A uniform offset of 0.1 gives the RMSE 0.1 and the PSNR 20 dB.
+        let brighter = gradient_image(32, 16, |x, _| x as Float / 31. * 0.8 + 0.1);
+        let darker = gradient_image(32, 16, |x, _| x as Float / 31. * 0.8);
+        let comparison = compare(&darker, &brighter, DEFAULT_PIXELS_PER_DEGREE).unwrap();
+        assert_approx_eq!(0.1, comparison.rmse, 1e-6);
+        assert_approx_eq!(20., comparison.psnr, 1e-4);
+        assert!(
+            comparison.flip > 0. && comparison.flip < 1.,
+            "{}",
+            comparison.flip
+        );
+
+        // Larger differences are more noticeable.
+        let slightly_brighter = gradient_image(32, 16, |x, _| x as Float / 31. * 0.8 + 0.02);
+        let slight_flip = compare(&darker, &slightly_brighter, DEFAULT_PIXELS_PER_DEGREE)
+            .unwrap()
+            .flip;
+        assert!(
+            slight_flip < comparison.flip,
+            "{} {}",
+            slight_flip,
+            comparison.flip
+        );
+
+        // Noise of single pixels is less noticeable from farther away, i.e., with more pixels per degree.
+        let noisy = gradient_image(32, 16, |x, y| {
+            let c = x as Float / 31.;
+            if (x + y) % 2 == 0 {
+                (c + 0.1).min(1.)
+            } else {
+                (c - 0.1).max(0.)
+            }
+        });
+        let near_flip = compare(&reference, &noisy, 10.).unwrap().flip;
+        let far_flip = compare(&reference, &noisy, DEFAULT_PIXELS_PER_DEGREE)
+            .unwrap()
+            .flip;
+        assert!(far_flip < near_flip, "{} {}", far_flip, near_flip);
+
+        // The heatmap is black where the images agree.
+        let square = gradient_image(32, 16, |x, y| {
+            if (12..20).contains(&x) && (4..12).contains(&y) {
+                1.
+            } else {
+                0.
+            }
+        });
+        let black = gradient_image(32, 16, |_, _| 0.);
+        let comparison = compare(&black, &square, DEFAULT_PIXELS_PER_DEGREE).unwrap();
+        let heatmap = comparison.heatmap();
+        assert_eq!(magma(0.), heatmap.pixels[0]);
+        assert!(comparison.flip_errors[8 * 32 + 16] > 0.5);
+
+        assert!(compare(&reference, &black_of(16, 16), DEFAULT_PIXELS_PER_DEGREE).is_err());
+    }
+
+    fn black_of(width: usize, height: usize) -> Image {
+        gradient_image(width, height, |_, _| 0.)
+    }
+}
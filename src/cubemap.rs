@@ -0,0 +1,156 @@
+use crate::camera::{Camera, CameraPose};
+use crate::film::Film;
+use crate::geometry::{Point3, Vec3};
+
+/// The faces of cubemaps, i.e., the images of the six directions along the axes seen from a point,
+/// which other programs use as environment maps.
+/// The images of the faces around the y-axis are upright toward +y, and those of +y and -y are
+/// upright toward +z and -z respectively, so that every face continues seamlessly into its neighbors
+/// in the layout of `assemble_cross`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CubeFace {
+    PositiveX,
+    NegativeX,
+    PositiveY,
+    NegativeY,
+    PositiveZ,
+    NegativeZ,
+}
+impl CubeFace {
+    pub const ALL: [CubeFace; 6] = [
+        CubeFace::PositiveX,
+        CubeFace::NegativeX,
+        CubeFace::PositiveY,
+        CubeFace::NegativeY,
+        CubeFace::PositiveZ,
+        CubeFace::NegativeZ,
+    ];
+
+    /// Returns the short name of the face for file names, e.g. `px` for `PositiveX`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            CubeFace::PositiveX => "px",
+            CubeFace::NegativeX => "nx",
+            CubeFace::PositiveY => "py",
+            CubeFace::NegativeY => "ny",
+            CubeFace::PositiveZ => "pz",
+            CubeFace::NegativeZ => "nz",
+        }
+    }
+
+    /// Returns the direction the face looks in and the direction its image is upright toward.
+    fn axes(&self) -> (Vec3, Vec3) {
+        let vec = |x, y, z| Vec3 { x, y, z };
+        match self {
+            CubeFace::PositiveX => (vec(1., 0., 0.), vec(0., 1., 0.)),
+            CubeFace::NegativeX => (vec(-1., 0., 0.), vec(0., 1., 0.)),
+            CubeFace::PositiveY => (vec(0., 1., 0.), vec(0., 0., 1.)),
+            CubeFace::NegativeY => (vec(0., -1., 0.), vec(0., 0., -1.)),
+            CubeFace::PositiveZ => (vec(0., 0., 1.), vec(0., 1., 0.)),
+            CubeFace::NegativeZ => (vec(0., 0., -1.), vec(0., 1., 0.)),
+        }
+    }
+
+    /// Returns the camera at `center` whose square image of the field of view of 90 degrees is the face.
+    pub fn camera(&self, center: &Point3) -> Camera {
+        let (look_in, view_up) = self.axes();
+        let pose = CameraPose {
+            look_from: center.clone(),
+            look_at: center + &look_in,
+            vertical_fov_degree: 90.,
+        };
+        Camera::with_view_up(pose, view_up, 1.)
+    }
+
+    /// Returns the column and the row of the face in the layout of `assemble_cross`.
+    fn cross_cell(&self) -> (usize, usize) {
+        match self {
+            CubeFace::NegativeX => (0, 1),
+            CubeFace::NegativeZ => (1, 1),
+            CubeFace::PositiveX => (2, 1),
+            CubeFace::PositiveZ => (3, 1),
+            CubeFace::PositiveY => (1, 0),
+            CubeFace::NegativeY => (1, 2),
+        }
+    }
+}
+
+/// Returns the image of the square `faces` given in the order of `CubeFace::ALL` laid out
+/// as a horizontal cross of 4 × 3 faces: -x, -z, +x, and +z from left to right in the middle row,
+/// and +y and -y above and below -z. The rest of the image is black.
+pub fn assemble_cross(faces: &[Film]) -> Film {
+    assert_eq!(CubeFace::ALL.len(), faces.len(), "a cubemap has six faces");
+    let size = faces[0].width;
+    let mut cross = Film::new(4 * size, 3 * size);
+    for (face, film) in CubeFace::ALL.iter().zip(faces) {
+        assert_eq!(
+            (size, size),
+            (film.width, film.height),
+            "faces must be squares of the same size"
+        );
+        let (column, row) = face.cross_cell();
+        for y in 0..size {
+            for x in 0..size {
+                cross.set_pixel(column * size + x, row * size + y, &film.pixel(x, y));
+            }
+        }
+    }
+    cross
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx::assert_approx_eq;
+    use crate::color::Color;
+    use crate::geometry::Float;
+
+    #[test]
+    fn cube_face_tests() {
+        let center = Point3 {
+            x: 1.,
+            y: 2.,
+            z: 3.,
+        };
+        // The face looks along its axis, through the center of its image.
+        for face in CubeFace::ALL {
+            let camera = face.camera(&center);
+            assert!(camera.validate().is_empty(), "{:?}", face);
+            let ray = camera.get_ray(0.5, 0.5);
+            assert_approx_eq!(center, ray.origin);
+            assert_approx_eq!(face.axes().0, ray.direction.inject());
+        }
+        // The faces adjacent in the cross share the rays along their common edges.
+        let ray = |face: CubeFace, u: Float, v: Float| face.camera(&center).get_ray(u, v).direction;
+        for t in [0., 0.3, 1.] {
+            let front = CubeFace::NegativeZ;
+            assert_approx_eq!(ray(front, 1., t), ray(CubeFace::PositiveX, 0., t));
+            assert_approx_eq!(
+                ray(CubeFace::PositiveX, 1., t),
+                ray(CubeFace::PositiveZ, 0., t)
+            );
+            assert_approx_eq!(
+                ray(CubeFace::PositiveZ, 1., t),
+                ray(CubeFace::NegativeX, 0., t)
+            );
+            assert_approx_eq!(ray(CubeFace::NegativeX, 1., t), ray(front, 0., t));
+            assert_approx_eq!(ray(front, t, 1.), ray(CubeFace::PositiveY, t, 0.));
+            assert_approx_eq!(ray(front, t, 0.), ray(CubeFace::NegativeY, t, 1.));
+        }
+
+        let faces: Vec<Film> = (0..6)
+            .map(|index| {
+                let mut film = Film::new(2, 2);
+                let c = index as Float;
+                film.set_pixel(1, 0, &Color { r: c, g: c, b: c });
+                film
+            })
+            .collect();
+        let cross = assemble_cross(&faces);
+        assert_eq!((8, 6), (cross.width, cross.height));
+        // -z is in the middle of the middle row, +y above it, and the corners are black.
+        assert_eq!(5., cross.pixel(3, 2).r);
+        assert_eq!(2., cross.pixel(3, 0).g);
+        assert_eq!(0., cross.pixel(7, 0).b);
+    }
+}
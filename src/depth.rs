@@ -0,0 +1,139 @@
+extern crate png;
+
+use std::io::{self, Write};
+
+use crate::geometry::Float;
+
+/// The type for the linear depths of the surfaces seen at the pixels, rendered by `Renderer::render_depth`,
+/// e.g. for training data or collision maps.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DepthMap {
+    pub width: usize,
+    pub height: usize,
+    /// The distance along the view direction to the surface seen at each pixel in row-major order
+    /// from the top left, which is `None` if the pixel sees nothing.
+    pub depths: Vec<Option<Float>>,
+}
+impl DepthMap {
+    /// Returns the largest depth, or `None` if no pixel sees anything.
+    pub fn max_depth(&self) -> Option<Float> {
+        self.depths.iter().flatten().copied().reduce(Float::max)
+    }
+
+    /// Writes the depths in the Portable Float Map format with one channel,
+    /// where the pixels that see nothing are 0.
+    // The cast to `f32` is a no-op only if the `f32` feature is enabled.
+    #[allow(clippy::unnecessary_cast)]
+    pub fn write_pfm(&self, out: &mut impl Write) -> io::Result<()> {
+        // The negative scale means little endian.
+        write!(out, "Pf\n{} {}\n-1.0\n", self.width, self.height)?;
+        // The scan lines go from the bottom to the top.
+        for row in self.depths.chunks(self.width).rev() {
+            for depth in row {
+                out.write_all(&(depth.unwrap_or(0.) as f32).to_le_bytes())?;
+            }
+        }
+        out.flush()
+    }
+
+    /// Writes the depths in the 16-bit grayscale PNG format, scaled so that `max_depth` is white,
+    /// where the pixels that see nothing are black.
+    pub fn write_png(&self, out: &mut impl Write) -> Result<(), png::EncodingError> {
+        let max_depth = self.max_depth().unwrap_or(1.);
+        let data: Vec<u8> = self
+            .depths
+            .iter()
+            .flat_map(|depth| {
+                let value = depth.map_or(0., |depth| depth / max_depth * u16::MAX as Float);
+                (value.round().clamp(0., u16::MAX as Float) as u16).to_be_bytes()
+            })
+            .collect();
+        write_grayscale_png(out, self.width, self.height, png::BitDepth::Sixteen, &data)
+    }
+
+    /// Writes the mask of the pixels that see something, which are white, in the plain PGM format.
+    pub fn write_mask_pgm(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "P2")?;
+        writeln!(out, "{} {}", self.width, self.height)?;
+        writeln!(out, "255")?;
+        for row in self.mask().chunks(self.width) {
+            let values: Vec<String> = row.iter().map(|value| value.to_string()).collect();
+            writeln!(out, "{}", values.join(" "))?;
+        }
+        out.flush()
+    }
+
+    /// Writes the mask of the pixels that see something, which are white, in the PNG format.
+    pub fn write_mask_png(&self, out: &mut impl Write) -> Result<(), png::EncodingError> {
+        let mask = self.mask();
+        write_grayscale_png(out, self.width, self.height, png::BitDepth::Eight, &mask)
+    }
+
+    fn mask(&self) -> Vec<u8> {
+        self.depths
+            .iter()
+            .map(|depth| if depth.is_some() { u8::MAX } else { 0 })
+            .collect()
+    }
+}
+
+fn write_grayscale_png(
+    out: &mut impl Write,
+    width: usize,
+    height: usize,
+    bit_depth: png::BitDepth,
+    data: &[u8],
+) -> Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(out, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(bit_depth);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(data)?;
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depth_map_tests() {
+        let depth_map = DepthMap {
+            width: 2,
+            height: 2,
+            depths: vec![Some(1.), None, Some(4.), Some(2.)],
+        };
+        assert_eq!(Some(4.), depth_map.max_depth());
+
+        let mut pfm = vec![];
+        depth_map.write_pfm(&mut pfm).unwrap();
+        let header = b"Pf\n2 2\n-1.0\n";
+        assert_eq!(header, &pfm[..header.len()]);
+        let values: Vec<f32> = pfm[header.len()..]
+            .chunks(4)
+            .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        // The bottom scan line comes first.
+        assert_eq!(vec![4., 2., 1., 0.], values);
+
+        let mut pgm = vec![];
+        depth_map.write_mask_pgm(&mut pgm).unwrap();
+        assert_eq!(
+            "P2\n2 2\n255\n255 0\n255 255\n",
+            String::from_utf8(pgm).unwrap()
+        );
+
+        let mut png_bytes = vec![];
+        depth_map.write_png(&mut png_bytes).unwrap();
+        let decoder = png::Decoder::new(png_bytes.as_slice());
+        let mut reader = decoder.read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+        assert_eq!(png::BitDepth::Sixteen, info.bit_depth);
+        let values: Vec<u16> = data[..info.buffer_size()]
+            .chunks(2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+            .collect();
+        assert_eq!(vec![16384, 0, 65535, 32768], values);
+    }
+}
@@ -0,0 +1,431 @@
+use std::sync::Arc;
+
+use crate::geometry::{Float, Point3, Ray, UnitVec3, Vec3};
+use crate::hittable_object::{
+    shared_memory_size, sphere_uv, HitRecord, Hittable, Mesh, SharedMaterial,
+};
+use crate::interval::Interval;
+use crate::texture::ImageTexture;
+
+/// The type for height fields given by the brightness of textures,
+/// which displace surfaces outward along their normals by up to `scale`.
+#[derive(Clone)]
+pub struct HeightMap {
+    pub texture: Arc<ImageTexture>,
+    pub scale: Float,
+}
+impl HeightMap {
+    /// Returns the displacement at the texture coordinates `(u, v)`, which is interpolated
+    /// bilinearly between the texel centers so that displaced surfaces have no steps at texels.
+    /// The texture wraps around horizontally like the longitudes of spheres, but not vertically,
+    /// so that the poles are not mixed with each other.
+    pub fn height(&self, u: Float, v: Float) -> Float {
+        let width = self.texture.width() as Float;
+        let height = self.texture.height() as Float;
+        let x = u * width - 0.5;
+        let y = (1. - v) * height - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        // `ImageTexture::value` repeats the image, so the columns wrap around at the edges.
+        let brightness = |x: Float, y: Float| {
+            let y = y.clamp(0., height - 1.);
+            let texel = self
+                .texture
+                .value((x + 0.5) / width, 1. - (y + 0.5) / height);
+            (texel.r + texel.g + texel.b) / 3.
+        };
+        let top = brightness(x0, y0) * (1. - fx) + brightness(x0 + 1., y0) * fx;
+        let bottom = brightness(x0, y0 + 1.) * (1. - fx) + brightness(x0 + 1., y0 + 1.) * fx;
+        (top * (1. - fy) + bottom * fy) * self.scale
+    }
+
+    fn validate(&self) -> Vec<String> {
+        if self.scale.is_finite() && self.scale >= 0. {
+            vec![]
+        } else {
+            vec![format!(
+                "displacement scale must be finite and non-negative, but is {}",
+                self.scale
+            )]
+        }
+    }
+}
+
+/// Tessellates each triangle of `mesh` into `subdivisions`^2 triangles and displaces the vertices
+/// along the normals interpolated from those of the vertices of `mesh` by `height`
+/// at their texture coordinates. Meshes without texture coordinates are displaced uniformly,
+/// and cracks may open along the seams of the texture coordinates.
+pub fn displace_mesh(mesh: &Mesh, height: &HeightMap, subdivisions: usize) -> Mesh {
+    let subdivisions = subdivisions.max(1);
+    let zero = Vec3 {
+        x: 0.,
+        y: 0.,
+        z: 0.,
+    };
+    // The normals of the vertices are the averages of those of the triangles weighted by area.
+    let mut vertex_normals = vec![zero.clone(); mesh.vertices().len()];
+    for triangle in mesh.triangles() {
+        let [a, b, c] = triangle.map(|vertex| &mesh.vertices()[vertex]);
+        let normal = (b - a).cross_product(&(c - a));
+        for &vertex in triangle {
+            vertex_normals[vertex] += &normal;
+        }
+    }
+
+    let mut vertices = vec![];
+    let mut triangles = vec![];
+    let mut texture_coordinates = vec![];
+    let n = subdivisions as Float;
+    for (index, triangle) in mesh.triangles().iter().enumerate() {
+        let corners = triangle.map(|vertex| &mesh.vertices()[vertex]);
+        let normals = triangle.map(|vertex| &vertex_normals[vertex]);
+        let uvs = mesh
+            .texture_coordinates()
+            .map_or([(0., 0.); 3], |texture_coordinates| {
+                texture_coordinates[index]
+            });
+        // The points of the grid on the triangle, where `(i, j)` is `A + i/n (B - A) + j/n (C - A)`.
+        let mut grid = vec![];
+        for i in 0..=subdivisions {
+            for j in 0..=(subdivisions - i) {
+                let (beta, gamma) = (i as Float / n, j as Float / n);
+                let alpha = 1. - beta - gamma;
+                let weights = [alpha, beta, gamma];
+                let interpolate = |values: [Float; 3]| {
+                    values
+                        .iter()
+                        .zip(weights.iter())
+                        .map(|(value, weight)| value * weight)
+                        .sum::<Float>()
+                };
+                let point = Point3 {
+                    x: interpolate(corners.map(|corner| corner.x)),
+                    y: interpolate(corners.map(|corner| corner.y)),
+                    z: interpolate(corners.map(|corner| corner.z)),
+                };
+                let normal = Vec3 {
+                    x: interpolate(normals.map(|normal| normal.x)),
+                    y: interpolate(normals.map(|normal| normal.y)),
+                    z: interpolate(normals.map(|normal| normal.z)),
+                };
+                let uv = (
+                    interpolate(uvs.map(|(u, _)| u)),
+                    interpolate(uvs.map(|(_, v)| v)),
+                );
+                let length = normal.length();
+                let displaced = if length > 0. {
+                    point + &normal * (height.height(uv.0, uv.1) / length)
+                } else {
+                    point
+                };
+                grid.push(((i, j), vertices.len(), uv));
+                vertices.push(displaced);
+            }
+        }
+        let at = |i: usize, j: usize| {
+            let (_, vertex, uv) = grid
+                .iter()
+                .find(|(position, _, _)| *position == (i, j))
+                .expect("the grid has all the points");
+            (*vertex, *uv)
+        };
+        for i in 0..subdivisions {
+            for j in 0..(subdivisions - i) {
+                let mut corners = vec![[at(i, j), at(i + 1, j), at(i, j + 1)]];
+                if i + j + 1 < subdivisions {
+                    corners.push([at(i + 1, j), at(i + 1, j + 1), at(i, j + 1)]);
+                }
+                for corners in corners {
+                    triangles.push(corners.map(|(vertex, _)| vertex));
+                    texture_coordinates.push(corners.map(|(_, uv)| uv));
+                }
+            }
+        }
+    }
+    let displaced = Mesh::new(vertices, triangles, mesh.material().clone());
+    if mesh.texture_coordinates().is_some() {
+        displaced.with_texture_coordinates(texture_coordinates)
+    } else {
+        displaced
+    }
+}
+
+/// The type for spheres whose surfaces are displaced outward by height maps, e.g. bumpy planets,
+/// which change the silhouettes unlike textures changing only the shading.
+/// Rays are marched through the shell between `radius` and `radius + height.scale`.
+pub struct DisplacedSphere {
+    pub center: Point3,
+    pub radius: Float,
+    pub height: HeightMap,
+    pub material: SharedMaterial,
+}
+impl DisplacedSphere {
+    /// The number of steps rays are marched in the shell, where thinner features may be missed.
+    const NUM_MARCH_STEPS: usize = 256;
+
+    /// The number of bisections to find the surface between two steps.
+    const NUM_BISECTIONS: usize = 32;
+
+    /// Returns the distance from the center to the surface in `direction`.
+    fn surface_radius(&self, direction: &UnitVec3) -> Float {
+        let (u, v) = sphere_uv(direction);
+        self.radius + self.height.height(u, v)
+    }
+
+    /// Returns how far `point` is outside the surface, which is negative inside.
+    fn excess(&self, point: &Point3) -> Float {
+        let offset = point - &self.center;
+        let distance = offset.length();
+        if distance > 0. {
+            distance - self.surface_radius(&offset.unit_vector())
+        } else {
+            -self.radius
+        }
+    }
+
+    /// Returns the outward normal at the surface in `direction` by finite differences.
+    fn surface_normal(&self, direction: &UnitVec3) -> UnitVec3 {
+        let d = direction.inject();
+        let axis = if d.x.abs() < 0.9 {
+            Vec3 {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            }
+        } else {
+            Vec3 {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            }
+        };
+        let tangent1 = d.cross_product(&axis).unit_vector().inject();
+        let tangent2 = d.cross_product(&tangent1);
+        let epsilon: Float = 1e-4;
+        let surface_point = |offset: Vec3| {
+            let direction = (&d + &offset).unit_vector();
+            &direction.inject() * self.surface_radius(&direction)
+        };
+        let along1 = surface_point(&tangent1 * epsilon) - surface_point(&tangent1 * -epsilon);
+        let along2 = surface_point(&tangent2 * epsilon) - surface_point(&tangent2 * -epsilon);
+        let normal = along1.cross_product(&along2);
+        if normal.inner_product(&d) > 0. {
+            normal.unit_vector()
+        } else {
+            (-normal).unit_vector()
+        }
+    }
+}
+impl Hittable for DisplacedSphere {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<(HitRecord, SharedMaterial)> {
+        // Restricts the ray to the bounding sphere, which is slightly larger than the shell
+        // so that rays from the outside start outside the highest points.
+        let outer_radius = (self.radius + self.height.scale) * (1. + 1e-6);
+        let v = &ray.origin - &self.center;
+        let b_half = v.inner_product(&ray.direction.inject());
+        let c = v.length_squared() - outer_radius * outer_radius;
+        let discriminant_quarter = b_half * b_half - c;
+        if discriminant_quarter < 0. {
+            return None;
+        }
+        let sqrt_of_discriminant_quarter = discriminant_quarter.sqrt();
+        let t_start = ray_t.min.max(-b_half - sqrt_of_discriminant_quarter);
+        let t_end = ray_t.max.min(-b_half + sqrt_of_discriminant_quarter);
+        if t_start >= t_end {
+            return None;
+        }
+
+        // Looks for the first step where the ray crosses the surface, and bisects it.
+        let dt = (t_end - t_start) / Self::NUM_MARCH_STEPS as Float;
+        let is_outside = |t: Float| self.excess(&ray.at(t)) > 0.;
+        let starts_outside = is_outside(t_start);
+        let step = (1..=Self::NUM_MARCH_STEPS)
+            .find(|&step| is_outside(t_start + step as Float * dt) != starts_outside)?;
+        let (mut low, mut high) = (
+            t_start + (step - 1) as Float * dt,
+            t_start + step as Float * dt,
+        );
+        for _ in 0..Self::NUM_BISECTIONS {
+            let middle = (low + high) / 2.;
+            if is_outside(middle) == starts_outside {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        let t = (low + high) / 2.;
+        let direction = (ray.at(t) - &self.center).unit_vector();
+        let (u, v) = sphere_uv(&direction);
+        let hit = HitRecord {
+            t,
+            surface_normal: self.surface_normal(&direction),
+            u,
+            v,
+        };
+        Some((hit, self.material.clone()))
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if !self.center.is_finite() {
+            problems.push(format!("center must be finite, but is {:?}", self.center));
+        }
+        if !(self.radius.is_finite() && self.radius > 0.) {
+            problems.push(format!("radius must be positive, but is {}", self.radius));
+        }
+        problems.extend(self.height.validate());
+        problems.extend(self.material.validate());
+        problems
+    }
+
+    fn memory_size(&self) -> usize {
+        std::mem::size_of_val(self)
+            + shared_memory_size(&self.height.texture, self.height.texture.memory_size())
+            + shared_memory_size(&self.material, self.material.memory_size())
+    }
+
+    /// Returns the box containing the outermost sphere of the shell.
+    fn bounding_box(&self) -> Option<(Point3, Point3)> {
+        let outer_radius = (self.radius + self.height.scale).abs();
+        let extent = Vec3 {
+            x: outer_radius,
+            y: outer_radius,
+            z: outer_radius,
+        };
+        Some((&self.center - &extent, &self.center + &extent))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Attenuation;
+    use crate::hittable_object::Lambertian;
+
+    fn gray(value: Float) -> Attenuation {
+        Attenuation {
+            r: value,
+            g: value,
+            b: value,
+        }
+    }
+
+    fn material() -> SharedMaterial {
+        Arc::new(Lambertian { albedo: gray(0.5) })
+    }
+
+    #[test]
+    fn height_tests() {
+        let height = HeightMap {
+            texture: Arc::new(ImageTexture::new(2, 1, vec![gray(0.), gray(1.)])),
+            scale: 2.,
+        };
+        // The texel centers have the values as they are, and the points between them are interpolated.
+        assert_eq!(0., height.height(0.25, 0.5));
+        assert_eq!(2., height.height(0.75, 0.5));
+        assert_eq!(1., height.height(0.5, 0.5));
+        // The texture wraps around horizontally but not vertically.
+        assert_eq!(1., height.height(0., 0.5));
+        assert_eq!(2., height.height(0.75, 1.));
+    }
+
+    #[test]
+    fn displace_mesh_tests() {
+        let mesh = Mesh::new(
+            vec![
+                Point3 {
+                    x: 0.,
+                    y: 0.,
+                    z: 0.,
+                },
+                Point3 {
+                    x: 1.,
+                    y: 0.,
+                    z: 0.,
+                },
+                Point3 {
+                    x: 0.,
+                    y: 1.,
+                    z: 0.,
+                },
+            ],
+            vec![[0, 1, 2]],
+            material(),
+        )
+        .with_texture_coordinates(vec![[(0., 0.), (1., 0.), (0., 1.)]]);
+        let height = HeightMap {
+            texture: Arc::new(ImageTexture::new(1, 1, vec![gray(1.)])),
+            scale: 0.5,
+        };
+        let displaced = displace_mesh(&mesh, &height, 2);
+        assert_eq!(6, displaced.vertices().len());
+        assert_eq!(4, displaced.triangles().len());
+        assert!(displaced.vertices().iter().all(|vertex| vertex.z == 0.5));
+        assert_eq!(
+            Some((0.5, 0.5)),
+            displaced
+                .texture_coordinates()
+                .unwrap()
+                .iter()
+                .flatten()
+                .copied()
+                .find(|&(u, v)| u == 0.5 && v == 0.5)
+        );
+        // The sub-triangles cover the triangle without overlaps.
+        let area: Float = displaced
+            .triangles()
+            .iter()
+            .map(|triangle| {
+                let [a, b, c] = triangle.map(|vertex| &displaced.vertices()[vertex]);
+                (b - a).cross_product(&(c - a)).length() / 2.
+            })
+            .sum();
+        assert!((area - 0.5).abs() < 1e-9, "{}", area);
+    }
+
+    #[test]
+    fn displaced_sphere_tests() {
+        let sphere = |texture: ImageTexture| DisplacedSphere {
+            center: Point3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            radius: 1.,
+            height: HeightMap {
+                texture: Arc::new(texture),
+                scale: 0.5,
+            },
+            material: material(),
+        };
+        let ray = |y: Float| Ray {
+            origin: Point3 { x: 0., y, z: 5. },
+            direction: Vec3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            }
+            .unit_vector(),
+        };
+        let ray_t = Interval {
+            min: 0.001,
+            max: Float::INFINITY,
+        };
+
+        // The uniform displacement makes the sphere larger.
+        let uniform = sphere(ImageTexture::new(1, 1, vec![gray(1.)]));
+        let (hit, _) = uniform.hit(&ray(0.), &ray_t).unwrap();
+        assert!((hit.t - 3.5).abs() < 1e-6, "{}", hit.t);
+        let normal = hit.surface_normal.inject();
+        assert!((normal.z - 1.).abs() < 1e-6, "{:?}", normal);
+        assert!(uniform.hit(&ray(1.2), &ray_t).is_some());
+        assert!(uniform.hit(&ray(1.6), &ray_t).is_none());
+
+        // The silhouette changes with the height, which is higher in the upper half.
+        let bumpy = sphere(ImageTexture::new(1, 2, vec![gray(1.), gray(0.)]));
+        assert!(bumpy.hit(&ray(0.), &ray_t).is_some());
+        assert!(bumpy.hit(&ray(1.2), &ray_t).is_some());
+        assert!(bumpy.hit(&ray(-1.2), &ray_t).is_none());
+        assert_eq!(Vec::<String>::new(), bumpy.validate());
+    }
+}
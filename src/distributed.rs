@@ -0,0 +1,799 @@
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+use try_ray_tracing::color::Color;
+use try_ray_tracing::film::{Film, Filter};
+use try_ray_tracing::geometry::Float;
+use try_ray_tracing::json::Json;
+use try_ray_tracing::render::{RenderSettings, Renderer, Tile, MAX_NUM_PIXELS};
+use try_ray_tracing::scenes::{check_density, Scene, SceneParams, MAX_SCENE_SCALE};
+
+/// The number of scan lines of each tile handed to a worker.
+/// Smaller tiles balance the load better at the cost of more round trips.
+const TILE_HEIGHT: usize = 16;
+
+/// The number of times a tile that looks corrupted is requested again before it is rendered locally.
+const MAX_TILE_RETRIES: usize = 2;
+
+/// The least number of decades by which the dispersion of a tile must exceed the median of
+/// those accepted so far to look corrupted, e.g. by a worker with broken memory flipping bits of
+/// the exponents, which scales values by 2^20 for the fifth bit (about 6 decades).
+/// The tiles of the preset scenes stay within about 2.5 decades, e.g. an emitter in a dark tile,
+/// while brighter light such as the sun itself may be rejected, which costs only retries
+/// since the tiles reproduced with `--seed` are accepted and the others are rendered locally at last.
+const MIN_OUTLIER_DECADES: Float = 4.;
+
+/// The number of the median absolute deviations of the dispersions of the accepted tiles in decades
+/// beyond their median above which a tile looks corrupted, if it is more than `MIN_OUTLIER_DECADES`.
+const OUTLIER_MADS: Float = 10.;
+
+/// The dispersion below which tiles count as flat, e.g. of an empty sky,
+/// whose logarithms would otherwise make the median so small that noisy tiles after them look corrupted.
+const MIN_DISPERSION: Float = 1e-3;
+
+/// The type for the frames rendered by workers, each of which builds the scene by itself.
+/// Only preset scenes can be shared this way since workers may not have the files of the others.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameSpec {
+    pub scene_name: String,
+    pub params: SceneParams,
+    /// The time of the frame for animations.
+    pub time: Option<Float>,
+    /// The aspect ratio of the camera, which may differ slightly from that of the image.
+    pub aspect_ratio: Float,
+    pub settings: RenderSettings,
+}
+impl FrameSpec {
+    /// Encodes the frame in JSON, where the seeds are strings since JSON numbers cannot hold every `u64`.
+    // The casts are no-ops unless the `f32` feature is enabled.
+    #[allow(clippy::unnecessary_cast)]
+    fn to_json(&self) -> Json {
+        let settings = &self.settings;
+        let number = |x: Float| Json::Number(x as f64);
+        let seed = |seed: u64| Json::String(seed.to_string());
+        Json::Object(vec![
+            ("scene".to_string(), Json::String(self.scene_name.clone())),
+            ("seed".to_string(), seed(self.params.seed)),
+            ("density".to_string(), number(self.params.density)),
+            ("scale".to_string(), Json::Number(self.params.scale as f64)),
+            ("time".to_string(), self.time.map_or(Json::Null, number)),
+            ("aspect_ratio".to_string(), number(self.aspect_ratio)),
+            (
+                "width".to_string(),
+                Json::Number(settings.image_width as f64),
+            ),
+            (
+                "height".to_string(),
+                Json::Number(settings.image_height as f64),
+            ),
+            (
+                "samples".to_string(),
+                Json::Number(settings.num_samples_per_pixel as f64),
+            ),
+            (
+                "max_depth".to_string(),
+                Json::Number(settings.max_diffusion_depth as f64),
+            ),
+            (
+                "roulette".to_string(),
+                Json::Array(settings.roulette.iter().map(|&p| number(p)).collect()),
+            ),
+            (
+                "render_seed".to_string(),
+                settings.seed.map_or(Json::Null, seed),
+            ),
+            (
+                "filter".to_string(),
+                Json::String(settings.filter.name().to_string()),
+            ),
+            (
+                "filter_sampling".to_string(),
+                Json::Bool(settings.filter_sampling),
+            ),
+        ])
+    }
+
+    /// Decodes a frame encoded by `to_json`, rendering it with the threads of `local_settings`.
+    fn from_json(json: &Json, local_settings: &RenderSettings) -> Result<Self, String> {
+        let field = |key: &str| json.get(key).ok_or_else(|| format!("{} is missing", key));
+        let number = |key: &str| {
+            field(key)?
+                .as_f64()
+                .map(|x| x as Float)
+                .ok_or_else(|| format!("{} must be a number", key))
+        };
+        let size = |key: &str| {
+            let value = field(key)?
+                .as_u64()
+                .ok_or_else(|| format!("{} must be a non-negative integer", key))?;
+            usize::try_from(value).map_err(|_| format!("{} is too large", key))
+        };
+        let seed = |key: &str| match field(key)? {
+            Json::Null => Ok(None),
+            value => value
+                .as_str()
+                .and_then(|s| s.parse().ok())
+                .map(Some)
+                .ok_or_else(|| format!("{} must be an integer in a string", key)),
+        };
+        let time = match field("time")? {
+            Json::Null => None,
+            _ => Some(number("time")?),
+        };
+        let max_depth = field("max_depth")?
+            .as_u64()
+            .and_then(|depth| i32::try_from(depth).ok())
+            .ok_or("max_depth must be a non-negative integer")?;
+        let roulette = match field("roulette")? {
+            Json::Array(probabilities) => probabilities
+                .iter()
+                .map(|p| p.as_f64().map(|p| p as Float))
+                .collect::<Option<Vec<Float>>>()
+                .ok_or("roulette must be a list of numbers")?,
+            _ => return Err("roulette must be a list of numbers".to_string()),
+        };
+        let filter = field("filter")?
+            .as_str()
+            .ok_or("filter must be a string")?
+            .parse::<Filter>()?;
+        let filter_sampling = match field("filter_sampling")? {
+            Json::Bool(b) => *b,
+            _ => return Err("filter_sampling must be a boolean".to_string()),
+        };
+        let settings = RenderSettings {
+            image_width: size("width")?,
+            image_height: size("height")?,
+            num_samples_per_pixel: size("samples")?,
+            max_diffusion_depth: max_depth,
+            roulette,
+            seed: seed("render_seed")?,
+            filter,
+            filter_sampling,
+            ..local_settings.clone()
+        };
+        if settings.image_width == 0 || settings.image_height < 2 {
+            return Err("the image must be at least 1 pixel wide and 2 pixels high".to_string());
+        }
+        if settings.image_width.saturating_mul(settings.image_height) > MAX_NUM_PIXELS {
+            return Err(format!(
+                "the image must have at most {} pixels",
+                MAX_NUM_PIXELS
+            ));
+        }
+        let scene_name = field("scene")?
+            .as_str()
+            .ok_or("scene must be a string")?
+            .to_string();
+        Ok(FrameSpec {
+            scene_name,
+            params: SceneParams {
+                seed: seed("seed")?.ok_or("seed must not be null")?,
+                // The density bounds the number of spheres the worker builds.
+                density: check_density(number("density")?)?,
+                scale: field("scale")?
+                    .as_u64()
+                    .filter(|&scale| scale <= MAX_SCENE_SCALE as u64)
+                    .ok_or(format!(
+                        "scale must be a non-negative integer of at most {}",
+                        MAX_SCENE_SCALE
+                    ))? as u32,
+            },
+            time,
+            aspect_ratio: number("aspect_ratio")?,
+            settings,
+        })
+    }
+
+    fn scene(&self) -> Result<Scene, String> {
+        let scene =
+            crate::preset_scene(&self.scene_name, &self.params, self.aspect_ratio, self.time)
+                .ok_or_else(|| format!("unknown scene: {}", self.scene_name))?;
+        scene
+            .validate()
+            .map_err(|problems| format!("invalid scene: {}", problems.join("; ")))?;
+        Ok(scene)
+    }
+}
+
+// The protocol between the coordinator and workers is a sequence of requests and responses
+// over a TCP connection. Each request is a line of JSON `{"frame": <frame>, "y": <y>, "height": <height>}`
+// asking for the scan lines from `y` to `y + height` of the frame encoded by `FrameSpec::to_json`.
+// Each response is either a line `{"height": <height>, "checksum": <checksum>}` followed by the RGB
+// of the pixels as little-endian `f64`s in row-major order, or a line `{"error": <message>}`,
+// where the checksum is the FNV-1a hash of the bytes of the pixels in 16 hexadecimal digits.
+
+/// Returns the FNV-1a hash of the bytes of `pixels` sent by `write_pixels`.
+// The casts are no-ops unless the `f32` feature is enabled.
+#[allow(clippy::unnecessary_cast)]
+fn checksum(pixels: &[Color]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for color in pixels {
+        for component in [color.r, color.g, color.b] {
+            for byte in (component as f64).to_le_bytes() {
+                hash = (hash ^ byte as u64).wrapping_mul(0x100000001b3);
+            }
+        }
+    }
+    hash
+}
+
+// The casts are no-ops unless the `f32` feature is enabled.
+#[allow(clippy::unnecessary_cast)]
+fn write_pixels(out: &mut impl Write, pixels: &[Color]) -> io::Result<()> {
+    for color in pixels {
+        for component in [color.r, color.g, color.b] {
+            out.write_all(&(component as f64).to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+fn read_pixels(reader: &mut impl Read, num_pixels: usize) -> io::Result<Vec<Color>> {
+    let mut read_component = || -> io::Result<Float> {
+        let mut bytes = [0; 8];
+        reader.read_exact(&mut bytes)?;
+        Ok(f64::from_le_bytes(bytes) as Float)
+    };
+    (0..num_pixels)
+        .map(|_| {
+            Ok(Color {
+                r: read_component()?,
+                g: read_component()?,
+                b: read_component()?,
+            })
+        })
+        .collect()
+}
+
+/// Renders the requested scan lines, reusing the scene of the previous request if the frame is the same.
+fn render_request(
+    line: &str,
+    local_settings: &RenderSettings,
+    cache: &mut Option<(Json, FrameSpec, Scene)>,
+) -> Result<Tile, String> {
+    let request = Json::parse(line)?;
+    let frame_json = request.get("frame").ok_or("frame is missing")?;
+    let position = |key: &str| {
+        request
+            .get(key)
+            .and_then(Json::as_u64)
+            .and_then(|value| usize::try_from(value).ok())
+            .ok_or_else(|| format!("{} must be a non-negative integer", key))
+    };
+    let (y, height) = (position("y")?, position("height")?);
+    if !matches!(cache, Some((cached, _, _)) if cached == frame_json) {
+        let frame = FrameSpec::from_json(frame_json, local_settings)?;
+        let scene = frame.scene()?;
+        *cache = Some((frame_json.clone(), frame, scene));
+    }
+    let (_, frame, scene) = cache.as_ref().unwrap();
+    if y.checked_add(height)
+        .is_none_or(|end| end > frame.settings.image_height)
+    {
+        return Err("the scan lines are out of the image".to_string());
+    }
+    let renderer = Renderer::new(frame.settings.clone());
+    Ok(renderer.render_tile(scene, y..y + height))
+}
+
+fn handle_coordinator(stream: TcpStream, local_settings: &RenderSettings) -> io::Result<()> {
+    let mut reader = BufReader::new(&stream);
+    let mut writer = BufWriter::new(&stream);
+    let mut cache = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        match render_request(&line, local_settings, &mut cache) {
+            Ok(tile) => {
+                let header = Json::Object(vec![
+                    ("height".to_string(), Json::Number(tile.height as f64)),
+                    (
+                        "checksum".to_string(),
+                        Json::String(format!("{:016x}", checksum(&tile.pixels))),
+                    ),
+                ]);
+                writeln!(writer, "{}", header)?;
+                write_pixels(&mut writer, &tile.pixels)?;
+            }
+            Err(message) => {
+                let header = Json::Object(vec![("error".to_string(), Json::String(message))]);
+                writeln!(writer, "{}", header)?;
+            }
+        }
+        writer.flush()?;
+    }
+}
+
+/// Renders the tiles requested by coordinators connecting to `listener` until the process is killed,
+/// using the threads of `local_settings`.
+fn serve_worker(listener: TcpListener, local_settings: RenderSettings) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("cannot accept a connection: {}", e);
+                continue;
+            }
+        };
+        let local_settings = local_settings.clone();
+        thread::spawn(move || {
+            let peer = stream
+                .peer_addr()
+                .map_or("?".to_string(), |peer| peer.to_string());
+            eprintln!("{} connected.", peer);
+            if let Err(e) = handle_coordinator(stream, &local_settings) {
+                eprintln!("connection error with {}: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// Works for coordinators on `address`, which are started by `--workers`.
+pub fn run_worker(address: &str, local_settings: RenderSettings) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    eprintln!("Waiting for coordinators on {}", listener.local_addr()?);
+    serve_worker(listener, local_settings);
+    Ok(())
+}
+
+/// Asks the worker on the other side of `reader` and `writer` to render `rows` of the frame,
+/// returning the tile with its checksum, or an error of `io::ErrorKind::InvalidData`
+/// if the pixels do not match the checksum.
+fn request_tile(
+    reader: &mut impl BufRead,
+    writer: &mut impl Write,
+    frame_json: &Json,
+    rows: Range<usize>,
+) -> io::Result<(Tile, u64)> {
+    let request = Json::Object(vec![
+        ("frame".to_string(), frame_json.clone()),
+        ("y".to_string(), Json::Number(rows.start as f64)),
+        ("height".to_string(), Json::Number(rows.len() as f64)),
+    ]);
+    writeln!(writer, "{}", request)?;
+    writer.flush()?;
+
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let header = Json::parse(&line).map_err(io::Error::other)?;
+    if let Some(message) = header.get("error").and_then(Json::as_str) {
+        return Err(io::Error::other(message.to_string()));
+    }
+    if header.get("height").and_then(Json::as_u64) != Some(rows.len() as u64) {
+        return Err(io::Error::other(format!(
+            "unexpected response: {}",
+            line.trim_end()
+        )));
+    }
+    let expected_checksum = header
+        .get("checksum")
+        .and_then(Json::as_str)
+        .and_then(|checksum| u64::from_str_radix(checksum, 16).ok())
+        .ok_or_else(|| io::Error::other(format!("unexpected response: {}", line.trim_end())))?;
+    let width = frame_json.get("width").and_then(Json::as_u64).unwrap_or(0) as usize;
+    let pixels = read_pixels(reader, width * rows.len())?;
+    let actual_checksum = checksum(&pixels);
+    if actual_checksum != expected_checksum {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the pixels do not match the checksum",
+        ));
+    }
+    let tile = Tile {
+        x: 0,
+        y: rows.start,
+        width,
+        height: rows.len(),
+        pixels,
+        num_finished: rows.len(),
+        num_total: rows.len(),
+    };
+    Ok((tile, actual_checksum))
+}
+
+/// Returns the dispersion, i.e., the variance divided by the mean, of the averages of the components
+/// of the finite pixels of `tile` together with the number of the other pixels. The dispersion is
+/// in the units of the brightness, e.g. about that of an emitter seen in a few pixels of a dark tile.
+fn tile_statistics(tile: &Tile) -> (Float, usize) {
+    let values: Vec<Float> = tile
+        .pixels
+        .iter()
+        .filter(|color| [color.r, color.g, color.b].iter().all(|c| c.is_finite()))
+        .map(|color| (color.r + color.g + color.b) / 3.)
+        .collect();
+    let num_non_finite = tile.pixels.len() - values.len();
+    if values.is_empty() {
+        return (0., num_non_finite);
+    }
+    let n = values.len() as Float;
+    let mean = values.iter().sum::<Float>() / n;
+    let variance = values
+        .iter()
+        .map(|v| (v - mean) * (v - mean))
+        .sum::<Float>()
+        / n;
+    let dispersion = if mean > 0. { variance / mean } else { 0. };
+    (dispersion, num_non_finite)
+}
+
+/// Returns the median of `values`, which must not be empty.
+fn median(values: &[Float]) -> Float {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(Float::total_cmp);
+    sorted[sorted.len() / 2]
+}
+
+/// Describes why `tile` looks corrupted, i.e., it has non-finite pixels or its dispersion is an outlier
+/// among `accepted_dispersions`, those of the tiles accepted so far, if any. The outliers are found
+/// in decades by the median and the median absolute deviation, which bright tiles, e.g. of lights,
+/// barely move, with the margin of `MIN_OUTLIER_DECADES` so that correct tiles of high dynamic range
+/// among smooth ones pass while the wild values of corrupted memory do not.
+fn tile_problem(tile: &Tile, accepted_dispersions: &[Float]) -> Option<String> {
+    let (dispersion, num_non_finite) = tile_statistics(tile);
+    if num_non_finite > 0 {
+        return Some(format!("{} non-finite pixels", num_non_finite));
+    }
+    if accepted_dispersions.is_empty() {
+        return None;
+    }
+    let decades = |dispersion: Float| dispersion.max(MIN_DISPERSION).log10();
+    let accepted: Vec<Float> = accepted_dispersions.iter().map(|&d| decades(d)).collect();
+    let center = median(&accepted);
+    let deviations: Vec<Float> = accepted.iter().map(|d| (d - center).abs()).collect();
+    let margin = (OUTLIER_MADS * median(&deviations)).max(MIN_OUTLIER_DECADES);
+    if decades(dispersion) > center + margin {
+        return Some(format!(
+            "the dispersion {} is an outlier among the median {}",
+            dispersion,
+            (10. as Float).powf(center)
+        ));
+    }
+    None
+}
+
+/// The type for the tiles waiting for workers.
+struct PendingTile {
+    rows: Range<usize>,
+    /// The number of times the tile has been rejected so far.
+    num_retries: usize,
+    /// The checksum of the last render rejected for its statistics, which is accepted
+    /// if reproduced since retries render the same pixels when the frame has a seed.
+    suspect: Option<u64>,
+}
+
+/// The type for the tiles left to be rendered.
+struct TileQueue {
+    pending: VecDeque<PendingTile>,
+    /// The tiles rejected `MAX_TILE_RETRIES` times, which are rendered locally.
+    given_up: Vec<Range<usize>>,
+}
+impl TileQueue {
+    /// Requests the tile again unless it has been rejected too many times.
+    fn reject(&mut self, tile: PendingTile, suspect: Option<u64>, reason: &str) {
+        eprintln!(
+            "rejected the tile of scan lines {}..{}: {}",
+            tile.rows.start, tile.rows.end, reason
+        );
+        if tile.num_retries < MAX_TILE_RETRIES {
+            self.pending.push_back(PendingTile {
+                rows: tile.rows,
+                num_retries: tile.num_retries + 1,
+                suspect,
+            });
+        } else {
+            self.given_up.push(tile.rows);
+        }
+    }
+}
+
+/// Renders `frame` by splitting it into tiles of scan lines and handing them out to `workers`
+/// (`<host>:<port>`), where `scene` must be the scene of the frame.
+/// `on_scan_line` is called with the film every time a tile is merged;
+/// if it returns `false`, the rendering is aborted and the partially rendered film is returned as `Err`.
+/// The tiles of the workers that fail are taken over by the others, or rendered locally if none is left.
+/// The tiles that do not match their checksums or look corrupted are requested again,
+/// and rendered locally once they have been rejected `MAX_TILE_RETRIES` times.
+pub fn render_on_workers(
+    workers: &[String],
+    frame: &FrameSpec,
+    scene: &Scene,
+    mut on_scan_line: impl FnMut(&Film) -> bool,
+) -> Result<Film, Film> {
+    let settings = &frame.settings;
+    let mut film = Film::new(settings.image_width, settings.image_height);
+    let queue = Mutex::new(TileQueue {
+        pending: (0..settings.image_height)
+            .step_by(TILE_HEIGHT)
+            .map(|y| PendingTile {
+                rows: y..(y + TILE_HEIGHT).min(settings.image_height),
+                num_retries: 0,
+                suspect: None,
+            })
+            .collect(),
+        given_up: vec![],
+    });
+    let frame_json = frame.to_json();
+    let is_aborted = AtomicBool::new(false);
+
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for worker in workers {
+            let sender = sender.clone();
+            let (queue, frame_json, is_aborted) = (&queue, &frame_json, &is_aborted);
+            scope.spawn(move || {
+                let result = (|| -> io::Result<()> {
+                    let stream = TcpStream::connect(worker.as_str())?;
+                    let mut reader = BufReader::new(&stream);
+                    let mut writer = BufWriter::new(&stream);
+                    while !is_aborted.load(Ordering::Relaxed) {
+                        let Some(pending) = queue.lock().unwrap().pending.pop_front() else {
+                            break;
+                        };
+                        let rows = pending.rows.clone();
+                        match request_tile(&mut reader, &mut writer, frame_json, rows) {
+                            Ok((tile, checksum)) => {
+                                if sender.send((pending, tile, checksum)).is_err() {
+                                    break;
+                                }
+                            }
+                            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                                let reason = format!("{} from {}", e, worker);
+                                queue.lock().unwrap().reject(pending, None, &reason);
+                            }
+                            Err(e) => {
+                                queue.lock().unwrap().pending.push_back(pending);
+                                return Err(e);
+                            }
+                        }
+                    }
+                    Ok(())
+                })();
+                if let Err(e) = result {
+                    eprintln!("worker {} failed: {}", worker, e);
+                }
+            });
+        }
+        // Lets `receiver` end once all the workers have finished.
+        drop(sender);
+
+        let mut accepted_dispersions = vec![];
+        for (pending, tile, checksum) in receiver {
+            if let Some(problem) = tile_problem(&tile, &accepted_dispersions) {
+                let is_reproduced = settings.seed.is_some() && pending.suspect == Some(checksum);
+                if !is_reproduced {
+                    queue
+                        .lock()
+                        .unwrap()
+                        .reject(pending, Some(checksum), &problem);
+                    continue;
+                }
+                eprintln!(
+                    "accepted the tile of scan lines {}..{} reproduced with {}",
+                    tile.y,
+                    tile.y + tile.height,
+                    problem
+                );
+            }
+            accepted_dispersions.push(tile_statistics(&tile).0);
+            film.add_tile(&tile);
+            if !on_scan_line(&film) {
+                is_aborted.store(true, Ordering::Relaxed);
+                break;
+            }
+        }
+    });
+    if is_aborted.into_inner() {
+        return Err(film);
+    }
+
+    let queue = queue.into_inner().unwrap();
+    let remaining: Vec<Range<usize>> = (queue.pending.into_iter().map(|pending| pending.rows))
+        .chain(queue.given_up)
+        .collect();
+    if !remaining.is_empty() {
+        eprintln!(
+            "Rendering the {} tiles left by the workers locally.",
+            remaining.len()
+        );
+    }
+    let renderer = Renderer::new(settings.clone());
+    for rows in remaining {
+        film.add_tile(&renderer.render_tile(scene, rows));
+        if !on_scan_line(&film) {
+            return Err(film);
+        }
+    }
+    Ok(film)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame() -> FrameSpec {
+        FrameSpec {
+            scene_name: "three-spheres".to_string(),
+            params: SceneParams {
+                seed: u64::MAX,
+                density: 1.,
+                scale: 2,
+            },
+            time: Some(0.5),
+            aspect_ratio: 16. / 9.,
+            settings: RenderSettings {
+                image_width: 16,
+                image_height: 40,
+                num_samples_per_pixel: 2,
+                max_diffusion_depth: 5,
+                roulette: vec![1., 0.5],
+                num_threads: 1,
+                low_priority: false,
+                check_samples: false,
+                collect_stats: false,
+                path_guiding: false,
+                adaptive_samples: 0,
+                seed: Some(7),
+                filter: Filter::Tent,
+                filter_sampling: true,
+                show_progress: true,
+            },
+        }
+    }
+
+    #[test]
+    fn frame_spec_tests() {
+        let frame = frame();
+        let local_settings = RenderSettings {
+            num_threads: 4,
+            low_priority: true,
+            ..frame.settings.clone()
+        };
+        let decoded = FrameSpec::from_json(&frame.to_json(), &local_settings).unwrap();
+        assert_eq!(
+            FrameSpec {
+                settings: local_settings.clone(),
+                ..frame.clone()
+            },
+            decoded
+        );
+        let mut json = frame.to_json();
+        if let Json::Object(members) = &mut json {
+            members.retain(|(key, _)| key != "width");
+        }
+        assert!(FrameSpec::from_json(&json, &local_settings).is_err());
+
+        let mut json = frame.to_json();
+        if let Json::Object(members) = &mut json {
+            for (key, value) in members.iter_mut() {
+                if key == "density" {
+                    *value = Json::Number(1e300);
+                }
+            }
+        }
+        assert!(FrameSpec::from_json(&json, &local_settings).is_err());
+    }
+
+    #[test]
+    fn render_on_workers_tests() {
+        let frame = frame();
+        let scene = frame.scene().unwrap();
+        let expected = Renderer::new(frame.settings.clone())
+            .render(&scene)
+            .to_rgba_f32();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let local_settings = frame.settings.clone();
+        thread::spawn(move || serve_worker(listener, local_settings));
+        // The second worker does not exist, so its tiles are taken over by the first.
+        let workers = [address, "127.0.0.1:1".to_string()];
+        let mut num_calls = 0;
+        let film = render_on_workers(&workers, &frame, &scene, |_| {
+            num_calls += 1;
+            true
+        })
+        .ok()
+        .unwrap();
+        assert_eq!(3, num_calls);
+        assert_eq!(expected, film.to_rgba_f32());
+    }
+
+    #[test]
+    fn request_tile_tests() {
+        let frame_json = frame().to_json();
+        let pixels = vec![
+            Color {
+                r: 0.5,
+                g: 0.25,
+                b: 1.,
+            };
+            16 * 2
+        ];
+        let response = |checksum: u64| {
+            let mut response =
+                format!("{{\"height\": 2, \"checksum\": \"{:016x}\"}}\n", checksum).into_bytes();
+            write_pixels(&mut response, &pixels).unwrap();
+            response
+        };
+        let (tile, received) = request_tile(
+            &mut io::Cursor::new(response(checksum(&pixels))),
+            &mut vec![],
+            &frame_json,
+            4..6,
+        )
+        .unwrap();
+        assert_eq!(checksum(&pixels), received);
+        assert_eq!((4, 2), (tile.y, tile.height));
+        assert_eq!(pixels, tile.pixels);
+
+        let error = request_tile(
+            &mut io::Cursor::new(response(checksum(&pixels) ^ 1)),
+            &mut vec![],
+            &frame_json,
+            4..6,
+        )
+        .unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+    }
+
+    #[test]
+    fn tile_problem_tests() {
+        let tile = |pixels: Vec<Color>| Tile {
+            x: 0,
+            y: 0,
+            width: pixels.len(),
+            height: 1,
+            num_finished: 1,
+            num_total: 1,
+            pixels,
+        };
+        let gray = |c: Float| Color { r: c, g: c, b: c };
+        let noisy = tile(vec![gray(0.25), gray(0.75)]);
+        assert_eq!(None, tile_problem(&noisy, &[]));
+        assert_eq!(None, tile_problem(&noisy, &[0.0625, 0.01, 0.1]));
+
+        let mut broken = noisy.clone();
+        broken.pixels[1].g = Float::NAN;
+        assert_eq!(
+            Some("1 non-finite pixels".to_string()),
+            tile_problem(&broken, &[])
+        );
+
+        // The smooth tiles accepted so far, whose dispersions are at most 0.1.
+        let smooth = [0.05, 0.01, 0.1, 0.02, 0.08];
+        // Correct tiles of high dynamic range are accepted among them,
+        // e.g. an emitter seen in a few pixels of a dark tile.
+        let mut emitter = vec![gray(0.3); 64];
+        emitter[10..13].fill(gray(15.));
+        let emitter = tile(emitter);
+        assert_eq!(None, tile_problem(&emitter, &smooth));
+        // A flat tile does not make the others look corrupted.
+        assert_eq!(None, tile_problem(&noisy, &[0., 0., 0.]));
+
+        // The exponents of corrupted memory are off, even if only by a flipped fifth bit.
+        let scale = |tile: &Tile, factor: Float| Tile {
+            pixels: tile
+                .pixels
+                .iter()
+                .map(|color| color.scale(factor))
+                .collect(),
+            ..tile.clone()
+        };
+        let factor = (2. as Float).powi(20);
+        assert!(tile_problem(&scale(&noisy, factor), &smooth).is_some());
+        assert!(tile_problem(&scale(&emitter, factor), &smooth).is_some());
+        let divergent = tile(vec![gray(0.), gray(1e20)]);
+        assert!(tile_problem(&divergent, &[]).is_none());
+        assert!(tile_problem(&divergent, &smooth).is_some());
+    }
+}
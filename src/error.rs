@@ -0,0 +1,107 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The type for errors that stop loading scenes or rendering them.
+#[derive(Debug)]
+pub enum RenderError {
+    /// Reading or writing a file failed, where `path` is `None` for the standard output.
+    Io {
+        path: Option<PathBuf>,
+        source: io::Error,
+    },
+    /// A scene file, e.g. a molecule file, is malformed.
+    SceneParse { path: PathBuf, message: String },
+    /// Encoding an image or a video failed for reasons other than I/O.
+    Encoding { path: PathBuf, message: String },
+    /// A parameter is out of range or inconsistent with the others or with the build features.
+    InvalidParameter(String),
+    /// The scene has the problems found by `Scene::validate`.
+    InvalidScene(Vec<String>),
+    /// The rendering was stopped by the user, e.g. by closing the preview window.
+    Aborted,
+    /// The rendering was stopped by Ctrl-C after the frame was saved partially.
+    Interrupted { frame: u32 },
+}
+impl RenderError {
+    pub fn io(path: &Path, source: io::Error) -> Self {
+        RenderError::Io {
+            path: Some(path.to_path_buf()),
+            source,
+        }
+    }
+
+    /// Converts errors of PNG encoders, which also wrap I/O errors.
+    pub fn png(path: &Path, e: png::EncodingError) -> Self {
+        match e {
+            png::EncodingError::IoError(source) => RenderError::io(path, source),
+            e => RenderError::Encoding {
+                path: path.to_path_buf(),
+                message: e.to_string(),
+            },
+        }
+    }
+}
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RenderError::Io {
+                path: Some(path),
+                source,
+            } => write!(f, "{}: {}", path.display(), source),
+            RenderError::Io { path: None, source } => write!(f, "{}", source),
+            RenderError::SceneParse { path, message } | RenderError::Encoding { path, message } => {
+                write!(f, "{}: {}", path.display(), message)
+            }
+            RenderError::InvalidParameter(message) => write!(f, "{}", message),
+            RenderError::InvalidScene(problems) => {
+                write!(f, "invalid scene:")?;
+                for problem in problems {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            }
+            RenderError::Aborted => write!(f, "Aborted."),
+            RenderError::Interrupted { frame } => {
+                write!(f, "Interrupted; saved frame {} partially.", frame)
+            }
+        }
+    }
+}
+impl Error for RenderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RenderError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_tests() {
+        let path = Path::new("out.png");
+        let e = RenderError::io(path, io::Error::new(io::ErrorKind::NotFound, "not found"));
+        assert_eq!("out.png: not found", e.to_string());
+        assert!(e.source().is_some());
+        assert_eq!(
+            "invalid scene:\n  object #1: radius must be positive, but is -1\n  the scene has no lights",
+            RenderError::InvalidScene(vec![
+                "object #1: radius must be positive, but is -1".to_string(),
+                "the scene has no lights".to_string(),
+            ])
+            .to_string()
+        );
+        assert!(matches!(
+            RenderError::png(
+                path,
+                png::EncodingError::IoError(io::Error::other("disk full"))
+            ),
+            RenderError::Io { .. }
+        ));
+    }
+}
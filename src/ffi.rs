@@ -0,0 +1,383 @@
+use std::ffi::{c_char, CStr};
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::film::{Film, Filter};
+use crate::geometry::Float;
+use crate::json::Json;
+use crate::render::{RenderSettings, Renderer, MAX_NUM_PIXELS};
+use crate::scene_file;
+use crate::scenes::{self, Scene, SceneParams};
+
+/// The type for the results of the C API, where everything but `TRT_OK` is an error.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrtStatus {
+    Ok = 0,
+    NullPointer = 1,
+    InvalidArgument = 2,
+    UnknownScene = 3,
+    BufferTooSmall = 4,
+    /// A bug of the renderer; the handles passed to the call should not be used any more.
+    Panic = 5,
+    InvalidScene = 6,
+}
+
+/// The type for the settings of renderers created by `trt_renderer_new`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct TrtRenderSettings {
+    pub image_width: u32,
+    pub image_height: u32,
+    pub num_samples_per_pixel: u32,
+    pub max_diffusion_depth: i32,
+    /// The number of worker threads, where `0` renders on the calling thread.
+    pub num_threads: u32,
+    /// Whether `seed` is used, which makes the image reproducible.
+    pub has_seed: bool,
+    pub seed: u64,
+}
+
+/// The opaque type for scenes handed to C.
+pub struct TrtScene(Scene);
+
+/// The opaque type for renderers handed to C.
+pub struct TrtRenderer(Renderer);
+
+/// Runs `f` so that panics do not unwind into C, which is undefined behavior.
+fn catch_panic(f: impl FnOnce() -> TrtStatus) -> TrtStatus {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(TrtStatus::Panic)
+}
+
+/// Returns a static, NUL-terminated description of `status`.
+#[no_mangle]
+pub extern "C" fn trt_status_message(status: TrtStatus) -> *const c_char {
+    let message = match status {
+        TrtStatus::Ok => c"success",
+        TrtStatus::NullPointer => c"a required pointer is null",
+        TrtStatus::InvalidArgument => c"an argument is out of range",
+        TrtStatus::UnknownScene => c"no preset scene has the name",
+        TrtStatus::BufferTooSmall => c"the buffer is smaller than the image",
+        TrtStatus::Panic => c"the renderer panicked",
+        TrtStatus::InvalidScene => c"the scene file is invalid",
+    };
+    message.as_ptr()
+}
+
+/// Builds the preset scene `name` for images whose width divided by height is `aspect_ratio`,
+/// and stores its handle into `*out_scene`, which must be freed by `trt_scene_free`.
+///
+/// # Safety
+///
+/// `name` must be a NUL-terminated string, and `out_scene` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn trt_scene_new_preset(
+    name: *const c_char,
+    aspect_ratio: f64,
+    out_scene: *mut *mut TrtScene,
+) -> TrtStatus {
+    if name.is_null() || out_scene.is_null() {
+        return TrtStatus::NullPointer;
+    }
+    catch_panic(|| {
+        if !(aspect_ratio.is_finite() && aspect_ratio > 0.) {
+            return TrtStatus::InvalidArgument;
+        }
+        let Ok(name) = CStr::from_ptr(name).to_str() else {
+            return TrtStatus::UnknownScene;
+        };
+        match scenes::by_name(name, aspect_ratio as Float, &SceneParams::default()) {
+            Some(scene) => {
+                *out_scene = Box::into_raw(Box::new(TrtScene(scene)));
+                TrtStatus::Ok
+            }
+            None => TrtStatus::UnknownScene,
+        }
+    })
+}
+
+/// Builds the scene described by `json` in the format of scene files for images whose width divided by height
+/// is `aspect_ratio`, and stores its handle into `*out_scene`, which must be freed by `trt_scene_free`.
+/// Scenes that cannot be parsed or fail `Scene::validate`, e.g. with a negative radius, are `InvalidScene`.
+///
+/// # Safety
+///
+/// `json` must be a NUL-terminated string, and `out_scene` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn trt_scene_new_json(
+    json: *const c_char,
+    aspect_ratio: f64,
+    out_scene: *mut *mut TrtScene,
+) -> TrtStatus {
+    if json.is_null() || out_scene.is_null() {
+        return TrtStatus::NullPointer;
+    }
+    catch_panic(|| {
+        if !(aspect_ratio.is_finite() && aspect_ratio > 0.) {
+            return TrtStatus::InvalidArgument;
+        }
+        let Ok(text) = CStr::from_ptr(json).to_str() else {
+            return TrtStatus::InvalidScene;
+        };
+        let scene =
+            Json::parse(text).and_then(|json| scene_file::from_json(&json, aspect_ratio as Float));
+        match scene {
+            Ok(scene) if scene.validate().is_ok() => {
+                *out_scene = Box::into_raw(Box::new(TrtScene(scene)));
+                TrtStatus::Ok
+            }
+            _ => TrtStatus::InvalidScene,
+        }
+    })
+}
+
+/// Frees a scene created by `trt_scene_new_preset` or `trt_scene_new_json`; does nothing for the null pointer.
+///
+/// # Safety
+///
+/// `scene` must be null or a handle that is not freed yet and is not used by other threads.
+#[no_mangle]
+pub unsafe extern "C" fn trt_scene_free(scene: *mut TrtScene) {
+    if !scene.is_null() {
+        drop(Box::from_raw(scene));
+    }
+}
+
+/// Creates a renderer and stores its handle into `*out_renderer`,
+/// which must be freed by `trt_renderer_free`, for images of at most `MAX_NUM_PIXELS` pixels.
+///
+/// # Safety
+///
+/// `settings` must be valid for reads, and `out_renderer` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn trt_renderer_new(
+    settings: *const TrtRenderSettings,
+    out_renderer: *mut *mut TrtRenderer,
+) -> TrtStatus {
+    if settings.is_null() || out_renderer.is_null() {
+        return TrtStatus::NullPointer;
+    }
+    let settings = *settings;
+    if settings.image_width == 0 || settings.image_height < 2 {
+        // `render::sample_ray` divides by `image_height - 1` for the vertical coordinate of the rays.
+        return TrtStatus::InvalidArgument;
+    }
+    let num_pixels = (settings.image_width as usize).checked_mul(settings.image_height as usize);
+    if num_pixels.is_none_or(|num_pixels| num_pixels > MAX_NUM_PIXELS) {
+        return TrtStatus::InvalidArgument;
+    }
+    if settings.num_samples_per_pixel == 0 || settings.max_diffusion_depth < 0 {
+        return TrtStatus::InvalidArgument;
+    }
+    let renderer = Renderer::new(RenderSettings {
+        image_width: settings.image_width as usize,
+        image_height: settings.image_height as usize,
+        num_samples_per_pixel: settings.num_samples_per_pixel as usize,
+        max_diffusion_depth: settings.max_diffusion_depth,
+        roulette: vec![],
+        num_threads: settings.num_threads as usize,
+        low_priority: false,
+        check_samples: false,
+        collect_stats: false,
+        path_guiding: false,
+        adaptive_samples: 0,
+        seed: settings.has_seed.then_some(settings.seed),
+        filter: Filter::Box,
+        filter_sampling: false,
+        show_progress: true,
+    });
+    *out_renderer = Box::into_raw(Box::new(TrtRenderer(renderer)));
+    TrtStatus::Ok
+}
+
+/// Frees a renderer created by `trt_renderer_new`; does nothing for the null pointer.
+///
+/// # Safety
+///
+/// `renderer` must be null or a handle that is not freed yet and is not used by other threads.
+#[no_mangle]
+pub unsafe extern "C" fn trt_renderer_free(renderer: *mut TrtRenderer) {
+    if !renderer.is_null() {
+        drop(Box::from_raw(renderer));
+    }
+}
+
+/// Renders `scene` by `renderer` and calls `write` with the image unless `buffer_len` is too small.
+///
+/// # Safety
+///
+/// `renderer` and `scene` must be live handles, which may be shared by threads rendering concurrently.
+unsafe fn render_into(
+    renderer: *const TrtRenderer,
+    scene: *const TrtScene,
+    buffer_len: usize,
+    write: impl FnOnce(&Film),
+) -> TrtStatus {
+    if renderer.is_null() || scene.is_null() {
+        return TrtStatus::NullPointer;
+    }
+    let TrtRenderer(renderer) = &*renderer;
+    let TrtScene(scene) = &*scene;
+    let settings = &renderer.settings;
+    let image_len = settings
+        .image_width
+        .checked_mul(settings.image_height)
+        .and_then(|num_pixels| num_pixels.checked_mul(4));
+    if image_len.is_none_or(|image_len| buffer_len < image_len) {
+        return TrtStatus::BufferTooSmall;
+    }
+    catch_panic(|| {
+        write(&renderer.render(scene));
+        TrtStatus::Ok
+    })
+}
+
+/// Renders `scene` into `buffer` as gamma-corrected RGBA bytes in row-major order from the top-left pixel,
+/// where `buffer_len` is the number of bytes, which must be at least `4 * width * height`.
+///
+/// # Safety
+///
+/// `renderer` and `scene` must be live handles, and `buffer` must be valid for `buffer_len` writes.
+#[no_mangle]
+pub unsafe extern "C" fn trt_render_rgba8(
+    renderer: *const TrtRenderer,
+    scene: *const TrtScene,
+    buffer: *mut u8,
+    buffer_len: usize,
+) -> TrtStatus {
+    if buffer.is_null() {
+        return TrtStatus::NullPointer;
+    }
+    render_into(renderer, scene, buffer_len, |film| {
+        let data = film.to_rgba8();
+        std::ptr::copy_nonoverlapping(data.as_ptr(), buffer, data.len());
+    })
+}
+
+/// Renders `scene` into `buffer` as linear RGBA floats, e.g. for HDR textures,
+/// where `buffer_len` is the number of floats, which must be at least `4 * width * height`.
+///
+/// # Safety
+///
+/// `renderer` and `scene` must be live handles, and `buffer` must be valid for `buffer_len` writes.
+#[no_mangle]
+pub unsafe extern "C" fn trt_render_rgba_f32(
+    renderer: *const TrtRenderer,
+    scene: *const TrtScene,
+    buffer: *mut f32,
+    buffer_len: usize,
+) -> TrtStatus {
+    if buffer.is_null() {
+        return TrtStatus::NullPointer;
+    }
+    render_into(renderer, scene, buffer_len, |film| {
+        let data = film.to_rgba_f32();
+        std::ptr::copy_nonoverlapping(data.as_ptr(), buffer, data.len());
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr;
+
+    #[test]
+    fn ffi_tests() {
+        unsafe {
+            let mut scene = ptr::null_mut();
+            assert_eq!(
+                TrtStatus::UnknownScene,
+                trt_scene_new_preset(c"no-such-scene".as_ptr(), 1., &mut scene)
+            );
+            assert_eq!(
+                TrtStatus::Ok,
+                trt_scene_new_preset(c"three-spheres".as_ptr(), 8. / 6., &mut scene)
+            );
+            let mut json_scene = ptr::null_mut();
+            assert_eq!(
+                TrtStatus::InvalidScene,
+                trt_scene_new_json(c"{\"camera\": {}}".as_ptr(), 1., &mut json_scene)
+            );
+            let negative_radius = c"{
+                \"camera\": {\"look_from\": [0, 0, 1], \"look_at\": [0, 0, 0], \"vertical_fov\": 60},
+                \"background\": \"sky\",
+                \"materials\": [{\"type\": \"lambertian\", \"albedo\": [0.5, 0.5, 0.5]}],
+                \"objects\": [{\"type\": \"sphere\", \"center\": [0, 0, 0], \"radius\": -0.5, \"material\": 0}]
+            }";
+            assert_eq!(
+                TrtStatus::InvalidScene,
+                trt_scene_new_json(negative_radius.as_ptr(), 1., &mut json_scene)
+            );
+            let json = c"{
+                \"camera\": {\"look_from\": [0, 0, 1], \"look_at\": [0, 0, 0], \"vertical_fov\": 60},
+                \"background\": \"sky\",
+                \"materials\": [{\"type\": \"lambertian\", \"albedo\": [0.5, 0.5, 0.5]}],
+                \"objects\": [{\"type\": \"sphere\", \"center\": [0, 0, 0], \"radius\": 0.5, \"material\": 0}]
+            }";
+            assert_eq!(
+                TrtStatus::Ok,
+                trt_scene_new_json(json.as_ptr(), 8. / 6., &mut json_scene)
+            );
+            let mut settings = TrtRenderSettings {
+                image_width: 8,
+                image_height: 6,
+                num_samples_per_pixel: 2,
+                max_diffusion_depth: 5,
+                num_threads: 1,
+                has_seed: true,
+                seed: 1,
+            };
+            let mut renderer = ptr::null_mut();
+            settings.image_height = 0;
+            assert_eq!(
+                TrtStatus::InvalidArgument,
+                trt_renderer_new(&settings, &mut renderer)
+            );
+            settings.image_width = u32::MAX;
+            settings.image_height = u32::MAX;
+            assert_eq!(
+                TrtStatus::InvalidArgument,
+                trt_renderer_new(&settings, &mut renderer)
+            );
+            settings.image_width = 8;
+            settings.image_height = 6;
+            assert_eq!(TrtStatus::Ok, trt_renderer_new(&settings, &mut renderer));
+
+            let mut buffer = vec![0; 4 * 8 * 6];
+            assert_eq!(
+                TrtStatus::BufferTooSmall,
+                trt_render_rgba8(renderer, scene, buffer.as_mut_ptr(), buffer.len() - 1)
+            );
+            assert_eq!(
+                TrtStatus::NullPointer,
+                trt_render_rgba8(ptr::null(), scene, buffer.as_mut_ptr(), buffer.len())
+            );
+            assert_eq!(
+                TrtStatus::Ok,
+                trt_render_rgba8(renderer, scene, buffer.as_mut_ptr(), buffer.len())
+            );
+            assert!(buffer.chunks(4).all(|pixel| pixel[3] == u8::MAX));
+            let mut floats = vec![0.; 4 * 8 * 6];
+            assert_eq!(
+                TrtStatus::Ok,
+                trt_render_rgba_f32(renderer, scene, floats.as_mut_ptr(), floats.len())
+            );
+            assert!(floats.iter().any(|&value| value > 0.));
+            assert_eq!(
+                TrtStatus::Ok,
+                trt_render_rgba8(renderer, json_scene, buffer.as_mut_ptr(), buffer.len())
+            );
+            // The sphere in the middle is darker than the sky at the top-left corner.
+            let middle = 4 * (3 * 8 + 4);
+            assert!(buffer[middle + 2] < buffer[2]);
+
+            let message = CStr::from_ptr(trt_status_message(TrtStatus::BufferTooSmall));
+            assert_eq!(
+                "the buffer is smaller than the image",
+                message.to_str().unwrap()
+            );
+            trt_renderer_free(renderer);
+            trt_scene_free(json_scene);
+            trt_scene_free(scene);
+        }
+    }
+}
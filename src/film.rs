@@ -0,0 +1,721 @@
+extern crate png;
+
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use crate::color::Color;
+use crate::geometry::Float;
+use crate::render::{InvalidSample, PathLengths, RenderStats, Tile};
+use crate::tonemap::TransferFunction;
+
+/// The maximum number of invalid samples kept by films, beyond which they are only counted.
+const MAX_INVALID_SAMPLES: usize = 10000;
+
+/// The reconstruction filters, which weight each sample by its offset from the centers of
+/// the pixels around it so that a sample also counts for its neighbors, e.g. to smooth fine geometry.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Filter {
+    /// Counts each sample only for its own pixel, which is the plain average of the samples.
+    #[default]
+    Box,
+    Tent,
+    Gaussian,
+    /// The filter of Mitchell and Netravali with B = C = 1/3, which is sharper than `Gaussian`
+    /// and may have negative weights.
+    Mitchell,
+}
+impl Filter {
+    /// Returns the distance from the center of a pixel in pixels beyond which samples weigh nothing.
+    pub fn radius(&self) -> Float {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell => 2.,
+        }
+    }
+
+    /// Returns the number of the neighboring pixels on each side that a sample can count for.
+    pub fn margin(&self) -> usize {
+        (self.radius() - 0.5).ceil() as usize
+    }
+
+    /// Returns the weight of a sample at the offset (`dx`, `dy`) in pixels from the center of a pixel.
+    pub fn weight(&self, dx: Float, dy: Float) -> Float {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    fn weight_1d(&self, d: Float) -> Float {
+        match self {
+            // Half-open so that a sample on the border of two pixels counts for only one of them.
+            Filter::Box => {
+                if (-0.5..0.5).contains(&d) {
+                    1.
+                } else {
+                    0.
+                }
+            }
+            Filter::Tent => (1. - d.abs()).max(0.),
+            Filter::Gaussian => {
+                // The standard deviation is half a pixel, and the tail is cut off at the radius.
+                let gaussian = |d: Float| (-2. * d * d).exp();
+                (gaussian(d) - gaussian(self.radius())).max(0.)
+            }
+            Filter::Mitchell => {
+                let d = d.abs();
+                const B: Float = 1. / 3.;
+                const C: Float = 1. / 3.;
+                let weight = if d < 1. {
+                    (12. - 9. * B - 6. * C) * d * d * d
+                        + (-18. + 12. * B + 6. * C) * d * d
+                        + (6. - 2. * B)
+                } else if d < 2. {
+                    (-B - 6. * C) * d * d * d
+                        + (6. * B + 30. * C) * d * d
+                        + (-12. * B - 48. * C) * d
+                        + (8. * B + 24. * C)
+                } else {
+                    0.
+                };
+                weight / 6.
+            }
+        }
+    }
+
+    /// Returns the name of the filter, which `from_str` parses back.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Filter::Box => "box",
+            Filter::Tent => "tent",
+            Filter::Gaussian => "gaussian",
+            Filter::Mitchell => "mitchell",
+        }
+    }
+}
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "box" => Ok(Filter::Box),
+            "tent" => Ok(Filter::Tent),
+            "gaussian" => Ok(Filter::Gaussian),
+            "mitchell" => Ok(Filter::Mitchell),
+            _ => Err(format!(
+                "unknown filter: {} (expected box, tent, gaussian, or mitchell)",
+                s
+            )),
+        }
+    }
+}
+
+/// The table for drawing the offsets of samples from the center of a pixel in proportion to
+/// the magnitudes of the weights of a filter, which is tabulated in bins along each axis
+/// since the filters have no closed-form inverses in general.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FilterSampler {
+    filter: Filter,
+    /// The cumulative magnitudes of the weights over the bins from `-filter.radius()`,
+    /// which start with zero and are normalized to end with one.
+    cdf: Vec<Float>,
+}
+impl FilterSampler {
+    const NUM_BINS: usize = 64;
+
+    pub fn new(filter: Filter) -> Self {
+        let radius = filter.radius();
+        let bin_width = 2. * radius / Self::NUM_BINS as Float;
+        let mut cdf = vec![0.];
+        for k in 0..Self::NUM_BINS {
+            let center = -radius + (k as Float + 0.5) * bin_width;
+            cdf.push(cdf[k] + filter.weight_1d(center).abs());
+        }
+        let total = cdf[Self::NUM_BINS];
+        for value in cdf.iter_mut() {
+            *value /= total;
+        }
+        FilterSampler { filter, cdf }
+    }
+
+    /// Returns an offset (`dx`, `dy`) in pixels drawn from `u` and `v` in [0, 1) with the weight
+    /// of the sample, i.e., the weight of the filter divided by the density of the offset,
+    /// which is negative where the filter is.
+    pub fn sample(&self, u: Float, v: Float) -> ((Float, Float), Float) {
+        let (dx, x_weight) = self.sample_1d(u);
+        let (dy, y_weight) = self.sample_1d(v);
+        ((dx, dy), x_weight * y_weight)
+    }
+
+    fn sample_1d(&self, u: Float) -> (Float, Float) {
+        let radius = self.filter.radius();
+        let bin_width = 2. * radius / Self::NUM_BINS as Float;
+        // The bins of zero weight are never drawn since they take no part of the CDF.
+        let k = self.cdf[1..]
+            .partition_point(|&value| value <= u)
+            .min(Self::NUM_BINS - 1);
+        let probability = self.cdf[k + 1] - self.cdf[k];
+        let t = ((u - self.cdf[k]) / probability).clamp(0., 1.);
+        let d = -radius + (k as Float + t) * bin_width;
+        (d, self.filter.weight_1d(d) * bin_width / probability)
+    }
+}
+
+/// The type for images being rendered.
+/// Accumulates the weighted samples of each pixel, where `(0, 0)` is the top-left pixel.
+pub struct Film {
+    pub width: usize,
+    pub height: usize,
+    sums: Vec<Color>,
+    weights: Vec<Float>,
+    invalid_samples: Vec<InvalidSample>,
+    num_invalid_samples: usize,
+    // Boxed to keep films small, which are returned in `Result`s.
+    stats: Box<RenderStats>,
+    /// The lengths of the paths of the pixels, which are allocated by the first `add_path_lengths`.
+    path_lengths: Box<[PathLengths]>,
+}
+impl Film {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            sums: vec![
+                Color {
+                    r: 0.,
+                    g: 0.,
+                    b: 0.,
+                };
+                width * height
+            ],
+            weights: vec![0.; width * height],
+            invalid_samples: vec![],
+            num_invalid_samples: 0,
+            stats: Box::default(),
+            path_lengths: Box::default(),
+        }
+    }
+
+    /// Records a sample whose color is NaN or infinite; only the first `MAX_INVALID_SAMPLES` are kept.
+    pub fn add_invalid_sample(&mut self, invalid_sample: InvalidSample) {
+        if self.invalid_samples.len() < MAX_INVALID_SAMPLES {
+            self.invalid_samples.push(invalid_sample);
+        }
+        self.num_invalid_samples += 1;
+    }
+
+    /// Returns the first invalid samples recorded by `add_invalid_sample`.
+    pub fn invalid_samples(&self) -> &[InvalidSample] {
+        &self.invalid_samples
+    }
+
+    /// Returns the number of all the invalid samples, including those not kept.
+    pub fn num_invalid_samples(&self) -> usize {
+        self.num_invalid_samples
+    }
+
+    /// Adds the statistics of some samples, e.g. those of a scan line.
+    pub fn add_stats(&mut self, stats: RenderStats) {
+        self.stats.merge(stats);
+    }
+
+    /// Returns the statistics of the samples added by `add_stats`,
+    /// which are empty unless `RenderSettings::collect_stats` is set.
+    pub fn stats(&self) -> &RenderStats {
+        &self.stats
+    }
+
+    /// Adds the lengths of the paths of some samples of the pixel.
+    pub fn add_path_lengths(&mut self, x: usize, y: usize, lengths: &PathLengths) {
+        if self.path_lengths.is_empty() {
+            self.path_lengths = vec![PathLengths::default(); self.width * self.height].into();
+        }
+        self.path_lengths[y * self.width + x].merge(lengths);
+    }
+
+    /// Returns the lengths of the paths of the pixels in row-major order added by `add_path_lengths`,
+    /// which are empty unless `RenderSettings::collect_stats` is set.
+    pub fn path_lengths(&self) -> &[PathLengths] {
+        &self.path_lengths
+    }
+
+    /// Adds `num_samples` samples to the pixel at once, where `sum` is the sum of their colors.
+    pub fn add_samples(&mut self, x: usize, y: usize, sum: &Color, num_samples: u32) {
+        self.add_weighted(x, y, sum, num_samples as Float);
+    }
+
+    /// Adds samples of the total weight `weight` to the pixel, where `sum` is the sum of their colors
+    /// multiplied by their weights, e.g. those splatted by a reconstruction filter.
+    pub fn add_weighted(&mut self, x: usize, y: usize, sum: &Color, weight: Float) {
+        let index = y * self.width + x;
+        self.sums[index] = self.sums[index].add(sum);
+        self.weights[index] += weight;
+    }
+
+    /// Adds the samples of the `y`-th scan line of `other`, which must be of the same size,
+    /// e.g. to accumulate the passes of progressive rendering.
+    pub fn add_scan_line(&mut self, other: &Film, y: usize) {
+        assert!(
+            self.width == other.width && self.height == other.height,
+            "the films have different sizes"
+        );
+        let row = y * self.width..(y + 1) * self.width;
+        for index in row {
+            self.sums[index] = self.sums[index].add(&other.sums[index]);
+            self.weights[index] += other.weights[index];
+        }
+    }
+
+    /// Adds the pixels of `tile`, e.g. rendered by another machine, each as a single sample
+    /// so that the resulting colors are exactly those of the tile.
+    pub fn add_tile(&mut self, tile: &Tile) {
+        for dy in 0..tile.height {
+            for dx in 0..tile.width {
+                let color = &tile.pixels[dy * tile.width + dx];
+                self.add_samples(tile.x + dx, tile.y + dy, color, 1);
+            }
+        }
+    }
+
+    /// Replaces the samples of the pixel with a single sample of `color`, e.g. for post-processing.
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: &Color) {
+        let index = y * self.width + x;
+        self.sums[index] = color.clone();
+        self.weights[index] = 1.;
+    }
+
+    /// Returns the weighted average of the samples of the pixel, or black if it has no samples yet.
+    /// The negative components, which filters with negative weights may leave, are clamped to zero.
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        let index = y * self.width + x;
+        let weight = self.weights[index];
+        if weight <= 0. {
+            Color {
+                r: 0.,
+                g: 0.,
+                b: 0.,
+            }
+        } else {
+            // Unlike `max`, keeps NaN so that invalid samples are not hidden.
+            let clamp = |c: Float| if c < 0. { 0. } else { c };
+            let color = self.sums[index].scale(1. / weight);
+            Color {
+                r: clamp(color.r),
+                g: clamp(color.g),
+                b: clamp(color.b),
+            }
+        }
+    }
+
+    /// Returns a smaller film whose pixels are the averages of the blocks of `factor` × `factor` pixels,
+    /// e.g. for previews sent over the network. The blocks at the right and bottom edges may be smaller.
+    pub fn downsample(&self, factor: usize) -> Film {
+        assert!(factor > 0, "the factor must be positive");
+        let mut film = Film::new(self.width.div_ceil(factor), self.height.div_ceil(factor));
+        for y in 0..self.height {
+            for x in 0..self.width {
+                film.add_samples(x / factor, y / factor, &self.pixel(x, y), 1);
+            }
+        }
+        film
+    }
+
+    /// Writes the image encoded by `transfer` in the plain PPM format.
+    pub fn write_ppm(&self, out: &mut impl Write, transfer: TransferFunction) -> io::Result<()> {
+        writeln!(out, "P3")?;
+        writeln!(out, "{} {}", self.width, self.height)?;
+        writeln!(out, "255")?;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                transfer.encode(&self.pixel(x, y)).write(out)?;
+            }
+        }
+        out.flush()
+    }
+
+    /// Returns the image encoded by `transfer` as RGB bytes in row-major order.
+    pub fn to_rgb8(&self, transfer: TransferFunction) -> Vec<u8> {
+        let mut data = Vec::with_capacity(3 * self.width * self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                data.extend(transfer.encode(&self.pixel(x, y)).to_bytes());
+            }
+        }
+        data
+    }
+
+    /// Returns the gamma-corrected image as RGBA bytes in row-major order,
+    /// where every pixel is opaque.
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(4 * self.width * self.height);
+        for rgb in self.to_rgb8(TransferFunction::Gamma2).chunks(3) {
+            data.extend(rgb);
+            data.push(u8::MAX);
+        }
+        data
+    }
+
+    /// Returns the linear colors of the image as RGBA in row-major order,
+    /// where the alpha of every pixel is 1.
+    // The casts are no-ops only if the `f32` feature is enabled.
+    #[allow(clippy::unnecessary_cast)]
+    pub fn to_rgba_f32(&self) -> Vec<f32> {
+        let mut data = Vec::with_capacity(4 * self.width * self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let color = self.pixel(x, y);
+                data.extend([color.r as f32, color.g as f32, color.b as f32, 1.]);
+            }
+        }
+        data
+    }
+
+    /// Writes the image encoded by `transfer`, which is usually linear, in the Portable Float Map format,
+    /// which keeps the colors brighter than white, e.g. for tone mapping afterwards.
+    // The casts to `f32` are no-ops only if the `f32` feature is enabled.
+    #[allow(clippy::unnecessary_cast)]
+    pub fn write_pfm(&self, out: &mut impl Write, transfer: TransferFunction) -> io::Result<()> {
+        // The negative scale means little endian.
+        write!(out, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        // The scan lines go from the bottom to the top.
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let color = transfer.encode(&self.pixel(x, y));
+                for value in [color.r, color.g, color.b] {
+                    out.write_all(&(value as f32).to_le_bytes())?;
+                }
+            }
+        }
+        out.flush()
+    }
+
+    /// Writes the lengths of the paths of the pixels in the Portable Float Map format with three channels,
+    /// the mean and the largest numbers of bounces and the fraction of the paths terminated
+    /// by the roulette, where the pixels without paths are 0.
+    // The casts to `f32` are no-ops only if the `f32` feature is enabled.
+    #[allow(clippy::unnecessary_cast)]
+    pub fn write_path_lengths_pfm(&self, out: &mut impl Write) -> io::Result<()> {
+        write!(out, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        let none = PathLengths::default();
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let lengths = self.path_lengths.get(y * self.width + x).unwrap_or(&none);
+                let terminated = if lengths.num_paths == 0 {
+                    0.
+                } else {
+                    lengths.num_terminated as Float / lengths.num_paths as Float
+                };
+                for value in [lengths.mean(), lengths.max as Float, terminated] {
+                    out.write_all(&(value as f32).to_le_bytes())?;
+                }
+            }
+        }
+        out.flush()
+    }
+
+    /// Writes the image encoded by `transfer` in the PNG format.
+    pub fn write_png(
+        &self,
+        out: &mut impl Write,
+        transfer: TransferFunction,
+    ) -> Result<(), png::EncodingError> {
+        let mut encoder = png::Encoder::new(out, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.to_rgb8(transfer))?;
+        writer.finish()
+    }
+}
+
+/// The formats in which images are written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    /// The plain PPM format of 8-bit colors.
+    Ppm,
+    /// The Portable Float Map format of floating-point colors, which keeps the colors brighter than white.
+    Pfm,
+    /// The PNG format of 8-bit colors.
+    Png,
+}
+impl ImageFormat {
+    /// Returns the transfer function of the images in the format unless another is chosen:
+    /// linear for PFM, which is read by tools expecting linear colors, and gamma 2.0 for the others.
+    pub fn default_transfer(&self) -> TransferFunction {
+        match self {
+            ImageFormat::Pfm => TransferFunction::Linear,
+            ImageFormat::Ppm | ImageFormat::Png => TransferFunction::Gamma2,
+        }
+    }
+
+    /// Whether the scan lines are stored from the bottom to the top.
+    pub fn is_bottom_up(&self) -> bool {
+        *self == ImageFormat::Pfm
+    }
+}
+
+/// The type for writing images scan line by scan line as soon as they are rendered, so that
+/// images too large to be in memory at once can be written in the same formats as `Film`.
+/// The scan lines must be written in the order the format stores them,
+/// i.e., from the bottom if `ImageFormat::is_bottom_up`.
+pub struct ScanLineWriter<W: Write + 'static> {
+    width: usize,
+    transfer: TransferFunction,
+    num_remaining: usize,
+    out: ScanLineOutput<W>,
+}
+enum ScanLineOutput<W: Write + 'static> {
+    Ppm(W),
+    Pfm(W),
+    Png(Box<png::StreamWriter<'static, W>>),
+}
+impl<W: Write + 'static> ScanLineWriter<W> {
+    /// Writes the header of an image of `width` x `height` pixels in `format` to `out`,
+    /// whose colors are encoded by `transfer`.
+    pub fn new(
+        mut out: W,
+        format: ImageFormat,
+        transfer: TransferFunction,
+        width: usize,
+        height: usize,
+    ) -> io::Result<Self> {
+        let out = match format {
+            ImageFormat::Ppm => {
+                write!(out, "P3\n{} {}\n255\n", width, height)?;
+                ScanLineOutput::Ppm(out)
+            }
+            ImageFormat::Pfm => {
+                // The negative scale means little endian.
+                write!(out, "PF\n{} {}\n-1.0\n", width, height)?;
+                ScanLineOutput::Pfm(out)
+            }
+            ImageFormat::Png => {
+                let mut encoder = png::Encoder::new(out, width as u32, height as u32);
+                encoder.set_color(png::ColorType::Rgb);
+                encoder.set_depth(png::BitDepth::Eight);
+                let writer = encoder.write_header().map_err(png_io_error)?;
+                ScanLineOutput::Png(Box::new(writer.into_stream_writer().map_err(png_io_error)?))
+            }
+        };
+        Ok(ScanLineWriter {
+            width,
+            transfer,
+            num_remaining: height,
+            out,
+        })
+    }
+
+    /// Writes the next scan line of the linear colors of the pixels from the left.
+    /// Panics if the number of the colors is not the width or all the scan lines are written.
+    // The casts to `f32` are no-ops only if the `f32` feature is enabled.
+    #[allow(clippy::unnecessary_cast)]
+    pub fn write_scan_line(&mut self, colors: &[Color]) -> io::Result<()> {
+        assert_eq!(self.width, colors.len(), "the scan line has a wrong width");
+        assert!(self.num_remaining > 0, "all the scan lines are written");
+        self.num_remaining -= 1;
+        let transfer = self.transfer;
+        match &mut self.out {
+            ScanLineOutput::Ppm(out) => {
+                for color in colors {
+                    transfer.encode(color).write(out)?;
+                }
+            }
+            ScanLineOutput::Pfm(out) => {
+                for color in colors {
+                    let color = transfer.encode(color);
+                    for value in [color.r, color.g, color.b] {
+                        out.write_all(&(value as f32).to_le_bytes())?;
+                    }
+                }
+            }
+            ScanLineOutput::Png(out) => {
+                let bytes: Vec<u8> = colors
+                    .iter()
+                    .flat_map(|color| transfer.encode(color).to_bytes())
+                    .collect();
+                out.write_all(&bytes)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Passes the scan lines written so far on to the output, e.g. so that the next program
+    /// of a pipeline can read them before the image is finished.
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.out {
+            ScanLineOutput::Ppm(out) | ScanLineOutput::Pfm(out) => out.flush(),
+            // This writes the compressed scan lines as a chunk.
+            ScanLineOutput::Png(out) => out.flush(),
+        }
+    }
+
+    /// Finishes the image. Panics if some scan lines are not written yet.
+    pub fn finish(self) -> io::Result<()> {
+        assert_eq!(0, self.num_remaining, "some scan lines are not written");
+        match self.out {
+            ScanLineOutput::Ppm(mut out) | ScanLineOutput::Pfm(mut out) => out.flush(),
+            ScanLineOutput::Png(out) => out.finish().map_err(png_io_error),
+        }
+    }
+}
+
+fn png_io_error(error: png::EncodingError) -> io::Error {
+    match error {
+        png::EncodingError::IoError(error) => error,
+        error => io::Error::other(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx::assert_approx_eq;
+
+    #[test]
+    fn film_tests() {
+        let mut film = Film::new(2, 1);
+        film.add_samples(
+            1,
+            0,
+            &Color {
+                r: 1.,
+                g: 0.5,
+                b: 0.,
+            },
+            1,
+        );
+        film.add_samples(
+            1,
+            0,
+            &Color {
+                r: 0.,
+                g: 1.5,
+                b: 2.,
+            },
+            3,
+        );
+        assert_eq!(
+            Color {
+                r: 0.25,
+                g: 0.5,
+                b: 0.5,
+            },
+            film.pixel(1, 0)
+        );
+        assert_eq!(
+            Color {
+                r: 0.,
+                g: 0.,
+                b: 0.,
+            },
+            film.pixel(0, 0)
+        );
+
+        let small = film.downsample(2);
+        assert_eq!((1, 1), (small.width, small.height));
+        assert_eq!(
+            Color {
+                r: 0.125,
+                g: 0.25,
+                b: 0.25,
+            },
+            small.pixel(0, 0)
+        );
+
+        let mut pfm = vec![];
+        film.write_pfm(&mut pfm, TransferFunction::Linear).unwrap();
+        let header = b"PF\n2 1\n-1.0\n";
+        assert_eq!(header, &pfm[..header.len()]);
+        let values: Vec<f32> = pfm[header.len()..]
+            .chunks(4)
+            .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        assert_eq!(vec![0., 0., 0., 0.25, 0.5, 0.5], values);
+    }
+
+    #[test]
+    fn filter_tests() {
+        // The box filter counts a sample on the border of two pixels only for the one to the right.
+        assert_eq!(1., Filter::Box.weight(-0.5, 0.));
+        assert_eq!(0., Filter::Box.weight(0.5, 0.));
+        assert_eq!(0.25, Filter::Tent.weight(0.5, -0.5));
+        // The Mitchell filter has negative lobes.
+        assert!(Filter::Mitchell.weight(1.5, 0.) < 0.);
+        for filter in [
+            Filter::Box,
+            Filter::Tent,
+            Filter::Gaussian,
+            Filter::Mitchell,
+        ] {
+            assert_eq!(Ok(filter), filter.name().parse());
+            // The offsets drawn from the filter are within its radius, and their weights
+            // average to the integral of the filter, which is one for those other than `Gaussian`.
+            let sampler = FilterSampler::new(filter);
+            let n = 1000;
+            let mut total_weight = 0.;
+            for a in 0..n {
+                let u = (a as Float + 0.5) / n as Float;
+                let ((dx, dy), weight) = sampler.sample(u, 0.5);
+                assert!(dx.abs() <= filter.radius(), "{:?} at {}", filter, dx);
+                assert_eq!(sampler.sample_1d(0.5).0, dy);
+                total_weight += weight / sampler.sample_1d(0.5).1;
+            }
+            if filter != Filter::Gaussian {
+                assert_approx_eq!(1., total_weight / n as Float, 1e-2);
+            }
+            assert!(filter.weight(0., 0.) > 0.);
+            assert_eq!(0., filter.weight(filter.radius(), 0.));
+            // The pixels within the margin get all the weight of a sample.
+            let margin = filter.margin() as Float;
+            let beyond = [0.5 + margin, -0.5 - margin - 0.01];
+            for d in beyond {
+                assert_eq!(0., filter.weight(d, 0.), "{:?} at {}", filter, d);
+            }
+        }
+        let ((_, dy), weight) = FilterSampler::new(Filter::Mitchell).sample(0.5, 0.01);
+        assert!(dy < -1. && weight < 0., "{} {}", dy, weight);
+        assert!("lanczos".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn scan_line_writer_tests() {
+        let mut film = Film::new(3, 2);
+        for (index, value) in [0.25, 0.5, 1.5, 0., 0.75, 0.125].into_iter().enumerate() {
+            let color = Color {
+                r: value,
+                g: 0.5 * value,
+                b: 1.,
+            };
+            film.add_samples(index % 3, index / 3, &color, 1);
+        }
+        let directory = std::env::temp_dir();
+        let path = directory.join(format!("scan_line_writer_{}", std::process::id()));
+        let srgb = TransferFunction::Srgb;
+        let write = |format: ImageFormat, transfer: TransferFunction| {
+            let file = std::fs::File::create(&path).unwrap();
+            let mut writer = ScanLineWriter::new(file, format, transfer, 3, 2).unwrap();
+            for y in [0, 1] {
+                let y = if format.is_bottom_up() { 1 - y } else { y };
+                let colors: Vec<Color> = (0..3).map(|x| film.pixel(x, y)).collect();
+                writer.write_scan_line(&colors).unwrap();
+            }
+            writer.finish().unwrap();
+            std::fs::read(&path).unwrap()
+        };
+
+        // The images are the same as those written by the film at once.
+        for transfer in [ImageFormat::Ppm.default_transfer(), srgb] {
+            let mut ppm = vec![];
+            film.write_ppm(&mut ppm, transfer).unwrap();
+            assert_eq!(ppm, write(ImageFormat::Ppm, transfer));
+            let mut pfm = vec![];
+            film.write_pfm(&mut pfm, transfer).unwrap();
+            assert_eq!(pfm, write(ImageFormat::Pfm, transfer));
+        }
+        let png = write(ImageFormat::Png, srgb);
+        let mut reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        assert_eq!(film.to_rgb8(srgb), pixels);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,51 +1,118 @@
 extern crate rand;
 
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cell::RefCell;
+use std::ops::{Add, AddAssign, Div, Index, IndexMut, Mul, Neg, Sub, SubAssign};
 
-#[derive(Clone, Debug, PartialEq)]
-pub struct Vec3 {
-    pub x: f64,
-    pub y: f64,
-    pub z: f64,
-}
-impl Vec3 {
-    pub fn add(&self, v: &Self) -> Self {
-        Vec3 {
-            x: self.x + v.x,
-            y: self.y + v.y,
-            z: self.z + v.z,
-        }
-    }
+/// The type for real numbers used for geometry and colors,
+/// which is `f32` if the `f32` feature is enabled and `f64` otherwise.
+#[cfg(not(feature = "f32"))]
+pub type Float = f64;
+#[cfg(feature = "f32")]
+pub type Float = f32;
 
-    pub fn subtract(&self, v: &Self) -> Self {
-        Vec3 {
-            x: self.x - v.x,
-            y: self.y - v.y,
-            z: self.z - v.z,
-        }
-    }
+#[cfg(feature = "f32")]
+pub use std::f32::consts;
+/// The mathematical constants of `Float`.
+#[cfg(not(feature = "f32"))]
+pub use std::f64::consts;
 
-    pub fn scale(&self, ratio: f64) -> Self {
-        Vec3 {
-            x: self.x * ratio,
-            y: self.y * ratio,
-            z: self.z * ratio,
+/// Implements a binary operator for all the combinations of owned and borrowed operands
+/// by using `$f`, which takes two borrowed operands.
+macro_rules! impl_binary_operator {
+    ($trait:ident, $method:ident, $lhs:ty, $rhs:ty, $output:ty, $f:expr) => {
+        impl $trait<&$rhs> for &$lhs {
+            type Output = $output;
+            fn $method(self, rhs: &$rhs) -> $output {
+                let f: fn(&$lhs, &$rhs) -> $output = $f;
+                f(self, rhs)
+            }
         }
-    }
+        impl $trait<$rhs> for &$lhs {
+            type Output = $output;
+            fn $method(self, rhs: $rhs) -> $output {
+                self.$method(&rhs)
+            }
+        }
+        impl $trait<&$rhs> for $lhs {
+            type Output = $output;
+            fn $method(self, rhs: &$rhs) -> $output {
+                (&self).$method(rhs)
+            }
+        }
+        impl $trait<$rhs> for $lhs {
+            type Output = $output;
+            fn $method(self, rhs: $rhs) -> $output {
+                (&self).$method(&rhs)
+            }
+        }
+    };
+}
 
-    pub fn divide(&self, d: f64) -> Self {
-        Vec3 {
-            x: self.x / d,
-            y: self.y / d,
-            z: self.z / d,
+/// Implements multiplication and division by scalars for both owned and borrowed operands
+/// for types with the fields `x`, `y`, and `z`.
+macro_rules! impl_scalar_operators {
+    ($t:ty) => {
+        impl Mul<Float> for &$t {
+            type Output = Vec3;
+            fn mul(self, ratio: Float) -> Vec3 {
+                Vec3 {
+                    x: self.x * ratio,
+                    y: self.y * ratio,
+                    z: self.z * ratio,
+                }
+            }
         }
-    }
+        impl Mul<Float> for $t {
+            type Output = Vec3;
+            fn mul(self, ratio: Float) -> Vec3 {
+                &self * ratio
+            }
+        }
+        impl Mul<&$t> for Float {
+            type Output = Vec3;
+            fn mul(self, v: &$t) -> Vec3 {
+                v * self
+            }
+        }
+        impl Mul<$t> for Float {
+            type Output = Vec3;
+            fn mul(self, v: $t) -> Vec3 {
+                &v * self
+            }
+        }
+        impl Div<Float> for &$t {
+            type Output = Vec3;
+            fn div(self, d: Float) -> Vec3 {
+                Vec3 {
+                    x: self.x / d,
+                    y: self.y / d,
+                    z: self.z / d,
+                }
+            }
+        }
+        impl Div<Float> for $t {
+            type Output = Vec3;
+            fn div(self, d: Float) -> Vec3 {
+                &self / d
+            }
+        }
+    };
+}
 
-    pub fn length_squared(&self) -> f64 {
+#[derive(Clone, Debug, PartialEq)]
+pub struct Vec3 {
+    pub x: Float,
+    pub y: Float,
+    pub z: Float,
+}
+impl Vec3 {
+    pub fn length_squared(&self) -> Float {
         self.x * self.x + self.y * self.y + self.z * self.z
     }
 
-    pub fn length(&self) -> f64 {
+    pub fn length(&self) -> Float {
         self.length_squared().sqrt()
     }
 
@@ -53,7 +120,7 @@ impl Vec3 {
         UnitVec3::new(self)
     }
 
-    pub fn inner_product(&self, v: &Self) -> f64 {
+    pub fn inner_product(&self, v: &Self) -> Float {
         self.x * v.x + self.y * v.y + self.z * v.z
     }
 
@@ -64,18 +131,57 @@ impl Vec3 {
             z: self.x * v.y - self.y * v.x,
         }
     }
+
+    /// Multiplies the vectors component by component.
+    pub fn elementwise_product(&self, v: &Self) -> Self {
+        Vec3 {
+            x: self.x * v.x,
+            y: self.y * v.y,
+            z: self.z * v.z,
+        }
+    }
+
+    /// Returns the component-wise minimum of the vectors.
+    pub fn min(&self, v: &Self) -> Self {
+        Vec3 {
+            x: self.x.min(v.x),
+            y: self.y.min(v.y),
+            z: self.z.min(v.z),
+        }
+    }
+
+    /// Returns the component-wise maximum of the vectors.
+    pub fn max(&self, v: &Self) -> Self {
+        Vec3 {
+            x: self.x.max(v.x),
+            y: self.y.max(v.y),
+            z: self.z.max(v.z),
+        }
+    }
+
+    /// Checks that all the components are neither NaN nor infinite.
+    pub fn is_finite(&self) -> bool {
+        self.x.is_finite() && self.y.is_finite() && self.z.is_finite()
+    }
 }
 
 /// The type for representing 3D unit vectors (i.e. 3D vectors with their length 1)
 #[derive(Clone, Debug, PartialEq)]
 pub struct UnitVec3 {
-    x: f64,
-    y: f64,
-    z: f64,
+    x: Float,
+    y: Float,
+    z: Float,
 }
 impl UnitVec3 {
+    /// Normalizes `v`. In debug builds, panics if `v` is zero or not finite,
+    /// which would otherwise result in NaN directions that show up only in the final image.
     pub fn new(v: &Vec3) -> Self {
-        let w = v.divide(v.length());
+        let w = v / v.length();
+        debug_assert!(
+            (w.length_squared() - 1.).abs() < 1e-4,
+            "cannot normalize {:?}",
+            v
+        );
         Self {
             x: w.x,
             y: w.y,
@@ -90,29 +196,409 @@ impl UnitVec3 {
             z: self.z,
         }
     }
+
+    /// Returns the cosine of the angle between the unit vectors.
+    pub fn inner_product(&self, other: &UnitVec3) -> Float {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    /// Returns the direction reflected at a surface whose normal is `normal`.
+    pub fn reflect(&self, normal: &UnitVec3) -> UnitVec3 {
+        let v_normal = normal.inject();
+        (&self.inject() - &v_normal * (2. * self.inner_product(normal))).unit_vector()
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Point3 {
-    pub x: f64,
-    pub y: f64,
-    pub z: f64,
+    pub x: Float,
+    pub y: Float,
+    pub z: Float,
 }
 impl Point3 {
-    pub fn add(&self, v: &Vec3) -> Self {
+    /// Returns the point whose coordinates are the minimum of those of the points,
+    /// i.e. the lower corner of the axis-aligned box spanned by them.
+    pub fn min(&self, pt: &Self) -> Self {
+        Point3 {
+            x: self.x.min(pt.x),
+            y: self.y.min(pt.y),
+            z: self.z.min(pt.z),
+        }
+    }
+
+    /// Returns the point whose coordinates are the maximum of those of the points,
+    /// i.e. the upper corner of the axis-aligned box spanned by them.
+    pub fn max(&self, pt: &Self) -> Self {
         Point3 {
-            x: self.x + v.x,
-            y: self.y + v.y,
-            z: self.z + v.z,
+            x: self.x.max(pt.x),
+            y: self.y.max(pt.y),
+            z: self.z.max(pt.z),
+        }
+    }
+
+    /// Checks that all the coordinates are neither NaN nor infinite.
+    pub fn is_finite(&self) -> bool {
+        self.x.is_finite() && self.y.is_finite() && self.z.is_finite()
+    }
+}
+
+/// Implements indexing by axes (0 for x, 1 for y, and 2 for z)
+/// for types with the fields `x`, `y`, and `z`.
+macro_rules! impl_index {
+    ($t:ty) => {
+        impl Index<usize> for $t {
+            type Output = Float;
+            fn index(&self, axis: usize) -> &Float {
+                match axis {
+                    0 => &self.x,
+                    1 => &self.y,
+                    2 => &self.z,
+                    _ => panic!("axis out of range: {}", axis),
+                }
+            }
         }
+        impl IndexMut<usize> for $t {
+            fn index_mut(&mut self, axis: usize) -> &mut Float {
+                match axis {
+                    0 => &mut self.x,
+                    1 => &mut self.y,
+                    2 => &mut self.z,
+                    _ => panic!("axis out of range: {}", axis),
+                }
+            }
+        }
+    };
+}
+
+impl_index!(Vec3);
+impl_index!(Point3);
+
+impl_binary_operator!(Add, add, Vec3, Vec3, Vec3, |u, v| Vec3 {
+    x: u.x + v.x,
+    y: u.y + v.y,
+    z: u.z + v.z,
+});
+impl_binary_operator!(Sub, sub, Vec3, Vec3, Vec3, |u, v| Vec3 {
+    x: u.x - v.x,
+    y: u.y - v.y,
+    z: u.z - v.z,
+});
+impl_binary_operator!(Add, add, Point3, Vec3, Point3, |pt, v| Point3 {
+    x: pt.x + v.x,
+    y: pt.y + v.y,
+    z: pt.z + v.z,
+});
+impl_binary_operator!(Sub, sub, Point3, Vec3, Point3, |pt, v| Point3 {
+    x: pt.x - v.x,
+    y: pt.y - v.y,
+    z: pt.z - v.z,
+});
+impl_binary_operator!(Sub, sub, Point3, Point3, Vec3, |pt1, pt2| Vec3 {
+    x: pt1.x - pt2.x,
+    y: pt1.y - pt2.y,
+    z: pt1.z - pt2.z,
+});
+impl_scalar_operators!(Vec3);
+impl_scalar_operators!(UnitVec3);
+
+impl Neg for &Vec3 {
+    type Output = Vec3;
+    fn neg(self) -> Vec3 {
+        self * -1.
+    }
+}
+impl Neg for Vec3 {
+    type Output = Vec3;
+    fn neg(self) -> Vec3 {
+        -&self
+    }
+}
+impl Neg for &UnitVec3 {
+    type Output = UnitVec3;
+    fn neg(self) -> UnitVec3 {
+        UnitVec3 {
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+}
+impl Neg for UnitVec3 {
+    type Output = UnitVec3;
+    fn neg(self) -> UnitVec3 {
+        -&self
     }
+}
+
+impl AddAssign<&Vec3> for Vec3 {
+    fn add_assign(&mut self, v: &Vec3) {
+        *self = &*self + v;
+    }
+}
+impl AddAssign<Vec3> for Vec3 {
+    fn add_assign(&mut self, v: Vec3) {
+        *self += &v;
+    }
+}
+impl SubAssign<&Vec3> for Vec3 {
+    fn sub_assign(&mut self, v: &Vec3) {
+        *self = &*self - v;
+    }
+}
+impl SubAssign<Vec3> for Vec3 {
+    fn sub_assign(&mut self, v: Vec3) {
+        *self -= &v;
+    }
+}
+impl AddAssign<&Vec3> for Point3 {
+    fn add_assign(&mut self, v: &Vec3) {
+        *self = &*self + v;
+    }
+}
+impl AddAssign<Vec3> for Point3 {
+    fn add_assign(&mut self, v: Vec3) {
+        *self += &v;
+    }
+}
 
-    pub fn subtract(&self, pt: &Point3) -> Vec3 {
+/// The type for orthonormal bases, i.e. local frames whose third axis is a given direction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Onb {
+    u: UnitVec3,
+    v: UnitVec3,
+    w: UnitVec3,
+}
+impl Onb {
+    /// Creates a right-handed basis whose third axis is `normal`;
+    /// the other two axes are chosen arbitrarily.
+    pub fn from_normal(normal: &UnitVec3) -> Self {
+        let w = normal.inject();
+        // Any vector not parallel to `w` can be used for making the tangent.
+        let a = if w.x.abs() > 0.9 {
+            Vec3 {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            }
+        } else {
+            Vec3 {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            }
+        };
+        let v = w.cross_product(&a).unit_vector();
+        let u = v.inject().cross_product(&w).unit_vector();
+        Self {
+            u,
+            v,
+            w: normal.clone(),
+        }
+    }
+
+    /// Converts the coordinates `(a, b, c)` in this basis into a vector in the world.
+    pub fn local(&self, a: Float, b: Float, c: Float) -> Vec3 {
+        &self.u * a + &self.v * b + &self.w * c
+    }
+}
+
+/// The type for quaternions, which are used for representing rotations.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Quat {
+    pub w: Float,
+    pub x: Float,
+    pub y: Float,
+    pub z: Float,
+}
+impl Quat {
+    /// Returns the rotation around `axis` by `radian` (counterclockwise when `axis` points to the viewer).
+    pub fn from_axis_angle(axis: &UnitVec3, radian: Float) -> Self {
+        let (sin, cos) = (radian / 2.).sin_cos();
+        let v = axis * sin;
+        Quat {
+            w: cos,
+            x: v.x,
+            y: v.y,
+            z: v.z,
+        }
+    }
+
+    /// Returns the rotation around the x, y, and z axes by the angles in degrees in this order.
+    pub fn from_euler_degrees(angles: &Vec3) -> Self {
+        let axis = |x: Float, y: Float, z: Float| Vec3 { x, y, z }.unit_vector();
+        let rotation_x = Quat::from_axis_angle(&axis(1., 0., 0.), angles.x.to_radians());
+        let rotation_y = Quat::from_axis_angle(&axis(0., 1., 0.), angles.y.to_radians());
+        let rotation_z = Quat::from_axis_angle(&axis(0., 0., 1.), angles.z.to_radians());
+        &(&rotation_z * &rotation_y) * &rotation_x
+    }
+
+    /// Returns the inverse rotation, assuming that the quaternion is of unit length.
+    pub fn inverse(&self) -> Self {
+        Quat {
+            w: self.w,
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+
+    pub fn rotate(&self, v: &Vec3) -> Vec3 {
+        // v' = v + 2w (q x v) + 2 q x (q x v), where q is the vector part.
+        let q = Vec3 {
+            x: self.x,
+            y: self.y,
+            z: self.z,
+        };
+        let t = q.cross_product(v) * 2.;
+        v + &t * self.w + q.cross_product(&t)
+    }
+}
+/// Composes rotations; `q1 * q2` is the rotation by `q2` followed by `q1`.
+impl Mul<&Quat> for &Quat {
+    type Output = Quat;
+    fn mul(self, q: &Quat) -> Quat {
+        Quat {
+            w: self.w * q.w - self.x * q.x - self.y * q.y - self.z * q.z,
+            x: self.w * q.x + self.x * q.w + self.y * q.z - self.z * q.y,
+            y: self.w * q.y - self.x * q.z + self.y * q.w + self.z * q.x,
+            z: self.w * q.z + self.x * q.y - self.y * q.x + self.z * q.w,
+        }
+    }
+}
+
+/// The type for affine transformations in homogeneous coordinates, stored in row-major order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mat4 {
+    pub rows: [[Float; 4]; 4],
+}
+impl Mat4 {
+    pub fn identity() -> Self {
+        let mut rows = [[0.; 4]; 4];
+        for (i, row) in rows.iter_mut().enumerate() {
+            row[i] = 1.;
+        }
+        Mat4 { rows }
+    }
+
+    pub fn translation(v: &Vec3) -> Self {
+        let mut m = Mat4::identity();
+        for axis in 0..3 {
+            m.rows[axis][3] = v[axis];
+        }
+        m
+    }
+
+    pub fn scaling(factors: &Vec3) -> Self {
+        let mut m = Mat4::identity();
+        for axis in 0..3 {
+            m.rows[axis][axis] = factors[axis];
+        }
+        m
+    }
+
+    pub fn rotation(q: &Quat) -> Self {
+        let Quat { w, x, y, z } = q;
+        Mat4 {
+            rows: [
+                [
+                    1. - 2. * (y * y + z * z),
+                    2. * (x * y - w * z),
+                    2. * (x * z + w * y),
+                    0.,
+                ],
+                [
+                    2. * (x * y + w * z),
+                    1. - 2. * (x * x + z * z),
+                    2. * (y * z - w * x),
+                    0.,
+                ],
+                [
+                    2. * (x * z - w * y),
+                    2. * (y * z + w * x),
+                    1. - 2. * (x * x + y * y),
+                    0.,
+                ],
+                [0., 0., 0., 1.],
+            ],
+        }
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut rows = [[0.; 4]; 4];
+        for (i, row) in rows.iter_mut().enumerate() {
+            for (j, entry) in row.iter_mut().enumerate() {
+                *entry = self.rows[j][i];
+            }
+        }
+        Mat4 { rows }
+    }
+
+    /// Returns the inverse matrix, or `None` if the matrix is (nearly) singular.
+    pub fn inverse(&self) -> Option<Self> {
+        // Gauss-Jordan elimination with partial pivoting:
+        let mut a = self.rows;
+        let mut inverse = Mat4::identity().rows;
+        for column in 0..4 {
+            let pivot = (column..4)
+                .max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs()))
+                .unwrap();
+            if a[pivot][column].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(column, pivot);
+            inverse.swap(column, pivot);
+            let d = a[column][column];
+            for j in 0..4 {
+                a[column][j] /= d;
+                inverse[column][j] /= d;
+            }
+            for i in 0..4 {
+                if i != column {
+                    let factor = a[i][column];
+                    for j in 0..4 {
+                        a[i][j] -= factor * a[column][j];
+                        inverse[i][j] -= factor * inverse[column][j];
+                    }
+                }
+            }
+        }
+        Some(Mat4 { rows: inverse })
+    }
+
+    pub fn transform_point(&self, pt: &Point3) -> Point3 {
+        let v = self.transform_direction(&Vec3 {
+            x: pt.x,
+            y: pt.y,
+            z: pt.z,
+        });
+        Point3 {
+            x: v.x + self.rows[0][3],
+            y: v.y + self.rows[1][3],
+            z: v.z + self.rows[2][3],
+        }
+    }
+
+    /// Transforms `v` by the linear part of the matrix, ignoring the translation.
+    pub fn transform_direction(&self, v: &Vec3) -> Vec3 {
+        let row = |i: usize| self.rows[i][0] * v.x + self.rows[i][1] * v.y + self.rows[i][2] * v.z;
         Vec3 {
-            x: self.x - pt.x,
-            y: self.y - pt.y,
-            z: self.z - pt.z,
+            x: row(0),
+            y: row(1),
+            z: row(2),
+        }
+    }
+}
+/// Composes transformations; `m1 * m2` is the transformation by `m2` followed by `m1`.
+impl Mul<&Mat4> for &Mat4 {
+    type Output = Mat4;
+    fn mul(self, m: &Mat4) -> Mat4 {
+        let mut rows = [[0.; 4]; 4];
+        for (i, row) in rows.iter_mut().enumerate() {
+            for (j, entry) in row.iter_mut().enumerate() {
+                *entry = (0..4).map(|k| self.rows[i][k] * m.rows[k][j]).sum();
+            }
         }
+        Mat4 { rows }
     }
 }
 
@@ -122,37 +608,149 @@ pub struct Ray {
     pub direction: UnitVec3,
 }
 impl Ray {
-    #[allow(dead_code)]
-    pub fn at(&self, t: f64) -> Point3 {
-        self.origin.add(&self.direction.inject().scale(t))
+    pub fn at(&self, t: Float) -> Point3 {
+        &self.origin + &self.direction * t
     }
+
+    /// Returns the ray leaving `point` on a surface whose normal is `normal` in `direction`,
+    /// where the origin is moved off the surface to the side `direction` goes to so that
+    /// the rounding errors of `point` do not make the ray hit the surface again.
+    /// The offset grows with the coordinates, in proportion to which the errors grow.
+    pub fn leaving(point: &Point3, normal: &UnitVec3, direction: UnitVec3) -> Ray {
+        let magnitude = point.x.abs().max(point.y.abs()).max(point.z.abs());
+        let offset = ORIGIN_OFFSET * (1. + magnitude);
+        let offset = if normal.inner_product(&direction) < 0. {
+            -offset
+        } else {
+            offset
+        };
+        Ray {
+            origin: point + &(normal * offset),
+            direction,
+        }
+    }
+}
+
+/// The offset of the origins of rays leaving surfaces relative to the magnitudes of the coordinates,
+/// which is about the square root of the machine epsilon, far larger than the rounding errors of
+/// the points of hits and far smaller than the details of scenes.
+#[cfg(not(feature = "f32"))]
+pub(crate) const ORIGIN_OFFSET: Float = 1e-8;
+#[cfg(feature = "f32")]
+pub(crate) const ORIGIN_OFFSET: Float = 3e-4;
+
+thread_local! {
+    /// The random number generator of each thread, which is seeded randomly unless `seed_random` is called.
+    static RNG: RefCell<StdRng> = RefCell::new(new_random_generator());
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn new_random_generator() -> StdRng {
+    StdRng::from_entropy()
+}
+
+/// Uses a fixed seed since `wasm32-unknown-unknown` has no source of entropy without JavaScript glue.
+#[cfg(target_arch = "wasm32")]
+fn new_random_generator() -> StdRng {
+    StdRng::seed_from_u64(0)
+}
+
+/// Resets the random number generator of the current thread
+/// so that the random values that follow are reproducible.
+pub fn seed_random(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
 }
 
 /// Returns a random double in [-0.5, 0.5).
-pub fn random_double() -> f64 {
-    let mut rng = rand::thread_rng();
-    rng.gen_range(-0.5..0.5)
+pub fn random_double() -> Float {
+    RNG.with(|rng| rng.borrow_mut().gen_range(-0.5..0.5))
 }
 
+/// Returns a direction uniformly distributed on the unit sphere.
+/// Since the area of the sphere between two heights is proportional to the difference of them,
+/// a uniform height together with a uniform azimuth suffices.
 pub fn random_unit_vector() -> UnitVec3 {
-    let v = Vec3 {
-        x: random_double(),
-        y: random_double(),
-        z: random_double(),
-    };
-    v.unit_vector()
+    let z = 2. * random_double();
+    let phi = 2. * consts::PI * (random_double() + 0.5);
+    let r = (1. - z * z).sqrt();
+    Vec3 {
+        x: r * phi.cos(),
+        y: r * phi.sin(),
+        z,
+    }
+    .unit_vector()
+}
+
+/// Returns a direction uniformly distributed on the hemisphere that `normal` points to.
+pub fn random_on_hemisphere(normal: &UnitVec3) -> UnitVec3 {
+    let u = random_unit_vector();
+    if u.inner_product(normal) >= 0. {
+        u
+    } else {
+        -&u
+    }
+}
+
+/// Returns a point uniformly distributed in the unit disk on the xy-plane.
+pub fn random_in_unit_disk() -> Vec3 {
+    loop {
+        let v = Vec3 {
+            x: 2. * random_double(),
+            y: 2. * random_double(),
+            z: 0.,
+        };
+        if v.length_squared() < 1. {
+            return v;
+        }
+    }
+}
+
+/// Returns a random direction around the z-axis whose density is proportional to
+/// the cosine of the angle from the axis, which is suitable for Lambertian reflectance.
+pub fn random_cosine_direction() -> Vec3 {
+    let r1 = random_double() + 0.5;
+    let r2 = random_double() + 0.5;
+    let phi = 2. * consts::PI * r1;
+    Vec3 {
+        x: phi.cos() * r2.sqrt(),
+        y: phi.sin() * r2.sqrt(),
+        z: (1. - r2).sqrt(),
+    }
 }
 
 pub fn reflect_vector(u_in: &UnitVec3, u_normal: &UnitVec3) -> UnitVec3 {
+    u_in.reflect(u_normal)
+}
+
+/// Returns the direction of `u_in` refracted at a surface by Snell's law,
+/// where `u_normal` is the normal on the side `u_in` comes from
+/// and `eta_ratio` is the refractive index of that side divided by that of the other side.
+/// Returns `None` if the light cannot refract, i.e., undergoes total internal reflection.
+pub fn refract_vector(u_in: &UnitVec3, u_normal: &UnitVec3, eta_ratio: Float) -> Option<UnitVec3> {
     let v_in = u_in.inject();
     let v_normal = u_normal.inject();
-    v_in.add(&v_normal.scale(-2. * v_in.inner_product(&v_normal)))
-        .unit_vector()
+
+    // v := d - (n^T d) n
+    let vp_in = &v_in - &v_normal * u_normal.inner_product(u_in);
+
+    // v' := (eta / eta') v
+    let vp_out = vp_in * eta_ratio;
+
+    // c := 1 - |v'|^2
+    let coeff_normal = 1. - vp_out.length_squared();
+    if coeff_normal < 0. {
+        return None;
+    }
+
+    // d' = v' - sqrt(c) n
+    Some((&vp_out - &v_normal * coeff_normal.sqrt()).unit_vector())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::approx::{assert_approx_eq, ApproxEq, DEFAULT_EPSILON};
+    use crate::arbitrary::{self, for_all_cases};
 
     #[test]
     fn vec3_tests() {
@@ -172,7 +770,7 @@ mod tests {
                 y: 5.,
                 z: 4.,
             },
-            v1.add(&v2)
+            &v1 + &v2
         );
         assert_eq!(11., v1.inner_product(&v2));
 
@@ -187,7 +785,7 @@ mod tests {
                 y: 6.,
                 z: 0.
             },
-            v3.scale(1.5)
+            &v3 * 1.5
         );
         assert_eq!(
             Vec3 {
@@ -195,7 +793,7 @@ mod tests {
                 y: 2.,
                 z: 0.,
             },
-            v3.divide(2.)
+            &v3 / 2.
         );
         assert_eq!(25., v3.length_squared());
         assert_eq!(5., v3.length());
@@ -245,7 +843,7 @@ mod tests {
                 y: -1.,
                 z: 2.,
             },
-            p1.subtract(&p2)
+            &p1 - &p2
         );
 
         let v = Vec3 {
@@ -259,10 +857,268 @@ mod tests {
                 y: 26.,
                 z: 33.,
             },
-            p1.add(&v)
+            &p1 + &v
         );
     }
 
+    #[test]
+    fn operator_tests() {
+        let v = Vec3 {
+            x: 1.,
+            y: -2.,
+            z: 3.,
+        };
+        assert_eq!(
+            Vec3 {
+                x: -2.,
+                y: 4.,
+                z: -6.,
+            },
+            -2. * &v
+        );
+        assert_eq!(
+            Vec3 {
+                x: -1.,
+                y: 2.,
+                z: -3.,
+            },
+            -v.clone()
+        );
+        let mut w = v.clone();
+        w += &v;
+        w -= Vec3 {
+            x: 0.,
+            y: 0.,
+            z: 6.,
+        };
+        assert_eq!(
+            Vec3 {
+                x: 2.,
+                y: -4.,
+                z: 0.,
+            },
+            w
+        );
+
+        let mut p = Point3 {
+            x: 1.,
+            y: 1.,
+            z: 1.,
+        };
+        assert_eq!(
+            Point3 {
+                x: 0.,
+                y: 3.,
+                z: -2.,
+            },
+            &p - &v
+        );
+        p += v;
+        assert_eq!(
+            Point3 {
+                x: 2.,
+                y: -1.,
+                z: 4.,
+            },
+            p
+        );
+
+        let u = Vec3 {
+            x: 0.,
+            y: 2.,
+            z: 0.,
+        }
+        .unit_vector();
+        assert_eq!(
+            Vec3 {
+                x: 0.,
+                y: -3.,
+                z: 0.,
+            },
+            -u * 3.
+        );
+    }
+
+    #[test]
+    fn elementwise_tests() {
+        let v1 = Vec3 {
+            x: 1.,
+            y: -2.,
+            z: 3.,
+        };
+        let v2 = Vec3 {
+            x: 2.,
+            y: 0.5,
+            z: -1.,
+        };
+        assert_eq!(
+            Vec3 {
+                x: 2.,
+                y: -1.,
+                z: -3.,
+            },
+            v1.elementwise_product(&v2)
+        );
+        assert_eq!(
+            Vec3 {
+                x: 1.,
+                y: -2.,
+                z: -1.,
+            },
+            v1.min(&v2)
+        );
+        assert_eq!(
+            Vec3 {
+                x: 2.,
+                y: 0.5,
+                z: 3.,
+            },
+            v1.max(&v2)
+        );
+
+        let mut p = Point3 {
+            x: 1.,
+            y: 2.,
+            z: 3.,
+        };
+        assert_eq!(
+            Point3 {
+                x: 0.,
+                y: 2.,
+                z: 3.,
+            },
+            p.min(&Point3 {
+                x: 0.,
+                y: 5.,
+                z: 5.,
+            })
+        );
+        assert_eq!(3., p[2]);
+        p[0] = 7.;
+        assert_eq!(7., p.x);
+        assert_eq!(-2., v1[1]);
+    }
+
+    #[test]
+    fn onb_tests() {
+        for normal in [
+            Vec3 {
+                x: 0.,
+                y: 0.,
+                z: 1.,
+            },
+            Vec3 {
+                x: -1.,
+                y: 0.,
+                z: 0.,
+            },
+            Vec3 {
+                x: 1.,
+                y: 2.,
+                z: -3.,
+            },
+        ] {
+            let normal = normal.unit_vector();
+            let onb = Onb::from_normal(&normal);
+            let (u, v, w) = (
+                onb.local(1., 0., 0.),
+                onb.local(0., 1., 0.),
+                onb.local(0., 0., 1.),
+            );
+            assert_eq!(normal.inject(), w);
+            assert_approx_eq!(0., u.inner_product(&v));
+            assert_approx_eq!(0., v.inner_product(&w));
+            assert_approx_eq!(0., w.inner_product(&u));
+            assert_approx_eq!(1., u.length());
+            assert_approx_eq!(1., v.length());
+            // Right-handed:
+            assert_approx_eq!(w, u.cross_product(&v));
+        }
+    }
+
+    #[test]
+    fn quat_tests() {
+        let x = Vec3 {
+            x: 1.,
+            y: 0.,
+            z: 0.,
+        };
+        let quarter_y = Quat::from_euler_degrees(&Vec3 {
+            x: 0.,
+            y: 90.,
+            z: 0.,
+        });
+        assert_approx_eq!(
+            Vec3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            },
+            quarter_y.rotate(&x)
+        );
+        assert_approx_eq!(x, quarter_y.inverse().rotate(&quarter_y.rotate(&x)));
+
+        // The x rotation is applied first, which leaves `x` as is.
+        let xy = Quat::from_euler_degrees(&Vec3 {
+            x: 90.,
+            y: 90.,
+            z: 0.,
+        });
+        assert_approx_eq!(quarter_y.rotate(&x), xy.rotate(&x));
+        assert_approx_eq!(xy.rotate(&x), Mat4::rotation(&xy).transform_direction(&x));
+    }
+
+    #[test]
+    fn mat4_tests() {
+        let m = &(&Mat4::translation(&Vec3 {
+            x: 1.,
+            y: 2.,
+            z: 3.,
+        }) * &Mat4::rotation(&Quat::from_euler_degrees(&Vec3 {
+            x: 10.,
+            y: 20.,
+            z: 30.,
+        }))) * &Mat4::scaling(&Vec3 {
+            x: 2.,
+            y: 3.,
+            z: 4.,
+        });
+        let pt = Point3 {
+            x: -1.,
+            y: 0.5,
+            z: 2.,
+        };
+        let inverse = m.inverse().unwrap();
+        let round_trip = inverse.transform_point(&m.transform_point(&pt));
+        assert_approx_eq!(pt, round_trip);
+        let product = &m * &inverse;
+        for i in 0..4 {
+            for j in 0..4 {
+                let expected = if i == j { 1. } else { 0. };
+                assert!(product.rows[i][j].approx_eq(&expected, DEFAULT_EPSILON));
+            }
+        }
+        assert_eq!(
+            Point3 {
+                x: 1.,
+                y: 2.,
+                z: 3.,
+            },
+            m.transform_point(&Point3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            })
+        );
+        assert!(Mat4::scaling(&Vec3 {
+            x: 1.,
+            y: 0.,
+            z: 1.,
+        })
+        .inverse()
+        .is_none());
+    }
+
     #[test]
     fn reflect_vector_tests() {
         let u_in = Vec3 {
@@ -277,7 +1133,7 @@ mod tests {
             z: 0.,
         }
         .unit_vector();
-        assert_eq!(
+        assert_approx_eq!(
             Vec3 {
                 x: 2.,
                 y: -1.,
@@ -287,4 +1143,264 @@ mod tests {
             reflect_vector(&u_in, &u_normal)
         );
     }
+
+    #[test]
+    fn unit_vec3_tests() {
+        let u1 = Vec3 {
+            x: 3.,
+            y: 4.,
+            z: 0.,
+        }
+        .unit_vector();
+        let u2 = Vec3 {
+            x: 0.,
+            y: 1.,
+            z: 0.,
+        }
+        .unit_vector();
+        assert_approx_eq!(0.8, u1.inner_product(&u2));
+        assert_approx_eq!(-1., u1.inner_product(&-&u1));
+        assert_approx_eq!(
+            Vec3 {
+                x: 3.,
+                y: -4.,
+                z: 0.,
+            }
+            .unit_vector(),
+            u1.reflect(&u2)
+        );
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "cannot normalize")]
+    fn unit_vec3_zero_test() {
+        Vec3 {
+            x: 0.,
+            y: 0.,
+            z: 0.,
+        }
+        .unit_vector();
+    }
+
+    #[test]
+    fn refract_vector_tests() {
+        let u_normal = Vec3 {
+            x: 0.,
+            y: 1.,
+            z: 0.,
+        }
+        .unit_vector();
+        // Coming in at 60 degrees from the normal into a medium with sqrt(3) times the index
+        // bends the light to 30 degrees.
+        let u_in = Vec3 {
+            x: Float::sqrt(3.),
+            y: -1.,
+            z: 0.,
+        }
+        .unit_vector();
+        assert_approx_eq!(
+            Vec3 {
+                x: 1.,
+                y: -Float::sqrt(3.),
+                z: 0.,
+            }
+            .unit_vector(),
+            refract_vector(&u_in, &u_normal, 1. / Float::sqrt(3.)).unwrap()
+        );
+        // The same index on both sides keeps the direction.
+        assert_approx_eq!(u_in, refract_vector(&u_in, &u_normal, 1.).unwrap());
+        // Going out at 60 degrees exceeds the critical angle of about 35 degrees.
+        assert!(refract_vector(&u_in, &u_normal, Float::sqrt(3.)).is_none());
+    }
+
+    /// Returns the component of `v` perpendicular to `normal`.
+    fn tangential(v: &UnitVec3, normal: &UnitVec3) -> Vec3 {
+        v.inject() - normal.inject() * v.inner_product(normal)
+    }
+
+    #[test]
+    fn unit_vector_properties() {
+        for_all_cases(|rng| {
+            let scale = (10 as Float).powi(rng.gen_range(-3..4));
+            let v = arbitrary::vec3(rng, scale);
+            if v.length_squared() == 0. {
+                return;
+            }
+            let u = v.unit_vector();
+            assert_approx_eq!(1., u.inject().length());
+            // The direction is kept.
+            assert_approx_eq!(
+                v.length(),
+                v.inner_product(&u.inject()),
+                DEFAULT_EPSILON * scale
+            );
+        });
+    }
+
+    #[test]
+    fn reflect_vector_properties() {
+        for_all_cases(|rng| {
+            let (u_in, u_normal) = (arbitrary::unit_vec3(rng), arbitrary::unit_vec3(rng));
+            let u_out = reflect_vector(&u_in, &u_normal);
+            assert_approx_eq!(1., u_out.inject().length());
+            // The angle of reflection equals the angle of incidence.
+            assert_approx_eq!(
+                -u_in.inner_product(&u_normal),
+                u_out.inner_product(&u_normal)
+            );
+            assert_approx_eq!(tangential(&u_in, &u_normal), tangential(&u_out, &u_normal));
+            // Reflecting twice restores the direction.
+            assert_approx_eq!(u_in, reflect_vector(&u_out, &u_normal));
+        });
+    }
+
+    #[test]
+    fn refract_vector_properties() {
+        for_all_cases(|rng| {
+            let u_normal = arbitrary::unit_vec3(rng);
+            let u_in = arbitrary::unit_vec3(rng);
+            // The normal must be on the side the light comes from.
+            let u_in = if u_in.inner_product(&u_normal) > 0. {
+                -u_in
+            } else {
+                u_in
+            };
+
+            // The light goes straight if the refractive indices are the same.
+            assert_approx_eq!(u_in, refract_vector(&u_in, &u_normal, 1.).unwrap());
+
+            let eta_ratio: Float = rng.gen_range(0.25..4.);
+            let sin_in = tangential(&u_in, &u_normal).length();
+            match refract_vector(&u_in, &u_normal, eta_ratio) {
+                Some(u_out) => {
+                    assert_approx_eq!(1., u_out.inject().length());
+                    // The light passes through the surface.
+                    assert!(u_out.inner_product(&u_normal) <= 0.);
+                    // Snell's law, in which the plane of incidence is kept as well.
+                    assert_approx_eq!(
+                        tangential(&u_in, &u_normal) * eta_ratio,
+                        tangential(&u_out, &u_normal)
+                    );
+                }
+                None => assert!(eta_ratio * sin_in > 1. - DEFAULT_EPSILON),
+            }
+        });
+    }
+
+    #[test]
+    fn onb_properties() {
+        for_all_cases(|rng| {
+            let normal = arbitrary::unit_vec3(rng);
+            let onb = Onb::from_normal(&normal);
+            let (a, b, c) = (
+                rng.gen_range(-1. ..1.),
+                rng.gen_range(-1. ..1.),
+                rng.gen_range(-1. ..1.),
+            );
+            // The basis is orthonormal, and thus keeps lengths.
+            let v = onb.local(a, b, c);
+            assert_approx_eq!((a * a + b * b + c * c).sqrt(), v.length());
+            assert_approx_eq!(c, v.inner_product(&normal.inject()));
+        });
+    }
+
+    #[test]
+    fn mat4_properties() {
+        for_all_cases(|rng| {
+            let m = &(&Mat4::translation(&arbitrary::vec3(rng, 10.))
+                * &Mat4::rotation(&Quat::from_euler_degrees(&arbitrary::vec3(rng, 180.))))
+                * &Mat4::scaling(&Vec3 {
+                    x: rng.gen_range(0.5..2.),
+                    y: rng.gen_range(0.5..2.),
+                    z: rng.gen_range(0.5..2.),
+                });
+            let inverse = m.inverse().unwrap();
+            let pt = arbitrary::point3(rng, 10.);
+            assert_approx_eq!(
+                pt,
+                inverse.transform_point(&m.transform_point(&pt)),
+                DEFAULT_EPSILON * 100.
+            );
+            let v = arbitrary::vec3(rng, 10.);
+            assert_approx_eq!(
+                v,
+                inverse.transform_direction(&m.transform_direction(&v)),
+                DEFAULT_EPSILON * 100.
+            );
+        });
+    }
+
+    const NUM_STATISTICAL_SAMPLES: usize = 100000;
+
+    /// Checks that `values` in [-1, 1] fall into each of four equal bins
+    /// with frequency close to 1/4.
+    fn assert_uniform_on_unit_interval(values: impl Iterator<Item = Float>) {
+        let mut bins = [0; 4];
+        for value in values {
+            assert!((-1. ..=1.).contains(&value));
+            bins[(((value + 1.) * 2.) as usize).min(3)] += 1;
+        }
+        for count in bins {
+            let frequency = count as Float / NUM_STATISTICAL_SAMPLES as Float;
+            assert!((frequency - 0.25).abs() < 0.01, "{:?}", bins);
+        }
+    }
+
+    #[test]
+    fn random_unit_vector_tests() {
+        let samples: Vec<Vec3> = (0..NUM_STATISTICAL_SAMPLES)
+            .map(|_| random_unit_vector().inject())
+            .collect();
+        for v in samples.iter() {
+            assert_approx_eq!(1., v.length());
+        }
+        // Each coordinate of uniform points on the unit sphere is uniform on [-1, 1].
+        for axis in 0..3 {
+            assert_uniform_on_unit_interval(samples.iter().map(|v| v[axis]));
+        }
+    }
+
+    #[test]
+    fn random_on_hemisphere_tests() {
+        let normal = Vec3 {
+            x: 1.,
+            y: -2.,
+            z: 2.,
+        }
+        .unit_vector();
+        let heights = (0..NUM_STATISTICAL_SAMPLES).map(|_| {
+            let u = random_on_hemisphere(&normal);
+            u.inner_product(&normal)
+        });
+        // The height along the normal is uniform on [0, 1].
+        assert_uniform_on_unit_interval(heights.map(|h| {
+            assert!(h >= 0.);
+            2. * h - 1.
+        }));
+    }
+
+    #[test]
+    fn random_in_unit_disk_tests() {
+        let samples: Vec<Vec3> = (0..NUM_STATISTICAL_SAMPLES)
+            .map(|_| random_in_unit_disk())
+            .collect();
+        for v in samples.iter() {
+            assert!(v.length_squared() < 1. && v.z == 0.);
+        }
+        // The squared distance from the center is uniform on [0, 1].
+        assert_uniform_on_unit_interval(samples.iter().map(|v| 2. * v.length_squared() - 1.));
+    }
+
+    #[test]
+    fn random_cosine_direction_tests() {
+        let samples: Vec<Vec3> = (0..NUM_STATISTICAL_SAMPLES)
+            .map(|_| random_cosine_direction())
+            .collect();
+        for v in samples.iter() {
+            assert_approx_eq!(1., v.length());
+        }
+        // The squared cosine from the z-axis is uniform on [0, 1].
+        assert_uniform_on_unit_interval(samples.iter().map(|v| 2. * v.z * v.z - 1.));
+    }
 }
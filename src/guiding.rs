@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+
+use crate::geometry::{consts, random_double, Float, Point3, UnitVec3, Vec3};
+
+/// The numbers of the bins of the directional histograms along the z axis and around it.
+/// The bins are uniform in z and in the azimuth, and thus all cover the same solid angle.
+const NUM_Z_BINS: usize = 8;
+const NUM_PHI_BINS: usize = 16;
+const NUM_BINS: usize = NUM_Z_BINS * NUM_PHI_BINS;
+
+/// The number of samples a cell needs before its histogram is used for sampling,
+/// which keeps a few lucky paths from making the guide send all the rays one way.
+const MIN_CELL_SAMPLES: usize = 16;
+
+/// The type for the light arriving at the cells of a grid over the scene, recorded in the
+/// directional histogram of each cell, which is learned from the paths traced while rendering.
+#[derive(Clone, Debug)]
+pub struct PathGuide {
+    cell_size: Float,
+    /// The sums of the recorded values of the bins and the numbers of the samples of each cell.
+    cells: HashMap<[i64; 3], (Vec<Float>, usize)>,
+}
+impl PathGuide {
+    pub fn new(cell_size: Float) -> Self {
+        PathGuide {
+            cell_size,
+            cells: HashMap::new(),
+        }
+    }
+
+    /// Records that the light `value` arrives at `point` from `direction`,
+    /// where `value` is the radiance divided by the density with which the direction was drawn.
+    pub fn record(&mut self, point: &Point3, direction: &UnitVec3, value: Float) {
+        if !(value.is_finite() && value >= 0.) {
+            return;
+        }
+        let (bins, num_samples) = self
+            .cells
+            .entry(cell_of(self.cell_size, point))
+            .or_insert_with(|| (vec![0.; NUM_BINS], 0));
+        bins[bin_of(direction)] += value;
+        *num_samples += 1;
+    }
+
+    /// Adds the samples of `other`, which must have the same cell size.
+    pub fn merge(&mut self, other: PathGuide) {
+        for (cell, (other_bins, other_num_samples)) in other.cells {
+            let (bins, num_samples) = self
+                .cells
+                .entry(cell)
+                .or_insert_with(|| (vec![0.; NUM_BINS], 0));
+            for (bin, other_bin) in bins.iter_mut().zip(other_bins) {
+                *bin += other_bin;
+            }
+            *num_samples += other_num_samples;
+        }
+    }
+
+    /// Returns the distribution of directions learned so far, which is used while the guide goes on learning.
+    pub fn distribution(&self) -> GuideDistribution {
+        let cells = self
+            .cells
+            .iter()
+            .filter(|(_, (_, num_samples))| *num_samples >= MIN_CELL_SAMPLES)
+            .filter_map(|(cell, (bins, _))| {
+                let total: Float = bins.iter().sum();
+                if total <= 0. {
+                    return None;
+                }
+                let mut sum = 0.;
+                let cdf = bins
+                    .iter()
+                    .map(|bin| {
+                        sum += bin;
+                        sum / total
+                    })
+                    .collect();
+                Some((*cell, cdf))
+            })
+            .collect();
+        GuideDistribution {
+            cell_size: self.cell_size,
+            cells,
+        }
+    }
+}
+
+/// The type for the distributions of directions of `PathGuide`s frozen for sampling.
+#[derive(Clone, Debug)]
+pub struct GuideDistribution {
+    cell_size: Float,
+    /// The cumulative distribution over the bins of each cell that has learned enough.
+    cells: HashMap<[i64; 3], Vec<Float>>,
+}
+impl GuideDistribution {
+    pub fn cell_size(&self) -> Float {
+        self.cell_size
+    }
+
+    /// Checks that the guide has learned the light arriving at `point`.
+    pub fn covers(&self, point: &Point3) -> bool {
+        self.cells.contains_key(&cell_of(self.cell_size, point))
+    }
+
+    /// Returns a random direction from which the light is likely to arrive at `point`,
+    /// or `None` if the guide has not learned about the point.
+    pub fn sample(&self, point: &Point3) -> Option<UnitVec3> {
+        let cdf = self.cells.get(&cell_of(self.cell_size, point))?;
+        let r = random_double() + 0.5;
+        let bin = cdf.partition_point(|&p| p <= r).min(NUM_BINS - 1);
+        let (z_bin, phi_bin) = (bin / NUM_PHI_BINS, bin % NUM_PHI_BINS);
+        let z = -1. + 2. * (z_bin as Float + random_double() + 0.5) / NUM_Z_BINS as Float;
+        let phi =
+            2. * consts::PI * (phi_bin as Float + random_double() + 0.5) / NUM_PHI_BINS as Float;
+        let r = (1. - z * z).max(0.).sqrt();
+        Some(
+            Vec3 {
+                x: r * phi.cos(),
+                y: r * phi.sin(),
+                z,
+            }
+            .unit_vector(),
+        )
+    }
+
+    /// Returns the density over solid angle with which `sample` draws `direction` at `point`,
+    /// or `None` if the guide has not learned about the point.
+    pub fn pdf(&self, point: &Point3, direction: &UnitVec3) -> Option<Float> {
+        let cdf = self.cells.get(&cell_of(self.cell_size, point))?;
+        let bin = bin_of(direction);
+        let probability = cdf[bin] - if bin == 0 { 0. } else { cdf[bin - 1] };
+        Some(probability * NUM_BINS as Float / (4. * consts::PI))
+    }
+}
+
+fn cell_of(cell_size: Float, point: &Point3) -> [i64; 3] {
+    [point.x, point.y, point.z].map(|c| (c / cell_size).floor() as i64)
+}
+
+fn bin_of(direction: &UnitVec3) -> usize {
+    let d = direction.inject();
+    let z_bin = ((d.z + 1.) / 2. * NUM_Z_BINS as Float) as usize;
+    let phi = d.y.atan2(d.x).rem_euclid(2. * consts::PI);
+    let phi_bin = (phi / (2. * consts::PI) * NUM_PHI_BINS as Float) as usize;
+    z_bin.min(NUM_Z_BINS - 1) * NUM_PHI_BINS + phi_bin.min(NUM_PHI_BINS - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::{random_unit_vector, seed_random};
+
+    #[test]
+    fn path_guide_tests() {
+        seed_random(0);
+        let point = Point3 {
+            x: 0.5,
+            y: 0.5,
+            z: 0.5,
+        };
+        let light_direction = Vec3 {
+            x: 1.,
+            y: 2.,
+            z: 3.,
+        }
+        .unit_vector();
+        // The light arrives only from around `light_direction`, learned from uniform directions.
+        let mut guide = PathGuide::new(1.);
+        assert!(!guide.distribution().covers(&point));
+        let mut other = PathGuide::new(1.);
+        for index in 0..2000 {
+            let direction = random_unit_vector();
+            let value = if direction.inner_product(&light_direction) > 0.95 {
+                4. * consts::PI
+            } else {
+                0.
+            };
+            if index % 2 == 0 {
+                guide.record(&point, &direction, value);
+            } else {
+                other.record(&point, &direction, value);
+            }
+        }
+        guide.merge(other);
+        let distribution = guide.distribution();
+        assert!(distribution.covers(&point));
+        let far = Point3 {
+            x: 3.5,
+            y: 0.5,
+            z: 0.5,
+        };
+        assert!(!distribution.covers(&far));
+        assert!(distribution.sample(&far).is_none());
+
+        let num_samples = 1000;
+        let mut num_toward_light = 0;
+        let mut total_inverse_pdf = 0.;
+        for _ in 0..num_samples {
+            let direction = distribution.sample(&point).unwrap();
+            if direction.inner_product(&light_direction) > 0.8 {
+                num_toward_light += 1;
+            }
+            total_inverse_pdf += 1. / distribution.pdf(&point, &direction).unwrap();
+        }
+        assert!(
+            num_toward_light > num_samples * 9 / 10,
+            "{}",
+            num_toward_light
+        );
+        // The directions that are never drawn have the density 0.
+        assert_eq!(Some(0.), distribution.pdf(&point, &(-&light_direction)));
+        // The average of the inverse densities is the solid angle of the bins drawn,
+        // which are a small part of the sphere.
+        let solid_angle = total_inverse_pdf / num_samples as Float;
+        assert!(
+            solid_angle > 0. && solid_angle < consts::PI,
+            "{}",
+            solid_angle
+        );
+    }
+}
@@ -1,20 +1,116 @@
-extern crate dyn_clone;
+use std::fmt;
+use std::sync::Arc;
 
-use dyn_clone::DynClone;
-
-use crate::color::Attenuation;
-use crate::geometry::{random_double, random_unit_vector, reflect_vector, Point3, Ray, UnitVec3};
+use crate::bvh::{union, Bvh};
+use crate::color::{Attenuation, Color, Spectrum};
+use crate::geometry::{
+    consts, random_cosine_direction, random_double, random_unit_vector, reflect_vector,
+    refract_vector, Float, Mat4, Onb, Point3, Quat, Ray, UnitVec3, Vec3,
+};
+use crate::interval::Interval;
+use crate::json::Json;
+use crate::light::{emits, Light};
+use crate::scene_file::{number, tagged, triple, MaterialTable};
+use crate::scene_graph::Node;
+use crate::sweep::MaterialParameter;
+use crate::texture::ImageTexture;
 
 /// The type for intersection points; see `Hittable` for the usage of this type.
 #[derive(Clone, Debug, PartialEq)]
 pub struct HitRecord {
-    pub t: f64,
+    pub t: Float,
     pub surface_normal: UnitVec3,
+    /// The texture coordinates of the point, which are in [0, 1] and defined by each kind of objects.
+    pub u: Float,
+    pub v: Float,
+}
+
+/// The type for the rays scattered by materials.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scattered {
+    /// How much of the light coming along `ray` is passed on to the incoming ray.
+    pub attenuation: Attenuation,
+    pub ray: Ray,
 }
 
-/// The trait for surface materials.
-pub trait Material: DynClone {
-    fn scatter(&self, ray_in: &Ray, hit: &HitRecord) -> (Attenuation, Ray);
+/// The trait for surface materials, which are printed with their parameters by `Debug`.
+pub trait Material: Send + Sync + fmt::Debug {
+    /// Returns the scattered ray if `ray_in` is scattered at `hit`,
+    /// or returns `None` if the ray is absorbed.
+    fn scatter(&self, ray_in: &Ray, hit: &HitRecord) -> Option<Scattered>;
+
+    /// Returns the problems of the parameters, e.g. out-of-range albedos.
+    fn validate(&self) -> Vec<String>;
+
+    /// Returns the medium inside the surface if the material is the boundary of a dielectric,
+    /// whose refraction depends on the medium outside.
+    fn medium(&self) -> Option<Medium> {
+        None
+    }
+
+    /// Does the same as `scatter` for the boundaries of dielectrics,
+    /// where `eta_outside` is the refractive index of the medium the object is in.
+    fn scatter_between(
+        &self,
+        ray_in: &Ray,
+        hit: &HitRecord,
+        _eta_outside: Float,
+    ) -> Option<Scattered> {
+        self.scatter(ray_in, hit)
+    }
+
+    /// Returns the albedo at `hit` if the material scatters rays into the hemisphere around
+    /// the surface normal with the density proportional to the cosine as `Lambertian` does,
+    /// for which the renderer also samples the lights directly.
+    fn diffuse_albedo(&self, _hit: &HitRecord) -> Option<Attenuation> {
+        None
+    }
+
+    /// Returns the reflectance of the surface if it is a shadow catcher as `ShadowCatcher` is,
+    /// which the renderer traces through instead of calling `scatter`.
+    fn catcher_reflectance(&self) -> Option<Float> {
+        None
+    }
+
+    /// Returns the light emitted from the surface at `hit`.
+    fn emitted(&self, _hit: &HitRecord) -> Color {
+        Color {
+            r: 0.,
+            g: 0.,
+            b: 0.,
+        }
+    }
+
+    /// Returns the color the surface shows at `hit` to `ray_in` by itself, e.g. for stylized materials,
+    /// which adds to `emitted` but does not make the surface a light that the renderer samples.
+    fn shown(&self, _ray_in: &Ray, _hit: &HitRecord) -> Color {
+        Color {
+            r: 0.,
+            g: 0.,
+            b: 0.,
+        }
+    }
+
+    /// Returns the estimated number of bytes the material occupies.
+    fn memory_size(&self) -> usize {
+        std::mem::size_of_val(self)
+    }
+
+    /// Returns the material as written in scene files,
+    /// or returns `None` if scene files cannot describe it, e.g. image textures.
+    fn to_json(&self) -> Option<Json> {
+        None
+    }
+
+    /// Returns the copy of the material with `parameter` set to `value` for contact sheets,
+    /// or returns `None` if the material has no such parameter.
+    fn with_parameter(
+        &self,
+        _parameter: MaterialParameter,
+        _value: Float,
+    ) -> Option<SharedMaterial> {
+        None
+    }
 }
 
 /// The type for materials that perform Lambertian reflectance.
@@ -23,129 +119,552 @@ pub struct Lambertian {
     pub albedo: Attenuation,
 }
 impl Material for Lambertian {
-    fn scatter(&self, ray_in: &Ray, hit: &HitRecord) -> (Attenuation, Ray) {
-        let surface_normal = hit.surface_normal.inject();
-        let scattered_direction = surface_normal.add(&random_unit_vector().inject());
-        let child_ray = Ray {
-            origin: ray_in.at(hit.t),
-            direction: scattered_direction.unit_vector(),
-            // TODO: make this work even when `scattered_direction` is close to the zero vector
-        };
-        (self.albedo.clone(), child_ray)
+    fn scatter(&self, ray_in: &Ray, hit: &HitRecord) -> Option<Scattered> {
+        let onb = Onb::from_normal(&hit.surface_normal);
+        let local_direction = random_cosine_direction();
+        let scattered_direction =
+            onb.local(local_direction.x, local_direction.y, local_direction.z);
+        let child_ray = Ray::leaving(
+            &ray_in.at(hit.t),
+            &hit.surface_normal,
+            scattered_direction.unit_vector(),
+        );
+        Some(Scattered {
+            attenuation: self.albedo.clone(),
+            ray: child_ray,
+        })
+    }
+
+    fn diffuse_albedo(&self, _hit: &HitRecord) -> Option<Attenuation> {
+        Some(self.albedo.clone())
+    }
+
+    fn validate(&self) -> Vec<String> {
+        validate_albedo(&self.albedo)
+    }
+
+    fn to_json(&self) -> Option<Json> {
+        Some(tagged(
+            "lambertian",
+            vec![("albedo", spectrum_json(&self.albedo))],
+        ))
+    }
+}
+
+/// The type for materials that perform Lambertian reflectance with the albedo given by a texture.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TexturedLambertian {
+    pub texture: Arc<ImageTexture>,
+}
+impl Material for TexturedLambertian {
+    fn scatter(&self, ray_in: &Ray, hit: &HitRecord) -> Option<Scattered> {
+        let albedo = self.texture.value(hit.u, hit.v).clone();
+        Lambertian { albedo }.scatter(ray_in, hit)
+    }
+
+    fn diffuse_albedo(&self, hit: &HitRecord) -> Option<Attenuation> {
+        Some(self.texture.value(hit.u, hit.v).clone())
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let texels = self.texture.texels();
+        match texels.iter().find(|texel| !texel.is_attenuation()) {
+            Some(texel) => validate_albedo(texel),
+            None => vec![],
+        }
+    }
+
+    fn memory_size(&self) -> usize {
+        std::mem::size_of_val(self) + shared_memory_size(&self.texture, self.texture.memory_size())
     }
 }
 
+/// The number of times a fuzzy reflection going below the surface is drawn again
+/// before falling back to the regular reflection.
+const MAX_FUZZ_ATTEMPTS: usize = 16;
+
 /// The type for metals, i.e., materials that perform the regular reflection.
-#[derive(Clone)]
+/// The parameter `fuzz` is in [0, 1] and blurs the reflection by that radius around the reflected direction.
+#[derive(Clone, Debug)]
 pub struct Metal {
     pub albedo: Attenuation,
-    pub fuzz: f64,
+    pub fuzz: Float,
 }
 impl Material for Metal {
-    fn scatter(&self, ray_in: &Ray, hit: &HitRecord) -> (Attenuation, Ray) {
-        let direction_raw = reflect_vector(&ray_in.direction, &hit.surface_normal);
-        let direction = direction_raw
-            .inject()
-            .add(&random_unit_vector().inject().scale(self.fuzz))
-            .unit_vector();
+    fn scatter(&self, ray_in: &Ray, hit: &HitRecord) -> Option<Scattered> {
+        let reflected = reflect_vector(&ray_in.direction, &hit.surface_normal);
+        // Fuzzy directions below the surface are drawn again instead of being absorbed,
+        // which would make fuzzy metals darker than their albedo.
+        let side = reflected.inner_product(&hit.surface_normal);
+        let direction = (0..MAX_FUZZ_ATTEMPTS)
+            .map(|_| (reflected.inject() + random_unit_vector() * self.fuzz).unit_vector())
+            .find(|direction| direction.inner_product(&hit.surface_normal) * side > 0.)
+            .unwrap_or(reflected);
+        let child_ray = Ray::leaving(&ray_in.at(hit.t), &hit.surface_normal, direction);
+        Some(Scattered {
+            attenuation: self.albedo.clone(),
+            ray: child_ray,
+        })
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut problems = validate_albedo(&self.albedo);
+        if !(0. ..=1.).contains(&self.fuzz) {
+            problems.push(format!("fuzz must be in [0, 1], but is {}", self.fuzz));
+        }
+        problems
+    }
+
+    fn to_json(&self) -> Option<Json> {
+        Some(tagged(
+            "metal",
+            vec![
+                ("albedo", spectrum_json(&self.albedo)),
+                ("fuzz", number(self.fuzz)),
+            ],
+        ))
+    }
+
+    fn with_parameter(&self, parameter: MaterialParameter, value: Float) -> Option<SharedMaterial> {
+        (parameter == MaterialParameter::Fuzz).then(|| -> SharedMaterial {
+            Arc::new(Metal {
+                fuzz: value,
+                ..self.clone()
+            })
+        })
+    }
+}
+
+/// The type for materials that emit light and do not scatter rays.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DiffuseLight {
+    pub emit: Color,
+}
+impl Material for DiffuseLight {
+    fn scatter(&self, _ray_in: &Ray, _hit: &HitRecord) -> Option<Scattered> {
+        None
+    }
+
+    fn emitted(&self, _hit: &HitRecord) -> Color {
+        self.emit.clone()
+    }
+
+    fn validate(&self) -> Vec<String> {
+        if self.emit.is_valid() {
+            vec![]
+        } else {
+            vec![format!(
+                "emission must be finite and non-negative, but is {:?}",
+                self.emit
+            )]
+        }
+    }
+
+    fn to_json(&self) -> Option<Json> {
+        Some(tagged("light", vec![("emit", spectrum_json(&self.emit))]))
+    }
+}
+
+/// The type for the particles of participating media, e.g. smoke,
+/// which scatter rays in uniformly random directions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Isotropic {
+    pub albedo: Attenuation,
+}
+impl Material for Isotropic {
+    fn scatter(&self, ray_in: &Ray, hit: &HitRecord) -> Option<Scattered> {
+        // Particles have no surfaces for rays to hit again, so rays start right at them.
         let child_ray = Ray {
             origin: ray_in.at(hit.t),
-            direction,
+            direction: random_unit_vector(),
+        };
+        Some(Scattered {
+            attenuation: self.albedo.clone(),
+            ray: child_ray,
+        })
+    }
+
+    fn validate(&self) -> Vec<String> {
+        validate_albedo(&self.albedo)
+    }
+
+    fn to_json(&self) -> Option<Json> {
+        Some(tagged(
+            "isotropic",
+            vec![("albedo", spectrum_json(&self.albedo))],
+        ))
+    }
+}
+
+/// The direction toward the light that shades `Toon` materials by default, i.e., from the upper left
+/// behind the cameras of the preset scenes.
+pub const TOON_LIGHT_DIRECTION: Vec3 = Vec3 {
+    x: -1.,
+    y: 1.,
+    z: 1.,
+};
+
+/// The type for the stylized materials of cel shading, which show their albedo in `bands` flat shades
+/// by the angle between the surface and the fixed direction `light_direction` toward the light,
+/// from `1 / bands` of the albedo on the side facing away to the full albedo facing the light,
+/// e.g. for illustrations with outlines. They neither are lit by the scene nor reflect anything.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Toon {
+    pub albedo: Attenuation,
+    pub bands: usize,
+    pub light_direction: Vec3,
+}
+impl Material for Toon {
+    fn scatter(&self, _ray_in: &Ray, _hit: &HitRecord) -> Option<Scattered> {
+        None
+    }
+
+    fn shown(&self, ray_in: &Ray, hit: &HitRecord) -> Color {
+        // The side of the surface facing the ray is shaded, as seen from inside of objects.
+        let normal = if hit.surface_normal.inner_product(&ray_in.direction) > 0. {
+            -hit.surface_normal.inject()
+        } else {
+            hit.surface_normal.inject()
         };
-        (self.albedo.clone(), child_ray)
+        let cosine = normal.inner_product(&self.light_direction.unit_vector().inject());
+        let bands = self.bands as Float;
+        let band = (cosine.max(0.) * bands).floor().min(bands - 1.);
+        let shade = (band + 1.) / bands;
+        Color {
+            r: self.albedo.r * shade,
+            g: self.albedo.g * shade,
+            b: self.albedo.b * shade,
+        }
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut problems = validate_albedo(&self.albedo);
+        if self.bands == 0 {
+            problems.push("bands must be positive".to_string());
+        }
+        if !self.light_direction.is_finite() || self.light_direction.length_squared() == 0. {
+            problems.push(format!(
+                "light direction must be finite and non-zero, but is {:?}",
+                self.light_direction
+            ));
+        }
+        problems
+    }
+
+    fn to_json(&self) -> Option<Json> {
+        Some(tagged(
+            "toon",
+            vec![
+                ("albedo", spectrum_json(&self.albedo)),
+                ("bands", number(self.bands as Float)),
+                ("light_direction", vector_json(&self.light_direction)),
+            ],
+        ))
+    }
+}
+
+/// The type for the material of shadow catchers, which are invisible except for the shadows
+/// and the reflections of the other objects on them, e.g. grounds for compositing objects onto
+/// photographs given as the background. Rays go through them dimmed by the fraction of the light
+/// the surface would receive that the other objects block, and are reflected as by a mirror with
+/// the probability `reflectance` if the reflected ray hits another object.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShadowCatcher {
+    pub reflectance: Float,
+}
+impl Material for ShadowCatcher {
+    fn scatter(&self, _ray_in: &Ray, _hit: &HitRecord) -> Option<Scattered> {
+        None
+    }
+
+    fn catcher_reflectance(&self) -> Option<Float> {
+        Some(self.reflectance)
+    }
+
+    fn validate(&self) -> Vec<String> {
+        if (0. ..=1.).contains(&self.reflectance) {
+            vec![]
+        } else {
+            vec![format!(
+                "reflectance must be in [0, 1], but is {}",
+                self.reflectance
+            )]
+        }
+    }
+
+    fn to_json(&self) -> Option<Json> {
+        Some(tagged(
+            "shadow_catcher",
+            vec![("reflectance", number(self.reflectance))],
+        ))
+    }
+}
+
+/// The type for the material of holdout objects, which absorbs rays and emits nothing,
+/// so that the objects are black but still hide and shadow the others.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Holdout;
+impl Material for Holdout {
+    fn scatter(&self, _ray_in: &Ray, _hit: &HitRecord) -> Option<Scattered> {
+        None
+    }
+
+    fn validate(&self) -> Vec<String> {
+        vec![]
     }
 }
 
-pub type BoxedMaterial = Box<dyn Material>;
-impl Clone for BoxedMaterial {
-    fn clone(&self) -> Self {
-        dyn_clone::clone_box(&**self)
+fn validate_albedo(albedo: &Attenuation) -> Vec<String> {
+    if albedo.is_attenuation() {
+        vec![]
+    } else {
+        vec![format!("albedo must be in [0, 1], but is {:?}", albedo)]
     }
 }
 
-fn reflectance(cosine: f64, refraction_index: f64) -> f64 {
+fn spectrum_json(spectrum: &Spectrum) -> Json {
+    triple(spectrum.r, spectrum.g, spectrum.b)
+}
+
+fn point_json(point: &Point3) -> Json {
+    triple(point.x, point.y, point.z)
+}
+
+fn vector_json(vector: &Vec3) -> Json {
+    triple(vector.x, vector.y, vector.z)
+}
+
+/// The type for materials shared among objects and threads;
+/// cloning this only increments the reference count.
+pub type SharedMaterial = Arc<dyn Material>;
+
+/// Returns the share of `size` bytes of `shared` for each of its owners,
+/// so that the memory of materials and textures is counted once in total however many objects share them.
+pub(crate) fn shared_memory_size<T: ?Sized>(shared: &Arc<T>, size: usize) -> usize {
+    size / Arc::strong_count(shared)
+}
+
+fn reflectance(cosine: Float, refraction_index: Float) -> Float {
     let r0 = (1. - refraction_index) / (1. + refraction_index);
     let r1 = r0 * r0;
     r1 + (1. - r1) * (1. - cosine).powi(5)
 }
 
+/// The refractive index of the space outside all the objects.
+pub const VACUUM_ETA: Float = 1.;
+
+/// The type for the media inside dielectrics, which may be nested, e.g. glass in water,
+/// or overlap, e.g. water filling a glass.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Medium {
+    pub eta: Float,
+    /// Where dielectrics overlap, the one with the highest priority fills the overlap
+    /// and the boundaries of the others inside it are ignored.
+    pub priority: u32,
+}
+
+/// The type for the media a ray is in, which are entered and left along a path,
+/// where each medium is identified by its material, e.g. by the address of the shared material.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MediumStack {
+    media: Vec<(usize, Medium)>,
+}
+impl MediumStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the medium filling the space the ray is in,
+    /// which is the one with the highest priority and the last entered one among them,
+    /// without counting the last entered `excluded` one.
+    fn current(&self, excluded: Option<usize>) -> Option<&Medium> {
+        let skipped = excluded.and_then(|id| self.media.iter().rposition(|(i, _)| *i == id));
+        self.media
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| Some(*index) != skipped)
+            .map(|(_, (_, medium))| medium)
+            .rev()
+            .max_by_key(|medium| medium.priority)
+    }
+
+    /// Returns the medium on the outer side of the boundary of the medium `id`
+    /// that the ray is `entering` or leaving.
+    fn outside(&self, id: usize, entering: bool) -> Option<&Medium> {
+        self.current(if entering { None } else { Some(id) })
+    }
+
+    /// Returns whether the boundary of the medium `id` that the ray is `entering` or leaving
+    /// is ignored because the space on both sides is filled by another medium of a higher priority.
+    pub fn is_false_hit(&self, id: usize, medium: &Medium, entering: bool) -> bool {
+        self.outside(id, entering)
+            .is_some_and(|outside| outside.priority > medium.priority)
+    }
+
+    /// Returns the refractive index on the outer side of the boundary of the medium `id`
+    /// that the ray is `entering` or leaving.
+    pub fn eta_outside(&self, id: usize, entering: bool) -> Float {
+        self.outside(id, entering)
+            .map_or(VACUUM_ETA, |medium| medium.eta)
+    }
+
+    pub fn enter(&mut self, id: usize, medium: Medium) {
+        self.media.push((id, medium));
+    }
+
+    /// Leaves the last entered medium `id`, which does nothing if the ray is not in it,
+    /// e.g. because the ray started inside it.
+    pub fn leave(&mut self, id: usize) {
+        if let Some(index) = self.media.iter().rposition(|(i, _)| *i == id) {
+            self.media.remove(index);
+        }
+    }
+}
+
 /// The type for glasses, i.e., materials that perform refraction.
-/// The parameter `eta` is the refractive index and should >= 1.
-#[derive(Clone)]
+/// The parameter `eta` is the refractive index and should >= 1,
+/// and `priority` decides which glass fills the space where glasses overlap (usually 0).
+#[derive(Clone, Debug)]
 pub struct Glass {
-    pub eta: f64,
+    pub eta: Float,
     pub albedo: Attenuation,
+    pub priority: u32,
 }
 impl Material for Glass {
-    fn scatter(&self, ray_in: &Ray, hit: &HitRecord) -> (Attenuation, Ray) {
-        let normal_raw = hit.surface_normal.inject();
-        let direction_in = ray_in.direction.inject();
-        let inprod_raw = normal_raw.inner_product(&direction_in);
+    fn scatter(&self, ray_in: &Ray, hit: &HitRecord) -> Option<Scattered> {
+        self.scatter_between(ray_in, hit, VACUUM_ETA)
+    }
+
+    fn medium(&self) -> Option<Medium> {
+        Some(Medium {
+            eta: self.eta,
+            priority: self.priority,
+        })
+    }
+
+    fn scatter_between(
+        &self,
+        ray_in: &Ray,
+        hit: &HitRecord,
+        eta_outside: Float,
+    ) -> Option<Scattered> {
+        let inprod_raw = hit.surface_normal.inner_product(&ray_in.direction);
 
-        // TODO: generalize the refractive index of external spaces.
         let (normal, inprod, eta_in, eta_out) = {
             if inprod_raw < 0. {
                 // If `ray_in` is coming into the object from the outside:
-                (normal_raw, inprod_raw, 1., self.eta)
+                (
+                    hit.surface_normal.clone(),
+                    inprod_raw,
+                    eta_outside,
+                    self.eta,
+                )
             } else {
                 // If `ray_in` is going out of the object from the inside:
-                (normal_raw.scale(-1.), -inprod_raw, self.eta, 1.)
+                (-&hit.surface_normal, -inprod_raw, self.eta, eta_outside)
             }
         };
 
-        // v := d - (n^T d) n
-        let vp_in = direction_in.subtract(&normal.scale(inprod));
-
-        // v' := (eta / eta') v
-        let vp_out = vp_in.scale(eta_in / eta_out);
+        let eta_ratio = eta_in / eta_out;
+        let direction_out = match refract_vector(&ray_in.direction, &normal, eta_ratio) {
+            // If the light can refract and is not reflected according to the Fresnel reflectance:
+            Some(refracted) if reflectance(-inprod, eta_ratio) <= random_double() + 0.5 => {
+                refracted
+            }
+            // If the light performs regular reflection:
+            _ => reflect_vector(&ray_in.direction, &normal),
+        };
+        let ray = Ray::leaving(&ray_in.at(hit.t), &hit.surface_normal, direction_out);
+        Some(Scattered {
+            attenuation: self.albedo.clone(),
+            ray,
+        })
+    }
 
-        // c := 1 - |v'|^2
-        let coeff_normal = 1. - vp_out.length_squared();
+    fn validate(&self) -> Vec<String> {
+        let mut problems = validate_albedo(&self.albedo);
+        if !(self.eta.is_finite() && self.eta >= 1.) {
+            problems.push(format!("eta must be >= 1, but is {}", self.eta));
+        }
+        problems
+    }
 
-        let direction_out = {
-            if coeff_normal >= 0. {
-                // If the light can refract:
+    fn to_json(&self) -> Option<Json> {
+        Some(tagged(
+            "glass",
+            vec![
+                ("eta", number(self.eta)),
+                ("albedo", spectrum_json(&self.albedo)),
+                ("priority", Json::Number(self.priority.into())),
+            ],
+        ))
+    }
 
-                if reflectance(-inprod, eta_in / eta_out) > random_double() {
-                    reflect_vector(&ray_in.direction, &normal.unit_vector())
-                } else {
-                    // d' = v' - sqrt(c) n
-                    vp_out
-                        .subtract(&normal.scale(coeff_normal.sqrt()))
-                        .unit_vector()
-                }
-            } else {
-                // If the light cannot refract and performs regular reflection:
-                reflect_vector(&ray_in.direction, &normal.unit_vector())
-            }
-        };
-        let ray = Ray {
-            origin: ray_in.at(hit.t),
-            direction: direction_out,
-        };
-        (self.albedo.clone(), ray)
+    fn with_parameter(&self, parameter: MaterialParameter, value: Float) -> Option<SharedMaterial> {
+        (parameter == MaterialParameter::Eta).then(|| -> SharedMaterial {
+            Arc::new(Glass {
+                eta: value,
+                ..self.clone()
+            })
+        })
     }
 }
 
 /// The trait for objects hittable by rays.
-pub trait Hittable {
-    /// Checks that `ray` intersects with the object.
-    /// Returns `Some((hit, material))` if it does
+pub trait Hittable: Send + Sync {
+    /// Checks that `ray` intersects with the object at some `t` in `ray_t`.
+    /// Returns `Some((hit, material))` for the nearest such intersection
     /// where `hit` is the information about the intersection point
     /// and `material` is the surface material of that point,
     /// or returns `None` otherwise.
-    fn hit(&self, ray: &Ray) -> Option<(HitRecord, Box<dyn Material>)>;
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<(HitRecord, SharedMaterial)>;
+
+    /// Returns the problems of the object and its material, e.g. negative radii.
+    fn validate(&self) -> Vec<String>;
+
+    /// Returns the estimated number of bytes the object occupies,
+    /// including its material and the objects it contains.
+    /// Materials shared among objects are counted for each of them.
+    fn memory_size(&self) -> usize;
+
+    /// Returns the minimum and the maximum corners of an axis-aligned box containing the object
+    /// so that BVHs can skip it, or `None` if it has no bounds known, in which case rays always test it.
+    fn bounding_box(&self) -> Option<(Point3, Point3)> {
+        None
+    }
+
+    /// Pushes the emitting surfaces of the object to `lights` so that the renderer samples them directly.
+    /// The light of the objects that push nothing, e.g. transformed ones, is still found by scattered rays.
+    fn collect_lights(&self, _lights: &mut Vec<Light>) {}
+
+    /// Returns the object as written in scene files, adding its materials to `materials`,
+    /// or returns `None` if scene files cannot describe it, e.g. volumes.
+    fn to_json(&self, _materials: &mut MaterialTable) -> Option<Json> {
+        None
+    }
+
+    /// Returns the object as a node of scene graphs if it is, so that nodes can be found by their names.
+    fn as_node(&self) -> Option<&Node> {
+        None
+    }
+
+    fn as_node_mut(&mut self) -> Option<&mut Node> {
+        None
+    }
+
+    /// Returns the object as a mesh if it is, e.g. to bake the light onto its texture coordinates.
+    fn as_mesh(&self) -> Option<&Mesh> {
+        None
+    }
 }
 
 pub struct Sphere {
     pub center: Point3,
-    pub radius: f64,
-    pub material: BoxedMaterial,
+    pub radius: Float,
+    pub material: SharedMaterial,
 }
 impl Hittable for Sphere {
-    fn hit(&self, ray: &Ray) -> Option<(HitRecord, Box<dyn Material>)> {
-        let t_min = 0.01; // This should be set in order for rays after reflection not to hit the sphere itself.
-
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<(HitRecord, SharedMaterial)> {
         let center = &self.center;
         let radius = &self.radius;
 
@@ -158,7 +677,7 @@ impl Hittable for Sphere {
 
         let origin = &ray.origin;
         let dir = &ray.direction.inject();
-        let v = origin.subtract(center);
+        let v = origin - center;
 
         let b_half = v.inner_product(dir);
         let c = v.length_squared() - radius * radius;
@@ -170,12 +689,12 @@ impl Hittable for Sphere {
             } else {
                 let sqrt_of_discriminant_quarter = discriminant_quarter.sqrt();
                 let t_minus = -b_half - sqrt_of_discriminant_quarter;
-                if t_minus >= t_min {
+                if ray_t.contains(t_minus) {
                     // If the ray hits the surface from the outside:
                     Some(t_minus)
                 } else {
                     let t_plus = -b_half + sqrt_of_discriminant_quarter;
-                    if t_plus >= t_min {
+                    if ray_t.contains(t_plus) {
                         // If the ray hits the surface from the inside:
                         Some(t_plus)
                     } else {
@@ -184,70 +703,1391 @@ impl Hittable for Sphere {
                 }
             }
         };
-        match t_opt {
-            None => None,
-            Some(t) => {
-                let intersection_point = ray.at(t);
-                let surface_normal = intersection_point.subtract(&center).unit_vector();
-                Some((HitRecord { t, surface_normal }, self.material.clone()))
-            }
-        }
+        match t_opt {
+            None => None,
+            Some(t) => {
+                let intersection_point = ray.at(t);
+                let surface_normal = (intersection_point - center).unit_vector();
+                let (u, v) = sphere_uv(&surface_normal);
+                let hit = HitRecord {
+                    t,
+                    surface_normal,
+                    u,
+                    v,
+                };
+                Some((hit, self.material.clone()))
+            }
+        }
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if !self.center.is_finite() {
+            problems.push(format!("center must be finite, but is {:?}", self.center));
+        }
+        if !(self.radius.is_finite() && self.radius > 0.) {
+            problems.push(format!("radius must be positive, but is {}", self.radius));
+        }
+        problems.extend(self.material.validate());
+        problems
+    }
+
+    fn memory_size(&self) -> usize {
+        std::mem::size_of_val(self)
+            + shared_memory_size(&self.material, self.material.memory_size())
+    }
+
+    fn bounding_box(&self) -> Option<(Point3, Point3)> {
+        let radius = self.radius.abs();
+        let extent = Vec3 {
+            x: radius,
+            y: radius,
+            z: radius,
+        };
+        Some((&self.center - &extent, &self.center + &extent))
+    }
+
+    fn collect_lights(&self, lights: &mut Vec<Light>) {
+        let light = Light::sphere(self.center.clone(), self.radius, self.material.clone());
+        if light.emits() {
+            lights.push(light);
+        }
+    }
+
+    fn to_json(&self, materials: &mut MaterialTable) -> Option<Json> {
+        Some(tagged(
+            "sphere",
+            vec![
+                ("center", point_json(&self.center)),
+                ("radius", number(self.radius)),
+                ("material", materials.index(&self.material)?),
+            ],
+        ))
+    }
+}
+
+/// Returns the texture coordinates of the point on unit spheres whose outward normal is `n`,
+/// where `u` goes around the y axis from -x and `v` goes from the bottom (-y) to the top (+y).
+pub(crate) fn sphere_uv(n: &UnitVec3) -> (Float, Float) {
+    let n = n.inject();
+    let theta = (-n.y).clamp(-1., 1.).acos();
+    let phi = (-n.z).atan2(n.x) + consts::PI;
+    (phi / (2. * consts::PI), theta / consts::PI)
+}
+
+/// The type for open cylinders (i.e. without caps)
+/// whose axis goes from `base` to `base + axis`.
+pub struct Cylinder {
+    pub base: Point3,
+    pub axis: Vec3,
+    pub radius: Float,
+    pub material: SharedMaterial,
+}
+impl Hittable for Cylinder {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<(HitRecord, SharedMaterial)> {
+        // (O, d) := ray
+        // B := base
+        // a := the unit vector of axis
+        //
+        // w_perp := w - (a^T w) a for each vector w
+        // |(O - B)_perp + t d_perp|^2 = r^2
+
+        let height = self.axis.length();
+        let a = &self.axis / height;
+        let perp = |w: &Vec3| w - &a * a.inner_product(w);
+
+        let dir = ray.direction.inject();
+        let v = &ray.origin - &self.base;
+        let d_perp = perp(&dir);
+        let v_perp = perp(&v);
+
+        let a_coeff = d_perp.length_squared();
+        if a_coeff < 1e-12 {
+            // If the ray is parallel to the axis:
+            return None;
+        }
+        let b_half = d_perp.inner_product(&v_perp);
+        let c = v_perp.length_squared() - self.radius * self.radius;
+        let discriminant_quarter = b_half * b_half - a_coeff * c;
+        if discriminant_quarter < 0. {
+            return None;
+        }
+        let sqrt_of_discriminant_quarter = discriminant_quarter.sqrt();
+        let t_minus = (-b_half - sqrt_of_discriminant_quarter) / a_coeff;
+        let t_plus = (-b_half + sqrt_of_discriminant_quarter) / a_coeff;
+        for t in [t_minus, t_plus] {
+            if !ray_t.contains(t) {
+                continue;
+            }
+            // The position of the intersection point along the axis:
+            let s = a.inner_product(&(&v + &dir * t));
+            if (Interval {
+                min: 0.,
+                max: height,
+            })
+            .contains(s)
+            {
+                let intersection_point = ray.at(t);
+                let radial = intersection_point - &self.base - &a * s;
+                // `u` goes around the axis, and `v` goes from the base to the other end.
+                let onb = Onb::from_normal(&a.unit_vector());
+                let angle = radial
+                    .inner_product(&onb.local(0., 1., 0.))
+                    .atan2(radial.inner_product(&onb.local(1., 0., 0.)));
+                let hit = HitRecord {
+                    t,
+                    surface_normal: radial.unit_vector(),
+                    u: (angle / (2. * consts::PI)).rem_euclid(1.),
+                    v: s / height,
+                };
+                return Some((hit, self.material.clone()));
+            }
+        }
+        None
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if !self.base.is_finite() {
+            problems.push(format!("base must be finite, but is {:?}", self.base));
+        }
+        if !(self.axis.is_finite() && self.axis.length_squared() > 0.) {
+            problems.push(format!(
+                "axis must be finite and non-zero, but is {:?}",
+                self.axis
+            ));
+        }
+        if !(self.radius.is_finite() && self.radius > 0.) {
+            problems.push(format!("radius must be positive, but is {}", self.radius));
+        }
+        problems.extend(self.material.validate());
+        problems
+    }
+
+    fn memory_size(&self) -> usize {
+        std::mem::size_of_val(self)
+            + shared_memory_size(&self.material, self.material.memory_size())
+    }
+
+    /// Returns the box containing the balls of `radius` around both ends of the axis.
+    fn bounding_box(&self) -> Option<(Point3, Point3)> {
+        let radius = self.radius.abs();
+        let extent = Vec3 {
+            x: radius,
+            y: radius,
+            z: radius,
+        };
+        let top = &self.base + &self.axis;
+        Some((
+            (&self.base - &extent).min(&(&top - &extent)),
+            (&self.base + &extent).max(&(&top + &extent)),
+        ))
+    }
+
+    fn to_json(&self, materials: &mut MaterialTable) -> Option<Json> {
+        Some(tagged(
+            "cylinder",
+            vec![
+                ("base", point_json(&self.base)),
+                ("axis", vector_json(&self.axis)),
+                ("radius", number(self.radius)),
+                ("material", materials.index(&self.material)?),
+            ],
+        ))
+    }
+}
+
+/// The type for parallelograms spanned by `u` and `v` at the corner `q`.
+/// Quads are two-sided; the surface normal always faces against the incoming ray.
+pub struct Quad {
+    pub q: Point3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub material: SharedMaterial,
+}
+impl Hittable for Quad {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<(HitRecord, SharedMaterial)> {
+        // n := u x v
+        // The plane consists of points P such that n^T (P - Q) = 0.
+        let n = self.u.cross_product(&self.v);
+        let dir = ray.direction.inject();
+        let denominator = n.inner_product(&dir);
+        if denominator.abs() < 1e-8 {
+            // If the ray is parallel to the plane:
+            return None;
+        }
+        let t = n.inner_product(&(&self.q - &ray.origin)) / denominator;
+        if !ray_t.contains(t) {
+            return None;
+        }
+
+        // Solves P - Q = alpha u + beta v by using w := n / |n|^2.
+        let p = ray.at(t) - &self.q;
+        let w = &n / n.length_squared();
+        let alpha = w.inner_product(&p.cross_product(&self.v));
+        let beta = w.inner_product(&self.u.cross_product(&p));
+        let unit = Interval { min: 0., max: 1. };
+        if !unit.contains(alpha) || !unit.contains(beta) {
+            return None;
+        }
+
+        let surface_normal = if denominator < 0. {
+            n.unit_vector()
+        } else {
+            (-n).unit_vector()
+        };
+        let hit = HitRecord {
+            t,
+            surface_normal,
+            u: alpha,
+            v: beta,
+        };
+        Some((hit, self.material.clone()))
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if !(self.q.is_finite() && self.u.is_finite() && self.v.is_finite()) {
+            problems.push(format!(
+                "corner and edges must be finite, but are {:?}, {:?}, and {:?}",
+                self.q, self.u, self.v
+            ));
+        } else if self.u.cross_product(&self.v).length_squared() == 0. {
+            problems.push("edges must not be parallel or zero".to_string());
+        }
+        problems.extend(self.material.validate());
+        problems
+    }
+
+    fn memory_size(&self) -> usize {
+        std::mem::size_of_val(self)
+            + shared_memory_size(&self.material, self.material.memory_size())
+    }
+
+    fn bounding_box(&self) -> Option<(Point3, Point3)> {
+        let corners = [
+            &self.q + &self.u,
+            &self.q + &self.v,
+            &(&self.q + &self.u) + &self.v,
+        ];
+        Some(
+            corners
+                .iter()
+                .fold((self.q.clone(), self.q.clone()), |(min, max), corner| {
+                    (min.min(corner), max.max(corner))
+                }),
+        )
+    }
+
+    fn collect_lights(&self, lights: &mut Vec<Light>) {
+        let light = Light::parallelogram(
+            self.q.clone(),
+            self.u.clone(),
+            self.v.clone(),
+            self.material.clone(),
+        );
+        if light.emits() {
+            lights.push(light);
+        }
+    }
+
+    fn to_json(&self, materials: &mut MaterialTable) -> Option<Json> {
+        Some(tagged(
+            "quad",
+            vec![
+                ("q", point_json(&self.q)),
+                ("u", vector_json(&self.u)),
+                ("v", vector_json(&self.v)),
+                ("material", materials.index(&self.material)?),
+            ],
+        ))
+    }
+}
+
+/// The type for triangle meshes, where each triangle is given by the indices of its three vertices.
+/// Triangles are two-sided like `Quad`s.
+pub struct Mesh {
+    vertices: Vec<Point3>,
+    triangles: Vec<[usize; 3]>,
+    /// The texture coordinates of the corners of each triangle;
+    /// the texture coordinates of all the points are 0 if `None`.
+    texture_coordinates: Option<Vec<[(Float, Float); 3]>>,
+    material: SharedMaterial,
+    /// The corners of the axis-aligned bounding box.
+    bounds: (Point3, Point3),
+    /// The bottom-level BVH over the triangles in the object space, which lets most rays skip them
+    /// and is kept as is while the mesh is moved by transformations.
+    bvh: Bvh,
+}
+impl Mesh {
+    pub fn new(
+        vertices: Vec<Point3>,
+        triangles: Vec<[usize; 3]>,
+        material: SharedMaterial,
+    ) -> Self {
+        let corner = |value: Float| Point3 {
+            x: value,
+            y: value,
+            z: value,
+        };
+        // The empty box is kept for meshes without vertices so that no rays hit them.
+        let bounds = vertices.iter().fold(
+            (corner(Float::INFINITY), corner(Float::NEG_INFINITY)),
+            |(min, max), vertex| (min.min(vertex), max.max(vertex)),
+        );
+        // The triangles referring to missing vertices are left out, which `validate` reports.
+        let bvh = Bvh::new(
+            triangles
+                .iter()
+                .enumerate()
+                .filter_map(|(index, triangle)| {
+                    let [a, b, c] = triangle.map(|vertex| vertices.get(vertex));
+                    let (a, b, c) = (a?, b?, c?);
+                    Some((index, (a.min(b).min(c), a.max(b).max(c))))
+                }),
+        );
+        Mesh {
+            vertices,
+            triangles,
+            texture_coordinates: None,
+            material,
+            bounds,
+            bvh,
+        }
+    }
+
+    /// Sets the texture coordinates of the corners of each triangle,
+    /// which are interpolated over the triangle.
+    pub fn with_texture_coordinates(self, texture_coordinates: Vec<[(Float, Float); 3]>) -> Self {
+        Mesh {
+            texture_coordinates: Some(texture_coordinates),
+            ..self
+        }
+    }
+
+    /// Returns the mesh with the vertices scaled by `factor` about the origin,
+    /// e.g. to convert them from the units of the file it is imported from.
+    pub fn scaled(self, factor: Float) -> Self {
+        let vertices = self
+            .vertices
+            .iter()
+            .map(|vertex| Point3 {
+                x: factor * vertex.x,
+                y: factor * vertex.y,
+                z: factor * vertex.z,
+            })
+            .collect();
+        let mesh = Mesh::new(vertices, self.triangles, self.material);
+        match self.texture_coordinates {
+            Some(texture_coordinates) => mesh.with_texture_coordinates(texture_coordinates),
+            None => mesh,
+        }
+    }
+
+    pub fn vertices(&self) -> &[Point3] {
+        &self.vertices
+    }
+
+    pub fn triangles(&self) -> &[[usize; 3]] {
+        &self.triangles
+    }
+
+    pub fn texture_coordinates(&self) -> Option<&[[(Float, Float); 3]]> {
+        self.texture_coordinates.as_deref()
+    }
+
+    pub fn material(&self) -> &SharedMaterial {
+        &self.material
+    }
+
+    /// Returns the minimum and the maximum corners of the bounding box of the vertices.
+    pub fn bounds(&self) -> &(Point3, Point3) {
+        &self.bounds
+    }
+
+    /// Intersects `ray` with the `index`-th triangle by the Moller-Trumbore algorithm.
+    fn hit_triangle(&self, index: usize, ray: &Ray, ray_t: &Interval) -> Option<HitRecord> {
+        let [a, b, c] = self.triangles[index].map(|vertex| &self.vertices[vertex]);
+        let edge1 = b - a;
+        let edge2 = c - a;
+        let dir = ray.direction.inject();
+        let p = dir.cross_product(&edge2);
+        let determinant = edge1.inner_product(&p);
+        if determinant.abs() < 1e-12 {
+            // If the ray is parallel to the triangle or the triangle is degenerate:
+            return None;
+        }
+        // Solves O + t D = A + u (B - A) + v (C - A) by Cramer's rule.
+        let s = &ray.origin - a;
+        let u = s.inner_product(&p) / determinant;
+        if !(0. ..=1.).contains(&u) {
+            return None;
+        }
+        let q = s.cross_product(&edge1);
+        let v = dir.inner_product(&q) / determinant;
+        if v < 0. || u + v > 1. {
+            return None;
+        }
+        let t = edge2.inner_product(&q) / determinant;
+        if !ray_t.contains(t) {
+            return None;
+        }
+
+        let n = edge1.cross_product(&edge2);
+        let surface_normal = if n.inner_product(&dir) < 0. {
+            n.unit_vector()
+        } else {
+            (-n).unit_vector()
+        };
+        let (texture_u, texture_v) = match &self.texture_coordinates {
+            Some(texture_coordinates) => {
+                let [(u0, v0), (u1, v1), (u2, v2)] = texture_coordinates[index];
+                let w = 1. - u - v;
+                (w * u0 + u * u1 + v * u2, w * v0 + u * v1 + v * v2)
+            }
+            None => (0., 0.),
+        };
+        Some(HitRecord {
+            t,
+            surface_normal,
+            u: texture_u,
+            v: texture_v,
+        })
+    }
+}
+impl Hittable for Mesh {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<(HitRecord, SharedMaterial)> {
+        let mut maybe_nearest: Option<HitRecord> = None;
+        self.bvh.traverse(ray, ray_t, |index, ray_t| {
+            let hit = self.hit_triangle(index, ray, ray_t)?;
+            let t = hit.t;
+            maybe_nearest = Some(hit);
+            Some(t)
+        });
+        maybe_nearest.map(|hit| (hit, self.material.clone()))
+    }
+
+    fn bounding_box(&self) -> Option<(Point3, Point3)> {
+        self.bvh.bounds().cloned()
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if let Some(vertex) = self.vertices.iter().find(|vertex| !vertex.is_finite()) {
+            problems.push(format!("vertices must be finite, but one is {:?}", vertex));
+        }
+        let num_vertices = self.vertices.len();
+        if let Some(triangle) = self
+            .triangles
+            .iter()
+            .find(|triangle| triangle.iter().any(|&index| index >= num_vertices))
+        {
+            problems.push(format!(
+                "triangle {:?} refers to a missing vertex (there are {} vertices)",
+                triangle, num_vertices
+            ));
+        }
+        if let Some(texture_coordinates) = &self.texture_coordinates {
+            if texture_coordinates.len() != self.triangles.len() {
+                problems.push(format!(
+                    "there are texture coordinates for {} triangles, but there are {} triangles",
+                    texture_coordinates.len(),
+                    self.triangles.len()
+                ));
+            }
+        }
+        problems.extend(self.material.validate());
+        problems
+    }
+
+    fn memory_size(&self) -> usize {
+        std::mem::size_of_val(self)
+            + self.vertices.capacity() * std::mem::size_of::<Point3>()
+            + self.triangles.capacity() * std::mem::size_of::<[usize; 3]>()
+            + self
+                .texture_coordinates
+                .as_ref()
+                .map_or(0, |texture_coordinates| {
+                    texture_coordinates.capacity() * std::mem::size_of::<[(Float, Float); 3]>()
+                })
+            + self.bvh.heap_size()
+            + shared_memory_size(&self.material, self.material.memory_size())
+    }
+
+    fn collect_lights(&self, lights: &mut Vec<Light>) {
+        let light = |index: usize| {
+            let corners = self.triangles[index].map(|vertex| &self.vertices[vertex]);
+            let texture_coordinates = self
+                .texture_coordinates
+                .as_ref()
+                .map_or([(0., 0.); 3], |texture_coordinates| {
+                    texture_coordinates[index]
+                });
+            Light::triangle(corners, texture_coordinates, self.material.clone())
+        };
+        if emits(&self.material) {
+            lights.extend((0..self.triangles.len()).map(light));
+        }
+    }
+
+    fn to_json(&self, materials: &mut MaterialTable) -> Option<Json> {
+        let vertices = self.vertices.iter().map(point_json).collect();
+        let triangles = self
+            .triangles
+            .iter()
+            .map(|triangle| Json::Array(triangle.map(|i| Json::Number(i as f64)).to_vec()))
+            .collect();
+        let mut members = vec![
+            ("vertices", Json::Array(vertices)),
+            ("triangles", Json::Array(triangles)),
+        ];
+        if let Some(texture_coordinates) = &self.texture_coordinates {
+            let corners = |corners: &[(Float, Float); 3]| {
+                Json::Array(
+                    corners
+                        .iter()
+                        .flat_map(|&(u, v)| [number(u), number(v)])
+                        .collect(),
+                )
+            };
+            members.push((
+                "texture_coordinates",
+                Json::Array(texture_coordinates.iter().map(corners).collect()),
+            ));
+        }
+        members.push(("material", materials.index(&self.material)?));
+        Some(tagged("mesh", members))
+    }
+
+    fn as_mesh(&self) -> Option<&Mesh> {
+        Some(self)
+    }
+}
+
+/// The type for transformations that scale by `scale` and rotate by `rotation_degree` around `pivot`,
+/// and then move by `translation`.
+#[derive(Clone, Debug)]
+pub struct Transform {
+    pivot: Point3,
+    translation: Vec3,
+    /// The angles of the rotations around the x, y, and z axes in degrees,
+    /// which are applied in this order.
+    rotation_degree: Vec3,
+    scale: Float,
+    /// The transformation from the object space to the world.
+    to_world: Mat4,
+    /// The inverse of `to_world`.
+    to_object: Mat4,
+    /// The transpose of `to_object`, which transforms normals to the world.
+    normal_to_world: Mat4,
+}
+impl Transform {
+    pub fn new(pivot: Point3, translation: Vec3, rotation_degree: Vec3, scale: Float) -> Self {
+        let origin = Point3 {
+            x: 0.,
+            y: 0.,
+            z: 0.,
+        };
+        let pivot_offset = &pivot - &origin;
+        let to_world = &(&(&Mat4::translation(&(&pivot_offset + &translation))
+            * &Mat4::rotation(&Quat::from_euler_degrees(&rotation_degree)))
+            * &Mat4::scaling(&Vec3 {
+                x: scale,
+                y: scale,
+                z: scale,
+            }))
+            * &Mat4::translation(&-pivot_offset);
+        // A singular transformation is reported by `validate`.
+        let to_object = to_world.inverse().unwrap_or_else(Mat4::identity);
+        let normal_to_world = to_object.transpose();
+        Self {
+            pivot,
+            translation,
+            rotation_degree,
+            scale,
+            to_world,
+            to_object,
+            normal_to_world,
+        }
+    }
+
+    /// Returns the transformation that changes nothing.
+    pub fn identity() -> Self {
+        let zero = Vec3 {
+            x: 0.,
+            y: 0.,
+            z: 0.,
+        };
+        Self::new(
+            Point3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            zero.clone(),
+            zero,
+            1.,
+        )
+    }
+
+    pub fn pivot(&self) -> &Point3 {
+        &self.pivot
+    }
+
+    pub fn translation(&self) -> &Vec3 {
+        &self.translation
+    }
+
+    pub fn rotation_degree(&self) -> &Vec3 {
+        &self.rotation_degree
+    }
+
+    pub fn scale(&self) -> Float {
+        self.scale
+    }
+
+    /// Returns `point` in the object space moved to the world.
+    pub fn point_to_world(&self, point: &Point3) -> Point3 {
+        self.to_world.transform_point(point)
+    }
+
+    /// Returns `point` in the world moved back to the object space.
+    pub fn point_to_object(&self, point: &Point3) -> Point3 {
+        self.to_object.transform_point(point)
+    }
+
+    /// Checks that the transformation changes nothing, in which case objects can skip it
+    /// so that they look exactly the same as without it.
+    pub fn is_identity(&self) -> bool {
+        let is_zero = |v: &Vec3| v.x == 0. && v.y == 0. && v.z == 0.;
+        is_zero(&self.translation) && is_zero(&self.rotation_degree) && self.scale == 1.
+    }
+
+    /// Intersects `ray` with `object` transformed by `self`.
+    pub fn hit(
+        &self,
+        object: &dyn Hittable,
+        ray: &Ray,
+        ray_t: &Interval,
+    ) -> Option<(HitRecord, SharedMaterial)> {
+        // Transforming the object is the same as transforming the ray inversely.
+        let object_direction = self.to_object.transform_direction(&ray.direction.inject());
+        // The distance along the ray in the object space per unit distance in the world:
+        let ratio = object_direction.length();
+        let object_ray = Ray {
+            origin: self.to_object.transform_point(&ray.origin),
+            direction: object_direction.unit_vector(),
+        };
+        let object_ray_t = Interval {
+            min: ray_t.min * ratio,
+            max: ray_t.max * ratio,
+        };
+        let (hit, material) = object.hit(&object_ray, &object_ray_t)?;
+        // Distances differ between the spaces, and thus `t` is recomputed from the point.
+        let point = self.to_world.transform_point(&object_ray.at(hit.t));
+        let hit = HitRecord {
+            t: (&point - &ray.origin).inner_product(&ray.direction.inject()),
+            surface_normal: self
+                .normal_to_world
+                .transform_direction(&hit.surface_normal.inject())
+                .unit_vector(),
+            ..hit
+        };
+        Some((hit, material))
+    }
+
+    /// Returns the box containing the box `bounds` in the object space transformed to the world.
+    pub fn bounding_box(&self, bounds: &(Point3, Point3)) -> (Point3, Point3) {
+        let (min, max) = bounds;
+        let corner = |index: usize| {
+            self.to_world.transform_point(&Point3 {
+                x: if index & 1 == 0 { min.x } else { max.x },
+                y: if index & 2 == 0 { min.y } else { max.y },
+                z: if index & 4 == 0 { min.z } else { max.z },
+            })
+        };
+        (1..8)
+            .map(corner)
+            .fold((corner(0), corner(0)), |(min, max), corner| {
+                (min.min(&corner), max.max(&corner))
+            })
+    }
+
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if !(self.pivot.is_finite()
+            && self.translation.is_finite()
+            && self.rotation_degree.is_finite())
+        {
+            problems.push(format!(
+                "pivot, translation, and rotation must be finite, but are {:?}, {:?}, and {:?}",
+                self.pivot, self.translation, self.rotation_degree
+            ));
+        }
+        if !(self.scale > 0. && self.scale.is_finite()) {
+            problems.push(format!(
+                "scale must be positive and finite, but is {}",
+                self.scale
+            ));
+        }
+        problems
+    }
+
+    /// Returns the members of scene files describing the transformation.
+    pub fn to_json_members(&self) -> Vec<(&'static str, Json)> {
+        vec![
+            ("pivot", point_json(&self.pivot)),
+            ("translation", vector_json(&self.translation)),
+            ("rotation", vector_json(&self.rotation_degree)),
+            ("scale", number(self.scale)),
+        ]
+    }
+}
+
+/// The type for objects transformed by `Transform`.
+pub struct Transformed {
+    transform: Transform,
+    object: Box<dyn Hittable>,
+}
+impl Transformed {
+    pub fn new(
+        object: Box<dyn Hittable>,
+        pivot: Point3,
+        translation: Vec3,
+        rotation_degree: Vec3,
+        scale: Float,
+    ) -> Self {
+        Self {
+            transform: Transform::new(pivot, translation, rotation_degree, scale),
+            object,
+        }
+    }
+}
+impl Hittable for Transformed {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<(HitRecord, SharedMaterial)> {
+        self.transform.hit(self.object.as_ref(), ray, ray_t)
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut problems = self.transform.validate();
+        problems.extend(self.object.validate());
+        problems
+    }
+
+    fn memory_size(&self) -> usize {
+        std::mem::size_of_val(self) + self.object.memory_size()
+    }
+
+    fn bounding_box(&self) -> Option<(Point3, Point3)> {
+        Some(self.transform.bounding_box(&self.object.bounding_box()?))
+    }
+
+    fn to_json(&self, materials: &mut MaterialTable) -> Option<Json> {
+        let mut members = self.transform.to_json_members();
+        members.push(("object", self.object.to_json(materials)?));
+        Some(tagged("transformed", members))
+    }
+}
+
+/// The type for objects whose materials are replaced by `material` where they emit no light,
+/// e.g. by a neutral one to check the geometry, while the lights keep lighting the scene.
+pub struct Overridden {
+    pub object: Box<dyn Hittable>,
+    pub material: SharedMaterial,
+}
+impl Hittable for Overridden {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<(HitRecord, SharedMaterial)> {
+        let (hit, material) = self.object.hit(ray, ray_t)?;
+        let emitted = material.emitted(&hit);
+        if emitted.r > 0. || emitted.g > 0. || emitted.b > 0. {
+            Some((hit, material))
+        } else {
+            Some((hit, self.material.clone()))
+        }
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut problems = self.object.validate();
+        problems.extend(self.material.validate());
+        problems
+    }
+
+    fn memory_size(&self) -> usize {
+        std::mem::size_of_val(self) + self.object.memory_size()
+    }
+
+    fn bounding_box(&self) -> Option<(Point3, Point3)> {
+        self.object.bounding_box()
+    }
+
+    /// Pushes the lights of the object, which keep their own materials.
+    fn collect_lights(&self, lights: &mut Vec<Light>) {
+        self.object.collect_lights(lights);
+    }
+
+    /// Returns the object with its own materials, since the override is not a part of the scene.
+    fn to_json(&self, materials: &mut MaterialTable) -> Option<Json> {
+        self.object.to_json(materials)
+    }
+
+    fn as_mesh(&self) -> Option<&Mesh> {
+        self.object.as_mesh()
+    }
+}
+
+/// Lets the objects of any kind be passed to `HittableList` as they are, as well as already boxed.
+impl<H: Hittable + 'static> From<H> for Box<dyn Hittable> {
+    fn from(object: H) -> Self {
+        Box::new(object)
+    }
+}
+
+#[derive(Default)]
+pub struct HittableList {
+    pub members: Vec<Box<dyn Hittable>>,
+}
+impl HittableList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the empty list with room for `capacity` objects, e.g. for generated scenes
+    /// whose numbers of objects are known in advance.
+    pub fn with_capacity(capacity: usize) -> Self {
+        HittableList {
+            members: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Adds `object` to the end of the list, boxing it unless it is already boxed.
+    pub fn push(&mut self, object: impl Into<Box<dyn Hittable>>) {
+        self.members.push(object.into());
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+}
+impl<T: Into<Box<dyn Hittable>>> FromIterator<T> for HittableList {
+    fn from_iter<I: IntoIterator<Item = T>>(objects: I) -> Self {
+        HittableList {
+            members: objects.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+impl<T: Into<Box<dyn Hittable>>> Extend<T> for HittableList {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, objects: I) {
+        self.members.extend(objects.into_iter().map(Into::into));
+    }
+}
+impl Hittable for HittableList {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<(HitRecord, SharedMaterial)> {
+        let mut maybe_nearest: Option<(HitRecord, SharedMaterial)> = None;
+        let mut ray_t = ray_t.clone();
+        for hittable in self.members.iter() {
+            // Only hits nearer than the nearest one so far are looked for.
+            if let Some(pair) = hittable.hit(ray, &ray_t) {
+                ray_t.max = pair.0.t;
+                maybe_nearest = Some(pair);
+            }
+        }
+        maybe_nearest
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        for (index, hittable) in self.members.iter().enumerate() {
+            for problem in hittable.validate() {
+                problems.push(format!("object #{}: {}", index, problem));
+            }
+        }
+        problems
+    }
+
+    fn memory_size(&self) -> usize {
+        let pointers_size = self.members.capacity() * std::mem::size_of::<Box<dyn Hittable>>();
+        let members_size: usize = self.members.iter().map(|member| member.memory_size()).sum();
+        std::mem::size_of_val(self) + pointers_size + members_size
+    }
+
+    /// Returns `None` if any of the members has no bounds or if there are no members.
+    fn bounding_box(&self) -> Option<(Point3, Point3)> {
+        let mut boxes = self.members.iter().map(|member| member.bounding_box());
+        let first = boxes.next()??;
+        boxes.try_fold(first, |bounds, member| Some(union(&bounds, &member?)))
+    }
+
+    fn collect_lights(&self, lights: &mut Vec<Light>) {
+        for member in self.members.iter() {
+            member.collect_lights(lights);
+        }
+    }
+
+    fn to_json(&self, materials: &mut MaterialTable) -> Option<Json> {
+        let members = self
+            .members
+            .iter()
+            .map(|member| member.to_json(materials))
+            .collect::<Option<_>>()?;
+        Some(tagged("list", vec![("members", Json::Array(members))]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx::{assert_approx_eq, ApproxEq, DEFAULT_EPSILON};
+    use crate::arbitrary::{self, for_all_cases};
+    use crate::geometry::{Vec3, ORIGIN_OFFSET};
+    use rand::Rng;
+
+    /// The range of `t` used for rendering.
+    fn forward() -> Interval {
+        Interval {
+            min: 0.,
+            max: Float::INFINITY,
+        }
+    }
+
+    fn create_dummy_material() -> SharedMaterial {
+        let albedo = Attenuation {
+            r: 0.5,
+            g: 0.5,
+            b: 0.5,
+        };
+        let material = Lambertian { albedo };
+        Arc::new(material)
+    }
+
+    #[test]
+    fn sphere_test1() {
+        let sphere = Sphere {
+            center: Point3 {
+                x: 0.,
+                y: 0.,
+                z: -3.,
+            },
+            radius: 1.,
+            material: create_dummy_material(),
+        };
+        let ray = Ray {
+            origin: Point3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            direction: Vec3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            }
+            .unit_vector(),
+        };
+        let expected_hit = HitRecord {
+            t: 2.,
+            surface_normal: Vec3 {
+                x: 0.,
+                y: 0.,
+                z: 1.,
+            }
+            .unit_vector(),
+            u: 0.25,
+            v: 0.5,
+        };
+        match sphere.hit(&ray, &forward()) {
+            Some((got_hit, _)) => {
+                assert_approx_eq!(expected_hit, got_hit);
+            }
+            None => {
+                panic!("expected a hit");
+            }
+        }
+    }
+
+    #[test]
+    fn sphere_test2() {
+        let sphere = Sphere {
+            center: Point3 {
+                x: 0.,
+                y: 0.,
+                z: -8.,
+            },
+            radius: 5.,
+            material: create_dummy_material(),
+        };
+        let ray = Ray {
+            origin: Point3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            direction: Vec3 {
+                x: -0.6,
+                y: 0.,
+                z: -0.8,
+            }
+            .unit_vector(),
+        };
+        let expected_hit = HitRecord {
+            t: 5.,
+            surface_normal: Vec3 {
+                x: -0.6,
+                y: 0.,
+                z: 0.8,
+            }
+            .unit_vector(),
+            u: (0.8 as Float).atan2(0.6) / (2. * consts::PI),
+            v: 0.5,
+        };
+        match sphere.hit(&ray, &forward()) {
+            Some((got_hit, _)) => {
+                assert_approx_eq!(expected_hit, got_hit);
+            }
+            None => {
+                panic!("expected a hit");
+            }
+        }
+    }
+
+    #[test]
+    fn sphere_test3() {
+        let sphere = Sphere {
+            center: Point3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            radius: 5.,
+            material: create_dummy_material(),
+        };
+        let ray = Ray {
+            origin: Point3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            direction: Vec3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            }
+            .unit_vector(),
+        };
+        let expected_hit = HitRecord {
+            t: 5.,
+            surface_normal: Vec3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            }
+            .unit_vector(),
+            u: 0.75,
+            v: 0.5,
+        };
+        match sphere.hit(&ray, &forward()) {
+            Some((got_hit, _)) => {
+                assert_approx_eq!(expected_hit, got_hit);
+            }
+            None => {
+                panic!("expected a hit");
+            }
+        }
+    }
+
+    #[test]
+    fn sphere_leaving_properties() {
+        for_all_cases(|rng| {
+            let sphere = Sphere {
+                center: arbitrary::point3(rng, 100.),
+                radius: rng.gen_range(0.1..10.),
+                material: create_dummy_material(),
+            };
+            let normal = arbitrary::unit_vec3(rng);
+            let point = &sphere.center + &(&normal * sphere.radius);
+            let direction = arbitrary::unit_vec3(rng);
+            let outward = direction.inner_product(&normal) > 0.;
+            let ray = Ray::leaving(&point, &normal, direction);
+            let everywhere = Interval {
+                min: 0.,
+                max: Float::INFINITY,
+            };
+            // Rays leaving the sphere never hit it again, and ones going into it hit the far side.
+            match sphere.hit(&ray, &everywhere) {
+                None => assert!(outward),
+                Some((hit, _)) => {
+                    assert!(!outward);
+                    assert!(hit.surface_normal.inner_product(&ray.direction) > 0.);
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn quad_test1() {
+        let quad = Quad {
+            q: Point3 {
+                x: -1.,
+                y: -1.,
+                z: -2.,
+            },
+            u: Vec3 {
+                x: 2.,
+                y: 0.,
+                z: 0.,
+            },
+            v: Vec3 {
+                x: 0.,
+                y: 2.,
+                z: 0.,
+            },
+            material: create_dummy_material(),
+        };
+        let ray = Ray {
+            origin: Point3 {
+                x: 0.5,
+                y: 0.5,
+                z: 0.,
+            },
+            direction: Vec3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            }
+            .unit_vector(),
+        };
+        let expected_hit = HitRecord {
+            t: 2.,
+            surface_normal: Vec3 {
+                x: 0.,
+                y: 0.,
+                z: 1.,
+            }
+            .unit_vector(),
+            u: 0.75,
+            v: 0.75,
+        };
+        match quad.hit(&ray, &forward()) {
+            Some((got_hit, _)) => {
+                assert_approx_eq!(expected_hit, got_hit);
+            }
+            None => {
+                panic!("expected a hit");
+            }
+        }
+    }
+
+    #[test]
+    fn quad_test2() {
+        let quad = Quad {
+            q: Point3 {
+                x: -1.,
+                y: -1.,
+                z: -2.,
+            },
+            u: Vec3 {
+                x: 2.,
+                y: 0.,
+                z: 0.,
+            },
+            v: Vec3 {
+                x: 0.,
+                y: 2.,
+                z: 0.,
+            },
+            material: create_dummy_material(),
+        };
+        let ray = Ray {
+            origin: Point3 {
+                x: 1.5,
+                y: 0.,
+                z: 0.,
+            },
+            direction: Vec3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            }
+            .unit_vector(),
+        };
+        assert!(quad.hit(&ray, &forward()).is_none());
     }
-}
 
-pub struct HittableList {
-    pub members: Vec<Box<dyn Hittable>>,
-}
-impl Hittable for HittableList {
-    fn hit(&self, ray: &Ray) -> Option<(HitRecord, BoxedMaterial)> {
-        let mut maybe_nearest: Option<(HitRecord, BoxedMaterial)> = None;
-        for hittable in self.members.iter() {
-            if let Some(pair) = hittable.hit(ray) {
-                let (hit, _material) = &pair;
-                if let Some(nearest) = &maybe_nearest {
-                    let (nearest_hit, _) = &nearest;
-                    if hit.t < nearest_hit.t {
-                        maybe_nearest = Some(pair);
-                    }
-                } else {
-                    maybe_nearest = Some(pair);
-                }
+    #[test]
+    fn mesh_test() {
+        let point = |x: Float, y: Float, z: Float| Point3 { x, y, z };
+        // A square of two triangles on the plane z = -2, and a triangle behind it:
+        let mesh = Mesh::new(
+            vec![
+                point(-1., -1., -2.),
+                point(1., -1., -2.),
+                point(1., 1., -2.),
+                point(-1., 1., -2.),
+                point(0., 0., -5.),
+            ],
+            vec![[0, 1, 2], [0, 2, 3], [4, 1, 2]],
+            create_dummy_material(),
+        );
+        assert_eq!(&(point(-1., -1., -5.), point(1., 1., -2.)), mesh.bounds());
+        assert!(mesh.validate().is_empty());
+        let ray_to = |x: Float, y: Float| Ray {
+            origin: point(x, y, 0.),
+            direction: Vec3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            }
+            .unit_vector(),
+        };
+        let expected_hit = HitRecord {
+            t: 2.,
+            surface_normal: Vec3 {
+                x: 0.,
+                y: 0.,
+                z: 1.,
+            }
+            .unit_vector(),
+            u: 0.,
+            v: 0.,
+        };
+        // The nearest triangle is hit regardless of the order of the triangles.
+        for (x, y) in [(0.5, -0.5), (-0.5, 0.5)] {
+            match mesh.hit(&ray_to(x, y), &forward()) {
+                Some((got_hit, _)) => assert_approx_eq!(expected_hit, got_hit),
+                None => panic!("expected a hit at ({}, {})", x, y),
             }
         }
-        maybe_nearest
-    }
-}
+        assert!(mesh.hit(&ray_to(1.5, 0.), &forward()).is_none());
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::geometry::Vec3;
+        // The texture coordinates are interpolated over the triangle.
+        let textured = Mesh::new(
+            vec![
+                point(-1., -1., -2.),
+                point(1., -1., -2.),
+                point(-1., 1., -2.),
+            ],
+            vec![[0, 1, 2]],
+            create_dummy_material(),
+        )
+        .with_texture_coordinates(vec![[(0., 0.), (1., 0.), (0., 1.)]]);
+        let (hit, _) = textured.hit(&ray_to(0.5, -0.5), &forward()).unwrap();
+        assert_approx_eq!(0.75, hit.u);
+        assert_approx_eq!(0.25, hit.v);
+        // Scaling keeps the texture coordinates, e.g. of a mesh in millimeters seen in meters.
+        let scaled = textured.scaled(0.5);
+        assert_eq!(
+            &(point(-0.5, -0.5, -1.), point(0.5, 0.5, -1.)),
+            scaled.bounds()
+        );
+        let (hit, _) = scaled.hit(&ray_to(0.25, -0.25), &forward()).unwrap();
+        assert_approx_eq!(1., hit.t);
+        assert_approx_eq!(0.75, hit.u);
 
-    fn create_dummy_material() -> BoxedMaterial {
-        let albedo = Attenuation {
-            r: 0.5,
-            g: 0.5,
-            b: 0.5,
+        let broken = Mesh::new(
+            vec![point(0., 0., 0.)],
+            vec![[0, 0, 1]],
+            create_dummy_material(),
+        );
+        assert_eq!(1, broken.validate().len());
+    }
+
+    #[test]
+    fn shared_memory_size_test() {
+        let texels = vec![
+            Attenuation {
+                r: 0.5,
+                g: 0.5,
+                b: 0.5,
+            };
+            64 * 64
+        ];
+        let texture = Arc::new(ImageTexture::new(64, 64, texels));
+        let material: SharedMaterial = Arc::new(TexturedLambertian {
+            texture: Arc::clone(&texture),
+        });
+        let sphere = |x: Float| Sphere {
+            center: Point3 { x, y: 0., z: 0. },
+            radius: 1.,
+            material: Arc::clone(&material),
         };
-        let material = Lambertian { albedo };
-        Box::new(material)
+        let world = HittableList {
+            members: vec![Box::new(sphere(0.)), Box::new(sphere(2.))],
+        };
+        // The texture is counted once even though both spheres refer to it.
+        let texture_size = texture.memory_size();
+        drop((texture, material));
+        let size = world.memory_size();
+        assert!(texture_size < size && size < 2 * texture_size, "{}", size);
     }
 
     #[test]
-    fn sphere_test1() {
-        let sphere = Sphere {
-            center: Point3 {
+    fn hittable_list_building_test() {
+        let material: SharedMaterial = Arc::new(Lambertian {
+            albedo: Attenuation {
+                r: 0.5,
+                g: 0.5,
+                b: 0.5,
+            },
+        });
+        let sphere = |z: Float| Sphere {
+            center: Point3 { x: 0., y: 0., z },
+            radius: 0.5,
+            material: Arc::clone(&material),
+        };
+        let mut world: HittableList = [-4., -6.].into_iter().map(sphere).collect();
+        assert_eq!(2, world.len());
+        // Both objects as they are and those already boxed are accepted.
+        world.push(sphere(-8.));
+        let boxed: Box<dyn Hittable> = Box::new(sphere(-2.));
+        world.push(boxed);
+        world.extend([sphere(-10.), sphere(-12.)]);
+        assert_eq!(6, world.len());
+        assert!(HittableList::with_capacity(10).is_empty());
+
+        let ray = Ray {
+            origin: Point3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            direction: Vec3 {
                 x: 0.,
                 y: 0.,
+                z: -1.,
+            }
+            .unit_vector(),
+        };
+        let ray_t = Interval {
+            min: 0.,
+            max: Float::INFINITY,
+        };
+        let (hit, _) = world.hit(&ray, &ray_t).unwrap();
+        assert_approx_eq!(1.5, hit.t);
+    }
+
+    #[test]
+    fn cylinder_test1() {
+        let cylinder = Cylinder {
+            base: Point3 {
+                x: 0.,
+                y: -1.,
                 z: -3.,
             },
+            axis: Vec3 {
+                x: 0.,
+                y: 2.,
+                z: 0.,
+            },
             radius: 1.,
             material: create_dummy_material(),
         };
         let ray = Ray {
             origin: Point3 {
                 x: 0.,
-                y: 0.,
+                y: 0.5,
                 z: 0.,
             },
             direction: Vec3 {
@@ -265,28 +2105,83 @@ mod tests {
                 z: 1.,
             }
             .unit_vector(),
+            u: 0.75,
+            v: 0.75,
         };
-        match sphere.hit(&ray) {
+        match cylinder.hit(&ray, &forward()) {
             Some((got_hit, _)) => {
-                assert_eq!(expected_hit, got_hit);
+                assert_approx_eq!(expected_hit, got_hit);
             }
             None => {
-                assert!(false);
+                panic!("expected a hit");
             }
         }
     }
 
     #[test]
-    fn sphere_test2() {
-        let sphere = Sphere {
-            center: Point3 {
+    fn cylinder_test2() {
+        let cylinder = Cylinder {
+            base: Point3 {
                 x: 0.,
-                y: 0.,
-                z: -8.,
+                y: -1.,
+                z: -3.,
             },
-            radius: 5.,
+            axis: Vec3 {
+                x: 0.,
+                y: 2.,
+                z: 0.,
+            },
+            radius: 1.,
             material: create_dummy_material(),
         };
+        // Passes above the top end of the cylinder:
+        let ray = Ray {
+            origin: Point3 {
+                x: 0.,
+                y: 1.5,
+                z: 0.,
+            },
+            direction: Vec3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            }
+            .unit_vector(),
+        };
+        assert!(cylinder.hit(&ray, &forward()).is_none());
+    }
+
+    #[test]
+    fn transformed_test1() {
+        let origin = Point3 {
+            x: 0.,
+            y: 0.,
+            z: 0.,
+        };
+        let zero = Vec3 {
+            x: 0.,
+            y: 0.,
+            z: 0.,
+        };
+        let translated = Transformed::new(
+            Box::new(Sphere {
+                center: Point3 {
+                    x: 0.,
+                    y: 0.,
+                    z: -1.,
+                },
+                radius: 1.,
+                material: create_dummy_material(),
+            }),
+            origin,
+            Vec3 {
+                x: 0.,
+                y: 0.,
+                z: -2.,
+            },
+            zero,
+            1.,
+        );
         let ray = Ray {
             origin: Point3 {
                 x: 0.,
@@ -294,42 +2189,64 @@ mod tests {
                 z: 0.,
             },
             direction: Vec3 {
-                x: -0.6,
+                x: 0.,
                 y: 0.,
-                z: -0.8,
+                z: -1.,
             }
             .unit_vector(),
         };
         let expected_hit = HitRecord {
-            t: 4.999999999999997, // Ideally `5.`
+            t: 2.,
             surface_normal: Vec3 {
-                x: -0.5999999999999996, // Ideally `-0.6`
+                x: 0.,
                 y: 0.,
-                z: 0.8000000000000004, // Ideally `0.8`
+                z: 1.,
             }
             .unit_vector(),
+            u: 0.25,
+            v: 0.5,
         };
-        match sphere.hit(&ray) {
+        match translated.hit(&ray, &forward()) {
             Some((got_hit, _)) => {
-                assert_eq!(expected_hit, got_hit);
+                assert_approx_eq!(expected_hit, got_hit);
             }
             None => {
-                assert!(false);
+                panic!("expected a hit");
             }
         }
     }
 
     #[test]
-    fn sphere_test3() {
-        let sphere = Sphere {
-            center: Point3 {
+    fn transformed_test2() {
+        // The sphere is doubled around the pivot and then moved onto the z-axis by the rotation.
+        let pivot = Point3 {
+            x: 0.,
+            y: 0.,
+            z: -3.,
+        };
+        let transformed = Transformed::new(
+            Box::new(Sphere {
+                center: Point3 {
+                    x: 1.,
+                    y: 0.,
+                    z: -3.,
+                },
+                radius: 0.5,
+                material: create_dummy_material(),
+            }),
+            pivot,
+            Vec3 {
                 x: 0.,
                 y: 0.,
                 z: 0.,
             },
-            radius: 5.,
-            material: create_dummy_material(),
-        };
+            Vec3 {
+                x: 0.,
+                y: 90.,
+                z: 0.,
+            },
+            2.,
+        );
         let ray = Ray {
             origin: Point3 {
                 x: 0.,
@@ -343,21 +2260,25 @@ mod tests {
             }
             .unit_vector(),
         };
-        let expected_hit = HitRecord {
-            t: 5.,
-            surface_normal: Vec3 {
-                x: 0.,
-                y: 0.,
-                z: -1.,
-            }
-            .unit_vector(),
-        };
-        match sphere.hit(&ray) {
+        match transformed.hit(&ray, &forward()) {
             Some((got_hit, _)) => {
-                assert_eq!(expected_hit, got_hit);
+                let expected_hit = HitRecord {
+                    t: 4.,
+                    surface_normal: Vec3 {
+                        x: 0.,
+                        y: 0.,
+                        z: 1.,
+                    }
+                    .unit_vector(),
+                    u: 0.,
+                    v: 0.5,
+                };
+                // The hit point is on the seam of the sphere, where `u` may be either 0 or 1 by rounding.
+                let u = got_hit.u.min(1. - got_hit.u);
+                assert_approx_eq!(expected_hit, HitRecord { u, ..got_hit });
             }
             None => {
-                assert!(false);
+                panic!("expected a hit");
             }
         }
     }
@@ -370,11 +2291,43 @@ mod tests {
         }
     }
 
+    /// Glass reflects rays stochastically according to the Fresnel reflectance,
+    /// and thus this accepts the regularly reflected ray as well as the expected refracted one.
+    fn assert_refracted_or_reflected(
+        expected_ray_out: &Ray,
+        ray_in: &Ray,
+        hit: &HitRecord,
+        ray_out: &Ray,
+    ) {
+        assert_leaving(&expected_ray_out.origin, hit, ray_out);
+        let reflected_direction = reflect_vector(&ray_in.direction, &hit.surface_normal);
+        if !ray_out
+            .direction
+            .approx_eq(&reflected_direction, DEFAULT_EPSILON)
+        {
+            assert_approx_eq!(expected_ray_out.direction, ray_out.direction);
+        }
+    }
+
+    /// Checks that `ray_out` starts slightly off `point` to the side of the surface it goes to.
+    fn assert_leaving(point: &Point3, hit: &HitRecord, ray_out: &Ray) {
+        assert_approx_eq!(*point, ray_out.origin, 1e3 * ORIGIN_OFFSET);
+        let offset = (&ray_out.origin - point).inner_product(&hit.surface_normal.inject());
+        let side = ray_out.direction.inner_product(&hit.surface_normal);
+        assert!(
+            offset * side > 0.,
+            "{:?} does not leave {:?}",
+            ray_out,
+            point
+        );
+    }
+
     #[test]
     fn glass_scatter_test1() {
         let glass = Glass {
             eta: 1.0,
             albedo: make_dummy_attenuation(),
+            priority: 0,
         };
         let ray_in = Ray {
             origin: Point3 {
@@ -397,6 +2350,8 @@ mod tests {
                 z: 0.,
             }
             .unit_vector(),
+            u: 0.,
+            v: 0.,
         };
         let expected_ray_out = Ray {
             origin: Point3 {
@@ -411,24 +2366,25 @@ mod tests {
             }
             .unit_vector(),
         };
-        let (_attenuation, ray_out) = glass.scatter(&ray_in, &hit);
-        assert_eq!(expected_ray_out, ray_out);
+        let ray_out = glass.scatter(&ray_in, &hit).unwrap().ray;
+        assert_refracted_or_reflected(&expected_ray_out, &ray_in, &hit, &ray_out);
     }
 
     #[test]
     fn glass_scatter_test2() {
         let glass = Glass {
-            eta: 3f64.sqrt(),
+            eta: Float::sqrt(3.),
             albedo: make_dummy_attenuation(),
+            priority: 0,
         };
         let ray_in = Ray {
             origin: Point3 {
-                x: -3f64.sqrt(),
+                x: -Float::sqrt(3.),
                 y: 1.,
                 z: 0.,
             },
             direction: Vec3 {
-                x: 3f64.sqrt(),
+                x: Float::sqrt(3.),
                 y: -1.,
                 z: 0.,
             }
@@ -442,39 +2398,42 @@ mod tests {
                 z: 0.,
             }
             .unit_vector(),
+            u: 0.,
+            v: 0.,
         };
         let expected_ray_out = Ray {
             origin: Point3 {
-                x: 2.220446049250313e-16,  // Ideally `0.`
-                y: -2.220446049250313e-16, // Ideally `0.`
+                x: 0.,
+                y: 0.,
                 z: 0.,
             },
             direction: Vec3 {
-                x: 0.5000000000000001,  // Ideally `0.5`
-                y: -0.8660254037844386, // Ideally `-3f64.sqrt() / 2.`
+                x: 0.5,
+                y: -Float::sqrt(3.) / 2.,
                 z: 0.,
             }
             .unit_vector(),
         };
-        let (_attenuation, ray_out) = glass.scatter(&ray_in, &hit);
-        assert_eq!(expected_ray_out, ray_out);
+        let ray_out = glass.scatter(&ray_in, &hit).unwrap().ray;
+        assert_refracted_or_reflected(&expected_ray_out, &ray_in, &hit, &ray_out);
     }
 
     #[test]
     fn glass_scatter_test3() {
         let glass = Glass {
-            eta: 3f64.sqrt(),
+            eta: Float::sqrt(3.),
             albedo: make_dummy_attenuation(),
+            priority: 0,
         };
         let ray_in = Ray {
             origin: Point3 {
                 x: -1.,
-                y: -3f64.sqrt(),
+                y: -Float::sqrt(3.),
                 z: 0.,
             },
             direction: Vec3 {
                 x: 1.,
-                y: 3f64.sqrt(),
+                y: Float::sqrt(3.),
                 z: 0.,
             }
             .unit_vector(),
@@ -487,21 +2446,346 @@ mod tests {
                 z: 0.,
             }
             .unit_vector(),
+            u: 0.,
+            v: 0.,
         };
         let expected_ray_out = Ray {
             origin: Point3 {
-                x: 2.220446049250313e-16, // Ideally `0.`
-                y: 2.220446049250313e-16, // Ideally `0.`
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            direction: Vec3 {
+                x: Float::sqrt(3.) / 2.,
+                y: 0.5,
+                z: 0.,
+            }
+            .unit_vector(),
+        };
+        let ray_out = glass.scatter(&ray_in, &hit).unwrap().ray;
+        assert_refracted_or_reflected(&expected_ray_out, &ray_in, &hit, &ray_out);
+    }
+
+    #[test]
+    fn glass_scatter_between_test() {
+        // Glass in water of the same refractive index does not bend rays.
+        let glass = Glass {
+            eta: 1.33,
+            albedo: make_dummy_attenuation(),
+            priority: 0,
+        };
+        let ray_in = Ray {
+            origin: Point3 {
+                x: -Float::sqrt(3.),
+                y: 1.,
                 z: 0.,
             },
             direction: Vec3 {
-                x: 0.8660254037844388,  // Ideally `3f64.sqrt() / 2.`
-                y: 0.49999999999999967, // Ideally `0.5`
+                x: Float::sqrt(3.),
+                y: -1.,
+                z: 0.,
+            }
+            .unit_vector(),
+        };
+        let hit = HitRecord {
+            t: 2.,
+            surface_normal: Vec3 {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            }
+            .unit_vector(),
+            u: 0.,
+            v: 0.,
+        };
+        let expected_ray_out = Ray {
+            origin: Point3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            direction: ray_in.direction.clone(),
+        };
+        let ray_out = glass.scatter_between(&ray_in, &hit, 1.33).unwrap().ray;
+        assert_refracted_or_reflected(&expected_ray_out, &ray_in, &hit, &ray_out);
+    }
+
+    #[test]
+    fn medium_stack_test() {
+        let water = Medium {
+            eta: 1.33,
+            priority: 0,
+        };
+        let glass = Medium {
+            eta: 1.5,
+            priority: 0,
+        };
+        let air = Medium {
+            eta: 1.,
+            priority: 0,
+        };
+
+        // Glass submerged in water:
+        let mut media = MediumStack::new();
+        assert_eq!(VACUUM_ETA, media.eta_outside(1, true));
+        media.enter(1, water.clone());
+        assert_eq!(1.33, media.eta_outside(2, true));
+        media.enter(2, glass.clone());
+        assert_eq!(1.33, media.eta_outside(2, false));
+        media.leave(2);
+        assert_eq!(VACUUM_ETA, media.eta_outside(1, false));
+        media.leave(1);
+        assert_eq!(MediumStack::new(), media);
+
+        // An air bubble in glass:
+        let mut media = MediumStack::new();
+        media.enter(2, glass.clone());
+        assert_eq!(1.5, media.eta_outside(3, true));
+        media.enter(3, air);
+        assert_eq!(1.5, media.eta_outside(3, false));
+        media.leave(3);
+        assert_eq!(VACUUM_ETA, media.eta_outside(2, false));
+
+        // Water filling a glass, whose boundary inside the glass is ignored:
+        let glass = Medium {
+            eta: 1.5,
+            priority: 1,
+        };
+        let mut media = MediumStack::new();
+        assert!(!media.is_false_hit(2, &glass, true));
+        media.enter(2, glass.clone());
+        assert!(media.is_false_hit(1, &water, true));
+        media.enter(1, water.clone());
+        assert!(!media.is_false_hit(2, &glass, false));
+        assert_eq!(1.33, media.eta_outside(2, false));
+        media.leave(2);
+        assert!(!media.is_false_hit(1, &water, false));
+        assert_eq!(VACUUM_ETA, media.eta_outside(1, false));
+
+        // Leaving a medium the ray has not entered does nothing:
+        media.leave(3);
+        assert_eq!(1.33, media.eta_outside(3, true));
+    }
+
+    #[test]
+    fn sphere_hit_properties() {
+        for_all_cases(|rng| {
+            let sphere = Sphere {
+                center: arbitrary::point3(rng, 10.),
+                radius: rng.gen_range(0.1..5.),
+                material: create_dummy_material(),
+            };
+            let origin = arbitrary::point3(rng, 20.);
+            let target = arbitrary::point3(rng, 10.);
+            if (&origin - &sphere.center).length() <= sphere.radius || origin == target {
+                return;
+            }
+            let ray = Ray {
+                direction: (&target - &origin).unit_vector(),
+                origin,
+            };
+            if let Some((hit, _)) = sphere.hit(&ray, &forward()) {
+                assert!(forward().contains(hit.t));
+                // The point is on the surface, and the normal points outward from it.
+                let offset = ray.at(hit.t) - &sphere.center;
+                assert_approx_eq!(sphere.radius, offset.length(), DEFAULT_EPSILON * 100.);
+                assert_approx_eq!(
+                    offset.unit_vector(),
+                    hit.surface_normal,
+                    DEFAULT_EPSILON * 100.
+                );
+                // Rays from the outside hit the front side first.
+                assert!(hit.surface_normal.inner_product(&ray.direction) <= 0.);
+            }
+        });
+    }
+
+    fn arbitrary_ray_and_hit(rng: &mut rand::rngs::StdRng) -> (Ray, HitRecord) {
+        let ray = Ray {
+            origin: arbitrary::point3(rng, 10.),
+            direction: arbitrary::unit_vec3(rng),
+        };
+        let hit = HitRecord {
+            t: rng.gen_range(0.1..10.),
+            surface_normal: arbitrary::unit_vec3(rng),
+            u: rng.gen_range(0. ..1.),
+            v: rng.gen_range(0. ..1.),
+        };
+        (ray, hit)
+    }
+
+    #[test]
+    fn lambertian_scatter_properties() {
+        for_all_cases(|rng| {
+            let (ray_in, hit) = arbitrary_ray_and_hit(rng);
+            let material = Lambertian {
+                albedo: make_dummy_attenuation(),
+            };
+            let ray_out = material.scatter(&ray_in, &hit).unwrap().ray;
+            assert_leaving(&ray_in.at(hit.t), &hit, &ray_out);
+            assert_approx_eq!(1., ray_out.direction.inject().length());
+            // Diffuse reflection never goes into the surface.
+            assert!(ray_out.direction.inner_product(&hit.surface_normal) >= -DEFAULT_EPSILON);
+        });
+    }
+
+    #[test]
+    fn metal_scatter_properties() {
+        for_all_cases(|rng| {
+            let (ray_in, hit) = arbitrary_ray_and_hit(rng);
+            let material = Metal {
+                albedo: make_dummy_attenuation(),
+                fuzz: 0.,
+            };
+            let ray_out = material.scatter(&ray_in, &hit).unwrap().ray;
+            assert_leaving(&ray_in.at(hit.t), &hit, &ray_out);
+            // Without fuzz, metals perform the regular reflection.
+            assert_approx_eq!(
+                reflect_vector(&ray_in.direction, &hit.surface_normal),
+                ray_out.direction
+            );
+
+            // With fuzz, the reflection stays on the side of the regular reflection.
+            let material = Metal {
+                albedo: make_dummy_attenuation(),
+                fuzz: rng.gen_range(0. ..=1.),
+            };
+            let reflected = reflect_vector(&ray_in.direction, &hit.surface_normal);
+            let side = reflected.inner_product(&hit.surface_normal);
+            let ray_out = material.scatter(&ray_in, &hit).unwrap().ray;
+            assert!(ray_out.direction.inner_product(&hit.surface_normal) * side >= 0.);
+        });
+    }
+
+    #[test]
+    fn metal_validate_test() {
+        let metal = |fuzz: Float| Metal {
+            albedo: make_dummy_attenuation(),
+            fuzz,
+        };
+        assert!(metal(0.).validate().is_empty());
+        assert!(metal(1.).validate().is_empty());
+        assert_eq!(1, metal(-0.1).validate().len());
+        assert_eq!(1, metal(1.5).validate().len());
+        assert_eq!(1, metal(Float::NAN).validate().len());
+    }
+
+    #[test]
+    fn shadow_catcher_validate_test() {
+        let catcher = |reflectance: Float| ShadowCatcher { reflectance };
+        assert!(catcher(0.).validate().is_empty());
+        assert!(catcher(1.).validate().is_empty());
+        assert_eq!(1, catcher(1.5).validate().len());
+        assert_eq!(1, catcher(Float::NAN).validate().len());
+        assert_eq!(Some(0.5), catcher(0.5).catcher_reflectance());
+    }
+
+    #[test]
+    fn toon_shading_test() {
+        let toon = Toon {
+            albedo: Attenuation {
+                r: 0.9,
+                g: 0.6,
+                b: 0.3,
+            },
+            bands: 3,
+            light_direction: Vec3 {
+                x: 0.,
+                y: 2.,
                 z: 0.,
+            },
+        };
+        let ray_in = Ray {
+            origin: Point3 {
+                x: 0.,
+                y: 0.,
+                z: 5.,
+            },
+            direction: Vec3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            }
+            .unit_vector(),
+        };
+        // The shades of the normals at the given angles from the light, or from the side facing the ray.
+        let shown_at = |degree: Float| {
+            let angle = degree.to_radians();
+            let hit = HitRecord {
+                t: 5.,
+                surface_normal: Vec3 {
+                    x: angle.sin(),
+                    y: angle.cos(),
+                    z: 0.,
+                }
+                .unit_vector(),
+                u: 0.,
+                v: 0.,
+            };
+            toon.shown(&ray_in, &hit).g / 0.6
+        };
+        assert_approx_eq!(1., shown_at(0.));
+        assert_approx_eq!(1., shown_at(45.));
+        assert_approx_eq!(2. / 3., shown_at(60.));
+        assert_approx_eq!(1. / 3., shown_at(80.));
+        assert_approx_eq!(1. / 3., shown_at(135.));
+        // Toon materials neither reflect nor are lights.
+        let hit = HitRecord {
+            t: 5.,
+            surface_normal: Vec3 {
+                x: 0.,
+                y: 0.,
+                z: 1.,
             }
             .unit_vector(),
+            u: 0.,
+            v: 0.,
+        };
+        assert!(toon.scatter(&ray_in, &hit).is_none());
+        assert_eq!(0., toon.emitted(&hit).r);
+
+        assert!(toon.validate().is_empty());
+        assert_eq!(
+            1,
+            Toon {
+                bands: 0,
+                ..toon.clone()
+            }
+            .validate()
+            .len()
+        );
+        let no_direction = Toon {
+            light_direction: Vec3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            ..toon.clone()
         };
-        let (_attenuation, ray_out) = glass.scatter(&ray_in, &hit);
-        assert_eq!(expected_ray_out, ray_out);
+        assert_eq!(1, no_direction.validate().len());
+    }
+
+    #[test]
+    fn glass_scatter_properties() {
+        for_all_cases(|rng| {
+            let (ray_in, hit) = arbitrary_ray_and_hit(rng);
+            let glass = Glass {
+                eta: rng.gen_range(1. ..3.),
+                albedo: make_dummy_attenuation(),
+                priority: 0,
+            };
+            let ray_out = glass.scatter(&ray_in, &hit).unwrap().ray;
+            assert_leaving(&ray_in.at(hit.t), &hit, &ray_out);
+            assert_approx_eq!(1., ray_out.direction.inject().length());
+            let side_in = ray_in.direction.inner_product(&hit.surface_normal);
+            let side_out = ray_out.direction.inner_product(&hit.surface_normal);
+            if side_in * side_out < 0. {
+                // Turning back to the side the ray came from means the regular reflection.
+                assert_approx_eq!(
+                    reflect_vector(&ray_in.direction, &hit.surface_normal),
+                    ray_out.direction
+                );
+            }
+        });
     }
 }
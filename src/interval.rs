@@ -0,0 +1,65 @@
+use crate::geometry::Float;
+
+/// The type for closed intervals of real numbers, e.g. the range of `t` along rays.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Interval {
+    pub min: Float,
+    pub max: Float,
+}
+impl Interval {
+    /// The interval that contains nothing.
+    pub const EMPTY: Interval = Interval {
+        min: Float::INFINITY,
+        max: Float::NEG_INFINITY,
+    };
+
+    /// The interval that contains everything.
+    pub const UNIVERSE: Interval = Interval {
+        min: Float::NEG_INFINITY,
+        max: Float::INFINITY,
+    };
+
+    pub fn size(&self) -> Float {
+        self.max - self.min
+    }
+
+    /// Checks that `x` is in the interval, including both ends.
+    pub fn contains(&self, x: Float) -> bool {
+        self.min <= x && x <= self.max
+    }
+
+    /// Checks that `x` is in the interval, excluding both ends.
+    pub fn surrounds(&self, x: Float) -> bool {
+        self.min < x && x < self.max
+    }
+
+    pub fn clamp(&self, x: Float) -> Float {
+        x.clamp(self.min, self.max)
+    }
+
+    /// Returns the interval widened by `delta / 2` on each side.
+    pub fn expand(&self, delta: Float) -> Interval {
+        let padding = delta / 2.;
+        Interval {
+            min: self.min - padding,
+            max: self.max + padding,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interval_tests() {
+        let interval = Interval { min: 1., max: 3. };
+        assert_eq!(2., interval.size());
+        assert!(interval.contains(1.) && interval.contains(3.));
+        assert!(!interval.surrounds(1.) && interval.surrounds(2.));
+        assert_eq!(3., interval.clamp(5.));
+        assert_eq!(Interval { min: 0.5, max: 3.5 }, interval.expand(1.));
+        assert!(!Interval::EMPTY.contains(0.));
+        assert!(Interval::UNIVERSE.surrounds(Float::MAX));
+    }
+}
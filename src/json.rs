@@ -0,0 +1,400 @@
+use std::fmt;
+
+/// The type for JSON values, which are enough for the small documents exchanged with the server.
+/// Objects keep the order of their members.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+impl Json {
+    /// Parses a JSON document, where nothing but whitespace may follow the value.
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser {
+            chars: text.char_indices().peekable(),
+            text,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        match parser.chars.next() {
+            None => Ok(value),
+            Some((position, _)) => Err(format!("unexpected trailing text at {}", position)),
+        }
+    }
+
+    /// Returns the member `key` if `self` is an object that has it.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    /// Returns the number if it is a non-negative integer, which JSON does not distinguish from others.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Json::Number(x) if x.fract() == 0. && (0. ..=u64::MAX as f64).contains(x) => {
+                Some(*x as u64)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Returns the form indented by two spaces for files edited by hand,
+    /// where arrays of numbers and other non-containers, e.g. coordinates, stay on a line.
+    pub fn pretty(&self) -> String {
+        let mut out = String::new();
+        self.write_pretty(&mut out, 0);
+        out
+    }
+
+    fn write_pretty(&self, out: &mut String, indent: usize) {
+        let is_container = |value: &Json| matches!(value, Json::Array(_) | Json::Object(_));
+        let break_line = |out: &mut String, indent: usize| {
+            out.push('\n');
+            out.push_str(&"  ".repeat(indent));
+        };
+        match self {
+            Json::Array(elements) if elements.iter().any(is_container) => {
+                out.push('[');
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    break_line(out, indent + 1);
+                    element.write_pretty(out, indent + 1);
+                }
+                break_line(out, indent);
+                out.push(']');
+            }
+            Json::Array(elements) => {
+                let elements: Vec<String> = elements.iter().map(Json::to_string).collect();
+                out.push_str(&format!("[{}]", elements.join(", ")));
+            }
+            Json::Object(members) if !members.is_empty() => {
+                out.push('{');
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    break_line(out, indent + 1);
+                    out.push_str(&format!("{}: ", Json::String(key.clone())));
+                    value.write_pretty(out, indent + 1);
+                }
+                break_line(out, indent);
+                out.push('}');
+            }
+            value => out.push_str(&value.to_string()),
+        }
+    }
+}
+
+/// Writes the compact form, where non-finite numbers, which JSON cannot express, become `null`.
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(x) if x.is_finite() => write!(f, "{}", x),
+            Json::Number(_) => write!(f, "null"),
+            Json::String(s) => write_string(f, s),
+            Json::Array(elements) => {
+                write!(f, "[")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    text: &'a str,
+}
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self
+            .chars
+            .next_if(|(_, c)| matches!(c, ' ' | '\t' | '\n' | '\r'))
+            .is_some()
+        {}
+    }
+
+    fn error(&mut self, expected: &str) -> String {
+        match self.chars.peek() {
+            Some((position, c)) => format!("expected {} at {}, found {:?}", expected, position, c),
+            None => format!("expected {}, found the end", expected),
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.chars.next_if(|(_, c)| *c == expected) {
+            Some(_) => Ok(()),
+            None => Err(self.error(&format!("{:?}", expected))),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str, value: Json) -> Result<Json, String> {
+        for expected in keyword.chars() {
+            if self.chars.next_if(|(_, c)| *c == expected).is_none() {
+                return Err(self.error(keyword));
+            }
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.chars.peek().map(|(_, c)| *c) {
+            Some('n') => self.keyword("null", Json::Null),
+            Some('t') => self.keyword("true", Json::Bool(true)),
+            Some('f') => self.keyword("false", Json::Bool(false)),
+            Some('"') => self.string().map(Json::String),
+            Some('[') => {
+                self.chars.next();
+                let mut elements = vec![];
+                self.skip_whitespace();
+                if self.chars.next_if(|(_, c)| *c == ']').is_some() {
+                    return Ok(Json::Array(elements));
+                }
+                loop {
+                    elements.push(self.value()?);
+                    self.skip_whitespace();
+                    match self.chars.next() {
+                        Some((_, ',')) => {}
+                        Some((_, ']')) => return Ok(Json::Array(elements)),
+                        _ => return Err(self.error("',' or ']'")),
+                    }
+                }
+            }
+            Some('{') => {
+                self.chars.next();
+                let mut members = vec![];
+                self.skip_whitespace();
+                if self.chars.next_if(|(_, c)| *c == '}').is_some() {
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    self.expect(':')?;
+                    members.push((key, self.value()?));
+                    self.skip_whitespace();
+                    match self.chars.next() {
+                        Some((_, ',')) => {}
+                        Some((_, '}')) => return Ok(Json::Object(members)),
+                        _ => return Err(self.error("',' or '}'")),
+                    }
+                }
+            }
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            _ => Err(self.error("a value")),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.chars.peek().map_or(self.text.len(), |(i, _)| *i);
+        while self
+            .chars
+            .next_if(|(_, c)| matches!(c, '-' | '+' | '.' | 'e' | 'E' | '0'..='9'))
+            .is_some()
+        {}
+        let end = self.chars.peek().map_or(self.text.len(), |(i, _)| *i);
+        let s = &self.text[start..end];
+        s.parse()
+            .map(Json::Number)
+            .map_err(|_| format!("invalid number at {}: {}", start, s))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        if self.chars.next_if(|(_, c)| *c == '"').is_none() {
+            return Err(self.error("a string"));
+        }
+        let mut s = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '"')) => return Ok(s),
+                Some((_, '\\')) => {
+                    let escaped = match self.chars.next() {
+                        Some((_, 'n')) => '\n',
+                        Some((_, 't')) => '\t',
+                        Some((_, 'r')) => '\r',
+                        Some((_, 'b')) => '\u{8}',
+                        Some((_, 'f')) => '\u{c}',
+                        Some((_, 'u')) => self.unicode_escape()?,
+                        Some((_, c @ ('"' | '\\' | '/'))) => c,
+                        _ => return Err(self.error("an escape sequence")),
+                    };
+                    s.push(escaped);
+                }
+                Some((_, c)) => s.push(c),
+                None => return Err("unterminated string".to_string()),
+            }
+        }
+    }
+
+    /// Reads the hexadecimal digits after `\u`, combining surrogate pairs.
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.hex4()?;
+        let code = if (0xd800..0xdc00).contains(&high) {
+            if self.chars.next().map(|(_, c)| c) != Some('\\')
+                || self.chars.next().map(|(_, c)| c) != Some('u')
+            {
+                return Err("unpaired surrogate".to_string());
+            }
+            let low = self.hex4()?;
+            0x10000 + ((high - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or_else(|| format!("invalid character: U+{:X}", code))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits: String = (0..4)
+            .filter_map(|_| self.chars.next())
+            .map(|(_, c)| c)
+            .collect();
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("invalid escape: \\u{}", digits));
+        }
+        u32::from_str_radix(&digits, 16).map_err(|_| format!("invalid escape: \\u{}", digits))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_tests() {
+        let json = Json::parse(
+            r#" {"scene": "cornell-box", "width": 64, "seed": 1e3,
+            "tags": [true, false, null], "name": "a\"bé😀"} "#,
+        )
+        .unwrap();
+        assert_eq!(
+            Some("cornell-box"),
+            json.get("scene").and_then(Json::as_str)
+        );
+        assert_eq!(Some(64), json.get("width").and_then(Json::as_u64));
+        assert_eq!(Some(1000), json.get("seed").and_then(Json::as_u64));
+        assert_eq!(
+            Some(&Json::Array(vec![
+                Json::Bool(true),
+                Json::Bool(false),
+                Json::Null
+            ])),
+            json.get("tags")
+        );
+        assert_eq!(
+            Some("a\"b\u{e9}\u{1f600}"),
+            json.get("name").and_then(Json::as_str)
+        );
+        assert_eq!(None, Json::Number(-1.).as_u64());
+        assert_eq!(None, Json::Number(1.5).as_u64());
+
+        assert!(Json::parse("{\"a\": 1,}").is_err());
+        assert!(Json::parse("[1 2]").is_err());
+        assert!(Json::parse("\"unterminated").is_err());
+        assert!(Json::parse("1 2").is_err());
+        assert!(Json::parse("nul").is_err());
+    }
+
+    #[test]
+    fn display_tests() {
+        let json = Json::Object(vec![
+            ("id".to_string(), Json::Number(3.)),
+            ("progress".to_string(), Json::Number(0.25)),
+            (
+                "error".to_string(),
+                Json::String("line 1:\t\"x\"".to_string()),
+            ),
+            (
+                "list".to_string(),
+                Json::Array(vec![Json::Null, Json::Number(f64::NAN)]),
+            ),
+        ]);
+        let text = json.to_string();
+        assert_eq!(
+            r#"{"id":3,"progress":0.25,"error":"line 1:\t\"x\"","list":[null,null]}"#,
+            text
+        );
+        assert_eq!(
+            Json::String("line 1:\t\"x\"".to_string()),
+            *Json::parse(&text).unwrap().get("error").unwrap()
+        );
+    }
+
+    #[test]
+    fn pretty_tests() {
+        let json = Json::Object(vec![
+            ("name".to_string(), Json::String("a".to_string())),
+            (
+                "points".to_string(),
+                Json::Array(vec![
+                    Json::Array(vec![Json::Number(0.), Json::Number(1.5)]),
+                    Json::Array(vec![]),
+                ]),
+            ),
+            ("empty".to_string(), Json::Object(vec![])),
+        ]);
+        let text = json.pretty();
+        assert_eq!(
+            "{\n  \"name\": \"a\",\n  \"points\": [\n    [0, 1.5],\n    []\n  ],\n  \"empty\": {}\n}",
+            text
+        );
+        assert_eq!(json, Json::parse(&text).unwrap());
+    }
+}
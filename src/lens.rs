@@ -0,0 +1,259 @@
+use crate::color::Color;
+use crate::film::Film;
+use crate::geometry::Float;
+
+/// The type for the imperfections of real lenses applied to rendered films, which make images
+/// look filmic. Every effect grows from the center of the image to the corners, and `0` turns it off.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LensEffects {
+    /// The lateral chromatic aberration, i.e., how much larger the red image is than the green one
+    /// as a fraction, while the blue image is as much smaller, which fringes edges near the corners.
+    pub chromatic_aberration: Float,
+    /// The natural vignetting by the cos⁴ law, given as the tangent of the angle of the corners
+    /// from the optical axis, which darkens them to `1 / (1 + vignetting²)²`.
+    pub vignetting: Float,
+    /// The standard deviation in pixels of the blur at the corners, where the image falls out of focus
+    /// by the curvature of the field. The blur grows with the square of the distance from the center.
+    pub defocus_falloff: Float,
+}
+impl LensEffects {
+    /// Whether any of the effects are turned on.
+    pub fn is_enabled(&self) -> bool {
+        *self != LensEffects::default()
+    }
+
+    /// Returns an error message if the parameters are out of range.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.chromatic_aberration >= 0. && self.chromatic_aberration < 1.) {
+            return Err("the chromatic aberration must be in [0, 1)".to_string());
+        }
+        if !(self.vignetting.is_finite() && self.vignetting >= 0.) {
+            return Err("the vignetting must be non-negative".to_string());
+        }
+        if !(self.defocus_falloff >= 0. && self.defocus_falloff <= MAX_DEFOCUS_FALLOFF) {
+            return Err(format!(
+                "the defocus falloff must be in [0, {}] pixels",
+                MAX_DEFOCUS_FALLOFF
+            ));
+        }
+        Ok(())
+    }
+
+    /// Applies the effects to the pixels of `film`, which keeps its records such as invalid samples.
+    pub fn apply(&self, film: &mut Film) {
+        if !self.is_enabled() {
+            return;
+        }
+        let image = Image::of(film);
+        let image = if self.defocus_falloff > 0. {
+            self.defocus(&image)
+        } else {
+            image
+        };
+        // Red is magnified and blue is shrunk, so their sources are nearer to and farther from the center.
+        let magnifications = [
+            1. + self.chromatic_aberration,
+            1.,
+            1. - self.chromatic_aberration,
+        ];
+        for y in 0..film.height {
+            for x in 0..film.width {
+                let (dx, dy) = image.offset(x as Float, y as Float);
+                let [r, g, b] = magnifications.map(|magnification| {
+                    let x = image.center_x + dx / magnification;
+                    let y = image.center_y + dy / magnification;
+                    image.bilinear(x, y)
+                });
+                let falloff = 1. + (self.vignetting * image.radius(x as Float, y as Float)).powi(2);
+                let color = Color {
+                    r: r.r,
+                    g: g.g,
+                    b: b.b,
+                };
+                film.set_pixel(x, y, &color.scale(1. / (falloff * falloff)));
+            }
+        }
+    }
+
+    /// Returns `image` blurred by Gaussians growing toward the corners.
+    fn defocus(&self, image: &Image) -> Image {
+        let mut pixels = Vec::with_capacity(image.pixels.len());
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let radius = image.radius(x as Float, y as Float);
+                let sigma = self.defocus_falloff * radius * radius;
+                pixels.push(image.gaussian(x, y, sigma));
+            }
+        }
+        Image { pixels, ..*image }
+    }
+}
+
+/// The maximum of `LensEffects::defocus_falloff`, which bounds the kernels of the blur.
+const MAX_DEFOCUS_FALLOFF: Float = 8.;
+
+/// The type for the pixels of films being processed, where `(0, 0)` is the top-left pixel.
+struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    center_x: Float,
+    center_y: Float,
+    /// The distance from the center to the corners.
+    half_diagonal: Float,
+}
+impl Image {
+    fn of(film: &Film) -> Self {
+        let mut pixels = Vec::with_capacity(film.width * film.height);
+        for y in 0..film.height {
+            for x in 0..film.width {
+                pixels.push(film.pixel(x, y));
+            }
+        }
+        let center_x = (film.width as Float - 1.) / 2.;
+        let center_y = (film.height as Float - 1.) / 2.;
+        Image {
+            width: film.width,
+            height: film.height,
+            pixels,
+            center_x,
+            center_y,
+            half_diagonal: (center_x * center_x + center_y * center_y).sqrt().max(1.),
+        }
+    }
+
+    fn offset(&self, x: Float, y: Float) -> (Float, Float) {
+        (x - self.center_x, y - self.center_y)
+    }
+
+    /// Returns the distance of the point from the center, which is `1` at the corners.
+    fn radius(&self, x: Float, y: Float) -> Float {
+        let (dx, dy) = self.offset(x, y);
+        (dx * dx + dy * dy).sqrt() / self.half_diagonal
+    }
+
+    /// Returns the pixel, where the pixels beyond the edges are those on the edges.
+    fn pixel(&self, x: isize, y: isize) -> &Color {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        &self.pixels[y * self.width + x]
+    }
+
+    /// Returns the color at the point interpolated bilinearly between the centers of the pixels.
+    fn bilinear(&self, x: Float, y: Float) -> Color {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as isize, y0 as isize);
+        let top = self.pixel(x0, y0).blend(fx, self.pixel(x0 + 1, y0));
+        let bottom = self.pixel(x0, y0 + 1).blend(fx, self.pixel(x0 + 1, y0 + 1));
+        top.blend(fy, &bottom)
+    }
+
+    /// Returns the average of the pixels around `(x, y)` weighted by the Gaussian of `sigma`.
+    fn gaussian(&self, x: usize, y: usize, sigma: Float) -> Color {
+        let reach = (2. * sigma).ceil() as isize;
+        if reach == 0 {
+            return self.pixels[y * self.width + x].clone();
+        }
+        let mut sum = Color {
+            r: 0.,
+            g: 0.,
+            b: 0.,
+        };
+        let mut total_weight = 0.;
+        for dy in -reach..=reach {
+            for dx in -reach..=reach {
+                let distance2 = (dx * dx + dy * dy) as Float;
+                let weight = (-distance2 / (2. * sigma * sigma)).exp();
+                let pixel = self.pixel(x as isize + dx, y as isize + dy);
+                sum = sum.add(&pixel.scale(weight));
+                total_weight += weight;
+            }
+        }
+        sum.scale(1. / total_weight)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn film_of(width: usize, height: usize, color: impl Fn(usize, usize) -> Color) -> Film {
+        let mut film = Film::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                film.add_samples(x, y, &color(x, y), 1);
+            }
+        }
+        film
+    }
+
+    #[test]
+    fn lens_effects_tests() {
+        let white = Color {
+            r: 1.,
+            g: 1.,
+            b: 1.,
+        };
+        assert!(!LensEffects::default().is_enabled());
+
+        // Vignetting darkens the corners but not the center.
+        let mut film = film_of(5, 5, |_, _| white.clone());
+        let vignetting = LensEffects {
+            vignetting: 1.,
+            ..LensEffects::default()
+        };
+        vignetting.apply(&mut film);
+        assert_eq!(white, film.pixel(2, 2));
+        let corner = film.pixel(0, 0);
+        assert!((corner.g - 0.25).abs() < 1e-6, "{:?}", corner);
+
+        // A blur keeps uniform images as they are.
+        let mut film = film_of(5, 5, |_, _| white.clone());
+        let defocus = LensEffects {
+            defocus_falloff: 2.,
+            ..LensEffects::default()
+        };
+        defocus.apply(&mut film);
+        let pixel = film.pixel(0, 4);
+        assert!((pixel.r - 1.).abs() < 1e-6, "{:?}", pixel);
+
+        // A white line at the right edge shifts its red outward and its blue inward.
+        let line = |x: usize, _| {
+            if x == 7 {
+                white.clone()
+            } else {
+                Color {
+                    r: 0.,
+                    g: 0.,
+                    b: 0.,
+                }
+            }
+        };
+        let mut film = film_of(9, 3, line);
+        let aberration = LensEffects {
+            chromatic_aberration: 0.2,
+            ..LensEffects::default()
+        };
+        aberration.apply(&mut film);
+        let (inner, outer) = (film.pixel(6, 1), film.pixel(8, 1));
+        assert_eq!((0., 0.), (inner.r, inner.g));
+        assert!(inner.b > 0.);
+        assert_eq!((0., 0.), (outer.b, outer.g));
+        assert!(outer.r > 0.);
+        assert_eq!(1., film.pixel(7, 1).g);
+
+        assert!(LensEffects {
+            chromatic_aberration: 1.,
+            ..LensEffects::default()
+        }
+        .validate()
+        .is_err());
+        assert!(LensEffects {
+            defocus_falloff: Float::NAN,
+            ..LensEffects::default()
+        }
+        .validate()
+        .is_err());
+    }
+}
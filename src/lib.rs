@@ -0,0 +1,50 @@
+mod adaptive;
+pub mod animation;
+pub mod approx;
+#[cfg(test)]
+mod arbitrary;
+pub mod arena;
+pub mod bake;
+pub mod bloom;
+pub mod bvh;
+pub mod camera;
+pub mod color;
+pub mod compare;
+pub mod cubemap;
+pub mod depth;
+pub mod displacement;
+pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod film;
+pub mod geometry;
+mod guiding;
+pub mod hittable_object;
+pub mod interval;
+pub mod json;
+pub mod lens;
+pub mod light;
+pub mod lut;
+pub mod memory;
+pub mod molecule;
+pub mod obj;
+pub mod outline;
+pub mod probe;
+mod progress;
+pub mod render;
+pub mod scene_file;
+pub mod scene_graph;
+pub mod scenes;
+pub mod sweep;
+pub mod texture;
+pub mod tonemap;
+pub mod units;
+#[cfg(feature = "usd")]
+pub mod usd;
+pub mod velocity;
+pub mod volume;
+
+pub use error::RenderError;
+pub use film::{Film, Filter};
+pub use render::{CancellationToken, RenderSettings, Renderer, Tile};
+pub use scenes::{HitInfo, Scene};
@@ -0,0 +1,1009 @@
+use std::sync::Arc;
+
+use crate::color::Color;
+use crate::geometry::{consts, random_double, Float, Onb, Point3, Ray, UnitVec3, Vec3};
+use crate::hittable_object::{sphere_uv, HitRecord, Hittable, SharedMaterial};
+use crate::texture::ImageTexture;
+
+/// The relative difference of `t` within which a ray is regarded as hitting a light
+/// at the point found by intersecting the whole scene.
+const SAME_HIT_TOLERANCE: Float = 1e-3;
+
+/// Returns the light emitted at the middle of the surfaces of `material`,
+/// which stands for the whole surfaces when estimating the power of lights.
+fn typical_emission(material: &SharedMaterial) -> Color {
+    let hit = HitRecord {
+        t: 0.,
+        surface_normal: Vec3 {
+            x: 0.,
+            y: 1.,
+            z: 0.,
+        }
+        .unit_vector(),
+        u: 0.5,
+        v: 0.5,
+    };
+    material.emitted(&hit)
+}
+
+/// Checks that the surfaces of `material` emit light, e.g. to skip making lights of large meshes.
+pub(crate) fn emits(material: &SharedMaterial) -> bool {
+    let emitted = typical_emission(material);
+    emitted.r + emitted.g + emitted.b > 0.
+}
+
+/// The type for the shapes of emitting surfaces that can be sampled as seen from a point.
+#[derive(Clone, Debug)]
+enum Shape {
+    Sphere {
+        center: Point3,
+        radius: Float,
+    },
+    /// The parallelogram spanned by `u` and `v` at the corner `q`, or the triangle of `q`, `q + u`,
+    /// and `q + v` if `is_triangle` is set, whose texture coordinates at those three corners are
+    /// `texture_coordinates` and interpolated linearly as `Mesh` does.
+    Flat {
+        q: Point3,
+        u: Vec3,
+        v: Vec3,
+        is_triangle: bool,
+        texture_coordinates: [(Float, Float); 3],
+    },
+}
+
+/// The type for directions from points toward lights drawn by `Light::sample`.
+#[derive(Clone, Debug)]
+pub struct LightSample {
+    pub direction: UnitVec3,
+    /// The distance from the point to the light along `direction`.
+    pub distance: Float,
+    /// The density of `direction` over solid angle.
+    pub pdf: Float,
+    /// The light emitted toward the point.
+    pub emitted: Color,
+}
+
+/// The type for emitting surfaces that the renderer samples directly
+/// instead of waiting for scattered rays to hit them by chance.
+#[derive(Clone, Debug)]
+pub struct Light {
+    shape: Shape,
+    material: SharedMaterial,
+    bounds: (Point3, Point3),
+    /// The estimated light emitted by the whole surface, which is used as the weight of the light.
+    power: Float,
+}
+impl Light {
+    pub fn sphere(center: Point3, radius: Float, material: SharedMaterial) -> Self {
+        let extent = Vec3 {
+            x: radius,
+            y: radius,
+            z: radius,
+        };
+        let bounds = (&center - &extent, &center + &extent);
+        let area = 4. * consts::PI * radius * radius;
+        Self::new(Shape::Sphere { center, radius }, material, bounds, area)
+    }
+
+    /// Creates the light of the parallelogram spanned by `u` and `v` at the corner `q` as `Quad`.
+    pub fn parallelogram(q: Point3, u: Vec3, v: Vec3, material: SharedMaterial) -> Self {
+        let texture_coordinates = [(0., 0.), (1., 0.), (0., 1.)];
+        Self::flat(q, u, v, false, texture_coordinates, material)
+    }
+
+    /// Creates the light of the triangle `corners` of a `Mesh`,
+    /// whose texture coordinates at the corners are `texture_coordinates`.
+    pub fn triangle(
+        corners: [&Point3; 3],
+        texture_coordinates: [(Float, Float); 3],
+        material: SharedMaterial,
+    ) -> Self {
+        let [a, b, c] = corners;
+        Self::flat(a.clone(), b - a, c - a, true, texture_coordinates, material)
+    }
+
+    fn flat(
+        q: Point3,
+        u: Vec3,
+        v: Vec3,
+        is_triangle: bool,
+        texture_coordinates: [(Float, Float); 3],
+        material: SharedMaterial,
+    ) -> Self {
+        let corners = [&q + &u, &q + &v, &q + &u + &v];
+        let bounds = corners
+            .iter()
+            .fold((q.clone(), q.clone()), |(min, max), corner| {
+                (min.min(corner), max.max(corner))
+            });
+        let area = u.cross_product(&v).length() * if is_triangle { 0.5 } else { 1. };
+        let shape = Shape::Flat {
+            q,
+            u,
+            v,
+            is_triangle,
+            texture_coordinates,
+        };
+        Self::new(shape, material, bounds, area)
+    }
+
+    fn new(shape: Shape, material: SharedMaterial, bounds: (Point3, Point3), area: Float) -> Self {
+        let emitted = typical_emission(&material);
+        let power = (emitted.r + emitted.g + emitted.b) / 3. * area;
+        Light {
+            shape,
+            material,
+            bounds,
+            power: if power.is_finite() { power } else { 0. },
+        }
+    }
+
+    /// Checks that the light emits anything, which is not the case for most materials.
+    pub fn emits(&self) -> bool {
+        self.power > 0.
+    }
+
+    /// Returns the minimum and the maximum corners of the bounding box of the light.
+    pub fn bounds(&self) -> &(Point3, Point3) {
+        &self.bounds
+    }
+
+    /// Returns a random direction from `origin` toward the light,
+    /// or returns `None` if the light cannot be seen from there, e.g. from inside of a sphere.
+    pub fn sample(&self, origin: &Point3) -> Option<LightSample> {
+        match &self.shape {
+            Shape::Sphere { center, radius } => {
+                let to_center = center - origin;
+                let distance_squared = to_center.length_squared();
+                let sin_squared = radius * radius / distance_squared;
+                if sin_squared >= 1. {
+                    return None;
+                }
+                // Directions are drawn uniformly from the cone that the sphere subtends,
+                // where `1 - cos` is computed so that far small spheres do not lose it by rounding.
+                let cos_max = (1. - sin_squared).sqrt();
+                let solid_angle_ratio = sin_squared / (1. + cos_max);
+                let one_minus_cos = (random_double() + 0.5) * solid_angle_ratio;
+                let cos_theta = 1. - one_minus_cos;
+                let sin_theta = (one_minus_cos * (2. - one_minus_cos)).max(0.).sqrt();
+                let phi = 2. * consts::PI * (random_double() + 0.5);
+                let onb = Onb::from_normal(&to_center.unit_vector());
+                let direction = onb
+                    .local(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta)
+                    .unit_vector();
+                // The direction is clamped onto the sphere if rounding errors have moved it off the edge.
+                let distance = distance_squared.sqrt() * cos_theta
+                    - (radius * radius - distance_squared * sin_theta * sin_theta)
+                        .max(0.)
+                        .sqrt();
+                let surface_normal = (origin + &direction * distance - center).unit_vector();
+                let (u, v) = sphere_uv(&surface_normal);
+                let hit = HitRecord {
+                    t: distance,
+                    surface_normal,
+                    u,
+                    v,
+                };
+                Some(LightSample {
+                    direction,
+                    distance,
+                    pdf: 1. / (2. * consts::PI * solid_angle_ratio),
+                    emitted: self.material.emitted(&hit),
+                })
+            }
+            Shape::Flat {
+                q,
+                u,
+                v,
+                is_triangle,
+                texture_coordinates,
+            } => {
+                let (mut alpha, mut beta) = (random_double() + 0.5, random_double() + 0.5);
+                if *is_triangle && alpha + beta > 1. {
+                    // Folds the other half of the parallelogram onto the triangle.
+                    (alpha, beta) = (1. - alpha, 1. - beta);
+                }
+                let to_point = q + &(u * alpha + v * beta) - origin;
+                let distance = to_point.length();
+                let direction = to_point.unit_vector();
+                let pdf = flat_pdf(u, v, *is_triangle, &direction, distance)?;
+                let n = u.cross_product(v);
+                let surface_normal = if n.inner_product(&direction.inject()) < 0. {
+                    n.unit_vector()
+                } else {
+                    (-n).unit_vector()
+                };
+                let (u, v) = interpolate(texture_coordinates, alpha, beta);
+                let hit = HitRecord {
+                    t: distance,
+                    surface_normal,
+                    u,
+                    v,
+                };
+                Some(LightSample {
+                    direction,
+                    distance,
+                    pdf,
+                    emitted: self.material.emitted(&hit),
+                })
+            }
+        }
+    }
+
+    /// Returns the density over solid angle with which `sample` draws the direction of `ray`
+    /// if the ray hits the light at `t`, or returns 0 if it does not.
+    pub fn pdf(&self, ray: &Ray, t: Float) -> Float {
+        let origin = &ray.origin;
+        let is_same_hit =
+            |light_t: Float| (light_t - t).abs() <= SAME_HIT_TOLERANCE * t.abs().max(1.);
+        match &self.shape {
+            Shape::Sphere { center, radius } => {
+                let sin_squared = radius * radius / (center - origin).length_squared();
+                if sin_squared >= 1. {
+                    return 0.;
+                }
+                match sphere_distance(center, *radius, origin, &ray.direction) {
+                    Some(distance) if is_same_hit(distance) => {
+                        let solid_angle_ratio = sin_squared / (1. + (1. - sin_squared).sqrt());
+                        1. / (2. * consts::PI * solid_angle_ratio)
+                    }
+                    _ => 0.,
+                }
+            }
+            Shape::Flat {
+                q,
+                u,
+                v,
+                is_triangle,
+                ..
+            } => {
+                let n = u.cross_product(v);
+                let dir = ray.direction.inject();
+                let denominator = n.inner_product(&dir);
+                if denominator == 0. {
+                    return 0.;
+                }
+                let distance = n.inner_product(&(q - origin)) / denominator;
+                let p = ray.at(distance) - q;
+                let w = &n / n.length_squared();
+                let alpha = w.inner_product(&p.cross_product(v));
+                let beta = w.inner_product(&u.cross_product(&p));
+                let is_inside = alpha >= 0.
+                    && beta >= 0.
+                    && if *is_triangle {
+                        alpha + beta <= 1.
+                    } else {
+                        alpha <= 1. && beta <= 1.
+                    };
+                if !is_inside || !is_same_hit(distance) {
+                    return 0.;
+                }
+                flat_pdf(u, v, *is_triangle, &ray.direction, distance).unwrap_or(0.)
+            }
+        }
+    }
+}
+
+/// Returns the density over solid angle of the directions toward uniformly distributed points
+/// of the parallelogram or the triangle, or `None` if the surface is seen edge-on.
+fn flat_pdf(
+    u: &Vec3,
+    v: &Vec3,
+    is_triangle: bool,
+    direction: &UnitVec3,
+    distance: Float,
+) -> Option<Float> {
+    let n = u.cross_product(v);
+    let area = n.length() * if is_triangle { 0.5 } else { 1. };
+    let cosine = n.unit_vector().inner_product(direction).abs();
+    let pdf = distance * distance / (cosine * area);
+    (pdf.is_finite() && pdf > 0.).then_some(pdf)
+}
+
+/// Returns the distance from `origin` outside the sphere to its near side along `direction`,
+/// or `None` if the sphere is not in that direction.
+fn sphere_distance(
+    center: &Point3,
+    radius: Float,
+    origin: &Point3,
+    direction: &UnitVec3,
+) -> Option<Float> {
+    let v = origin - center;
+    let dir = direction.inject();
+    let b_half = v.inner_product(&dir);
+    // The discriminant is computed from the distance between the center and the line,
+    // which loses less to rounding than the usual formula for far spheres.
+    let discriminant_quarter = radius * radius - (&v - &dir * b_half).length_squared();
+    // Grazing rays are kept since rounding errors may make their discriminants slightly negative.
+    if discriminant_quarter < -SAME_HIT_TOLERANCE * radius * radius {
+        return None;
+    }
+    let distance = -b_half - discriminant_quarter.max(0.).sqrt();
+    (distance > 0.).then_some(distance)
+}
+
+fn interpolate(
+    texture_coordinates: &[(Float, Float); 3],
+    alpha: Float,
+    beta: Float,
+) -> (Float, Float) {
+    let [(u0, v0), (u1, v1), (u2, v2)] = *texture_coordinates;
+    let w = 1. - alpha - beta;
+    (
+        w * u0 + alpha * u1 + beta * u2,
+        w * v0 + alpha * v1 + beta * v2,
+    )
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Leaf(usize),
+    /// The indices of the two children in `LightTree::nodes`.
+    Inner(usize, usize),
+}
+
+/// The type for bounding volume hierarchies of lights, which pick lights at random
+/// in proportion to the estimated light they give to each point,
+/// so that near or bright lights are sampled more often than the many others.
+#[derive(Clone, Debug, Default)]
+pub struct LightTree {
+    lights: Vec<Light>,
+    /// The nodes with their bounding boxes and total power, where the root is the last one.
+    nodes: Vec<((Point3, Point3), Float, Node)>,
+}
+impl LightTree {
+    pub fn new(lights: Vec<Light>) -> Self {
+        let mut tree = LightTree {
+            lights: vec![],
+            nodes: vec![],
+        };
+        let lights: Vec<Light> = lights.into_iter().filter(|light| light.emits()).collect();
+        let mut indices: Vec<usize> = (0..lights.len()).collect();
+        tree.lights = lights;
+        if !indices.is_empty() {
+            tree.build(&mut indices);
+        }
+        tree
+    }
+
+    /// Collects the lights of all the objects in `world`.
+    pub fn from_world(world: &dyn Hittable) -> Self {
+        let mut lights = vec![];
+        world.collect_lights(&mut lights);
+        Self::new(lights)
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    /// Adds the nodes for the lights of `indices`, splitting them at the median along the longest
+    /// axis of their centers, and returns the index of the root of them.
+    fn build(&mut self, indices: &mut [usize]) -> usize {
+        let node = if let [index] = indices {
+            let light = &self.lights[*index];
+            (light.bounds.clone(), light.power, Node::Leaf(*index))
+        } else {
+            let center = |index: &usize| {
+                let (min, max) = &self.lights[*index].bounds;
+                min + &((max - min) * 0.5)
+            };
+            let (min, max) = indices.iter().map(center).fold(
+                (center(&indices[0]), center(&indices[0])),
+                |(min, max), center| (min.min(&center), max.max(&center)),
+            );
+            let extent = max - min;
+            let axis = (0..3)
+                .max_by(|&a, &b| extent[a].total_cmp(&extent[b]))
+                .unwrap_or(0);
+            indices.sort_by(|a, b| center(a)[axis].total_cmp(&center(b)[axis]));
+            let (left, right) = indices.split_at_mut(indices.len() / 2);
+            let left = self.build(left);
+            let right = self.build(right);
+            let ((left_min, left_max), left_power, _) = &self.nodes[left];
+            let ((right_min, right_max), right_power, _) = &self.nodes[right];
+            let bounds = (left_min.min(right_min), left_max.max(right_max));
+            (bounds, left_power + right_power, Node::Inner(left, right))
+        };
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    /// Returns the estimated light that the lights of the `index`-th node give to `point`
+    /// on a surface facing `normal`, which is 0 if the lights are all behind the surface.
+    fn importance(&self, index: usize, point: &Point3, normal: &UnitVec3) -> Float {
+        let ((min, max), power, _) = &self.nodes[index];
+        let normal = normal.inject();
+        let is_in_front = (0..8).any(|corner| {
+            let corner = Point3 {
+                x: if corner & 1 == 0 { min.x } else { max.x },
+                y: if corner & 2 == 0 { min.y } else { max.y },
+                z: if corner & 4 == 0 { min.z } else { max.z },
+            };
+            (corner - point).inner_product(&normal) > 0.
+        });
+        if !is_in_front {
+            return 0.;
+        }
+        // The distance is not taken shorter than the size of the box
+        // so that points near or inside it do not make the lights infinitely important.
+        let half_diagonal = (max - min) * 0.5;
+        let center = min + &half_diagonal;
+        power
+            / (center - point)
+                .length_squared()
+                .max(half_diagonal.length_squared())
+    }
+
+    /// Returns the probability of going down to the left child of the `index`-th node.
+    fn left_probability(
+        &self,
+        left: usize,
+        right: usize,
+        point: &Point3,
+        normal: &UnitVec3,
+    ) -> Float {
+        let left_importance = self.importance(left, point, normal);
+        let right_importance = self.importance(right, point, normal);
+        let total = left_importance + right_importance;
+        if total > 0. {
+            left_importance / total
+        } else {
+            0.5
+        }
+    }
+
+    /// Picks a light for `point` on a surface facing `normal` and returns a direction toward it,
+    /// where the density of the direction includes the probability of picking the light.
+    /// Returns `None` if there are no lights in front of the surface.
+    pub fn sample(&self, point: &Point3, normal: &UnitVec3) -> Option<LightSample> {
+        let mut index = self.nodes.len().checked_sub(1)?;
+        if self.importance(index, point, normal) == 0. {
+            return None;
+        }
+        let mut probability = 1.;
+        // A single random number is reused along the way down by rescaling it at each node.
+        let mut r = random_double() + 0.5;
+        loop {
+            match &self.nodes[index].2 {
+                Node::Leaf(light) => {
+                    let mut sample = self.lights[*light].sample(point)?;
+                    sample.pdf *= probability;
+                    return Some(sample);
+                }
+                Node::Inner(left, right) => {
+                    let left_probability = self.left_probability(*left, *right, point, normal);
+                    if r < left_probability {
+                        r /= left_probability;
+                        probability *= left_probability;
+                        index = *left;
+                    } else {
+                        r = (r - left_probability) / (1. - left_probability);
+                        probability *= 1. - left_probability;
+                        index = *right;
+                    }
+                    if probability == 0. {
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the density with which `sample` draws the direction of `ray`
+    /// from its origin on a surface facing `normal` if the ray hits some lights at `t`.
+    pub fn pdf(&self, ray: &Ray, normal: &UnitVec3, t: Float) -> Float {
+        let Some(root) = self.nodes.len().checked_sub(1) else {
+            return 0.;
+        };
+        let point = &ray.origin;
+        if self.importance(root, point, normal) == 0. {
+            return 0.;
+        }
+        let hit_point = ray.at(t);
+        let margin = SAME_HIT_TOLERANCE * t.abs().max(1.);
+        let mut pdf = 0.;
+        let mut stack = vec![(root, 1.)];
+        while let Some((index, probability)) = stack.pop() {
+            let ((min, max), _, node) = &self.nodes[index];
+            let is_in_bounds = (0..3).all(|axis| {
+                min[axis] - margin <= hit_point[axis] && hit_point[axis] <= max[axis] + margin
+            });
+            if !is_in_bounds {
+                continue;
+            }
+            match node {
+                Node::Leaf(light) => pdf += probability * self.lights[*light].pdf(ray, t),
+                Node::Inner(left, right) => {
+                    let left_probability = self.left_probability(*left, *right, point, normal);
+                    stack.push((*left, probability * left_probability));
+                    stack.push((*right, probability * (1. - left_probability)));
+                }
+            }
+        }
+        pdf
+    }
+}
+
+/// Returns the texture coordinates of the equirectangular environment maps for `direction`,
+/// where the center of the image is the direction -z, the right edge is +x at the middle,
+/// and the top edge is +y.
+pub fn environment_uv(direction: &UnitVec3) -> (Float, Float) {
+    let d = direction.inject();
+    let phi = d.x.atan2(-d.z);
+    let elevation = d.y.clamp(-1., 1.).asin();
+    (0.5 + phi / (2. * consts::PI), 0.5 + elevation / consts::PI)
+}
+
+/// Returns the direction at the texture coordinates `(u, v)` of the equirectangular
+/// environment maps, which is the inverse of `environment_uv`.
+fn environment_direction(u: Float, v: Float) -> UnitVec3 {
+    let phi = 2. * consts::PI * (u - 0.5);
+    let y = -(consts::PI * v).cos();
+    let r = (consts::PI * v).sin();
+    Vec3 {
+        x: r * phi.sin(),
+        y,
+        z: -r * phi.cos(),
+    }
+    .unit_vector()
+}
+
+/// The type for equirectangular environment maps that the renderer samples directly
+/// by drawing texels in proportion to their luminance times the solid angles they cover,
+/// so that small bright parts of the sky, e.g. the sun, are found by the shadow rays
+/// instead of by the rare scattered rays that happen to hit them.
+#[derive(Clone, Debug)]
+pub struct EnvironmentLight {
+    map: Arc<ImageTexture>,
+    /// The cumulative distribution over the rows from the top.
+    row_cdf: Vec<Float>,
+    /// The cumulative distribution over the texels of each row.
+    column_cdfs: Vec<Vec<Float>>,
+}
+impl EnvironmentLight {
+    /// Returns `None` if the map is black, where there is nothing to sample.
+    pub fn new(map: Arc<ImageTexture>) -> Option<Self> {
+        let (width, height) = (map.width(), map.height());
+        let mut row_weights = Vec::with_capacity(height);
+        let mut column_cdfs = Vec::with_capacity(height);
+        for (y, row) in map.texels().chunks_exact(width).enumerate() {
+            // The solid angles of the texels shrink toward the poles as the sine of the polar angle.
+            let sine = (consts::PI * (y as Float + 0.5) / height as Float).sin();
+            let mut sum = 0.;
+            let mut cdf: Vec<Float> = row
+                .iter()
+                .map(|texel| {
+                    sum += (0.2126 * texel.r + 0.7152 * texel.g + 0.0722 * texel.b).max(0.);
+                    sum
+                })
+                .collect();
+            if sum > 0. {
+                cdf.iter_mut().for_each(|p| *p /= sum);
+            }
+            row_weights.push(sum * sine);
+            column_cdfs.push(cdf);
+        }
+        let total: Float = row_weights.iter().sum();
+        if !(total > 0. && total.is_finite()) {
+            return None;
+        }
+        let mut sum = 0.;
+        let row_cdf = row_weights
+            .iter()
+            .map(|weight| {
+                sum += weight;
+                sum / total
+            })
+            .collect();
+        Some(EnvironmentLight {
+            map,
+            row_cdf,
+            column_cdfs,
+        })
+    }
+
+    /// Returns a random direction toward the environment,
+    /// which is at an infinite distance in every direction.
+    pub fn sample(&self) -> Option<LightSample> {
+        let (width, height) = (self.map.width(), self.map.height());
+        let r = random_double() + 0.5;
+        let y = self.row_cdf.partition_point(|&p| p <= r).min(height - 1);
+        let r = random_double() + 0.5;
+        let x = self.column_cdfs[y]
+            .partition_point(|&p| p <= r)
+            .min(width - 1);
+        let u = (x as Float + random_double() + 0.5) / width as Float;
+        let v = 1. - (y as Float + random_double() + 0.5) / height as Float;
+        let direction = environment_direction(u, v);
+        // The texel is found again from the direction as the background is looked up,
+        // which may be the next one if rounding has moved the direction onto the edge.
+        let (x, y, v) = self.texel_of(&direction);
+        let pdf = self.texel_pdf(x, y, v)?;
+        Some(LightSample {
+            direction,
+            distance: Float::INFINITY,
+            pdf,
+            emitted: self.map.texels()[y * width + x].clone(),
+        })
+    }
+
+    /// Returns the density over solid angle with which `sample` draws `direction`.
+    pub fn pdf(&self, direction: &UnitVec3) -> Float {
+        let (x, y, v) = self.texel_of(direction);
+        self.texel_pdf(x, y, v).unwrap_or(0.)
+    }
+
+    /// Returns the column and the row of the texel in `direction` with the height `v` in the map.
+    fn texel_of(&self, direction: &UnitVec3) -> (usize, usize, Float) {
+        let (width, height) = (self.map.width(), self.map.height());
+        let (u, v) = environment_uv(direction);
+        let x = ((u * width as Float) as usize).min(width - 1);
+        let y = (((1. - v) * height as Float) as usize).min(height - 1);
+        (x, y, v)
+    }
+
+    /// Returns the density over solid angle of the directions drawn from the texel at `(x, y)`
+    /// at the height `v` in the map, or `None` at the poles, where the texels cover no solid angle.
+    fn texel_pdf(&self, x: usize, y: usize, v: Float) -> Option<Float> {
+        let difference = |cdf: &[Float], i: usize| cdf[i] - if i == 0 { 0. } else { cdf[i - 1] };
+        let probability = difference(&self.row_cdf, y) * difference(&self.column_cdfs[y], x);
+        let texels = (self.map.width() * self.map.height()) as Float;
+        // The texture coordinates cover the sphere with the Jacobian `2 * PI^2 * sin(PI * v)`.
+        let jacobian = 2. * consts::PI * consts::PI * (consts::PI * v).sin();
+        let pdf = probability * texels / jacobian;
+        (pdf.is_finite() && pdf > 0.).then_some(pdf)
+    }
+}
+
+/// The type for the sun of the sky, the disk of uniform radiance around `direction` whose
+/// angular radius softens the shadows, which the renderer samples directly as a directional light.
+#[derive(Clone, Debug)]
+pub struct Sun {
+    /// The direction toward the center of the sun.
+    pub direction: UnitVec3,
+    /// The angular radius in radians, which must be positive and less than a right angle.
+    pub angular_radius: Float,
+    pub radiance: Color,
+}
+impl Sun {
+    /// Creates the sun that gives `irradiance` to the surfaces facing it.
+    pub fn from_irradiance(direction: UnitVec3, angular_radius: Float, irradiance: &Color) -> Self {
+        let solid_angle = 2. * consts::PI * Self::one_minus_cos(angular_radius);
+        Sun {
+            direction,
+            angular_radius,
+            radiance: irradiance.scale(1. / solid_angle),
+        }
+    }
+
+    /// Returns `1 - cos(angular_radius)` without losing small suns to rounding.
+    fn one_minus_cos(angular_radius: Float) -> Float {
+        let half_sin = (0.5 * angular_radius).sin();
+        2. * half_sin * half_sin
+    }
+
+    /// Checks that `direction` is within the disk of the sun.
+    pub fn contains(&self, direction: &UnitVec3) -> bool {
+        1. - self.direction.inner_product(direction) <= Self::one_minus_cos(self.angular_radius)
+    }
+
+    /// Returns a random direction within the disk of the sun,
+    /// drawn uniformly from the cone that it subtends.
+    pub fn sample(&self) -> UnitVec3 {
+        let one_minus_cos = (random_double() + 0.5) * Self::one_minus_cos(self.angular_radius);
+        let cos_theta = 1. - one_minus_cos;
+        let sin_theta = (one_minus_cos * (2. - one_minus_cos)).max(0.).sqrt();
+        let phi = 2. * consts::PI * (random_double() + 0.5);
+        Onb::from_normal(&self.direction)
+            .local(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta)
+            .unit_vector()
+    }
+
+    /// Returns the density over solid angle with which `sample` draws `direction`.
+    pub fn pdf(&self, direction: &UnitVec3) -> Float {
+        if self.contains(direction) {
+            1. / (2. * consts::PI * Self::one_minus_cos(self.angular_radius))
+        } else {
+            0.
+        }
+    }
+}
+
+/// The type for the parts of backgrounds that the renderer samples directly as lights at infinity.
+#[derive(Clone, Debug)]
+pub enum BackgroundLight {
+    /// The sun of the sky, where the light of the sky around it is left to the renderer.
+    Sun(Sun),
+    Environment(EnvironmentLight),
+}
+impl BackgroundLight {
+    /// Returns a random direction toward the background, which is at an infinite distance.
+    pub fn sample(&self) -> Option<LightSample> {
+        match self {
+            BackgroundLight::Sun(sun) => {
+                let direction = sun.sample();
+                // Rounding may have moved the direction off the edge of the sun.
+                let pdf = sun.pdf(&direction);
+                (pdf > 0.).then(|| LightSample {
+                    direction,
+                    distance: Float::INFINITY,
+                    pdf,
+                    emitted: sun.radiance.clone(),
+                })
+            }
+            BackgroundLight::Environment(environment) => environment.sample(),
+        }
+    }
+
+    /// Returns the density over solid angle with which `sample` draws `direction`.
+    pub fn pdf(&self, direction: &UnitVec3) -> Float {
+        match self {
+            BackgroundLight::Sun(sun) => sun.pdf(direction),
+            BackgroundLight::Environment(environment) => environment.pdf(direction),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Attenuation;
+    use crate::geometry::seed_random;
+    use crate::hittable_object::{DiffuseLight, HittableList, Lambertian, Quad, Sphere};
+    use std::sync::Arc;
+
+    fn light_material() -> SharedMaterial {
+        Arc::new(DiffuseLight {
+            emit: Color {
+                r: 4.,
+                g: 4.,
+                b: 4.,
+            },
+        })
+    }
+
+    fn point(x: Float, y: Float, z: Float) -> Point3 {
+        Point3 { x, y, z }
+    }
+
+    fn up() -> UnitVec3 {
+        Vec3 {
+            x: 0.,
+            y: 1.,
+            z: 0.,
+        }
+        .unit_vector()
+    }
+
+    #[test]
+    fn light_sample_tests() {
+        seed_random(0);
+        let lights = [
+            Light::sphere(point(0., 3., 0.), 0.5, light_material()),
+            Light::parallelogram(
+                point(-1., 2., -1.),
+                Vec3 {
+                    x: 2.,
+                    y: 0.,
+                    z: 0.,
+                },
+                Vec3 {
+                    x: 0.,
+                    y: 0.5,
+                    z: 2.,
+                },
+                light_material(),
+            ),
+            Light::triangle(
+                [&point(-1., 2., 0.), &point(1., 2., 0.), &point(0., 3., 1.)],
+                [(0., 0.); 3],
+                light_material(),
+            ),
+        ];
+        let origin = point(0.2, 0., 0.1);
+        for light in lights.iter() {
+            // The average of the inverse densities is the solid angle the light subtends.
+            let mut solid_angle = 0.;
+            let num_samples = 20000;
+            for _ in 0..num_samples {
+                let sample = light.sample(&origin).unwrap();
+                assert_eq!(4., sample.emitted.r);
+                let ray = Ray {
+                    origin: origin.clone(),
+                    direction: sample.direction.clone(),
+                };
+                let pdf = light.pdf(&ray, sample.distance);
+                assert!(
+                    (pdf - sample.pdf).abs() <= 1e-3 * sample.pdf,
+                    "{:?}: {} and {}",
+                    light,
+                    pdf,
+                    sample.pdf
+                );
+                solid_angle += 1. / sample.pdf / num_samples as Float;
+            }
+            // Rays hitting the light elsewhere have nothing to do with it.
+            let ray = Ray {
+                origin: origin.clone(),
+                direction: up(),
+            };
+            assert_eq!(0., light.pdf(&ray, 100.));
+            if let Shape::Sphere { center, radius } = &light.shape {
+                let sin_squared = radius * radius / (center - &origin).length_squared();
+                let expected = 2. * consts::PI * (1. - (1. - sin_squared).sqrt());
+                assert!((solid_angle - expected).abs() < 1e-3 * expected);
+            }
+            assert!(solid_angle > 0.);
+        }
+        let inside = point(0., 3.1, 0.);
+        assert!(lights[0].sample(&inside).is_none());
+    }
+
+    #[test]
+    fn light_tree_tests() {
+        seed_random(0);
+        // A string of small lights over the x axis, like LEDs.
+        let num_lights = 100;
+        let world = HittableList {
+            members: (0..num_lights)
+                .map(|index| {
+                    Box::new(Sphere {
+                        center: point(index as Float, 1., 0.),
+                        radius: 0.05,
+                        material: light_material(),
+                    }) as Box<dyn Hittable>
+                })
+                .chain([Box::new(Quad {
+                    q: point(-1., 0., -1.),
+                    u: Vec3 {
+                        x: 200.,
+                        y: 0.,
+                        z: 0.,
+                    },
+                    v: Vec3 {
+                        x: 0.,
+                        y: 0.,
+                        z: 2.,
+                    },
+                    material: Arc::new(Lambertian {
+                        albedo: Attenuation {
+                            r: 0.5,
+                            g: 0.5,
+                            b: 0.5,
+                        },
+                    }),
+                }) as Box<dyn Hittable>])
+                .collect(),
+        };
+        let tree = LightTree::from_world(&world);
+        assert_eq!(num_lights, tree.len());
+
+        // The five lights within the distance 2 along the string are picked far more often
+        // than the 5% of the time that picking lights uniformly would give them.
+        let origin = point(10., 0., 0.);
+        let num_samples = 1000;
+        let mut num_near = 0;
+        for _ in 0..num_samples {
+            let sample = tree.sample(&origin, &up()).unwrap();
+            let ray = Ray {
+                origin: origin.clone(),
+                direction: sample.direction.clone(),
+            };
+            let hit_point = ray.at(sample.distance);
+            if (hit_point.x - origin.x).abs() <= 2.1 {
+                num_near += 1;
+            }
+            let pdf = tree.pdf(&ray, &up(), sample.distance);
+            assert!((pdf - sample.pdf).abs() <= 1e-3 * sample.pdf);
+        }
+        assert!(num_near > num_samples / 4, "{}", num_near);
+
+        // No lights are picked for the surfaces facing away from all of them.
+        assert!(tree.sample(&origin, &-up()).is_none());
+        assert!(LightTree::default().sample(&origin, &up()).is_none());
+    }
+
+    #[test]
+    fn environment_light_tests() {
+        seed_random(0);
+        for (u, v) in [(0.5, 0.5), (0.1, 0.8), (0.9, 0.3)] {
+            let (u2, v2) = environment_uv(&environment_direction(u, v));
+            assert!((u - u2).abs() < 1e-4 && (v - v2).abs() < 1e-4);
+        }
+        let (width, height) = (16, 8);
+        let sky = vec![
+            Attenuation {
+                r: 0.1,
+                g: 0.2,
+                b: 0.4,
+            };
+            width * height
+        ];
+        // A bright sun in the upper half of the sky.
+        let mut sunny = sky.clone();
+        sunny[2 * width + 5] = Attenuation {
+            r: 1000.,
+            g: 1000.,
+            b: 800.,
+        };
+        let num_samples = 20000;
+        for (texels, is_sunny) in [(sky, false), (sunny, true)] {
+            let map = Arc::new(ImageTexture::new(width, height, texels));
+            let environment = EnvironmentLight::new(map).unwrap();
+            let mut solid_angle = 0.;
+            let mut num_sun_samples = 0;
+            for _ in 0..num_samples {
+                // Directions rounded onto the poles, which cover no solid angle, are not drawn.
+                let Some(sample) = environment.sample() else {
+                    continue;
+                };
+                let pdf = environment.pdf(&sample.direction);
+                assert!(
+                    (pdf - sample.pdf).abs() <= 1e-3 * sample.pdf,
+                    "{} and {}",
+                    pdf,
+                    sample.pdf
+                );
+                assert!(sample.distance.is_infinite());
+                if sample.emitted.r == 1000. {
+                    num_sun_samples += 1;
+                }
+                solid_angle += 1. / sample.pdf / num_samples as Float;
+            }
+            if is_sunny {
+                assert!(num_sun_samples > num_samples * 9 / 10);
+            } else {
+                // The average of the inverse densities is the solid angle of the whole sphere.
+                let expected = 4. * consts::PI;
+                assert!((solid_angle - expected).abs() < 0.01 * expected);
+            }
+        }
+
+        let black = Attenuation {
+            r: 0.,
+            g: 0.,
+            b: 0.,
+        };
+        let map = Arc::new(ImageTexture::new(2, 2, vec![black; 4]));
+        assert!(EnvironmentLight::new(map).is_none());
+    }
+
+    #[test]
+    fn sun_tests() {
+        seed_random(0);
+        let direction = Vec3 {
+            x: 1.,
+            y: 2.,
+            z: -1.,
+        }
+        .unit_vector();
+        let irradiance = Color {
+            r: 3.,
+            g: 3.,
+            b: 2.,
+        };
+        let sun = Sun::from_irradiance(direction.clone(), 0.02, &irradiance);
+        let light = BackgroundLight::Sun(sun.clone());
+        // The average of the inverse densities is the solid angle of the sun.
+        let mut solid_angle = 0.;
+        let num_samples = 10000;
+        for _ in 0..num_samples {
+            let sample = light.sample().unwrap();
+            assert!(sun.contains(&sample.direction));
+            assert_eq!(sun.radiance, sample.emitted);
+            assert_eq!(sample.pdf, light.pdf(&sample.direction));
+            solid_angle += 1. / sample.pdf / num_samples as Float;
+        }
+        let expected = 2. * consts::PI * (1. - (0.02 as Float).cos());
+        assert!((solid_angle - expected).abs() < 1e-3 * expected);
+        // The radiance over the small disk adds up to the irradiance.
+        assert!((sun.radiance.r * solid_angle - irradiance.r).abs() < 1e-2);
+        assert!(!sun.contains(&up()));
+        assert_eq!(0., light.pdf(&up()));
+    }
+}
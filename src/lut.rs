@@ -0,0 +1,164 @@
+use std::path::Path;
+
+use crate::color::Color;
+use crate::error::RenderError;
+use crate::geometry::Float;
+
+/// The type for 3D lookup tables in the `.cube` format of Resolve, e.g. the display LUTs
+/// shipped with cameras, which map the encoded colors in the domain to those of the grid
+/// interpolated trilinearly between its points.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lut {
+    /// The number of the points along each axis.
+    size: usize,
+    domain_min: [Float; 3],
+    domain_max: [Float; 3],
+    /// The colors at the points, where red varies fastest and blue slowest.
+    table: Vec<Color>,
+}
+impl Lut {
+    /// Loads the LUT in the `.cube` file at `path`.
+    pub fn load(path: &Path) -> Result<Self, RenderError> {
+        let text = std::fs::read_to_string(path).map_err(|e| RenderError::io(path, e))?;
+        parse_cube(&text).map_err(|message| RenderError::SceneParse {
+            path: path.to_path_buf(),
+            message,
+        })
+    }
+
+    /// Returns the color the LUT maps `color` to, where the colors outside the domain
+    /// are clamped onto it.
+    pub fn apply(&self, color: &Color) -> Color {
+        let last = (self.size - 1) as Float;
+        // The position in the grid along each axis, split into the index and the fraction.
+        let position = |c: Float, axis: usize| {
+            let (min, max) = (self.domain_min[axis], self.domain_max[axis]);
+            let t = ((c - min) / (max - min)).clamp(0., 1.) * last;
+            let i = (t.floor() as usize).min(self.size - 2);
+            (i, t - i as Float)
+        };
+        let (ri, rt) = position(color.r, 0);
+        let (gi, gt) = position(color.g, 1);
+        let (bi, bt) = position(color.b, 2);
+        let at = |r: usize, g: usize, b: usize| &self.table[(b * self.size + g) * self.size + r];
+        let lerp = |a: &Color, b: &Color, t: Float| a.blend(t, b);
+        let along_r = |g: usize, b: usize| lerp(at(ri, g, b), at(ri + 1, g, b), rt);
+        let along_g = |b: usize| lerp(&along_r(gi, b), &along_r(gi + 1, b), gt);
+        lerp(&along_g(bi), &along_g(bi + 1), bt)
+    }
+}
+
+/// Parses the 3D LUT in the `.cube` format: the keywords `TITLE`, `LUT_3D_SIZE`, `DOMAIN_MIN`,
+/// and `DOMAIN_MAX` followed by the colors of the points, one per line, with `#` for comments.
+pub fn parse_cube(text: &str) -> Result<Lut, String> {
+    let mut size = None;
+    let mut domain_min = [0.; 3];
+    let mut domain_max = [1.; 3];
+    let mut table = vec![];
+    let parse_numbers = |words: &[&str], line_number: usize| {
+        let numbers = words
+            .iter()
+            .map(|word| word.parse::<Float>())
+            .collect::<Result<Vec<_>, _>>();
+        match numbers.as_deref() {
+            Ok(&[r, g, b]) if [r, g, b].iter().all(|c| c.is_finite()) => Ok([r, g, b]),
+            _ => Err(format!("line {}: expected three numbers", line_number)),
+        }
+    };
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.split('#').next().unwrap_or("").trim();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            [] => {}
+            ["TITLE", ..] => {}
+            ["LUT_3D_SIZE", n] => match n.parse::<usize>() {
+                Ok(n) if n >= 2 => size = Some(n),
+                _ => return Err(format!("line {}: invalid LUT_3D_SIZE: {}", line_number, n)),
+            },
+            ["LUT_1D_SIZE", ..] => return Err("1D LUTs are not supported".to_string()),
+            ["DOMAIN_MIN", ..] => domain_min = parse_numbers(&words[1..], line_number)?,
+            ["DOMAIN_MAX", ..] => domain_max = parse_numbers(&words[1..], line_number)?,
+            _ => {
+                let [r, g, b] = parse_numbers(&words, line_number)?;
+                table.push(Color { r, g, b });
+            }
+        }
+    }
+    let size = size.ok_or_else(|| "missing LUT_3D_SIZE".to_string())?;
+    if table.len() != size * size * size {
+        return Err(format!(
+            "expected {} colors for LUT_3D_SIZE {}, but found {}",
+            size * size * size,
+            size,
+            table.len()
+        ));
+    }
+    if (0..3).any(|axis| domain_min[axis] >= domain_max[axis]) {
+        return Err("DOMAIN_MIN must be less than DOMAIN_MAX".to_string());
+    }
+    Ok(Lut {
+        size,
+        domain_min,
+        domain_max,
+        table,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx::assert_approx_eq;
+
+    #[test]
+    fn parse_cube_tests() {
+        // The identity but with red and blue swapped.
+        let text = "TITLE \"swap\"\n# red varies fastest\nLUT_3D_SIZE 2\n\
+            0 0 0\n0 0 1\n0 1 0\n0 1 1\n1 0 0\n1 0 1\n1 1 0\n1 1 1\n";
+        let lut = parse_cube(text).unwrap();
+        let color = Color {
+            r: 0.25,
+            g: 0.5,
+            b: 1.,
+        };
+        let mapped = lut.apply(&color);
+        assert_approx_eq!(1., mapped.r);
+        assert_approx_eq!(0.5, mapped.g);
+        assert_approx_eq!(0.25, mapped.b);
+        // The colors outside the domain are clamped.
+        let bright = Color {
+            r: 4.,
+            g: -1.,
+            b: 0.,
+        };
+        assert_eq!(
+            Color {
+                r: 0.,
+                g: 0.,
+                b: 1.,
+            },
+            lut.apply(&bright)
+        );
+
+        // The domain is mapped onto the grid.
+        let halved = format!("DOMAIN_MIN 0 0 0\nDOMAIN_MAX 2 2 2\n{}", text);
+        assert_approx_eq!(0.25, parse_cube(&halved).unwrap().apply(&color).g);
+
+        assert_eq!(
+            Err("missing LUT_3D_SIZE".to_string()),
+            parse_cube("0 0 0\n")
+        );
+        assert_eq!(
+            Err("expected 8 colors for LUT_3D_SIZE 2, but found 1".to_string()),
+            parse_cube("LUT_3D_SIZE 2\n0 0 0\n")
+        );
+        assert_eq!(
+            Err("line 2: expected three numbers".to_string()),
+            parse_cube("LUT_3D_SIZE 2\n0 0\n")
+        );
+        assert_eq!(
+            Err("1D LUTs are not supported".to_string()),
+            parse_cube("LUT_1D_SIZE 16\n")
+        );
+    }
+}
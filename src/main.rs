@@ -1,237 +1,2929 @@
-mod camera;
-mod color;
-mod geometry;
-mod hittable_object;
-
-use camera::Camera;
-use color::{Attenuation, Color};
-use geometry::{random_double, Point3, Ray, Vec3};
-use hittable_object::{Glass, Hittable, HittableList, Lambertian, Metal, Sphere};
-
-fn ray_background_color(ray: &Ray) -> Color {
-    let u = &ray.direction;
-    let t = 0.5 * (u.inject().y + 1.);
-    let white = Color {
-        r: 1.,
-        g: 1.,
-        b: 1.,
-    };
-    let sky = Color {
-        r: 0.5,
-        g: 0.7,
-        b: 1.,
-    };
-    white.blend(t, &sky)
-}
-
-fn ray_color(ray: &Ray, world: &dyn Hittable, diffusion_depth: i32) -> Color {
-    if diffusion_depth <= 0 {
+mod distributed;
+#[cfg(feature = "preview")]
+mod preview;
+mod server;
+#[cfg(feature = "video")]
+mod video;
+
+use distributed::FrameSpec;
+use std::cmp::Reverse;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use try_ray_tracing::bake::{self, SurfacePoint};
+use try_ray_tracing::bloom::Bloom;
+use try_ray_tracing::camera::{Camera, CameraPose, Projection};
+use try_ray_tracing::color::Color;
+use try_ray_tracing::compare::{self, Image};
+use try_ray_tracing::cubemap::{assemble_cross, CubeFace};
+use try_ray_tracing::displacement::{self, HeightMap};
+use try_ray_tracing::error::RenderError;
+use try_ray_tracing::film::{Film, Filter, ImageFormat, ScanLineWriter};
+use try_ray_tracing::geometry::{Float, Point3, Vec3};
+use try_ray_tracing::hittable_object::{Hittable, Mesh, Scattered};
+use try_ray_tracing::json::Json;
+use try_ray_tracing::lens::LensEffects;
+use try_ray_tracing::light::Sun;
+use try_ray_tracing::lut::Lut;
+use try_ray_tracing::memory;
+use try_ray_tracing::molecule::{parse_material_override, Molecule, MoleculeOptions};
+use try_ray_tracing::obj::{self, ObjModel};
+use try_ray_tracing::outline::Outline;
+use try_ray_tracing::probe::LightProbe;
+use try_ray_tracing::render::{
+    render, render_for, Bounce, FocusPick, InvalidSample, ObjectStats, RenderSettings, RenderStats,
+    Renderer, TracedPath,
+};
+use try_ray_tracing::scene_file;
+use try_ray_tracing::scene_graph::LayerSelection;
+use try_ray_tracing::scenes::{
+    self, Background, MaterialOverride, Scene, SceneParams, MAX_SCENE_DENSITY, MAX_SCENE_SCALE,
+    SCENE_NAMES,
+};
+use try_ray_tracing::sweep::{assemble_sheet, sweep_cells, SweepAxis, Swept, SweptMaterials};
+use try_ray_tracing::texture::ImageTexture;
+use try_ray_tracing::tonemap::{ToneCurve, ToneMapping, TransferFunction};
+use try_ray_tracing::units::LengthUnit;
+#[cfg(feature = "usd")]
+use try_ray_tracing::usd::UsdStage;
+use try_ray_tracing::velocity::VelocityMap;
+use try_ray_tracing::volume::VolumeFile;
+
+/// The number of samples per pixel for antialiasing.
+const NUM_SAMPLES_PER_PIXEL: usize = 100;
+
+/// The number of samples per pixel in the watch mode, where quick feedback matters more.
+const WATCH_NUM_SAMPLES_PER_PIXEL: usize = 10;
+
+/// The number of samples per pixel of each pass with `--time-limit`,
+/// which is small so that the first pass, which is always finished, ends early.
+const TIME_LIMIT_NUM_SAMPLES_PER_PIXEL: usize = 4;
+
+/// The number of samples whose paths `debug-pixel` prints, which are few to keep the output readable.
+const DEBUG_PIXEL_NUM_SAMPLES_PER_PIXEL: usize = 4;
+
+/// The number of rays traced from each point of `probes`, which are many since each is a single value.
+const PROBE_NUM_SAMPLES: usize = 4096;
+
+/// The distance between the pixels whose paths `--dump-rays` writes without `debug-pixel`.
+const DUMP_RAYS_PIXEL_STRIDE: usize = 20;
+
+/// The number of the slowest objects and materials that `--stats` lists.
+const MAX_LISTED_STATS: usize = 10;
+
+/// The maximum number of bounces of each ray.
+const MAX_DIFFUSION_DEPTH: i32 = 10;
+
+/// The interval of checking whether the watched file is modified.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The address the server listens on by default, which is reachable only from the same machine.
+const DEFAULT_SERVE_ADDRESS: &str = "127.0.0.1:8080";
+
+/// The address workers listen on by default; pass `--address 0.0.0.0:8081` to accept other machines.
+const DEFAULT_WORKER_ADDRESS: &str = "127.0.0.1:8081";
+
+/// The margin around the objects framed by `--auto-frame` as a fraction of their size.
+const AUTO_FRAME_MARGIN: Float = 0.1;
+
+/// The exit status after Ctrl-C, following the shell convention of 128 + SIGINT.
+const EXIT_CODE_INTERRUPTED: i32 = 130;
+
+/// The number of texels by which `bake` grows the textures past the edges of the layouts,
+/// so that the textures filtered across the seams do not pick up black.
+const BAKE_DILATION: usize = 2;
+
+/// The default angular radius of `--sun` in degrees, about twice that of the real sun,
+/// whose slightly soft shadows look less harsh.
+const DEFAULT_SUN_RADIUS: Float = 0.5;
+
+/// The default number of the scan lines of the bands rendered and written at once by `--stream`.
+const DEFAULT_STREAM_BAND_HEIGHT: usize = 16;
+
+/// The irradiance given by `--sun`, a slightly warm light several times as bright as the sky.
+const SUN_IRRADIANCE: Color = Color {
+    r: 3.,
+    g: 2.85,
+    b: 2.55,
+};
+
+/// Whether the user has pressed Ctrl-C.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Makes Ctrl-C stop the rendering gracefully so that the partial image can be saved.
+/// Pressing Ctrl-C again exits immediately.
+#[cfg(not(target_arch = "wasm32"))]
+fn install_interrupt_handler() {
+    let result = ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(EXIT_CODE_INTERRUPTED);
+        }
+    });
+    if let Err(e) = result {
+        eprintln!("cannot handle Ctrl-C: {}", e);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn install_interrupt_handler() {}
+
+fn usage() -> String {
+    format!(
+        "usage: try_ray_tracing [options] [> <output.ppm>]
+       try_ray_tracing serve [--address <host:port>] [--threads <number>] [--low-priority]
+                            serve the HTTP API for submitting jobs and fetching the images
+                            (default address: {})
+       try_ray_tracing worker [--address <host:port>] [--threads <number>] [--low-priority]
+                            render tiles for the coordinators started with --workers
+                            (default address: {})
+       try_ray_tracing debug-pixel <x> <y> [options]
+                            print the bounces of the paths traced for the pixel
+                            at (<x>, <y>) from the top left, seeded by --seed
+       try_ray_tracing pick-focus <x> <y> [options]
+                            print the distance to the surface seen at the center
+                            of the pixel at (<x>, <y>) from the top left
+       try_ray_tracing depth --output <depth.pfm|depth.png> [--mask <mask.pgm|mask.png>] [options]
+                            write the depth along the view direction of the surface seen at
+                            each pixel (0 for none), as floats or as 16-bit integers scaled
+                            so that the farthest one is white, and the mask of the pixels
+                            that see something
+       try_ray_tracing cubemap <size> --output <file> [--cross] [options]
+                            render the six faces of <size> x <size> pixels seen along the axes
+                            from the camera (or --look-from) to files named e.g. `sky_px.png`
+                            for `--output sky.png`, or with --cross, to a single image of them
+                            laid out as a horizontal cross, for environment maps
+       try_ray_tracing bake <size> --output <file> [options]
+                            bake the light arriving at the front sides of the meshes with
+                            texture coordinates, e.g. of --obj, into <size> x <size> textures
+                            over their layouts, e.g. for lightmaps, written to files named
+                            e.g. `light_0.png` for `--output light.png` if there are several
+       try_ray_tracing probes <x>,<y>,<z> [<x>,<y>,<z> ...] --output <file.json> [options]
+                            capture the irradiance at the points as the coefficients of
+                            the spherical harmonics up to the second band, e.g. for the light
+                            probes of game engines (--samples: the rays per point, default: {})
+       try_ray_tracing sweep <parameter>=<from>..<to>:<steps> [<parameter>=...] [options]
+                            render the scene once for each value of the material parameter
+                            (fuzz of metals or eta of glasses) evenly spaced over the range
+                            and write a contact sheet of the images, whose columns vary the
+                            first parameter and whose rows vary the second if given
+       try_ray_tracing compare <reference> <test> [--ppd <number>] [--output <heatmap.ppm>]
+                            print the RMSE, the PSNR, and the FLIP error between the PPM
+                            or PNG images, and write the heatmap of the FLIP error
+                            (--ppd: the pixels per degree of visual angle, default: {})
+options:
+  --output <file.ppm>       write the image to the file instead of the standard output
+  --scene <{}>
+  --seed <integer>          the seed of the random scenes and of the samples, with which
+                            the images are the same regardless of --threads
+  --density <number>        the number of the small spheres of book-cover per unit area
+                            (default: 1, at most {})
+  --scale <integer>         the size of the stress scenes sphereflake and sphere-grid,
+                            i.e., the levels of the flake and the power of 2 of the spheres
+                            along each side of the grid (default: 4, at most {})
+  --molecule <file.xyz|file.pdb>
+  --molecule-style <ball-and-stick|space-filling|wireframe>
+  --element-material <element>=<lambertian|metal[:<fuzz>]|glass[:<eta>]|toon[:<bands>]>
+  --obj <file.obj>          render the model with the materials in its MTL files
+  --displacement <height.png>
+                            displace the surfaces of --obj by the brightness of the image
+                            at their texture coordinates
+  --displacement-scale <number>
+                            the displacement of the white texels (default: 0.1)
+  --subdivisions <number>   the number of pieces each edge of --obj is divided into for
+                            --displacement (default: 4)
+  --usd <file.usda>         render the meshes in the USD file (requires `--features usd`)
+  --units <mm|cm|m|in|ft>   the unit of the scene, into which --obj and --usd are converted
+                            and in which depth is measured (default: m)
+  --import-units <mm|cm|m|in|ft>
+                            the unit of --obj or --usd (default: --units, or the
+                            metersPerUnit of --usd)
+  --volume <file.vol>       render the density grid in the Mitsuba grid volume file as smoke
+  --extinction <number>     the extinction coefficient per unit density of --volume (default: 1)
+  --scene-file <file.json>  render the scene file written by --export-scene
+  --export-scene <file.json>
+                            write the scene, e.g. a preset with --seed, to a scene file
+                            instead of rendering
+  --environment <map.png|map.pfm>
+                            light the scene by the equirectangular image, e.g. an HDRI, instead
+                            of its background, sampling its bright parts directly (not with --workers)
+  --sun <elevation>,<azimuth>
+                            add the sun to the sky of the scene at the angles in degrees, where the
+                            azimuth turns from -z toward +x, sampling it directly for clean shadows
+                            (not with --workers)
+  --sun-radius <degrees>    the angular radius of --sun, which softens the shadows (default: {})
+  --auto-frame              move the camera along its view direction until it sees all the
+                            objects, e.g. for imported scenes of unknown scale (not with --workers)
+  --little-planet <degrees> replace the camera by one at its position looking straight down through
+                            the stereographic projection with the field of view (less than 360,
+                            e.g. 300), which shows the ground as a little planet amid the sky
+                            (not with --workers)
+  --override-material clay  render every object except the lights in neutral gray to check
+                            the geometry, without changing the scene (not with --workers)
+  --layers <layer>[,<layer>...]
+                            render only the objects in the layers, e.g. `molecule` or `ground`
+  --holdout <layer>[,<layer>...]
+                            render the objects in the layers black while they still hide and
+                            shadow the others, to composite the passes of the other layers
+  --workers <host:port>[,<host:port>...]
+                            split the images into tiles rendered by the workers
+                            (only for preset scenes, and not with --turntable or --watch)
+  --dump-rays <file.obj>    write the paths traced for every {}th pixel, or the pixel of
+                            debug-pixel, to the file as lines instead of rendering
+  --samples <number>        the number of samples per pixel, or per pass with --time-limit
+                            (default: 100, or 10 with --watch and 4 with debug-pixel or --time-limit)
+  --roulette <p1>,<p2>,... trace the rays scattered at the n-th bounce further with probability pn,
+                            e.g. `1,0.5,0.25` to spend less time on deep bounces (default: 1)
+  --time-limit <duration>   render passes until the time (e.g. 90s, 10m, or 1.5h) has elapsed
+                            and write the image of all of them
+  --threads <number>        the number of threads for rendering (default: the number of CPUs)
+  --memory-budget <size>    fail if the estimated memory usage exceeds the size (e.g. 512M or 2G)
+  --width <pixels>          the width of the image, which is 16:9 (default: 400)
+  --stream                  render the image in bands of scan lines from the top (or the bottom
+                            for PFM) and write each to the output as soon as it is finished, e.g.
+                            for pipelines or so that only a band of a huge image is in memory
+                            (only for single images without --workers, whose tiles finish out
+                            of order, --time-limit, --guide, --adaptive, or the effects)
+  --band <rows>             the number of the scan lines of the bands of --stream (default: {})
+  --quiet                   do not report the progress, e.g. the progress bar, the memory estimate,
+                            and the bands, on the standard error, which still reports errors
+                            and warnings
+  --low-priority            render at a lower priority so that other programs stay responsive
+  --check-samples           paint the pixels with NaN or infinite samples magenta and report
+                            the bounces that produced them (slower; not with --workers)
+  --stats                   report the objects and the materials that take the longest
+                            to intersect and shade (slower; not with --workers)
+  --path-lengths <file.pfm> also write the mean and the largest numbers of bounces of the paths
+                            and the fraction terminated by --roulette at each pixel (implies --stats)
+  --guide                   learn where the light comes from while rendering and send the rays
+                            there, for scenes lit indirectly (not with --workers)
+  --adaptive <number>       add the number of samples per pixel to the pixels on edges found
+                            after rendering, for crisp edges with fewer samples
+                            (not with --time-limit or --workers)
+  --filter <box|tent|gaussian|mitchell>
+                            the filter that also counts each sample for the pixels around it
+                            for smoother edges of fine geometry (default: box)
+  --filter-sampling         draw the samples around each pixel as the filter weights them
+                            instead of splatting them into the pixels around them
+  --bloom <intensity>       make the parts brighter than --bloom-threshold glow around them
+                            by the intensity relative to their excess brightness (e.g. 0.5)
+  --bloom-threshold <luminance>
+                            the luminance above which pixels glow (default: 1, i.e., white)
+  --bloom-levels <number>   the number of levels of the glow, each of which doubles
+                            its reach (default: 5)
+  --chromatic-aberration <fraction>
+                            make the red image larger and the blue one smaller by the fraction,
+                            which fringes edges toward the corners (e.g. 0.003)
+  --vignetting <tangent>    darken the corners by the cos^4 law as if they were at the angle
+                            of the tangent from the optical axis (e.g. 0.5)
+  --defocus-falloff <pixels>
+                            blur the corners by the standard deviation in pixels, as if they
+                            were out of focus, growing from the sharp center (e.g. 1.5)
+  --exposure <ev>[,<ev>...] brighten the images by the exposure values, each of which doubles
+                            the brightness (default: 0)
+  --tone-map <linear|reinhard|aces>[,...]
+                            the curve bringing the colors brighter than white into the images
+                            (default: linear, i.e., clipped at white)
+  --transfer <linear|gamma2|gamma2.2|srgb|rec709|logc3|slog3>[,...]
+                            the transfer function encoding the colors in the files (default:
+                            linear for PFM, and gamma2, i.e., the square root, for the others);
+                            with several values of these three, one image is written for each
+                            combination from a single rendering, named e.g. `out_ev+1_aces.png`
+                            for `--output out.png`
+  --lut <file.cube>         apply the 3D LUT to the colors encoded by --transfer, e.g. the LUT
+                            of a camera for logc3 or slog3, and write its results as they are
+  --outline <r>,<g>,<b>     draw lines of the color along the silhouettes, the creases, and the
+                            boundaries between materials, e.g. `0,0,0` for black
+  --look-from <x>,<y>,<z>   the position of the camera of depth or cubemap
+                            (default: that of the scene)
+  --look-at <x>,<y>,<z>     the point the camera of depth looks at (default: that of the scene)
+  --fov <degrees>           the vertical field of view of the camera of depth
+  --orthographic <height>   make the camera of depth orthographic with the viewport height
+  --preview                 show the image in a window while rendering (Esc to abort)
+  --watch                   render the molecule file again whenever it is modified
+                            (requires --molecule and --output)
+  --animate                 render the frames of the animation of the scene to numbered files
+                            (requires --output, e.g. `--output out_####.png`,
+                            or `--output out.mp4` if built with `--features video`)
+  --frames <first>..<last>  the frames to render (default: 1..48)
+  --fps <number>            the frames per second (default: 24)
+  --velocity <file.pfm>     also write the motions of the surfaces at the pixels to the next frame
+                            in pixels to numbered files for --animate of preset scenes
+  --turntable <frames>      render the given number of frames to numbered files
+                            while orbiting the camera 360 degrees around its target
+                            (requires --output)",
+        DEFAULT_SERVE_ADDRESS,
+        DEFAULT_WORKER_ADDRESS,
+        PROBE_NUM_SAMPLES,
+        compare::DEFAULT_PIXELS_PER_DEGREE,
+        SCENE_NAMES.join("|"),
+        MAX_SCENE_DENSITY,
+        MAX_SCENE_SCALE,
+        DEFAULT_SUN_RADIUS,
+        DUMP_RAYS_PIXEL_STRIDE,
+        DEFAULT_STREAM_BAND_HEIGHT
+    )
+}
+
+/// The type for command-line arguments.
+struct Args {
+    scene_name: String,
+    scene_params: SceneParams,
+    /// The seed of the samples, which is given by `--seed` together with that of the scene.
+    render_seed: Option<u64>,
+    /// The molecule file to render instead of the preset scene.
+    molecule_path: Option<PathBuf>,
+    molecule_options: MoleculeOptions,
+    /// The OBJ file to render instead of the preset scene.
+    obj_path: Option<PathBuf>,
+    /// The height texture displacing the surfaces of the OBJ model.
+    displacement_path: Option<PathBuf>,
+    /// The displacement of the white texels of the height texture.
+    displacement_scale: Float,
+    /// The number of pieces each edge of the triangles is divided into for displacement.
+    subdivisions: usize,
+    /// The USD file whose meshes are rendered instead of the preset scene.
+    usd_path: Option<PathBuf>,
+    /// The unit of the scene, into which the imported meshes are converted.
+    units: LengthUnit,
+    /// The unit of the imported meshes if it is given instead of that of the file or the scene.
+    import_units: Option<LengthUnit>,
+    /// The grid volume file to render instead of the preset scene.
+    volume_path: Option<PathBuf>,
+    /// The extinction coefficient per unit density of the grid volume.
+    extinction: Float,
+    /// The scene file to render instead of the preset scene.
+    scene_file_path: Option<PathBuf>,
+    /// The file to write the scene to instead of rendering.
+    export_scene: Option<PathBuf>,
+    /// The equirectangular image replacing the background of the scene.
+    environment_path: Option<PathBuf>,
+    /// The elevation and the azimuth in degrees of the sun added to the sky of the scene.
+    sun: Option<(Float, Float)>,
+    /// The angular radius of the sun in degrees.
+    sun_radius: Float,
+    /// The layers to render and those to render as holdouts.
+    layer_selection: LayerSelection,
+    /// Whether to show the image in a window while rendering it.
+    preview: bool,
+    /// The file to write the image to instead of the standard output.
+    output: Option<PathBuf>,
+    /// Whether to render the molecule file again whenever it is modified.
+    watch: bool,
+    /// Whether to render the frames of the animation.
+    animate: bool,
+    /// The first and the last frame numbers, both inclusive and starting from 1.
+    frames: (u32, u32),
+    fps: f64,
+    /// The numbered files to write the velocity maps of the frames of the animation to.
+    velocity_output: Option<PathBuf>,
+    /// The number of frames of a turntable around the scene, if it is requested.
+    turntable: Option<u32>,
+    /// The number of samples per pixel if it is given instead of the default of the mode.
+    num_samples_per_pixel: Option<usize>,
+    /// The number of threads for rendering; all the CPUs are used if `None`.
+    num_threads: Option<usize>,
+    low_priority: bool,
+    /// The survival probabilities of the rays scattered at each bounce.
+    roulette: Vec<Float>,
+    /// The time to render the image for in passes instead of a fixed number of samples.
+    time_limit: Option<Duration>,
+    /// Whether to look for NaN or infinite samples and report them.
+    check_samples: bool,
+    /// Whether to print the statistics of the objects and the materials after rendering.
+    stats: bool,
+    /// The file to write the lengths of the paths of the pixels to.
+    path_lengths_output: Option<PathBuf>,
+    /// Whether to guide the scattered rays by the light learned while rendering.
+    guide: bool,
+    /// The number of extra samples per pixel for the pixels on edges.
+    adaptive_samples: usize,
+    /// The reconstruction filter of the samples.
+    filter: Filter,
+    /// Whether to draw the samples from the filter instead of splatting them.
+    filter_sampling: bool,
+    /// The glow around the bright parts of the rendered images.
+    bloom: Bloom,
+    /// The imperfections of the lens applied to the rendered images.
+    lens: LensEffects,
+    /// The lines drawn over the rendered images along the edges of the scenes.
+    outline: Option<Outline>,
+    /// The mappings of the rendered films to the written images,
+    /// each of which is written to its own file if there are several.
+    tone_mappings: Vec<ToneMapping>,
+    /// The maximum number of bytes the scene and the film may occupy.
+    memory_budget: Option<usize>,
+    /// Whether to move the camera so that it sees all the objects.
+    auto_frame: bool,
+    /// The field of view of the stereographic camera looking down from that of the scene, if any.
+    little_planet: Option<Float>,
+    /// The material to render the objects in instead of their own.
+    material_override: Option<MaterialOverride>,
+    /// The width of the image in pixels.
+    image_width: usize,
+    /// Whether to write the scan lines of the image as soon as they are finished.
+    stream: bool,
+    /// The number of the scan lines of the bands in which the image is rendered and written
+    /// with `--stream`, which is `None` without it.
+    band_height: Option<usize>,
+    /// Whether to keep the progress off the standard error.
+    quiet: bool,
+    /// The address to serve the HTTP API on instead of rendering, which is set by `serve`.
+    serve_address: Option<String>,
+    /// The address to wait for coordinators on instead of rendering, which is set by `worker`.
+    worker_address: Option<String>,
+    /// The workers (`<host>:<port>`) to render the tiles of the images.
+    workers: Vec<String>,
+    /// The pixel, counted from the top left, whose paths are printed instead of rendering,
+    /// which is set by `debug-pixel`.
+    debug_pixel: Option<(usize, usize)>,
+    /// The pixel, counted from the top left, whose distance to the surface is printed instead of
+    /// rendering, which is set by `pick-focus`.
+    pick_focus: Option<(usize, usize)>,
+    /// Whether to write the depth map instead of rendering, which is set by `depth`.
+    depth: bool,
+    /// The file to write the mask of the pixels seeing something to with `depth`.
+    mask_output: Option<PathBuf>,
+    /// The side length in pixels of the faces of the cubemap rendered instead of the image,
+    /// which is set by `cubemap`.
+    cubemap_size: Option<usize>,
+    /// Whether to write the faces of `cubemap` laid out as a cross to a single file.
+    cross: bool,
+    /// The side length in texels of the textures the light is baked into instead of the image,
+    /// which is set by `bake`.
+    bake_size: Option<usize>,
+    /// The points where the light is captured into light probes instead of the image,
+    /// which are set by `probes`.
+    probe_positions: Vec<Point3>,
+    /// The axes of the material parameters varied by the contact sheet rendered instead of
+    /// the image, which are set by `sweep`.
+    sweep_axes: Vec<SweepAxis>,
+    /// The placement of the camera of `depth`, each part of which defaults to that of the scene.
+    look_from: Option<Point3>,
+    look_at: Option<Point3>,
+    vertical_fov_degree: Option<Float>,
+    /// The viewport height of the orthographic camera of `depth`, which is perspective if `None`.
+    orthographic: Option<Float>,
+    /// The reference and the test images to compare instead of rendering, which are set by `compare`.
+    compare: Option<(PathBuf, PathBuf)>,
+    /// The pixels per degree of visual angle assumed by `compare`.
+    pixels_per_degree: Float,
+    /// The OBJ file to write traced paths to instead of rendering.
+    dump_rays: Option<PathBuf>,
+}
+
+/// Parses the value following the option `option`.
+fn parse_value<T: FromStr>(option: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("{} requires a value", option))?;
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {}: {}", option, value))
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut parsed = Args {
+        scene_name: "methanol".to_string(),
+        scene_params: SceneParams::default(),
+        render_seed: None,
+        molecule_path: None,
+        molecule_options: MoleculeOptions::default(),
+        obj_path: None,
+        displacement_path: None,
+        displacement_scale: 0.1,
+        subdivisions: 4,
+        usd_path: None,
+        units: LengthUnit::default(),
+        import_units: None,
+        volume_path: None,
+        extinction: 1.,
+        scene_file_path: None,
+        export_scene: None,
+        environment_path: None,
+        sun: None,
+        sun_radius: DEFAULT_SUN_RADIUS,
+        layer_selection: LayerSelection::default(),
+        preview: false,
+        output: None,
+        watch: false,
+        animate: false,
+        frames: (1, 48),
+        fps: 24.,
+        velocity_output: None,
+        turntable: None,
+        num_samples_per_pixel: None,
+        num_threads: None,
+        low_priority: false,
+        roulette: vec![],
+        time_limit: None,
+        check_samples: false,
+        stats: false,
+        path_lengths_output: None,
+        guide: false,
+        adaptive_samples: 0,
+        filter: Filter::Box,
+        filter_sampling: false,
+        bloom: Bloom::default(),
+        lens: LensEffects::default(),
+        outline: None,
+        tone_mappings: vec![ToneMapping::default()],
+        memory_budget: None,
+        auto_frame: false,
+        little_planet: None,
+        material_override: None,
+        image_width: 400,
+        stream: false,
+        band_height: None,
+        quiet: false,
+        serve_address: None,
+        worker_address: None,
+        workers: vec![],
+        debug_pixel: None,
+        pick_focus: None,
+        depth: false,
+        cubemap_size: None,
+        bake_size: None,
+        probe_positions: vec![],
+        sweep_axes: vec![],
+        cross: false,
+        mask_output: None,
+        look_from: None,
+        look_at: None,
+        vertical_fov_degree: None,
+        orthographic: None,
+        compare: None,
+        pixels_per_degree: compare::DEFAULT_PIXELS_PER_DEGREE,
+        dump_rays: None,
+    };
+    let mut args = std::env::args().skip(1).peekable();
+    if args.next_if(|arg| arg == "serve").is_some() {
+        parsed.serve_address = Some(DEFAULT_SERVE_ADDRESS.to_string());
+    } else if args.next_if(|arg| arg == "worker").is_some() {
+        parsed.worker_address = Some(DEFAULT_WORKER_ADDRESS.to_string());
+    } else if let Some(arg) = args.next_if(|arg| arg == "debug-pixel") {
+        let x = parse_value(&arg, args.next())?;
+        let y = parse_value(&arg, args.next())?;
+        parsed.debug_pixel = Some((x, y));
+    } else if let Some(arg) = args.next_if(|arg| arg == "pick-focus") {
+        let x = parse_value(&arg, args.next())?;
+        let y = parse_value(&arg, args.next())?;
+        parsed.pick_focus = Some((x, y));
+    } else if args.next_if(|arg| arg == "depth").is_some() {
+        parsed.depth = true;
+    } else if let Some(arg) = args.next_if(|arg| arg == "cubemap") {
+        let size = parse_value(&arg, args.next())?;
+        if size == 0 {
+            return Err("the size of cubemap must be positive".to_string());
+        }
+        parsed.cubemap_size = Some(size);
+    } else if let Some(arg) = args.next_if(|arg| arg == "bake") {
+        let size = parse_value(&arg, args.next())?;
+        if size == 0 {
+            return Err("the size of bake must be positive".to_string());
+        }
+        parsed.bake_size = Some(size);
+    } else if let Some(arg) = args.next_if(|arg| arg == "probes") {
+        while let Some(point) = args.next_if(|arg| !arg.starts_with("--")) {
+            parsed.probe_positions.push(parse_point(&arg, &point)?);
+        }
+        if parsed.probe_positions.is_empty() {
+            return Err("probes requires at least one point".to_string());
+        }
+    } else if args.next_if(|arg| arg == "sweep").is_some() {
+        while let Some(axis) = args.next_if(|arg| !arg.starts_with("--")) {
+            parsed.sweep_axes.push(axis.parse()?);
+        }
+        if !(1..=2).contains(&parsed.sweep_axes.len()) {
+            return Err("sweep requires one or two parameters".to_string());
+        }
+    } else if let Some(arg) = args.next_if(|arg| arg == "compare") {
+        let reference = parse_value(&arg, args.next())?;
+        let test = parse_value(&arg, args.next())?;
+        parsed.compare = Some((reference, test));
+    }
+    let mut exposures = vec![0.];
+    let mut tone_curves = vec![ToneCurve::default()];
+    let mut transfers = vec![None];
+    let mut lut = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--scene" => parsed.scene_name = parse_value(&arg, args.next())?,
+            "--seed" => {
+                parsed.scene_params.seed = parse_value(&arg, args.next())?;
+                parsed.render_seed = Some(parsed.scene_params.seed);
+            }
+            "--density" => {
+                parsed.scene_params.density =
+                    scenes::check_density(parse_value(&arg, args.next())?)?;
+            }
+            "--scale" => {
+                let scale = parse_value(&arg, args.next())?;
+                if scale > MAX_SCENE_SCALE {
+                    return Err(format!("--scale must be at most {}", MAX_SCENE_SCALE));
+                }
+                parsed.scene_params.scale = scale;
+            }
+            "--molecule" => parsed.molecule_path = Some(parse_value(&arg, args.next())?),
+            "--molecule-style" => {
+                parsed.molecule_options.style = parse_value(&arg, args.next())?;
+            }
+            "--element-material" => {
+                let value: String = parse_value(&arg, args.next())?;
+                let (element, kind) = parse_material_override(&value)?;
+                parsed
+                    .molecule_options
+                    .material_overrides
+                    .insert(element, kind);
+            }
+            "--obj" => parsed.obj_path = Some(parse_value(&arg, args.next())?),
+            "--displacement" => parsed.displacement_path = Some(parse_value(&arg, args.next())?),
+            "--displacement-scale" => parsed.displacement_scale = parse_value(&arg, args.next())?,
+            "--subdivisions" => parsed.subdivisions = parse_value(&arg, args.next())?,
+            "--usd" => {
+                if cfg!(feature = "usd") {
+                    parsed.usd_path = Some(parse_value(&arg, args.next())?);
+                } else {
+                    return Err("--usd requires building with `--features usd`".to_string());
+                }
+            }
+            "--units" => parsed.units = parse_value(&arg, args.next())?,
+            "--import-units" => parsed.import_units = Some(parse_value(&arg, args.next())?),
+            "--volume" => parsed.volume_path = Some(parse_value(&arg, args.next())?),
+            "--extinction" => parsed.extinction = parse_value(&arg, args.next())?,
+            "--scene-file" => parsed.scene_file_path = Some(parse_value(&arg, args.next())?),
+            "--export-scene" => parsed.export_scene = Some(parse_value(&arg, args.next())?),
+            "--environment" => parsed.environment_path = Some(parse_value(&arg, args.next())?),
+            "--sun" => {
+                let value: String = parse_value(&arg, args.next())?;
+                parsed.sun = match parse_list(&arg, &value)?[..] {
+                    [elevation, azimuth] => Some((elevation, azimuth)),
+                    _ => {
+                        return Err(format!(
+                            "invalid angles for --sun (expected <elevation>,<azimuth>): {}",
+                            value
+                        ))
+                    }
+                };
+            }
+            "--sun-radius" => parsed.sun_radius = parse_value(&arg, args.next())?,
+            "--auto-frame" => parsed.auto_frame = true,
+            "--little-planet" => {
+                let degree: Float = parse_value(&arg, args.next())?;
+                if !(degree > 0. && degree < 360.) {
+                    return Err("--little-planet must be in (0, 360) degrees".to_string());
+                }
+                parsed.little_planet = Some(degree);
+            }
+            "--override-material" => {
+                parsed.material_override = Some(parse_value(&arg, args.next())?);
+            }
+            "--layers" => {
+                let value: String = parse_value(&arg, args.next())?;
+                parsed.layer_selection.layers =
+                    value.split(',').map(|layer| layer.to_string()).collect();
+            }
+            "--holdout" => {
+                let value: String = parse_value(&arg, args.next())?;
+                parsed.layer_selection.holdout =
+                    value.split(',').map(|layer| layer.to_string()).collect();
+            }
+            "--output" => parsed.output = Some(parse_value(&arg, args.next())?),
+            "--watch" => parsed.watch = true,
+            "--animate" => parsed.animate = true,
+            "--frames" => {
+                let value: String = parse_value(&arg, args.next())?;
+                parsed.frames = parse_frame_range(&value)?;
+            }
+            "--fps" => parsed.fps = parse_value(&arg, args.next())?,
+            "--velocity" => parsed.velocity_output = Some(parse_value(&arg, args.next())?),
+            "--turntable" => {
+                let num_frames: u32 = parse_value(&arg, args.next())?;
+                if num_frames == 0 {
+                    return Err("--turntable requires at least one frame".to_string());
+                }
+                parsed.turntable = Some(num_frames);
+            }
+            "--dump-rays" => parsed.dump_rays = Some(parse_value(&arg, args.next())?),
+            "--cross" if parsed.cubemap_size.is_some() => parsed.cross = true,
+            "--mask" if parsed.depth => parsed.mask_output = Some(parse_value(&arg, args.next())?),
+            "--look-from" if parsed.depth => {
+                let value: String = parse_value(&arg, args.next())?;
+                parsed.look_from = Some(parse_point(&arg, &value)?);
+            }
+            "--look-at" if parsed.depth => {
+                let value: String = parse_value(&arg, args.next())?;
+                parsed.look_at = Some(parse_point(&arg, &value)?);
+            }
+            "--fov" if parsed.depth => {
+                let degree: Float = parse_value(&arg, args.next())?;
+                if !(degree > 0. && degree < 180.) {
+                    return Err("--fov must be in (0, 180) degrees".to_string());
+                }
+                parsed.vertical_fov_degree = Some(degree);
+            }
+            "--orthographic" if parsed.depth => {
+                let viewport_height: Float = parse_value(&arg, args.next())?;
+                if !(viewport_height.is_finite() && viewport_height > 0.) {
+                    return Err("--orthographic requires a positive height".to_string());
+                }
+                parsed.orthographic = Some(viewport_height);
+            }
+            "--ppd" => {
+                let pixels_per_degree: Float = parse_value(&arg, args.next())?;
+                if !(pixels_per_degree.is_finite() && pixels_per_degree > 0.) {
+                    return Err("--ppd must be positive".to_string());
+                }
+                parsed.pixels_per_degree = pixels_per_degree;
+            }
+            "--samples" => {
+                let num_samples: usize = parse_value(&arg, args.next())?;
+                if num_samples == 0 {
+                    return Err("--samples requires at least one sample".to_string());
+                }
+                parsed.num_samples_per_pixel = Some(num_samples);
+            }
+            "--threads" => {
+                let num_threads: usize = parse_value(&arg, args.next())?;
+                if num_threads == 0 {
+                    return Err("--threads requires at least one thread".to_string());
+                }
+                parsed.num_threads = Some(num_threads);
+            }
+            "--low-priority" => parsed.low_priority = true,
+            "--roulette" => {
+                let value: String = parse_value(&arg, args.next())?;
+                parsed.roulette = parse_roulette(&value)?;
+            }
+            "--time-limit" => {
+                let value: String = parse_value(&arg, args.next())?;
+                parsed.time_limit = Some(parse_duration(&value)?);
+            }
+            "--check-samples" => parsed.check_samples = true,
+            "--stats" => parsed.stats = true,
+            "--path-lengths" => {
+                parsed.path_lengths_output = Some(parse_value(&arg, args.next())?);
+                parsed.stats = true;
+            }
+            "--guide" => parsed.guide = true,
+            "--adaptive" => parsed.adaptive_samples = parse_value(&arg, args.next())?,
+            "--filter" => parsed.filter = parse_value(&arg, args.next())?,
+            "--filter-sampling" => parsed.filter_sampling = true,
+            "--bloom" => parsed.bloom.intensity = parse_value(&arg, args.next())?,
+            "--bloom-threshold" => parsed.bloom.threshold = parse_value(&arg, args.next())?,
+            "--bloom-levels" => parsed.bloom.levels = parse_value(&arg, args.next())?,
+            "--chromatic-aberration" => {
+                parsed.lens.chromatic_aberration = parse_value(&arg, args.next())?;
+            }
+            "--vignetting" => parsed.lens.vignetting = parse_value(&arg, args.next())?,
+            "--defocus-falloff" => parsed.lens.defocus_falloff = parse_value(&arg, args.next())?,
+            "--outline" => {
+                let value: String = parse_value(&arg, args.next())?;
+                let color = match parse_list(&arg, &value)?[..] {
+                    [r, g, b] => Color { r, g, b },
+                    _ => {
+                        return Err(format!(
+                            "invalid color for --outline (expected <r>,<g>,<b>): {}",
+                            value
+                        ))
+                    }
+                };
+                parsed.outline = Some(Outline { color });
+            }
+            "--exposure" => {
+                let value: String = parse_value(&arg, args.next())?;
+                exposures = parse_list(&arg, &value)?;
+            }
+            "--tone-map" => {
+                let value: String = parse_value(&arg, args.next())?;
+                tone_curves = parse_list(&arg, &value)?;
+            }
+            "--transfer" => {
+                let value: String = parse_value(&arg, args.next())?;
+                let functions: Vec<TransferFunction> = parse_list(&arg, &value)?;
+                transfers = functions.into_iter().map(Some).collect();
+            }
+            "--lut" => {
+                let path: PathBuf = parse_value(&arg, args.next())?;
+                lut = Some(Arc::new(Lut::load(&path).map_err(|e| e.to_string())?));
+            }
+            "--address" if parsed.serve_address.is_some() => {
+                parsed.serve_address = Some(parse_value(&arg, args.next())?);
+            }
+            "--address" if parsed.worker_address.is_some() => {
+                parsed.worker_address = Some(parse_value(&arg, args.next())?);
+            }
+            "--workers" => {
+                let value: String = parse_value(&arg, args.next())?;
+                parsed.workers = value.split(',').map(|worker| worker.to_string()).collect();
+            }
+            "--memory-budget" => {
+                let value: String = parse_value(&arg, args.next())?;
+                parsed.memory_budget = Some(memory::parse_size(&value)?);
+            }
+            "--width" => parsed.image_width = parse_value(&arg, args.next())?,
+            "--stream" => parsed.stream = true,
+            "--band" => parsed.band_height = Some(parse_value(&arg, args.next())?),
+            "--quiet" => parsed.quiet = true,
+            "--preview" => {
+                if cfg!(feature = "preview") {
+                    parsed.preview = true;
+                } else {
+                    return Err("--preview requires building with `--features preview`".to_string());
+                }
+            }
+            _ => return Err(format!("unknown argument: {}", arg)),
+        }
+    }
+    if parsed.displacement_path.is_some() && parsed.obj_path.is_none() {
+        return Err("--displacement requires --obj".to_string());
+    }
+    if parsed.subdivisions == 0 {
+        return Err("--subdivisions must be positive".to_string());
+    }
+    let uses_files = parsed.molecule_path.is_some()
+        || parsed.obj_path.is_some()
+        || parsed.usd_path.is_some()
+        || parsed.volume_path.is_some()
+        || parsed.scene_file_path.is_some();
+    if !parsed.workers.is_empty() && (uses_files || parsed.turntable.is_some() || parsed.watch) {
+        return Err(
+            "--workers supports only preset scenes, without --turntable or --watch".to_string(),
+        );
+    }
+    if parsed.velocity_output.is_some() && (!parsed.animate || uses_files) {
+        return Err("--velocity requires --animate of a preset scene".to_string());
+    }
+    if !parsed.workers.is_empty() && parsed.layer_selection != LayerSelection::default() {
+        return Err("--layers and --holdout cannot be used with --workers".to_string());
+    }
+    if !parsed.workers.is_empty() && parsed.check_samples {
+        return Err("--check-samples cannot be used with --workers".to_string());
+    }
+    if !parsed.workers.is_empty() && parsed.stats {
+        return Err("--stats cannot be used with --workers".to_string());
+    }
+    if !parsed.workers.is_empty() && parsed.guide {
+        return Err("--guide cannot be used with --workers".to_string());
+    }
+    // The workers build the preset scenes by themselves.
+    if !parsed.workers.is_empty() && parsed.auto_frame {
+        return Err("--auto-frame cannot be used with --workers".to_string());
+    }
+    if !parsed.workers.is_empty() && parsed.little_planet.is_some() {
+        return Err("--little-planet cannot be used with --workers".to_string());
+    }
+    if !parsed.workers.is_empty() && parsed.material_override.is_some() {
+        return Err("--override-material cannot be used with --workers".to_string());
+    }
+    if !parsed.workers.is_empty() && parsed.environment_path.is_some() {
+        return Err("--environment cannot be used with --workers".to_string());
+    }
+    if parsed.sun.is_some() && (parsed.environment_path.is_some() || !parsed.workers.is_empty()) {
+        return Err("--sun cannot be used with --environment or --workers".to_string());
+    }
+    parsed.bloom.validate()?;
+    parsed.lens.validate()?;
+    if let Some(outline) = &parsed.outline {
+        outline.validate()?;
+    }
+    if lut.is_some() && transfers.contains(&None) {
+        return Err("--lut requires --transfer, the encoding of the input of the LUT".to_string());
+    }
+    let transfers = &transfers;
+    let lut = &lut;
+    parsed.tone_mappings = exposures
+        .iter()
+        .flat_map(|&exposure| {
+            tone_curves.iter().flat_map(move |&curve| {
+                transfers.iter().map(move |&transfer| ToneMapping {
+                    exposure,
+                    curve,
+                    transfer,
+                    lut: lut.clone(),
+                })
+            })
+        })
+        .collect();
+    for tone_mapping in parsed.tone_mappings.iter() {
+        tone_mapping.validate()?;
+    }
+    if parsed.tone_mappings.len() > 1 && parsed.output.is_none() {
+        return Err(
+            "several values of --exposure, --tone-map, or --transfer require --output".to_string(),
+        );
+    }
+    if parsed.adaptive_samples > 0 && (parsed.time_limit.is_some() || !parsed.workers.is_empty()) {
+        return Err("--adaptive cannot be used with --time-limit or --workers".to_string());
+    }
+    let renders_images = parsed.animate || parsed.turntable.is_some() || parsed.watch;
+    if parsed.export_scene.is_some()
+        && (renders_images
+            || !parsed.workers.is_empty()
+            || parsed.debug_pixel.is_some()
+            || parsed.pick_focus.is_some()
+            || parsed.depth)
+    {
+        return Err(
+            "--export-scene cannot be used with debug-pixel, pick-focus, depth, --animate, --turntable, --watch, or --workers"
+                .to_string(),
+        );
+    }
+    if parsed.depth && parsed.output.is_none() {
+        return Err("depth requires --output".to_string());
+    }
+    if parsed.depth && (renders_images || !parsed.workers.is_empty() || parsed.time_limit.is_some())
+    {
+        return Err(
+            "depth cannot be used with --animate, --turntable, --watch, --workers, or --time-limit"
+                .to_string(),
+        );
+    }
+    if parsed.cubemap_size.is_some() && parsed.output.is_none() {
+        return Err("cubemap requires --output".to_string());
+    }
+    if parsed.cubemap_size.is_some()
+        && (renders_images || !parsed.workers.is_empty() || parsed.time_limit.is_some())
+    {
+        return Err(
+            "cubemap cannot be used with --animate, --turntable, --watch, --workers, or --time-limit"
+                .to_string(),
+        );
+    }
+    if parsed.bake_size.is_some() && parsed.output.is_none() {
+        return Err("bake requires --output".to_string());
+    }
+    if parsed.bake_size.is_some()
+        && (renders_images || !parsed.workers.is_empty() || parsed.time_limit.is_some())
+    {
+        return Err(
+            "bake cannot be used with --animate, --turntable, --watch, --workers, or --time-limit"
+                .to_string(),
+        );
+    }
+    let captures_probes = !parsed.probe_positions.is_empty();
+    if captures_probes && parsed.output.is_none() {
+        return Err("probes requires --output".to_string());
+    }
+    if captures_probes
+        && (renders_images || !parsed.workers.is_empty() || parsed.time_limit.is_some())
+    {
+        return Err(
+            "probes cannot be used with --animate, --turntable, --watch, --workers, or --time-limit"
+                .to_string(),
+        );
+    }
+    let sweeps = !parsed.sweep_axes.is_empty();
+    if sweeps && (renders_images || !parsed.workers.is_empty() || parsed.time_limit.is_some()) {
+        return Err(
+            "sweep cannot be used with --animate, --turntable, --watch, --workers, or --time-limit"
+                .to_string(),
+        );
+    }
+    let traces_paths = parsed.debug_pixel.is_some() || parsed.dump_rays.is_some();
+    if parsed.path_lengths_output.is_some()
+        && (renders_images
+            || traces_paths
+            || parsed.pick_focus.is_some()
+            || parsed.depth
+            || parsed.cubemap_size.is_some()
+            || parsed.bake_size.is_some()
+            || captures_probes
+            || sweeps)
+    {
+        return Err(
+            "--path-lengths cannot be used with debug-pixel, pick-focus, depth, cubemap, bake, probes, sweep, --dump-rays, --animate, --turntable, or --watch"
+                .to_string(),
+        );
+    }
+    if (traces_paths || parsed.pick_focus.is_some())
+        && (renders_images || !parsed.workers.is_empty())
+    {
+        return Err(
+            "debug-pixel, pick-focus, and --dump-rays cannot be used with --animate, --turntable, --watch, or --workers"
+                .to_string(),
+        );
+    }
+    if parsed.time_limit.is_some()
+        && (renders_images
+            || traces_paths
+            || parsed.pick_focus.is_some()
+            || !parsed.workers.is_empty())
+    {
+        return Err(
+            "--time-limit cannot be used with debug-pixel, pick-focus, --dump-rays, --animate, --turntable, --watch, or --workers"
+                .to_string(),
+        );
+    }
+    if parsed.import_units.is_some() && parsed.obj_path.is_none() && parsed.usd_path.is_none() {
+        return Err("--import-units requires --obj or --usd".to_string());
+    }
+    if parsed.image_width < 4 {
+        return Err("--width must be at least 4".to_string());
+    }
+    if parsed.band_height.is_some() && !parsed.stream {
+        return Err("--band requires --stream".to_string());
+    }
+    if parsed.stream {
+        let band_height = *parsed.band_height.get_or_insert(DEFAULT_STREAM_BAND_HEIGHT);
+        if band_height == 0 {
+            return Err("--band must be positive".to_string());
+        }
+        // The tiles of workers finish out of order while the scan lines are written in order.
+        if !parsed.workers.is_empty() {
+            return Err("--stream cannot be used with --workers".to_string());
+        }
+        if renders_images
+            || parsed.time_limit.is_some()
+            || parsed.guide
+            || parsed.adaptive_samples > 0
+            || parsed.check_samples
+            || parsed.stats
+            || parsed.preview
+        {
+            return Err(
+                "--stream cannot be used with --animate, --turntable, --watch, --time-limit, --guide, --adaptive, --check-samples, --stats, or --preview"
+                    .to_string(),
+            );
+        }
+        // The effects need the whole image.
+        if parsed.bloom.intensity > 0. || parsed.lens.is_enabled() || parsed.outline.is_some() {
+            return Err(
+                "--stream cannot be used with --bloom, the lens effects, or --outline".to_string(),
+            );
+        }
+        if parsed.depth
+            || parsed.cubemap_size.is_some()
+            || parsed.bake_size.is_some()
+            || captures_probes
+            || sweeps
+            || traces_paths
+            || parsed.pick_focus.is_some()
+        {
+            return Err(
+                "--stream cannot be used with depth, cubemap, bake, probes, sweep, debug-pixel, pick-focus, or --dump-rays"
+                    .to_string(),
+            );
+        }
+    }
+    Ok(parsed)
+}
+
+/// Converts `meshes` in units of `meters_per_unit` meters into those of the scene given by `--units`.
+fn convert_units(meshes: Vec<Mesh>, meters_per_unit: Float, units: LengthUnit) -> Vec<Mesh> {
+    let factor = meters_per_unit / units.meters();
+    if factor == 1. {
+        return meshes;
+    }
+    meshes.into_iter().map(|mesh| mesh.scaled(factor)).collect()
+}
+
+/// Builds the scene of the meshes in a USD file as they are at `time`,
+/// where the camera frames them at the first frame so that it does not follow the animation.
+/// The meshes are converted from `--import-units`, or from the `metersPerUnit` of the stage.
+#[cfg(feature = "usd")]
+fn load_usd(
+    args: &Args,
+    path: &Path,
+    aspect_ratio: Float,
+    time: Option<Float>,
+) -> Result<Scene, RenderError> {
+    let stage = UsdStage::load(path)?;
+    let meters_per_unit = match args.import_units {
+        Some(import_units) => import_units.meters(),
+        None => stage.meters_per_unit.unwrap_or(args.units.meters()),
+    };
+    let meshes_at = |time| {
+        stage
+            .meshes_at(time)
+            .map(|meshes| convert_units(meshes, meters_per_unit, args.units))
+            .map_err(|message| RenderError::SceneParse {
+                path: path.to_path_buf(),
+                message,
+            })
+    };
+    let mut scene = scenes::meshes(meshes_at(0.)?, aspect_ratio);
+    if let Some(time) = time {
+        scene.world = scenes::meshes(meshes_at(time)?, aspect_ratio).world;
+    }
+    Ok(scene)
+}
+
+#[cfg(not(feature = "usd"))]
+fn load_usd(
+    _args: &Args,
+    _path: &Path,
+    _aspect_ratio: Float,
+    _time: Option<Float>,
+) -> Result<Scene, RenderError> {
+    unreachable!("--usd is rejected unless the `usd` feature is enabled")
+}
+
+/// Returns the preset scene `name`, where the objects and the camera are placed as they are at `time`
+/// if it is given, or `None` if there is no such scene.
+fn preset_scene(
+    name: &str,
+    params: &SceneParams,
+    aspect_ratio: Float,
+    time: Option<Float>,
+) -> Option<Scene> {
+    let scene = scenes::by_name(name, aspect_ratio, params)?;
+    Some(match time {
+        None => scene,
+        Some(time) => scenes::animation_by_name(name).apply(scene, time, aspect_ratio),
+    })
+}
+
+/// Builds and validates the scene specified by the command-line arguments,
+/// and checks that rendering it with `settings` fits in the memory budget.
+/// If `time` is given, the objects and the camera are placed as they are at that time.
+fn load_scene(
+    args: &Args,
+    aspect_ratio: Float,
+    settings: &RenderSettings,
+    time: Option<Float>,
+) -> Result<Scene, RenderError> {
+    let mut scene = if let Some(molecule_path) = &args.molecule_path {
+        let molecule = Molecule::load(molecule_path)?;
+        scenes::molecule(&molecule, &args.molecule_options, aspect_ratio)
+    } else if let Some(obj_path) = &args.obj_path {
+        let model = ObjModel::load(obj_path)?;
+        for warning in model.warnings.iter() {
+            eprintln!("warning: {}", warning);
+        }
+        let import_units = args.import_units.unwrap_or(args.units);
+        let meshes = convert_units(model.meshes, import_units.meters(), args.units);
+        let meshes = match &args.displacement_path {
+            Some(displacement_path) => {
+                let height = HeightMap {
+                    texture: Arc::new(ImageTexture::load(displacement_path)?),
+                    scale: args.displacement_scale,
+                };
+                meshes
+                    .iter()
+                    .map(|mesh| displacement::displace_mesh(mesh, &height, args.subdivisions))
+                    .collect()
+            }
+            None => meshes,
+        };
+        scenes::meshes(meshes, aspect_ratio)
+    } else if let Some(usd_path) = &args.usd_path {
+        load_usd(args, usd_path, aspect_ratio, time)?
+    } else if let Some(volume_path) = &args.volume_path {
+        let file = VolumeFile::load(volume_path)?;
+        scenes::volume(file, args.extinction, aspect_ratio)
+    } else if let Some(scene_file_path) = &args.scene_file_path {
+        scene_file::load(scene_file_path, aspect_ratio)?
+    } else {
+        preset_scene(&args.scene_name, &args.scene_params, aspect_ratio, time).ok_or_else(|| {
+            RenderError::InvalidParameter(format!(
+                "unknown scene: {}\n{}",
+                args.scene_name,
+                usage()
+            ))
+        })?
+    };
+    scene
+        .world
+        .select_layers(&args.layer_selection)
+        .map_err(RenderError::InvalidParameter)?;
+    if args.auto_frame {
+        scene
+            .auto_frame(AUTO_FRAME_MARGIN)
+            .map_err(RenderError::InvalidParameter)?;
+    }
+    if let Some(vertical_fov_degree) = args.little_planet {
+        let pose = scene.camera.pose();
+        let forward = &pose.look_at - &pose.look_from;
+        scene.camera = Camera::little_planet(
+            pose.look_from.clone(),
+            &forward,
+            vertical_fov_degree,
+            aspect_ratio,
+        );
+    }
+    if let Some(material_override) = args.material_override {
+        scene.override_materials(material_override);
+    }
+    if let Some(environment_path) = &args.environment_path {
+        scene.background = Background::Environment(Arc::new(ImageTexture::load(environment_path)?));
+    }
+    if let Some((elevation, azimuth)) = args.sun {
+        let Background::Sky = scene.background else {
+            return Err(RenderError::InvalidParameter(
+                "--sun requires a scene whose background is the sky".to_string(),
+            ));
+        };
+        scene.background = Background::SunnySky(sun_at(elevation, azimuth, args.sun_radius));
+    }
+    scene.validate().map_err(RenderError::InvalidScene)?;
+
+    let scene_size = scene.world.memory_size();
+    // With `--stream`, only a band of the image is in memory with the scan lines the filter splats into it.
+    let film_height = args
+        .band_height
+        .map_or(settings.image_height, |band_height| {
+            (band_height + 2 * settings.margin()).min(settings.image_height)
+        });
+    let film_size = memory::film_memory_size(settings.image_width, film_height);
+    let total_size = scene_size + film_size;
+    if !args.quiet {
+        eprintln!(
+            "Estimated memory usage: {} (scene: {}, film: {})",
+            memory::format_size(total_size),
+            memory::format_size(scene_size),
+            memory::format_size(film_size)
+        );
+    }
+    if let Some(budget) = args.memory_budget {
+        if total_size > budget {
+            return Err(RenderError::InvalidParameter(format!(
+                "the estimated memory usage {} exceeds the budget {}",
+                memory::format_size(total_size),
+                memory::format_size(budget)
+            )));
+        }
+    }
+    Ok(scene)
+}
+
+/// Returns the sun of `--sun` at `elevation` and `azimuth` with the angular radius `radius`,
+/// all in degrees.
+fn sun_at(elevation: Float, azimuth: Float, radius: Float) -> Sun {
+    let (elevation, azimuth) = (elevation.to_radians(), azimuth.to_radians());
+    let direction = Vec3 {
+        x: elevation.cos() * azimuth.sin(),
+        y: elevation.sin(),
+        z: -elevation.cos() * azimuth.cos(),
+    };
+    Sun::from_irradiance(
+        direction.unit_vector(),
+        radius.to_radians(),
+        &SUN_IRRADIANCE,
+    )
+}
+
+/// Renders `scene` on this machine, or on the workers given by `--workers`,
+/// which build the same preset scene at `time` by themselves, and post-processes the image.
+fn render_scene(
+    args: &Args,
+    scene: &Scene,
+    time: Option<Float>,
+    aspect_ratio: Float,
+    settings: &RenderSettings,
+    on_scan_line: impl FnMut(&Film) -> bool,
+) -> Result<Film, Film> {
+    let mut result = if let Some(time_limit) = args.time_limit {
+        render_for(scene, settings, time_limit, on_scan_line)
+    } else if args.workers.is_empty() {
+        render(scene, settings, on_scan_line)
+    } else {
+        let frame = FrameSpec {
+            scene_name: args.scene_name.clone(),
+            params: args.scene_params.clone(),
+            time,
+            aspect_ratio,
+            settings: settings.clone(),
+        };
+        distributed::render_on_workers(&args.workers, &frame, scene, on_scan_line)
+    };
+    // The partial images of interrupted renderings are saved with the effects as well.
+    let (Ok(film) | Err(film)) = &mut result;
+    post_process(args, scene, film);
+    result
+}
+
+/// Applies the bloom to the linear colors of `film`, then draws the outline of `scene` if any,
+/// and then applies the lens effects, which distort the lines as they do the objects.
+fn post_process(args: &Args, scene: &Scene, film: &mut Film) {
+    args.bloom.apply(film);
+    if let Some(outline) = &args.outline {
+        outline.apply(scene, film);
+    }
+    args.lens.apply(film);
+}
+
+/// Whether images are written to or read from `path` in the PNG format, i.e., its name ends with `.png`.
+fn is_png_path(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+}
+
+/// Whether images are written to `path` in the Portable Float Map format, i.e., its name ends with `.pfm`.
+fn is_pfm_path(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("pfm"))
+}
+
+/// Returns the format of the images written to `path`, which is decided by its extension.
+fn image_format(path: &Path) -> ImageFormat {
+    if is_png_path(path) {
+        ImageFormat::Png
+    } else if is_pfm_path(path) {
+        ImageFormat::Pfm
+    } else {
+        ImageFormat::Ppm
+    }
+}
+
+/// Writes `film` to `output`, or to the standard output if `output` is `None`.
+/// The image format is PNG if the file name ends with `.png`, PFM if it ends with `.pfm`,
+/// and PPM otherwise, whose colors are encoded by `transfer` or the default of the format.
+fn write_film(
+    film: &Film,
+    output: &Option<PathBuf>,
+    transfer: Option<TransferFunction>,
+) -> Result<(), RenderError> {
+    let format = output.as_deref().map_or(ImageFormat::Ppm, image_format);
+    let transfer = transfer.unwrap_or(format.default_transfer());
+    match output {
+        Some(path) => {
+            let file = File::create(path).map_err(|e| RenderError::io(path, e))?;
+            let mut out = BufWriter::new(file);
+            match format {
+                ImageFormat::Png => film
+                    .write_png(&mut out, transfer)
+                    .map_err(|e| RenderError::png(path, e)),
+                ImageFormat::Pfm => film
+                    .write_pfm(&mut out, transfer)
+                    .map_err(|e| RenderError::io(path, e)),
+                ImageFormat::Ppm => film
+                    .write_ppm(&mut out, transfer)
+                    .map_err(|e| RenderError::io(path, e)),
+            }
+        }
+        None => film
+            .write_ppm(&mut BufWriter::new(std::io::stdout().lock()), transfer)
+            .map_err(|source| RenderError::Io { path: None, source }),
+    }
+}
+
+/// Writes the images of `film` mapped by each of `tone_mappings` to `output` by `write_film`.
+/// If there are several mappings, the name of each is appended to the file name,
+/// e.g. `out_ev+1_aces.png` for `out.png`.
+fn write_images(
+    film: &Film,
+    output: &Option<PathBuf>,
+    tone_mappings: &[ToneMapping],
+) -> Result<(), RenderError> {
+    for tone_mapping in tone_mappings {
+        let output = tone_mapped_output(output, tone_mappings, tone_mapping);
+        write_film(
+            &tone_mapping.apply(film),
+            &output,
+            tone_mapping.file_transfer(),
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes the lengths of the paths of the pixels of `film` to `path` if it is given.
+fn write_path_lengths(film: &Film, path: &Option<PathBuf>) -> Result<(), RenderError> {
+    let Some(path) = path else {
+        return Ok(());
+    };
+    let file = File::create(path).map_err(|e| RenderError::io(path, e))?;
+    film.write_path_lengths_pfm(&mut BufWriter::new(file))
+        .map_err(|e| RenderError::io(path, e))
+}
+
+/// Returns the output of the image mapped by `tone_mapping`, one of `tone_mappings`,
+/// whose name is appended to the file name of `output` if there are several mappings.
+fn tone_mapped_output(
+    output: &Option<PathBuf>,
+    tone_mappings: &[ToneMapping],
+    tone_mapping: &ToneMapping,
+) -> Option<PathBuf> {
+    match output {
+        Some(path) if tone_mappings.len() > 1 => Some(suffixed_path(path, &tone_mapping.name())),
+        _ => output.clone(),
+    }
+}
+
+/// Renders `scene` in bands of `band_height` scan lines and writes each band to the images
+/// of `--output` as `write_images` does as soon as it is finished, so that only a band of
+/// a huge image is in memory at once. An interruption stops the rendering after the band
+/// in progress, and the bands not rendered are left black. Returns whether it is interrupted.
+fn render_bands(
+    args: &Args,
+    scene: &Scene,
+    settings: &RenderSettings,
+    band_height: usize,
+) -> Result<bool, RenderError> {
+    let (width, height) = (settings.image_width, settings.image_height);
+    let format = args
+        .output
+        .as_deref()
+        .map_or(ImageFormat::Ppm, image_format);
+    let io_error = |output: &Option<PathBuf>, source| RenderError::Io {
+        path: output.clone(),
+        source,
+    };
+    let mut writers = vec![];
+    for tone_mapping in &args.tone_mappings {
+        let output = tone_mapped_output(&args.output, &args.tone_mappings, tone_mapping);
+        let out: Box<dyn Write> = match &output {
+            Some(path) => {
+                let file = File::create(path).map_err(|e| RenderError::io(path, e))?;
+                Box::new(BufWriter::new(file))
+            }
+            None => Box::new(BufWriter::new(std::io::stdout())),
+        };
+        let transfer = tone_mapping
+            .file_transfer()
+            .unwrap_or(format.default_transfer());
+        let writer = ScanLineWriter::new(out, format, transfer, width, height)
+            .map_err(|e| io_error(&output, e))?;
+        writers.push((tone_mapping, output, writer));
+    }
+    let mut bands: Vec<Range<usize>> = (0..height)
+        .step_by(band_height)
+        .map(|top| top..(top + band_height).min(height))
+        .collect();
+    // The bands are rendered in the order the format stores the scan lines.
+    if format.is_bottom_up() {
+        bands.reverse();
+    }
+    let num_bands = bands.len();
+    let renderer = Renderer::new(settings.clone());
+    let black_pixels = vec![
         Color {
             r: 0.,
             g: 0.,
             b: 0.,
+        };
+        width * band_height
+    ];
+    let mut is_aborted = false;
+    for (index, rows) in bands.into_iter().enumerate() {
+        is_aborted = is_aborted || is_interrupted();
+        let (top, pixels) = if is_aborted {
+            (rows.start, black_pixels.clone())
+        } else {
+            if !args.quiet {
+                eprintln!("Rendering band {}/{}", index + 1, num_bands);
+            }
+            let tile = renderer.render_tile(scene, rows.clone());
+            (tile.y, tile.pixels)
+        };
+        let scan_lines: Box<dyn Iterator<Item = usize>> = if format.is_bottom_up() {
+            Box::new(rows.rev())
+        } else {
+            Box::new(rows)
+        };
+        for y in scan_lines {
+            let pixels = &pixels[(y - top) * width..(y - top + 1) * width];
+            for (tone_mapping, output, writer) in writers.iter_mut() {
+                let colors: Vec<Color> = pixels.iter().map(|c| tone_mapping.map_color(c)).collect();
+                writer
+                    .write_scan_line(&colors)
+                    .map_err(|e| io_error(output, e))?;
+            }
         }
-    } else {
-        if let Some((hit, material)) = world.hit(ray) {
-            let (attenuation, child_ray) = material.scatter(ray, &hit);
-            let color = ray_color(&child_ray, world, diffusion_depth - 1);
-            color.attenuate(&attenuation)
+        for (_, output, writer) in writers.iter_mut() {
+            writer.flush().map_err(|e| io_error(output, e))?;
+        }
+    }
+    for (_, output, writer) in writers {
+        writer.finish().map_err(|e| io_error(&output, e))?;
+    }
+    Ok(is_aborted)
+}
+
+/// Reports that the rendering has finished unless `--quiet` is given.
+fn report_done(args: &Args) {
+    if !args.quiet {
+        eprintln!("Done.");
+    }
+}
+
+/// Returns the path of one of the images written for `path`, whose file name has `suffix` appended.
+fn suffixed_path(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match path.extension() {
+        Some(extension) => format!("{}_{}.{}", stem, suffix, extension.to_string_lossy()),
+        None => format!("{}_{}", stem, suffix),
+    };
+    path.with_file_name(file_name)
+}
+
+/// Prints the differences between the images given by `compare`,
+/// and writes the heatmap of the FLIP error to the file given by `--output` if any.
+fn compare_images(reference: &Path, test: &Path, args: &Args) -> Result<(), RenderError> {
+    let reference_image = Image::load(reference)?;
+    let test_image = Image::load(test)?;
+    let comparison = compare::compare(&reference_image, &test_image, args.pixels_per_degree)
+        .map_err(RenderError::InvalidParameter)?;
+    println!("RMSE: {:.6}", comparison.rmse);
+    println!("PSNR: {:.2} dB", comparison.psnr);
+    println!("FLIP: {:.6}", comparison.flip);
+    if let Some(path) = &args.output {
+        let file = File::create(path).map_err(|e| RenderError::io(path, e))?;
+        let mut out = BufWriter::new(file);
+        let heatmap = comparison.heatmap();
+        if is_png_path(path) {
+            heatmap
+                .write_png(&mut out)
+                .map_err(|e| RenderError::png(path, e))?;
         } else {
-            ray_background_color(ray)
+            heatmap
+                .write_ppm(&mut out)
+                .map_err(|e| RenderError::io(path, e))?;
         }
     }
+    Ok(())
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
 }
 
-/// Performs Gamma Correction.
-fn filter_color(color: &Color) -> Color {
-    Color {
-        r: color.r.sqrt(),
-        g: color.g.sqrt(),
-        b: color.b.sqrt(),
+/// Renders the molecule file again every time it is modified.
+/// A rendering in progress is restarted as soon as the file changes.
+fn watch(
+    args: &Args,
+    molecule_path: &Path,
+    aspect_ratio: Float,
+    settings: &RenderSettings,
+    mut on_scan_line: impl FnMut(&Film) -> bool,
+) -> ! {
+    let mut last_modified = None;
+    let mut is_first = true;
+    loop {
+        let modified = modified_time(molecule_path);
+        if is_interrupted() {
+            std::process::exit(EXIT_CODE_INTERRUPTED);
+        }
+        if !is_first && modified == last_modified {
+            std::thread::sleep(WATCH_INTERVAL);
+            continue;
+        }
+        is_first = false;
+        last_modified = modified;
+        match load_scene(args, aspect_ratio, settings, None) {
+            Err(msg) => eprintln!("{}", msg),
+            Ok(scene) => {
+                let mut is_aborted = false;
+                let film = render(&scene, settings, |film| {
+                    is_aborted = !on_scan_line(film);
+                    !is_aborted && modified_time(molecule_path) == last_modified
+                });
+                if is_aborted {
+                    // Keeps the image of the last complete rendering even if interrupted.
+                    if is_interrupted() {
+                        eprintln!("Interrupted.");
+                        std::process::exit(EXIT_CODE_INTERRUPTED);
+                    }
+                    eprintln!("Aborted.");
+                    std::process::exit(1);
+                }
+                match film {
+                    Ok(mut film) => {
+                        post_process(args, &scene, &mut film);
+                        if let Err(msg) = write_images(&film, &args.output, &args.tone_mappings) {
+                            eprintln!("{}", msg);
+                        }
+                        report_invalid_samples(&film, settings);
+                        report_stats(&film, settings);
+                    }
+                    Err(_) => {
+                        eprintln!("{} was modified; restarting.", molecule_path.display());
+                        continue;
+                    }
+                }
+            }
+        }
+        eprintln!(
+            "Watching {} for changes (Ctrl-C to quit)...",
+            molecule_path.display()
+        );
     }
 }
 
-fn oxygen(x: f64, y: f64, z: f64) -> Box<dyn Hittable> {
-    Box::new(Sphere {
-        center: Point3 { x, y, z },
-        radius: 0.3,
-        material: Box::new(Glass {
-            eta: 1.5,
-            albedo: Attenuation {
-                r: 0.9,
-                g: 0.5,
-                b: 0.5,
-            },
-        }),
-    })
+/// Returns the path of the `frame`-th frame, where the sequence of `#` in `pattern` is replaced
+/// by the zero-padded frame number (e.g. `out_####.png` becomes `out_0001.png`).
+/// If `pattern` has no `#`, `_0001` and so on are inserted before the extension.
+fn frame_path(pattern: &Path, frame: u32) -> PathBuf {
+    let pattern = pattern.to_string_lossy();
+    match pattern.find('#') {
+        Some(start) => {
+            let width = pattern[start..].chars().take_while(|c| *c == '#').count();
+            let number = format!("{:0width$}", frame, width = width);
+            PathBuf::from(format!(
+                "{}{}{}",
+                &pattern[..start],
+                number,
+                &pattern[start + width..]
+            ))
+        }
+        None => {
+            let path = Path::new(pattern.as_ref());
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let file_name = match path.extension() {
+                Some(extension) => {
+                    format!("{}_{:04}.{}", stem, frame, extension.to_string_lossy())
+                }
+                None => format!("{}_{:04}", stem, frame),
+            };
+            path.with_file_name(file_name)
+        }
+    }
 }
 
-fn carbon(x: f64, y: f64, z: f64) -> Box<dyn Hittable> {
-    Box::new(Sphere {
-        center: Point3 { x, y, z },
-        radius: 0.35,
-        material: Box::new(Metal {
-            albedo: Attenuation {
-                r: 0.5,
-                g: 0.5,
-                b: 0.5,
-            },
-            fuzz: 0.1,
-        }),
+/// The extensions of the output files that are encoded as videos instead of numbered images.
+const VIDEO_EXTENSIONS: [&str; 3] = ["mp4", "gif", "webm"];
+
+fn is_video_path(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| {
+        VIDEO_EXTENSIONS
+            .iter()
+            .any(|video_extension| extension.eq_ignore_ascii_case(video_extension))
     })
 }
 
-fn hydrogen(x: f64, y: f64, z: f64) -> Box<dyn Hittable> {
-    Box::new(Sphere {
-        center: Point3 { x, y, z },
-        radius: 0.25,
-        material: Box::new(Lambertian {
-            albedo: Attenuation {
-                r: 0.8,
-                g: 0.8,
-                b: 0.9,
-            },
-        }),
-    })
+/// Renders the scenes returned by `scene_at` for the frames in `frames` by `render_frame`,
+/// either to numbered image files or, if `output` is a video file, to a video played at `fps`,
+/// where each frame is mapped by `tone_mappings`, which must be a single one for videos.
+/// `scene_at` also returns a short description of each frame for the log,
+/// which warns of the frames whose cameras are inside objects.
+fn render_frames(
+    output: &Path,
+    frames: (u32, u32),
+    fps: f64,
+    settings: &RenderSettings,
+    tone_mappings: &[ToneMapping],
+    mut render_frame: impl FnMut(u32, &Scene) -> Result<Film, Film>,
+    mut scene_at: impl FnMut(u32) -> Result<(Scene, String), RenderError>,
+) -> Result<(), RenderError> {
+    if is_video_path(output) && tone_mappings.len() > 1 {
+        return Err(RenderError::InvalidParameter(
+            "video output cannot have several values of --exposure, --tone-map, or --transfer"
+                .to_string(),
+        ));
+    }
+    #[cfg(feature = "video")]
+    let mut encoder = if is_video_path(output) {
+        Some(video::VideoEncoder::start(
+            output,
+            settings.image_width,
+            settings.image_height,
+            fps,
+        )?)
+    } else {
+        None
+    };
+    #[cfg(not(feature = "video"))]
+    if is_video_path(output) {
+        let _ = (fps, settings);
+        return Err(RenderError::InvalidParameter(
+            "video output requires building with `--features video`".to_string(),
+        ));
+    }
+
+    let (first, last) = frames;
+    let mut result = Ok(());
+    for frame in first..=last {
+        let (scene, description) = scene_at(frame)?;
+        eprintln!("Frame {} of {}..{} ({}):", frame, first, last, description);
+        // A camera flying into an object sees only its inside, which renders as a black frame.
+        if let Some(object) = scene.enclosing_object(&scene.camera.pose().look_from) {
+            eprintln!(
+                "warning: the camera is inside object #{}, which may hide the rest of the scene",
+                object
+            );
+        }
+        let film = match render_frame(frame, &scene) {
+            Ok(film) => film,
+            Err(film) if is_interrupted() => {
+                result = Err(RenderError::Interrupted { frame });
+                film
+            }
+            Err(_) => return Err(RenderError::Aborted),
+        };
+        report_invalid_samples(&film, settings);
+        report_stats(&film, settings);
+        #[cfg(feature = "video")]
+        if let Some(encoder) = &mut encoder {
+            let transfer = tone_mappings[0]
+                .file_transfer()
+                .unwrap_or(TransferFunction::Gamma2);
+            encoder.add_frame(&tone_mappings[0].apply(&film), transfer)?;
+        } else {
+            write_images(&film, &Some(frame_path(output, frame)), tone_mappings)?;
+        }
+        #[cfg(not(feature = "video"))]
+        write_images(&film, &Some(frame_path(output, frame)), tone_mappings)?;
+        if result.is_err() {
+            break;
+        }
+    }
+
+    // The video is finished even if interrupted so that the frames so far can be played.
+    #[cfg(feature = "video")]
+    if let Some(encoder) = encoder {
+        encoder.finish()?;
+    }
+    result
+}
+
+/// Renders the frames of the animation of the preset scene to numbered files.
+fn animate(
+    args: &Args,
+    aspect_ratio: Float,
+    settings: &RenderSettings,
+    mut on_scan_line: impl FnMut(&Film) -> bool,
+) -> Result<(), RenderError> {
+    let output = args
+        .output
+        .as_ref()
+        .ok_or_else(|| RenderError::InvalidParameter("--animate requires --output".to_string()))?;
+    let time_of = |frame: u32| ((frame - 1) as f64 / args.fps) as Float;
+    render_frames(
+        output,
+        args.frames,
+        args.fps,
+        settings,
+        &args.tone_mappings,
+        |frame, scene| {
+            let time = Some(time_of(frame));
+            render_scene(args, scene, time, aspect_ratio, settings, &mut on_scan_line)
+        },
+        |frame| {
+            let time = time_of(frame);
+            let scene = load_scene(args, aspect_ratio, settings, Some(time))?;
+            if let Some(velocity_output) = &args.velocity_output {
+                let path = frame_path(velocity_output, frame);
+                write_velocity(args, &scene, time, aspect_ratio, settings, &path)?;
+            }
+            Ok((scene, format!("t = {:.3}s", time)))
+        },
+    )
+}
+
+/// Writes the velocity map of `scene`, which the animation of the preset scene has placed
+/// as it is at `time`, to `path` in the PFM format.
+fn write_velocity(
+    args: &Args,
+    scene: &Scene,
+    time: Float,
+    aspect_ratio: Float,
+    settings: &RenderSettings,
+    path: &Path,
+) -> Result<(), RenderError> {
+    let animation = scenes::animation_by_name(&args.scene_name);
+    let velocity_map = VelocityMap::render(
+        scene,
+        &animation,
+        time,
+        (1. / args.fps) as Float,
+        aspect_ratio,
+        settings.image_width,
+        settings.image_height,
+    );
+    let file = File::create(path).map_err(|e| RenderError::io(path, e))?;
+    velocity_map
+        .write_pfm(&mut BufWriter::new(file))
+        .map_err(|e| RenderError::io(path, e))
+}
+
+/// Renders `num_frames` frames to numbered files,
+/// orbiting the camera once around the point it looks at.
+fn turntable(
+    args: &Args,
+    num_frames: u32,
+    aspect_ratio: Float,
+    settings: &RenderSettings,
+    mut on_scan_line: impl FnMut(&Film) -> bool,
+) -> Result<(), RenderError> {
+    let output = args.output.as_ref().ok_or_else(|| {
+        RenderError::InvalidParameter("--turntable requires --output".to_string())
+    })?;
+    render_frames(
+        output,
+        (1, num_frames),
+        args.fps,
+        settings,
+        &args.tone_mappings,
+        |_, scene| render(scene, settings, &mut on_scan_line),
+        |frame| {
+            let degree = 360. * (frame - 1) as Float / num_frames as Float;
+            let mut scene = load_scene(args, aspect_ratio, settings, None)?;
+            scene.camera = Camera::new(scene.camera.pose().orbit(degree), aspect_ratio);
+            Ok((scene, format!("{:.1} degrees", degree)))
+        },
+    )
+}
+
+/// Returns an error if the pixel at (`x`, `y`) is outside the image of `settings`.
+fn check_pixel(settings: &RenderSettings, x: usize, y: usize) -> Result<(), RenderError> {
+    let (width, height) = (settings.image_width, settings.image_height);
+    if x >= width || y >= height {
+        return Err(RenderError::InvalidParameter(format!(
+            "the pixel ({}, {}) is outside the image of {}x{}",
+            x, y, width, height
+        )));
+    }
+    Ok(())
+}
+
+/// Prints the distances to the surface seen at the pixel given by `pick-focus`.
+fn pick_focus(
+    args: &Args,
+    aspect_ratio: Float,
+    settings: RenderSettings,
+    x: usize,
+    y: usize,
+) -> Result<(), RenderError> {
+    check_pixel(&settings, x, y)?;
+    let scene = load_scene(args, aspect_ratio, &settings, None)?;
+    let pick = Renderer::new(settings).pick_focus(&scene, x, y);
+    println!("{}", format_focus_pick(x, y, pick.as_ref()));
+    Ok(())
+}
+
+/// Writes the scene to the scene file at `path` given by `--export-scene`.
+fn export_scene(
+    args: &Args,
+    aspect_ratio: Float,
+    settings: &RenderSettings,
+    path: &Path,
+) -> Result<(), RenderError> {
+    let scene = load_scene(args, aspect_ratio, settings, None)?;
+    let json = scene_file::to_json(&scene).map_err(RenderError::InvalidParameter)?;
+    std::fs::write(path, json.pretty() + "\n").map_err(|e| RenderError::io(path, e))
+}
+
+/// Renders the depth map of the scene seen from the camera given by the options of `depth`,
+/// and writes it to the file given by `--output` and its mask to the file given by `--mask` if any.
+/// The image formats are PNG if the file names end with `.png`, and PFM and PGM respectively otherwise.
+fn render_depth(
+    args: &Args,
+    aspect_ratio: Float,
+    settings: RenderSettings,
+) -> Result<(), RenderError> {
+    let mut scene = load_scene(args, aspect_ratio, &settings, None)?;
+    let pose = scene.camera.pose();
+    let pose = CameraPose {
+        look_from: args
+            .look_from
+            .clone()
+            .unwrap_or_else(|| pose.look_from.clone()),
+        look_at: args.look_at.clone().unwrap_or_else(|| pose.look_at.clone()),
+        vertical_fov_degree: args.vertical_fov_degree.unwrap_or(pose.vertical_fov_degree),
+    };
+    let projection = match args.orthographic {
+        Some(viewport_height) => Projection::Orthographic { viewport_height },
+        None => Projection::Perspective,
+    };
+    scene.camera = Camera::with_projection(pose, projection, aspect_ratio);
+    scene.validate().map_err(RenderError::InvalidScene)?;
+
+    let depth_map = Renderer::new(settings).render_depth(&scene);
+    match depth_map.max_depth() {
+        Some(max_depth) => println!("max depth: {:.4} {}", max_depth, args.units.name()),
+        None => eprintln!("warning: no pixel sees anything"),
+    }
+    let create = |path: &PathBuf| {
+        let file = File::create(path).map_err(|e| RenderError::io(path, e))?;
+        Ok::<_, RenderError>((BufWriter::new(file), is_png_path(path)))
+    };
+    if let Some(path) = &args.output {
+        let (mut out, is_png) = create(path)?;
+        if is_png {
+            depth_map
+                .write_png(&mut out)
+                .map_err(|e| RenderError::png(path, e))?;
+        } else {
+            depth_map
+                .write_pfm(&mut out)
+                .map_err(|e| RenderError::io(path, e))?;
+        }
+    }
+    if let Some(path) = &args.mask_output {
+        let (mut out, is_png) = create(path)?;
+        if is_png {
+            depth_map
+                .write_mask_png(&mut out)
+                .map_err(|e| RenderError::png(path, e))?;
+        } else {
+            depth_map
+                .write_mask_pgm(&mut out)
+                .map_err(|e| RenderError::io(path, e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Renders the faces of the cubemap given by `cubemap` around the camera of the scene or `--look-from`,
+/// and writes each of them to its own file named after `--output`, or all to it as a cross with `--cross`.
+/// The bloom and the lens effects are not applied, since they would break the seams between the faces.
+fn render_cubemap(args: &Args, size: usize, settings: RenderSettings) -> Result<(), RenderError> {
+    let output = args
+        .output
+        .as_ref()
+        .ok_or_else(|| RenderError::InvalidParameter("cubemap requires --output".to_string()))?;
+    let mut scene = load_scene(args, 1., &settings, None)?;
+    let center = args
+        .look_from
+        .clone()
+        .unwrap_or_else(|| scene.camera.pose().look_from.clone());
+    // The BVH of the scene is kept by the renderer for all the faces.
+    let renderer = Renderer::new(RenderSettings {
+        image_width: size,
+        image_height: size,
+        ..settings
+    });
+    let mut faces = vec![];
+    for face in CubeFace::ALL {
+        eprintln!("Face {}:", face.name());
+        scene.camera = face.camera(&center);
+        scene.validate().map_err(RenderError::InvalidScene)?;
+        let film = renderer.render(&scene);
+        if !args.cross {
+            let path = suffixed_path(output, face.name());
+            write_images(&film, &Some(path), &args.tone_mappings)?;
+        }
+        faces.push(film);
+    }
+    if args.cross {
+        write_images(&assemble_cross(&faces), &args.output, &args.tone_mappings)?;
+    }
+    Ok(())
+}
+
+/// Bakes the light arriving at the meshes with texture coordinates among the objects of the scene
+/// into the textures given by `bake`, grown by `BAKE_DILATION` texels past the edges of their layouts,
+/// and writes them to `--output`, to which the indices of the meshes are appended if there are several.
+/// The bloom and the lens effects are not applied, since they belong to images seen from cameras.
+fn bake_textures(args: &Args, size: usize, settings: RenderSettings) -> Result<(), RenderError> {
+    let output = args
+        .output
+        .as_ref()
+        .ok_or_else(|| RenderError::InvalidParameter("bake requires --output".to_string()))?;
+    let scene = load_scene(args, 1., &settings, None)?;
+    let layouts: Vec<Vec<Option<SurfacePoint>>> = scene
+        .world
+        .members
+        .iter()
+        .filter_map(|member| bake::surface_points(member.as_mesh()?, size, size))
+        .collect();
+    if layouts.is_empty() {
+        return Err(RenderError::InvalidParameter(
+            "bake requires meshes with texture coordinates, e.g. of --obj".to_string(),
+        ));
+    }
+    let renderer = Renderer::new(RenderSettings {
+        image_width: size,
+        image_height: size,
+        ..settings
+    });
+    for (index, points) in layouts.iter().enumerate() {
+        eprintln!("Mesh {} of {}:", index + 1, layouts.len());
+        let film = bake::dilate(&renderer.bake(&scene, points), points, BAKE_DILATION);
+        let path = if layouts.len() > 1 {
+            suffixed_path(output, &index.to_string())
+        } else {
+            output.clone()
+        };
+        write_images(&film, &Some(path), &args.tone_mappings)?;
+    }
+    Ok(())
+}
+
+/// Captures the light at the points given by `probes` into light probes and writes them to `--output`
+/// as `{"basis": ..., "probes": [{"position": [x, y, z], "irradiance": [[r, g, b], ...]}, ...]}`,
+/// where the nine coefficients of each follow the order of `basis`.
+fn capture_probes(args: &Args, settings: RenderSettings) -> Result<(), RenderError> {
+    let output = args
+        .output
+        .as_ref()
+        .ok_or_else(|| RenderError::InvalidParameter("probes requires --output".to_string()))?;
+    let scene = load_scene(args, 1., &settings, None)?;
+    let probes = Renderer::new(settings).capture_probes(&scene, &args.probe_positions);
+    let basis = [
+        "0,0", "1,-1", "1,0", "1,1", "2,-2", "2,-1", "2,0", "2,1", "2,2",
+    ]
+    .iter()
+    .map(|lm| Json::String(format!("l,m={}", lm)))
+    .collect();
+    let json = Json::Object(vec![
+        ("basis".to_string(), Json::Array(basis)),
+        (
+            "probes".to_string(),
+            Json::Array(probes.iter().map(LightProbe::to_json).collect()),
+        ),
+    ]);
+    std::fs::write(output, json.pretty() + "\n").map_err(|e| RenderError::io(output, e))
+}
+
+/// Renders the scene once for each cell of the contact sheet of `sweep`, where the materials
+/// have the parameters of the cell, and writes the sheet of the images to `--output`.
+/// The objects of the scene and its BVH are built once and shared by all the cells.
+fn render_sweep(
+    args: &Args,
+    aspect_ratio: Float,
+    settings: RenderSettings,
+) -> Result<(), RenderError> {
+    let mut scene = load_scene(args, aspect_ratio, &settings, None)?;
+    let objects: Vec<Arc<dyn Hittable>> = std::mem::take(&mut scene.world.members)
+        .into_iter()
+        .map(Arc::from)
+        .collect();
+    let renderer = Renderer::new(settings);
+    let cells = sweep_cells(&args.sweep_axes);
+    let mut films = vec![];
+    for (index, values) in cells.iter().enumerate() {
+        if !args.quiet {
+            let description: Vec<String> = values
+                .iter()
+                .map(|(parameter, value)| format!("{}={}", parameter.name(), value))
+                .collect();
+            eprintln!(
+                "Cell {} of {} ({}):",
+                index + 1,
+                cells.len(),
+                description.join(", ")
+            );
+        }
+        let materials = Arc::new(SweptMaterials::new(values.clone()));
+        scene.world.members = objects
+            .iter()
+            .map(|object| -> Box<dyn Hittable> {
+                Box::new(Swept {
+                    object: object.clone(),
+                    materials: materials.clone(),
+                })
+            })
+            .collect();
+        let mut film = renderer.render(&scene);
+        post_process(args, &scene, &mut film);
+        films.push(film);
+    }
+    let columns = args.sweep_axes[0].steps;
+    write_images(
+        &assemble_sheet(&films, columns),
+        &args.output,
+        &args.tone_mappings,
+    )
+}
+
+fn format_focus_pick(x: usize, y: usize, pick: Option<&FocusPick>) -> String {
+    match pick {
+        Some(pick) => {
+            let point = &pick.point;
+            format!(
+                "The pixel ({}, {}) sees the surface at {}.\n\
+                 focus distance (along the view direction): {:.4}\n\
+                 distance from the camera: {:.4}",
+                x,
+                y,
+                format_xyz(point.x, point.y, point.z),
+                pick.focus_distance,
+                pick.distance
+            )
+        }
+        None => format!("The pixel ({}, {}) sees no surface.", x, y),
+    }
+}
+
+/// Traces the paths of the pixel given by `debug-pixel` and prints them,
+/// and writes them, or those of every `DUMP_RAYS_PIXEL_STRIDE`th pixel without `debug-pixel`,
+/// to the file given by `--dump-rays`. The paths are seeded by `--seed`.
+fn trace_paths(
+    args: &Args,
+    aspect_ratio: Float,
+    settings: RenderSettings,
+) -> Result<(), RenderError> {
+    let (width, height) = (settings.image_width, settings.image_height);
+    let pixels = match args.debug_pixel {
+        Some((x, y)) => {
+            check_pixel(&settings, x, y)?;
+            vec![(x, y)]
+        }
+        None => {
+            let offset = DUMP_RAYS_PIXEL_STRIDE / 2;
+            let rows = (offset..height).step_by(DUMP_RAYS_PIXEL_STRIDE);
+            rows.flat_map(|y| {
+                (offset..width)
+                    .step_by(DUMP_RAYS_PIXEL_STRIDE)
+                    .map(move |x| (x, y))
+            })
+            .collect()
+        }
+    };
+    let scene = load_scene(args, aspect_ratio, &settings, None)?;
+    let renderer = Renderer::new(RenderSettings {
+        seed: Some(args.scene_params.seed),
+        ..settings
+    });
+    let traced: Vec<((usize, usize), Vec<TracedPath>)> = pixels
+        .into_iter()
+        .map(|(x, y)| ((x, y), renderer.trace_pixel(&scene, x, y)))
+        .collect();
+    if args.debug_pixel.is_some() {
+        for (index, path) in traced.iter().flat_map(|(_, paths)| paths).enumerate() {
+            println!("{}", format_traced_path(index, path));
+        }
+    }
+    if let Some(path) = &args.dump_rays {
+        // The rays that hit nothing are drawn as long as the longest ray that hits something.
+        let escaped_length = traced
+            .iter()
+            .flat_map(|(_, paths)| paths)
+            .flat_map(|path| path.bounces.iter())
+            .filter_map(|bounce| match bounce {
+                Bounce::Hit { hit, .. } => Some(hit.t),
+                _ => None,
+            })
+            .fold(1., Float::max);
+        let mut polylines = vec![];
+        for ((x, y), paths) in traced.iter() {
+            for (index, traced_path) in paths.iter().enumerate() {
+                let name = format!("pixel_{}_{}_sample_{}", x, y, index + 1);
+                polylines.push((name, traced_path.points(escaped_length)));
+            }
+        }
+        let file = File::create(path).map_err(|e| RenderError::io(path, e))?;
+        obj::write_polylines(&mut BufWriter::new(file), &polylines)
+            .map_err(|e| RenderError::io(path, e))?;
+        eprintln!("Wrote {} paths to {}.", polylines.len(), path.display());
+    }
+    Ok(())
+}
+
+/// Prints the summary of the NaN or infinite samples in `film` if `settings.check_samples` is set.
+fn report_invalid_samples(film: &Film, settings: &RenderSettings) {
+    if settings.check_samples {
+        eprintln!("{}", format_invalid_samples(film));
+    }
+}
+
+/// Prints the statistics of the objects and the materials in `film` if `settings.collect_stats` is set.
+fn report_stats(film: &Film, settings: &RenderSettings) {
+    if settings.collect_stats {
+        eprintln!("{}", format_stats(film.stats()));
+    }
+}
+
+/// Lists the `MAX_LISTED_STATS` objects that take the longest in intersection tests
+/// and the `MAX_LISTED_STATS` materials that take the longest in shading,
+/// followed by the numbers of the paths by their numbers of bounces if there are any.
+fn format_stats(stats: &RenderStats) -> String {
+    let percentage = |time: Duration, total: Duration| {
+        if total.is_zero() {
+            0.
+        } else {
+            100. * time.as_secs_f64() / total.as_secs_f64()
+        }
+    };
+    let mut objects: Vec<(usize, &ObjectStats)> = stats.objects.iter().enumerate().collect();
+    objects.sort_by_key(|(_, object)| Reverse(object.intersection_time));
+    let total: Duration = objects.iter().map(|(_, o)| o.intersection_time).sum();
+    let mut lines = vec![
+        "Objects by intersection time:".to_string(),
+        format!(
+            "  {:>8} {:>12} {:>12} {:>10}",
+            "object", "tests", "hits", "time"
+        ),
+    ];
+    for (index, object) in objects.iter().take(MAX_LISTED_STATS) {
+        lines.push(format!(
+            "  {:>8} {:>12} {:>12} {:>9.3}s {:>5.1}%",
+            format!("#{}", index),
+            object.num_tests,
+            object.num_hits,
+            object.intersection_time.as_secs_f64(),
+            percentage(object.intersection_time, total)
+        ));
+    }
+    if objects.len() > MAX_LISTED_STATS {
+        lines.push(format!(
+            "  and {} more objects",
+            objects.len() - MAX_LISTED_STATS
+        ));
+    }
+
+    let materials = stats.materials();
+    let total: Duration = materials.iter().map(|m| m.shading_time).sum();
+    lines.push("Materials by shading time:".to_string());
+    lines.push(format!("  {:>12} {:>10}         material", "hits", "time"));
+    for material in materials.iter().take(MAX_LISTED_STATS) {
+        lines.push(format!(
+            "  {:>12} {:>9.3}s {:>5.1}%  {:?}",
+            material.num_hits,
+            material.shading_time.as_secs_f64(),
+            percentage(material.shading_time, total),
+            material.material
+        ));
+    }
+    if materials.len() > MAX_LISTED_STATS {
+        lines.push(format!(
+            "  and {} more materials",
+            materials.len() - MAX_LISTED_STATS
+        ));
+    }
+
+    let num_paths: u64 = stats.path_lengths.iter().sum();
+    if num_paths > 0 {
+        lines.push("Paths by bounces:".to_string());
+        lines.push(format!(
+            "  {:>8} {:>12}        {:>12}",
+            "bounces", "paths", "terminated"
+        ));
+        for (length, &count) in stats.path_lengths.iter().enumerate() {
+            let terminated = stats.terminated_path_lengths.get(length).unwrap_or(&0);
+            lines.push(format!(
+                "  {:>8} {:>12} {:>5.1}% {:>12}",
+                length,
+                count,
+                100. * count as f64 / num_paths as f64,
+                terminated
+            ));
+        }
+    }
+    lines.join("\n")
+}
+
+/// Summarizes the NaN or infinite samples in `film`, grouping them by where they were produced.
+fn format_invalid_samples(film: &Film) -> String {
+    if film.num_invalid_samples() == 0 {
+        return "No NaN or infinite samples were found.".to_string();
+    }
+    // The groups are in the order of their first samples.
+    let mut groups: Vec<(String, usize, &InvalidSample)> = vec![];
+    for sample in film.invalid_samples() {
+        let source = match &sample.bounce {
+            Bounce::Hit { material, .. } => format!("on {:?}", material),
+            Bounce::Background { .. } => "by the background".to_string(),
+            Bounce::DepthExceeded { .. } => "by the ray beyond the maximum depth".to_string(),
+        };
+        let source = format!("at bounce {} {}", sample.depth + 1, source);
+        match groups.iter_mut().find(|(s, _, _)| *s == source) {
+            Some((_, count, _)) => *count += 1,
+            None => groups.push((source, 1, sample)),
+        }
+    }
+    let num_kept = film.invalid_samples().len();
+    let mut lines = vec![format!(
+        "Found {} NaN or infinite samples, whose pixels are painted magenta.",
+        film.num_invalid_samples()
+    )];
+    if num_kept < film.num_invalid_samples() {
+        lines.push(format!("The first {} of them were produced:", num_kept));
+    }
+    for (source, count, first) in groups {
+        let location = match &first.bounce {
+            Bounce::Hit { ray, hit, .. } => {
+                let point = ray.at(hit.t);
+                format!(" at {}", format_xyz(point.x, point.y, point.z))
+            }
+            _ => String::new(),
+        };
+        lines.push(format!(
+            "  {} samples {}, first in the pixel ({}, {}){}",
+            count, source, first.x, first.y, location
+        ));
+    }
+    lines.join("\n")
+}
+
+fn format_xyz(x: Float, y: Float, z: Float) -> String {
+    format!("({:.4}, {:.4}, {:.4})", x, y, z)
+}
+
+/// Formats the `index`-th path of `debug-pixel` with one line per bounce followed by the details.
+fn format_traced_path(index: usize, path: &TracedPath) -> String {
+    let color = &path.color;
+    let mut lines = vec![format!(
+        "sample {}: color {}",
+        index + 1,
+        format_xyz(color.r, color.g, color.b)
+    )];
+    for (depth, bounce) in path.bounces.iter().enumerate() {
+        let prefix = format!("  bounce {}: ", depth + 1);
+        let indent = " ".repeat(prefix.len());
+        match bounce {
+            Bounce::Hit {
+                ray,
+                hit,
+                material,
+                emitted,
+                direct,
+                scattered,
+            } => {
+                let point = ray.at(hit.t);
+                let normal = hit.surface_normal.inject();
+                let side = if hit.surface_normal.inner_product(&ray.direction) < 0. {
+                    "entering"
+                } else {
+                    "leaving"
+                };
+                lines.push(format!(
+                    "{}hit at t = {:.4}, point {}, normal {}, {}",
+                    prefix,
+                    hit.t,
+                    format_xyz(point.x, point.y, point.z),
+                    format_xyz(normal.x, normal.y, normal.z),
+                    side
+                ));
+                lines.push(format!("{}material {:?}", indent, material));
+                let outcome = match scattered {
+                    Some(Scattered { attenuation, ray }) => {
+                        let direction = ray.direction.inject();
+                        format!(
+                            "attenuation {}, scattered to {}",
+                            format_xyz(attenuation.r, attenuation.g, attenuation.b),
+                            format_xyz(direction.x, direction.y, direction.z)
+                        )
+                    }
+                    None => "absorbed".to_string(),
+                };
+                lines.push(format!(
+                    "{}emitted {}, direct light {}, {}",
+                    indent,
+                    format_xyz(emitted.r, emitted.g, emitted.b),
+                    format_xyz(direct.r, direct.g, direct.b),
+                    outcome
+                ));
+            }
+            Bounce::Background { color, .. } => lines.push(format!(
+                "{}missed, background {}",
+                prefix,
+                format_xyz(color.r, color.g, color.b)
+            )),
+            Bounce::DepthExceeded { .. } => {
+                lines.push(format!("{}not traced beyond the maximum depth", prefix))
+            }
+        }
+    }
+    lines.join("\n")
+}
+
+/// Parses frame ranges of the form `<first>..<last>`, where both ends are inclusive.
+fn parse_frame_range(s: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("invalid frame range (expected <first>..<last>): {}", s);
+    let (first, last) = s.split_once("..").ok_or_else(invalid)?;
+    let first: u32 = first.parse().map_err(|_| invalid())?;
+    let last: u32 = last.parse().map_err(|_| invalid())?;
+    if first == 0 || first > last {
+        return Err(invalid());
+    }
+    Ok((first, last))
+}
+
+/// Parses the values separated by commas given to the option `option`.
+fn parse_list<T: FromStr>(option: &str, s: &str) -> Result<Vec<T>, String> {
+    s.split(',')
+        .map(|value| parse_value(option, Some(value.trim().to_string())))
+        .collect()
+}
+
+/// Parses the point `<x>,<y>,<z>` given to the option `option`.
+fn parse_point(option: &str, s: &str) -> Result<Point3, String> {
+    let invalid = || format!("invalid point for {} (expected <x>,<y>,<z>): {}", option, s);
+    let coordinates: Vec<Float> = s
+        .split(',')
+        .map(|c| c.trim().parse::<Float>().ok().filter(|c| c.is_finite()))
+        .collect::<Option<_>>()
+        .ok_or_else(invalid)?;
+    match coordinates[..] {
+        [x, y, z] => Ok(Point3 { x, y, z }),
+        _ => Err(invalid()),
+    }
+}
+
+/// Parses the survival probabilities of the rays scattered at each bounce, separated by commas.
+fn parse_roulette(s: &str) -> Result<Vec<Float>, String> {
+    s.split(',')
+        .map(|p| match p.trim().parse::<Float>() {
+            Ok(p) if p > 0. && p <= 1. => Ok(p),
+            _ => Err(format!(
+                "invalid probability for --roulette (expected a number in (0, 1]): {}",
+                p
+            )),
+        })
+        .collect()
+}
+
+/// Parses durations such as `90s`, `10m`, or `1.5h`, where a number alone is in seconds.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration (expected e.g. 90s, 10m, or 1.5h): {}", s);
+    let (number, unit) = match s.char_indices().last() {
+        Some((i, 's')) => (&s[..i], 1.),
+        Some((i, 'm')) => (&s[..i], 60.),
+        Some((i, 'h')) => (&s[..i], 3600.),
+        _ => (s, 1.),
+    };
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    Duration::try_from_secs_f64(number * unit)
+        .ok()
+        .filter(|duration| !duration.is_zero())
+        .ok_or_else(invalid)
 }
 
 fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            eprintln!("{}", usage());
+            std::process::exit(1);
+        }
+    };
+
     // Constants for the image:
-    let aspect_ratio: f64 = 16.0 / 9.0;
-    let image_width: i32 = 400;
-    let image_height: i32 = ((image_width as f64) / aspect_ratio) as i32;
+    let aspect_ratio: Float = 16.0 / 9.0;
+    let image_width: usize = args.image_width;
+    let image_height: usize = ((image_width as Float) / aspect_ratio) as usize;
 
-    // Constants for the camera:
-    let origin = Point3 {
-        x: 0.,
-        y: 0.,
-        z: 0.5,
+    let settings = RenderSettings {
+        image_width,
+        image_height,
+        num_samples_per_pixel: args.num_samples_per_pixel.unwrap_or(if args.watch {
+            WATCH_NUM_SAMPLES_PER_PIXEL
+        } else if args.debug_pixel.is_some() || args.dump_rays.is_some() {
+            DEBUG_PIXEL_NUM_SAMPLES_PER_PIXEL
+        } else if args.time_limit.is_some() {
+            TIME_LIMIT_NUM_SAMPLES_PER_PIXEL
+        } else if !args.probe_positions.is_empty() {
+            PROBE_NUM_SAMPLES
+        } else {
+            NUM_SAMPLES_PER_PIXEL
+        }),
+        max_diffusion_depth: MAX_DIFFUSION_DEPTH,
+        roulette: args.roulette.clone(),
+        num_threads: args.num_threads.unwrap_or_else(|| {
+            std::thread::available_parallelism().map_or(1, |num_cpus| num_cpus.get())
+        }),
+        low_priority: args.low_priority,
+        check_samples: args.check_samples,
+        collect_stats: args.stats,
+        path_guiding: args.guide,
+        adaptive_samples: args.adaptive_samples,
+        seed: args.render_seed,
+        filter: args.filter,
+        filter_sampling: args.filter_sampling,
+        show_progress: !args.quiet,
     };
-    let look_in = Vec3 {
-        x: 0.,
-        y: 0.,
-        z: -1.,
+
+    if let Some(address) = &args.serve_address {
+        if let Err(e) = server::serve(address, settings) {
+            eprintln!("cannot serve on {}: {}", address, e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(address) = &args.worker_address {
+        if let Err(e) = distributed::run_worker(address, settings) {
+            eprintln!("cannot listen on {}: {}", address, e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some((reference, test)) = &args.compare {
+        if let Err(msg) = compare_images(reference, test, &args) {
+            eprintln!("{}", msg);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(path) = &args.export_scene {
+        if let Err(msg) = export_scene(&args, aspect_ratio, &settings, path) {
+            eprintln!("{}", msg);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.depth {
+        if let Err(msg) = render_depth(&args, aspect_ratio, settings) {
+            eprintln!("{}", msg);
+            std::process::exit(1);
+        }
+        return;
     }
-    .unit_vector();
-    let view_up = Vec3 {
-        x: 0.,
-        y: 1.,
-        z: 0.,
+    if let Some(size) = args.cubemap_size {
+        if let Err(msg) = render_cubemap(&args, size, settings) {
+            eprintln!("{}", msg);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(size) = args.bake_size {
+        if let Err(msg) = bake_textures(&args, size, settings) {
+            eprintln!("{}", msg);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if !args.probe_positions.is_empty() {
+        if let Err(msg) = capture_probes(&args, settings) {
+            eprintln!("{}", msg);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if !args.sweep_axes.is_empty() {
+        if let Err(msg) = render_sweep(&args, aspect_ratio, settings) {
+            eprintln!("{}", msg);
+            std::process::exit(1);
+        }
+        report_done(&args);
+        return;
+    }
+    if let Some((x, y)) = args.pick_focus {
+        if let Err(msg) = pick_focus(&args, aspect_ratio, settings, x, y) {
+            eprintln!("{}", msg);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.debug_pixel.is_some() || args.dump_rays.is_some() {
+        if let Err(msg) = trace_paths(&args, aspect_ratio, settings) {
+            eprintln!("{}", msg);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    #[cfg(feature = "preview")]
+    let mut preview = if args.preview {
+        match preview::Preview::open(image_width, image_height) {
+            Ok(preview) => Some(preview),
+            Err(msg) => {
+                eprintln!("{}", msg);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+    install_interrupt_handler();
+    let on_scan_line = |film: &Film| {
+        if is_interrupted() {
+            return false;
+        }
+        #[cfg(feature = "preview")]
+        if let Some(preview) = &mut preview {
+            return preview.update(film);
+        }
+        #[cfg(not(feature = "preview"))]
+        let _ = film;
+        true
     };
 
-    let vertical_fov_radian = std::f64::consts::PI / 1.5;
+    if args.watch {
+        match &args.molecule_path {
+            Some(molecule_path) if args.output.is_some() => {
+                watch(&args, molecule_path, aspect_ratio, &settings, on_scan_line)
+            }
+            _ => {
+                eprintln!("--watch requires --molecule and --output");
+                std::process::exit(1);
+            }
+        }
+    }
 
-    let camera = Camera::new(origin, look_in, view_up, vertical_fov_radian, aspect_ratio);
+    if args.animate || args.turntable.is_some() {
+        let result = match args.turntable {
+            Some(_) if args.animate => Err(RenderError::InvalidParameter(
+                "--animate and --turntable cannot be used together".to_string(),
+            )),
+            Some(num_frames) => turntable(&args, num_frames, aspect_ratio, &settings, on_scan_line),
+            None => animate(&args, aspect_ratio, &settings, on_scan_line),
+        };
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(match e {
+                RenderError::Interrupted { .. } => EXIT_CODE_INTERRUPTED,
+                _ => 1,
+            });
+        }
+        report_done(&args);
+        return;
+    }
 
-    // Constants for antialiasing:
-    let num_samples_per_pixel = 100;
+    let scene = match load_scene(&args, aspect_ratio, &settings, None) {
+        Ok(scene) => scene,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            std::process::exit(1);
+        }
+    };
+    if let Some(band_height) = args.band_height {
+        match render_bands(&args, &scene, &settings, band_height) {
+            Ok(false) => report_done(&args),
+            Ok(true) => {
+                eprintln!("Interrupted; saved the finished bands.");
+                std::process::exit(EXIT_CODE_INTERRUPTED);
+            }
+            Err(msg) => {
+                eprintln!("{}", msg);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    match render_scene(&args, &scene, None, aspect_ratio, &settings, on_scan_line) {
+        Ok(film) => {
+            let written = write_images(&film, &args.output, &args.tone_mappings)
+                .and_then(|()| write_path_lengths(&film, &args.path_lengths_output));
+            if let Err(msg) = written {
+                eprintln!("{}", msg);
+                std::process::exit(1);
+            }
+            report_invalid_samples(&film, &settings);
+            report_stats(&film, &settings);
+            report_done(&args);
+        }
+        Err(film) if is_interrupted() => {
+            report_invalid_samples(&film, &settings);
+            report_stats(&film, &settings);
+            // The scan lines finished so far are saved, and the rest are left black.
+            let written = write_images(&film, &args.output, &args.tone_mappings)
+                .and_then(|()| write_path_lengths(&film, &args.path_lengths_output));
+            if let Err(msg) = written {
+                eprintln!("{}", msg);
+                std::process::exit(1);
+            }
+            eprintln!("Interrupted; saved the partial image.");
+            std::process::exit(EXIT_CODE_INTERRUPTED);
+        }
+        Err(_) => {
+            eprintln!("Aborted.");
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_path_tests() {
+        assert_eq!(
+            PathBuf::from("out/frame_0012.png"),
+            frame_path(Path::new("out/frame_####.png"), 12)
+        );
+        assert_eq!(
+            PathBuf::from("out/frame_3.png"),
+            frame_path(Path::new("out/frame_#.png"), 3)
+        );
+        assert_eq!(
+            PathBuf::from("out/anim_0007.ppm"),
+            frame_path(Path::new("out/anim.ppm"), 7)
+        );
+    }
 
-    // Constants for diffusion:
-    let max_diffusion_depth = 10;
+    #[test]
+    fn suffixed_path_tests() {
+        let tone_mapping = ToneMapping {
+            exposure: -1.,
+            curve: ToneCurve::Reinhard,
+            transfer: None,
+            lut: None,
+        };
+        assert_eq!(
+            PathBuf::from("out/frame_0001_ev-1_reinhard.png"),
+            suffixed_path(Path::new("out/frame_0001.png"), &tone_mapping.name())
+        );
+        assert_eq!(
+            PathBuf::from("sky_px"),
+            suffixed_path(Path::new("sky"), CubeFace::PositiveX.name())
+        );
+        assert_eq!(
+            Ok(vec![-1., 0., 1.5]),
+            parse_list("--exposure", "-1, 0,1.5")
+        );
+        assert!(parse_list::<ToneCurve>("--tone-map", "aces,filmic").is_err());
+    }
+
+    #[test]
+    fn parse_frame_range_tests() {
+        assert_eq!(Ok((1, 48)), parse_frame_range("1..48"));
+        assert_eq!(Ok((5, 5)), parse_frame_range("5..5"));
+        assert!(parse_frame_range("0..3").is_err());
+        assert!(parse_frame_range("3..1").is_err());
+        assert!(parse_frame_range("3").is_err());
+    }
 
-    // Hittable objects:
-    /*
-        let sphere1 = Sphere {
-            center: Point3 {
-                x: -1.,
+    #[test]
+    fn format_invalid_samples_tests() {
+        use try_ray_tracing::geometry::{Point3, Ray, Vec3};
+
+        let mut film = Film::new(4, 4);
+        assert_eq!(
+            "No NaN or infinite samples were found.",
+            format_invalid_samples(&film)
+        );
+        let nan = Color {
+            r: Float::NAN,
+            g: 0.,
+            b: 0.,
+        };
+        let ray = Ray {
+            origin: Point3 {
+                x: 0.,
                 y: 0.,
-                z: -1.,
+                z: 0.,
             },
-            radius: 0.5,
-            material: Box::new(Lambertian {
-                albedo: Attenuation {
-                    r: 0.8,
-                    g: 0.5,
-                    b: 0.5,
-                },
-            }),
-        };
-        let sphere2 = Sphere {
-            center: Point3 {
-                x: 1.,
+            direction: Vec3 {
+                x: 0.,
                 y: 0.,
                 z: -1.,
-            },
-            radius: 0.5,
-            material: Box::new(Metal {
-                albedo: Attenuation {
-                    r: 0.5,
-                    g: 0.5,
-                    b: 0.5,
+            }
+            .unit_vector(),
+        };
+        for (x, depth) in [(1, 2), (2, 0), (3, 2)] {
+            film.add_invalid_sample(InvalidSample {
+                x,
+                y: 0,
+                color: nan.clone(),
+                depth,
+                bounce: Bounce::Background {
+                    ray: ray.clone(),
+                    color: nan.clone(),
                 },
-                fuzz: 0.3,
-            }),
+            });
+        }
+        assert_eq!(
+            "Found 3 NaN or infinite samples, whose pixels are painted magenta.\n  \
+             2 samples at bounce 3 by the background, first in the pixel (1, 0)\n  \
+             1 samples at bounce 1 by the background, first in the pixel (2, 0)",
+            format_invalid_samples(&film)
+        );
+    }
+
+    #[test]
+    fn parse_roulette_tests() {
+        assert_eq!(Ok(vec![1., 0.5, 0.25]), parse_roulette("1,0.5, 0.25"));
+        assert!(parse_roulette("1,0").is_err());
+        assert!(parse_roulette("1.5").is_err());
+        assert!(parse_roulette("1,,0.5").is_err());
+        assert!(parse_roulette("NaN").is_err());
+    }
+
+    #[test]
+    fn parse_point_tests() {
+        let expected = Point3 {
+            x: 0.,
+            y: 5.,
+            z: -1.5,
         };
-        let sphere3 = Sphere {
-            center: Point3 {
+        assert_eq!(Ok(expected), parse_point("--look-from", "0, 5,-1.5"));
+        assert!(parse_point("--look-from", "0,5").is_err());
+        assert!(parse_point("--look-from", "0,5,1,2").is_err());
+        assert!(parse_point("--look-from", "0,inf,1").is_err());
+    }
+
+    #[test]
+    fn parse_duration_tests() {
+        assert_eq!(Ok(Duration::from_secs(90)), parse_duration("90s"));
+        assert_eq!(Ok(Duration::from_secs(90)), parse_duration("90"));
+        assert_eq!(Ok(Duration::from_secs(600)), parse_duration("10m"));
+        assert_eq!(Ok(Duration::from_secs(5400)), parse_duration("1.5h"));
+        assert!(parse_duration("0s").is_err());
+        assert!(parse_duration("-1m").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("10d").is_err());
+        assert!(parse_duration("").is_err());
+    }
+
+    #[test]
+    fn format_stats_tests() {
+        let mut stats = RenderStats::default();
+        stats.objects = (0..12)
+            .map(|i| ObjectStats {
+                num_tests: 100,
+                num_hits: i,
+                intersection_time: Duration::from_millis(i),
+            })
+            .collect();
+        let text = format_stats(&stats);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            vec![
+                "Objects by intersection time:",
+                "    object        tests         hits       time",
+                "       #11          100           11     0.011s  16.7%",
+                "       #10          100           10     0.010s  15.2%",
+            ],
+            lines[..4]
+        );
+        assert_eq!("  and 2 more objects", lines[12]);
+        assert_eq!("Materials by shading time:", lines[13]);
+        assert_eq!(15, lines.len());
+
+        stats.path_lengths = vec![1, 2, 0, 1];
+        stats.terminated_path_lengths = vec![0, 1];
+        let text = format_stats(&stats);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            vec![
+                "Paths by bounces:",
+                "   bounces        paths          terminated",
+                "         0            1  25.0%            0",
+                "         1            2  50.0%            1",
+                "         2            0   0.0%            0",
+                "         3            1  25.0%            0",
+            ],
+            lines[15..]
+        );
+    }
+
+    #[test]
+    fn format_traced_path_tests() {
+        use try_ray_tracing::color::{Attenuation, Color};
+        use try_ray_tracing::geometry::{Point3, Ray, Vec3};
+        use try_ray_tracing::hittable_object::{HitRecord, Lambertian};
+
+        let ray = |z: Float| Ray {
+            origin: Point3 { x: 0., y: 0., z },
+            direction: Vec3 {
                 x: 0.,
                 y: 0.,
                 z: -1.,
+            }
+            .unit_vector(),
+        };
+        let path = TracedPath {
+            color: Color {
+                r: 0.5,
+                g: 0.5,
+                b: 0.5,
             },
-            radius: 0.5,
-            material: Box::new(Glass {
-                eta: 1.5,
-                albedo: Attenuation {
-                    r: 0.9,
-                    g: 0.9,
-                    b: 0.9,
+            bounces: vec![
+                Bounce::Hit {
+                    ray: ray(1.),
+                    hit: HitRecord {
+                        t: 1.,
+                        surface_normal: Vec3 {
+                            x: 0.,
+                            y: 0.,
+                            z: 1.,
+                        }
+                        .unit_vector(),
+                        u: 0.,
+                        v: 0.,
+                    },
+                    material: std::sync::Arc::new(Lambertian {
+                        albedo: Attenuation {
+                            r: 0.5,
+                            g: 0.5,
+                            b: 0.5,
+                        },
+                    }),
+                    emitted: Color {
+                        r: 0.,
+                        g: 0.,
+                        b: 0.,
+                    },
+                    direct: Color {
+                        r: 0.25,
+                        g: 0.25,
+                        b: 0.25,
+                    },
+                    scattered: Some(Scattered {
+                        attenuation: Attenuation {
+                            r: 0.5,
+                            g: 0.5,
+                            b: 0.5,
+                        },
+                        ray: ray(0.),
+                    }),
                 },
-            }),
+                Bounce::Background {
+                    ray: ray(0.),
+                    color: Color {
+                        r: 1.,
+                        g: 1.,
+                        b: 1.,
+                    },
+                },
+            ],
         };
-    */
-    let ground = Sphere {
-        center: Point3 {
-            x: 0.,
-            y: -100.5,
-            z: -1.,
-        },
-        radius: 100.,
-        material: Box::new(Lambertian {
-            albedo: Attenuation {
-                r: 0.2,
-                g: 0.4,
-                b: 0.2,
-            },
-        }),
-    };
-    let (x1, y1, z1) = (0f64, 0f64, -1f64);
-    let len_oh = 0.11;
-    let len_ch = 0.14;
-    let len_co = 0.2;
-    let hittable_list = HittableList {
-        members: vec![
-            carbon(x1, y1, z1),
-            oxygen(x1 + len_co, y1 + len_co, z1 + len_co),
-            hydrogen(
-                x1 + len_co + len_oh,
-                y1 + len_co - len_oh,
-                z1 + len_co + len_oh,
-            ),
-            hydrogen(x1 + len_ch, y1 - len_ch, z1 - len_ch),
-            hydrogen(x1 - len_ch, y1 - len_ch, z1 + len_ch),
-            hydrogen(x1 - len_ch, y1 + len_ch, z1 - len_ch),
-            Box::new(ground),
-        ],
-    };
-
-    // Rendering operations:
-    println!("P3");
-    println!("{} {}", image_width, image_height);
-    println!("255");
-    for j in (0..image_height).rev() {
-        eprintln!("Scan lines remaining: {}", j + 1);
-        for i in 0..image_width {
-            let mut colors: Vec<Color> = vec![];
-            for _ in 0..num_samples_per_pixel {
-                let u: f64 = (i as f64 + random_double()) / ((image_width - 1) as f64);
-                let v: f64 = (j as f64 + random_double()) / ((image_height - 1) as f64);
-                let ray = camera.get_ray(u, v);
-                let color = ray_color(&ray, &hittable_list, max_diffusion_depth);
-                colors.push(color);
-            }
-            let color = Color::average(&colors);
-            filter_color(&color).write();
-        }
-    }
-    eprintln!("Done.");
+        let lines: Vec<String> = format_traced_path(0, &path)
+            .lines()
+            .map(|line| line.to_string())
+            .collect();
+        assert_eq!(
+            vec![
+                "sample 1: color (0.5000, 0.5000, 0.5000)",
+                "  bounce 1: hit at t = 1.0000, point (0.0000, 0.0000, 0.0000), normal (0.0000, 0.0000, 1.0000), entering",
+                "            material Lambertian { albedo: Spectrum { r: 0.5, g: 0.5, b: 0.5 } }",
+                "            emitted (0.0000, 0.0000, 0.0000), direct light (0.2500, 0.2500, 0.2500), attenuation (0.5000, 0.5000, 0.5000), scattered to (0.0000, 0.0000, -1.0000)",
+                "  bounce 2: missed, background (1.0000, 1.0000, 1.0000)",
+            ],
+            lines
+        );
+    }
 }
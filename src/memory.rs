@@ -0,0 +1,76 @@
+use crate::color::Color;
+use crate::geometry::Float;
+
+/// Returns the estimated number of bytes needed for a film of `width` x `height` pixels,
+/// including the weight of the samples of each pixel and the 8-bit image produced when it is written out.
+pub fn film_memory_size(width: usize, height: usize) -> usize {
+    let bytes_per_pixel = std::mem::size_of::<Color>() + std::mem::size_of::<Float>() + 3;
+    width * height * bytes_per_pixel
+}
+
+/// Parses sizes in bytes with an optional binary suffix, e.g. `512M` or `2G`.
+pub fn parse_size(s: &str) -> Result<usize, String> {
+    let invalid = || format!("invalid size (expected e.g. 512M or 2G): {}", s);
+    let (number, unit) = match s.char_indices().find(|(_, c)| c.is_ascii_alphabetic()) {
+        Some((index, _)) => s.split_at(index),
+        None => (s, ""),
+    };
+    let multiplier: usize = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        _ => return Err(invalid()),
+    };
+    let number: f64 = number.trim().parse().map_err(|_| invalid())?;
+    if !(number >= 0. && number.is_finite()) {
+        return Err(invalid());
+    }
+    Ok((number * multiplier as f64) as usize)
+}
+
+/// Formats sizes in bytes with binary prefixes, e.g. `1.50 MiB`.
+pub fn format_size(bytes: usize) -> String {
+    let bytes_f64 = bytes as f64;
+    if bytes >= 1 << 30 {
+        format!("{:.2} GiB", bytes_f64 / (1u64 << 30) as f64)
+    } else if bytes >= 1 << 20 {
+        format!("{:.2} MiB", bytes_f64 / (1u64 << 20) as f64)
+    } else if bytes >= 1 << 10 {
+        format!("{:.2} KiB", bytes_f64 / (1u64 << 10) as f64)
+    } else {
+        format!("{} B", bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn film_memory_size_tests() {
+        // Each pixel has the sum of the colors and the weight of its samples, and 3 bytes in the image.
+        let bytes_per_pixel = 4 * std::mem::size_of::<Float>() + 3;
+        assert_eq!(6 * bytes_per_pixel, film_memory_size(2, 3));
+        #[cfg(not(feature = "f32"))]
+        assert_eq!(1920 * 1080 * 35, film_memory_size(1920, 1080));
+    }
+
+    #[test]
+    fn parse_size_tests() {
+        assert_eq!(Ok(1000), parse_size("1000"));
+        assert_eq!(Ok(512 << 20), parse_size("512M"));
+        assert_eq!(Ok(3 << 29), parse_size("1.5GiB"));
+        assert_eq!(Ok(4 << 10), parse_size("4k"));
+        assert!(parse_size("12X").is_err());
+        assert!(parse_size("-1M").is_err());
+        assert!(parse_size("M").is_err());
+    }
+
+    #[test]
+    fn format_size_tests() {
+        assert_eq!("999 B", format_size(999));
+        assert_eq!("1.50 KiB", format_size(1536));
+        assert_eq!("2.00 GiB", format_size(2 << 30));
+    }
+}
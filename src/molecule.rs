@@ -0,0 +1,637 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::color::Attenuation;
+use crate::error::RenderError;
+use crate::geometry::{Float, Point3, Vec3};
+use crate::hittable_object::{
+    Cylinder, Glass, Hittable, Lambertian, Metal, SharedMaterial, Sphere, Toon,
+    TOON_LIGHT_DIRECTION,
+};
+
+/// The ratio of the radius of atoms to their van der Waals radius in ball-and-stick models.
+const BALL_RADIUS_RATIO: Float = 0.25;
+
+/// The radius of bond cylinders in angstroms.
+const BOND_RADIUS: Float = 0.12;
+
+/// The radius of both atoms and bonds in wireframe models.
+const WIRE_RADIUS: Float = 0.05;
+
+/// Two atoms are regarded as bonded if their distance is at most
+/// this ratio times the sum of their covalent radii.
+const BOND_TOLERANCE: Float = 1.2;
+
+/// The type for the properties of chemical elements used for rendering.
+/// Radii are in angstroms.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ElementProperties {
+    pub covalent_radius: Float,
+    pub van_der_waals_radius: Float,
+    /// The CPK color of the element.
+    pub color: Attenuation,
+    pub metallic: bool,
+}
+
+/// Returns the properties of the element whose symbol is `symbol` (case-insensitive).
+/// Unknown elements are rendered as large pink atoms.
+pub fn element_properties(symbol: &str) -> ElementProperties {
+    let (covalent_radius, van_der_waals_radius, (r, g, b), metallic) =
+        match normalize_symbol(symbol).as_str() {
+            "H" => (0.31, 1.20, (1., 1., 1.), false),
+            "C" => (0.76, 1.70, (0.3, 0.3, 0.3), false),
+            "N" => (0.71, 1.55, (0.19, 0.31, 0.97), false),
+            "O" => (0.66, 1.52, (1., 0.05, 0.05), false),
+            "F" => (0.57, 1.47, (0.56, 0.88, 0.31), false),
+            "P" => (1.07, 1.80, (1., 0.5, 0.), false),
+            "S" => (1.05, 1.80, (1., 1., 0.19), false),
+            "Cl" => (1.02, 1.75, (0.12, 0.94, 0.12), false),
+            "Br" => (1.20, 1.85, (0.65, 0.16, 0.16), false),
+            "I" => (1.39, 1.98, (0.58, 0., 0.58), false),
+            "Na" => (1.66, 2.27, (0.67, 0.36, 0.95), true),
+            "Mg" => (1.41, 1.73, (0.54, 1., 0.), true),
+            "Ca" => (1.76, 2.31, (0.24, 1., 0.), true),
+            "Fe" => (1.32, 2.04, (0.88, 0.4, 0.2), true),
+            "Cu" => (1.32, 1.40, (0.78, 0.5, 0.2), true),
+            "Zn" => (1.22, 1.39, (0.49, 0.5, 0.69), true),
+            _ => (1.0, 2.0, (1., 0.08, 0.58), false),
+        };
+    ElementProperties {
+        covalent_radius,
+        van_der_waals_radius,
+        color: Attenuation { r, g, b },
+        metallic,
+    }
+}
+
+/// Converts element symbols like `CL` or `cl` into the standard form `Cl`.
+fn normalize_symbol(symbol: &str) -> String {
+    let mut chars = symbol.trim().chars();
+    match chars.next() {
+        None => String::new(),
+        Some(first) => first
+            .to_uppercase()
+            .chain(chars.flat_map(char::to_lowercase))
+            .collect(),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Atom {
+    /// The symbol of the element in the standard form (e.g. `Cl`).
+    pub element: String,
+    /// The position in angstroms.
+    pub position: Point3,
+}
+impl Atom {
+    pub fn properties(&self) -> ElementProperties {
+        element_properties(&self.element)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Molecule {
+    pub atoms: Vec<Atom>,
+}
+impl Molecule {
+    /// Loads a molecule from an XYZ file (`*.xyz`) or a PDB file (`*.pdb`).
+    pub fn load(path: &Path) -> Result<Self, RenderError> {
+        let text = std::fs::read_to_string(path).map_err(|e| RenderError::io(path, e))?;
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+        let result = match extension.as_deref() {
+            Some("xyz") => Self::parse_xyz(&text),
+            Some("pdb") => Self::parse_pdb(&text),
+            _ => Err("unknown molecule format (expected *.xyz or *.pdb)".to_string()),
+        };
+        result.map_err(|message| RenderError::SceneParse {
+            path: path.to_path_buf(),
+            message,
+        })
+    }
+
+    /// Parses the XYZ format, i.e., the number of atoms, a comment line,
+    /// and then lines of the form `<element> <x> <y> <z>`.
+    pub fn parse_xyz(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines().enumerate();
+        let num_atoms: usize = match lines.next() {
+            Some((_, line)) => line
+                .trim()
+                .parse()
+                .map_err(|_| format!("line 1: invalid number of atoms: {}", line.trim()))?,
+            None => return Err("empty file".to_string()),
+        };
+        lines.next(); // Skips the comment line.
+
+        let mut atoms = vec![];
+        for (index, line) in lines {
+            if atoms.len() == num_atoms {
+                break;
+            }
+            let line_number = index + 1;
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 4 {
+                return Err(format!(
+                    "line {}: expected `<element> <x> <y> <z>`",
+                    line_number
+                ));
+            }
+            let coordinate = |s: &str| {
+                s.parse::<Float>()
+                    .map_err(|_| format!("line {}: invalid coordinate: {}", line_number, s))
+            };
+            atoms.push(Atom {
+                element: normalize_symbol(fields[0]),
+                position: Point3 {
+                    x: coordinate(fields[1])?,
+                    y: coordinate(fields[2])?,
+                    z: coordinate(fields[3])?,
+                },
+            });
+        }
+        if atoms.len() < num_atoms {
+            return Err(format!(
+                "expected {} atoms but found only {}",
+                num_atoms,
+                atoms.len()
+            ));
+        }
+        Ok(Self { atoms })
+    }
+
+    /// Parses `ATOM` and `HETATM` records of the PDB format.
+    /// Only the first model is read if the file contains several ones.
+    pub fn parse_pdb(text: &str) -> Result<Self, String> {
+        let mut atoms = vec![];
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            if line.starts_with("ENDMDL") {
+                break;
+            }
+            if !(line.starts_with("ATOM  ") || line.starts_with("HETATM")) {
+                continue;
+            }
+            let column = |start: usize, end: usize| line.get(start..end).unwrap_or("").trim();
+            let coordinate = |start: usize, end: usize| {
+                column(start, end).parse::<Float>().map_err(|_| {
+                    format!(
+                        "line {}: invalid coordinate in columns {}-{}",
+                        line_number,
+                        start + 1,
+                        end
+                    )
+                })
+            };
+            let element = {
+                let element = column(76, 78);
+                if element.is_empty() {
+                    // Old files lack the element column; the element is then right-justified
+                    // in the first two columns of the atom name.
+                    let name = line.get(12..16).unwrap_or("");
+                    if name.starts_with(' ') {
+                        name.get(1..2).unwrap_or("").to_string()
+                    } else {
+                        name.get(0..2).unwrap_or("").to_string()
+                    }
+                } else {
+                    element.to_string()
+                }
+            };
+            if element.trim().is_empty() {
+                return Err(format!("line {}: missing element", line_number));
+            }
+            atoms.push(Atom {
+                element: normalize_symbol(&element),
+                position: Point3 {
+                    x: coordinate(30, 38)?,
+                    y: coordinate(38, 46)?,
+                    z: coordinate(46, 54)?,
+                },
+            });
+        }
+        if atoms.is_empty() {
+            return Err("no ATOM or HETATM records".to_string());
+        }
+        Ok(Self { atoms })
+    }
+
+    /// Infers bonds from the distances between atoms.
+    /// Returns the pairs of the indices of bonded atoms in ascending order.
+    pub fn bonds(&self) -> Vec<(usize, usize)> {
+        let radii: Vec<Float> = self
+            .atoms
+            .iter()
+            .map(|atom| atom.properties().covalent_radius)
+            .collect();
+        // Atoms are put into the cells of a grid as large as the longest possible bond
+        // so that only the atoms in the neighboring cells are compared, which keeps proteins tractable.
+        let cell_size = BOND_TOLERANCE * 2. * radii.iter().copied().fold(0., Float::max);
+        let cell_of = |position: &Point3| {
+            [position.x, position.y, position.z].map(|x| (x / cell_size).floor() as i64)
+        };
+        let mut cells: HashMap<[i64; 3], Vec<usize>> = HashMap::new();
+        for (i, atom) in self.atoms.iter().enumerate() {
+            cells.entry(cell_of(&atom.position)).or_default().push(i);
+        }
+        let mut bonds = vec![];
+        for (i, atom1) in self.atoms.iter().enumerate() {
+            let [x, y, z] = cell_of(&atom1.position);
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        let cell = [x.wrapping_add(dx), y.wrapping_add(dy), z.wrapping_add(dz)];
+                        let Some(others) = cells.get(&cell) else {
+                            continue;
+                        };
+                        for &j in others.iter().filter(|&&j| j > i) {
+                            let distance = (&atom1.position - &self.atoms[j].position).length();
+                            if distance <= BOND_TOLERANCE * (radii[i] + radii[j]) {
+                                bonds.push((i, j));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        bonds.sort_unstable();
+        bonds
+    }
+
+    /// Returns the center of the atoms.
+    pub fn centroid(&self) -> Point3 {
+        let origin = Point3 {
+            x: 0.,
+            y: 0.,
+            z: 0.,
+        };
+        let mut sum = Vec3 {
+            x: 0.,
+            y: 0.,
+            z: 0.,
+        };
+        for atom in self.atoms.iter() {
+            sum += &atom.position - &origin;
+        }
+        origin + sum / self.atoms.len() as Float
+    }
+
+    /// Returns the radius of a sphere around the centroid
+    /// that contains all the atoms drawn in `style`.
+    pub fn bounding_radius(&self, style: MoleculeStyle) -> Float {
+        let centroid = self.centroid();
+        self.atoms
+            .iter()
+            .map(|atom| {
+                (&atom.position - &centroid).length() + style.atom_radius(&atom.properties())
+            })
+            .fold(0., Float::max)
+    }
+
+    /// Builds a model of the molecule in `options.style`, colored by the CPK convention.
+    /// Each bond consists of two cylinders colored as the atoms at their ends.
+    pub fn to_hittables(&self, options: &MoleculeOptions) -> Vec<Box<dyn Hittable>> {
+        // The atoms of each element share the material, which also keeps scene files from listing it for each atom.
+        let mut materials: HashMap<String, SharedMaterial> = HashMap::new();
+        let mut material_of = |atom: &Atom| {
+            materials
+                .entry(atom.element.clone())
+                .or_insert_with(|| {
+                    let properties = atom.properties();
+                    let kind = match options.material_overrides.get(&atom.element) {
+                        Some(kind) => kind.clone(),
+                        None => MaterialKind::default_for(&properties),
+                    };
+                    kind.to_material(&properties.color)
+                })
+                .clone()
+        };
+
+        let mut hittables: Vec<Box<dyn Hittable>> = vec![];
+        for atom in self.atoms.iter() {
+            hittables.push(Box::new(Sphere {
+                center: atom.position.clone(),
+                radius: options.style.atom_radius(&atom.properties()),
+                material: material_of(atom),
+            }));
+        }
+        if let Some(bond_radius) = options.style.bond_radius() {
+            for (i, j) in self.bonds() {
+                let atom1 = &self.atoms[i];
+                let atom2 = &self.atoms[j];
+                let half = (&atom2.position - &atom1.position) * 0.5;
+                hittables.push(Box::new(Cylinder {
+                    base: atom1.position.clone(),
+                    axis: half.clone(),
+                    radius: bond_radius,
+                    material: material_of(atom1),
+                }));
+                hittables.push(Box::new(Cylinder {
+                    base: &atom1.position + &half,
+                    axis: half,
+                    radius: bond_radius,
+                    material: material_of(atom2),
+                }));
+            }
+        }
+        hittables
+    }
+}
+
+/// The ways of drawing molecules.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MoleculeStyle {
+    /// Small atoms connected by bond cylinders.
+    BallAndStick,
+    /// Atoms of their van der Waals radii without bonds.
+    SpaceFilling,
+    /// Thin bond cylinders only; atoms are as thin as the bonds so that joints look smooth.
+    Wireframe,
+}
+impl MoleculeStyle {
+    fn atom_radius(&self, properties: &ElementProperties) -> Float {
+        match self {
+            MoleculeStyle::BallAndStick => BALL_RADIUS_RATIO * properties.van_der_waals_radius,
+            MoleculeStyle::SpaceFilling => properties.van_der_waals_radius,
+            MoleculeStyle::Wireframe => WIRE_RADIUS,
+        }
+    }
+
+    fn bond_radius(&self) -> Option<Float> {
+        match self {
+            MoleculeStyle::BallAndStick => Some(BOND_RADIUS),
+            MoleculeStyle::SpaceFilling => None,
+            MoleculeStyle::Wireframe => Some(WIRE_RADIUS),
+        }
+    }
+}
+impl FromStr for MoleculeStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ball-and-stick" => Ok(MoleculeStyle::BallAndStick),
+            "space-filling" => Ok(MoleculeStyle::SpaceFilling),
+            "wireframe" => Ok(MoleculeStyle::Wireframe),
+            _ => Err(format!(
+                "unknown molecule style: {} (expected ball-and-stick, space-filling, or wireframe)",
+                s
+            )),
+        }
+    }
+}
+
+/// The kinds of materials that atoms can be made of.
+/// The albedo is always the CPK color of the element.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MaterialKind {
+    Lambertian,
+    Metal { fuzz: Float },
+    Glass { eta: Float },
+    Toon { bands: usize },
+}
+impl MaterialKind {
+    fn default_for(properties: &ElementProperties) -> Self {
+        if properties.metallic {
+            MaterialKind::Metal { fuzz: 0.2 }
+        } else {
+            MaterialKind::Lambertian
+        }
+    }
+
+    fn to_material(&self, albedo: &Attenuation) -> SharedMaterial {
+        let albedo = albedo.clone();
+        match self {
+            MaterialKind::Lambertian => Arc::new(Lambertian { albedo }),
+            MaterialKind::Metal { fuzz } => Arc::new(Metal {
+                albedo,
+                fuzz: *fuzz,
+            }),
+            MaterialKind::Glass { eta } => Arc::new(Glass {
+                eta: *eta,
+                albedo,
+                priority: 0,
+            }),
+            MaterialKind::Toon { bands } => Arc::new(Toon {
+                albedo,
+                bands: *bands,
+                light_direction: TOON_LIGHT_DIRECTION,
+            }),
+        }
+    }
+}
+impl FromStr for MaterialKind {
+    type Err = String;
+
+    /// Parses `lambertian`, `metal[:<fuzz>]`, `glass[:<eta>]`, or `toon[:<bands>]`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, parameter) = match s.split_once(':') {
+            Some((name, parameter)) => (name, Some(parameter)),
+            None => (s, None),
+        };
+        let parse_parameter = |default: Float| match parameter {
+            None => Ok(default),
+            Some(parameter) => parameter
+                .parse::<Float>()
+                .map_err(|_| format!("invalid material parameter: {}", parameter)),
+        };
+        match name {
+            "lambertian" if parameter.is_none() => Ok(MaterialKind::Lambertian),
+            "metal" => Ok(MaterialKind::Metal {
+                fuzz: parse_parameter(0.2)?,
+            }),
+            "glass" => Ok(MaterialKind::Glass {
+                eta: parse_parameter(1.5)?,
+            }),
+            "toon" => Ok(MaterialKind::Toon {
+                bands: match parameter {
+                    None => 3,
+                    Some(parameter) => parameter
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|bands| *bands > 0)
+                        .ok_or_else(|| format!("invalid number of bands: {}", parameter))?,
+                },
+            }),
+            _ => Err(format!(
+                "unknown material: {} (expected lambertian, metal[:<fuzz>], glass[:<eta>], or toon[:<bands>])",
+                s
+            )),
+        }
+    }
+}
+
+/// Parses element material overrides of the form `<element>=<material>`, e.g. `O=glass:1.5`.
+pub fn parse_material_override(s: &str) -> Result<(String, MaterialKind), String> {
+    match s.split_once('=') {
+        Some((element, kind)) => Ok((normalize_symbol(element), kind.parse()?)),
+        None => Err(format!("expected <element>=<material>: {}", s)),
+    }
+}
+
+/// The options for building models of molecules.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MoleculeOptions {
+    pub style: MoleculeStyle,
+    /// The materials used instead of the default ones for each element symbol.
+    pub material_overrides: HashMap<String, MaterialKind>,
+}
+impl Default for MoleculeOptions {
+    fn default() -> Self {
+        Self {
+            style: MoleculeStyle::BallAndStick,
+            material_overrides: HashMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WATER_XYZ: &str = "3
+water
+O   0.000   0.000   0.117
+H   0.000   0.757  -0.467
+H   0.000  -0.757  -0.467
+";
+
+    #[test]
+    fn parse_xyz_tests() {
+        let molecule = Molecule::parse_xyz(WATER_XYZ).unwrap();
+        assert_eq!(3, molecule.atoms.len());
+        assert_eq!(
+            Atom {
+                element: "H".to_string(),
+                position: Point3 {
+                    x: 0.,
+                    y: 0.757,
+                    z: -0.467,
+                },
+            },
+            molecule.atoms[1]
+        );
+        assert_eq!(vec![(0, 1), (0, 2)], molecule.bonds());
+
+        assert!(Molecule::parse_xyz("4\nwater\nO 0 0 0\n").is_err());
+        assert!(Molecule::parse_xyz("1\n\nO 0 zero 0\n").is_err());
+    }
+
+    #[test]
+    fn bonds_tests() {
+        // Scattered atoms of various sizes on both sides of the origin, whose bonds cross the cells of the grid.
+        let elements = ["H", "C", "O", "Fe", "Xx"];
+        let atoms: Vec<Atom> = (0..500)
+            .map(|i| Atom {
+                element: elements[i % elements.len()].to_string(),
+                position: Point3 {
+                    x: ((i * 37) % 101) as Float * 0.11 - 5.,
+                    y: ((i * 53) % 97) as Float * 0.09 - 4.,
+                    z: ((i * 71) % 89) as Float * 0.07 - 3.,
+                },
+            })
+            .collect();
+        let mut expected = vec![];
+        for i in 0..atoms.len() {
+            for j in i + 1..atoms.len() {
+                let distance = (&atoms[i].position - &atoms[j].position).length();
+                let radii =
+                    atoms[i].properties().covalent_radius + atoms[j].properties().covalent_radius;
+                if distance <= BOND_TOLERANCE * radii {
+                    expected.push((i, j));
+                }
+            }
+        }
+        assert!(!expected.is_empty());
+        assert_eq!(expected, Molecule { atoms }.bonds());
+    }
+
+    #[test]
+    fn parse_pdb_tests() {
+        let text = "\
+HEADER    TEST
+HETATM    1  C1  MOH     1      -0.046   0.663   0.000  1.00  0.00           C
+HETATM    2  O1  MOH     1      -0.046  -0.757   0.000  1.00  0.00           O
+HETATM    3 CL   CL      2       3.000   0.000   0.000  1.00  0.00
+END
+";
+        let molecule = Molecule::parse_pdb(text).unwrap();
+        let elements: Vec<&str> = molecule
+            .atoms
+            .iter()
+            .map(|atom| atom.element.as_str())
+            .collect();
+        assert_eq!(vec!["C", "O", "Cl"], elements);
+        assert_eq!(
+            Point3 {
+                x: -0.046,
+                y: -0.757,
+                z: 0.,
+            },
+            molecule.atoms[1].position
+        );
+        assert_eq!(vec![(0, 1)], molecule.bonds());
+
+        assert!(Molecule::parse_pdb("HEADER    TEST\n").is_err());
+    }
+
+    #[test]
+    fn to_hittables_tests() {
+        let molecule = Molecule::parse_xyz(WATER_XYZ).unwrap();
+        let count = |style| {
+            let options = MoleculeOptions {
+                style,
+                ..MoleculeOptions::default()
+            };
+            molecule.to_hittables(&options).len()
+        };
+        // 3 atoms and 2 bonds, each of which consists of 2 cylinders:
+        assert_eq!(7, count(MoleculeStyle::BallAndStick));
+        assert_eq!(3, count(MoleculeStyle::SpaceFilling));
+        assert_eq!(7, count(MoleculeStyle::Wireframe));
+        assert!(
+            molecule.bounding_radius(MoleculeStyle::SpaceFilling)
+                > molecule.bounding_radius(MoleculeStyle::BallAndStick)
+        );
+    }
+
+    #[test]
+    fn parse_options_tests() {
+        assert_eq!(
+            Ok(MoleculeStyle::SpaceFilling),
+            "space-filling".parse::<MoleculeStyle>()
+        );
+        assert!("sticks".parse::<MoleculeStyle>().is_err());
+        assert_eq!(
+            Ok(("O".to_string(), MaterialKind::Glass { eta: 1.33 })),
+            parse_material_override("o=glass:1.33")
+        );
+        assert_eq!(
+            Ok(("Fe".to_string(), MaterialKind::Metal { fuzz: 0.2 })),
+            parse_material_override("FE=metal")
+        );
+        assert_eq!(
+            Ok(("C".to_string(), MaterialKind::Lambertian)),
+            parse_material_override("C=lambertian")
+        );
+        assert_eq!(
+            Ok(("N".to_string(), MaterialKind::Toon { bands: 4 })),
+            parse_material_override("N=toon:4")
+        );
+        assert_eq!(Ok(MaterialKind::Toon { bands: 3 }), "toon".parse());
+        assert!(parse_material_override("N=toon:0").is_err());
+        assert!(parse_material_override("C").is_err());
+        assert!(parse_material_override("C=lambertian:1").is_err());
+        assert!(parse_material_override("C=glass:thick").is_err());
+    }
+
+    #[test]
+    fn normalize_symbol_tests() {
+        assert_eq!("Cl", normalize_symbol("CL"));
+        assert_eq!("Cl", normalize_symbol(" cl"));
+        assert_eq!("H", normalize_symbol("h"));
+    }
+}
@@ -0,0 +1,582 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::color::{Attenuation, Color};
+use crate::error::RenderError;
+use crate::geometry::{Float, Point3};
+use crate::hittable_object::{
+    DiffuseLight, Glass, Lambertian, Mesh, Metal, SharedMaterial, TexturedLambertian,
+};
+use crate::texture::{ImageTexture, TextureCache};
+
+/// The refractive index of transparent materials without `Ni`, which is that of glass.
+const DEFAULT_REFRACTIVE_INDEX: Float = 1.5;
+
+/// The corner of a face: the index of the vertex and that of the texture coordinates if any.
+pub type Corner = (usize, Option<usize>);
+
+/// The type for the contents of Wavefront OBJ files before the materials are resolved.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ObjGeometry {
+    pub vertices: Vec<Point3>,
+    pub texture_coordinates: Vec<(Float, Float)>,
+    /// The triangles grouped by the names of their materials in the order of appearance,
+    /// where the name is `None` for the faces before any `usemtl`.
+    pub groups: Vec<(Option<String>, Vec<[Corner; 3]>)>,
+    /// The material files referred to by `mtllib`.
+    pub material_libraries: Vec<String>,
+}
+
+/// Parses the vertices (`v`), the texture coordinates (`vt`), the faces (`f`),
+/// and the material statements (`usemtl` and `mtllib`) of the OBJ format,
+/// where polygons are split into triangles and the other statements are ignored.
+pub fn parse_obj(text: &str) -> Result<ObjGeometry, String> {
+    let mut geometry = ObjGeometry::default();
+    let mut current_group = None;
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let mut fields = line.split_whitespace();
+        let Some(keyword) = fields.next() else {
+            continue;
+        };
+        let number = |s: &str| {
+            s.parse::<Float>()
+                .map_err(|_| format!("line {}: invalid number: {}", line_number, s))
+        };
+        match keyword {
+            "v" => {
+                let coordinates: Vec<&str> = fields.collect();
+                if coordinates.len() < 3 {
+                    return Err(format!("line {}: expected `v <x> <y> <z>`", line_number));
+                }
+                geometry.vertices.push(Point3 {
+                    x: number(coordinates[0])?,
+                    y: number(coordinates[1])?,
+                    z: number(coordinates[2])?,
+                });
+            }
+            "vt" => {
+                let u = number(fields.next().unwrap_or_default())?;
+                let v = fields.next().map_or(Ok(0.), number)?;
+                geometry.texture_coordinates.push((u, v));
+            }
+            "f" => {
+                let corners = fields
+                    .map(|field| parse_corner(field, &geometry, line_number))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if corners.len() < 3 {
+                    return Err(format!(
+                        "line {}: a face needs at least 3 vertices",
+                        line_number
+                    ));
+                }
+                let group_index = *current_group.get_or_insert_with(|| {
+                    geometry.groups.push((None, vec![]));
+                    geometry.groups.len() - 1
+                });
+                let triangles = &mut geometry.groups[group_index].1;
+                // Splits the polygon into a fan of triangles around its first vertex.
+                for i in 2..corners.len() {
+                    triangles.push([corners[0], corners[i - 1], corners[i]]);
+                }
+            }
+            "usemtl" => {
+                let name = fields.collect::<Vec<_>>().join(" ");
+                let existing = geometry
+                    .groups
+                    .iter()
+                    .position(|(group_name, _)| group_name.as_deref() == Some(name.as_str()));
+                current_group = Some(existing.unwrap_or_else(|| {
+                    geometry.groups.push((Some(name), vec![]));
+                    geometry.groups.len() - 1
+                }));
+            }
+            "mtllib" => geometry
+                .material_libraries
+                .extend(fields.map(|field| field.to_string())),
+            _ => {}
+        }
+    }
+    Ok(geometry)
+}
+
+/// Parses a corner of a face, i.e., `v`, `v/vt`, `v//vn`, or `v/vt/vn`,
+/// where negative indices count from the last vertex defined so far.
+fn parse_corner(field: &str, geometry: &ObjGeometry, line_number: usize) -> Result<Corner, String> {
+    let resolve = |s: &str, len: usize| {
+        let index: i64 = s
+            .parse()
+            .map_err(|_| format!("line {}: invalid index: {}", line_number, s))?;
+        let resolved = if index < 0 {
+            len as i64 + index
+        } else {
+            index - 1
+        };
+        if (0..len as i64).contains(&resolved) {
+            Ok(resolved as usize)
+        } else {
+            Err(format!(
+                "line {}: index {} is out of range (there are {})",
+                line_number, index, len
+            ))
+        }
+    };
+    let mut indices = field.split('/');
+    let vertex = resolve(indices.next().unwrap_or_default(), geometry.vertices.len())?;
+    let texture_coordinate = match indices.next() {
+        Some(s) if !s.is_empty() => Some(resolve(s, geometry.texture_coordinates.len())?),
+        _ => None,
+    };
+    Ok((vertex, texture_coordinate))
+}
+
+/// The type for materials in MTL files.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MtlMaterial {
+    /// `Kd`
+    pub diffuse: Attenuation,
+    /// `Ks`
+    pub specular: Attenuation,
+    /// `Ns`, which is larger for shinier surfaces.
+    pub specular_exponent: Float,
+    /// `d`, or 1 minus `Tr`, which is less than 1 for transparent materials.
+    pub dissolve: Float,
+    /// `Ni`
+    pub refractive_index: Option<Float>,
+    /// `Ke`
+    pub emission: Color,
+    /// `illum`
+    pub illumination_model: Option<u32>,
+    /// The file name of `map_Kd`.
+    pub diffuse_map: Option<String>,
+}
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        MtlMaterial {
+            diffuse: Attenuation {
+                r: 0.8,
+                g: 0.8,
+                b: 0.8,
+            },
+            specular: Attenuation {
+                r: 0.,
+                g: 0.,
+                b: 0.,
+            },
+            specular_exponent: 0.,
+            dissolve: 1.,
+            refractive_index: None,
+            emission: Color {
+                r: 0.,
+                g: 0.,
+                b: 0.,
+            },
+            illumination_model: None,
+            diffuse_map: None,
+        }
+    }
+}
+impl MtlMaterial {
+    /// Chooses the material of the renderer closest to the MTL material:
+    /// - `DiffuseLight` if `Ke` is not black,
+    /// - `Glass` if `d` is less than 1 or `illum` is one of the transparent models,
+    /// - `Metal` if `illum` is 3 or `Ks` is brighter than `Kd`, which is fuzzier for smaller `Ns`,
+    /// - and `Lambertian` otherwise, textured by `texture` (i.e. `map_Kd`) if any.
+    pub fn to_material(&self, texture: Option<Arc<ImageTexture>>) -> SharedMaterial {
+        let max = |c: &Attenuation| c.r.max(c.g).max(c.b);
+        let emission = &self.emission;
+        if emission.r > 0. || emission.g > 0. || emission.b > 0. {
+            return Arc::new(DiffuseLight {
+                emit: emission.clone(),
+            });
+        }
+        let is_transparent_model = matches!(self.illumination_model, Some(4 | 6 | 7 | 9));
+        if self.dissolve < 1. || is_transparent_model {
+            return Arc::new(Glass {
+                eta: self
+                    .refractive_index
+                    .filter(|&eta| eta >= 1.)
+                    .unwrap_or(DEFAULT_REFRACTIVE_INDEX),
+                albedo: Attenuation {
+                    r: 1.,
+                    g: 1.,
+                    b: 1.,
+                },
+                priority: 0,
+            });
+        }
+        if self.illumination_model == Some(3) || max(&self.specular) > max(&self.diffuse) {
+            // The roughness corresponding to the Phong exponent by the usual approximation.
+            let fuzz = (2. / (self.specular_exponent.max(0.) + 2.)).sqrt();
+            return Arc::new(Metal {
+                albedo: self.specular.clone(),
+                fuzz,
+            });
+        }
+        match texture {
+            Some(texture) => Arc::new(TexturedLambertian { texture }),
+            None => Arc::new(Lambertian {
+                albedo: self.diffuse.clone(),
+            }),
+        }
+    }
+}
+
+/// Parses the MTL format and returns the materials with their names.
+/// Colors are clamped to [0, 1] except for `Ke`.
+pub fn parse_mtl(text: &str) -> Result<Vec<(String, MtlMaterial)>, String> {
+    let mut materials: Vec<(String, MtlMaterial)> = vec![];
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let mut fields = line.split_whitespace();
+        let Some(keyword) = fields.next() else {
+            continue;
+        };
+        let arguments: Vec<&str> = fields.collect();
+        if keyword == "newmtl" {
+            materials.push((arguments.join(" "), MtlMaterial::default()));
+            continue;
+        }
+        if keyword.starts_with('#') {
+            continue;
+        }
+        let Some((_, material)) = materials.last_mut() else {
+            return Err(format!("line {}: expected `newmtl` first", line_number));
+        };
+        let numbers = || {
+            arguments
+                .iter()
+                .map(|s| {
+                    s.parse::<Float>()
+                        .map_err(|_| format!("line {}: invalid number: {}", line_number, s))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let number = || match numbers()?.as_slice() {
+            [x] => Ok(*x),
+            _ => Err(format!("line {}: expected a number", line_number)),
+        };
+        let color = || match numbers()?.as_slice() {
+            [r, g, b] => Ok((*r, *g, *b)),
+            // A single value is a gray.
+            [c] => Ok((*c, *c, *c)),
+            _ => Err(format!("line {}: expected a color", line_number)),
+        };
+        let attenuation = || {
+            color().map(|(r, g, b)| Attenuation {
+                r: r.clamp(0., 1.),
+                g: g.clamp(0., 1.),
+                b: b.clamp(0., 1.),
+            })
+        };
+        match keyword {
+            "Kd" => material.diffuse = attenuation()?,
+            "Ks" => material.specular = attenuation()?,
+            "Ns" => material.specular_exponent = number()?,
+            "d" => material.dissolve = number()?,
+            "Tr" => material.dissolve = 1. - number()?,
+            "Ni" => material.refractive_index = Some(number()?),
+            "Ke" => {
+                let (r, g, b) = color()?;
+                material.emission = Color { r, g, b };
+            }
+            "illum" => {
+                let model = arguments.first().and_then(|s| s.parse().ok());
+                material.illumination_model = Some(
+                    model.ok_or_else(|| format!("line {}: expected an integer", line_number))?,
+                );
+            }
+            // The options such as `-s 1 1 1` precede the file name.
+            "map_Kd" => material.diffuse_map = arguments.last().map(|s| s.to_string()),
+            _ => {}
+        }
+    }
+    Ok(materials)
+}
+
+/// The type for models loaded from OBJ files, whose faces are grouped into a mesh per material.
+pub struct ObjModel {
+    pub meshes: Vec<Mesh>,
+    /// The problems that did not stop loading, e.g. missing material files,
+    /// in which case the default materials are used instead.
+    pub warnings: Vec<String>,
+}
+impl ObjModel {
+    /// Loads an OBJ file together with the MTL files and the PNG textures it refers to,
+    /// which are looked for relative to the OBJ file and the MTL files respectively.
+    pub fn load(path: &Path) -> Result<Self, RenderError> {
+        Self::load_with_textures(path, &TextureCache::new())
+    }
+
+    /// Does the same as `load` but takes the textures from `textures`,
+    /// which loads the ones not cached yet, e.g. to share them among models.
+    pub fn load_with_textures(path: &Path, textures: &TextureCache) -> Result<Self, RenderError> {
+        let parse_error = |path: &Path| {
+            let path = path.to_path_buf();
+            move |message| RenderError::SceneParse { path, message }
+        };
+        let text = std::fs::read_to_string(path).map_err(|e| RenderError::io(path, e))?;
+        let geometry = parse_obj(&text).map_err(parse_error(path))?;
+        let directory = path.parent().unwrap_or(Path::new(""));
+
+        let mut warnings = vec![];
+        let mut materials = HashMap::new();
+        // Remembers the textures that failed so that each problem is reported once.
+        let mut failed_textures = HashSet::new();
+        for library in geometry.material_libraries.iter() {
+            let library_path = directory.join(library);
+            let text = match std::fs::read_to_string(&library_path) {
+                Ok(text) => text,
+                Err(e) => {
+                    warnings.push(format!("{}: {}", library_path.display(), e));
+                    continue;
+                }
+            };
+            for (name, material) in parse_mtl(&text).map_err(parse_error(&library_path))? {
+                let texture = material.diffuse_map.as_ref().and_then(|file_name| {
+                    let texture_path = library_path.with_file_name(file_name);
+                    if failed_textures.contains(&texture_path) {
+                        return None;
+                    }
+                    match load_texture(&texture_path, textures) {
+                        Ok(texture) => Some(texture),
+                        Err(e) => {
+                            warnings.push(format!("{} (using Kd instead)", e));
+                            failed_textures.insert(texture_path);
+                            None
+                        }
+                    }
+                });
+                materials.insert(name, material.to_material(texture));
+            }
+        }
+
+        let default_material = MtlMaterial::default().to_material(None);
+        let mut meshes = vec![];
+        for (name, triangles) in geometry.groups.iter() {
+            let material = match name {
+                Some(name) => materials.get(name).cloned().unwrap_or_else(|| {
+                    warnings.push(format!("unknown material: {}", name));
+                    default_material.clone()
+                }),
+                None => default_material.clone(),
+            };
+            meshes.push(to_mesh(&geometry, triangles, material));
+        }
+        Ok(ObjModel { meshes, warnings })
+    }
+}
+
+fn load_texture(path: &Path, textures: &TextureCache) -> Result<Arc<ImageTexture>, String> {
+    let is_png = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("png"));
+    if !is_png {
+        return Err(format!(
+            "{}: only PNG textures are supported",
+            path.display()
+        ));
+    }
+    textures.get(path).map_err(|e| e.to_string())
+}
+
+/// Builds a mesh of `triangles`, which has only the vertices used by them.
+fn to_mesh(geometry: &ObjGeometry, triangles: &[[Corner; 3]], material: SharedMaterial) -> Mesh {
+    let mut local_indices = HashMap::new();
+    let mut vertices = vec![];
+    let mut local_triangles = vec![];
+    for triangle in triangles.iter() {
+        local_triangles.push(triangle.map(|(vertex, _)| {
+            *local_indices.entry(vertex).or_insert_with(|| {
+                vertices.push(geometry.vertices[vertex].clone());
+                vertices.len() - 1
+            })
+        }));
+    }
+    let mesh = Mesh::new(vertices, local_triangles, material);
+    let is_textured = triangles
+        .iter()
+        .flatten()
+        .any(|(_, texture_coordinate)| texture_coordinate.is_some());
+    if !is_textured {
+        return mesh;
+    }
+    let texture_coordinates = triangles
+        .iter()
+        .map(|triangle| {
+            triangle.map(|(_, texture_coordinate)| match texture_coordinate {
+                Some(index) => geometry.texture_coordinates[index],
+                None => (0., 0.),
+            })
+        })
+        .collect();
+    mesh.with_texture_coordinates(texture_coordinates)
+}
+
+/// Writes named polylines, e.g. traced paths of rays, as objects of line elements,
+/// which DCC tools such as Blender import as edges. Polylines with fewer than two points are skipped.
+pub fn write_polylines(
+    out: &mut impl Write,
+    polylines: &[(String, Vec<Point3>)],
+) -> io::Result<()> {
+    let mut num_vertices = 0;
+    for (name, points) in polylines.iter().filter(|(_, points)| points.len() >= 2) {
+        writeln!(out, "o {}", name)?;
+        for point in points.iter() {
+            writeln!(out, "v {} {} {}", point.x, point.y, point.z)?;
+        }
+        let indices: Vec<String> = (num_vertices + 1..=num_vertices + points.len())
+            .map(|index| index.to_string())
+            .collect();
+        writeln!(out, "l {}", indices.join(" "))?;
+        num_vertices += points.len();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUAD_OBJ: &str = "\
+# A square and a triangle with different materials
+mtllib quad.mtl
+v -1 -1 0
+v 1 -1 0
+v 1 1 0
+v -1 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+usemtl red
+f 1/1 2/2 3/3 4/4
+usemtl mirror
+f -4//1 -3//1 -1//1
+";
+
+    #[test]
+    fn parse_obj_tests() {
+        let geometry = parse_obj(QUAD_OBJ).unwrap();
+        assert_eq!(4, geometry.vertices.len());
+        assert_eq!(vec!["quad.mtl".to_string()], geometry.material_libraries);
+        assert_eq!(
+            vec![
+                (
+                    Some("red".to_string()),
+                    vec![
+                        [(0, Some(0)), (1, Some(1)), (2, Some(2))],
+                        [(0, Some(0)), (2, Some(2)), (3, Some(3))],
+                    ]
+                ),
+                (
+                    Some("mirror".to_string()),
+                    vec![[(0, None), (1, None), (3, None)]]
+                ),
+            ],
+            geometry.groups
+        );
+
+        let mesh = to_mesh(
+            &geometry,
+            &geometry.groups[1].1,
+            MtlMaterial::default().to_material(None),
+        );
+        assert_eq!(3, mesh.vertices().len());
+        assert_eq!(&[[0, 1, 2]], mesh.triangles());
+
+        assert!(parse_obj("v 0 0 0\nf 1 2 3\n").is_err());
+        assert!(parse_obj("v 0 zero 0\n").is_err());
+        assert!(parse_obj("v 0 0 0\nv 1 0 0\nf 1 2\n").is_err());
+    }
+
+    #[test]
+    fn parse_mtl_tests() {
+        let text = "\
+newmtl red
+Kd 1 0 0
+Ks 0.5 0.5 0.5
+Ns 100
+illum 2
+
+newmtl mirror
+Kd 0.1 0.1 0.1
+Ks 0.9 0.9 0.9
+Ns 1000
+
+newmtl glass
+d 0.2
+Ni 1.33
+
+newmtl lamp
+Ke 4 4 4
+map_Kd -s 1 1 1 lamp.png
+";
+        let materials = parse_mtl(text).unwrap();
+        let names: Vec<&str> = materials.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(vec!["red", "mirror", "glass", "lamp"], names);
+        let red = &materials[0].1;
+        assert_eq!(
+            Attenuation {
+                r: 1.,
+                g: 0.,
+                b: 0.,
+            },
+            red.diffuse
+        );
+        assert_eq!(Some(2), red.illumination_model);
+        assert_eq!(Some("lamp.png".to_string()), materials[3].1.diffuse_map);
+
+        assert!(parse_mtl("Kd 1 1 1\n").is_err());
+        assert!(parse_mtl("newmtl a\nKd red\n").is_err());
+    }
+
+    #[test]
+    fn to_material_tests() {
+        let materials = parse_mtl(
+            "newmtl plastic\nKd 0.8 0.1 0.1\nKs 0.5 0.5 0.5\n\
+             newmtl mirror\nKd 0.1 0.1 0.1\nKs 0.9 0.9 0.9\nNs 1000\n\
+             newmtl glass\nd 0.2\nNi 1.33\n\
+             newmtl lamp\nKe 4 4 4\n",
+        )
+        .unwrap();
+        let memory_sizes: Vec<usize> = materials
+            .iter()
+            .map(|(_, material)| material.to_material(None).memory_size())
+            .collect();
+        assert_eq!(
+            vec![
+                std::mem::size_of::<Lambertian>(),
+                std::mem::size_of::<Metal>(),
+                std::mem::size_of::<Glass>(),
+                std::mem::size_of::<DiffuseLight>(),
+            ],
+            memory_sizes
+        );
+        for (name, material) in materials.iter() {
+            assert!(material.to_material(None).validate().is_empty(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn write_polylines_tests() {
+        let point = |x: Float, y: Float, z: Float| Point3 { x, y, z };
+        let polylines = vec![
+            (
+                "a".to_string(),
+                vec![point(0., 0., 1.), point(0., 0., -1.), point(1., 0.5, 0.)],
+            ),
+            ("empty".to_string(), vec![point(1., 1., 1.)]),
+            ("b".to_string(), vec![point(0., 0., 1.), point(-1., 0., 0.)]),
+        ];
+        let mut out = vec![];
+        write_polylines(&mut out, &polylines).unwrap();
+        assert_eq!(
+            "o a\nv 0 0 1\nv 0 0 -1\nv 1 0.5 0\nl 1 2 3\n\
+             o b\nv 0 0 1\nv -1 0 0\nl 4 5\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
@@ -0,0 +1,85 @@
+use crate::adaptive::EdgeMask;
+use crate::color::Color;
+use crate::film::Film;
+use crate::scenes::Scene;
+
+/// The type for the lines drawn over rendered images along the geometric edges of scenes,
+/// i.e., the silhouettes, the creases, and the boundaries between objects of different materials,
+/// e.g. for technical illustrations of molecules. The edges are found by the rays through
+/// the centers of the pixels, and thus the lines are as sharp as the pixels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Outline {
+    pub color: Color,
+}
+impl Outline {
+    /// Returns an error message if the parameters are out of range.
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.color.is_valid() {
+            return Err(format!(
+                "the outline color must be finite and non-negative, but is {:?}",
+                self.color
+            ));
+        }
+        Ok(())
+    }
+
+    /// Paints the pixels of `film` on the edges of `scene`, which keeps its records such as invalid samples.
+    pub fn apply(&self, scene: &Scene, film: &mut Film) {
+        let edges = EdgeMask::find_geometric(scene, film.width, film.height);
+        for y in 0..film.height {
+            for (x, is_edge) in edges.row(y).iter().enumerate() {
+                if *is_edge {
+                    film.set_pixel(x, y, &self.color);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Float;
+    use crate::scenes;
+
+    #[test]
+    fn outline_tests() {
+        // The gray sphere of the furnace fills the middle of the image.
+        let scene = scenes::furnace(1.);
+        let gray = Color {
+            r: 0.5,
+            g: 0.5,
+            b: 0.5,
+        };
+        let mut film = Film::new(32, 32);
+        for y in 0..32 {
+            for x in 0..32 {
+                film.set_pixel(x, y, &gray);
+            }
+        }
+        let red = Color {
+            r: 1.,
+            g: 0.,
+            b: 0.,
+        };
+        let outline = Outline { color: red.clone() };
+        outline.apply(&scene, &mut film);
+
+        // The silhouette is painted on the middle scan line, but the inside and the outside are not.
+        let row: Vec<Color> = (0..32).map(|x| film.pixel(x, 16)).collect();
+        assert!(row.contains(&red));
+        assert_eq!(gray, row[0]);
+        assert_eq!(gray, row[16]);
+        assert_eq!(gray, row[31]);
+
+        assert_eq!(Ok(()), outline.validate());
+        let invalid = Outline {
+            color: Color {
+                r: Float::NAN,
+                g: 0.,
+                b: 0.,
+            },
+        };
+        assert!(invalid.validate().is_err());
+    }
+}
@@ -0,0 +1,57 @@
+extern crate minifb;
+
+use std::time::{Duration, Instant};
+
+use minifb::{Key, Window, WindowOptions};
+
+use try_ray_tracing::film::Film;
+
+/// The minimum interval between redraws.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The type for windows that display films while they are being rendered.
+pub struct Preview {
+    window: Window,
+    buffer: Vec<u32>,
+    last_draw: Option<Instant>,
+}
+impl Preview {
+    pub fn open(width: usize, height: usize) -> Result<Self, String> {
+        let window = Window::new(
+            "try_ray_tracing (Esc to abort)",
+            width,
+            height,
+            WindowOptions::default(),
+        )
+        .map_err(|e| format!("cannot open the preview window: {}", e))?;
+        Ok(Self {
+            window,
+            buffer: vec![0; width * height],
+            last_draw: None,
+        })
+    }
+
+    /// Redraws the window with `film` unless it was redrawn very recently.
+    /// Returns `false` if the user has closed the window or pressed Esc.
+    pub fn update(&mut self, film: &Film) -> bool {
+        if !self.window.is_open() || self.window.is_key_down(Key::Escape) {
+            return false;
+        }
+        let now = Instant::now();
+        if let Some(last_draw) = self.last_draw {
+            if now.duration_since(last_draw) < REDRAW_INTERVAL {
+                return true;
+            }
+        }
+        for y in 0..film.height {
+            for x in 0..film.width {
+                let [r, g, b] = film.pixel(x, y).gamma_correct().to_bytes();
+                self.buffer[y * film.width + x] = ((r as u32) << 16) | ((g as u32) << 8) | b as u32;
+            }
+        }
+        self.last_draw = Some(now);
+        self.window
+            .update_with_buffer(&self.buffer, film.width, film.height)
+            .is_ok()
+    }
+}
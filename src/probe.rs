@@ -0,0 +1,151 @@
+use crate::color::Color;
+use crate::geometry::{consts, Float, Point3, UnitVec3};
+use crate::json::Json;
+use crate::scene_file::triple;
+
+/// The number of the real spherical harmonics up to the second band,
+/// which represent irradiance within a few percent since it is smooth.
+pub const NUM_SH_COEFFICIENTS: usize = 9;
+
+/// Returns the real spherical harmonics up to the second band at `direction`
+/// in the order of (l, m) = (0, 0), (1, -1), (1, 0), (1, 1), (2, -2), (2, -1), (2, 0), (2, 1), (2, 2),
+/// where the axes are those of the scene, normalized so that each is of unit norm on the sphere.
+pub fn sh_basis(direction: &UnitVec3) -> [Float; NUM_SH_COEFFICIENTS] {
+    let d = direction.inject();
+    let (x, y, z) = (d.x, d.y, d.z);
+    [
+        0.282095,
+        0.488603 * y,
+        0.488603 * z,
+        0.488603 * x,
+        1.092548 * x * y,
+        1.092548 * y * z,
+        0.315392 * (3. * z * z - 1.),
+        1.092548 * x * z,
+        0.546274 * (x * x - y * y),
+    ]
+}
+
+/// Returns the band l of the `index`-th spherical harmonic in the order of `sh_basis`.
+fn band(index: usize) -> usize {
+    match index {
+        0 => 0,
+        1..=3 => 1,
+        _ => 2,
+    }
+}
+
+/// The type for the light around points of scenes projected onto spherical harmonics,
+/// e.g. for the light probes of game engines.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LightProbe {
+    pub position: Point3,
+    /// The coefficients of the irradiance, i.e., the light arriving at a surface facing each direction
+    /// weighted by the cosine, in the order of `sh_basis`.
+    pub irradiance: [Color; NUM_SH_COEFFICIENTS],
+}
+impl LightProbe {
+    /// Returns the probe at `position` from `sums`, the sums of the light arriving from
+    /// `num_samples` directions drawn uniformly from the sphere multiplied by `sh_basis` of them.
+    /// The light is convolved with the cosine by scaling each band (Ramamoorthi and Hanrahan).
+    pub fn from_radiance_sums(
+        position: Point3,
+        sums: &[Color; NUM_SH_COEFFICIENTS],
+        num_samples: usize,
+    ) -> Self {
+        let pi = consts::PI;
+        let convolution = [pi, 2. * pi / 3., pi / 4.];
+        let sphere = 4. * pi / num_samples.max(1) as Float;
+        let irradiance =
+            std::array::from_fn(|index| sums[index].scale(sphere * convolution[band(index)]));
+        LightProbe {
+            position,
+            irradiance,
+        }
+    }
+
+    /// Returns the irradiance at a surface facing `normal`, which is `π` times the color it looks
+    /// if it is white and diffuse.
+    pub fn irradiance(&self, normal: &UnitVec3) -> Color {
+        let black = Color {
+            r: 0.,
+            g: 0.,
+            b: 0.,
+        };
+        self.irradiance
+            .iter()
+            .zip(sh_basis(normal))
+            .fold(black, |sum, (coefficient, y)| {
+                sum.add(&coefficient.scale(y))
+            })
+    }
+
+    /// Returns the probe as `{"position": [x, y, z], "irradiance": [[r, g, b], ...]}`.
+    pub fn to_json(&self) -> Json {
+        let position = &self.position;
+        let coefficients = self
+            .irradiance
+            .iter()
+            .map(|color| triple(color.r, color.g, color.b))
+            .collect();
+        Json::Object(vec![
+            (
+                "position".to_string(),
+                triple(position.x, position.y, position.z),
+            ),
+            ("irradiance".to_string(), Json::Array(coefficients)),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx::assert_approx_eq;
+    use crate::geometry::Vec3;
+
+    fn direction(x: Float, y: Float, z: Float) -> UnitVec3 {
+        Vec3 { x, y, z }.unit_vector()
+    }
+
+    fn gray(value: Float) -> Color {
+        Color {
+            r: value,
+            g: value,
+            b: value,
+        }
+    }
+
+    #[test]
+    fn light_probe_tests() {
+        let origin = Point3 {
+            x: 0.,
+            y: 0.,
+            z: 0.,
+        };
+        // The uniform light of 1 gives the irradiance of π whichever way surfaces face.
+        let num_samples = 1000;
+        let mut sums = std::array::from_fn(|_| gray(0.));
+        sums[0] = gray(num_samples as Float * sh_basis(&direction(0., 1., 0.))[0]);
+        let probe = LightProbe::from_radiance_sums(origin.clone(), &sums, num_samples);
+        for normal in [direction(0., 1., 0.), direction(1., -2., 3.)] {
+            assert_approx_eq!(consts::PI, probe.irradiance(&normal).g, 1e-4);
+        }
+
+        // The light from a single direction gives the cosine, which the second band blurs a little.
+        let light = direction(1., 1., 0.);
+        let sums = sh_basis(&light).map(|y| gray(y / (4. * consts::PI)));
+        let probe = LightProbe::from_radiance_sums(origin, &sums, 1);
+        assert_approx_eq!(1.0625, probe.irradiance(&light).r, 1e-4);
+        assert_approx_eq!(0.0625, probe.irradiance(&-&light).r, 1e-4);
+        let side = probe.irradiance(&direction(0., 0., 1.)).r;
+        assert!(side.abs() < 0.2, "{}", side);
+
+        let json = probe.to_json();
+        assert_eq!(Some(&triple(0., 0., 0.)), json.get("position"));
+        let Some(Json::Array(coefficients)) = json.get("irradiance") else {
+            panic!("no coefficients: {:?}", json);
+        };
+        assert_eq!(NUM_SH_COEFFICIENTS, coefficients.len());
+    }
+}
@@ -0,0 +1,151 @@
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// The width of the bar in characters.
+const BAR_WIDTH: usize = 30;
+
+/// The minimum interval between redraws.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+#[cfg(test)]
+thread_local! {
+    /// The number of times the progress bars of this thread have been drawn,
+    /// by which tests check that renderings without progress draw nothing.
+    pub static NUM_DRAWS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// The type for progress bars drawn in place on the standard error.
+pub struct ProgressBar {
+    total: usize,
+    done: usize,
+    num_samples: u64,
+    start: Instant,
+    last_draw: Option<Instant>,
+}
+impl ProgressBar {
+    /// Creates a progress bar for `total` units of work (e.g. scan lines).
+    pub fn new(total: usize) -> Self {
+        Self {
+            total,
+            done: 0,
+            num_samples: 0,
+            start: Instant::now(),
+            last_draw: None,
+        }
+    }
+
+    /// Records that `units` units of work consisting of `num_samples` samples have been done.
+    pub fn advance(&mut self, units: usize, num_samples: u64) {
+        self.done += units;
+        self.num_samples += num_samples;
+        let now = Instant::now();
+        let should_draw = match self.last_draw {
+            None => true,
+            Some(last_draw) => now.duration_since(last_draw) >= REDRAW_INTERVAL,
+        };
+        if should_draw || self.done >= self.total {
+            self.draw(now);
+        }
+    }
+
+    /// Draws the final state and moves to the next line.
+    pub fn finish(&mut self) {
+        self.draw(Instant::now());
+        eprintln!();
+    }
+
+    fn draw(&mut self, now: Instant) {
+        #[cfg(test)]
+        NUM_DRAWS.with(|num_draws| num_draws.set(num_draws.get() + 1));
+        let line = format_progress(
+            self.done,
+            self.total,
+            now.duration_since(self.start),
+            self.num_samples,
+        );
+        let mut stderr = std::io::stderr();
+        // `\x1b[K` clears the rest of the previous line.
+        let _ = write!(stderr, "\r{}\x1b[K", line);
+        let _ = stderr.flush();
+        self.last_draw = Some(now);
+    }
+}
+
+/// Formats durations as `mm:ss`, or `h:mm:ss` if they are one hour or longer.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, minutes, seconds) = (secs / 3600, (secs / 60) % 60, secs % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{:02}:{:02}", minutes, seconds)
+    }
+}
+
+/// Formats rates with SI prefixes, e.g. `1.23M`.
+fn format_rate(rate: f64) -> String {
+    if rate >= 1e9 {
+        format!("{:.2}G", rate / 1e9)
+    } else if rate >= 1e6 {
+        format!("{:.2}M", rate / 1e6)
+    } else if rate >= 1e3 {
+        format!("{:.2}k", rate / 1e3)
+    } else {
+        format!("{:.0}", rate)
+    }
+}
+
+fn format_progress(done: usize, total: usize, elapsed: Duration, num_samples: u64) -> String {
+    let ratio = if total == 0 {
+        1.
+    } else {
+        (done as f64 / total as f64).min(1.)
+    };
+    let filled = (ratio * BAR_WIDTH as f64).round() as usize;
+    let bar = format!("{}{}", "#".repeat(filled), "-".repeat(BAR_WIDTH - filled));
+
+    let elapsed_secs = elapsed.as_secs_f64();
+    let eta = if done == 0 {
+        "--:--".to_string()
+    } else {
+        let remaining_secs = elapsed_secs * (1. - ratio) / ratio;
+        format_duration(Duration::from_secs_f64(remaining_secs))
+    };
+    let rate = if elapsed_secs > 0. {
+        num_samples as f64 / elapsed_secs
+    } else {
+        0.
+    };
+    format!(
+        "[{}] {:3.0}% elapsed {} ETA {} {} samples/s",
+        bar,
+        100. * ratio,
+        format_duration(elapsed),
+        eta,
+        format_rate(rate)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_duration_tests() {
+        assert_eq!("00:00", format_duration(Duration::from_millis(999)));
+        assert_eq!("01:05", format_duration(Duration::from_secs(65)));
+        assert_eq!("2:00:03", format_duration(Duration::from_secs(7203)));
+    }
+
+    #[test]
+    fn format_progress_tests() {
+        assert_eq!(
+            "[###############---------------]  50% elapsed 00:10 ETA 00:10 1.50k samples/s",
+            format_progress(50, 100, Duration::from_secs(10), 15000)
+        );
+        assert_eq!(
+            "[------------------------------]   0% elapsed 00:00 ETA --:-- 0 samples/s",
+            format_progress(0, 100, Duration::from_secs(0), 0)
+        );
+    }
+}
@@ -0,0 +1,3961 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::adaptive::EdgeMask;
+use crate::bake::SurfacePoint;
+use crate::bvh::TopLevelBvh;
+use crate::color::{Attenuation, Color};
+use crate::depth::DepthMap;
+use crate::film::{Film, Filter, FilterSampler};
+use crate::geometry::{
+    consts, random_cosine_direction, random_double, random_unit_vector, seed_random, Float, Onb,
+    Point3, Ray, UnitVec3,
+};
+use crate::guiding::{GuideDistribution, PathGuide};
+use crate::hittable_object::{HitRecord, Hittable, Medium, MediumStack, Scattered, SharedMaterial};
+use crate::interval::Interval;
+use crate::light::{environment_uv, BackgroundLight, EnvironmentLight, LightSample, LightTree};
+use crate::probe::{sh_basis, LightProbe, NUM_SH_COEFFICIENTS};
+use crate::progress::ProgressBar;
+use crate::scenes::{Background, Scene};
+
+/// The range of `t` where rays look for hits, which starts right at the origins since rays leaving
+/// surfaces start slightly off them by `Ray::leaving` instead of skipping the first part of their way.
+pub(crate) const RAY_T: Interval = Interval {
+    min: 0.,
+    max: Float::INFINITY,
+};
+
+/// The relative difference of `t` by which a shadow ray must stop short of the light
+/// to be regarded as blocked, which keeps rounding errors from letting lights cast shadows on themselves.
+const SHADOW_TOLERANCE: Float = 1e-3;
+
+/// The maximum number of pixels of images requested from outside, e.g. by the HTTP server or the C API,
+/// which keeps a request from using up the memory.
+pub const MAX_NUM_PIXELS: usize = 1 << 26;
+
+/// The type for the parameters of rendering that do not depend on scenes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderSettings {
+    pub image_width: usize,
+    pub image_height: usize,
+    /// The number of samples per pixel for antialiasing.
+    pub num_samples_per_pixel: usize,
+    /// The maximum number of bounces of each ray.
+    pub max_diffusion_depth: i32,
+    /// The probabilities that the rays scattered at each bounce, from the first one, are traced
+    /// further (Russian roulette), which saves the work on deep bounces of scenes that do not need them.
+    /// The surviving rays are brightened so that the image stays unbiased, though noisier.
+    /// The rays scattered at the bounces beyond the list are always traced.
+    pub roulette: Vec<Float>,
+    /// The number of worker threads, where `0` renders on the calling thread without spawning any.
+    /// It is always treated as `0` on WebAssembly, which has no threads.
+    pub num_threads: usize,
+    /// Whether to run the worker threads at a lower priority than other processes.
+    pub low_priority: bool,
+    /// Whether to look for samples whose colors are NaN or infinite, which are recorded in the film
+    /// with the bounces that produced them and paint their pixels magenta. Rendering gets slower.
+    pub check_samples: bool,
+    /// Whether to collect the statistics of the objects and the materials, which are recorded
+    /// in the film. Rendering gets slower, and it is not supported on WebAssembly.
+    pub collect_stats: bool,
+    /// Whether to learn where the light comes from while rendering and guide the rays scattered
+    /// by diffuse surfaces there, which helps scenes lit indirectly, e.g. through small openings.
+    /// It applies to `render` and `render_for` but not to `Renderer`.
+    pub path_guiding: bool,
+    /// The number of extra samples per pixel for the pixels on edges, i.e., geometric silhouettes
+    /// and high-contrast edges found after `num_samples_per_pixel` samples per pixel are rendered,
+    /// which makes edges crisp with fewer samples elsewhere. It applies to `render` but not to
+    /// `render_for` or `Renderer`, and `0` turns it off.
+    pub adaptive_samples: usize,
+    /// The seed of the random numbers, which makes the image reproducible regardless of
+    /// the number of threads; the random numbers are not reproducible if `None`.
+    pub seed: Option<u64>,
+    /// The reconstruction filter, by which each sample also counts for the pixels around it
+    /// unless it is `Filter::Box` or `filter_sampling` is set.
+    pub filter: Filter,
+    /// Whether to draw the offsets of the samples from the centers of their pixels in proportion
+    /// to `filter` and count each sample only for its own pixel, instead of drawing them uniformly
+    /// within the pixel and splatting them into the pixels around it, which spreads the samples
+    /// of each pixel as the filter weights them and saves the work of splatting.
+    pub filter_sampling: bool,
+    /// Whether `render` and `render_for` draw their progress bars on the standard error,
+    /// which is turned off, e.g. to keep the standard error of pipelines clean.
+    pub show_progress: bool,
+}
+impl RenderSettings {
+    /// Returns the number of the neighboring pixels on each side that a sample can count for.
+    pub fn margin(&self) -> usize {
+        if self.filter_sampling {
+            0
+        } else {
+            self.filter.margin()
+        }
+    }
+}
+
+fn ray_background_color(ray: &Ray, background: &Background) -> Color {
+    match background {
+        Background::Sky => sky_color(ray),
+        Background::SunnySky(sun) if sun.contains(&ray.direction) => {
+            sky_color(ray).add(&sun.radiance)
+        }
+        Background::SunnySky(_) => sky_color(ray),
+        Background::Solid(color) => color.clone(),
+        Background::Environment(map) => {
+            let (u, v) = environment_uv(&ray.direction);
+            map.value(u, v).clone()
+        }
+    }
+}
+
+fn sky_color(ray: &Ray) -> Color {
+    let u = &ray.direction;
+    let t = 0.5 * (u.inject().y + 1.);
+    let white = Color {
+        r: 1.,
+        g: 1.,
+        b: 1.,
+    };
+    let sky = Color {
+        r: 0.5,
+        g: 0.7,
+        b: 1.,
+    };
+    white.blend(t, &sky)
+}
+
+/// Returns the identifier of a material as a medium, which is the address of the shared material.
+fn material_id(material: &SharedMaterial) -> usize {
+    Arc::as_ptr(material) as *const () as usize
+}
+
+/// Updates `media` as `ray` goes through the boundary of `medium` at `hit`.
+fn go_through(media: &mut MediumStack, id: usize, medium: Medium, ray: &Ray, hit: &HitRecord) {
+    if hit.surface_normal.inner_product(&ray.direction) < 0. {
+        media.enter(id, medium);
+    } else {
+        media.leave(id);
+    }
+}
+
+/// The type for the statistics of an object, i.e., a member of `Scene::world`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ObjectStats {
+    /// The number of rays tested for hits with the object, i.e., those passing through its bounding box.
+    pub num_tests: u64,
+    /// The number of the tests that found hits, which may be farther than those of other objects.
+    pub num_hits: u64,
+    pub intersection_time: Duration,
+}
+
+/// The type for the statistics of a material.
+#[derive(Clone, Debug)]
+pub struct MaterialStats {
+    pub material: SharedMaterial,
+    /// The number of rays that hit the material first and were shaded by it.
+    pub num_hits: u64,
+    /// The time spent on computing the emitted light and scattering rays.
+    pub shading_time: Duration,
+}
+
+/// The type for how a path of a sample ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct PathEnd {
+    /// The number of the bounces, i.e., the rays scattered, before the path ended.
+    length: usize,
+    /// Whether the path was terminated by the roulette, rather than hitting nothing,
+    /// being absorbed, or reaching the maximum depth.
+    terminated: bool,
+}
+
+/// The type for the lengths of the paths of the samples of a pixel,
+/// which are recorded in the film if `RenderSettings::collect_stats` is set.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PathLengths {
+    pub num_paths: u32,
+    /// The sum of the numbers of the bounces of the paths.
+    pub total: u64,
+    /// The largest number of bounces of the paths.
+    pub max: u32,
+    /// The number of the paths terminated by the roulette.
+    pub num_terminated: u32,
+}
+impl PathLengths {
+    /// Returns the mean number of bounces of the paths, or 0 if there are none.
+    pub fn mean(&self) -> Float {
+        if self.num_paths == 0 {
+            0.
+        } else {
+            self.total as Float / self.num_paths as Float
+        }
+    }
+
+    /// Adds the paths of `other`, e.g. those of another pass.
+    pub fn merge(&mut self, other: &PathLengths) {
+        self.num_paths += other.num_paths;
+        self.total += other.total;
+        self.max = self.max.max(other.max);
+        self.num_terminated += other.num_terminated;
+    }
+
+    fn add(&mut self, end: PathEnd) {
+        self.merge(&PathLengths {
+            num_paths: 1,
+            total: end.length as u64,
+            max: end.length as u32,
+            num_terminated: end.terminated as u32,
+        });
+    }
+}
+
+/// The type for the statistics collected if `RenderSettings::collect_stats` is set,
+/// which tell which objects and materials make rendering slow and where the bounces go.
+/// There are no shadow rays since lights are hit only by scattered rays.
+#[derive(Clone, Debug, Default)]
+pub struct RenderStats {
+    /// The statistics of the members of `Scene::world` in the same order.
+    pub objects: Vec<ObjectStats>,
+    /// The statistics of the materials by their identifiers, i.e., the addresses of the shared materials.
+    materials: HashMap<usize, MaterialStats>,
+    /// The numbers of the paths of the samples by their numbers of bounces, from 0.
+    pub path_lengths: Vec<u64>,
+    /// The numbers of the paths terminated by the roulette by their numbers of bounces in the same way,
+    /// which are also counted in `path_lengths`.
+    pub terminated_path_lengths: Vec<u64>,
+}
+impl RenderStats {
+    /// Returns the statistics of the materials that have been hit, taking the longest to shade first.
+    pub fn materials(&self) -> Vec<&MaterialStats> {
+        let mut materials: Vec<&MaterialStats> = self.materials.values().collect();
+        materials.sort_by_key(|material| Reverse(material.shading_time));
+        materials
+    }
+
+    /// Adds the statistics of `other`, e.g. those of another scan line.
+    pub fn merge(&mut self, other: RenderStats) {
+        if self.objects.len() < other.objects.len() {
+            self.objects
+                .resize(other.objects.len(), ObjectStats::default());
+        }
+        for (object, other) in self.objects.iter_mut().zip(other.objects) {
+            object.num_tests += other.num_tests;
+            object.num_hits += other.num_hits;
+            object.intersection_time += other.intersection_time;
+        }
+        for (id, other) in other.materials {
+            match self.materials.get_mut(&id) {
+                Some(material) => {
+                    material.num_hits += other.num_hits;
+                    material.shading_time += other.shading_time;
+                }
+                None => {
+                    self.materials.insert(id, other);
+                }
+            }
+        }
+        add_counts(&mut self.path_lengths, &other.path_lengths);
+        add_counts(
+            &mut self.terminated_path_lengths,
+            &other.terminated_path_lengths,
+        );
+    }
+
+    fn add_path(&mut self, end: PathEnd) {
+        let mut counts = vec![0; end.length + 1];
+        counts[end.length] = 1;
+        add_counts(&mut self.path_lengths, &counts);
+        if end.terminated {
+            add_counts(&mut self.terminated_path_lengths, &counts);
+        }
+    }
+
+    fn add_shading(&mut self, material: &SharedMaterial, time: Duration) {
+        let stats = self
+            .materials
+            .entry(material_id(material))
+            .or_insert_with(|| MaterialStats {
+                material: material.clone(),
+                num_hits: 0,
+                shading_time: Duration::ZERO,
+            });
+        stats.num_hits += 1;
+        stats.shading_time += time;
+    }
+}
+
+/// Adds `other` to `counts` element-wise, extending `counts` with zeros if it is shorter.
+fn add_counts(counts: &mut Vec<u64>, other: &[u64]) {
+    if counts.len() < other.len() {
+        counts.resize(other.len(), 0);
+    }
+    for (count, other) in counts.iter_mut().zip(other) {
+        *count += other;
+    }
+}
+
+/// Returns the nearest hit of `ray` in the scene of `lit` by its top-level BVH,
+/// while timing the tests with each object if `stats` is given.
+fn hit_scene(
+    ray: &Ray,
+    lit: &LitScene,
+    stats: Option<&mut RenderStats>,
+) -> Option<(HitRecord, SharedMaterial)> {
+    let world = &lit.scene.world;
+    let Some(stats) = stats else {
+        return lit.bvh.hit(world, ray, &RAY_T);
+    };
+    if stats.objects.len() < world.members.len() {
+        stats
+            .objects
+            .resize(world.members.len(), ObjectStats::default());
+    }
+    lit.bvh.hit_members(ray, &RAY_T, |index, ray_t| {
+        let object = &mut stats.objects[index];
+        let start = Instant::now();
+        let maybe_hit = world.members[index].hit(ray, ray_t);
+        object.intersection_time += start.elapsed();
+        object.num_tests += 1;
+        if maybe_hit.is_some() {
+            object.num_hits += 1;
+        }
+        maybe_hit
+    })
+}
+
+/// Returns the first hit of `ray` with its material, where the boundaries of dielectrics
+/// ignored due to their priorities are passed through while updating `media`,
+/// together with the ray continued from the last boundary passed through.
+fn hit_in_media(
+    ray: &Ray,
+    lit: &LitScene,
+    media: &mut MediumStack,
+    mut stats: Option<&mut RenderStats>,
+) -> (Ray, Option<(HitRecord, SharedMaterial)>) {
+    let mut ray = ray.clone();
+    while let Some((hit, material)) = hit_scene(&ray, lit, stats.as_deref_mut()) {
+        let entering = hit.surface_normal.inner_product(&ray.direction) < 0.;
+        match material.medium() {
+            Some(medium) if media.is_false_hit(material_id(&material), &medium, entering) => {
+                go_through(media, material_id(&material), medium, &ray, &hit);
+                let point = ray.at(hit.t);
+                ray = Ray::leaving(&point, &hit.surface_normal, ray.direction.clone());
+            }
+            _ => return (ray, Some((hit, material))),
+        }
+    }
+    (ray, None)
+}
+
+/// Scatters the ray of `path` at `hit`, where dielectrics refract it by the media on both sides
+/// and update the media of `path` if the scattered ray goes through them.
+fn scatter_in_media(
+    path: &mut PathState,
+    hit: &HitRecord,
+    material: &SharedMaterial,
+) -> Option<Scattered> {
+    let ray = &path.ray;
+    let Some(medium) = material.medium() else {
+        return material.scatter(ray, hit);
+    };
+    let id = material_id(material);
+    let side_in = hit.surface_normal.inner_product(&ray.direction);
+    let eta_outside = path.medium.eta_outside(id, side_in < 0.);
+    let scattered = material.scatter_between(ray, hit, eta_outside);
+    if let Some(scattered) = &scattered {
+        if side_in * hit.surface_normal.inner_product(&scattered.ray.direction) > 0. {
+            go_through(&mut path.medium, id, medium, ray, hit);
+        }
+    }
+    scattered
+}
+
+/// Returns the light emitted at `hit` and the ray of `path` scattered there,
+/// timing them for `material` if `stats` is given.
+fn shade(
+    path: &mut PathState,
+    hit: &HitRecord,
+    material: &SharedMaterial,
+    stats: Option<&mut RenderStats>,
+) -> (Color, Option<Scattered>) {
+    let start = stats.as_ref().map(|_| Instant::now());
+    let emitted = material.emitted(hit).add(&material.shown(&path.ray, hit));
+    let scattered = scatter_in_media(path, hit, material);
+    if let (Some(stats), Some(start)) = (stats, start) {
+        stats.add_shading(material, start.elapsed());
+    }
+    (emitted, scattered)
+}
+
+/// Decides whether the ray `scattered` at a bounce is traced further according to `roulette`,
+/// the survival probabilities from the bounce, and returns the ray if so, together with
+/// the weight of the color of the ray, i.e., the inverse of the probability,
+/// and the survival probabilities from the next bounce.
+fn play_roulette(
+    scattered: Option<Scattered>,
+    roulette: &[Float],
+) -> (Option<Scattered>, Float, &[Float]) {
+    let Some((&probability, rest)) = roulette.split_first() else {
+        return (scattered, 1., roulette);
+    };
+    match scattered {
+        // Random numbers are not consumed unless the ray may be terminated.
+        Some(scattered) if probability < 1. => {
+            // `random_double` returns numbers in [-0.5, 0.5).
+            if random_double() + 0.5 < probability {
+                (Some(scattered), 1. / probability, rest)
+            } else {
+                (None, 1., rest)
+            }
+        }
+        scattered => (scattered, 1., rest),
+    }
+}
+
+/// The probability that the rays scattered by diffuse surfaces are drawn from the path guide
+/// instead of the material where the guide has learned the light.
+const GUIDE_PROBABILITY: Float = 0.5;
+
+/// The probability that the background is sampled directly instead of the lights
+/// where the scene has both.
+const ENVIRONMENT_PROBABILITY: Float = 0.5;
+
+/// The type for scenes being rendered together with their lights to be sampled directly.
+struct LitScene<'a> {
+    scene: &'a Scene,
+    /// The top-level BVH over the members of the world, which is built once for all the passes.
+    bvh: &'a TopLevelBvh,
+    lights: LightTree,
+    /// The sun or the environment map of the background if any, which is sampled directly
+    /// as the lights are.
+    environment: Option<BackgroundLight>,
+    /// The directions that the rays scattered by diffuse surfaces are guided to if path guiding is on.
+    guide: Option<&'a GuideDistribution>,
+}
+impl<'a> LitScene<'a> {
+    fn new(scene: &'a Scene, bvh: &'a TopLevelBvh, guide: Option<&'a GuideDistribution>) -> Self {
+        let environment = match &scene.background {
+            Background::SunnySky(sun) => Some(BackgroundLight::Sun(sun.clone())),
+            Background::Environment(map) => {
+                EnvironmentLight::new(Arc::clone(map)).map(BackgroundLight::Environment)
+            }
+            Background::Sky | Background::Solid(_) => None,
+        };
+        LitScene {
+            scene,
+            bvh,
+            lights: LightTree::from_world(&scene.world),
+            environment,
+            guide,
+        }
+    }
+
+    /// Checks that there is something to sample directly, i.e., the lights or the background.
+    fn has_lights(&self) -> bool {
+        !self.lights.is_empty() || self.environment.is_some()
+    }
+
+    /// Returns the probability with which `sample_light` samples the background.
+    fn environment_probability(&self) -> Float {
+        match (&self.environment, self.lights.is_empty()) {
+            (None, _) => 0.,
+            (Some(_), true) => 1.,
+            (Some(_), false) => ENVIRONMENT_PROBABILITY,
+        }
+    }
+
+    /// Samples either the lights or the background for `point` on a surface facing `normal`,
+    /// where the density of the direction includes the probability of the choice.
+    fn sample_light(&self, point: &Point3, normal: &UnitVec3) -> Option<LightSample> {
+        let probability = self.environment_probability();
+        match &self.environment {
+            // Random numbers are not consumed for the choice without backgrounds to sample.
+            Some(environment) if random_double() + 0.5 < probability => {
+                let mut sample = environment.sample()?;
+                sample.pdf *= probability;
+                // The sample carries the whole background along the direction, e.g. the sky around the sun.
+                let ray = Ray {
+                    origin: point.clone(),
+                    direction: sample.direction.clone(),
+                };
+                sample.emitted = ray_background_color(&ray, &self.scene.background);
+                Some(sample)
+            }
+            _ => {
+                let mut sample = self.lights.sample(point, normal)?;
+                sample.pdf *= 1. - probability;
+                Some(sample)
+            }
+        }
+    }
+}
+
+/// Returns the weight of a sample drawn with the density `pdf` against those drawn with `other_pdf`
+/// for the same light by the power heuristic of multiple importance sampling.
+fn power_heuristic(pdf: Float, other_pdf: Float) -> Float {
+    if other_pdf == 0. {
+        return 1.;
+    }
+    // The ratio keeps the squares of the large densities of small lights from overflowing.
+    let ratio = other_pdf / pdf;
+    1. / (1. + ratio * ratio)
+}
+
+/// The type for the points on diffuse surfaces, where the lights are sampled directly
+/// and the scattered rays are guided by the path guide.
+struct DiffuseVertex {
+    point: Point3,
+    normal: UnitVec3,
+    albedo: Attenuation,
+}
+impl DiffuseVertex {
+    /// Returns the vertex at `hit` if `material` is diffuse and there is something to sample there.
+    fn new(lit: &LitScene, ray: &Ray, hit: &HitRecord, material: &SharedMaterial) -> Option<Self> {
+        if !lit.has_lights() && lit.guide.is_none() {
+            return None;
+        }
+        Some(DiffuseVertex {
+            point: ray.at(hit.t),
+            normal: hit.surface_normal.clone(),
+            albedo: material.diffuse_albedo(hit)?,
+        })
+    }
+
+    /// Returns the density over solid angle with which the ray scattered at the vertex goes to `direction`.
+    fn scattering_pdf(&self, lit: &LitScene, direction: &UnitVec3) -> Float {
+        let cosine_pdf = direction.inner_product(&self.normal).max(0.) / consts::PI;
+        match lit
+            .guide
+            .and_then(|guide| guide.pdf(&self.point, direction))
+        {
+            Some(guide_pdf) => {
+                GUIDE_PROBABILITY * guide_pdf + (1. - GUIDE_PROBABILITY) * cosine_pdf
+            }
+            None => cosine_pdf,
+        }
+    }
+}
+
+/// The type for the rays scattered at `DiffuseVertex`es, whose density is needed
+/// to weight the light they find against the light sampled directly and to train the path guide.
+#[derive(Clone)]
+struct DiffuseScatter {
+    point: Point3,
+    normal: UnitVec3,
+    pdf: Float,
+}
+
+/// Returns the weight of the light emitted at `hit` for `ray` scattered from a diffuse surface
+/// by `scattered_from`, where the lights have also been sampled directly.
+fn emission_weight(
+    lit: &LitScene,
+    scattered_from: Option<&DiffuseScatter>,
+    ray: &Ray,
+    hit: &HitRecord,
+) -> Float {
+    let Some(scattered_from) = scattered_from else {
+        return 1.;
+    };
+    let light_pdf = lit.lights.pdf(ray, &scattered_from.normal, hit.t);
+    power_heuristic(
+        scattered_from.pdf,
+        (1. - lit.environment_probability()) * light_pdf,
+    )
+}
+
+/// Returns the weight of the background found by `ray` scattered from a diffuse surface
+/// by `scattered_from`, where the background has also been sampled directly.
+fn background_weight(lit: &LitScene, scattered_from: Option<&DiffuseScatter>, ray: &Ray) -> Float {
+    match (scattered_from, &lit.environment) {
+        (Some(scattered_from), Some(environment)) => {
+            let environment_pdf = lit.environment_probability() * environment.pdf(&ray.direction);
+            power_heuristic(scattered_from.pdf, environment_pdf)
+        }
+        _ => 1.,
+    }
+}
+
+/// Samples a light or the background directly at `vertex`
+/// and returns the light it gives through the shadow ray.
+fn sample_lights(lit: &LitScene, vertex: &DiffuseVertex, stats: Option<&mut RenderStats>) -> Color {
+    let black = Color {
+        r: 0.,
+        g: 0.,
+        b: 0.,
+    };
+    let Some(sample) = lit.sample_light(&vertex.point, &vertex.normal) else {
+        return black;
+    };
+    let cosine = sample.direction.inner_product(&vertex.normal);
+    if cosine <= 0. {
+        return black;
+    }
+    let scattering_pdf = vertex.scattering_pdf(lit, &sample.direction);
+    let shadow_ray = Ray::leaving(&vertex.point, &vertex.normal, sample.direction);
+    let max_t = sample.distance * (1. - SHADOW_TOLERANCE);
+    if hit_scene(&shadow_ray, lit, stats).is_some_and(|(hit, _)| hit.t < max_t) {
+        return black;
+    }
+    // The BRDF times the cosine is `albedo * cosine / PI` for Lambertian reflectance.
+    let weight = power_heuristic(sample.pdf, scattering_pdf) * cosine / consts::PI / sample.pdf;
+    sample.emitted.scale(weight).attenuate(&vertex.albedo)
+}
+
+/// Returns the first object other than shadow catchers that `ray` hits.
+fn hit_object(
+    ray: &Ray,
+    lit: &LitScene,
+    mut stats: Option<&mut RenderStats>,
+) -> Option<(HitRecord, SharedMaterial)> {
+    let mut ray = ray.clone();
+    let mut distance = 0.;
+    while let Some((hit, material)) = hit_scene(&ray, lit, stats.as_deref_mut()) {
+        let point = ray.at(hit.t);
+        if material.catcher_reflectance().is_none() {
+            let t = distance + hit.t;
+            return Some((HitRecord { t, ..hit }, material));
+        }
+        distance += hit.t;
+        ray = Ray::leaving(&point, &hit.surface_normal, ray.direction.clone());
+    }
+    None
+}
+
+/// Returns the fraction of the light arriving at `point` on a shadow catcher from the side of `normal`
+/// that the objects do not block, estimated by a ray toward a light sampled directly,
+/// or toward the background drawn with the cosine-weighted density if there are no lights.
+fn catcher_visibility(
+    lit: &LitScene,
+    point: &Point3,
+    normal: &UnitVec3,
+    stats: Option<&mut RenderStats>,
+) -> Float {
+    let (direction, max_t) = if lit.lights.is_empty() {
+        let local_direction = random_cosine_direction();
+        let direction =
+            Onb::from_normal(normal).local(local_direction.x, local_direction.y, local_direction.z);
+        (direction.unit_vector(), Float::INFINITY)
+    } else {
+        match lit.lights.sample(point, normal) {
+            Some(sample) if sample.direction.inner_product(normal) > 0. => {
+                (sample.direction, sample.distance * (1. - SHADOW_TOLERANCE))
+            }
+            // The light from behind the surface casts no shadows on it.
+            _ => return 1.,
+        }
+    };
+    let ray = Ray::leaving(point, normal, direction);
+    if hit_object(&ray, lit, stats).is_some_and(|(hit, _)| hit.t < max_t) {
+        0.
+    } else {
+        1.
+    }
+}
+
+/// Continues `path` at `hit` on a shadow catcher of `reflectance`, which reflects the ray with
+/// the probability if the reflected ray hits another object, and otherwise lets the ray go through
+/// without counting it as a bounce, dimmed by the shadow unless `behind_catcher`, i.e.,
+/// the ray has already gone through a catcher, e.g. the near side of the same one.
+/// Returns the ray with its attenuation.
+fn pass_catcher(
+    path: &mut PathState,
+    lit: &LitScene,
+    hit: &HitRecord,
+    reflectance: Float,
+    behind_catcher: bool,
+    mut stats: Option<&mut RenderStats>,
+) -> Scattered {
+    let point = path.ray.at(hit.t);
+    let normal = if hit.surface_normal.inner_product(&path.ray.direction) > 0. {
+        -&hit.surface_normal
+    } else {
+        hit.surface_normal.clone()
+    };
+    // Random numbers are not consumed unless the catcher reflects.
+    if !behind_catcher && reflectance > 0. && random_double() + 0.5 < reflectance {
+        let reflected = Ray::leaving(&point, &normal, path.ray.direction.reflect(&normal));
+        if hit_object(&reflected, lit, stats.as_deref_mut()).is_some() {
+            let scattered = Scattered {
+                attenuation: Attenuation {
+                    r: 1.,
+                    g: 1.,
+                    b: 1.,
+                },
+                ray: reflected,
+            };
+            path.scatter(scattered.clone(), 1.);
+            return scattered;
+        }
+    }
+    let visibility = if behind_catcher {
+        1.
+    } else {
+        catcher_visibility(lit, &point, &normal, stats)
+    };
+    let ray = Ray::leaving(&point, &normal, path.ray.direction.clone());
+    path.throughput = path.throughput.scale(visibility);
+    path.ray = ray.clone();
+    Scattered {
+        attenuation: Attenuation {
+            r: visibility,
+            g: visibility,
+            b: visibility,
+        },
+        ray,
+    }
+}
+
+/// Draws the ray scattered at `vertex` from the mixture of the material and the path guide, where
+/// `scattered` is the ray drawn from the material, and returns the ray with its weight, i.e.,
+/// the ratio of the densities of the material and the mixture, together with its density.
+fn guide_scatter(
+    lit: &LitScene,
+    vertex: &DiffuseVertex,
+    scattered: Option<Scattered>,
+) -> (Option<Scattered>, Float, Option<DiffuseScatter>) {
+    let guided_direction = match lit.guide {
+        // Random numbers are not consumed where the guide has not learned the light.
+        Some(guide) if guide.covers(&vertex.point) => {
+            // `random_double` returns numbers in [-0.5, 0.5).
+            if random_double() + 0.5 < GUIDE_PROBABILITY {
+                guide.sample(&vertex.point)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    };
+    let scattered = match guided_direction {
+        Some(direction) => {
+            let ray = Ray::leaving(&vertex.point, &vertex.normal, direction);
+            Some(Scattered {
+                attenuation: vertex.albedo.clone(),
+                ray,
+            })
+        }
+        None => scattered,
+    };
+    let Some(scattered) = scattered else {
+        return (None, 1., None);
+    };
+    let cosine_pdf = scattered.ray.direction.inner_product(&vertex.normal) / consts::PI;
+    if cosine_pdf <= 0. {
+        // Guided rays going below the surface carry no light.
+        return (None, 1., None);
+    }
+    let pdf = vertex.scattering_pdf(lit, &scattered.ray.direction);
+    let scatter = DiffuseScatter {
+        point: vertex.point.clone(),
+        normal: vertex.normal.clone(),
+        pdf,
+    };
+    (Some(scattered), cosine_pdf / pdf, Some(scatter))
+}
+
+/// Samples the lights directly and guides the ray `scattered` by `material` at `hit` if the material
+/// is diffuse, and returns the light sampled directly, the ray with its weight, and its density.
+fn shade_diffuse(
+    lit: &LitScene,
+    ray: &Ray,
+    hit: &HitRecord,
+    material: &SharedMaterial,
+    scattered: Option<Scattered>,
+    stats: Option<&mut RenderStats>,
+) -> (Color, Option<Scattered>, Float, Option<DiffuseScatter>) {
+    let Some(vertex) = DiffuseVertex::new(lit, ray, hit, material) else {
+        let black = Color {
+            r: 0.,
+            g: 0.,
+            b: 0.,
+        };
+        return (black, scattered, 1., None);
+    };
+    let direct = if !lit.has_lights() {
+        Color {
+            r: 0.,
+            g: 0.,
+            b: 0.,
+        }
+    } else {
+        sample_lights(lit, &vertex, stats)
+    };
+    let (scattered, weight, scatter) = guide_scatter(lit, &vertex, scattered);
+    (direct, scattered, weight, scatter)
+}
+
+/// The type for the state of a path being traced, which is updated at each bounce.
+struct PathState {
+    /// The product of the attenuations and the weights of the bounces so far, by which the light
+    /// found by `ray` is multiplied to give its contribution to the sample.
+    throughput: Color,
+    /// The ray to be traced next.
+    ray: Ray,
+    /// The number of the bounces the path can still make.
+    depth: i32,
+    /// The media `ray` is in, which are updated as it enters and leaves them.
+    medium: MediumStack,
+}
+impl PathState {
+    fn new(ray: &Ray, depth: i32) -> Self {
+        PathState {
+            throughput: Color {
+                r: 1.,
+                g: 1.,
+                b: 1.,
+            },
+            ray: ray.clone(),
+            depth,
+            medium: MediumStack::new(),
+        }
+    }
+
+    /// Continues the path along the ray of `scattered`, whose light is also multiplied by `weight`.
+    fn scatter(&mut self, scattered: Scattered, weight: Float) {
+        self.throughput = self
+            .throughput
+            .attenuate(&scattered.attenuation)
+            .scale(weight);
+        self.ray = scattered.ray;
+        self.depth -= 1;
+    }
+}
+
+/// The type for the rays scattered at diffuse surfaces while the path guide is being trained,
+/// which collect the light arriving along them from the rest of the path.
+struct GuideSample {
+    scatter: DiffuseScatter,
+    ray: Ray,
+    /// The throughput from the surface, by which the light found later is multiplied.
+    throughput: Color,
+    incident: Color,
+}
+
+/// Returns the color of `ray` traced for at most `diffusion_depth` bounces, where `roulette` is
+/// the survival probabilities of the scattered rays from the first bounce, collecting the statistics
+/// in `stats`, the light arriving at diffuse surfaces in `trained`, and each bounce in `bounces`
+/// if given, together with how the path ended. The rays terminated by the roulette are recorded
+/// as absorbed in `bounces`.
+fn ray_color(
+    ray: &Ray,
+    lit: &LitScene,
+    diffusion_depth: i32,
+    mut roulette: &[Float],
+    mut stats: Option<&mut RenderStats>,
+    trained: Option<&mut PathGuide>,
+    mut bounces: Option<&mut Vec<Bounce>>,
+) -> (Color, PathEnd) {
+    let mut path = PathState::new(ray, diffusion_depth);
+    let mut terminated = false;
+    let mut color = Color {
+        r: 0.,
+        g: 0.,
+        b: 0.,
+    };
+    // The diffuse bounce the ray is scattered from if the lights have been sampled directly there.
+    let mut scattered_from: Option<DiffuseScatter> = None;
+    let mut guide_samples: Vec<GuideSample> = vec![];
+    // Whether the ray has gone through a shadow catcher since the last bounce.
+    let mut behind_catcher = false;
+    // The light found is also added to that arriving at the diffuse surfaces on the path so far.
+    let add_light =
+        |color: &mut Color, guide_samples: &mut [GuideSample], path: &PathState, light: &Color| {
+            *color = color.add(&light.attenuate(&path.throughput));
+            for sample in guide_samples.iter_mut() {
+                sample.incident = sample.incident.add(&light.attenuate(&sample.throughput));
+            }
+        };
+    loop {
+        if path.depth <= 0 {
+            if let Some(bounces) = bounces.as_deref_mut() {
+                bounces.push(Bounce::DepthExceeded { ray: path.ray });
+            }
+            break;
+        }
+        let origin = path.ray.origin.clone();
+        let (continued, maybe_hit) =
+            hit_in_media(&path.ray, lit, &mut path.medium, stats.as_deref_mut());
+        path.ray = continued;
+        // Shadow rays are blocked by the boundaries of dielectrics and the shadow catchers
+        // that rays pass through.
+        let from = scattered_from.take().filter(|_| path.ray.origin == origin);
+        let Some((hit, material)) = maybe_hit else {
+            let weight = background_weight(lit, from.as_ref(), &path.ray);
+            let background = ray_background_color(&path.ray, &lit.scene.background).scale(weight);
+            add_light(&mut color, &mut guide_samples, &path, &background);
+            if let Some(bounces) = bounces.as_deref_mut() {
+                bounces.push(Bounce::Background {
+                    ray: path.ray,
+                    color: background,
+                });
+            }
+            break;
+        };
+        if let Some(reflectance) = material.catcher_reflectance() {
+            let ray = path.ray.clone();
+            let scattered = pass_catcher(
+                &mut path,
+                lit,
+                &hit,
+                reflectance,
+                behind_catcher,
+                stats.as_deref_mut(),
+            );
+            behind_catcher = scattered.ray.direction == ray.direction;
+            for sample in guide_samples.iter_mut() {
+                sample.throughput = sample.throughput.attenuate(&scattered.attenuation);
+            }
+            if let Some(bounces) = bounces.as_deref_mut() {
+                let black = Color {
+                    r: 0.,
+                    g: 0.,
+                    b: 0.,
+                };
+                bounces.push(Bounce::Hit {
+                    ray,
+                    hit,
+                    material,
+                    emitted: black.clone(),
+                    direct: black,
+                    scattered: Some(scattered),
+                });
+            }
+            continue;
+        }
+        let (emitted, scattered) = shade(&mut path, &hit, &material, stats.as_deref_mut());
+        let weight = emission_weight(lit, from.as_ref(), &path.ray, &hit);
+        let emitted = emitted.scale(weight);
+        let (direct, scattered, guide_weight, scatter) = shade_diffuse(
+            lit,
+            &path.ray,
+            &hit,
+            &material,
+            scattered,
+            stats.as_deref_mut(),
+        );
+        let was_scattered = scattered.is_some();
+        let (scattered, roulette_weight, rest) = play_roulette(scattered, roulette);
+        roulette = rest;
+        terminated = was_scattered && scattered.is_none();
+        add_light(&mut color, &mut guide_samples, &path, &emitted.add(&direct));
+        if let Some(bounces) = bounces.as_deref_mut() {
+            bounces.push(Bounce::Hit {
+                ray: path.ray.clone(),
+                hit,
+                material,
+                emitted,
+                direct,
+                scattered: scattered.clone(),
+            });
+        }
+        let Some(scattered) = scattered else {
+            break;
+        };
+        for sample in guide_samples.iter_mut() {
+            sample.throughput = sample
+                .throughput
+                .attenuate(&scattered.attenuation)
+                .scale(roulette_weight * guide_weight);
+        }
+        if let (Some(_), Some(scatter)) = (&trained, &scatter) {
+            // The light arriving along the ray includes the weight of the roulette.
+            let throughput = Color {
+                r: 1.,
+                g: 1.,
+                b: 1.,
+            };
+            guide_samples.push(GuideSample {
+                scatter: scatter.clone(),
+                ray: scattered.ray.clone(),
+                throughput: throughput.scale(roulette_weight),
+                incident: Color {
+                    r: 0.,
+                    g: 0.,
+                    b: 0.,
+                },
+            });
+        }
+        path.scatter(scattered, roulette_weight * guide_weight);
+        scattered_from = scatter;
+        behind_catcher = false;
+    }
+    if let Some(trained) = trained {
+        // The deepest bounces are recorded first as the light arrives there first.
+        for sample in guide_samples.iter().rev() {
+            let radiance = (sample.incident.r + sample.incident.g + sample.incident.b) / 3.;
+            let point = &sample.scatter.point;
+            trained.record(point, &sample.ray.direction, radiance / sample.scatter.pdf);
+        }
+    }
+    let end = PathEnd {
+        length: (diffusion_depth - path.depth).max(0) as usize,
+        terminated,
+    };
+    if let Some(stats) = stats {
+        stats.add_path(end);
+    }
+    (color, end)
+}
+
+/// The type for what happened to each ray of a path traced by `Renderer::trace_pixel`.
+#[derive(Clone, Debug)]
+pub enum Bounce {
+    /// The ray hit a surface, where `scattered` is `None` if the ray was absorbed there.
+    /// `emitted` is the light emitted at the surface weighted against the light sampled directly
+    /// at the previous bounce, and `direct` is the light sampled directly at this bounce.
+    Hit {
+        ray: Ray,
+        hit: HitRecord,
+        material: SharedMaterial,
+        emitted: Color,
+        direct: Color,
+        scattered: Option<Scattered>,
+    },
+    /// The ray hit nothing and got the color of the background.
+    Background { ray: Ray, color: Color },
+    /// The ray was not traced because the path had already bounced as many times as allowed.
+    DepthExceeded { ray: Ray },
+}
+impl Bounce {
+    /// Returns the ray arriving at the bounce.
+    pub fn ray(&self) -> &Ray {
+        match self {
+            Bounce::Hit { ray, .. }
+            | Bounce::Background { ray, .. }
+            | Bounce::DepthExceeded { ray } => ray,
+        }
+    }
+}
+
+/// The type for the paths of the samples of pixels.
+#[derive(Clone, Debug)]
+pub struct TracedPath {
+    /// The color the path contributes to the pixel.
+    pub color: Color,
+    pub bounces: Vec<Bounce>,
+}
+impl TracedPath {
+    /// Returns the point where the path starts and those where it hits surfaces, e.g. to draw it
+    /// as a polyline. If the last ray hits nothing, it ends at the distance `escaped_length`.
+    pub fn points(&self, escaped_length: Float) -> Vec<Point3> {
+        let mut points: Vec<Point3> = self
+            .bounces
+            .first()
+            .map(|bounce| bounce.ray().origin.clone())
+            .into_iter()
+            .collect();
+        for bounce in self.bounces.iter() {
+            match bounce {
+                Bounce::Hit { ray, hit, .. } => points.push(ray.at(hit.t)),
+                Bounce::Background { ray, .. } => points.push(ray.at(escaped_length)),
+                Bounce::DepthExceeded { .. } => {}
+            }
+        }
+        points
+    }
+}
+
+/// The type for samples whose colors are NaN or infinite, found if `RenderSettings::check_samples` is set.
+#[derive(Clone, Debug)]
+pub struct InvalidSample {
+    /// The pixel, counted from the top left.
+    pub x: usize,
+    pub y: usize,
+    pub color: Color,
+    /// The index of the bounce in the path where the values first became NaN or infinite,
+    /// or of the last bounce if all the values were finite but the color was not, e.g. by overflow.
+    pub depth: usize,
+    pub bounce: Bounce,
+}
+
+fn are_finite(values: &[Float]) -> bool {
+    values.iter().all(|value| value.is_finite())
+}
+
+fn is_finite_ray(ray: &Ray) -> bool {
+    let (origin, direction) = (&ray.origin, ray.direction.inject());
+    are_finite(&[
+        origin.x,
+        origin.y,
+        origin.z,
+        direction.x,
+        direction.y,
+        direction.z,
+    ])
+}
+
+impl Bounce {
+    /// Checks that all the values of the bounce are finite.
+    fn is_finite(&self) -> bool {
+        match self {
+            Bounce::Hit {
+                ray,
+                hit,
+                emitted,
+                direct,
+                scattered,
+                ..
+            } => {
+                let normal = hit.surface_normal.inject();
+                let is_finite_scattered = scattered.as_ref().is_none_or(|scattered| {
+                    let attenuation = &scattered.attenuation;
+                    are_finite(&[attenuation.r, attenuation.g, attenuation.b])
+                        && is_finite_ray(&scattered.ray)
+                });
+                is_finite_ray(ray)
+                    && are_finite(&[hit.t, normal.x, normal.y, normal.z])
+                    && are_finite(&[emitted.r, emitted.g, emitted.b])
+                    && are_finite(&[direct.r, direct.g, direct.b])
+                    && is_finite_scattered
+            }
+            Bounce::Background { ray, color } => {
+                is_finite_ray(ray) && are_finite(&[color.r, color.g, color.b])
+            }
+            Bounce::DepthExceeded { ray } => is_finite_ray(ray),
+        }
+    }
+}
+
+/// Seeds the random numbers for the `sample`-th sample of the `i`-th pixel of the `j`-th row
+/// from the bottom if `settings` has a seed.
+fn seed_sample(settings: &RenderSettings, (i, j): (usize, usize), sample: usize) {
+    if let Some(seed) = settings.seed {
+        // Each sample has its own sequence so that it depends neither on the thread rendering it
+        // nor on the samples taken before it, e.g. those of the pixels skipped by `render_edges`.
+        seed_random(sample_seed(seed, (i, j), sample));
+    }
+}
+
+/// Returns the seed of the `sample`-th sample of the `i`-th pixel of the `j`-th row mixed
+/// from `seed` by the finalizer of SplitMix64, so that neighboring samples have unrelated seeds.
+fn sample_seed(seed: u64, (i, j): (usize, usize), sample: usize) -> u64 {
+    let mix = |z: u64| {
+        let z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        let z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    };
+    [i, j, sample].into_iter().fold(mix(seed), |hash, n| {
+        mix(hash.wrapping_add(0x9e3779b97f4a7c15) ^ n as u64)
+    })
+}
+
+/// Returns a ray for a random sample in the `i`-th pixel of the `j`-th row from the bottom
+/// with the offset of the sample from the center of the pixel and the weight of the sample,
+/// where the offset is drawn from `sampler` if given and uniformly within the pixel otherwise.
+fn sample_ray(
+    scene: &Scene,
+    settings: &RenderSettings,
+    sampler: Option<&FilterSampler>,
+    i: usize,
+    j: usize,
+) -> (Ray, (Float, Float), Float) {
+    let ((dx, dy), weight) = match sampler {
+        Some(sampler) => sampler.sample(random_double() + 0.5, random_double() + 0.5),
+        None => ((random_double(), random_double()), 1.),
+    };
+    let u: Float = (i as Float + dx) / ((settings.image_width - 1) as Float);
+    let v: Float = (j as Float + dy) / ((settings.image_height - 1) as Float);
+    (scene.camera.get_ray(u, v), (dx, dy), weight)
+}
+
+/// The type for the surfaces seen at pixels, which are returned by `Renderer::pick_focus`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FocusPick {
+    pub point: Point3,
+    /// The distance from the camera to `point`.
+    pub distance: Float,
+    /// The distance from the camera to the plane through `point` perpendicular to the view direction,
+    /// which is what thin-lens cameras take as the focus distance.
+    pub focus_distance: Float,
+}
+
+/// Returns the color of a sample of the `i`-th pixel of the `j`-th row from the bottom
+/// and how its path ended, pushing it to `invalid_samples` if it is NaN or infinite.
+fn check_sample(
+    ray: &Ray,
+    lit: &LitScene,
+    settings: &RenderSettings,
+    (i, j): (usize, usize),
+    invalid_samples: &mut Vec<InvalidSample>,
+    stats: Option<&mut RenderStats>,
+    trained: Option<&mut PathGuide>,
+) -> (Color, PathEnd) {
+    let mut bounces = vec![];
+    let depth = settings.max_diffusion_depth;
+    let roulette = &settings.roulette;
+    let (color, end) = ray_color(
+        ray,
+        lit,
+        depth,
+        roulette,
+        stats,
+        trained,
+        Some(&mut bounces),
+    );
+    if !are_finite(&[color.r, color.g, color.b]) {
+        let depth = bounces
+            .iter()
+            .position(|bounce| !bounce.is_finite())
+            .unwrap_or(bounces.len() - 1);
+        invalid_samples.push(InvalidSample {
+            x: i,
+            y: settings.image_height - 1 - j,
+            color: color.clone(),
+            depth,
+            bounce: bounces.swap_remove(depth),
+        });
+    }
+    (color, end)
+}
+
+/// The type for the results of rendering a scan line.
+struct RenderedRow {
+    /// The sums of the weighted colors of the samples splatted into the pixels of the scan lines
+    /// from `RenderSettings::margin()` lines above to as many lines below, from the top.
+    sums: Vec<Color>,
+    /// The sums of the weights of the samples in the same order as `sums`.
+    weights: Vec<Float>,
+    /// The samples that are NaN or infinite if `RenderSettings::check_samples` is set,
+    /// in which case the sums of the pixels with such samples are magenta.
+    invalid_samples: Vec<InvalidSample>,
+    /// The statistics if `RenderSettings::collect_stats` is set.
+    stats: Option<RenderStats>,
+    /// The lengths of the paths of the pixels from the left if `RenderSettings::collect_stats` is set,
+    /// and empty otherwise.
+    path_lengths: Vec<PathLengths>,
+    /// The light arriving at the diffuse surfaces if the path guide is being trained.
+    guide: Option<PathGuide>,
+}
+
+/// Renders the `j`-th row from the bottom, training a new path guide with the same cells
+/// as the guide of `lit` if `train_guide` is set. If `edges` is given, only the pixels on edges
+/// are sampled, though the others may get the samples of their neighbors through the filter.
+fn render_row(
+    lit: &LitScene,
+    settings: &RenderSettings,
+    j: usize,
+    train_guide: bool,
+    edges: Option<&[bool]>,
+) -> RenderedRow {
+    let mut invalid_samples = vec![];
+    let mut stats = settings.collect_stats.then(RenderStats::default);
+    let mut path_lengths = vec![];
+    if settings.collect_stats {
+        path_lengths.resize(settings.image_width, PathLengths::default());
+    }
+    let mut guide = lit
+        .guide
+        .filter(|_| train_guide)
+        .map(|guide| PathGuide::new(guide.cell_size()));
+    let width = settings.image_width;
+    let margin = settings.margin();
+    let sampler = settings
+        .filter_sampling
+        .then(|| FilterSampler::new(settings.filter));
+    let num_lines = 2 * margin + 1;
+    let black = Color {
+        r: 0.,
+        g: 0.,
+        b: 0.,
+    };
+    let mut sums = vec![black; num_lines * width];
+    let mut weights = vec![0.; num_lines * width];
+    let mut samples = Vec::with_capacity(settings.num_samples_per_pixel);
+    for i in 0..width {
+        if edges.is_some_and(|edges| !edges[i]) {
+            continue;
+        }
+        samples.clear();
+        let num_invalid_samples = invalid_samples.len();
+        for sample in 0..settings.num_samples_per_pixel {
+            seed_sample(settings, (i, j), sample);
+            let (ray, offset, weight) = sample_ray(lit.scene, settings, sampler.as_ref(), i, j);
+            let stats = stats.as_mut();
+            let trained = guide.as_mut();
+            let (color, end) = if settings.check_samples {
+                let invalid_samples = &mut invalid_samples;
+                check_sample(&ray, lit, settings, (i, j), invalid_samples, stats, trained)
+            } else {
+                let depth = settings.max_diffusion_depth;
+                let roulette = &settings.roulette;
+                ray_color(&ray, lit, depth, roulette, stats, trained, None)
+            };
+            if let Some(lengths) = path_lengths.get_mut(i) {
+                lengths.add(end);
+            }
+            samples.push((offset, weight, color));
+        }
+        if invalid_samples.len() > num_invalid_samples {
+            let magenta = Color {
+                r: 1.,
+                g: 0.,
+                b: 1.,
+            };
+            for (_, _, color) in samples.iter_mut() {
+                *color = magenta.clone();
+            }
+        }
+        // The `line`-th scan line of the buffers is the `(j + margin - line)`-th one from the bottom.
+        for ((sample_dx, sample_dy), sample_weight, color) in &samples {
+            if sampler.is_some() {
+                // The filter is already applied by drawing the offsets from it.
+                sums[i] = sums[i].add(&color.scale(*sample_weight));
+                weights[i] += sample_weight;
+                continue;
+            }
+            for line in 0..num_lines {
+                let dy = sample_dy + line as Float - margin as Float;
+                for x in i.saturating_sub(margin)..(i + margin + 1).min(width) {
+                    let dx = sample_dx + i as Float - x as Float;
+                    let weight = settings.filter.weight(dx, dy);
+                    if weight != 0. {
+                        let index = line * width + x;
+                        sums[index] = sums[index].add(&color.scale(weight));
+                        weights[index] += weight;
+                    }
+                }
+            }
+        }
+    }
+    RenderedRow {
+        sums,
+        weights,
+        invalid_samples,
+        stats,
+        path_lengths,
+        guide,
+    }
+}
+
+/// Returns the sums of the light arriving at the points of a row of texels from the hemispheres
+/// on their front sides drawn in proportion to the cosine, where `j` is the row from the bottom.
+fn bake_row(
+    lit: &LitScene,
+    settings: &RenderSettings,
+    points: &[Option<SurfacePoint>],
+    j: usize,
+) -> Vec<Color> {
+    let black = Color {
+        r: 0.,
+        g: 0.,
+        b: 0.,
+    };
+    let mut sums = vec![black; points.len()];
+    for (i, point) in points.iter().enumerate() {
+        let Some(SurfacePoint { point, normal }) = point else {
+            continue;
+        };
+        let onb = Onb::from_normal(normal);
+        for sample in 0..settings.num_samples_per_pixel {
+            seed_sample(settings, (i, j), sample);
+            let local = random_cosine_direction();
+            let direction = onb.local(local.x, local.y, local.z).unit_vector();
+            let ray = Ray::leaving(point, normal, direction);
+            let depth = settings.max_diffusion_depth;
+            let (color, _) = ray_color(&ray, lit, depth, &settings.roulette, None, None, None);
+            sums[i] = sums[i].add(&color);
+        }
+    }
+    sums
+}
+
+/// The number of the samples of a light probe traced by a worker at once.
+const PROBE_CHUNK_SIZE: usize = 256;
+
+/// Returns the sums of the light arriving at `position` from the directions of the samples
+/// in `samples`, drawn uniformly from the sphere, multiplied by the spherical harmonics of them,
+/// where the samples are seeded as those of the `index`-th pixel of the bottom row.
+fn probe_chunk(
+    lit: &LitScene,
+    settings: &RenderSettings,
+    position: &Point3,
+    index: usize,
+    samples: Range<usize>,
+) -> [Color; NUM_SH_COEFFICIENTS] {
+    let mut sums = std::array::from_fn(|_| Color {
+        r: 0.,
+        g: 0.,
+        b: 0.,
+    });
+    for sample in samples {
+        seed_sample(settings, (index, 0), sample);
+        let ray = Ray {
+            origin: position.clone(),
+            direction: random_unit_vector(),
+        };
+        let depth = settings.max_diffusion_depth;
+        let (color, _) = ray_color(&ray, lit, depth, &settings.roulette, None, None, None);
+        for (sum, y) in sums.iter_mut().zip(sh_basis(&ray.direction)) {
+            *sum = sum.add(&color.scale(y));
+        }
+    }
+    sums
+}
+
+/// Lowers the scheduling priority of the current thread as `nice 10` does.
+#[cfg(unix)]
+fn lower_thread_priority() {
+    // On Linux, this affects only the calling thread; on other Unix systems, the whole process.
+    unsafe {
+        libc::setpriority(libc::PRIO_PROCESS, 0, 10);
+    }
+}
+
+#[cfg(not(unix))]
+fn lower_thread_priority() {}
+
+/// Renders `scene` into a new film with `settings.num_threads` threads while showing the progress.
+/// `on_scan_line` is called with the film every time a scan line is finished;
+/// if it returns `false`, the rendering is aborted and the partially rendered film is returned
+/// as `Err`. With `settings.path_guiding`, the samples are rendered in passes as `render_guided` does,
+/// and with `settings.adaptive_samples`, the edges get extra samples afterwards as `render_edges` does.
+pub fn render(
+    scene: &Scene,
+    settings: &RenderSettings,
+    mut on_scan_line: impl FnMut(&Film) -> bool,
+) -> Result<Film, Film> {
+    let bvh = TopLevelBvh::new(&scene.world);
+    let film = if settings.path_guiding {
+        render_guided(scene, &bvh, settings, &mut on_scan_line)?
+    } else {
+        let mut progress_bar = settings
+            .show_progress
+            .then(|| ProgressBar::new(settings.image_height));
+        let token = CancellationToken::new();
+        let result = render_scan_lines(
+            &LitScene::new(scene, &bvh, None),
+            settings,
+            None,
+            None,
+            &token,
+            0..settings.image_height,
+            |film, _| {
+                if let Some(progress_bar) = &mut progress_bar {
+                    progress_bar.advance(
+                        1,
+                        (settings.image_width * settings.num_samples_per_pixel) as u64,
+                    );
+                }
+                on_scan_line(film)
+            },
+        );
+        if let Some(progress_bar) = &mut progress_bar {
+            progress_bar.finish();
+        }
+        result?
+    };
+    if settings.adaptive_samples == 0 {
+        return Ok(film);
+    }
+    render_edges(scene, &bvh, settings, film, on_scan_line)
+}
+
+/// The number mixed into the seed of the pass of `render_edges`
+/// so that it takes random numbers different from those of the passes before it.
+const EDGE_PASS_SEED: u64 = 0xed9e;
+
+/// Adds `settings.adaptive_samples` samples per pixel to the pixels of `film` on the edges
+/// of `scene` and of the image so far while showing the progress, calling `on_scan_line` as `render`.
+fn render_edges(
+    scene: &Scene,
+    bvh: &TopLevelBvh,
+    settings: &RenderSettings,
+    mut film: Film,
+    mut on_scan_line: impl FnMut(&Film) -> bool,
+) -> Result<Film, Film> {
+    let edges = EdgeMask::find(scene, &film);
+    let pass_settings = RenderSettings {
+        num_samples_per_pixel: settings.adaptive_samples,
+        seed: settings.seed.map(|seed| seed ^ EDGE_PASS_SEED),
+        ..settings.clone()
+    };
+    let mut progress_bar = settings
+        .show_progress
+        .then(|| ProgressBar::new(settings.image_height));
+    let token = CancellationToken::new();
+    let rows = 0..settings.image_height;
+    let result = render_scan_lines(
+        &LitScene::new(scene, bvh, None),
+        &pass_settings,
+        None,
+        Some(&edges),
+        &token,
+        rows,
+        |pass_film, y| {
+            film.add_scan_line(pass_film, y);
+            let num_edges = edges.row(y).iter().filter(|&&is_edge| is_edge).count();
+            if let Some(progress_bar) = &mut progress_bar {
+                progress_bar.advance(1, (num_edges * settings.adaptive_samples) as u64);
+            }
+            on_scan_line(&film)
+        },
+    );
+    if let Some(progress_bar) = &mut progress_bar {
+        progress_bar.finish();
+    }
+    match result {
+        Ok(pass_film) => {
+            add_pass_records(&mut film, &pass_film);
+            Ok(film)
+        }
+        Err(pass_film) => {
+            add_pass_records(&mut film, &pass_film);
+            Err(film)
+        }
+    }
+}
+
+/// Adds the invalid samples and the statistics of `pass_film` to `film`,
+/// whose samples are added scan line by scan line while the pass is rendered.
+fn add_pass_records(film: &mut Film, pass_film: &Film) {
+    for invalid_sample in pass_film.invalid_samples() {
+        film.add_invalid_sample(invalid_sample.clone());
+    }
+    film.add_stats(pass_film.stats().clone());
+    for (index, lengths) in pass_film.path_lengths().iter().enumerate() {
+        film.add_path_lengths(index % film.width, index / film.width, lengths);
+    }
+}
+
+/// The number of cells of path guides along the longest side of the visible part of scenes.
+const GUIDE_RESOLUTION: Float = 32.;
+
+/// Returns the size of the cells of the path guide for `scene`, which is based on the size of
+/// the part of the scene seen through a grid of pixels so that the guide does not spend its cells
+/// on the far background.
+fn guide_cell_size(scene: &Scene) -> Float {
+    let num_rays = 17;
+    let mut bounds: Option<(Point3, Point3)> = None;
+    for i in 0..num_rays {
+        for j in 0..num_rays {
+            let u = i as Float / (num_rays - 1) as Float;
+            let v = j as Float / (num_rays - 1) as Float;
+            let ray = scene.camera.get_ray(u, v);
+            let Some((hit, _)) = scene.world.hit(&ray, &RAY_T) else {
+                continue;
+            };
+            let point = ray.at(hit.t);
+            bounds = Some(match bounds {
+                None => (point.clone(), point),
+                Some((min, max)) => (
+                    Point3 {
+                        x: min.x.min(point.x),
+                        y: min.y.min(point.y),
+                        z: min.z.min(point.z),
+                    },
+                    Point3 {
+                        x: max.x.max(point.x),
+                        y: max.y.max(point.y),
+                        z: max.z.max(point.z),
+                    },
+                ),
+            });
+        }
+    }
+    let size = bounds.map_or(0., |(min, max)| {
+        (max.x - min.x).max(max.y - min.y).max(max.z - min.z)
+    });
+    if size > 0. && size.is_finite() {
+        size / GUIDE_RESOLUTION
+    } else {
+        1.
+    }
+}
+
+/// Splits `num_samples_per_pixel` into the passes of guided renderings, which double in size
+/// while they take up to half of the samples, so that the guide learns quickly at first
+/// and the rest of the samples are rendered with the guide learned by then.
+fn guided_passes(num_samples_per_pixel: usize) -> Vec<usize> {
+    let mut passes = vec![];
+    let mut num_rendered = 0;
+    let mut pass = 1;
+    while num_rendered + pass <= num_samples_per_pixel / 2 {
+        passes.push(pass);
+        num_rendered += pass;
+        pass *= 2;
+    }
+    if num_rendered < num_samples_per_pixel {
+        passes.push(num_samples_per_pixel - num_rendered);
+    }
+    passes
+}
+
+/// Renders `scene` as `render` does but in passes, where the light arriving at diffuse surfaces
+/// is learned by a path guide in each pass and the scattered rays of the next pass are guided to
+/// where the light comes from. The film has the samples of all the passes, which are all unbiased.
+fn render_guided(
+    scene: &Scene,
+    bvh: &TopLevelBvh,
+    settings: &RenderSettings,
+    mut on_scan_line: impl FnMut(&Film) -> bool,
+) -> Result<Film, Film> {
+    let passes = guided_passes(settings.num_samples_per_pixel);
+    let mut progress_bar = settings
+        .show_progress
+        .then(|| ProgressBar::new(passes.len() * settings.image_height));
+    let mut film = Film::new(settings.image_width, settings.image_height);
+    let token = CancellationToken::new();
+    let mut guide = PathGuide::new(guide_cell_size(scene));
+    for (pass, &num_samples) in passes.iter().enumerate() {
+        let pass_settings = RenderSettings {
+            num_samples_per_pixel: num_samples,
+            seed: settings.seed.map(|seed| seed.wrapping_add(pass as u64)),
+            ..settings.clone()
+        };
+        let distribution = guide.distribution();
+        // The last pass has nothing to train the guide for.
+        let trained = (pass + 1 < passes.len()).then_some(&mut guide);
+        let rows = 0..settings.image_height;
+        let result = render_scan_lines(
+            &LitScene::new(scene, bvh, Some(&distribution)),
+            &pass_settings,
+            trained,
+            None,
+            &token,
+            rows,
+            |pass_film, y| {
+                film.add_scan_line(pass_film, y);
+                if let Some(progress_bar) = &mut progress_bar {
+                    progress_bar.advance(1, (settings.image_width * num_samples) as u64);
+                }
+                on_scan_line(&film)
+            },
+        );
+        let (pass_film, is_aborted) = match result {
+            Ok(pass_film) => (pass_film, false),
+            Err(pass_film) => (pass_film, true),
+        };
+        add_pass_records(&mut film, &pass_film);
+        if is_aborted {
+            if let Some(progress_bar) = &mut progress_bar {
+                progress_bar.finish();
+            }
+            return Err(film);
+        }
+    }
+    if let Some(progress_bar) = &mut progress_bar {
+        progress_bar.finish();
+    }
+    Ok(film)
+}
+
+/// Renders `scene` in passes of `settings.num_samples_per_pixel` samples per pixel until `time_limit`
+/// has elapsed while showing the progress, and returns the film of the samples of all the passes.
+/// The first pass is always finished so that every pixel has samples, while the pass in progress
+/// at the deadline is stopped, whose finished scan lines are kept with more samples than the others.
+/// `on_scan_line` is called as in `render` but with the film of all the passes.
+/// With `settings.path_guiding`, each pass is guided by what the passes before it have learned.
+pub fn render_for(
+    scene: &Scene,
+    settings: &RenderSettings,
+    time_limit: Duration,
+    mut on_scan_line: impl FnMut(&Film) -> bool,
+) -> Result<Film, Film> {
+    let start = Instant::now();
+    // The progress is measured in milliseconds towards the deadline.
+    let total_millis = (time_limit.as_millis() as usize).max(1);
+    let elapsed_millis = || (start.elapsed().as_millis() as usize).min(total_millis);
+    let mut progress_bar = settings
+        .show_progress
+        .then(|| ProgressBar::new(total_millis));
+    let mut reported_millis = 0;
+    let mut film = Film::new(settings.image_width, settings.image_height);
+    let token = CancellationToken::new();
+    let mut guide = settings
+        .path_guiding
+        .then(|| PathGuide::new(guide_cell_size(scene)));
+    let bvh = TopLevelBvh::new(&scene.world);
+    let mut pass = 0;
+    loop {
+        // The passes take different random numbers even with a seed.
+        let pass_settings = RenderSettings {
+            seed: settings.seed.map(|seed| seed.wrapping_add(pass)),
+            ..settings.clone()
+        };
+        let mut is_aborted = false;
+        let rows = 0..settings.image_height;
+        let distribution = guide.as_ref().map(PathGuide::distribution);
+        let result = render_scan_lines(
+            &LitScene::new(scene, &bvh, distribution.as_ref()),
+            &pass_settings,
+            guide.as_mut(),
+            None,
+            &token,
+            rows,
+            |pass_film, y| {
+                film.add_scan_line(pass_film, y);
+                let millis = elapsed_millis();
+                if let Some(progress_bar) = &mut progress_bar {
+                    progress_bar.advance(
+                        millis - reported_millis,
+                        (settings.image_width * settings.num_samples_per_pixel) as u64,
+                    );
+                }
+                reported_millis = millis;
+                if !on_scan_line(&film) {
+                    is_aborted = true;
+                    return false;
+                }
+                pass == 0 || start.elapsed() < time_limit
+            },
+        );
+        let pass_film = match result {
+            Ok(pass_film) | Err(pass_film) => pass_film,
+        };
+        add_pass_records(&mut film, &pass_film);
+        if is_aborted || start.elapsed() >= time_limit {
+            if let Some(progress_bar) = &mut progress_bar {
+                progress_bar.finish();
+            }
+            return if is_aborted { Err(film) } else { Ok(film) };
+        }
+        pass += 1;
+    }
+}
+
+/// The type for finished rectangular parts of images, which are reported while rendering.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tile {
+    /// The top-left pixel of the part, where `(0, 0)` is the top-left pixel of the image.
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    /// The averaged linear colors of the pixels in the part in row-major order.
+    pub pixels: Vec<Color>,
+    /// The number of the parts finished so far including this one.
+    pub num_finished: usize,
+    /// The number of the parts of the whole image.
+    pub num_total: usize,
+}
+impl Tile {
+    fn from_scan_line(film: &Film, y: usize, num_finished: usize) -> Self {
+        Tile {
+            x: 0,
+            y,
+            width: film.width,
+            height: 1,
+            pixels: (0..film.width).map(|x| film.pixel(x, y)).collect(),
+            num_finished,
+            num_total: film.height,
+        }
+    }
+}
+
+/// The type for flags that stop renderings from other threads, e.g. by a cancel button of a host
+/// application. Clones of a token share the flag.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    is_cancelled: Arc<AtomicBool>,
+}
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops the renderings using this token once the scan lines in progress are finished.
+    pub fn cancel(&self) {
+        self.is_cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.is_cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// The type for renderers for applications embedding this crate,
+/// which return images in memory instead of showing the progress or writing files.
+#[derive(Clone, Debug)]
+pub struct Renderer {
+    pub settings: RenderSettings,
+    cancellation_token: CancellationToken,
+    /// The top-level BVH of the scene rendered last, which is refitted for the next scene,
+    /// e.g. the next frame of an animation, instead of building another one.
+    bvh: Arc<Mutex<Option<TopLevelBvh>>>,
+}
+impl Renderer {
+    pub fn new(settings: RenderSettings) -> Self {
+        Self {
+            settings,
+            cancellation_token: CancellationToken::new(),
+            bvh: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Returns the top-level BVH of `scene` refitted from that of the last scene if any,
+    /// which is typically the same scene with some objects moved.
+    fn take_bvh(&self, scene: &Scene) -> TopLevelBvh {
+        let last = self
+            .bvh
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        match last {
+            Some(mut bvh) => {
+                bvh.refit(&scene.world);
+                bvh
+            }
+            None => TopLevelBvh::new(&scene.world),
+        }
+    }
+
+    /// Keeps `bvh` for the next scene.
+    fn keep_bvh(&self, bvh: TopLevelBvh) {
+        *self.bvh.lock().unwrap_or_else(PoisonError::into_inner) = Some(bvh);
+    }
+
+    /// Makes the renderings stop when `token` is cancelled,
+    /// in which case the partially rendered films are returned,
+    /// where the unfinished scan lines are black.
+    pub fn with_cancellation(self, token: CancellationToken) -> Self {
+        Self {
+            cancellation_token: token,
+            ..self
+        }
+    }
+
+    /// Renders `scene` into a new film, which can be read by `Film::to_rgba_f32` for example.
+    pub fn render(&self, scene: &Scene) -> Film {
+        self.render_with_progress(scene, |_| {})
+    }
+
+    /// Renders `scene` while calling `on_tile` with each part of the image as soon as it is finished.
+    /// The parts are currently the scan lines, which are finished roughly from top to bottom.
+    pub fn render_with_progress(&self, scene: &Scene, mut on_tile: impl FnMut(&Tile)) -> Film {
+        let mut num_finished = 0;
+        let token = &self.cancellation_token;
+        let rows = 0..self.settings.image_height;
+        let bvh = self.take_bvh(scene);
+        let lit = &LitScene::new(scene, &bvh, None);
+        let result = render_scan_lines(lit, &self.settings, None, None, token, rows, |film, y| {
+            num_finished += 1;
+            on_tile(&Tile::from_scan_line(film, y, num_finished));
+            true
+        });
+        self.keep_bvh(bvh);
+        match result {
+            Ok(film) | Err(film) => film,
+        }
+    }
+
+    /// Renders only the scan lines in `rows`, counted from the top, e.g. to share an image among machines
+    /// or to render an image too large to be in memory at once part by part.
+    /// `num_finished` and `num_total` of the returned tile count the scan lines in it,
+    /// where unfinished ones are black if the rendering is cancelled.
+    /// With a seed, the pixels are the same as those of `render`.
+    pub fn render_tile(&self, scene: &Scene, rows: Range<usize>) -> Tile {
+        let width = self.settings.image_width;
+        let mut tile = Tile {
+            x: 0,
+            y: rows.start,
+            width,
+            height: rows.len(),
+            pixels: vec![
+                Color {
+                    r: 0.,
+                    g: 0.,
+                    b: 0.,
+                };
+                width * rows.len()
+            ],
+            num_finished: 0,
+            num_total: rows.len(),
+        };
+        let token = &self.cancellation_token;
+        // The scan lines around the tile are also rendered for the samples the filter splats into it.
+        let margin = self.settings.margin();
+        let image_height = self.settings.image_height;
+        let rendered_rows =
+            rows.start.saturating_sub(margin)..(rows.end + margin).min(image_height);
+        let film_top = rendered_rows.start;
+        let settings = &self.settings;
+        let bvh = self.take_bvh(scene);
+        let _ = render_scan_lines(
+            &LitScene::new(scene, &bvh, None),
+            settings,
+            None,
+            None,
+            token,
+            rendered_rows,
+            |film, y| {
+                if !rows.contains(&y) {
+                    return true;
+                }
+                let offset = (y - tile.y) * width;
+                for x in 0..width {
+                    tile.pixels[offset + x] = film.pixel(x, y - film_top);
+                }
+                tile.num_finished += 1;
+                true
+            },
+        );
+        self.keep_bvh(bvh);
+        tile
+    }
+
+    /// Traces the samples of the pixel at (`x`, `y`), counted from the top left, and returns
+    /// their paths for debugging. With a seed, the paths are the same as those behind the pixel
+    /// of `render`, since each sample takes the random numbers seeded for it.
+    /// Panics if the pixel is outside the image.
+    pub fn trace_pixel(&self, scene: &Scene, x: usize, y: usize) -> Vec<TracedPath> {
+        let settings = &self.settings;
+        assert!(
+            x < settings.image_width && y < settings.image_height,
+            "the pixel ({}, {}) is outside the image",
+            x,
+            y
+        );
+        let j = settings.image_height - 1 - y;
+        let depth = settings.max_diffusion_depth;
+        let bvh = TopLevelBvh::new(&scene.world);
+        let lit = LitScene::new(scene, &bvh, None);
+        let sampler = settings
+            .filter_sampling
+            .then(|| FilterSampler::new(settings.filter));
+        (0..settings.num_samples_per_pixel)
+            .map(|sample| {
+                seed_sample(settings, (x, j), sample);
+                let (ray, _, _) = sample_ray(scene, settings, sampler.as_ref(), x, j);
+                let mut bounces = vec![];
+                let roulette = &settings.roulette;
+                let bounces_mut = Some(&mut bounces);
+                let (color, _) = ray_color(&ray, &lit, depth, roulette, None, None, bounces_mut);
+                TracedPath { color, bounces }
+            })
+            .collect()
+    }
+
+    /// Bakes the light arriving at `points`, e.g. those of `bake::surface_points` for the size of
+    /// the image, into a film of that size instead of rendering the image seen from the camera.
+    /// Each texel is the average of `num_samples_per_pixel` rays drawn from the hemisphere on
+    /// the front side in proportion to the cosine, i.e., the irradiance divided by π, which
+    /// multiplied by the albedo gives the light a diffuse surface reflects, e.g. for lightmaps.
+    /// The texels without points get no samples, and the filter is not applied.
+    /// Panics if `points` does not have as many points as the image has pixels.
+    pub fn bake(&self, scene: &Scene, points: &[Option<SurfacePoint>]) -> Film {
+        let settings = &self.settings;
+        let (width, height) = (settings.image_width, settings.image_height);
+        assert_eq!(
+            width * height,
+            points.len(),
+            "a point is needed for each texel"
+        );
+        let bvh = self.take_bvh(scene);
+        let lit = &LitScene::new(scene, &bvh, None);
+        let token = &self.cancellation_token;
+        let mut film = Film::new(width, height);
+        let mut add_row = |y: usize, sums: Vec<Color>| {
+            for (x, sum) in sums.iter().enumerate() {
+                if points[y * width + x].is_some() {
+                    let num_samples = settings.num_samples_per_pixel as u32;
+                    film.add_samples(x, y, sum, num_samples);
+                }
+            }
+        };
+        let row_points = |y: usize| &points[y * width..(y + 1) * width];
+        for_each_row(
+            settings,
+            token,
+            height,
+            |y| bake_row(lit, settings, row_points(y), height - 1 - y),
+            &mut add_row,
+        );
+        self.keep_bvh(bvh);
+        film
+    }
+
+    /// Captures the light arriving at `positions` from all around into light probes instead of
+    /// rendering the image, tracing `num_samples_per_pixel` rays drawn uniformly from the sphere for
+    /// each probe. The probes are the same regardless of the number of threads if there is a seed.
+    pub fn capture_probes(&self, scene: &Scene, positions: &[Point3]) -> Vec<LightProbe> {
+        let settings = &self.settings;
+        let num_samples = settings.num_samples_per_pixel;
+        let bvh = self.take_bvh(scene);
+        let lit = &LitScene::new(scene, &bvh, None);
+        let token = &self.cancellation_token;
+        // The samples of each probe are traced in chunks, whose sums are added up in order afterwards.
+        let chunks: Vec<(usize, Range<usize>)> = (0..positions.len())
+            .flat_map(|index| {
+                (0..num_samples)
+                    .step_by(PROBE_CHUNK_SIZE)
+                    .map(move |start| (index, start..(start + PROBE_CHUNK_SIZE).min(num_samples)))
+            })
+            .collect();
+        let trace = |(index, samples): &(usize, Range<usize>)| {
+            probe_chunk(lit, settings, &positions[*index], *index, samples.clone())
+        };
+        let mut chunk_sums = vec![None; chunks.len()];
+        if settings.num_threads == 0 || cfg!(target_arch = "wasm32") {
+            for (sums, chunk) in chunk_sums.iter_mut().zip(&chunks) {
+                if token.is_cancelled() {
+                    break;
+                }
+                *sums = Some(trace(chunk));
+            }
+        } else {
+            let next_chunk = AtomicUsize::new(0);
+            let (sender, receiver) = mpsc::channel();
+            thread::scope(|scope| {
+                for _ in 0..settings.num_threads {
+                    let sender = sender.clone();
+                    let (next_chunk, chunks, trace) = (&next_chunk, &chunks, &trace);
+                    scope.spawn(move || {
+                        if settings.low_priority {
+                            lower_thread_priority();
+                        }
+                        while !token.is_cancelled() {
+                            let index = next_chunk.fetch_add(1, Ordering::Relaxed);
+                            let Some(chunk) = chunks.get(index) else {
+                                break;
+                            };
+                            if sender.send((index, trace(chunk))).is_err() {
+                                break;
+                            }
+                        }
+                    });
+                }
+                // Lets `receiver` end once all the workers have finished.
+                drop(sender);
+
+                for (index, sums) in receiver {
+                    chunk_sums[index] = Some(sums);
+                }
+            });
+        }
+        self.keep_bvh(bvh);
+        positions
+            .iter()
+            .enumerate()
+            .map(|(index, position)| {
+                let mut sums = std::array::from_fn(|_| Color {
+                    r: 0.,
+                    g: 0.,
+                    b: 0.,
+                });
+                let mut num_traced = 0;
+                // The chunks not traced by cancellation are left out.
+                for ((probe, samples), chunk) in chunks.iter().zip(&chunk_sums) {
+                    let Some(chunk) = chunk.as_ref().filter(|_| *probe == index) else {
+                        continue;
+                    };
+                    for (sum, value) in sums.iter_mut().zip(chunk) {
+                        *sum = sum.add(value);
+                    }
+                    num_traced += samples.len();
+                }
+                LightProbe::from_radiance_sums(position.clone(), &sums, num_traced)
+            })
+            .collect()
+    }
+
+    /// Traces the ray through the center of the pixel at (`x`, `y`), counted from the top left,
+    /// and returns where it hits first, or `None` if it hits nothing.
+    /// Panics if the pixel is outside the image.
+    pub fn pick_focus(&self, scene: &Scene, x: usize, y: usize) -> Option<FocusPick> {
+        let settings = &self.settings;
+        assert!(
+            x < settings.image_width && y < settings.image_height,
+            "the pixel ({}, {}) is outside the image",
+            x,
+            y
+        );
+        let ray = center_ray(scene, settings, x, y);
+        let hit = scene.raycast(&ray)?;
+        let pose = scene.camera.pose();
+        let view_direction = (&pose.look_at - &pose.look_from).unit_vector();
+        Some(FocusPick {
+            point: hit.point,
+            distance: hit.distance,
+            focus_distance: hit.distance * ray.direction.inner_product(&view_direction),
+        })
+    }
+
+    /// Renders the depth of the surface seen at the center of each pixel, which is the same as
+    /// `FocusPick::focus_distance` of `pick_focus`, with a single ray per pixel and without shading.
+    /// The scan lines are shared by the threads as in `render`, and the rays hit the scene through its BVH.
+    /// The pixels left by cancellation have no depth.
+    pub fn render_depth(&self, scene: &Scene) -> DepthMap {
+        let settings = &self.settings;
+        let (width, height) = (settings.image_width, settings.image_height);
+        let bvh = self.take_bvh(scene);
+        let pose = scene.camera.pose();
+        let view_direction = (&pose.look_at - &pose.look_from).unit_vector();
+        let depth_row = |y: usize| -> Vec<Option<Float>> {
+            (0..width)
+                .map(|x| {
+                    let ray = center_ray(scene, settings, x, y);
+                    let (hit, _) = bvh.hit(&scene.world, &ray, &RAY_T)?;
+                    Some(hit.t * ray.direction.inner_product(&view_direction))
+                })
+                .collect()
+        };
+        let mut depths = vec![None; width * height];
+        for_each_row(
+            settings,
+            &self.cancellation_token,
+            height,
+            depth_row,
+            |y, row| depths[y * width..(y + 1) * width].copy_from_slice(&row),
+        );
+        self.keep_bvh(bvh);
+        DepthMap {
+            width,
+            height,
+            depths,
+        }
+    }
+
+    /// Renders `scene` while sending each part of the image to `sender` as soon as it is finished,
+    /// e.g. to a UI thread. The rendering goes on even if the receiver is dropped.
+    pub fn render_to_channel(&self, scene: &Scene, sender: &mpsc::Sender<Tile>) -> Film {
+        self.render_with_progress(scene, |tile| {
+            let _ = sender.send(tile.clone());
+        })
+    }
+}
+
+/// Returns the ray through the center of the pixel at (`x`, `y`), counted from the top left.
+fn center_ray(scene: &Scene, settings: &RenderSettings, x: usize, y: usize) -> Ray {
+    let j = settings.image_height - 1 - y;
+    let u = (x as Float + 0.5) / ((settings.image_width - 1) as Float);
+    let v = (j as Float + 0.5) / ((settings.image_height - 1) as Float);
+    scene.camera.get_ray(u, v)
+}
+
+/// Computes `row(y)` for each scan line `y` in `0..height`, counted from the top, on the threads of `settings`,
+/// which take the scan lines one by one from the top, and passes the results to `add_row` on the calling thread
+/// in the order they are finished. The scan lines not started yet are skipped once `token` is cancelled.
+fn for_each_row<T: Send>(
+    settings: &RenderSettings,
+    token: &CancellationToken,
+    height: usize,
+    row: impl Fn(usize) -> T + Sync,
+    mut add_row: impl FnMut(usize, T),
+) {
+    if settings.num_threads == 0 || cfg!(target_arch = "wasm32") {
+        for y in 0..height {
+            if token.is_cancelled() {
+                break;
+            }
+            add_row(y, row(y));
+        }
+        return;
+    }
+    let next_row = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..settings.num_threads {
+            let sender = sender.clone();
+            let (next_row, row) = (&next_row, &row);
+            scope.spawn(move || {
+                if settings.low_priority {
+                    lower_thread_priority();
+                }
+                while !token.is_cancelled() {
+                    let y = next_row.fetch_add(1, Ordering::Relaxed);
+                    if y >= height {
+                        break;
+                    }
+                    if sender.send((y, row(y))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        // Lets `receiver` end once all the workers have finished.
+        drop(sender);
+
+        for (y, result) in receiver {
+            add_row(y, result);
+        }
+    });
+}
+
+/// Does the same as `render` without showing the progress but only for the scan lines in `rows`,
+/// where `on_scan_line` also receives the index of the finished scan line from the top,
+/// and the rendering is also aborted when `token` is cancelled. The film has only the scan lines
+/// in `rows`, from `rows.start` at its top, so that a part of a huge image fits in memory. The scan lines are finished
+/// from the top in order, each once the scan lines in `rows` whose samples the filter splats
+/// into it are all rendered.
+/// The scattered rays are guided by the guide of `lit` if it has one,
+/// and `trained` is trained further if given.
+/// If `edges` is given, only the pixels on the edges are sampled.
+fn render_scan_lines(
+    lit: &LitScene,
+    settings: &RenderSettings,
+    mut trained: Option<&mut PathGuide>,
+    edges: Option<&EdgeMask>,
+    token: &CancellationToken,
+    rows: Range<usize>,
+    mut on_scan_line: impl FnMut(&Film, usize) -> bool,
+) -> Result<Film, Film> {
+    let image_width = settings.image_width;
+    let image_height = settings.image_height;
+    let mut film = Film::new(image_width, rows.len());
+    let train_guide = trained.is_some();
+    // The scan lines are handed out from the top one by one to whichever worker is free.
+    let next_row = AtomicUsize::new(rows.start);
+    let is_aborted = AtomicBool::new(false);
+    let mut num_finished_rows = 0;
+    let margin = settings.margin();
+    // The rows are added to the film from the top in order, whichever arrives first, so that
+    // the splats of neighboring rows are summed in the same order regardless of the threads.
+    let mut pending_rows: Vec<Option<RenderedRow>> = rows.clone().map(|_| None).collect();
+    let mut next_added = rows.start;
+    let mut next_reported = rows.start;
+    let mut add_scan_line = |y: usize, row: RenderedRow| {
+        pending_rows[y - rows.start] = Some(row);
+        while let Some(row) = pending_rows
+            .get_mut(next_added - rows.start)
+            .and_then(Option::take)
+        {
+            let lines = row
+                .sums
+                .chunks(image_width)
+                .zip(row.weights.chunks(image_width));
+            for (line, (sums, weights)) in lines.enumerate() {
+                let y = (next_added + line).checked_sub(margin);
+                let Some(y) = y.filter(|y| rows.contains(y)) else {
+                    continue;
+                };
+                for (x, (sum, &weight)) in sums.iter().zip(weights).enumerate() {
+                    film.add_weighted(x, y - rows.start, sum, weight);
+                }
+            }
+            for invalid_sample in row.invalid_samples {
+                film.add_invalid_sample(invalid_sample);
+            }
+            if let Some(stats) = row.stats {
+                film.add_stats(stats);
+            }
+            for (x, lengths) in row.path_lengths.iter().enumerate() {
+                film.add_path_lengths(x, next_added - rows.start, lengths);
+            }
+            if let (Some(trained), Some(guide)) = (trained.as_deref_mut(), row.guide) {
+                trained.merge(guide);
+            }
+            num_finished_rows += 1;
+            next_added += 1;
+        }
+        // A scan line is finished once the rows within the margin below it are added too.
+        while next_reported < next_added
+            && (next_reported + margin < next_added || next_added == rows.end)
+        {
+            next_reported += 1;
+            if !on_scan_line(&film, next_reported - 1) {
+                return false;
+            }
+        }
+        true
+    };
+    if settings.num_threads == 0 || cfg!(target_arch = "wasm32") {
+        // Renders on the calling thread, which is the only option on WebAssembly.
+        for y in rows.clone() {
+            if token.is_cancelled() {
+                break;
+            }
+            let row = render_row(
+                lit,
+                settings,
+                image_height - 1 - y,
+                train_guide,
+                edges.map(|edges| edges.row(y)),
+            );
+            if !add_scan_line(y, row) {
+                is_aborted.store(true, Ordering::Relaxed);
+                break;
+            }
+        }
+    } else {
+        let (sender, receiver) = mpsc::channel();
+        thread::scope(|scope| {
+            for _ in 0..settings.num_threads {
+                let sender = sender.clone();
+                let (next_row, is_aborted) = (&next_row, &is_aborted);
+                scope.spawn(move || {
+                    if settings.low_priority {
+                        lower_thread_priority();
+                    }
+                    while !is_aborted.load(Ordering::Relaxed) && !token.is_cancelled() {
+                        let y = next_row.fetch_add(1, Ordering::Relaxed);
+                        if y >= rows.end {
+                            break;
+                        }
+                        let row = render_row(
+                            lit,
+                            settings,
+                            image_height - 1 - y,
+                            train_guide,
+                            edges.map(|edges| edges.row(y)),
+                        );
+                        if sender.send((y, row)).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            // Lets `receiver` end once all the workers have finished.
+            drop(sender);
+
+            for (y, row) in receiver {
+                if !add_scan_line(y, row) {
+                    is_aborted.store(true, Ordering::Relaxed);
+                    break;
+                }
+            }
+        });
+    }
+    if is_aborted.into_inner() || num_finished_rows < rows.len() {
+        Err(film)
+    } else {
+        Ok(film)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{Camera, Projection};
+    use crate::geometry::{Point3, Vec3};
+    use crate::hittable_object::{
+        DiffuseLight, Glass, Lambertian, Metal, Quad, ShadowCatcher, SharedMaterial, Sphere,
+        Transformed,
+    };
+    use crate::light::Sun;
+    use crate::scenes::{self, SceneParams, FURNACE_ALBEDO};
+    use crate::texture::ImageTexture;
+
+    #[test]
+    fn show_progress_tests() {
+        let settings = RenderSettings {
+            image_width: 8,
+            image_height: 6,
+            num_samples_per_pixel: 2,
+            max_diffusion_depth: 5,
+            roulette: vec![],
+            num_threads: 2,
+            low_priority: false,
+            check_samples: false,
+            collect_stats: false,
+            path_guiding: false,
+            adaptive_samples: 0,
+            seed: Some(1),
+            filter: Filter::Box,
+            filter_sampling: false,
+            show_progress: false,
+        };
+        let scene = scenes::by_name("three-spheres", 8. / 6., &SceneParams::default()).unwrap();
+        let num_draws = || crate::progress::NUM_DRAWS.with(|num_draws| num_draws.get());
+        // Neither the plain, the guided, nor the adaptive rendering draws any progress bar,
+        // and nor does the rendering against the clock.
+        let variants = [
+            settings.clone(),
+            RenderSettings {
+                path_guiding: true,
+                ..settings.clone()
+            },
+            RenderSettings {
+                adaptive_samples: 2,
+                ..settings.clone()
+            },
+        ];
+        for variant in &variants {
+            assert!(render(&scene, variant, |_| true).is_ok());
+        }
+        assert!(render_for(&scene, &settings, Duration::ZERO, |_| true).is_ok());
+        assert_eq!(0, num_draws());
+        // The bars are drawn on the thread calling the renderers, where they are counted.
+        let shown = RenderSettings {
+            show_progress: true,
+            ..settings
+        };
+        assert!(render(&scene, &shown, |_| true).is_ok());
+        assert!(num_draws() > 0);
+    }
+
+    #[test]
+    fn renderer_tests() {
+        let settings = RenderSettings {
+            image_width: 8,
+            image_height: 6,
+            num_samples_per_pixel: 4,
+            max_diffusion_depth: 5,
+            roulette: vec![],
+            num_threads: 1,
+            low_priority: false,
+            check_samples: false,
+            collect_stats: false,
+            path_guiding: false,
+            adaptive_samples: 0,
+            seed: Some(1),
+            filter: Filter::Box,
+            filter_sampling: false,
+            show_progress: true,
+        };
+        let scene = scenes::by_name("three-spheres", 8. / 6., &SceneParams::default()).unwrap();
+        let film = Renderer::new(settings.clone()).render(&scene);
+        let pixels = film.to_rgba_f32();
+        assert_eq!(4 * 8 * 6, pixels.len());
+        assert!(pixels.chunks(4).all(|pixel| pixel[3] == 1.));
+        // With a seed, the image does not depend on the number of threads.
+        let renderer = Renderer::new(RenderSettings {
+            num_threads: 3,
+            ..settings.clone()
+        });
+        assert_eq!(pixels, renderer.render(&scene).to_rgba_f32());
+        let renderer = Renderer::new(RenderSettings {
+            num_threads: 0,
+            ..settings
+        });
+        assert_eq!(pixels, renderer.render(&scene).to_rgba_f32());
+
+        // Tiles rendered separately make up the same image.
+        let mut tiled_film = Film::new(8, 6);
+        for rows in [0..4, 4..6] {
+            let tile = renderer.render_tile(&scene, rows.clone());
+            assert_eq!(
+                (rows.len(), rows.len()),
+                (tile.num_finished, tile.num_total)
+            );
+            tiled_film.add_tile(&tile);
+        }
+        assert_eq!(pixels, tiled_film.to_rgba_f32());
+
+        // The BVH kept from the last rendering is refitted to the objects moved since then.
+        let mut moved = scenes::by_name("three-spheres", 8. / 6., &SceneParams::default()).unwrap();
+        let member = moved.world.members.remove(1);
+        let zero = Vec3 {
+            x: 0.,
+            y: 0.,
+            z: 0.,
+        };
+        let translation = Vec3 {
+            x: 0.3,
+            y: 0.2,
+            z: 0.,
+        };
+        let origin = Point3 {
+            x: 0.,
+            y: 0.,
+            z: 0.,
+        };
+        let member = Transformed::new(member, origin, translation, zero, 1.);
+        moved.world.members.insert(1, Box::new(member));
+        let moved_pixels = renderer.render(&moved).to_rgba_f32();
+        assert_ne!(pixels, moved_pixels);
+        let fresh_renderer = Renderer::new(renderer.settings.clone());
+        assert_eq!(fresh_renderer.render(&moved).to_rgba_f32(), moved_pixels);
+    }
+
+    #[test]
+    fn sample_seed_tests() {
+        let seeds: Vec<_> = [(0, 0, 0), (1, 0, 0), (0, 1, 0), (0, 0, 1), (1, 1, 1)]
+            .into_iter()
+            .map(|(i, j, sample)| sample_seed(7, (i, j), sample))
+            .collect();
+        for (n, seed) in seeds.iter().enumerate() {
+            assert!(!seeds[n + 1..].contains(seed), "{:?}", seeds);
+        }
+        assert_eq!(seeds[0], sample_seed(7, (0, 0), 0));
+        assert_ne!(seeds[0], sample_seed(8, (0, 0), 0));
+    }
+
+    #[test]
+    fn bake_tests() {
+        use crate::approx::assert_approx_eq;
+        use crate::bake;
+        use crate::hittable_object::Mesh;
+
+        let settings = RenderSettings {
+            image_width: 8,
+            image_height: 8,
+            num_samples_per_pixel: 64,
+            max_diffusion_depth: 5,
+            roulette: vec![],
+            num_threads: 0,
+            low_priority: false,
+            check_samples: false,
+            collect_stats: false,
+            path_guiding: false,
+            adaptive_samples: 0,
+            seed: Some(0),
+            filter: Filter::Box,
+            filter_sampling: false,
+            show_progress: true,
+        };
+        // The lower left half of the square of side 2 on the xy-plane facing +z is laid out
+        // over the lower left half of the texture.
+        let point = |x, y| Point3 { x, y, z: 0. };
+        let black = Arc::new(Lambertian {
+            albedo: Attenuation {
+                r: 0.,
+                g: 0.,
+                b: 0.,
+            },
+        });
+        let triangle = Mesh::new(
+            vec![point(0., 0.), point(2., 0.), point(0., 2.)],
+            vec![[0, 1, 2]],
+            black.clone(),
+        )
+        .with_texture_coordinates(vec![[(0., 0.), (1., 0.), (0., 1.)]]);
+        let points = bake::surface_points(&triangle, 8, 8).unwrap();
+        let mut scene = scenes::furnace(1.);
+        scene.world.members = vec![Box::new(triangle)];
+        let renderer = Renderer::new(settings.clone());
+
+        // Nothing blocks the white background above the triangle.
+        let film = renderer.bake(&scene, &points);
+        for (index, point) in points.iter().enumerate() {
+            let expected = if point.is_some() { 1. } else { 0. };
+            assert_approx_eq!(expected, film.pixel(index % 8, index / 8).g);
+        }
+
+        // A black sphere above the corner at the origin shades the texels near it the most.
+        scene.world.members.push(Box::new(Sphere {
+            center: Point3 {
+                x: 0.,
+                y: 0.,
+                z: 1.5,
+            },
+            radius: 1.,
+            material: black,
+        }));
+        let film = renderer.bake(&scene, &points);
+        let (near, far) = (film.pixel(0, 7).g, film.pixel(0, 0).g);
+        assert!(near < far && far < 1., "{} {}", near, far);
+        // The texels are the same whichever thread bakes them.
+        let threaded = Renderer::new(RenderSettings {
+            num_threads: 2,
+            ..settings
+        });
+        assert_eq!(
+            film.to_rgba_f32(),
+            threaded.bake(&scene, &points).to_rgba_f32()
+        );
+    }
+
+    #[test]
+    fn capture_probes_tests() {
+        let settings = RenderSettings {
+            image_width: 1,
+            image_height: 1,
+            num_samples_per_pixel: 1000,
+            max_diffusion_depth: 5,
+            roulette: vec![],
+            num_threads: 0,
+            low_priority: false,
+            check_samples: false,
+            collect_stats: false,
+            path_guiding: false,
+            adaptive_samples: 0,
+            seed: Some(0),
+            filter: Filter::Box,
+            filter_sampling: false,
+            show_progress: true,
+        };
+        // The probe above the sphere of the furnace sees the white background above it
+        // and the sphere, which is darker than the background, below it.
+        let scene = scenes::furnace(1.);
+        let positions = [
+            Point3 {
+                x: 0.,
+                y: 1.5,
+                z: 0.,
+            },
+            Point3 {
+                x: 0.,
+                y: 100.,
+                z: 0.,
+            },
+        ];
+        let probes = Renderer::new(settings.clone()).capture_probes(&scene, &positions);
+        assert_eq!(2, probes.len());
+        assert_eq!(positions[0], probes[0].position);
+        let up = Vec3 {
+            x: 0.,
+            y: 1.,
+            z: 0.,
+        }
+        .unit_vector();
+        let (above, below) = (probes[0].irradiance(&up), probes[0].irradiance(&-&up));
+        assert!(below.g < above.g - 0.1, "{:?} {:?}", below, above);
+        assert!((above.g - consts::PI).abs() < 0.3, "{:?}", above);
+        // The sphere is too far from the other probe to matter.
+        let far = probes[1].irradiance(&-&up);
+        assert!((far.g - consts::PI).abs() < 0.3, "{:?}", far);
+
+        // The probes are the same whichever thread traces them.
+        let threaded = Renderer::new(RenderSettings {
+            num_threads: 3,
+            ..settings
+        });
+        assert_eq!(probes, threaded.capture_probes(&scene, &positions));
+    }
+
+    #[test]
+    fn trace_pixel_tests() {
+        let settings = RenderSettings {
+            image_width: 8,
+            image_height: 6,
+            num_samples_per_pixel: 4,
+            max_diffusion_depth: 3,
+            roulette: vec![],
+            num_threads: 0,
+            low_priority: false,
+            check_samples: false,
+            collect_stats: false,
+            path_guiding: false,
+            adaptive_samples: 0,
+            seed: Some(2),
+            filter: Filter::Box,
+            filter_sampling: false,
+            show_progress: true,
+        };
+        let scene = scenes::by_name("three-spheres", 8. / 6., &SceneParams::default()).unwrap();
+        let renderer = Renderer::new(settings);
+        let film = renderer.render(&scene);
+        for (x, y) in [(0, 0), (3, 2), (7, 5)] {
+            let paths = renderer.trace_pixel(&scene, x, y);
+            assert_eq!(4, paths.len());
+            let mut sum = Color {
+                r: 0.,
+                g: 0.,
+                b: 0.,
+            };
+            for path in paths.iter() {
+                sum = sum.add(&path.color);
+                assert!(!path.bounces.is_empty() && path.bounces.len() <= 4);
+                // Only the last ray of a path leaves the scene or is cut off.
+                let (last, rest) = path.bounces.split_last().unwrap();
+                assert!(rest.iter().all(|bounce| matches!(
+                    bounce,
+                    Bounce::Hit {
+                        scattered: Some(_),
+                        ..
+                    }
+                )));
+                if path.bounces.len() == 4 {
+                    assert!(matches!(last, Bounce::DepthExceeded { .. }));
+                }
+                // The polyline starts at the camera and has a point for each traced ray.
+                let points = path.points(100.);
+                assert_eq!(scene.camera.get_ray(0.5, 0.5).origin, points[0]);
+                let num_traced = match last {
+                    Bounce::DepthExceeded { .. } => path.bounces.len() - 1,
+                    _ => path.bounces.len(),
+                };
+                assert_eq!(num_traced + 1, points.len());
+            }
+            // The paths are those behind the rendered pixel.
+            let mut traced_film = Film::new(8, 6);
+            traced_film.add_samples(x, y, &sum, 4);
+            assert_eq!(film.pixel(x, y), traced_film.pixel(x, y));
+        }
+    }
+
+    #[test]
+    fn pick_focus_tests() {
+        let settings = RenderSettings {
+            image_width: 17,
+            image_height: 17,
+            num_samples_per_pixel: 1,
+            max_diffusion_depth: 1,
+            roulette: vec![],
+            num_threads: 0,
+            low_priority: false,
+            check_samples: false,
+            collect_stats: false,
+            path_guiding: false,
+            adaptive_samples: 0,
+            seed: None,
+            filter: Filter::Box,
+            filter_sampling: false,
+            show_progress: true,
+        };
+        let renderer = Renderer::new(settings);
+        let mut scene = scenes::furnace(1.);
+        let look_from = scene.camera.pose().look_from.clone();
+        let distance_to_center = (&look_from
+            - &Point3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            })
+            .length();
+
+        // The central pixel sees the point of the unit sphere nearest to the camera.
+        let pick = renderer.pick_focus(&scene, 8, 8).unwrap();
+        assert!((pick.distance - (distance_to_center - 1.)).abs() < 0.05);
+        assert!((pick.focus_distance - pick.distance).abs() < 0.05);
+        // Off-center pixels see farther points, which are closer along the view direction.
+        let pick = renderer.pick_focus(&scene, 6, 8).unwrap();
+        assert!(pick.distance > pick.focus_distance);
+        assert!(((&pick.point - &look_from).length() - pick.distance).abs() < 1e-6);
+        // The corners see only the environment.
+        assert_eq!(None, renderer.pick_focus(&scene, 0, 0));
+
+        scene.world.members.clear();
+        assert_eq!(None, renderer.pick_focus(&scene, 8, 8));
+    }
+
+    #[test]
+    fn render_depth_tests() {
+        let settings = RenderSettings {
+            image_width: 17,
+            image_height: 17,
+            num_samples_per_pixel: 1,
+            max_diffusion_depth: 1,
+            roulette: vec![],
+            num_threads: 0,
+            low_priority: false,
+            check_samples: false,
+            collect_stats: false,
+            path_guiding: false,
+            adaptive_samples: 0,
+            seed: None,
+            filter: Filter::Box,
+            filter_sampling: false,
+            show_progress: true,
+        };
+        let renderer = Renderer::new(settings.clone());
+        let mut scene = scenes::furnace(1.);
+        let depth_map = renderer.render_depth(&scene);
+        assert_eq!((17, 17), (depth_map.width, depth_map.height));
+        for (x, y) in [(8, 8), (6, 8), (0, 0)] {
+            let expected = renderer
+                .pick_focus(&scene, x, y)
+                .map(|pick| pick.focus_distance);
+            assert_eq!(expected, depth_map.depths[y * 17 + x]);
+        }
+        // The threads give the same depths through the BVH as `pick_focus` does by testing every object.
+        let threaded = Renderer::new(RenderSettings {
+            num_threads: 4,
+            ..settings
+        });
+        let three_spheres = scenes::three_spheres(1.);
+        let depth_map = threaded.render_depth(&three_spheres);
+        for y in 0..17 {
+            for x in 0..17 {
+                let expected = renderer
+                    .pick_focus(&three_spheres, x, y)
+                    .map(|pick| pick.focus_distance);
+                assert_eq!(expected, depth_map.depths[y * 17 + x], "({}, {})", x, y);
+            }
+        }
+
+        // Orthographic cameras see the unit sphere as a disk of the same size at any distance.
+        let pose = scene.camera.pose().clone();
+        let distance_to_center = (&pose.look_from - &pose.look_at).length();
+        let projection = Projection::Orthographic {
+            viewport_height: 4.,
+        };
+        scene.camera = Camera::with_projection(pose, projection, 1.);
+        let depth_map = renderer.render_depth(&scene);
+        // The center of the central pixel is off the center of the image by `offset` in both directions.
+        let offset: Float = 0.125;
+        let expected = distance_to_center - (1. - 2. * offset * offset).sqrt();
+        assert!((depth_map.depths[8 * 17 + 8].unwrap() - expected).abs() < 1e-6);
+        let num_hits = depth_map.depths.iter().flatten().count();
+        // The disk covers about pi / 16 of the viewport.
+        assert!((num_hits as Float / (17. * 17.) - consts::PI / 16.).abs() < 0.03);
+        assert_eq!(None, depth_map.depths[8 * 17 + 15]);
+    }
+
+    #[test]
+    fn furnace_tests() {
+        let settings = RenderSettings {
+            image_width: 16,
+            image_height: 16,
+            num_samples_per_pixel: 16,
+            max_diffusion_depth: 10,
+            roulette: vec![],
+            num_threads: 1,
+            low_priority: false,
+            check_samples: false,
+            collect_stats: false,
+            path_guiding: false,
+            adaptive_samples: 0,
+            seed: Some(0),
+            filter: Filter::Box,
+            filter_sampling: false,
+            show_progress: true,
+        };
+        let gray = |c: Float| Attenuation { r: c, g: c, b: c };
+        // The materials and the brightness the sphere must have in the white environment.
+        let cases: Vec<(&str, SharedMaterial, Float)> = vec![
+            (
+                "lambertian",
+                Arc::new(Lambertian {
+                    albedo: gray(FURNACE_ALBEDO),
+                }),
+                FURNACE_ALBEDO,
+            ),
+            (
+                "metal",
+                Arc::new(Metal {
+                    albedo: gray(0.7),
+                    fuzz: 0.,
+                }),
+                0.7,
+            ),
+            (
+                "fuzzy metal",
+                Arc::new(Metal {
+                    albedo: gray(0.7),
+                    fuzz: 1.,
+                }),
+                0.7,
+            ),
+            (
+                "glass",
+                Arc::new(Glass {
+                    eta: 1.5,
+                    albedo: gray(1.),
+                    priority: 0,
+                }),
+                1.,
+            ),
+        ];
+        for (name, material, expected) in cases {
+            let mut scene = scenes::furnace(1.);
+            scene.world.members = vec![Box::new(Sphere {
+                center: Point3 {
+                    x: 0.,
+                    y: 0.,
+                    z: 0.,
+                },
+                radius: 1.,
+                material,
+            })];
+            let film = Renderer::new(settings.clone()).render(&scene);
+            // The central pixels see nothing but the sphere.
+            for y in 5..11 {
+                for x in 5..11 {
+                    let color = film.pixel(x, y);
+                    for c in [color.r, color.g, color.b] {
+                        assert!(
+                            (c - expected).abs() < 0.01,
+                            "{} at ({}, {}) is {:?} instead of {}",
+                            name,
+                            x,
+                            y,
+                            color,
+                            expected
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn roulette_tests() {
+        let settings = RenderSettings {
+            image_width: 16,
+            image_height: 16,
+            num_samples_per_pixel: 64,
+            max_diffusion_depth: 10,
+            roulette: vec![],
+            num_threads: 1,
+            low_priority: false,
+            check_samples: false,
+            collect_stats: true,
+            path_guiding: false,
+            adaptive_samples: 0,
+            seed: Some(0),
+            filter: Filter::Box,
+            filter_sampling: false,
+            show_progress: true,
+        };
+        let mut scene = scenes::furnace(1.);
+        scene.world.members = vec![Box::new(Sphere {
+            center: Point3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            radius: 1.,
+            material: Arc::new(Lambertian {
+                albedo: Attenuation {
+                    r: FURNACE_ALBEDO,
+                    g: FURNACE_ALBEDO,
+                    b: FURNACE_ALBEDO,
+                },
+            }),
+        })];
+        let render = |roulette: Vec<Float>| {
+            Renderer::new(RenderSettings {
+                roulette,
+                ..settings.clone()
+            })
+            .render(&scene)
+        };
+        let film = render(vec![]);
+        let roulette_film = render(vec![0.5]);
+
+        // The roulette traces fewer rays without changing the brightness on average.
+        let num_tests = |film: &Film| film.stats().objects[0].num_tests;
+        assert!(num_tests(&roulette_film) < num_tests(&film) * 9 / 10);
+        let central_average = |film: &Film| {
+            let pixels: Vec<(usize, usize)> =
+                (5..11).flat_map(|y| (5..11).map(move |x| (x, y))).collect();
+            pixels
+                .iter()
+                .map(|&(x, y)| film.pixel(x, y).r)
+                .sum::<Float>()
+                / pixels.len() as Float
+        };
+        assert!((central_average(&film) - FURNACE_ALBEDO).abs() < 0.01);
+        assert!((central_average(&roulette_film) - FURNACE_ALBEDO).abs() < 0.05);
+        assert_ne!(film.to_rgba_f32(), roulette_film.to_rgba_f32());
+
+        // The paths hitting the convex sphere bounce once unless the roulette terminates them first.
+        let num_paths = 16 * 16 * 64;
+        for film in [&film, &roulette_film] {
+            assert_eq!(num_paths, film.stats().path_lengths.iter().sum::<u64>());
+            let lengths = film.path_lengths();
+            assert_eq!(16 * 16, lengths.len());
+            let num_terminated: u32 = lengths.iter().map(|l| l.num_terminated).sum();
+            let terminated = &film.stats().terminated_path_lengths;
+            assert_eq!(num_terminated as u64, terminated.iter().sum::<u64>());
+            assert!(lengths.iter().all(|l| l.num_paths == 64 && l.max <= 1));
+        }
+        assert!(film.stats().terminated_path_lengths.is_empty());
+        assert_eq!(1., film.path_lengths()[8 * 16 + 8].mean());
+        let terminated = &roulette_film.stats().terminated_path_lengths;
+        assert!(terminated.len() == 1 && terminated[0] > 0);
+        assert!(roulette_film.path_lengths()[8 * 16 + 8].mean() < 0.75);
+    }
+
+    #[test]
+    fn light_sampling_tests() {
+        // A string of small lights over a gray floor, seen only by their light on the floor.
+        let mut scene = scenes::furnace(1.);
+        scene.background = Background::Solid(Color {
+            r: 0.,
+            g: 0.,
+            b: 0.,
+        });
+        scene.world.members = vec![Box::new(Sphere {
+            center: Point3 {
+                x: 0.,
+                y: -1000.,
+                z: 0.,
+            },
+            radius: 1000.,
+            material: Arc::new(Lambertian {
+                albedo: Attenuation {
+                    r: 0.5,
+                    g: 0.5,
+                    b: 0.5,
+                },
+            }),
+        })];
+        for index in 0..50 {
+            scene.world.members.push(Box::new(Sphere {
+                center: Point3 {
+                    x: index as Float * 0.2 - 5.,
+                    y: 1.,
+                    z: 0.,
+                },
+                radius: 0.05,
+                material: Arc::new(DiffuseLight {
+                    emit: Color {
+                        r: 10.,
+                        g: 10.,
+                        b: 10.,
+                    },
+                }),
+            }));
+        }
+        let ray = Ray {
+            origin: Point3 {
+                x: 0.,
+                y: 1.,
+                z: 2.,
+            },
+            direction: Vec3 {
+                x: 0.,
+                y: -1.,
+                z: -2.,
+            }
+            .unit_vector(),
+        };
+        // Returns the mean of the colors and the variance of the mean.
+        let estimate = |lit: &LitScene| {
+            seed_random(0);
+            let num_samples = 4000;
+            let colors: Vec<Float> = (0..num_samples)
+                .map(|_| ray_color(&ray, lit, 5, &[], None, None, None).0.r)
+                .collect();
+            let mean = colors.iter().sum::<Float>() / num_samples as Float;
+            let variance = colors.iter().map(|c| (c - mean).powi(2)).sum::<Float>()
+                / (num_samples * (num_samples - 1)) as Float;
+            (mean, variance)
+        };
+        let bvh = TopLevelBvh::new(&scene.world);
+        let (mean, variance) = estimate(&LitScene::new(&scene, &bvh, None));
+        let unlit = LitScene {
+            scene: &scene,
+            bvh: &bvh,
+            lights: LightTree::default(),
+            environment: None,
+            guide: None,
+        };
+        let (unlit_mean, unlit_variance) = estimate(&unlit);
+
+        // Sampling the lights makes the light on the floor much less noisy without changing it.
+        assert!(
+            variance < unlit_variance / 10.,
+            "{} {}",
+            variance,
+            unlit_variance
+        );
+        let error = (mean - unlit_mean).abs();
+        assert!(
+            error < 4. * (variance + unlit_variance).sqrt(),
+            "{} and {}",
+            mean,
+            unlit_mean
+        );
+    }
+
+    #[test]
+    fn background_sampling_tests() {
+        // A gray floor under a dim sky with a small bright sun and a small light,
+        // seen only by the light on the floor.
+        let (width, height) = (64, 32);
+        let mut texels = vec![
+            Color {
+                r: 0.2,
+                g: 0.2,
+                b: 0.2,
+            };
+            width * height
+        ];
+        texels[6 * width + 40] = Color {
+            r: 5000.,
+            g: 5000.,
+            b: 5000.,
+        };
+        let sun = Sun::from_irradiance(
+            Vec3 {
+                x: 1.,
+                y: 2.,
+                z: -1.,
+            }
+            .unit_vector(),
+            0.1,
+            &Color {
+                r: 20.,
+                g: 20.,
+                b: 20.,
+            },
+        );
+        let backgrounds = [
+            Background::Environment(Arc::new(ImageTexture::new(width, height, texels))),
+            Background::SunnySky(sun),
+        ];
+        for background in backgrounds {
+            let mut scene = scenes::furnace(1.);
+            scene.background = background;
+            scene.world.members = vec![
+                Box::new(Sphere {
+                    center: Point3 {
+                        x: 0.,
+                        y: -1000.,
+                        z: 0.,
+                    },
+                    radius: 1000.,
+                    material: Arc::new(Lambertian {
+                        albedo: Attenuation {
+                            r: 0.5,
+                            g: 0.5,
+                            b: 0.5,
+                        },
+                    }),
+                }),
+                Box::new(Sphere {
+                    center: Point3 {
+                        x: 1.,
+                        y: 1.,
+                        z: 0.,
+                    },
+                    radius: 0.05,
+                    material: Arc::new(DiffuseLight {
+                        emit: Color {
+                            r: 100.,
+                            g: 100.,
+                            b: 100.,
+                        },
+                    }),
+                }),
+            ];
+            let ray = Ray {
+                origin: Point3 {
+                    x: 0.,
+                    y: 1.,
+                    z: 2.,
+                },
+                direction: Vec3 {
+                    x: 0.,
+                    y: -1.,
+                    z: -2.,
+                }
+                .unit_vector(),
+            };
+            // Returns the mean of the colors and the variance of the mean.
+            let estimate = |lit: &LitScene| {
+                seed_random(0);
+                let num_samples = 4000;
+                let colors: Vec<Float> = (0..num_samples)
+                    .map(|_| ray_color(&ray, lit, 5, &[], None, None, None).0.r)
+                    .collect();
+                let mean = colors.iter().sum::<Float>() / num_samples as Float;
+                let variance = colors.iter().map(|c| (c - mean).powi(2)).sum::<Float>()
+                    / (num_samples * (num_samples - 1)) as Float;
+                (mean, variance)
+            };
+            let bvh = TopLevelBvh::new(&scene.world);
+            let lit = LitScene::new(&scene, &bvh, None);
+            assert!(lit.environment.is_some());
+            let (mean, variance) = estimate(&lit);
+            let unsampled = LitScene {
+                environment: None,
+                ..LitScene::new(&scene, &bvh, None)
+            };
+            let (unsampled_mean, unsampled_variance) = estimate(&unsampled);
+
+            // Sampling the background finds the sun far more often without changing the light.
+            assert!(
+                variance < unsampled_variance / 10.,
+                "{} {}",
+                variance,
+                unsampled_variance
+            );
+            let error = (mean - unsampled_mean).abs();
+            assert!(
+                error < 4. * (variance + unsampled_variance).sqrt(),
+                "{} and {}",
+                mean,
+                unsampled_mean
+            );
+        }
+    }
+
+    #[test]
+    fn soft_shadow_tests() {
+        let up = Vec3 {
+            x: 0.,
+            y: 1.,
+            z: 0.,
+        }
+        .unit_vector();
+        // Returns the fractions of the light reaching the floor along the x-axis through the shadow,
+        // estimated by the shadow rays with and without the sphere.
+        let visibilities = |light_size: Float, xs: &[Float]| {
+            let scene = scenes::soft_shadow(light_size, 1.);
+            let mut unoccluded = scenes::soft_shadow(light_size, 1.);
+            unoccluded.world.members.remove(1);
+            let irradiances = |scene: &Scene| {
+                let bvh = TopLevelBvh::new(&scene.world);
+                let lit = LitScene::new(scene, &bvh, None);
+                seed_random(0);
+                xs.iter()
+                    .map(|&x| {
+                        let vertex = DiffuseVertex {
+                            point: Point3 { x, y: 0., z: 0. },
+                            normal: up.clone(),
+                            albedo: Attenuation {
+                                r: 1.,
+                                g: 1.,
+                                b: 1.,
+                            },
+                        };
+                        (0..1000)
+                            .map(|_| sample_lights(&lit, &vertex, None).r)
+                            .sum::<Float>()
+                    })
+                    .collect::<Vec<_>>()
+            };
+            irradiances(&scene)
+                .into_iter()
+                .zip(irradiances(&unoccluded))
+                .map(|(occluded, unoccluded)| occluded / unoccluded)
+                .collect::<Vec<_>>()
+        };
+        let xs: Vec<Float> = (0..=80).map(|i| i as Float * 0.05).collect();
+        // Returns the width of the part of the floor that sees some but not all of the light.
+        let penumbra_width = |light_size: Float| {
+            let visibilities = visibilities(light_size, &xs);
+            // The point right under the sphere sees none of the light but the farthest one sees all of it.
+            assert!(visibilities[0] < 0.01, "{:?}", visibilities);
+            assert!(visibilities[80] > 0.99, "{:?}", visibilities);
+            visibilities
+                .iter()
+                .filter(|&&visibility| 0.05 < visibility && visibility < 0.95)
+                .count() as Float
+                * 0.05
+        };
+
+        // Larger lights cast softer shadows.
+        let widths: Vec<Float> = [0.5, 1., 2.].into_iter().map(penumbra_width).collect();
+        assert!(
+            widths[0] < widths[1] && widths[1] < widths[2],
+            "{:?}",
+            widths
+        );
+        // The silhouette of the sphere seen from the light is halfway down to the floor,
+        // so the penumbra is as wide as the light, less the faint ends cut off by the thresholds.
+        for (light_size, width) in [0.5, 1., 2.].into_iter().zip(widths) {
+            assert!(
+                0.6 * light_size < width && width <= light_size + 0.05,
+                "{} for {}",
+                width,
+                light_size
+            );
+        }
+    }
+
+    #[test]
+    fn shadow_catcher_tests() {
+        // The soft-shadow scene whose floor is a shadow catcher in front of a gray background.
+        let gray = Color {
+            r: 0.5,
+            g: 0.5,
+            b: 0.5,
+        };
+        let catcher_scene = |reflectance: Float| {
+            let mut scene = scenes::soft_shadow(1., 1.);
+            scene.background = Background::Solid(gray.clone());
+            scene.world.members[0] = Box::new(Quad {
+                q: Point3 {
+                    x: -10.,
+                    y: 0.,
+                    z: -10.,
+                },
+                u: Vec3 {
+                    x: 20.,
+                    y: 0.,
+                    z: 0.,
+                },
+                v: Vec3 {
+                    x: 0.,
+                    y: 0.,
+                    z: 20.,
+                },
+                material: Arc::new(ShadowCatcher { reflectance }),
+            });
+            scene
+        };
+        // Returns the average color of the rays coming down to the floor at `(x, 0, z)` from the front.
+        let color_at = |scene: &Scene, x: Float, z: Float| {
+            let bvh = TopLevelBvh::new(&scene.world);
+            let lit = LitScene::new(scene, &bvh, None);
+            let ray = Ray {
+                origin: Point3 {
+                    x,
+                    y: 0.5,
+                    z: z + 5.,
+                },
+                direction: Vec3 {
+                    x: 0.,
+                    y: -0.5,
+                    z: -5.,
+                }
+                .unit_vector(),
+            };
+            seed_random(0);
+            let samples = 100;
+            let sum = (0..samples).fold(
+                Color {
+                    r: 0.,
+                    g: 0.,
+                    b: 0.,
+                },
+                |sum, _| sum.add(&ray_color(&ray, &lit, 10, &[], None, None, None).0),
+            );
+            sum.scale(1. / samples as Float)
+        };
+
+        // The catcher shows the background behind it except in the shadow of the sphere.
+        let scene = catcher_scene(0.);
+        assert_eq!(gray, color_at(&scene, 4., 0.));
+        assert_eq!(gray, color_at(&scene, 0., 3.));
+        assert_eq!(0., color_at(&scene, 0.5, 0.).r);
+        let penumbra = color_at(&scene, 1.7, 0.).g;
+        assert!(0. < penumbra && penumbra < 0.5, "{}", penumbra);
+
+        // The reflecting catcher also shows the sphere in front of it, but only the background elsewhere.
+        let scene = catcher_scene(1.);
+        assert_eq!(gray, color_at(&scene, 4., 0.));
+        assert_ne!(gray, color_at(&scene, 0., 3.));
+
+        let mut bounces = vec![];
+        let bvh = TopLevelBvh::new(&scene.world);
+        let lit = LitScene::new(&scene, &bvh, None);
+        let ray = Ray {
+            origin: Point3 {
+                x: 4.,
+                y: 1.,
+                z: 0.,
+            },
+            direction: Vec3 {
+                x: 0.,
+                y: -1.,
+                z: 0.,
+            }
+            .unit_vector(),
+        };
+        ray_color(&ray, &lit, 10, &[], None, None, Some(&mut bounces));
+        // Going through the catcher is recorded but is not a bounce.
+        assert_eq!(2, bounces.len());
+        assert!(matches!(bounces[1], Bounce::Background { .. }));
+    }
+
+    #[test]
+    fn path_guiding_tests() {
+        assert_eq!(vec![1, 2, 4, 8, 16, 69], guided_passes(100));
+        assert_eq!(vec![1, 1], guided_passes(2));
+        assert_eq!(vec![1], guided_passes(1));
+
+        // A gray floor lit only by a small light, which the lights are not sampled directly for.
+        let mut scene = scenes::furnace(1.);
+        scene.background = Background::Solid(Color {
+            r: 0.,
+            g: 0.,
+            b: 0.,
+        });
+        scene.world.members = vec![
+            Box::new(Sphere {
+                center: Point3 {
+                    x: 0.,
+                    y: -1000.,
+                    z: 0.,
+                },
+                radius: 1000.,
+                material: Arc::new(Lambertian {
+                    albedo: Attenuation {
+                        r: 0.5,
+                        g: 0.5,
+                        b: 0.5,
+                    },
+                }),
+            }),
+            Box::new(Sphere {
+                center: Point3 {
+                    x: 0.3,
+                    y: 1.,
+                    z: 0.,
+                },
+                radius: 0.05,
+                material: Arc::new(DiffuseLight {
+                    emit: Color {
+                        r: 100.,
+                        g: 100.,
+                        b: 100.,
+                    },
+                }),
+            }),
+        ];
+        let ray = Ray {
+            origin: Point3 {
+                x: 0.,
+                y: 1.,
+                z: 2.,
+            },
+            direction: Vec3 {
+                x: 0.,
+                y: -1.,
+                z: -2.,
+            }
+            .unit_vector(),
+        };
+        let num_samples = 4000;
+        // Returns the mean of the colors and the variance of the mean, training `trained` if given.
+        let estimate = |lit: &LitScene, mut trained: Option<&mut PathGuide>| {
+            let colors: Vec<Float> = (0..num_samples)
+                .map(|_| {
+                    let trained = trained.as_deref_mut();
+                    ray_color(&ray, lit, 5, &[], None, trained, None).0.r
+                })
+                .collect();
+            let mean = colors.iter().sum::<Float>() / num_samples as Float;
+            let variance = colors.iter().map(|c| (c - mean).powi(2)).sum::<Float>()
+                / (num_samples * (num_samples - 1)) as Float;
+            (mean, variance)
+        };
+        seed_random(0);
+        let mut guide = PathGuide::new(0.25);
+        let untrained = guide.distribution();
+        let bvh = TopLevelBvh::new(&scene.world);
+        let lit = |guide| LitScene {
+            scene: &scene,
+            bvh: &bvh,
+            lights: LightTree::default(),
+            environment: None,
+            guide,
+        };
+        // The guide that has learned nothing leaves the rays as they are while it learns.
+        let (unguided_mean, unguided_variance) = estimate(&lit(Some(&untrained)), Some(&mut guide));
+        let distribution = guide.distribution();
+        let (mean, variance) = estimate(&lit(Some(&distribution)), None);
+
+        // The rays guided to the light make the light on the floor much less noisy without changing it.
+        assert!(
+            variance < unguided_variance / 5.,
+            "{} {}",
+            variance,
+            unguided_variance
+        );
+        let error = (mean - unguided_mean).abs();
+        assert!(
+            error < 4. * (variance + unguided_variance).sqrt(),
+            "{} and {}",
+            mean,
+            unguided_mean
+        );
+    }
+
+    #[test]
+    fn check_samples_tests() {
+        /// The material whose attenuation is NaN on the upper half of the surface.
+        #[derive(Debug)]
+        struct BrokenMaterial;
+        impl crate::hittable_object::Material for BrokenMaterial {
+            fn scatter(&self, ray_in: &Ray, hit: &HitRecord) -> Option<Scattered> {
+                let scattered = Lambertian {
+                    albedo: Attenuation {
+                        r: 0.5,
+                        g: 0.5,
+                        b: 0.5,
+                    },
+                }
+                .scatter(ray_in, hit)?;
+                let c = if hit.surface_normal.inject().y > 0. {
+                    Float::NAN
+                } else {
+                    0.5
+                };
+                Some(Scattered {
+                    attenuation: Attenuation { r: c, g: c, b: c },
+                    ..scattered
+                })
+            }
+
+            fn validate(&self) -> Vec<String> {
+                vec![]
+            }
+        }
+
+        let settings = RenderSettings {
+            image_width: 16,
+            image_height: 16,
+            num_samples_per_pixel: 4,
+            max_diffusion_depth: 5,
+            roulette: vec![],
+            num_threads: 1,
+            low_priority: false,
+            check_samples: true,
+            collect_stats: false,
+            path_guiding: false,
+            adaptive_samples: 0,
+            seed: Some(0),
+            filter: Filter::Box,
+            filter_sampling: false,
+            show_progress: true,
+        };
+        let mut scene = scenes::furnace(1.);
+        scene.world.members = vec![Box::new(Sphere {
+            center: Point3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            radius: 1.,
+            material: Arc::new(BrokenMaterial),
+        })];
+        let film = Renderer::new(settings.clone()).render(&scene);
+        let magenta = Color {
+            r: 1.,
+            g: 0.,
+            b: 1.,
+        };
+        // The upper half of the sphere is magenta, and the lower half is not.
+        assert_eq!(magenta, film.pixel(8, 4));
+        assert_ne!(magenta, film.pixel(8, 12));
+        assert!(film.num_invalid_samples() > 0);
+        assert_eq!(film.num_invalid_samples(), film.invalid_samples().len());
+        for sample in film.invalid_samples() {
+            assert!(sample.y < 8);
+            assert!(sample.color.r.is_nan());
+            // The first bounce is the culprit.
+            assert_eq!(0, sample.depth);
+            assert!(matches!(sample.bounce, Bounce::Hit { .. }));
+        }
+
+        // Without the check, the samples are not recorded and the image is otherwise the same.
+        let unchecked_film = Renderer::new(RenderSettings {
+            check_samples: false,
+            ..settings
+        })
+        .render(&scene);
+        assert_eq!(0, unchecked_film.num_invalid_samples());
+        assert!(unchecked_film.pixel(8, 4).r.is_nan());
+        assert_eq!(film.pixel(8, 12), unchecked_film.pixel(8, 12));
+    }
+
+    #[test]
+    fn collect_stats_tests() {
+        let settings = RenderSettings {
+            image_width: 16,
+            image_height: 16,
+            num_samples_per_pixel: 4,
+            max_diffusion_depth: 5,
+            roulette: vec![],
+            num_threads: 2,
+            low_priority: false,
+            check_samples: false,
+            collect_stats: true,
+            path_guiding: false,
+            adaptive_samples: 0,
+            seed: Some(0),
+            filter: Filter::Box,
+            filter_sampling: false,
+            show_progress: true,
+        };
+        let material: SharedMaterial = Arc::new(Lambertian {
+            albedo: Attenuation {
+                r: 0.5,
+                g: 0.5,
+                b: 0.5,
+            },
+        });
+        let sphere = |y: Float| Sphere {
+            center: Point3 { x: 0., y, z: 0. },
+            radius: 0.5,
+            material: material.clone(),
+        };
+        let mut scene = scenes::furnace(1.);
+        scene.world.members = vec![Box::new(sphere(0.5)), Box::new(sphere(-0.5))];
+        let film = Renderer::new(settings.clone()).render(&scene);
+        let stats = film.stats();
+        assert_eq!(2, stats.objects.len());
+        // Every ray is tested with every object.
+        assert_eq!(stats.objects[0].num_tests, stats.objects[1].num_tests);
+        for object in stats.objects.iter() {
+            assert!(0 < object.num_hits && object.num_hits < object.num_tests);
+        }
+        let materials = stats.materials();
+        assert_eq!(1, materials.len());
+        assert!(Arc::ptr_eq(&material, &materials[0].material));
+        let num_hits = stats.objects[0].num_hits + stats.objects[1].num_hits;
+        assert!(0 < materials[0].num_hits && materials[0].num_hits <= num_hits);
+
+        // Without collecting them, the statistics are empty and the image is the same.
+        let plain_film = Renderer::new(RenderSettings {
+            collect_stats: false,
+            path_guiding: false,
+            adaptive_samples: 0,
+            ..settings
+        })
+        .render(&scene);
+        assert!(plain_film.stats().objects.is_empty());
+        assert!(plain_film.stats().materials().is_empty());
+        assert!(plain_film.path_lengths().is_empty());
+        assert_eq!(film.to_rgba_f32(), plain_film.to_rgba_f32());
+    }
+
+    #[test]
+    fn render_for_tests() {
+        let settings = RenderSettings {
+            image_width: 16,
+            image_height: 9,
+            num_samples_per_pixel: 2,
+            max_diffusion_depth: 5,
+            roulette: vec![],
+            num_threads: 2,
+            low_priority: false,
+            check_samples: false,
+            collect_stats: false,
+            path_guiding: false,
+            adaptive_samples: 0,
+            seed: Some(0),
+            filter: Filter::Box,
+            filter_sampling: false,
+            show_progress: true,
+        };
+        let scene = scenes::furnace(16. / 9.);
+
+        // Only the first pass is rendered if the time is already up, which is the same as `render`.
+        let expected = Renderer::new(settings.clone()).render(&scene);
+        let Ok(film) = render_for(&scene, &settings, Duration::ZERO, |_| true) else {
+            panic!("the rendering is aborted");
+        };
+        assert_eq!(expected.to_rgba_f32(), film.to_rgba_f32());
+
+        let Ok(film) = render_for(&scene, &settings, Duration::from_millis(100), |_| true) else {
+            panic!("the rendering is aborted");
+        };
+        assert_ne!(expected.to_rgba_f32(), film.to_rgba_f32());
+
+        // The passes so far are returned if aborted.
+        let mut num_calls = 0;
+        let result = render_for(&scene, &settings, Duration::from_secs(60), |_| {
+            num_calls += 1;
+            num_calls < 20
+        });
+        let Err(film) = result else {
+            panic!("the rendering is not aborted");
+        };
+        assert_eq!(20, num_calls);
+        assert_ne!(expected.to_rgba_f32(), film.to_rgba_f32());
+    }
+
+    #[test]
+    fn filter_tests() {
+        let settings = RenderSettings {
+            image_width: 8,
+            image_height: 6,
+            num_samples_per_pixel: 4,
+            max_diffusion_depth: 5,
+            roulette: vec![],
+            num_threads: 1,
+            low_priority: false,
+            check_samples: false,
+            collect_stats: false,
+            path_guiding: false,
+            adaptive_samples: 0,
+            seed: Some(3),
+            filter: Filter::Box,
+            filter_sampling: false,
+            show_progress: true,
+        };
+        let scene = scenes::by_name("three-spheres", 8. / 6., &SceneParams::default()).unwrap();
+        let box_pixels = Renderer::new(settings.clone()).render(&scene).to_rgba_f32();
+        for filter in [Filter::Tent, Filter::Gaussian, Filter::Mitchell] {
+            let settings = RenderSettings {
+                filter,
+                ..settings.clone()
+            };
+            // Every scan line is reported once, after the samples splatted into it are all added.
+            let threaded_settings = RenderSettings {
+                num_threads: 3,
+                ..settings.clone()
+            };
+            let mut num_calls = 0;
+            let Ok(film) = render(&scene, &threaded_settings, |_| {
+                num_calls += 1;
+                true
+            }) else {
+                panic!("the rendering is aborted");
+            };
+            assert_eq!(settings.image_height, num_calls);
+            let pixels = film.to_rgba_f32();
+            assert_ne!(box_pixels, pixels, "{:?}", filter);
+            assert!(pixels.iter().all(|c| c.is_finite() && *c >= 0.));
+            let renderer = Renderer::new(settings.clone());
+            assert_eq!(pixels, renderer.render(&scene).to_rgba_f32());
+
+            // Tiles are rendered with the scan lines around them, so they make up the same image.
+            let mut tiled_film = Film::new(8, 6);
+            for rows in [0..1, 1..4, 4..6] {
+                let tile = renderer.render_tile(&scene, rows.clone());
+                assert_eq!(
+                    (rows.len(), rows.len()),
+                    (tile.num_finished, tile.num_total)
+                );
+                tiled_film.add_tile(&tile);
+            }
+            assert_eq!(pixels, tiled_film.to_rgba_f32(), "{:?}", filter);
+
+            // The samples drawn from the filter count only for their own pixels,
+            // so the tiles need no scan lines around them.
+            let sampled_settings = RenderSettings {
+                filter_sampling: true,
+                ..settings.clone()
+            };
+            let renderer = Renderer::new(sampled_settings);
+            let sampled_pixels = renderer.render(&scene).to_rgba_f32();
+            assert_ne!(pixels, sampled_pixels, "{:?}", filter);
+            assert!(sampled_pixels.iter().all(|c| c.is_finite() && *c >= 0.));
+            let mut tiled_film = Film::new(8, 6);
+            tiled_film.add_tile(&renderer.render_tile(&scene, 2..3));
+            let row = 4 * 8 * 2..4 * 8 * 3;
+            assert_eq!(sampled_pixels[row.clone()], tiled_film.to_rgba_f32()[row]);
+        }
+        // The box filter drawn from is the same as the uniform offsets within the pixels.
+        let renderer = Renderer::new(RenderSettings {
+            filter_sampling: true,
+            ..settings
+        });
+        let sampled_pixels = renderer.render(&scene).to_rgba_f32();
+        for (expected, actual) in box_pixels.iter().zip(&sampled_pixels) {
+            assert!((expected - actual).abs() < 1e-4, "{} {}", expected, actual);
+        }
+    }
+
+    #[test]
+    fn adaptive_samples_tests() {
+        let settings = RenderSettings {
+            image_width: 32,
+            image_height: 18,
+            num_samples_per_pixel: 2,
+            max_diffusion_depth: 5,
+            roulette: vec![],
+            num_threads: 2,
+            low_priority: false,
+            check_samples: false,
+            collect_stats: false,
+            path_guiding: false,
+            adaptive_samples: 0,
+            seed: Some(0),
+            filter: Filter::Box,
+            filter_sampling: false,
+            show_progress: true,
+        };
+        let scene = scenes::furnace(16. / 9.);
+        let Ok(expected) = render(&scene, &settings, |_| true) else {
+            panic!("the rendering is aborted");
+        };
+        let adaptive_settings = RenderSettings {
+            adaptive_samples: 16,
+            ..settings
+        };
+        let Ok(film) = render(&scene, &adaptive_settings, |_| true) else {
+            panic!("the rendering is aborted");
+        };
+        let edges = EdgeMask::find(&scene, &expected);
+        let mut num_changed = 0;
+        for y in 0..film.height {
+            for x in 0..film.width {
+                if edges.row(y)[x] {
+                    num_changed += usize::from(film.pixel(x, y) != expected.pixel(x, y));
+                } else {
+                    // The pixels off the edges keep the samples of the first pass.
+                    assert_eq!(expected.pixel(x, y), film.pixel(x, y), "({}, {})", x, y);
+                }
+            }
+        }
+        // The silhouette of the sphere gets more samples, which smooth its antialiasing.
+        assert!(num_changed > 0);
+    }
+
+    #[test]
+    fn renderer_progress_tests() {
+        let settings = RenderSettings {
+            image_width: 8,
+            image_height: 6,
+            num_samples_per_pixel: 2,
+            max_diffusion_depth: 5,
+            roulette: vec![],
+            num_threads: 2,
+            low_priority: false,
+            check_samples: false,
+            collect_stats: false,
+            path_guiding: false,
+            adaptive_samples: 0,
+            seed: None,
+            filter: Filter::Box,
+            filter_sampling: false,
+            show_progress: true,
+        };
+        let scene = scenes::by_name("three-spheres", 8. / 6., &SceneParams::default()).unwrap();
+        let renderer = Renderer::new(settings);
+
+        let mut tiles = vec![];
+        let film = renderer.render_with_progress(&scene, |tile| tiles.push(tile.clone()));
+        assert_eq!(
+            (1..=6).collect::<Vec<_>>(),
+            tiles
+                .iter()
+                .map(|tile| tile.num_finished)
+                .collect::<Vec<_>>()
+        );
+        let mut ys: Vec<usize> = tiles.iter().map(|tile| tile.y).collect();
+        ys.sort();
+        assert_eq!((0..6).collect::<Vec<_>>(), ys);
+        for tile in tiles {
+            assert_eq!(
+                (0, 8, 1, 6),
+                (tile.x, tile.width, tile.height, tile.num_total)
+            );
+            for (x, pixel) in tile.pixels.iter().enumerate() {
+                assert_eq!(film.pixel(x, tile.y), *pixel);
+            }
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        renderer.render_to_channel(&scene, &sender);
+        drop(sender);
+        assert_eq!(6, receiver.iter().count());
+    }
+
+    #[test]
+    fn renderer_cancellation_tests() {
+        let settings = RenderSettings {
+            image_width: 4,
+            image_height: 50,
+            num_samples_per_pixel: 2,
+            max_diffusion_depth: 5,
+            roulette: vec![],
+            // Worker threads may run ahead of the cancellation by any number of scan lines.
+            num_threads: 0,
+            low_priority: false,
+            check_samples: false,
+            collect_stats: false,
+            path_guiding: false,
+            adaptive_samples: 0,
+            seed: None,
+            filter: Filter::Box,
+            filter_sampling: false,
+            show_progress: true,
+        };
+        let scene = scenes::by_name("three-spheres", 4. / 50., &SceneParams::default()).unwrap();
+        let token = CancellationToken::new();
+        let renderer = Renderer::new(settings).with_cancellation(token.clone());
+
+        let mut num_tiles = 0;
+        let film = renderer.render_with_progress(&scene, |_| {
+            num_tiles += 1;
+            token.cancel();
+        });
+        // The rendering stops as soon as the first scan line is reported.
+        assert_eq!(1, num_tiles);
+        let black = Color {
+            r: 0.,
+            g: 0.,
+            b: 0.,
+        };
+        assert_eq!(black, film.pixel(0, 49));
+
+        // Cancelled tokens stay cancelled.
+        let mut num_tiles = 0;
+        renderer.render_with_progress(&scene, |_| num_tiles += 1);
+        assert_eq!(0, num_tiles);
+    }
+
+    // The reference images are rendered with `f64`, and random scenes differ with `f32`.
+    #[cfg(not(feature = "f32"))]
+    mod golden_tests {
+        use super::super::*;
+        use crate::scenes::{self, SceneParams, SCENE_NAMES};
+        use crate::tonemap::TransferFunction;
+        use std::fs::File;
+        use std::io::BufWriter;
+        use std::path::PathBuf;
+
+        /// The directory of the reference images of `golden_image_tests`,
+        /// which are written instead of compared if `UPDATE_GOLDEN_IMAGES` is set.
+        const GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden");
+
+        /// The side length in pixels of the blocks averaged before comparison,
+        /// which keeps the sampling noise from failing the tests
+        /// when changes alter how random numbers are consumed.
+        const GOLDEN_BLOCK_SIZE: usize = 8;
+
+        /// Returns the maximum difference of the block averages allowed for the scene, in [0, 1].
+        fn golden_tolerance(name: &str) -> f64 {
+            match name {
+                // Lit only by a small light, and thus much noisier than the others.
+                "cornell-box" => 0.12,
+                _ => 0.025,
+            }
+        }
+
+        fn golden_settings() -> RenderSettings {
+            RenderSettings {
+                image_width: 64,
+                image_height: 36,
+                num_samples_per_pixel: 32,
+                max_diffusion_depth: 10,
+                roulette: vec![],
+                num_threads: 2,
+                low_priority: false,
+                check_samples: false,
+                collect_stats: false,
+                path_guiding: false,
+                adaptive_samples: 0,
+                seed: Some(0),
+                filter: Filter::Box,
+                filter_sampling: false,
+                show_progress: true,
+            }
+        }
+
+        fn read_png(path: &PathBuf) -> (usize, usize, Vec<u8>) {
+            let file = File::open(path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+            let mut reader = png::Decoder::new(file).read_info().unwrap();
+            let mut data = vec![0; reader.output_buffer_size()];
+            let info = reader.next_frame(&mut data).unwrap();
+            assert_eq!(png::ColorType::Rgb, info.color_type);
+            data.truncate(info.buffer_size());
+            (info.width as usize, info.height as usize, data)
+        }
+
+        /// Returns the largest difference between the averages of the corresponding blocks
+        /// of two RGB images of `width` x `height` pixels.
+        fn block_difference(width: usize, height: usize, expected: &[u8], actual: &[u8]) -> f64 {
+            let mut max_difference: f64 = 0.;
+            for block_y in (0..height).step_by(GOLDEN_BLOCK_SIZE) {
+                for block_x in (0..width).step_by(GOLDEN_BLOCK_SIZE) {
+                    for channel in 0..3 {
+                        let mut sum = 0.;
+                        let mut count = 0;
+                        for y in block_y..(block_y + GOLDEN_BLOCK_SIZE).min(height) {
+                            for x in block_x..(block_x + GOLDEN_BLOCK_SIZE).min(width) {
+                                let index = 3 * (y * width + x) + channel;
+                                sum += actual[index] as f64 - expected[index] as f64;
+                                count += 1;
+                            }
+                        }
+                        max_difference = max_difference.max((sum / count as f64).abs() / 255.);
+                    }
+                }
+            }
+            max_difference
+        }
+
+        #[test]
+        fn golden_image_tests() {
+            let settings = golden_settings();
+            let aspect_ratio = settings.image_width as Float / settings.image_height as Float;
+            let update = std::env::var_os("UPDATE_GOLDEN_IMAGES").is_some();
+            for name in SCENE_NAMES {
+                let scene = scenes::by_name(name, aspect_ratio, &SceneParams::default()).unwrap();
+                let Ok(film) = render(&scene, &settings, |_| true) else {
+                    panic!("{} was aborted", name);
+                };
+                let path = PathBuf::from(GOLDEN_DIR).join(format!("{}.png", name));
+                if update {
+                    let mut out = BufWriter::new(File::create(&path).unwrap());
+                    film.write_png(&mut out, TransferFunction::Gamma2).unwrap();
+                    continue;
+                }
+                let (width, height, expected) = read_png(&path);
+                assert_eq!(
+                    (settings.image_width, settings.image_height),
+                    (width, height)
+                );
+                let difference = block_difference(
+                    width,
+                    height,
+                    &expected,
+                    &film.to_rgb8(TransferFunction::Gamma2),
+                );
+                assert!(
+                    difference <= golden_tolerance(name),
+                    "{} differs from {} by {} (rerun with UPDATE_GOLDEN_IMAGES=1 if intended)",
+                    name,
+                    path.display(),
+                    difference
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,952 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::camera::{Camera, CameraPose, Projection};
+use crate::color::{Color, Spectrum};
+use crate::error::RenderError;
+use crate::geometry::{Float, Point3, Vec3};
+use crate::hittable_object::{
+    Cylinder, DiffuseLight, Glass, Hittable, HittableList, Isotropic, Lambertian, Mesh, Metal,
+    Quad, ShadowCatcher, SharedMaterial, Sphere, Toon, Transform, Transformed,
+};
+use crate::json::Json;
+use crate::light::Sun;
+use crate::scene_graph::Node;
+use crate::scenes::{Background, Scene};
+
+// Scene files are JSON documents of the following form, where each object refers to its material
+// by the index in `materials` so that shared materials are written once:
+//
+//     {
+//       "camera": {"look_from": [0, 1, 3], "look_at": [0, 0, 0], "vertical_fov": 40},
+//       "background": "sky",
+//       "materials": [{"type": "lambertian", "albedo": [0.5, 0.5, 0.5]}],
+//       "objects": [{"type": "sphere", "center": [0, 0, 0], "radius": 1, "material": 0}]
+//     }
+//
+// The camera may have `"orthographic": <viewport height>`, and the background may be a color `[r, g, b]`
+// or the sky with the sun `{"sun": [x, y, z], "sun_radius": <degrees>, "sun_radiance": [r, g, b]}`.
+// The types of the materials are `lambertian`, `metal`, `glass`, `light`, `isotropic`, `toon`, and `shadow_catcher`,
+// and those of the objects are `sphere`, `cylinder`, `quad`, `mesh`, `transformed`, and `list`,
+// whose other members are the fields of the types of the same names, and `node`:
+//
+//     {"type": "node", "name": "molecule", "visible": true, "translation": [1, 0, 0], "children": [...]}
+//
+// where `visible`, `layer`, `holdout`, `pivot`, `translation`, `rotation`, and `scale` are optional,
+// and a node may have `"instance_of": <name>` instead of `children` to duplicate the earlier node of the name.
+// A material may also have `"name": <name>`, by which objects may refer to it instead of the index,
+// e.g. `"material": "ground"` for hand-written files; the names are not kept when scenes are written again.
+
+/// The type for the materials of the objects written to a scene file.
+#[derive(Default)]
+pub struct MaterialTable {
+    materials: Vec<(SharedMaterial, Json)>,
+}
+impl MaterialTable {
+    /// Returns the index of `material`, adding it to the table unless it is already there,
+    /// or returns `None` if scene files cannot describe it, e.g. image textures.
+    pub fn index(&mut self, material: &SharedMaterial) -> Option<Json> {
+        let index = match self
+            .materials
+            .iter()
+            .position(|(other, _)| Arc::ptr_eq(other, material))
+        {
+            Some(index) => index,
+            None => {
+                self.materials.push((material.clone(), material.to_json()?));
+                self.materials.len() - 1
+            }
+        };
+        Some(Json::Number(index as f64))
+    }
+}
+
+// The casts are no-ops unless the `f32` feature is enabled.
+#[allow(clippy::unnecessary_cast)]
+pub fn number(x: Float) -> Json {
+    Json::Number(x as f64)
+}
+
+/// Returns the array of the three numbers, e.g. of points, vectors, and colors.
+pub fn triple(x: Float, y: Float, z: Float) -> Json {
+    Json::Array(vec![number(x), number(y), number(z)])
+}
+
+/// Returns the object whose member `type` is `kind` followed by `members`.
+pub fn tagged(kind: &str, members: Vec<(&str, Json)>) -> Json {
+    let mut object = vec![("type".to_string(), Json::String(kind.to_string()))];
+    object.extend(
+        members
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value)),
+    );
+    Json::Object(object)
+}
+
+/// Converts `scene` into the scene file, which fails if it has objects or materials
+/// scene files cannot describe, e.g. volumes, image textures, or environment maps.
+pub fn to_json(scene: &Scene) -> Result<Json, String> {
+    let pose = scene.camera.pose();
+    let mut camera = vec![
+        (
+            "look_from".to_string(),
+            triple(pose.look_from.x, pose.look_from.y, pose.look_from.z),
+        ),
+        (
+            "look_at".to_string(),
+            triple(pose.look_at.x, pose.look_at.y, pose.look_at.z),
+        ),
+        ("vertical_fov".to_string(), number(pose.vertical_fov_degree)),
+    ];
+    match scene.camera.projection() {
+        Projection::Perspective => {}
+        Projection::Orthographic { viewport_height } => {
+            camera.push(("orthographic".to_string(), number(*viewport_height)));
+        }
+        // Scene files have no view-up vector, which the cameras looking straight down need.
+        Projection::Stereographic => {
+            return Err("scene files cannot describe stereographic cameras".to_string());
+        }
+    }
+    let background = match &scene.background {
+        Background::Sky => Json::String("sky".to_string()),
+        Background::SunnySky(sun) => {
+            let direction = sun.direction.inject();
+            let radiance = &sun.radiance;
+            Json::Object(vec![
+                (
+                    "sun".to_string(),
+                    triple(direction.x, direction.y, direction.z),
+                ),
+                (
+                    "sun_radius".to_string(),
+                    number(sun.angular_radius.to_degrees()),
+                ),
+                (
+                    "sun_radiance".to_string(),
+                    triple(radiance.r, radiance.g, radiance.b),
+                ),
+            ])
+        }
+        Background::Solid(color) => triple(color.r, color.g, color.b),
+        Background::Environment(_) => {
+            return Err(
+                "the environment map cannot be written to scene files, which support no images"
+                    .to_string(),
+            )
+        }
+    };
+    let mut materials = MaterialTable::default();
+    let objects = scene
+        .world
+        .members
+        .iter()
+        .enumerate()
+        .map(|(index, object)| {
+            object.to_json(&mut materials).ok_or_else(|| {
+                format!(
+                    "object #{} cannot be written to scene files, which support neither volumes nor image textures",
+                    index
+                )
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Json::Object(vec![
+        ("camera".to_string(), Json::Object(camera)),
+        ("background".to_string(), background),
+        (
+            "materials".to_string(),
+            Json::Array(
+                materials
+                    .materials
+                    .into_iter()
+                    .map(|(_, json)| json)
+                    .collect(),
+            ),
+        ),
+        ("objects".to_string(), Json::Array(objects)),
+    ]))
+}
+
+/// Loads the scene file at `path` for images of `aspect_ratio`.
+pub fn load(path: &Path, aspect_ratio: Float) -> Result<Scene, RenderError> {
+    let text = std::fs::read_to_string(path).map_err(|e| RenderError::io(path, e))?;
+    let parse_error = |message| RenderError::SceneParse {
+        path: path.to_path_buf(),
+        message,
+    };
+    let json = Json::parse(&text).map_err(parse_error)?;
+    from_json(&json, aspect_ratio).map_err(parse_error)
+}
+
+/// Builds the scene of the scene file `json` for images of `aspect_ratio`.
+pub fn from_json(json: &Json, aspect_ratio: Float) -> Result<Scene, String> {
+    let scene = Members::new(json, "the scene")?;
+    let camera = Members::new(scene.get("camera")?, "camera")?;
+    let pose = CameraPose {
+        look_from: camera.point("look_from")?,
+        look_at: camera.point("look_at")?,
+        vertical_fov_degree: camera.number("vertical_fov")?,
+    };
+    let projection = match camera.optional("orthographic") {
+        Some(_) => Projection::Orthographic {
+            viewport_height: camera.number("orthographic")?,
+        },
+        None => Projection::Perspective,
+    };
+    camera.check_keys(&["look_from", "look_at", "vertical_fov", "orthographic"])?;
+    let background = match scene.get("background")? {
+        Json::String(s) if s == "sky" => Background::Sky,
+        Json::String(s) => return Err(format!("unknown background: {}", s)),
+        json @ Json::Object(_) => {
+            let sky = Members::new(json, "background")?;
+            let [x, y, z] = sky.triple("sun")?;
+            let direction = Vec3 { x, y, z };
+            if direction.length_squared() == 0. {
+                return Err("background: sun must not be zero".to_string());
+            }
+            let [r, g, b] = sky.triple("sun_radiance")?;
+            sky.check_keys(&["sun", "sun_radius", "sun_radiance"])?;
+            Background::SunnySky(Sun {
+                direction: direction.unit_vector(),
+                angular_radius: sky.number("sun_radius")?.to_radians(),
+                radiance: Color { r, g, b },
+            })
+        }
+        _ => {
+            let [r, g, b] = scene.triple("background")?;
+            Background::Solid(Color { r, g, b })
+        }
+    };
+    let mut materials = Materials::default();
+    for (index, material) in scene.array("materials")?.iter().enumerate() {
+        materials
+            .add(material)
+            .map_err(|e| format!("material #{}: {}", index, e))?;
+    }
+    let mut nodes = vec![];
+    let members = scene
+        .array("objects")?
+        .iter()
+        .enumerate()
+        .map(|(index, object)| {
+            parse_object(object, &materials, &mut nodes)
+                .map_err(|e| format!("object #{}: {}", index, e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    scene.check_keys(&["camera", "background", "materials", "objects"])?;
+    Ok(Scene {
+        camera: Camera::with_projection(pose, projection, aspect_ratio),
+        world: HittableList { members },
+        background,
+    })
+}
+
+fn parse_material(json: &Json) -> Result<SharedMaterial, String> {
+    let material = Members::new(json, "a material")?;
+    let spectrum = |key: &str| {
+        let [r, g, b] = material.triple(key)?;
+        Ok::<_, String>(Spectrum { r, g, b })
+    };
+    let (parsed, keys): (SharedMaterial, &[&str]) = match material.string("type")? {
+        "lambertian" => (
+            Arc::new(Lambertian {
+                albedo: spectrum("albedo")?,
+            }),
+            &["albedo"],
+        ),
+        "metal" => (
+            Arc::new(Metal {
+                albedo: spectrum("albedo")?,
+                fuzz: material.number("fuzz")?,
+            }),
+            &["albedo", "fuzz"],
+        ),
+        "glass" => (
+            Arc::new(Glass {
+                eta: material.number("eta")?,
+                albedo: spectrum("albedo")?,
+                priority: material.integer("priority")?,
+            }),
+            &["eta", "albedo", "priority"],
+        ),
+        "light" => (
+            Arc::new(DiffuseLight {
+                emit: spectrum("emit")?,
+            }),
+            &["emit"],
+        ),
+        "isotropic" => (
+            Arc::new(Isotropic {
+                albedo: spectrum("albedo")?,
+            }),
+            &["albedo"],
+        ),
+        "toon" => {
+            let [x, y, z] = material.triple("light_direction")?;
+            (
+                Arc::new(Toon {
+                    albedo: spectrum("albedo")?,
+                    bands: material.integer("bands")?,
+                    light_direction: Vec3 { x, y, z },
+                }),
+                &["albedo", "bands", "light_direction"],
+            )
+        }
+        "shadow_catcher" => (
+            Arc::new(ShadowCatcher {
+                reflectance: material.number("reflectance")?,
+            }),
+            &["reflectance"],
+        ),
+        kind => return Err(format!("unknown type of materials: {}", kind)),
+    };
+    material.check_keys(&[&["type", "name"], keys].concat())?;
+    Ok(parsed)
+}
+
+/// The type for the materials of scene files, which objects refer to by their indices or names.
+#[derive(Default)]
+struct Materials {
+    materials: Vec<SharedMaterial>,
+    indices_by_name: HashMap<String, usize>,
+}
+impl Materials {
+    /// Parses the material `json` and adds it, which fails if an earlier material has its name.
+    fn add(&mut self, json: &Json) -> Result<(), String> {
+        let material = Members::new(json, "a material")?;
+        if material.optional("name").is_some() {
+            let name = material.string("name")?;
+            if self.indices_by_name.contains_key(name) {
+                return Err(format!("another material is named {}", name));
+            }
+            self.indices_by_name
+                .insert(name.to_string(), self.materials.len());
+        }
+        self.materials.push(parse_material(json)?);
+        Ok(())
+    }
+
+    /// Returns the material referred to by `reference`, which is its index or its name.
+    fn get(&self, reference: &Json) -> Result<SharedMaterial, String> {
+        let index = match reference {
+            Json::String(name) => *self
+                .indices_by_name
+                .get(name)
+                .ok_or_else(|| format!("no material is named {}", name))?,
+            _ => reference
+                .as_u64()
+                .and_then(|index| index.try_into().ok())
+                .ok_or("material must be a non-negative integer or a name")?,
+        };
+        self.materials.get(index).cloned().ok_or_else(|| {
+            format!(
+                "material {} is out of range (there are {} materials)",
+                index,
+                self.materials.len()
+            )
+        })
+    }
+}
+
+/// Parses the object `json`, where `nodes` are the nodes parsed so far, which `instance_of` refers to.
+fn parse_object(
+    json: &Json,
+    materials: &Materials,
+    nodes: &mut Vec<Node>,
+) -> Result<Box<dyn Hittable>, String> {
+    let object = Members::new(json, "an object")?;
+    let material = || materials.get(object.get("material")?);
+    let vector = |key: &str| {
+        let [x, y, z] = object.triple(key)?;
+        Ok::<_, String>(Vec3 { x, y, z })
+    };
+    let (parsed, keys): (Box<dyn Hittable>, &[&str]) = match object.string("type")? {
+        "sphere" => (
+            Box::new(Sphere {
+                center: object.point("center")?,
+                radius: object.number("radius")?,
+                material: material()?,
+            }),
+            &["center", "radius", "material"],
+        ),
+        "cylinder" => (
+            Box::new(Cylinder {
+                base: object.point("base")?,
+                axis: vector("axis")?,
+                radius: object.number("radius")?,
+                material: material()?,
+            }),
+            &["base", "axis", "radius", "material"],
+        ),
+        "quad" => (
+            Box::new(Quad {
+                q: object.point("q")?,
+                u: vector("u")?,
+                v: vector("v")?,
+                material: material()?,
+            }),
+            &["q", "u", "v", "material"],
+        ),
+        "mesh" => {
+            let vertices = object
+                .array("vertices")?
+                .iter()
+                .map(|vertex| {
+                    let [x, y, z] = parse_numbers(vertex, "a vertex")?;
+                    Ok(Point3 { x, y, z })
+                })
+                .collect::<Result<_, String>>()?;
+            let triangles = object
+                .array("triangles")?
+                .iter()
+                .map(|triangle| {
+                    parse_array(triangle, "a triangle", "vertex indices", |index| {
+                        index.as_u64()?.try_into().ok()
+                    })
+                })
+                .collect::<Result<_, String>>()?;
+            let mesh = Mesh::new(vertices, triangles, material()?);
+            let mesh = match object.optional("texture_coordinates") {
+                Some(_) => {
+                    let texture_coordinates = object
+                        .array("texture_coordinates")?
+                        .iter()
+                        .map(|corners| {
+                            let corners: [Float; 6] =
+                                parse_numbers(corners, "the texture coordinates of a triangle")?;
+                            Ok([
+                                (corners[0], corners[1]),
+                                (corners[2], corners[3]),
+                                (corners[4], corners[5]),
+                            ])
+                        })
+                        .collect::<Result<_, String>>()?;
+                    mesh.with_texture_coordinates(texture_coordinates)
+                }
+                None => mesh,
+            };
+            (
+                Box::new(mesh),
+                &["vertices", "triangles", "texture_coordinates", "material"],
+            )
+        }
+        "transformed" => (
+            Box::new(Transformed::new(
+                parse_object(object.get("object")?, materials, nodes)?,
+                object.point("pivot")?,
+                vector("translation")?,
+                vector("rotation")?,
+                object.number("scale")?,
+            )),
+            &["object", "pivot", "translation", "rotation", "scale"],
+        ),
+        "list" => {
+            let members = object
+                .array("members")?
+                .iter()
+                .map(|member| parse_object(member, materials, nodes))
+                .collect::<Result<_, _>>()?;
+            (Box::new(HittableList { members }), &["members"])
+        }
+        "node" => {
+            let name = object.string("name")?;
+            let node = match object.optional("instance_of") {
+                Some(_) if object.optional("children").is_some() => {
+                    return Err("a node cannot have both instance_of and children".to_string())
+                }
+                Some(_) => {
+                    let original = object.string("instance_of")?;
+                    nodes
+                        .iter()
+                        .find(|node| node.name == original)
+                        .ok_or_else(|| format!("no earlier node is named {}", original))?
+                        .duplicate(name)
+                }
+                None => {
+                    let members = object
+                        .array("children")?
+                        .iter()
+                        .map(|child| parse_object(child, materials, nodes))
+                        .collect::<Result<_, _>>()?;
+                    Node::new(name, HittableList { members })
+                }
+            };
+            let zero = Vec3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            };
+            let optional_vector = |key: &str| match object.optional(key) {
+                Some(_) => vector(key),
+                None => Ok(zero.clone()),
+            };
+            let transform = Transform::new(
+                match object.optional("pivot") {
+                    Some(_) => object.point("pivot")?,
+                    None => Point3 {
+                        x: 0.,
+                        y: 0.,
+                        z: 0.,
+                    },
+                },
+                optional_vector("translation")?,
+                optional_vector("rotation")?,
+                match object.optional("scale") {
+                    Some(_) => object.number("scale")?,
+                    None => 1.,
+                },
+            );
+            let boolean = |key: &str, default: bool| match object.optional(key) {
+                None => Ok(default),
+                Some(Json::Bool(b)) => Ok(*b),
+                Some(_) => Err(format!("{} must be a boolean", key)),
+            };
+            let mut node = node.with_transform(transform);
+            node.visible = boolean("visible", true)?;
+            node.holdout = boolean("holdout", false)?;
+            if object.optional("layer").is_some() {
+                node.layer = Some(object.string("layer")?.to_string());
+            }
+            nodes.push(node.clone());
+            (
+                Box::new(node),
+                &[
+                    "name",
+                    "visible",
+                    "layer",
+                    "holdout",
+                    "pivot",
+                    "translation",
+                    "rotation",
+                    "scale",
+                    "instance_of",
+                    "children",
+                ],
+            )
+        }
+        kind => return Err(format!("unknown type of objects: {}", kind)),
+    };
+    object.check_keys(&[&["type"], keys].concat())?;
+    Ok(parsed)
+}
+
+/// Parses the array of `N` numbers, e.g. `[x, y, z]`, where `what` names it in errors.
+fn parse_numbers<const N: usize>(json: &Json, what: &str) -> Result<[Float; N], String> {
+    parse_array(json, what, "numbers", |x| Some(x.as_f64()? as Float))
+}
+
+/// Parses the array of `N` elements converted by `element`, where `what` names the array
+/// and `elements` names the elements in errors.
+fn parse_array<T, const N: usize>(
+    json: &Json,
+    what: &str,
+    elements: &str,
+    element: impl Fn(&Json) -> Option<T>,
+) -> Result<[T; N], String> {
+    let invalid = || format!("{} must be an array of {} {}", what, N, elements);
+    let Json::Array(values) = json else {
+        return Err(invalid());
+    };
+    let parsed: Vec<T> = values
+        .iter()
+        .map(element)
+        .collect::<Option<_>>()
+        .ok_or_else(invalid)?;
+    parsed.try_into().map_err(|_| invalid())
+}
+
+/// The type for the members of the JSON objects in scene files,
+/// whose errors name the missing, invalid, and unknown members.
+struct Members<'a> {
+    members: &'a [(String, Json)],
+}
+impl<'a> Members<'a> {
+    fn new(json: &'a Json, what: &str) -> Result<Self, String> {
+        match json {
+            Json::Object(members) => Ok(Members { members }),
+            _ => Err(format!("{} must be a JSON object", what)),
+        }
+    }
+
+    fn optional(&self, key: &str) -> Option<&'a Json> {
+        self.members.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    fn get(&self, key: &str) -> Result<&'a Json, String> {
+        self.optional(key).ok_or_else(|| format!("missing {}", key))
+    }
+
+    fn number(&self, key: &str) -> Result<Float, String> {
+        let value = self.get(key)?.as_f64();
+        value
+            .map(|x| x as Float)
+            .ok_or_else(|| format!("{} must be a number", key))
+    }
+
+    fn integer<T: TryFrom<u64>>(&self, key: &str) -> Result<T, String> {
+        let value = self.get(key)?.as_u64();
+        value
+            .and_then(|i| i.try_into().ok())
+            .ok_or_else(|| format!("{} must be a non-negative integer", key))
+    }
+
+    fn string(&self, key: &str) -> Result<&'a str, String> {
+        let value = self.get(key)?.as_str();
+        value.ok_or_else(|| format!("{} must be a string", key))
+    }
+
+    fn array(&self, key: &str) -> Result<&'a [Json], String> {
+        match self.get(key)? {
+            Json::Array(elements) => Ok(elements),
+            _ => Err(format!("{} must be an array", key)),
+        }
+    }
+
+    fn triple(&self, key: &str) -> Result<[Float; 3], String> {
+        parse_numbers(self.get(key)?, key)
+    }
+
+    fn point(&self, key: &str) -> Result<Point3, String> {
+        let [x, y, z] = self.triple(key)?;
+        Ok(Point3 { x, y, z })
+    }
+
+    /// Fails if there are members other than `keys`, which are likely typos.
+    fn check_keys(&self, keys: &[&str]) -> Result<(), String> {
+        match self
+            .members
+            .iter()
+            .find(|(k, _)| !keys.contains(&k.as_str()))
+        {
+            Some((key, _)) => Err(format!("unknown member: {}", key)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Ray;
+    use crate::hittable_object::TexturedLambertian;
+    use crate::interval::Interval;
+    use crate::scenes::{self, SceneParams};
+    use crate::texture::ImageTexture;
+
+    /// Checks that `scene` and `loaded` look the same through the rays of a grid of pixels.
+    fn assert_same_hits(scene: &Scene, loaded: &Scene) {
+        let ray_t = Interval {
+            min: 0.001,
+            max: Float::INFINITY,
+        };
+        for i in 0..16 {
+            for j in 0..16 {
+                let (u, v) = (i as Float / 15., j as Float / 15.);
+                let ray: Ray = scene.camera.get_ray(u, v);
+                assert_eq!(ray, loaded.camera.get_ray(u, v));
+                let hit = scene.world.hit(&ray, &ray_t);
+                let loaded_hit = loaded.world.hit(&ray, &ray_t);
+                assert_eq!(
+                    hit.as_ref()
+                        .map(|(hit, material)| (hit, format!("{:?}", material))),
+                    loaded_hit
+                        .as_ref()
+                        .map(|(hit, material)| (hit, format!("{:?}", material))),
+                    "({}, {})",
+                    u,
+                    v
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_tests() {
+        let params = SceneParams {
+            seed: 7,
+            density: 1.,
+            ..SceneParams::default()
+        };
+        for name in ["three-spheres", "book-cover", "cornell-box", "methanol"] {
+            let scene = scenes::by_name(name, 1.5, &params).unwrap();
+            let json = to_json(&scene).unwrap();
+            let loaded = from_json(&Json::parse(&json.pretty()).unwrap(), 1.5).unwrap();
+            assert_eq!(Ok(()), loaded.validate(), "{}", name);
+            assert_eq!(
+                scene.world.members.len(),
+                loaded.world.members.len(),
+                "{}",
+                name
+            );
+            assert_same_hits(&scene, &loaded);
+            // Writing the loaded scene gives the same file.
+            assert_eq!(json, to_json(&loaded).unwrap(), "{}", name);
+        }
+
+        let mut scene = scenes::by_name("three-spheres", 1.5, &params).unwrap();
+        let sun = Sun::from_irradiance(
+            Vec3 {
+                x: 1.,
+                y: 2.,
+                z: -1.,
+            }
+            .unit_vector(),
+            0.01,
+            &Color {
+                r: 3.,
+                g: 3.,
+                b: 2.,
+            },
+        );
+        scene.background = Background::SunnySky(sun.clone());
+        let json = to_json(&scene).unwrap();
+        let loaded = from_json(&Json::parse(&json.pretty()).unwrap(), 1.5).unwrap();
+        let Background::SunnySky(loaded_sun) = &loaded.background else {
+            panic!("the sun is lost");
+        };
+        assert!(sun.direction.inner_product(&loaded_sun.direction) > 1. - 1e-6);
+        assert!((sun.angular_radius - loaded_sun.angular_radius).abs() < 1e-6);
+        assert_eq!(sun.radiance, loaded_sun.radiance);
+    }
+
+    #[test]
+    fn to_json_tests() {
+        let shared: SharedMaterial = Arc::new(Lambertian {
+            albedo: Spectrum {
+                r: 0.5,
+                g: 0.5,
+                b: 0.5,
+            },
+        });
+        let sphere = |x: Float, material: &SharedMaterial| -> Box<dyn Hittable> {
+            Box::new(Sphere {
+                center: Point3 { x, y: 0., z: 0. },
+                radius: 1.,
+                material: material.clone(),
+            })
+        };
+        let mut scene = scenes::furnace(1.);
+        scene.world.members = vec![sphere(0., &shared), sphere(2., &shared)];
+        let json = to_json(&scene).unwrap();
+        // The material shared by the spheres is written once.
+        assert_eq!(
+            Some(&Json::Array(vec![tagged(
+                "lambertian",
+                vec![("albedo", triple(0.5, 0.5, 0.5))]
+            )])),
+            json.get("materials")
+        );
+        assert_eq!(Some(&triple(1., 1., 1.)), json.get("background"));
+
+        let texture: SharedMaterial = Arc::new(TexturedLambertian {
+            texture: Arc::new(ImageTexture::new(
+                1,
+                1,
+                vec![Spectrum {
+                    r: 1.,
+                    g: 1.,
+                    b: 1.,
+                }],
+            )),
+        });
+        scene.world.members.push(sphere(4., &texture));
+        assert_eq!(
+            Err("object #2 cannot be written to scene files, which support neither volumes nor image textures".to_string()),
+            to_json(&scene)
+        );
+    }
+
+    #[test]
+    fn from_json_tests() {
+        let text = r#"{
+            "camera": {"look_from": [0, 0, 5], "look_at": [0, 0, 0], "vertical_fov": 40, "orthographic": 4},
+            "background": "sky",
+            "materials": [
+                {"type": "metal", "albedo": [0.5, 0.5, 0.5], "fuzz": 0},
+                {"type": "toon", "albedo": [0.75, 0.25, 0.25], "bands": 3, "light_direction": [-1, 1, 1]},
+                {"type": "shadow_catcher", "reflectance": 0.5},
+                {"type": "light", "emit": [4, 4, 4]}
+            ],
+            "objects": [
+                {"type": "sphere", "center": [0, 0, -2], "radius": 0.5, "material": 0},
+                {"type": "sphere", "center": [0, 2, -2], "radius": 0.5, "material": 1},
+                {"type": "quad", "q": [-2, -1, -2], "u": [4, 0, 0], "v": [0, 0, 4], "material": 2},
+                {"type": "transformed", "pivot": [0, 0, 0], "translation": [1, 0, 0],
+                 "rotation": [0, 0, 0], "scale": 2,
+                 "object": {"type": "list", "members": [
+                     {"type": "mesh", "vertices": [[0, 0, 0], [1, 0, 0], [0, 1, 0]],
+                      "triangles": [[0, 1, 2]], "material": 3}
+                 ]}}
+            ]
+        }"#;
+        let scene = from_json(&Json::parse(text).unwrap(), 1.).unwrap();
+        assert_eq!(Ok(()), scene.validate());
+        assert_eq!(
+            &Projection::Orthographic {
+                viewport_height: 4.
+            },
+            scene.camera.projection()
+        );
+        let json = to_json(&scene).unwrap();
+        assert_eq!(Json::parse(text).unwrap(), json);
+
+        let error = |text: &str| from_json(&Json::parse(text).unwrap(), 1.).map(|_| ());
+        let camera =
+            r#""camera": {"look_from": [0, 0, 5], "look_at": [0, 0, 0], "vertical_fov": 40}"#;
+        assert_eq!(
+            Err("object #0: material 1 is out of range (there are 1 materials)".to_string()),
+            error(&format!(
+                r#"{{{}, "background": "sky", "materials": [{{"type": "isotropic", "albedo": [1, 1, 1]}}],
+                "objects": [{{"type": "sphere", "center": [0, 0, 0], "radius": 1, "material": 1}}]}}"#,
+                camera
+            ))
+        );
+        assert_eq!(
+            Err("object #0: unknown member: radus".to_string()),
+            error(&format!(
+                r#"{{{}, "background": "sky", "materials": [{{"type": "light", "emit": [1, 1, 1]}}],
+                "objects": [{{"type": "sphere", "center": [0, 0, 0], "radus": 1, "radius": 1, "material": 0}}]}}"#,
+                camera
+            ))
+        );
+        assert_eq!(
+            Err("material #0: albedo must be an array of 3 numbers".to_string()),
+            error(&format!(
+                r#"{{{}, "background": "sky", "materials": [{{"type": "lambertian", "albedo": [1, 1]}}],
+                "objects": []}}"#,
+                camera
+            ))
+        );
+        assert_eq!(
+            Err("background: sun must not be zero".to_string()),
+            error(&format!(
+                r#"{{{}, "background": {{"sun": [0, 0, 0], "sun_radius": 1, "sun_radiance": [1, 1, 1]}},
+                "materials": [], "objects": []}}"#,
+                camera
+            ))
+        );
+        assert_eq!(
+            Err("missing objects".to_string()),
+            error(&format!(
+                r#"{{{}, "background": [0, 0, 0], "materials": []}}"#,
+                camera
+            ))
+        );
+    }
+
+    #[test]
+    fn material_name_tests() {
+        let scene_text = |materials: &str, objects: &str| {
+            format!(
+                r#"{{"camera": {{"look_from": [0, 0, 5], "look_at": [0, 0, 0], "vertical_fov": 40}},
+                "background": "sky", "materials": [{}], "objects": [{}]}}"#,
+                materials, objects
+            )
+        };
+        let text = scene_text(
+            r#"{"type": "lambertian", "name": "ground", "albedo": [0.5, 0.5, 0.5]},
+               {"type": "metal", "name": "gold", "albedo": [0.8, 0.6, 0.2], "fuzz": 0}"#,
+            r#"{"type": "sphere", "center": [0, -100, 0], "radius": 99, "material": "ground"},
+               {"type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "gold"},
+               {"type": "sphere", "center": [2, 0, 0], "radius": 1, "material": 0}"#,
+        );
+        let scene = from_json(&Json::parse(&text).unwrap(), 1.).unwrap();
+        assert_eq!(Ok(()), scene.validate());
+        // The names and the indices refer to the same materials, which are written once.
+        let json = to_json(&scene).unwrap();
+        let Some(Json::Array(materials)) = json.get("materials") else {
+            panic!("no materials");
+        };
+        assert_eq!(2, materials.len());
+        let object_materials: Vec<_> = match json.get("objects") {
+            Some(Json::Array(objects)) => objects
+                .iter()
+                .map(|object| object.get("material").and_then(Json::as_u64))
+                .collect(),
+            _ => panic!("no objects"),
+        };
+        assert_eq!(vec![Some(0), Some(1), Some(0)], object_materials);
+
+        let error = |materials: &str, objects: &str| {
+            from_json(&Json::parse(&scene_text(materials, objects)).unwrap(), 1.).map(|_| ())
+        };
+        let lambertian = r#"{"type": "lambertian", "name": "ground", "albedo": [0.5, 0.5, 0.5]}"#;
+        assert_eq!(
+            Err("object #0: no material is named grund".to_string()),
+            error(
+                lambertian,
+                r#"{"type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "grund"}"#
+            )
+        );
+        assert_eq!(
+            Err("material #1: another material is named ground".to_string()),
+            error(&format!("{}, {}", lambertian, lambertian), "")
+        );
+        assert_eq!(
+            Err("material #0: name must be a string".to_string()),
+            error(
+                r#"{"type": "lambertian", "name": 1, "albedo": [0.5, 0.5, 0.5]}"#,
+                ""
+            )
+        );
+    }
+
+    #[test]
+    fn node_tests() {
+        let text = r#"{
+            "camera": {"look_from": [0, 0, 5], "look_at": [0, 0, 0], "vertical_fov": 40},
+            "background": "sky",
+            "materials": [{"type": "lambertian", "albedo": [0.5, 0.5, 0.5]}],
+            "objects": [
+                {"type": "node", "name": "molecule", "visible": false, "children": [
+                    {"type": "sphere", "center": [0, 0, 0], "radius": 0.5, "material": 0}
+                ]},
+                {"type": "node", "name": "copy", "instance_of": "molecule", "translation": [2, 0, 0]},
+                {"type": "node", "name": "ground", "layer": "ground", "holdout": true, "children": []}
+            ]
+        }"#;
+        let scene = from_json(&Json::parse(text).unwrap(), 1.).unwrap();
+        assert_eq!(Ok(()), scene.validate());
+        let molecule = scene.world.node("molecule").unwrap();
+        let copy = scene.world.node("copy").unwrap();
+        assert!(!molecule.visible);
+        assert!(copy.visible);
+        assert!(copy.is_duplicate_of(molecule));
+        let ground = scene.world.node("ground").unwrap();
+        assert_eq!(Some("ground"), ground.layer.as_deref());
+        assert!(ground.holdout);
+        assert_eq!(
+            &Vec3 {
+                x: 2.,
+                y: 0.,
+                z: 0.
+            },
+            copy.transform.translation()
+        );
+
+        // Duplicates are written with their objects.
+        let loaded = from_json(&to_json(&scene).unwrap(), 1.).unwrap();
+        assert_same_hits(&scene, &loaded);
+        let copy = loaded.world.node("copy").unwrap();
+        assert_eq!(1, copy.children().members.len());
+
+        let error = |objects: &str| {
+            let text = format!(
+                r#"{{"camera": {{"look_from": [0, 0, 5], "look_at": [0, 0, 0], "vertical_fov": 40}},
+                "background": "sky", "materials": [], "objects": [{}]}}"#,
+                objects
+            );
+            from_json(&Json::parse(&text).unwrap(), 1.).map(|_| ())
+        };
+        assert_eq!(
+            Err("object #0: no earlier node is named molecule".to_string()),
+            error(r#"{"type": "node", "name": "copy", "instance_of": "molecule"}"#)
+        );
+        assert_eq!(
+            Err("object #0: visible must be a boolean".to_string()),
+            error(r#"{"type": "node", "name": "empty", "visible": 1, "children": []}"#)
+        );
+    }
+}
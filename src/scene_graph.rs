@@ -0,0 +1,486 @@
+use std::sync::{Arc, OnceLock};
+
+use crate::geometry::{Point3, Ray};
+use crate::hittable_object::{
+    shared_memory_size, HitRecord, Hittable, HittableList, Holdout, SharedMaterial, Transform,
+};
+use crate::interval::Interval;
+use crate::json::Json;
+use crate::light::Light;
+use crate::scene_file::{tagged, MaterialTable};
+
+/// The type for the named nodes of scene graphs, which transform and hide groups of objects,
+/// e.g. a whole molecule, as a unit. Nodes can be nested, and duplicates of a node share its objects.
+#[derive(Clone)]
+pub struct Node {
+    pub name: String,
+    /// Whether the objects are rendered, which rays skip if `false`.
+    pub visible: bool,
+    pub transform: Transform,
+    /// The render layer of the objects, which are in that of the nearest node with one,
+    /// or in `DEFAULT_LAYER` if no node has one.
+    pub layer: Option<String>,
+    /// Whether the objects are rendered black while still hiding and shadowing the others,
+    /// e.g. to composite them over another pass.
+    pub holdout: bool,
+    /// The objects in the node, which are shared with the duplicates of the node.
+    children: Arc<HittableList>,
+}
+impl Node {
+    /// Creates the visible node of `children` without transformation.
+    pub fn new(name: impl Into<String>, children: HittableList) -> Self {
+        Node {
+            name: name.into(),
+            visible: true,
+            transform: Transform::identity(),
+            layer: None,
+            holdout: false,
+            children: Arc::new(children),
+        }
+    }
+
+    pub fn with_transform(self, transform: Transform) -> Self {
+        Node { transform, ..self }
+    }
+
+    pub fn with_layer(self, layer: impl Into<String>) -> Self {
+        Node {
+            layer: Some(layer.into()),
+            ..self
+        }
+    }
+
+    pub fn children(&self) -> &HittableList {
+        &self.children
+    }
+
+    /// Returns the objects of the node to modify, or `None` if they are shared with duplicates.
+    pub fn children_mut(&mut self) -> Option<&mut HittableList> {
+        Arc::get_mut(&mut self.children)
+    }
+
+    /// Returns the node named `name` sharing the objects with `self`, e.g. to place another copy
+    /// of a molecule, which can be transformed and hidden separately without copying the objects.
+    pub fn duplicate(&self, name: impl Into<String>) -> Self {
+        Node {
+            name: name.into(),
+            ..self.clone()
+        }
+    }
+
+    /// Checks that `self` and `other` share their objects.
+    pub fn is_duplicate_of(&self, other: &Node) -> bool {
+        Arc::ptr_eq(&self.children, &other.children)
+    }
+}
+impl Hittable for Node {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<(HitRecord, SharedMaterial)> {
+        let hit = if !self.visible {
+            None
+        } else if self.transform.is_identity() {
+            self.children.hit(ray, ray_t)
+        } else {
+            self.transform.hit(self.children.as_ref(), ray, ray_t)
+        };
+        if self.holdout {
+            hit.map(|(hit, _)| (hit, holdout_material()))
+        } else {
+            hit
+        }
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut problems = self.transform.validate();
+        problems.extend(self.children.validate());
+        problems
+            .into_iter()
+            .map(|problem| format!("node {}: {}", self.name, problem))
+            .collect()
+    }
+
+    fn memory_size(&self) -> usize {
+        std::mem::size_of_val(self)
+            + shared_memory_size(&self.children, self.children.memory_size())
+    }
+
+    fn bounding_box(&self) -> Option<(Point3, Point3)> {
+        let bounds = self.children.bounding_box()?;
+        if self.transform.is_identity() {
+            Some(bounds)
+        } else {
+            Some(self.transform.bounding_box(&bounds))
+        }
+    }
+
+    /// Pushes the lights of the objects only without transformation, like `Transformed`.
+    fn collect_lights(&self, lights: &mut Vec<Light>) {
+        if self.visible && !self.holdout && self.transform.is_identity() {
+            self.children.collect_lights(lights);
+        }
+    }
+
+    fn to_json(&self, materials: &mut MaterialTable) -> Option<Json> {
+        let mut members = vec![
+            ("name", Json::String(self.name.clone())),
+            ("visible", Json::Bool(self.visible)),
+        ];
+        if let Some(layer) = &self.layer {
+            members.push(("layer", Json::String(layer.clone())));
+        }
+        if self.holdout {
+            members.push(("holdout", Json::Bool(true)));
+        }
+        members.extend(self.transform.to_json_members());
+        let children = self
+            .children
+            .members
+            .iter()
+            .map(|child| child.to_json(materials))
+            .collect::<Option<_>>()?;
+        members.push(("children", Json::Array(children)));
+        Some(tagged("node", members))
+    }
+
+    fn as_node(&self) -> Option<&Node> {
+        Some(self)
+    }
+
+    fn as_node_mut(&mut self) -> Option<&mut Node> {
+        Some(self)
+    }
+}
+
+fn holdout_material() -> SharedMaterial {
+    static HOLDOUT: OnceLock<SharedMaterial> = OnceLock::new();
+    HOLDOUT.get_or_init(|| Arc::new(Holdout)).clone()
+}
+
+/// The layer of the objects in no node with a layer.
+pub const DEFAULT_LAYER: &str = "default";
+
+/// The type for the selections of the layers to render, e.g. to output a molecule
+/// and the ground under it as separate passes for compositing.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LayerSelection {
+    /// The layers to render, or all the layers if empty.
+    pub layers: Vec<String>,
+    /// The layers rendered as holdouts, i.e., black but still hiding and shadowing the others,
+    /// whether they are in `layers` or not.
+    pub holdout: Vec<String>,
+}
+impl LayerSelection {
+    /// Returns whether the objects in `layer` are rendered and whether they are holdouts.
+    fn state(&self, layer: &str) -> (bool, bool) {
+        let is_holdout = self.holdout.iter().any(|holdout| holdout == layer);
+        let is_selected = self.layers.is_empty() || self.layers.iter().any(|name| name == layer);
+        (is_holdout || is_selected, is_holdout)
+    }
+}
+
+impl HittableList {
+    /// Returns the names of the layers of the objects in alphabetical order.
+    /// The nodes in a node with a layer do not make other layers.
+    pub fn layers(&self) -> Vec<String> {
+        let mut layers = vec![];
+        self.collect_layers(&mut layers);
+        layers.sort();
+        layers.dedup();
+        layers
+    }
+
+    fn collect_layers(&self, layers: &mut Vec<String>) {
+        for member in self.members.iter() {
+            match member.as_node() {
+                Some(Node {
+                    layer: Some(layer), ..
+                }) => layers.push(layer.clone()),
+                Some(node) => node.children.collect_layers(layers),
+                None => layers.push(DEFAULT_LAYER.to_string()),
+            }
+        }
+    }
+
+    /// Hides the objects in the layers other than the selected ones and makes those in
+    /// the holdout layers holdouts, which fails if the selection names unknown layers.
+    /// The objects in `DEFAULT_LAYER` outside nodes are put into nodes named after the layer,
+    /// and the nodes whose objects are shared with duplicates are in the layer as a whole.
+    pub fn select_layers(&mut self, selection: &LayerSelection) -> Result<(), String> {
+        let layers = self.layers();
+        if let Some(unknown) = selection
+            .layers
+            .iter()
+            .chain(selection.holdout.iter())
+            .find(|name| !layers.contains(name))
+        {
+            return Err(format!(
+                "unknown layer: {} (the layers are {})",
+                unknown,
+                layers.join(", ")
+            ));
+        }
+        self.apply_layers(selection);
+        Ok(())
+    }
+
+    fn apply_layers(&mut self, selection: &LayerSelection) {
+        let default_state = selection.state(DEFAULT_LAYER);
+        for member in self.members.iter_mut() {
+            match member.as_node_mut() {
+                Some(node) => {
+                    let layer = node.layer.as_deref().unwrap_or(DEFAULT_LAYER);
+                    let (is_rendered, is_holdout) = selection.state(layer);
+                    // The objects of the nodes without layers can be in different layers.
+                    let children = match node.layer {
+                        None => node.children_mut(),
+                        Some(_) => None,
+                    };
+                    match children {
+                        Some(children) => children.apply_layers(selection),
+                        None => {
+                            node.visible &= is_rendered;
+                            node.holdout |= is_holdout;
+                        }
+                    }
+                }
+                None if default_state != (true, false) => {
+                    let object =
+                        std::mem::replace(member, Box::new(HittableList { members: vec![] }));
+                    let mut node = Node::new(
+                        DEFAULT_LAYER,
+                        HittableList {
+                            members: vec![object],
+                        },
+                    );
+                    (node.visible, node.holdout) = default_state;
+                    *member = Box::new(node);
+                }
+                None => {}
+            }
+        }
+    }
+
+    /// Returns the node named `name` among the members and the nodes in them.
+    pub fn node(&self, name: &str) -> Option<&Node> {
+        self.members.iter().find_map(|member| {
+            let node = member.as_node()?;
+            if node.name == name {
+                Some(node)
+            } else {
+                node.children.node(name)
+            }
+        })
+    }
+
+    /// Returns the node named `name` to modify among the members and the nodes in them,
+    /// except for those in the nodes whose objects are shared with duplicates.
+    pub fn node_mut(&mut self, name: &str) -> Option<&mut Node> {
+        self.members.iter_mut().find_map(|member| {
+            let node = member.as_node_mut()?;
+            if node.name == name {
+                Some(node)
+            } else {
+                node.children_mut()?.node_mut(name)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Attenuation;
+    use crate::geometry::{Float, Point3, Vec3};
+    use crate::hittable_object::{Lambertian, Sphere};
+
+    fn unit_sphere(x: Float) -> Box<dyn Hittable> {
+        Box::new(Sphere {
+            center: Point3 { x, y: 0., z: 0. },
+            radius: 1.,
+            material: Arc::new(Lambertian {
+                albedo: Attenuation {
+                    r: 0.5,
+                    g: 0.5,
+                    b: 0.5,
+                },
+            }),
+        })
+    }
+
+    /// Returns the distance to the object hit by the ray from `(x, 0, 10)` toward -z.
+    fn distance(world: &HittableList, x: Float) -> Option<Float> {
+        let ray = Ray {
+            origin: Point3 { x, y: 0., z: 10. },
+            direction: Vec3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            }
+            .unit_vector(),
+        };
+        let ray_t = Interval {
+            min: 0.001,
+            max: Float::INFINITY,
+        };
+        world.hit(&ray, &ray_t).map(|(hit, _)| hit.t)
+    }
+
+    #[test]
+    fn node_tests() {
+        let inner = Node::new(
+            "inner",
+            HittableList {
+                members: vec![unit_sphere(0.)],
+            },
+        );
+        let outer = Node::new(
+            "outer",
+            HittableList {
+                members: vec![Box::new(inner), unit_sphere(3.)],
+            },
+        );
+        let copy = outer.duplicate("copy").with_transform(Transform::new(
+            Point3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            Vec3 {
+                x: 10.,
+                y: 0.,
+                z: -2.,
+            },
+            Vec3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            1.,
+        ));
+        let mut world = HittableList {
+            members: vec![Box::new(outer), Box::new(copy)],
+        };
+        assert_eq!(Some(9.), distance(&world, 0.));
+        assert_eq!(Some(9.), distance(&world, 3.));
+        // The duplicate shares the spheres moved by (10, 0, -2).
+        let moved = distance(&world, 10.).unwrap();
+        assert!((moved - 11.).abs() < 1e-9, "{}", moved);
+        assert!(world
+            .node("copy")
+            .unwrap()
+            .is_duplicate_of(world.node("outer").unwrap()));
+        assert!(world.node("inner").is_some());
+        assert!(world.node("missing").is_none());
+
+        // Hiding a node hides the nodes in it but not its duplicates.
+        world.node_mut("outer").unwrap().visible = false;
+        assert_eq!(None, distance(&world, 0.));
+        assert!(distance(&world, 13.).is_some());
+        // The nodes in shared objects cannot be modified.
+        assert!(world.node_mut("inner").is_none());
+        assert_eq!(Vec::<String>::new(), world.validate());
+
+        world.node_mut("copy").unwrap().transform = Transform::new(
+            Point3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            Vec3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            Vec3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            0.,
+        );
+        assert_eq!(
+            vec!["object #1: node copy: scale must be positive and finite, but is 0".to_string()],
+            world.validate()
+        );
+    }
+
+    #[test]
+    fn layer_tests() {
+        let world = || HittableList {
+            members: vec![
+                Box::new(
+                    Node::new(
+                        "molecule",
+                        HittableList {
+                            members: vec![unit_sphere(0.)],
+                        },
+                    )
+                    .with_layer("molecule"),
+                ),
+                unit_sphere(3.),
+            ],
+        };
+        assert_eq!(vec!["default", "molecule"], world().layers());
+
+        let mut molecule_pass = world();
+        let selection = LayerSelection {
+            layers: vec!["molecule".to_string()],
+            holdout: vec![],
+        };
+        molecule_pass.select_layers(&selection).unwrap();
+        assert_eq!(Some(9.), distance(&molecule_pass, 0.));
+        assert_eq!(None, distance(&molecule_pass, 3.));
+        assert!(molecule_pass.node(DEFAULT_LAYER).is_some());
+
+        // Holdouts are hit but have the holdout material.
+        let mut holdout_pass = world();
+        let selection = LayerSelection {
+            layers: vec!["molecule".to_string()],
+            holdout: vec!["default".to_string()],
+        };
+        holdout_pass.select_layers(&selection).unwrap();
+        let ray = Ray {
+            origin: Point3 {
+                x: 3.,
+                y: 0.,
+                z: 10.,
+            },
+            direction: Vec3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            }
+            .unit_vector(),
+        };
+        let ray_t = Interval {
+            min: 0.001,
+            max: Float::INFINITY,
+        };
+        let (hit, material) = holdout_pass.hit(&ray, &ray_t).unwrap();
+        assert_eq!(9., hit.t);
+        assert!(Arc::ptr_eq(&holdout_material(), &material));
+        assert!(material.scatter(&ray, &hit).is_none());
+        let (_, material) = holdout_pass
+            .hit(
+                &Ray {
+                    origin: Point3 {
+                        x: 0.,
+                        y: 0.,
+                        z: 10.,
+                    },
+                    ..ray
+                },
+                &ray_t,
+            )
+            .unwrap();
+        assert!(!Arc::ptr_eq(&holdout_material(), &material));
+
+        let selection = LayerSelection {
+            layers: vec!["ground".to_string()],
+            holdout: vec![],
+        };
+        assert_eq!(
+            Err("unknown layer: ground (the layers are default, molecule)".to_string()),
+            world().select_layers(&selection)
+        );
+    }
+}
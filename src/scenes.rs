@@ -0,0 +1,1940 @@
+extern crate rand;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::animation::{Animation, Keyframe, ObjectAnimation, ObjectTransform, Track};
+use crate::arena::Arena;
+use crate::bvh;
+use crate::camera::{Camera, CameraPose, Projection};
+use crate::color::{Attenuation, Color};
+use crate::geometry::{consts, Float, Onb, Point3, Ray, UnitVec3, Vec3};
+use crate::hittable_object::{
+    DiffuseLight, Glass, Hittable, HittableList, Isotropic, Lambertian, Mesh, Metal, Overridden,
+    Quad, SharedMaterial, Sphere, TexturedLambertian,
+};
+use crate::interval::Interval;
+use crate::light::Sun;
+use crate::molecule::{Molecule, MoleculeOptions};
+use crate::scene_graph::Node;
+use crate::texture::ImageTexture;
+use crate::volume::{DensityGrid, HeterogeneousMedium, VolumeFile};
+
+/// The type for backgrounds, i.e., the colors of rays that hit nothing.
+pub enum Background {
+    /// The gradation from white to sky blue.
+    Sky,
+    /// `Sky` with the sun in it.
+    SunnySky(Sun),
+    Solid(Color),
+    /// The equirectangular image of the light from all directions, e.g. a PFM image of high
+    /// dynamic range, whose texture coordinates are given by `light::environment_uv`.
+    Environment(Arc<ImageTexture>),
+}
+
+/// The type for the hits found by `Scene::raycast`.
+#[derive(Clone, Debug)]
+pub struct HitInfo {
+    /// The index of the object hit among `world.members` of the scene.
+    pub object: usize,
+    pub point: Point3,
+    /// The distance from the origin of the ray, which is `t` since the directions of rays are unit.
+    pub distance: Float,
+    /// The normal of the surface at the point, which points outward for closed objects, e.g. spheres.
+    pub normal: UnitVec3,
+    pub material: SharedMaterial,
+}
+
+/// The type for scenes selectable by `--scene <name>`.
+pub struct Scene {
+    pub camera: Camera,
+    pub world: HittableList,
+    pub background: Background,
+}
+
+impl Scene {
+    /// Checks the scene before rendering it,
+    /// and returns the list of the problems found if any.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut problems = vec![];
+        for problem in self.camera.validate() {
+            problems.push(format!("camera: {}", problem));
+        }
+        if self.world.members.is_empty() {
+            problems.push("the scene has no objects".to_string());
+        }
+        problems.extend(self.world.validate());
+        match &self.background {
+            Background::Solid(color) if !color.is_valid() => {
+                problems.push(format!(
+                    "background: color must be finite and non-negative, but is {:?}",
+                    color
+                ));
+            }
+            Background::SunnySky(sun) => {
+                let right_angle = 0.5 * consts::PI;
+                if !(sun.angular_radius > 0. && sun.angular_radius < right_angle) {
+                    problems.push(format!(
+                        "background: the angular radius of the sun must be in (0, 90) degrees, but is {}",
+                        sun.angular_radius.to_degrees()
+                    ));
+                }
+                if !sun.radiance.is_valid() {
+                    problems.push(format!(
+                        "background: the radiance of the sun must be finite and non-negative, but is {:?}",
+                        sun.radiance
+                    ));
+                }
+            }
+            Background::Environment(map) => {
+                if let Some(texel) = map.texels().iter().find(|texel| !texel.is_valid()) {
+                    problems.push(format!(
+                        "background: the environment map must be finite and non-negative, but has {:?}",
+                        texel
+                    ));
+                }
+            }
+            _ => {}
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Returns the minimum and the maximum corners of the box containing the objects with bounds,
+    /// leaving out those without, or `None` if no objects have bounds.
+    pub fn bounding_box(&self) -> Option<(Point3, Point3)> {
+        self.world
+            .members
+            .iter()
+            .filter_map(|member| member.bounding_box())
+            .reduce(|a, b| bvh::union(&a, &b))
+    }
+
+    /// Returns the nearest hit of `ray` on the objects, or `None` if it hits nothing, the same as
+    /// the rays of rendering see, e.g. for picking objects or measuring distances in applications.
+    /// The objects are tested one by one without the BVH built for rendering.
+    pub fn raycast(&self, ray: &Ray) -> Option<HitInfo> {
+        let mut ray_t = Interval {
+            min: 0.,
+            max: Float::INFINITY,
+        };
+        let mut nearest = None;
+        for (object, member) in self.world.members.iter().enumerate() {
+            // Only hits nearer than the nearest one so far are looked for, as in `HittableList`.
+            if let Some((hit, material)) = member.hit(ray, &ray_t) {
+                ray_t.max = hit.t;
+                nearest = Some(HitInfo {
+                    object,
+                    point: ray.at(hit.t),
+                    distance: hit.t,
+                    normal: hit.surface_normal,
+                    material,
+                });
+            }
+        }
+        nearest
+    }
+
+    /// Returns the index of the closed object enclosing `point`, e.g. that the camera of a fly-through
+    /// has flown into, where it sees only the inside, or `None` if there is no such object.
+    /// The point is regarded as inside an object if the rays along the six axes from it all hit
+    /// the back of it first, which quads, having no insides, never report.
+    pub fn enclosing_object(&self, point: &Point3) -> Option<usize> {
+        let axes = [
+            (1., 0., 0.),
+            (-1., 0., 0.),
+            (0., 1., 0.),
+            (0., -1., 0.),
+            (0., 0., 1.),
+            (0., 0., -1.),
+        ];
+        let objects = axes
+            .into_iter()
+            .map(|(x, y, z)| {
+                let ray = Ray {
+                    origin: point.clone(),
+                    direction: Vec3 { x, y, z }.unit_vector(),
+                };
+                let hit = self.raycast(&ray)?;
+                let is_back = hit.normal.inner_product(&ray.direction) > 0.;
+                is_back.then_some(hit.object)
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let first = objects[0];
+        objects
+            .iter()
+            .all(|&object| object == first)
+            .then_some(first)
+    }
+
+    /// Moves the camera along its view direction to the distance at which the sphere around
+    /// the box of `bounding_box` fits in the image with `margin` as a fraction of its radius on each side,
+    /// aiming the camera at the center, so that scenes of unknown scale, e.g. imported meshes,
+    /// are not rendered as empty sky. Orthographic cameras have their viewports resized instead.
+    /// Returns an error if no objects have bounds or the camera has no view direction.
+    pub fn auto_frame(&mut self, margin: Float) -> Result<(), String> {
+        let (min, max) = self
+            .bounding_box()
+            .ok_or("cannot frame a scene without bounded objects")?;
+        let half_diagonal = (&max - &min) * 0.5;
+        let center = &min + &half_diagonal;
+        let radius = (1. + margin) * half_diagonal.length().max(Float::EPSILON);
+        let pose = self.camera.pose();
+        let look_in = &pose.look_at - &pose.look_from;
+        let look_in = &look_in / look_in.length();
+        if !look_in.is_finite() {
+            return Err("cannot frame a scene with a camera looking nowhere".to_string());
+        }
+        let aspect_ratio = self.camera.aspect_ratio();
+        let (distance, projection) = match self.camera.projection() {
+            Projection::Perspective => {
+                let vertical_half_fov = (pose.vertical_fov_degree / 2.).to_radians();
+                let horizontal_half_fov = (aspect_ratio * vertical_half_fov.tan()).atan();
+                let half_fov = vertical_half_fov.min(horizontal_half_fov);
+                (radius / half_fov.sin(), Projection::Perspective)
+            }
+            Projection::Orthographic { .. } => {
+                // The camera stays outside the sphere since its rays start from the viewport.
+                let viewport_height = 2. * radius * (1. / aspect_ratio).max(1.);
+                (2. * radius, Projection::Orthographic { viewport_height })
+            }
+            // The camera sees almost all around itself, so nothing is framed by moving it.
+            Projection::Stereographic => {
+                return Err("cannot frame a scene with a stereographic camera".to_string());
+            }
+        };
+        let pose = CameraPose {
+            look_from: &center - &look_in * distance,
+            look_at: center,
+            vertical_fov_degree: pose.vertical_fov_degree,
+        };
+        self.camera = self.camera.reposed(pose, projection);
+        Ok(())
+    }
+
+    /// Replaces the materials of the objects by that of `material_override` except where
+    /// they emit light, e.g. for a clay render checking the geometry. The objects stay in
+    /// the same order, so that animations still find them.
+    pub fn override_materials(&mut self, material_override: MaterialOverride) {
+        let material = material_override.material();
+        let members = std::mem::take(&mut self.world.members);
+        self.world.members = members
+            .into_iter()
+            .map(|object| -> Box<dyn Hittable> {
+                Box::new(Overridden {
+                    object,
+                    material: material.clone(),
+                })
+            })
+            .collect();
+    }
+}
+
+/// The materials that `Scene::override_materials` can put in place of those of the objects.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MaterialOverride {
+    /// A neutral gray Lambertian, which shows the shapes of the objects and how they occlude
+    /// one another without their colors, textures, or reflections.
+    Clay,
+}
+impl MaterialOverride {
+    pub fn material(&self) -> SharedMaterial {
+        match self {
+            MaterialOverride::Clay => Arc::new(Lambertian {
+                albedo: Attenuation {
+                    r: 0.7,
+                    g: 0.7,
+                    b: 0.7,
+                },
+            }),
+        }
+    }
+}
+impl FromStr for MaterialOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clay" => Ok(MaterialOverride::Clay),
+            _ => Err(format!("unknown material override: {} (expected clay)", s)),
+        }
+    }
+}
+
+/// The parameters for procedurally generated scenes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SceneParams {
+    /// The seed of the random number generator.
+    pub seed: u64,
+    /// The number of small objects per unit area. At most `MAX_SCENE_DENSITY`.
+    pub density: Float,
+    /// The size of the stress scenes, which multiplies their objects by about 8 or 9 per step:
+    /// the levels of recursion of `sphereflake` and the power of 2 of the spheres along each side
+    /// of `sphere-grid`. At most `MAX_SCENE_SCALE`.
+    pub scale: u32,
+}
+impl Default for SceneParams {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            density: 1.,
+            scale: 4,
+        }
+    }
+}
+
+/// The largest `SceneParams::scale`, with which the stress scenes have hundreds of thousands of spheres.
+pub const MAX_SCENE_SCALE: u32 = 6;
+
+/// The largest `SceneParams::density`, with which `book-cover` has about half a million small spheres.
+pub const MAX_SCENE_DENSITY: Float = 1000.;
+
+/// Returns `density` if it is a valid `SceneParams::density`, i.e., in [0, `MAX_SCENE_DENSITY`],
+/// so that no one can make the scenes too large to build, e.g. through the HTTP API.
+pub fn check_density(density: Float) -> Result<Float, String> {
+    if (0. ..=MAX_SCENE_DENSITY).contains(&density) {
+        Ok(density)
+    } else {
+        Err(format!(
+            "density must be in [0, {}], but is {}",
+            MAX_SCENE_DENSITY, density
+        ))
+    }
+}
+
+/// The names of the preset scenes accepted by `by_name`.
+pub const SCENE_NAMES: [&str; 10] = [
+    "methanol",
+    "three-spheres",
+    "book-cover",
+    "cornell-box",
+    "furnace",
+    "cloud",
+    "planet",
+    "soft-shadow",
+    "sphereflake",
+    "sphere-grid",
+];
+
+/// Returns the preset scene named `name`, or `None` if there is no such scene.
+pub fn by_name(name: &str, aspect_ratio: Float, params: &SceneParams) -> Option<Scene> {
+    match name {
+        "methanol" => Some(methanol(aspect_ratio)),
+        "three-spheres" => Some(three_spheres(aspect_ratio)),
+        "book-cover" => Some(book_cover(aspect_ratio, params)),
+        "cornell-box" => Some(cornell_box(aspect_ratio)),
+        "soft-shadow" => Some(soft_shadow(SOFT_SHADOW_LIGHT_SIZE, aspect_ratio)),
+        "furnace" => Some(furnace(aspect_ratio)),
+        "cloud" => Some(cloud(aspect_ratio)),
+        "planet" => Some(planet(aspect_ratio)),
+        "sphereflake" => Some(sphereflake(aspect_ratio, params)),
+        "sphere-grid" => Some(sphere_grid(aspect_ratio, params)),
+        _ => None,
+    }
+}
+
+/// Returns the preset animation of the scene named `name`;
+/// scenes without animations stay still.
+pub fn animation_by_name(name: &str) -> Animation {
+    fn keyframe<T>(time: Float, value: T) -> Keyframe<T> {
+        Keyframe { time, value }
+    }
+    match name {
+        "methanol" => {
+            // The camera moves from left to right around the molecule,
+            // while the atoms other than the carbon one revolve around it.
+            let pose = |x: Float| CameraPose {
+                look_from: Point3 { x, y: 0., z: 0.5 },
+                look_at: Point3 {
+                    x: 0.,
+                    y: 0.,
+                    z: -1.,
+                },
+                vertical_fov_degree: 80.,
+            };
+            let turn = |degree: Float| ObjectTransform {
+                rotation_degree: Vec3 {
+                    x: 0.,
+                    y: degree,
+                    z: 0.,
+                },
+                ..ObjectTransform::default()
+            };
+            let revolution = Track::new(vec![keyframe(0., turn(0.)), keyframe(2., turn(90.))]);
+            Animation {
+                camera: Some(Track::new(vec![
+                    keyframe(0., pose(-0.8)),
+                    keyframe(2., pose(0.8)),
+                ])),
+                objects: (1..=5)
+                    .map(|index| ObjectAnimation {
+                        index,
+                        pivot: Point3 {
+                            x: 0.,
+                            y: 0.,
+                            z: -1.,
+                        },
+                        transform: revolution.clone(),
+                    })
+                    .collect(),
+            }
+        }
+        "three-spheres" => {
+            // The glass sphere in the middle bounces twice,
+            // and the diffuse one on the left swells and shrinks.
+            let height = |y: Float| ObjectTransform {
+                translation: Vec3 { x: 0., y, z: 0. },
+                ..ObjectTransform::default()
+            };
+            let bounce = Track::new(vec![
+                keyframe(0., height(0.)),
+                keyframe(0.5, height(0.6)),
+                keyframe(1., height(0.)),
+                keyframe(1.5, height(0.6)),
+                keyframe(2., height(0.)),
+            ]);
+            let size = |scale: Float| ObjectTransform {
+                scale,
+                ..ObjectTransform::default()
+            };
+            let pulse = Track::new(vec![
+                keyframe(0., size(1.)),
+                keyframe(1., size(1.3)),
+                keyframe(2., size(1.)),
+            ]);
+            Animation {
+                camera: None,
+                objects: vec![
+                    ObjectAnimation {
+                        index: 0,
+                        // The bottom of the sphere, so that it stays on the ground.
+                        pivot: Point3 {
+                            x: -1.,
+                            y: -0.5,
+                            z: -1.,
+                        },
+                        transform: pulse,
+                    },
+                    ObjectAnimation {
+                        index: 2,
+                        pivot: Point3 {
+                            x: 0.,
+                            y: 0.,
+                            z: -1.,
+                        },
+                        transform: bounce,
+                    },
+                ],
+            }
+        }
+        _ => Animation::default(),
+    }
+}
+
+pub fn looking_at(
+    look_from: Point3,
+    look_at: Point3,
+    vertical_fov_degree: Float,
+    aspect_ratio: Float,
+) -> Camera {
+    let pose = CameraPose {
+        look_from,
+        look_at,
+        vertical_fov_degree,
+    };
+    Camera::new(pose, aspect_ratio)
+}
+
+fn default_camera(aspect_ratio: Float) -> Camera {
+    let look_from = Point3 {
+        x: 0.,
+        y: 0.,
+        z: 0.5,
+    };
+    let look_at = Point3 {
+        x: 0.,
+        y: 0.,
+        z: -0.5,
+    };
+    looking_at(look_from, look_at, 80., aspect_ratio)
+}
+
+fn ground() -> Box<dyn Hittable> {
+    Box::new(Sphere {
+        center: Point3 {
+            x: 0.,
+            y: -100.5,
+            z: -1.,
+        },
+        radius: 100.,
+        material: Arc::new(Lambertian {
+            albedo: Attenuation {
+                r: 0.2,
+                g: 0.4,
+                b: 0.2,
+            },
+        }),
+    })
+}
+
+fn oxygen(x: Float, y: Float, z: Float) -> Box<dyn Hittable> {
+    Box::new(Sphere {
+        center: Point3 { x, y, z },
+        radius: 0.3,
+        material: Arc::new(Glass {
+            eta: 1.5,
+            albedo: Attenuation {
+                r: 0.9,
+                g: 0.5,
+                b: 0.5,
+            },
+            priority: 0,
+        }),
+    })
+}
+
+fn carbon(x: Float, y: Float, z: Float) -> Box<dyn Hittable> {
+    Box::new(Sphere {
+        center: Point3 { x, y, z },
+        radius: 0.35,
+        material: Arc::new(Metal {
+            albedo: Attenuation {
+                r: 0.5,
+                g: 0.5,
+                b: 0.5,
+            },
+            fuzz: 0.1,
+        }),
+    })
+}
+
+fn hydrogen(x: Float, y: Float, z: Float) -> Box<dyn Hittable> {
+    Box::new(Sphere {
+        center: Point3 { x, y, z },
+        radius: 0.25,
+        material: Arc::new(Lambertian {
+            albedo: Attenuation {
+                r: 0.8,
+                g: 0.8,
+                b: 0.9,
+            },
+        }),
+    })
+}
+
+/// A methanol molecule (CH3OH) placed on the ground, which is in the layer `ground`.
+pub fn methanol(aspect_ratio: Float) -> Scene {
+    let (x1, y1, z1) = (0., 0., -1.);
+    let len_oh = 0.11;
+    let len_ch = 0.14;
+    let len_co = 0.2;
+    let world = HittableList {
+        members: vec![
+            carbon(x1, y1, z1),
+            oxygen(x1 + len_co, y1 + len_co, z1 + len_co),
+            hydrogen(
+                x1 + len_co + len_oh,
+                y1 + len_co - len_oh,
+                z1 + len_co + len_oh,
+            ),
+            hydrogen(x1 + len_ch, y1 - len_ch, z1 - len_ch),
+            hydrogen(x1 - len_ch, y1 - len_ch, z1 + len_ch),
+            hydrogen(x1 - len_ch, y1 + len_ch, z1 - len_ch),
+            Box::new(
+                Node::new(
+                    "ground",
+                    HittableList {
+                        members: vec![ground()],
+                    },
+                )
+                .with_layer("ground"),
+            ),
+        ],
+    };
+    Scene {
+        camera: default_camera(aspect_ratio),
+        world,
+        background: Background::Sky,
+    }
+}
+
+/// A Lambertian, a glass, and a metal sphere side by side.
+pub fn three_spheres(aspect_ratio: Float) -> Scene {
+    let sphere1 = Sphere {
+        center: Point3 {
+            x: -1.,
+            y: 0.,
+            z: -1.,
+        },
+        radius: 0.5,
+        material: Arc::new(Lambertian {
+            albedo: Attenuation {
+                r: 0.8,
+                g: 0.5,
+                b: 0.5,
+            },
+        }),
+    };
+    let sphere2 = Sphere {
+        center: Point3 {
+            x: 1.,
+            y: 0.,
+            z: -1.,
+        },
+        radius: 0.5,
+        material: Arc::new(Metal {
+            albedo: Attenuation {
+                r: 0.5,
+                g: 0.5,
+                b: 0.5,
+            },
+            fuzz: 0.3,
+        }),
+    };
+    let sphere3 = Sphere {
+        center: Point3 {
+            x: 0.,
+            y: 0.,
+            z: -1.,
+        },
+        radius: 0.5,
+        material: Arc::new(Glass {
+            eta: 1.5,
+            albedo: Attenuation {
+                r: 0.9,
+                g: 0.9,
+                b: 0.9,
+            },
+            priority: 0,
+        }),
+    };
+    let world = HittableList {
+        members: vec![
+            Box::new(sphere1),
+            Box::new(sphere2),
+            Box::new(sphere3),
+            ground(),
+        ],
+    };
+    Scene {
+        camera: default_camera(aspect_ratio),
+        world,
+        background: Background::Sky,
+    }
+}
+
+fn random_attenuation(rng: &mut StdRng, min: Float, max: Float) -> Attenuation {
+    let mut component = || rng.gen_range(min..max);
+    Attenuation {
+        r: component(),
+        g: component(),
+        b: component(),
+    }
+}
+
+/// Returns a Lambertian of a random color most of the time, and otherwise a random metal or glass.
+fn random_material(rng: &mut StdRng) -> SharedMaterial {
+    let choice = rng.gen::<Float>();
+    if choice < 0.8 {
+        let c1 = random_attenuation(rng, 0., 1.);
+        let c2 = random_attenuation(rng, 0., 1.);
+        Arc::new(Lambertian {
+            albedo: Attenuation {
+                r: c1.r * c2.r,
+                g: c1.g * c2.g,
+                b: c1.b * c2.b,
+            },
+        })
+    } else if choice < 0.95 {
+        Arc::new(Metal {
+            albedo: random_attenuation(rng, 0.5, 1.),
+            fuzz: rng.gen_range(0. ..0.5),
+        })
+    } else {
+        Arc::new(Glass {
+            eta: 1.5,
+            albedo: random_attenuation(rng, 0.9, 1.),
+            priority: 0,
+        })
+    }
+}
+
+/// The half of the side length of the square area where small spheres are scattered.
+const BOOK_COVER_HALF_EXTENT: Float = 11.;
+
+/// Generates the small spheres of the book cover scene.
+/// The ground is split into square cells so that each cell holds
+/// one randomly jittered sphere, and `params.density` cells fit in a unit area.
+fn book_cover_small_spheres(params: &SceneParams) -> Vec<Sphere> {
+    // Negative and NaN densities have no spheres, and too large ones have as many as the largest.
+    if params.density.is_nan() || params.density <= 0. {
+        return vec![];
+    }
+    let density = params.density.min(MAX_SCENE_DENSITY);
+    let mut rng = StdRng::seed_from_u64(params.seed);
+    let cell_size = 1. / density.sqrt();
+    let radius = 0.2 * cell_size;
+    let num_cells = (2. * BOOK_COVER_HALF_EXTENT / cell_size).ceil() as i32;
+    let big_sphere_centers = [
+        Point3 {
+            x: 0.,
+            y: 1.,
+            z: 0.,
+        },
+        Point3 {
+            x: -4.,
+            y: 1.,
+            z: 0.,
+        },
+        Point3 {
+            x: 4.,
+            y: 1.,
+            z: 0.,
+        },
+    ];
+
+    let mut spheres = vec![];
+    for a in 0..num_cells {
+        for b in 0..num_cells {
+            let center = Point3 {
+                x: -BOOK_COVER_HALF_EXTENT + cell_size * (a as Float + 0.9 * rng.gen::<Float>()),
+                y: radius,
+                z: -BOOK_COVER_HALF_EXTENT + cell_size * (b as Float + 0.9 * rng.gen::<Float>()),
+            };
+            let material = random_material(&mut rng);
+            let overlaps_big_sphere = big_sphere_centers
+                .iter()
+                .any(|big| (&center - big).length() <= 1. + radius);
+            if overlaps_big_sphere {
+                continue;
+            }
+            spheres.push(Sphere {
+                center,
+                radius,
+                material,
+            });
+        }
+    }
+    spheres
+}
+
+/// The cover image of _Ray Tracing in One Weekend_:
+/// small spheres of random materials scattered around three big ones.
+/// The small spheres are generated from `params.seed`,
+/// and thus the same parameters always produce the same scene.
+pub fn book_cover(aspect_ratio: Float, params: &SceneParams) -> Scene {
+    let mut world = HittableList::with_capacity(5);
+    world.push(Sphere {
+        center: Point3 {
+            x: 0.,
+            y: -1000.,
+            z: 0.,
+        },
+        radius: 1000.,
+        material: Arc::new(Lambertian {
+            albedo: Attenuation {
+                r: 0.5,
+                g: 0.5,
+                b: 0.5,
+            },
+        }),
+    });
+
+    // The hundreds of small spheres are stored in place rather than boxed one by one.
+    let small_spheres: Arena = book_cover_small_spheres(params).into_iter().collect();
+    world.push(small_spheres);
+
+    world.push(Sphere {
+        center: Point3 {
+            x: 0.,
+            y: 1.,
+            z: 0.,
+        },
+        radius: 1.,
+        material: Arc::new(Glass {
+            eta: 1.5,
+            albedo: Attenuation {
+                r: 1.,
+                g: 1.,
+                b: 1.,
+            },
+            priority: 0,
+        }),
+    });
+    world.push(Sphere {
+        center: Point3 {
+            x: -4.,
+            y: 1.,
+            z: 0.,
+        },
+        radius: 1.,
+        material: Arc::new(Lambertian {
+            albedo: Attenuation {
+                r: 0.4,
+                g: 0.2,
+                b: 0.1,
+            },
+        }),
+    });
+    world.push(Sphere {
+        center: Point3 {
+            x: 4.,
+            y: 1.,
+            z: 0.,
+        },
+        radius: 1.,
+        material: Arc::new(Metal {
+            albedo: Attenuation {
+                r: 0.7,
+                g: 0.6,
+                b: 0.5,
+            },
+            fuzz: 0.,
+        }),
+    });
+
+    let camera = looking_at(
+        Point3 {
+            x: 13.,
+            y: 2.,
+            z: 3.,
+        },
+        Point3 {
+            x: 0.,
+            y: 0.,
+            z: 0.,
+        },
+        20.,
+        aspect_ratio,
+    );
+    Scene {
+        camera,
+        world,
+        background: Background::Sky,
+    }
+}
+
+/// The gray ground of the stress scenes, which is a huge sphere touching the plane y = 0.
+fn stress_ground() -> Sphere {
+    Sphere {
+        center: Point3 {
+            x: 0.,
+            y: -1000.,
+            z: 0.,
+        },
+        radius: 1000.,
+        material: lambertian(0.5, 0.5, 0.5),
+    }
+}
+
+/// Adds to `spheres` the nine spheres of a third the radius on the sphere at `center` with `radius`,
+/// six around it and three above it as seen along `up`, and the spheres on them down to `levels` deep.
+fn add_sphereflake_children(
+    spheres: &mut Vec<Sphere>,
+    center: &Point3,
+    radius: Float,
+    up: &UnitVec3,
+    levels: u32,
+    material: &SharedMaterial,
+) {
+    if levels == 0 {
+        return;
+    }
+    let onb = Onb::from_normal(up);
+    let child_radius = radius / 3.;
+    // The azimuths and the elevations in degrees.
+    let around = (0..6).map(|k| -> (Float, Float) { (k as Float * 60., 0.) });
+    let above = (0..3).map(|k| -> (Float, Float) { (30. + k as Float * 120., 60.) });
+    for (azimuth, elevation) in around.chain(above) {
+        let (azimuth, elevation) = (azimuth.to_radians(), elevation.to_radians());
+        let direction = onb
+            .local(
+                elevation.cos() * azimuth.cos(),
+                elevation.cos() * azimuth.sin(),
+                elevation.sin(),
+            )
+            .unit_vector();
+        let child_center = center + &(direction.inject() * (radius + child_radius));
+        add_sphereflake_children(
+            spheres,
+            &child_center,
+            child_radius,
+            &direction,
+            levels - 1,
+            material,
+        );
+        spheres.push(Sphere {
+            center: child_center,
+            radius: child_radius,
+            material: material.clone(),
+        });
+    }
+}
+
+/// A stress scene of Haines' sphereflake: a mirror sphere with nine smaller ones on it,
+/// each of which has nine smaller ones on it in turn, down to `params.scale` levels,
+/// i.e., (9^(scale + 1) - 1) / 8 spheres, e.g. for benchmarking the BVH and the threads.
+pub fn sphereflake(aspect_ratio: Float, params: &SceneParams) -> Scene {
+    let material: SharedMaterial = Arc::new(Metal {
+        albedo: Attenuation {
+            r: 0.8,
+            g: 0.8,
+            b: 0.85,
+        },
+        fuzz: 0.05,
+    });
+    let center = Point3 {
+        x: 0.,
+        y: 1.,
+        z: 0.,
+    };
+    let up = Vec3 {
+        x: 0.,
+        y: 1.,
+        z: 0.,
+    }
+    .unit_vector();
+    let mut spheres = vec![];
+    add_sphereflake_children(&mut spheres, &center, 1., &up, params.scale, &material);
+    spheres.push(Sphere {
+        center,
+        radius: 1.,
+        material,
+    });
+
+    let mut world = HittableList::with_capacity(2);
+    world.push(stress_ground());
+    world.push(spheres.into_iter().collect::<Arena>());
+    let camera = looking_at(
+        Point3 {
+            x: 4.,
+            y: 3.5,
+            z: 5.5,
+        },
+        Point3 {
+            x: 0.,
+            y: 1.,
+            z: 0.,
+        },
+        40.,
+        aspect_ratio,
+    );
+    Scene {
+        camera,
+        world,
+        background: Background::Sky,
+    }
+}
+
+/// The side length of the cube filled by the spheres of `sphere_grid`.
+const SPHERE_GRID_SIZE: Float = 4.;
+
+/// A stress scene of the cube of 2^`params.scale` spheres along each side standing on the ground,
+/// whose materials are random as those of `book_cover` and generated from `params.seed`,
+/// e.g. for benchmarking the BVH and the threads.
+pub fn sphere_grid(aspect_ratio: Float, params: &SceneParams) -> Scene {
+    let mut rng = StdRng::seed_from_u64(params.seed);
+    let num_spheres = 1_usize << params.scale;
+    let cell_size = SPHERE_GRID_SIZE / num_spheres as Float;
+    let half = SPHERE_GRID_SIZE / 2.;
+    let mut spheres = Vec::with_capacity(num_spheres.pow(3));
+    for i in 0..num_spheres {
+        for j in 0..num_spheres {
+            for k in 0..num_spheres {
+                let at = |index: usize| (index as Float + 0.5) * cell_size;
+                spheres.push(Sphere {
+                    center: Point3 {
+                        x: at(i) - half,
+                        y: at(j),
+                        z: at(k) - half,
+                    },
+                    radius: 0.35 * cell_size,
+                    material: random_material(&mut rng),
+                });
+            }
+        }
+    }
+
+    let mut world = HittableList::with_capacity(2);
+    world.push(stress_ground());
+    world.push(spheres.into_iter().collect::<Arena>());
+    let camera = looking_at(
+        Point3 {
+            x: 7.,
+            y: 6.,
+            z: 9.,
+        },
+        Point3 {
+            x: 0.,
+            y: half,
+            z: 0.,
+        },
+        40.,
+        aspect_ratio,
+    );
+    Scene {
+        camera,
+        world,
+        background: Background::Sky,
+    }
+}
+
+fn quad(q: Point3, u: Vec3, v: Vec3, material: SharedMaterial) -> Box<dyn Hittable> {
+    Box::new(Quad { q, u, v, material })
+}
+
+/// Returns the six faces of the axis-aligned box with the opposite corners `a` and `b`.
+fn axis_aligned_box(a: &Point3, b: &Point3, material: SharedMaterial) -> Vec<Box<dyn Hittable>> {
+    let min = a.min(b);
+    let size = &a.max(b) - &min;
+    // The edges of the box along each axis:
+    let edge = |axis: usize| {
+        let mut unit = Vec3 {
+            x: 0.,
+            y: 0.,
+            z: 0.,
+        };
+        unit[axis] = 1.;
+        size.elementwise_product(&unit)
+    };
+    let (dx, dy, dz) = (edge(0), edge(1), edge(2));
+    vec![
+        quad(min.clone(), dx.clone(), dy.clone(), material.clone()), // back
+        quad(&min + &dz, dx.clone(), dy.clone(), material.clone()),  // front
+        quad(min.clone(), dz.clone(), dy.clone(), material.clone()), // left
+        quad(&min + &dx, dz.clone(), dy.clone(), material.clone()),  // right
+        quad(min.clone(), dx.clone(), dz.clone(), material.clone()), // bottom
+        quad(&min + &dy, dx, dz, material),                          // top
+    ]
+}
+
+fn lambertian(r: Float, g: Float, b: Float) -> SharedMaterial {
+    Arc::new(Lambertian {
+        albedo: Attenuation { r, g, b },
+    })
+}
+
+/// The Cornell box: a closed room with a red and a green wall lit by an area light on the ceiling.
+pub fn cornell_box(aspect_ratio: Float) -> Scene {
+    let size = 555.;
+    let point = |x: Float, y: Float, z: Float| Point3 { x, y, z };
+    let vec = |x: Float, y: Float, z: Float| Vec3 { x, y, z };
+
+    let red = lambertian(0.65, 0.05, 0.05);
+    let white = lambertian(0.73, 0.73, 0.73);
+    let green = lambertian(0.12, 0.45, 0.15);
+    let light: SharedMaterial = Arc::new(DiffuseLight {
+        emit: Color {
+            r: 15.,
+            g: 15.,
+            b: 15.,
+        },
+    });
+
+    let mut members: Vec<Box<dyn Hittable>> = vec![
+        quad(
+            point(size, 0., 0.),
+            vec(0., size, 0.),
+            vec(0., 0., size),
+            red,
+        ),
+        quad(
+            point(0., 0., 0.),
+            vec(0., size, 0.),
+            vec(0., 0., size),
+            green,
+        ),
+        quad(
+            point(343., size - 1., 332.),
+            vec(-130., 0., 0.),
+            vec(0., 0., -105.),
+            light,
+        ),
+        quad(
+            point(0., 0., 0.),
+            vec(size, 0., 0.),
+            vec(0., 0., size),
+            white.clone(),
+        ),
+        quad(
+            point(size, size, size),
+            vec(-size, 0., 0.),
+            vec(0., 0., -size),
+            white.clone(),
+        ),
+        quad(
+            point(0., 0., size),
+            vec(size, 0., 0.),
+            vec(0., size, 0.),
+            white.clone(),
+        ),
+    ];
+    members.extend(axis_aligned_box(
+        &point(265., 0., 295.),
+        &point(430., 330., 460.),
+        white.clone(),
+    ));
+    members.extend(axis_aligned_box(
+        &point(130., 0., 65.),
+        &point(295., 165., 230.),
+        white,
+    ));
+
+    let camera = looking_at(
+        point(278., 278., -800.),
+        point(278., 278., 0.),
+        40.,
+        aspect_ratio,
+    );
+    Scene {
+        camera,
+        world: HittableList { members },
+        background: Background::Solid(Color {
+            r: 0.,
+            g: 0.,
+            b: 0.,
+        }),
+    }
+}
+
+/// The side length of the light of the preset `soft-shadow`.
+pub const SOFT_SHADOW_LIGHT_SIZE: Float = 2.;
+
+/// A sphere of radius 1 on a plane under a square light `light_size` on a side at the height 3.
+/// The silhouette of the sphere seen from the light is halfway down to the plane, and thus the
+/// shadow has a penumbra as wide as the light, where the shadow rays reach only part of the light;
+/// how much of it they reach tells whether the direct lighting is right.
+/// The light emits the same power whatever its size, and thus only the shadow changes with it.
+pub fn soft_shadow(light_size: Float, aspect_ratio: Float) -> Scene {
+    let point = |x: Float, y: Float, z: Float| Point3 { x, y, z };
+    let vec = |x: Float, y: Float, z: Float| Vec3 { x, y, z };
+
+    let radiance = 50. / (light_size * light_size);
+    let light: SharedMaterial = Arc::new(DiffuseLight {
+        emit: Color {
+            r: radiance,
+            g: radiance,
+            b: radiance,
+        },
+    });
+    let half = light_size / 2.;
+    let members: Vec<Box<dyn Hittable>> = vec![
+        quad(
+            point(-10., 0., -10.),
+            vec(20., 0., 0.),
+            vec(0., 0., 20.),
+            lambertian(0.73, 0.73, 0.73),
+        ),
+        Box::new(Sphere {
+            center: point(0., 1., 0.),
+            radius: 1.,
+            material: lambertian(0.7, 0.3, 0.2),
+        }),
+        quad(
+            point(-half, 3., -half),
+            vec(light_size, 0., 0.),
+            vec(0., 0., light_size),
+            light,
+        ),
+    ];
+    Scene {
+        camera: looking_at(point(0., 4., 6.), point(0., 0.5, 0.), 45., aspect_ratio),
+        world: HittableList { members },
+        background: Background::Solid(Color {
+            r: 0.,
+            g: 0.,
+            b: 0.,
+        }),
+    }
+}
+
+/// The albedo of the sphere of `furnace`.
+pub const FURNACE_ALBEDO: Float = 0.5;
+
+/// The furnace test: a gray sphere in a uniformly white environment.
+/// Since the sphere is convex, every ray leaving it reaches the environment,
+/// so the sphere must look exactly as bright as its albedo if the material conserves energy;
+/// a darker or brighter sphere reveals a bug of the material.
+pub fn furnace(aspect_ratio: Float) -> Scene {
+    let center = Point3 {
+        x: 0.,
+        y: 0.,
+        z: 0.,
+    };
+    let sphere = Sphere {
+        center: center.clone(),
+        radius: 1.,
+        material: lambertian(FURNACE_ALBEDO, FURNACE_ALBEDO, FURNACE_ALBEDO),
+    };
+    Scene {
+        camera: framing_camera(center, 1., aspect_ratio),
+        world: HittableList {
+            members: vec![Box::new(sphere)],
+        },
+        background: Background::Solid(Color {
+            r: 1.,
+            g: 1.,
+            b: 1.,
+        }),
+    }
+}
+
+/// The white particles of clouds and smoke.
+fn white_particles() -> SharedMaterial {
+    Arc::new(Isotropic {
+        albedo: Attenuation {
+            r: 0.9,
+            g: 0.9,
+            b: 0.9,
+        },
+    })
+}
+
+/// A cloud floating over the ground under the sky, whose density is a few overlapping puffs
+/// thinning out towards their edges.
+pub fn cloud(aspect_ratio: Float) -> Scene {
+    // The centers and the radii of the puffs in the unit cube the grid spans.
+    let puffs: [(Float, Float, Float, Float); 5] = [
+        (0.5, 0.4, 0.5, 0.3),
+        (0.3, 0.35, 0.45, 0.22),
+        (0.7, 0.35, 0.55, 0.2),
+        (0.45, 0.6, 0.5, 0.2),
+        (0.6, 0.55, 0.4, 0.18),
+    ];
+    let grid = DensityGrid::from_fn([48, 32, 48], |x, y, z| {
+        let density: Float = puffs
+            .iter()
+            .map(|&(cx, cy, cz, radius)| {
+                let d2 = (x - cx).powi(2) + (y - cy).powi(2) + (z - cz).powi(2);
+                (1. - d2 / (radius * radius)).max(0.)
+            })
+            .sum();
+        density.min(1.)
+    });
+    let cloud = HeterogeneousMedium {
+        min: Point3 {
+            x: -1.5,
+            y: -0.5,
+            z: -2.5,
+        },
+        max: Point3 {
+            x: 1.5,
+            y: 1.5,
+            z: 0.5,
+        },
+        grid: Arc::new(grid),
+        extinction: 6.,
+        material: white_particles(),
+    };
+    let ground = Sphere {
+        center: Point3 {
+            x: 0.,
+            y: -1000.5,
+            z: -1.,
+        },
+        radius: 1000.,
+        material: lambertian(0.4, 0.5, 0.3),
+    };
+    let camera = looking_at(
+        Point3 {
+            x: 0.,
+            y: 0.8,
+            z: 3.,
+        },
+        Point3 {
+            x: 0.,
+            y: 0.3,
+            z: -1.,
+        },
+        50.,
+        aspect_ratio,
+    );
+    Scene {
+        camera,
+        world: HittableList {
+            members: vec![Box::new(ground), Box::new(cloud)],
+        },
+        background: Background::Sky,
+    }
+}
+
+/// Returns the value in [0, 1) assigned to the lattice point `(i, j, k)` by hashing it.
+fn lattice_value(i: i64, j: i64, k: i64) -> Float {
+    let mut h =
+        (i.wrapping_mul(73856093) ^ j.wrapping_mul(19349663) ^ k.wrapping_mul(83492791)) as u64;
+    h = h.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    h ^= h >> 29;
+    h = h.wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    h ^= h >> 32;
+    (h >> 11) as Float / (1_u64 << 53) as Float
+}
+
+/// Returns the fractal value noise at `(x, y, z)`, which is in [0, 1] and smooth in space,
+/// made of five octaves of the lattice values interpolated trilinearly.
+fn fractal_noise(x: Float, y: Float, z: Float) -> Float {
+    let smooth = |t: Float| t * t * (3. - 2. * t);
+    let octave = |x: Float, y: Float, z: Float| {
+        let (i, j, k) = (x.floor(), y.floor(), z.floor());
+        let (fx, fy, fz) = (smooth(x - i), smooth(y - j), smooth(z - k));
+        let (i, j, k) = (i as i64, j as i64, k as i64);
+        let lerp = |a: Float, b: Float, t: Float| a + (b - a) * t;
+        let along_x = |j: i64, k: i64| lerp(lattice_value(i, j, k), lattice_value(i + 1, j, k), fx);
+        let along_y = |k: i64| lerp(along_x(j, k), along_x(j + 1, k), fy);
+        lerp(along_y(k), along_y(k + 1), fz)
+    };
+    let mut sum = 0.;
+    let mut amplitude = 0.5;
+    let mut frequency = 1.;
+    for _ in 0..5 {
+        sum += amplitude * octave(x * frequency, y * frequency, z * frequency);
+        amplitude *= 0.5;
+        frequency *= 2.;
+    }
+    // The amplitudes add up to 1 - 1/32.
+    sum / (1. - 1. / 32.)
+}
+
+/// Returns the surface texture of the planet of the `planet` scene, whose oceans, lands, and ice caps
+/// are given by the noise on the unit sphere so that the texture has no seams.
+fn planet_texture() -> ImageTexture {
+    const SEA_LEVEL: Float = 0.56;
+    let (width, height) = (256, 128);
+    let mut texels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            // Inverts `sphere_uv`, where `v` goes from the bottom to the top.
+            let u = (x as Float + 0.5) / width as Float;
+            let v = 1. - (y as Float + 0.5) / height as Float;
+            let theta = v * consts::PI;
+            let phi = u * 2. * consts::PI;
+            let (nx, ny, nz) = (
+                -theta.sin() * phi.cos(),
+                -theta.cos(),
+                theta.sin() * phi.sin(),
+            );
+            let elevation = fractal_noise(3. * nx + 10., 3. * ny + 10., 3. * nz + 10.);
+            let texel = if ny.abs() > 0.85 + 0.1 * elevation {
+                Attenuation {
+                    r: 0.9,
+                    g: 0.9,
+                    b: 0.92,
+                }
+            } else if elevation < SEA_LEVEL {
+                // Deeper oceans are darker.
+                let depth = (SEA_LEVEL - elevation) / SEA_LEVEL;
+                Attenuation {
+                    r: 0.02,
+                    g: 0.08 - 0.04 * depth,
+                    b: 0.3 - 0.15 * depth,
+                }
+            } else {
+                // Higher lands are browner than the green lowlands.
+                let altitude = ((elevation - SEA_LEVEL) * 4.).min(1.);
+                Attenuation {
+                    r: 0.1 + 0.3 * altitude,
+                    g: 0.3 - 0.05 * altitude,
+                    b: 0.08 + 0.1 * altitude,
+                }
+            };
+            texels.push(texel);
+        }
+    }
+    ImageTexture::new(width, height, texels)
+}
+
+/// A planet with oceans and continents in space, wrapped in an atmosphere whose density falls off
+/// exponentially with altitude, and lit by a distant sun far brighter than the white of images,
+/// e.g. to be written in the PFM format.
+pub fn planet(aspect_ratio: Float) -> Scene {
+    let radius = 1.;
+    let planet = Sphere {
+        center: Point3 {
+            x: 0.,
+            y: 0.,
+            z: 0.,
+        },
+        radius,
+        material: Arc::new(TexturedLambertian {
+            texture: Arc::new(planet_texture()),
+        }),
+    };
+    // The grid spans the cube around the atmosphere, which is empty inside the planet.
+    let top = 1.15;
+    let scale_height = 0.03;
+    let grid = DensityGrid::from_fn([64, 64, 64], |x, y, z| {
+        let altitude =
+            ((2. * x - 1.).powi(2) + (2. * y - 1.).powi(2) + (2. * z - 1.).powi(2)).sqrt() * top
+                - radius;
+        if (0. ..top - radius).contains(&altitude) {
+            (-altitude / scale_height).exp()
+        } else {
+            0.
+        }
+    });
+    let atmosphere = HeterogeneousMedium {
+        min: Point3 {
+            x: -top,
+            y: -top,
+            z: -top,
+        },
+        max: Point3 {
+            x: top,
+            y: top,
+            z: top,
+        },
+        grid: Arc::new(grid),
+        extinction: 2.,
+        // Blue light is scattered more than red light as by air molecules.
+        material: Arc::new(Isotropic {
+            albedo: Attenuation {
+                r: 0.5,
+                g: 0.7,
+                b: 1.,
+            },
+        }),
+    };
+    let sun = Sphere {
+        center: Point3 {
+            x: -30.,
+            y: 10.,
+            z: 15.,
+        },
+        radius: 18.,
+        material: Arc::new(DiffuseLight {
+            emit: Color {
+                r: 8.,
+                g: 7.6,
+                b: 6.8,
+            },
+        }),
+    };
+    let camera = looking_at(
+        Point3 {
+            x: 0.8,
+            y: 0.4,
+            z: 3.2,
+        },
+        Point3 {
+            x: -0.3,
+            y: 0.1,
+            z: 0.,
+        },
+        45.,
+        aspect_ratio,
+    );
+    Scene {
+        camera,
+        world: HittableList {
+            members: vec![Box::new(planet), Box::new(atmosphere), Box::new(sun)],
+        },
+        background: Background::Solid(Color {
+            r: 0.,
+            g: 0.,
+            b: 0.,
+        }),
+    }
+}
+
+/// A density grid loaded from a file, e.g. a smoke simulation, made of white particles
+/// with the extinction coefficient `extinction` per unit density,
+/// viewed from the front so that it fits in the image.
+pub fn volume(file: VolumeFile, extinction: Float, aspect_ratio: Float) -> Scene {
+    let half_diagonal = (&file.max - &file.min) * 0.5;
+    let camera = framing_camera(
+        &file.min + &half_diagonal,
+        half_diagonal.length(),
+        aspect_ratio,
+    );
+    let medium = HeterogeneousMedium {
+        min: file.min,
+        max: file.max,
+        grid: Arc::new(file.grid),
+        extinction,
+        material: white_particles(),
+    };
+    Scene {
+        camera,
+        world: HittableList {
+            members: vec![Box::new(medium)],
+        },
+        background: Background::Sky,
+    }
+}
+
+/// Returns a camera looking at `center` along the -z axis
+/// from the distance at which the sphere of `radius` around `center` fits in the image.
+fn framing_camera(center: Point3, radius: Float, aspect_ratio: Float) -> Camera {
+    let vertical_fov_degree: Float = 40.;
+    let vertical_half_fov = (vertical_fov_degree / 2.).to_radians();
+    let horizontal_half_fov = (aspect_ratio * vertical_half_fov.tan()).atan();
+    let distance = 1.1 * radius / vertical_half_fov.min(horizontal_half_fov).sin();
+    looking_at(
+        &center
+            + Vec3 {
+                x: 0.,
+                y: 0.,
+                z: distance,
+            },
+        center,
+        vertical_fov_degree,
+        aspect_ratio,
+    )
+}
+
+/// A molecule viewed from the front so that all the atoms fit in the image,
+/// whose atoms and bonds are in the node and the layer named `molecule` to move or duplicate them as a unit.
+pub fn molecule(molecule: &Molecule, options: &MoleculeOptions, aspect_ratio: Float) -> Scene {
+    let camera = framing_camera(
+        molecule.centroid(),
+        molecule.bounding_radius(options.style),
+        aspect_ratio,
+    );
+    Scene {
+        camera,
+        world: HittableList {
+            members: vec![Box::new(
+                Node::new(
+                    "molecule",
+                    HittableList {
+                        members: molecule.to_hittables(options),
+                    },
+                )
+                .with_layer("molecule"),
+            )],
+        },
+        background: Background::Sky,
+    }
+}
+
+/// Meshes, e.g. imported from files, viewed from the front so that all of them fit in the image.
+pub fn meshes(meshes: Vec<Mesh>, aspect_ratio: Float) -> Scene {
+    let mut bounds: Option<(Point3, Point3)> = None;
+    for mesh in meshes.iter().filter(|mesh| !mesh.vertices().is_empty()) {
+        let (min, max) = mesh.bounds();
+        bounds = Some(match bounds {
+            None => (min.clone(), max.clone()),
+            Some((total_min, total_max)) => (total_min.min(min), total_max.max(max)),
+        });
+    }
+    let origin = Point3 {
+        x: 0.,
+        y: 0.,
+        z: 0.,
+    };
+    let (min, max) = bounds.unwrap_or((origin.clone(), origin));
+    let half_diagonal = (&max - &min) * 0.5;
+    let camera = framing_camera(&min + &half_diagonal, half_diagonal.length(), aspect_ratio);
+    Scene {
+        camera,
+        world: meshes.into_iter().collect(),
+        background: Background::Sky,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx::assert_approx_eq;
+
+    fn centers(spheres: &[Sphere]) -> Vec<(Point3, Float)> {
+        spheres
+            .iter()
+            .map(|sphere| (sphere.center.clone(), sphere.radius))
+            .collect()
+    }
+
+    #[test]
+    fn validate_tests() {
+        for name in SCENE_NAMES {
+            let scene = by_name(name, 16. / 9., &SceneParams::default()).unwrap();
+            assert_eq!(Ok(()), scene.validate(), "{}", name);
+        }
+
+        let mut scene = three_spheres(16. / 9.);
+        scene.world.members.push(Box::new(Sphere {
+            center: Point3 {
+                x: Float::NAN,
+                y: 0.,
+                z: 0.,
+            },
+            radius: -1.,
+            material: Arc::new(Glass {
+                eta: 0.5,
+                albedo: Attenuation {
+                    r: 1.,
+                    g: 1.,
+                    b: 1.,
+                },
+                priority: 0,
+            }),
+        }));
+        assert_eq!(
+            Err(vec![
+                "object #4: center must be finite, but is Point3 { x: NaN, y: 0.0, z: 0.0 }"
+                    .to_string(),
+                "object #4: radius must be positive, but is -1".to_string(),
+                "object #4: eta must be >= 1, but is 0.5".to_string(),
+            ]),
+            scene.validate()
+        );
+
+        let origin = Point3 {
+            x: 0.,
+            y: 0.,
+            z: 0.,
+        };
+        let empty_scene = Scene {
+            camera: looking_at(origin.clone(), origin, 40., 1.),
+            world: HittableList { members: vec![] },
+            background: Background::Sky,
+        };
+        let problems = empty_scene.validate().unwrap_err();
+        assert_eq!(2, problems.len());
+        assert!(problems[0].starts_with("camera: "));
+        assert_eq!("the scene has no objects", problems[1]);
+    }
+
+    #[test]
+    fn override_materials_tests() {
+        let mut scene = three_spheres(1.);
+        scene.world.members.push(Box::new(Sphere {
+            center: Point3 {
+                x: 0.,
+                y: 5.,
+                z: -1.,
+            },
+            radius: 0.5,
+            material: Arc::new(DiffuseLight {
+                emit: Color {
+                    r: 4.,
+                    g: 4.,
+                    b: 4.,
+                },
+            }),
+        }));
+        let num_members = scene.world.members.len();
+        let mut lights = vec![];
+        scene.world.collect_lights(&mut lights);
+        let num_lights = lights.len();
+        assert_eq!(Ok(MaterialOverride::Clay), "clay".parse());
+        assert!("chalk".parse::<MaterialOverride>().is_err());
+        scene.override_materials(MaterialOverride::Clay);
+        assert_eq!(num_members, scene.world.members.len());
+        let mut lights = vec![];
+        scene.world.collect_lights(&mut lights);
+        assert_eq!(num_lights, lights.len());
+        assert_eq!(Ok(()), scene.validate());
+
+        let material_at = |x: Float, y: Float| {
+            let ray = Ray {
+                origin: Point3 { x, y, z: 1. },
+                direction: Vec3 {
+                    x: 0.,
+                    y: 0.,
+                    z: -1.,
+                }
+                .unit_vector(),
+            };
+            let ray_t = Interval {
+                min: 0.,
+                max: Float::INFINITY,
+            };
+            let (_, material) = scene.world.hit(&ray, &ray_t).unwrap();
+            format!("{:?}", material)
+        };
+        // The metal sphere is clay, while the light still emits.
+        let clay = format!("{:?}", MaterialOverride::Clay.material());
+        assert_eq!(clay, material_at(1., 0.));
+        assert_eq!(clay, material_at(0., 0.));
+        assert!(material_at(0., 5.).starts_with("DiffuseLight"));
+    }
+
+    #[test]
+    fn raycast_tests() {
+        let scene = three_spheres(1.);
+        let ray = |origin: Point3, direction: Vec3| Ray {
+            origin,
+            direction: direction.unit_vector(),
+        };
+        // The metal sphere in front of the camera.
+        let hit = scene
+            .raycast(&ray(
+                Point3 {
+                    x: 1.,
+                    y: 0.,
+                    z: 1.,
+                },
+                Vec3 {
+                    x: 0.,
+                    y: 0.,
+                    z: -1.,
+                },
+            ))
+            .unwrap();
+        assert_eq!(1, hit.object);
+        assert_approx_eq!(1.5, hit.distance);
+        assert_approx_eq!(-0.5, hit.point.z);
+        assert_approx_eq!(1., hit.normal.inject().z);
+        assert!(format!("{:?}", hit.material).starts_with("Metal"));
+
+        // The glass sphere is in front of the ground below it.
+        let down = Vec3 {
+            x: 0.,
+            y: -1.,
+            z: 0.,
+        };
+        let above = |x| Point3 { x, y: 5., z: -1. };
+        let hit = scene.raycast(&ray(above(0.), down.clone())).unwrap();
+        assert_eq!(2, hit.object);
+        assert_approx_eq!(4.5, hit.distance);
+        assert_eq!(3, scene.raycast(&ray(above(3.), down)).unwrap().object);
+
+        let up = Vec3 {
+            x: 0.,
+            y: 1.,
+            z: 0.,
+        };
+        assert!(scene.raycast(&ray(above(0.), up)).is_none());
+    }
+
+    #[test]
+    fn enclosing_object_tests() {
+        let mut scene = three_spheres(1.);
+        let point = |x: Float, y: Float, z: Float| Point3 { x, y, z };
+        assert_eq!(Some(1), scene.enclosing_object(&point(1.1, 0.2, -1.)));
+        assert_eq!(None, scene.enclosing_object(&point(0.5, 0.2, -1.)));
+        // Below the ground, which is a huge sphere.
+        assert_eq!(Some(3), scene.enclosing_object(&point(0., -5., 0.)));
+
+        // Quads are two-sided, so no point is behind them.
+        scene.world.members = vec![Box::new(Quad {
+            q: point(-1., -1., -1.),
+            u: Vec3 {
+                x: 2.,
+                y: 0.,
+                z: 0.,
+            },
+            v: Vec3 {
+                x: 0.,
+                y: 2.,
+                z: 0.,
+            },
+            material: lambertian(0.5, 0.5, 0.5),
+        })];
+        assert_eq!(None, scene.enclosing_object(&point(0., 0., 0.)));
+    }
+
+    #[test]
+    fn stress_scenes_tests() {
+        let material = lambertian(0.5, 0.5, 0.5);
+        let center = Point3 {
+            x: 0.,
+            y: 1.,
+            z: 0.,
+        };
+        let up = Vec3 {
+            x: 0.,
+            y: 1.,
+            z: 0.,
+        }
+        .unit_vector();
+        let mut spheres = vec![];
+        add_sphereflake_children(&mut spheres, &center, 1., &up, 2, &material);
+        assert_eq!(9 + 81, spheres.len());
+        // The spheres touch their parents and the ground at most.
+        spheres.push(Sphere {
+            center,
+            radius: 1.,
+            material,
+        });
+        for (i, a) in spheres.iter().enumerate() {
+            assert!(a.center.y - a.radius > -1e-6, "{:?}", a.center);
+            for b in spheres[i + 1..].iter() {
+                let distance = (&a.center - &b.center).length();
+                assert!(distance > a.radius + b.radius - 1e-6, "{}", distance);
+            }
+        }
+
+        // The top of the grid is a layer of spheres right below the height of the cube.
+        let params = SceneParams {
+            scale: 2,
+            ..SceneParams::default()
+        };
+        let scene = sphere_grid(1., &params);
+        assert_eq!(Ok(()), scene.validate());
+        let ray = Ray {
+            origin: Point3 {
+                x: 0.5,
+                y: 10.,
+                z: 0.5,
+            },
+            direction: -&up,
+        };
+        let hit = scene.raycast(&ray).unwrap();
+        assert_eq!(1, hit.object);
+        assert_approx_eq!(SPHERE_GRID_SIZE - 0.5 + 0.35, hit.point.y);
+        assert!(sphereflake(1., &params).validate().is_ok());
+    }
+
+    #[test]
+    fn auto_frame_tests() {
+        // A huge sphere far off to the side of the camera, like a mesh in millimeters.
+        let sphere = |x: Float, radius: Float| Sphere {
+            center: Point3 {
+                x,
+                y: 0.,
+                z: -1000.,
+            },
+            radius,
+            material: lambertian(0.5, 0.5, 0.5),
+        };
+        let origin = Point3 {
+            x: 0.,
+            y: 0.,
+            z: 0.,
+        };
+        let look_at = Point3 {
+            x: 0.,
+            y: 0.,
+            z: -1.,
+        };
+        let mut scene = Scene {
+            camera: looking_at(origin.clone(), look_at.clone(), 40., 2.),
+            world: HittableList {
+                members: vec![Box::new(sphere(1000., 50.)), Box::new(sphere(1200., 50.))],
+            },
+            background: Background::Sky,
+        };
+        let (min, max) = scene.bounding_box().unwrap();
+        assert_approx_eq!(950., min.x);
+        assert_approx_eq!(1250., max.x);
+        assert_approx_eq!(-50., min.y);
+        assert_approx_eq!(-1050., min.z);
+        assert!(scene.camera.project(&min).unwrap().0 > 1.);
+
+        scene.auto_frame(0.1).unwrap();
+        let center = Point3 {
+            x: 1100.,
+            y: 0.,
+            z: -1000.,
+        };
+        let (u, v) = scene.camera.project(&center).unwrap();
+        assert_approx_eq!(0.5, u);
+        assert_approx_eq!(0.5, v);
+        assert_approx_eq!(2., scene.camera.aspect_ratio());
+        // Every corner of the box is in the image, and the view direction is kept.
+        for x in [min.x, max.x] {
+            for y in [min.y, max.y] {
+                for z in [min.z, max.z] {
+                    let (u, v) = scene.camera.project(&Point3 { x, y, z }).unwrap();
+                    assert!(
+                        (0. ..=1.).contains(&u) && (0. ..=1.).contains(&v),
+                        "{} {}",
+                        u,
+                        v
+                    );
+                }
+            }
+        }
+        let pose = scene.camera.pose();
+        assert_approx_eq!(pose.look_from.x, pose.look_at.x);
+        assert_approx_eq!(pose.look_from.y, pose.look_at.y);
+
+        // Orthographic cameras get their viewports resized to the objects.
+        let pose = CameraPose {
+            look_from: origin.clone(),
+            look_at,
+            vertical_fov_degree: 40.,
+        };
+        let projection = Projection::Orthographic {
+            viewport_height: 1.,
+        };
+        scene.camera = Camera::with_projection(pose, projection, 0.5);
+        scene.auto_frame(0.).unwrap();
+        let Projection::Orthographic { viewport_height } = scene.camera.projection() else {
+            panic!("the projection is changed");
+        };
+        // The width of half the height fits the sphere of the radius of the half diagonal.
+        let radius = (150. * 150. + 50. * 50. + 50. * 50. as Float).sqrt();
+        assert_approx_eq!(4. * radius, *viewport_height, 1e-3);
+
+        scene.world.members.clear();
+        assert_eq!(None, scene.bounding_box());
+        assert!(scene.auto_frame(0.1).is_err());
+    }
+
+    #[test]
+    fn scenes_are_thread_shareable() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Scene>();
+    }
+
+    #[test]
+    fn book_cover_is_deterministic() {
+        let params = SceneParams {
+            seed: 42,
+            density: 1.,
+            ..SceneParams::default()
+        };
+        let spheres1 = book_cover_small_spheres(&params);
+        let spheres2 = book_cover_small_spheres(&params);
+        assert_eq!(centers(&spheres1), centers(&spheres2));
+
+        let other_params = SceneParams {
+            seed: 43,
+            density: 1.,
+            ..SceneParams::default()
+        };
+        let spheres3 = book_cover_small_spheres(&other_params);
+        assert_ne!(centers(&spheres1), centers(&spheres3));
+    }
+
+    #[test]
+    fn book_cover_density_tests() {
+        let sparse = book_cover_small_spheres(&SceneParams {
+            seed: 0,
+            density: 1.,
+            ..SceneParams::default()
+        });
+        let dense = book_cover_small_spheres(&SceneParams {
+            seed: 0,
+            density: 4.,
+            ..SceneParams::default()
+        });
+        let empty = book_cover_small_spheres(&SceneParams {
+            seed: 0,
+            density: 0.,
+            ..SceneParams::default()
+        });
+        // There are 22 x 22 cells minus the ones overlapping the big spheres.
+        assert!(sparse.len() > 400 && sparse.len() <= 484);
+        assert!(dense.len() > 3 * sparse.len());
+        assert!(empty.is_empty());
+        for density in [-1., Float::NAN] {
+            let params = SceneParams {
+                density,
+                ..SceneParams::default()
+            };
+            assert!(book_cover_small_spheres(&params).is_empty());
+        }
+        // Huge densities are capped instead of building billions of spheres.
+        let num_cells = (2. * BOOK_COVER_HALF_EXTENT * MAX_SCENE_DENSITY.sqrt()).ceil() as usize;
+        let huge = book_cover_small_spheres(&SceneParams {
+            seed: 0,
+            density: Float::MAX,
+            ..SceneParams::default()
+        });
+        assert!(huge.len() <= num_cells * num_cells);
+
+        assert_eq!(Ok(0.), check_density(0.));
+        assert_eq!(Ok(MAX_SCENE_DENSITY), check_density(MAX_SCENE_DENSITY));
+        for density in [-1., 1e6, Float::INFINITY, Float::NAN] {
+            assert!(check_density(density).is_err());
+        }
+    }
+}
@@ -0,0 +1,895 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use try_ray_tracing::film::{Film, ImageFormat};
+
+use try_ray_tracing::geometry::Float;
+use try_ray_tracing::json::Json;
+use try_ray_tracing::render::{CancellationToken, RenderSettings, Renderer, MAX_NUM_PIXELS};
+use try_ray_tracing::scenes::{self, Scene, SceneParams};
+
+/// The maximum number of bytes of request bodies, which are small JSON documents.
+const MAX_BODY_SIZE: usize = 1 << 20;
+
+/// The maximum number of samples per pixel of requested images, which keeps a request from occupying the server
+/// for days.
+const MAX_NUM_SAMPLES_PER_PIXEL: usize = 1 << 16;
+
+/// The maximum number of jobs kept by servers, whether waiting, rendering, or finished.
+/// The oldest finished job is dropped for a new one, which is refused if none has finished,
+/// so that the queue and the finished images cannot use up the memory.
+const MAX_NUM_JOBS: usize = 32;
+
+/// The time after which idle connections are closed.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The interval of checking whether streamed jobs have progressed.
+const STREAM_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The maximum width of streamed previews unless `max_width` is given,
+/// which keeps them light enough for slow networks.
+const DEFAULT_STREAM_MAX_WIDTH: usize = 320;
+
+/// The page that shows the stream of the job `{id}` and its status.
+const VIEWER_HTML: &str = r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>try_ray_tracing</title></head>
+<body style="background: #222; color: #ddd; font-family: sans-serif">
+<p id="status">connecting...</p>
+<img src="/jobs/{id}/stream" style="width: 100%; max-width: 960px; image-rendering: pixelated">
+<script>
+  async function poll() {
+    const job = await (await fetch("/jobs/{id}")).json();
+    document.getElementById("status").textContent =
+      `job ${job.id}: ${job.status} (${Math.floor(100 * job.progress)}%)`;
+    if (job.status === "queued" || job.status === "rendering") setTimeout(poll, 1000);
+  }
+  poll();
+</script>
+</body>
+</html>
+"#;
+
+/// The type for the states of jobs, i.e., submitted renderings.
+enum JobStatus {
+    /// Waiting for the earlier jobs, holding what to render.
+    Queued(Box<(Scene, RenderSettings)>),
+    Rendering,
+    /// Finished, holding the PNG image.
+    Done(Vec<u8>),
+    Failed(String),
+    Cancelled,
+}
+impl JobStatus {
+    fn name(&self) -> &'static str {
+        match self {
+            JobStatus::Queued(_) => "queued",
+            JobStatus::Rendering => "rendering",
+            JobStatus::Done(_) => "done",
+            JobStatus::Failed(_) => "failed",
+            JobStatus::Cancelled => "cancelled",
+        }
+    }
+
+    fn is_over(&self) -> bool {
+        !matches!(self, JobStatus::Queued(_) | JobStatus::Rendering)
+    }
+}
+
+struct Job {
+    id: usize,
+    status: JobStatus,
+    num_finished_rows: usize,
+    num_total_rows: usize,
+    /// The image rendered so far, where the unfinished scan lines are black,
+    /// which exists only while the job is rendered since it is as large as the image.
+    preview: Option<Film>,
+    token: CancellationToken,
+}
+
+/// The type for the jobs kept by servers in the order of submission.
+#[derive(Default)]
+struct Jobs {
+    list: Vec<Job>,
+    /// The ID of the next job, which starts at 1 and is never reused even after the jobs are dropped.
+    next_id: usize,
+}
+impl Jobs {
+    fn get(&self, id: usize) -> Option<&Job> {
+        self.list.iter().find(|job| job.id == id)
+    }
+
+    fn get_mut(&mut self, id: usize) -> Option<&mut Job> {
+        self.list.iter_mut().find(|job| job.id == id)
+    }
+}
+
+/// The type for render servers, which render the submitted jobs one at a time in the order of submission
+/// so that each job gets all the threads.
+pub struct Server {
+    jobs: Mutex<Jobs>,
+    queue: mpsc::Sender<usize>,
+    /// The settings for the parameters that jobs omit.
+    default_settings: RenderSettings,
+}
+impl Server {
+    /// Creates a server and spawns the thread that renders its jobs.
+    pub fn start(default_settings: RenderSettings) -> Arc<Self> {
+        let (queue, receiver) = mpsc::channel();
+        let server = Arc::new(Server {
+            jobs: Mutex::new(Jobs::default()),
+            queue,
+            default_settings,
+        });
+        let runner = Arc::clone(&server);
+        thread::spawn(move || {
+            for id in receiver {
+                runner.run_job(id);
+            }
+        });
+        server
+    }
+
+    /// Locks the jobs, or returns the response of 500 if the runner has panicked while holding the lock.
+    fn lock_jobs(&self) -> Result<MutexGuard<'_, Jobs>, Response> {
+        self.jobs
+            .lock()
+            .map_err(|_| Response::error(500, "the render thread has crashed"))
+    }
+
+    /// Renders the job, or gives up if the lock is poisoned since the jobs may be inconsistent.
+    fn run_job(&self, id: usize) {
+        let (scene, settings, token) = {
+            let Ok(mut jobs) = self.jobs.lock() else {
+                return;
+            };
+            // Cancelled and then dropped for newer jobs while waiting.
+            let Some(job) = jobs.get_mut(id) else {
+                return;
+            };
+            match std::mem::replace(&mut job.status, JobStatus::Rendering) {
+                JobStatus::Queued(pending) => {
+                    let (scene, settings) = *pending;
+                    job.preview = Some(Film::new(settings.image_width, settings.image_height));
+                    (scene, settings, job.token.clone())
+                }
+                // Cancelled while waiting.
+                status => {
+                    job.status = status;
+                    return;
+                }
+            }
+        };
+        let renderer = Renderer::new(settings).with_cancellation(token.clone());
+        let film = renderer.render_with_progress(&scene, |tile| {
+            if let Some(job) = self
+                .jobs
+                .lock()
+                .ok()
+                .as_mut()
+                .and_then(|jobs| jobs.get_mut(id))
+            {
+                job.num_finished_rows = tile.num_finished;
+                if let Some(preview) = &mut job.preview {
+                    preview.add_tile(tile);
+                }
+            }
+        });
+        let status = if token.is_cancelled() {
+            JobStatus::Cancelled
+        } else {
+            let mut png = vec![];
+            match film.write_png(&mut png, ImageFormat::Png.default_transfer()) {
+                Ok(()) => JobStatus::Done(png),
+                Err(e) => JobStatus::Failed(e.to_string()),
+            }
+        };
+        if let Some(job) = self
+            .jobs
+            .lock()
+            .ok()
+            .as_mut()
+            .and_then(|jobs| jobs.get_mut(id))
+        {
+            job.status = status;
+            job.preview = None;
+        }
+    }
+
+    /// Handles an HTTP request. The API is:
+    /// - `POST /jobs` with a job in JSON (see `parse_job`), which returns the ID of the job
+    ///   or 503 if `MAX_NUM_JOBS` jobs are waiting or rendering,
+    /// - `GET /jobs/<id>`, which returns the status and the progress in [0, 1],
+    /// - `GET /jobs/<id>/image.png`, which returns the image once the job is done,
+    /// - `GET /jobs/<id>/stream?max_width=<width>`, which streams the image while it is rendered
+    ///   (see `stream`),
+    /// - `GET /jobs/<id>/viewer`, which is a web page showing the stream,
+    /// - `DELETE /jobs/<id>`, which cancels the job,
+    /// - and `GET /scenes`, which returns the names of the preset scenes.
+    ///
+    /// Every request but `GET /scenes` fails with 500 once the thread rendering the jobs has crashed.
+    pub fn respond(&self, request: &Request) -> Response {
+        let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+        match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["scenes"]) => Response::json(
+                200,
+                &Json::Array(
+                    scenes::SCENE_NAMES
+                        .iter()
+                        .map(|name| Json::String(name.to_string()))
+                        .collect(),
+                ),
+            ),
+            ("POST", ["jobs"]) => self.submit(&request.body),
+            (_, ["scenes"]) | (_, ["jobs"]) => Response::error(405, "method not allowed"),
+            (method, ["jobs", id, rest @ ..]) => {
+                let Ok(id) = id.parse::<usize>() else {
+                    return Response::error(404, "no such job");
+                };
+                let mut jobs = match self.lock_jobs() {
+                    Ok(jobs) => jobs,
+                    Err(response) => return response,
+                };
+                let Some(job) = jobs.get_mut(id) else {
+                    return Response::error(404, "no such job");
+                };
+                match (method, rest) {
+                    ("GET", []) => Response::json(200, &job_json(job)),
+                    ("DELETE", []) => {
+                        job.token.cancel();
+                        if let JobStatus::Queued(_) = job.status {
+                            job.status = JobStatus::Cancelled;
+                        }
+                        Response::json(200, &job_json(job))
+                    }
+                    ("GET", ["image.png"]) => match &job.status {
+                        JobStatus::Done(png) => Response {
+                            status: 200,
+                            content_type: "image/png",
+                            body: png.clone(),
+                        },
+                        status => Response::error(409, &format!("the job is {}", status.name())),
+                    },
+                    ("GET", ["viewer"]) => Response {
+                        status: 200,
+                        content_type: "text/html; charset=utf-8",
+                        body: VIEWER_HTML.replace("{id}", &id.to_string()).into_bytes(),
+                    },
+                    (_, []) | (_, ["image.png"]) | (_, ["viewer"]) => {
+                        Response::error(405, "method not allowed")
+                    }
+                    _ => Response::error(404, "not found"),
+                }
+            }
+            _ => Response::error(404, "not found"),
+        }
+    }
+
+    fn submit(&self, body: &[u8]) -> Response {
+        let parsed = std::str::from_utf8(body)
+            .map_err(|e| e.to_string())
+            .and_then(Json::parse)
+            .and_then(|json| parse_job(&json, &self.default_settings));
+        let (scene, settings) = match parsed {
+            Ok(job) => job,
+            Err(message) => return Response::error(400, &message),
+        };
+        let mut jobs = match self.lock_jobs() {
+            Ok(jobs) => jobs,
+            Err(response) => return response,
+        };
+        if jobs.list.len() >= MAX_NUM_JOBS {
+            let Some(oldest_finished) = jobs.list.iter().position(|job| job.status.is_over())
+            else {
+                return Response::error(503, "too many jobs are waiting; try again later");
+            };
+            jobs.list.remove(oldest_finished);
+        }
+        jobs.next_id += 1;
+        let id = jobs.next_id;
+        jobs.list.push(Job {
+            id,
+            num_finished_rows: 0,
+            num_total_rows: settings.image_height,
+            preview: None,
+            status: JobStatus::Queued(Box::new((scene, settings))),
+            token: CancellationToken::new(),
+        });
+        let job = jobs.list.last_mut().unwrap();
+        // Sending fails only if the runner has crashed and dropped the receiver.
+        if self.queue.send(id).is_err() {
+            job.status = JobStatus::Failed("the render thread has crashed".to_string());
+            return Response::error(500, "the render thread has crashed");
+        }
+        Response::json(201, &job_json(job))
+    }
+}
+
+impl Server {
+    /// Returns the ID of the job if `request` asks for its stream.
+    fn stream_target(&self, request: &Request) -> Option<usize> {
+        let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+        let ["jobs", id, "stream"] = segments.as_slice() else {
+            return None;
+        };
+        let id = id.parse::<usize>().ok()?;
+        // A poisoned lock is left to `respond`, which returns 500.
+        let is_job = self.jobs.lock().ok()?.get(id).is_some();
+        (request.method == "GET" && is_job).then_some(id)
+    }
+
+    /// Streams the image of the job as PNG images downsampled to at most `max_width` pixels wide
+    /// in `multipart/x-mixed-replace`, which browsers show as a live image,
+    /// sending a new one whenever scan lines are finished and then the final image if the job is done.
+    fn stream(&self, id: usize, max_width: usize, out: &mut impl Write) -> io::Result<()> {
+        write!(
+            out,
+            "HTTP/1.1 200 OK\r\nContent-Type: multipart/x-mixed-replace; boundary=frame\r\n\
+             Cache-Control: no-cache\r\nConnection: close\r\n\r\n"
+        )?;
+        let mut last_sent = None;
+        loop {
+            let (preview, final_png, is_over) = {
+                let jobs = self
+                    .jobs
+                    .lock()
+                    .map_err(|_| io::Error::other("the render thread has crashed"))?;
+                let job = jobs
+                    .get(id)
+                    .ok_or_else(|| io::Error::other("the job has been dropped"))?;
+                let is_over = job.status.is_over();
+                let state = (job.num_finished_rows, is_over);
+                if last_sent == Some(state) {
+                    (None, None, is_over)
+                } else {
+                    last_sent = Some(state);
+                    let preview = job.preview.as_ref().map(|preview| {
+                        preview.downsample(preview.width.div_ceil(max_width.max(1)))
+                    });
+                    // The preview is dropped once the image is encoded, which is sent as it is.
+                    let final_png = match &job.status {
+                        JobStatus::Done(png) => Some(png.clone()),
+                        _ => None,
+                    };
+                    (preview, final_png, is_over)
+                }
+            };
+            let png = match (preview, final_png) {
+                (_, Some(png)) => Some(png),
+                (Some(preview), None) => {
+                    let mut png = vec![];
+                    preview
+                        .write_png(&mut png, ImageFormat::Png.default_transfer())
+                        .map_err(io::Error::other)?;
+                    Some(png)
+                }
+                (None, None) => None,
+            };
+            if let Some(png) = png {
+                write!(
+                    out,
+                    "--frame\r\nContent-Type: image/png\r\nContent-Length: {}\r\n\r\n",
+                    png.len()
+                )?;
+                out.write_all(&png)?;
+                write!(out, "\r\n")?;
+                out.flush()?;
+            }
+            if is_over {
+                write!(out, "--frame--\r\n")?;
+                return out.flush();
+            }
+            thread::sleep(STREAM_INTERVAL);
+        }
+    }
+}
+
+fn job_json(job: &Job) -> Json {
+    let progress = job.num_finished_rows as f64 / job.num_total_rows as f64;
+    let mut members = vec![
+        ("id".to_string(), Json::Number(job.id as f64)),
+        (
+            "status".to_string(),
+            Json::String(job.status.name().to_string()),
+        ),
+        ("progress".to_string(), Json::Number(progress)),
+    ];
+    if let JobStatus::Failed(message) = &job.status {
+        members.push(("error".to_string(), Json::String(message.clone())));
+    }
+    Json::Object(members)
+}
+
+/// Builds the scene and the settings of a job of the form
+/// `{"scene": <name>, "seed": <integer>, "density": <number>, "scale": <integer>, "width": <integer>, "height": <integer>,
+/// "samples": <integer>, "max_depth": <integer>}`, where everything but `scene` is optional.
+/// `seed` is used both for the scene and for rendering, which makes the image reproducible.
+fn parse_job(
+    json: &Json,
+    default_settings: &RenderSettings,
+) -> Result<(Scene, RenderSettings), String> {
+    let Json::Object(members) = json else {
+        return Err("a job must be a JSON object".to_string());
+    };
+    let mut scene_name = None;
+    let mut params = SceneParams::default();
+    let mut settings = default_settings.clone();
+    let mut height = None;
+    for (key, value) in members.iter() {
+        let integer = || {
+            value
+                .as_u64()
+                .ok_or_else(|| format!("{} must be a non-negative integer", key))
+        };
+        let size = || {
+            integer()?
+                .try_into()
+                .map_err(|_| format!("{} is too large", key))
+        };
+        match key.as_str() {
+            "scene" => {
+                let name = value.as_str().ok_or("scene must be a string")?;
+                scene_name = Some(name.to_string());
+            }
+            "seed" => {
+                params.seed = integer()?;
+                settings.seed = Some(params.seed);
+            }
+            "density" => {
+                let density = value.as_f64().ok_or("density must be a number")?;
+                params.density = scenes::check_density(density as Float)?;
+            }
+            "scale" => {
+                params.scale = integer()?
+                    .try_into()
+                    .ok()
+                    .filter(|&scale| scale <= scenes::MAX_SCENE_SCALE)
+                    .ok_or_else(|| format!("scale must be at most {}", scenes::MAX_SCENE_SCALE))?;
+            }
+            "width" => settings.image_width = size()?,
+            "height" => height = Some(size()?),
+            "samples" => {
+                settings.num_samples_per_pixel = size()?;
+                if settings.num_samples_per_pixel > MAX_NUM_SAMPLES_PER_PIXEL {
+                    return Err(format!(
+                        "samples must be at most {}",
+                        MAX_NUM_SAMPLES_PER_PIXEL
+                    ));
+                }
+            }
+            "max_depth" => {
+                settings.max_diffusion_depth = integer()?
+                    .try_into()
+                    .map_err(|_| "max_depth is too large".to_string())?;
+            }
+            _ => return Err(format!("unknown key: {}", key)),
+        }
+    }
+    // Keeps the default aspect ratio if only the width is given.
+    settings.image_height = height.unwrap_or_else(|| {
+        default_settings.image_height * settings.image_width / default_settings.image_width.max(1)
+    });
+    if settings.image_width == 0 || settings.image_height < 2 {
+        return Err("the image must be at least 1 pixel wide and 2 pixels high".to_string());
+    }
+    if settings.image_width.saturating_mul(settings.image_height) > MAX_NUM_PIXELS {
+        return Err(format!(
+            "the image must have at most {} pixels",
+            MAX_NUM_PIXELS
+        ));
+    }
+    if settings.num_samples_per_pixel == 0 {
+        return Err("samples must be positive".to_string());
+    }
+
+    let scene_name = scene_name.ok_or("scene is required")?;
+    let aspect_ratio = settings.image_width as Float / settings.image_height as Float;
+    let scene = scenes::by_name(&scene_name, aspect_ratio, &params)
+        .ok_or_else(|| format!("unknown scene: {}", scene_name))?;
+    scene
+        .validate()
+        .map_err(|problems| format!("invalid scene: {}", problems.join("; ")))?;
+    Ok((scene, settings))
+}
+
+/// The type for HTTP requests, which are read entirely before being handled.
+#[derive(Debug, PartialEq)]
+pub struct Request {
+    pub method: String,
+    /// The path without the query string.
+    pub path: String,
+    /// The query string without `?`.
+    pub query: String,
+    pub body: Vec<u8>,
+}
+impl Request {
+    /// Returns the value of the parameter `name` in the query string.
+    fn query_param(&self, name: &str) -> Option<&str> {
+        self.query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+}
+
+/// Reads an HTTP/1.x request; returns `Ok(None)` if the connection is closed before the request.
+pub fn read_request(reader: &mut impl BufRead) -> Result<Option<Request>, Response> {
+    let mut line = String::new();
+    let bad_request = |message: &str| Response::error(400, message);
+    let read_line = |reader: &mut dyn BufRead, line: &mut String| {
+        line.clear();
+        // Bounds the line so that a client cannot make the server buffer without limit.
+        reader
+            .take(MAX_BODY_SIZE as u64)
+            .read_line(line)
+            .map_err(|e| bad_request(&e.to_string()))
+    };
+    if read_line(reader, &mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target), Some(_version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(bad_request("malformed request line"));
+    };
+    let method = method.to_string();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let (path, query) = (path.to_string(), query.to_string());
+
+    let mut content_length = 0;
+    loop {
+        if read_line(reader, &mut line)? == 0 {
+            return Err(bad_request("unexpected end of the headers"));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            return Err(bad_request("malformed header"));
+        };
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value
+                .trim()
+                .parse()
+                .map_err(|_| bad_request("invalid Content-Length"))?;
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            return Err(Response::error(411, "Content-Length is required"));
+        }
+    }
+    if content_length > MAX_BODY_SIZE {
+        return Err(Response::error(413, "the request body is too large"));
+    }
+    let mut body = vec![0; content_length];
+    reader
+        .read_exact(&mut body)
+        .map_err(|e| bad_request(&e.to_string()))?;
+    Ok(Some(Request {
+        method,
+        path,
+        query,
+        body,
+    }))
+}
+
+/// The type for HTTP responses.
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+impl Response {
+    fn json(status: u16, json: &Json) -> Self {
+        Response {
+            status,
+            content_type: "application/json",
+            body: json.to_string().into_bytes(),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        let json = Json::Object(vec![(
+            "error".to_string(),
+            Json::String(message.to_string()),
+        )]);
+        Self::json(status, &json)
+    }
+
+    fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        let reason = match self.status {
+            200 => "OK",
+            201 => "Created",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            409 => "Conflict",
+            411 => "Length Required",
+            413 => "Content Too Large",
+            500 => "Internal Server Error",
+            503 => "Service Unavailable",
+            _ => "",
+        };
+        write!(
+            out,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            self.status,
+            reason,
+            self.content_type,
+            self.body.len()
+        )?;
+        write!(out, "\r\n")?;
+        out.write_all(&self.body)?;
+        out.flush()
+    }
+}
+
+fn handle_connection(server: &Server, stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let response = match read_request(&mut reader) {
+        Ok(Some(request)) => match server.stream_target(&request) {
+            Some(id) => {
+                let max_width = match request.query_param("max_width") {
+                    Some(value) => match value.parse() {
+                        Ok(max_width) if max_width > 0 => max_width,
+                        _ => return Response::error(400, "invalid max_width").write(&mut &stream),
+                    },
+                    None => DEFAULT_STREAM_MAX_WIDTH,
+                };
+                return server.stream(id, max_width, &mut &stream);
+            }
+            None => server.respond(&request),
+        },
+        Ok(None) => return Ok(()),
+        Err(response) => response,
+    };
+    response.write(&mut &stream)
+}
+
+/// Serves the HTTP API described at `Server::respond` on `address` until the process is killed.
+/// Each connection handles a single request.
+pub fn serve(address: &str, default_settings: RenderSettings) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    eprintln!("Listening on http://{}", listener.local_addr()?);
+    let server = Server::start(default_settings);
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("cannot accept a connection: {}", e);
+                continue;
+            }
+        };
+        let server = Arc::clone(&server);
+        thread::spawn(move || {
+            if let Err(e) = handle_connection(&server, stream) {
+                eprintln!("connection error: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use try_ray_tracing::film::Filter;
+
+    fn request(method: &str, path: &str, body: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: String::new(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    fn response_json(response: &Response) -> Json {
+        Json::parse(std::str::from_utf8(&response.body).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn read_request_tests() {
+        let text = "POST /jobs?verbose HTTP/1.1\r\nHost: x\r\ncontent-length: 2\r\n\r\n{}";
+        assert_eq!(
+            Some(Request {
+                query: "verbose".to_string(),
+                ..request("POST", "/jobs", "{}")
+            }),
+            read_request(&mut text.as_bytes()).unwrap()
+        );
+        let text = "GET /jobs/1/stream?a=1&max_width=64 HTTP/1.1\r\n\r\n";
+        let stream_request = read_request(&mut text.as_bytes()).unwrap().unwrap();
+        assert_eq!(Some("64"), stream_request.query_param("max_width"));
+        assert_eq!(None, stream_request.query_param("b"));
+        assert_eq!(None, read_request(&mut "".as_bytes()).unwrap());
+        let too_large = format!("POST /jobs HTTP/1.1\r\nContent-Length: {}\r\n\r\n", 1 << 30);
+        assert_eq!(
+            413,
+            read_request(&mut too_large.as_bytes()).unwrap_err().status
+        );
+        assert_eq!(
+            400,
+            read_request(&mut "GET\r\n\r\n".as_bytes())
+                .unwrap_err()
+                .status
+        );
+    }
+
+    fn test_settings() -> RenderSettings {
+        RenderSettings {
+            image_width: 16,
+            image_height: 9,
+            num_samples_per_pixel: 1,
+            max_diffusion_depth: 5,
+            roulette: vec![],
+            num_threads: 1,
+            low_priority: false,
+            check_samples: false,
+            collect_stats: false,
+            path_guiding: false,
+            adaptive_samples: 0,
+            seed: None,
+            filter: Filter::Box,
+            filter_sampling: false,
+            show_progress: true,
+        }
+    }
+
+    #[test]
+    fn server_tests() {
+        let server = Server::start(test_settings());
+        assert_eq!(
+            400,
+            server
+                .respond(&request("POST", "/jobs", "{\"scene\": \"nothing\"}"))
+                .status
+        );
+        assert_eq!(
+            400,
+            server
+                .respond(&request("POST", "/jobs", "{\"scene\": 1}"))
+                .status
+        );
+        assert_eq!(
+            400,
+            server
+                .respond(&request(
+                    "POST",
+                    "/jobs",
+                    "{\"scene\": \"methanol\", \"witdh\": 8}"
+                ))
+                .status
+        );
+        // Too dense scenes would take the server down while building them.
+        assert_eq!(
+            400,
+            server
+                .respond(&request(
+                    "POST",
+                    "/jobs",
+                    "{\"scene\": \"book-cover\", \"density\": 1e300}"
+                ))
+                .status
+        );
+        assert_eq!(
+            400,
+            server
+                .respond(&request(
+                    "POST",
+                    "/jobs",
+                    "{\"scene\": \"three-spheres\", \"samples\": 1000000000}"
+                ))
+                .status
+        );
+        assert_eq!(404, server.respond(&request("GET", "/jobs/1", "")).status);
+
+        let submitted = server.respond(&request(
+            "POST",
+            "/jobs",
+            "{\"scene\": \"three-spheres\", \"width\": 8, \"seed\": 1}",
+        ));
+        assert_eq!(201, submitted.status);
+        assert_eq!(
+            Some(1),
+            response_json(&submitted).get("id").and_then(Json::as_u64)
+        );
+        loop {
+            let status = response_json(&server.respond(&request("GET", "/jobs/1", "")));
+            match status.get("status").and_then(Json::as_str) {
+                Some("done") => break,
+                Some("queued" | "rendering") => thread::sleep(Duration::from_millis(10)),
+                _ => panic!("unexpected status: {}", status),
+            }
+        }
+        let image = server.respond(&request("GET", "/jobs/1/image.png", ""));
+        assert_eq!(200, image.status);
+        assert!(image.body.starts_with(b"\x89PNG"));
+        // The preview is dropped once the image is encoded.
+        assert!(server
+            .jobs
+            .lock()
+            .unwrap()
+            .get(1)
+            .unwrap()
+            .preview
+            .is_none());
+        assert_eq!(405, server.respond(&request("PUT", "/jobs/1", "")).status);
+
+        // The stream of a finished job is its final image.
+        let stream_request = request("GET", "/jobs/1/stream", "");
+        assert_eq!(Some(1), server.stream_target(&stream_request));
+        assert_eq!(
+            None,
+            server.stream_target(&request("GET", "/jobs/2/stream", ""))
+        );
+        let mut out = vec![];
+        server.stream(1, 4, &mut out).unwrap();
+        let text = String::from_utf8_lossy(&out);
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(1, text.matches("Content-Type: image/png").count());
+        assert!(text.ends_with("--frame--\r\n"));
+        assert_eq!(404, server.respond(&request("GET", "/jobs/0", "")).status);
+
+        // A panic while holding the lock, e.g. in the runner, turns the requests into 500 instead of panics.
+        let poisoner = Arc::clone(&server);
+        let _ = thread::spawn(move || {
+            let _jobs = poisoner.jobs.lock().unwrap();
+            panic!("poisoning the lock");
+        })
+        .join();
+        assert_eq!(500, server.respond(&request("GET", "/jobs/1", "")).status);
+        assert_eq!(
+            500,
+            server
+                .respond(&request("POST", "/jobs", "{\"scene\": \"three-spheres\"}"))
+                .status
+        );
+        assert_eq!(None, server.stream_target(&stream_request));
+    }
+
+    #[test]
+    fn max_num_jobs_tests() {
+        // A server without the runner, whose jobs stay queued.
+        let (queue, _receiver) = mpsc::channel();
+        let server = Server {
+            jobs: Mutex::new(Jobs::default()),
+            queue,
+            default_settings: test_settings(),
+        };
+        let job = "{\"scene\": \"three-spheres\"}";
+        for _ in 0..MAX_NUM_JOBS {
+            assert_eq!(201, server.respond(&request("POST", "/jobs", job)).status);
+        }
+        // Queued jobs have no preview yet.
+        assert!(server
+            .jobs
+            .lock()
+            .unwrap()
+            .get(1)
+            .unwrap()
+            .preview
+            .is_none());
+        assert_eq!(503, server.respond(&request("POST", "/jobs", job)).status);
+
+        // A finished job is dropped for a new one, which gets a new ID.
+        assert_eq!(
+            200,
+            server.respond(&request("DELETE", "/jobs/2", "")).status
+        );
+        let submitted = server.respond(&request("POST", "/jobs", job));
+        assert_eq!(201, submitted.status);
+        assert_eq!(
+            Some(MAX_NUM_JOBS as u64 + 1),
+            response_json(&submitted).get("id").and_then(Json::as_u64)
+        );
+        assert_eq!(404, server.respond(&request("GET", "/jobs/2", "")).status);
+        assert_eq!(200, server.respond(&request("GET", "/jobs/1", "")).status);
+        assert_eq!(503, server.respond(&request("POST", "/jobs", job)).status);
+    }
+}
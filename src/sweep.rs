@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, PoisonError, RwLock};
+
+use crate::film::Film;
+use crate::geometry::{Float, Point3, Ray};
+use crate::hittable_object::{HitRecord, Hittable, Mesh, SharedMaterial};
+use crate::interval::Interval;
+use crate::light::Light;
+
+/// The parameters of materials that contact sheets vary.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MaterialParameter {
+    /// The `fuzz` of metals, in [0, 1].
+    Fuzz,
+    /// The refractive index `eta` of glasses, at least 1.
+    Eta,
+}
+impl MaterialParameter {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MaterialParameter::Fuzz => "fuzz",
+            MaterialParameter::Eta => "eta",
+        }
+    }
+
+    fn validate(&self, value: Float) -> Result<(), String> {
+        let is_valid = match self {
+            MaterialParameter::Fuzz => (0. ..=1.).contains(&value),
+            MaterialParameter::Eta => value >= 1.,
+        };
+        if is_valid {
+            Ok(())
+        } else {
+            let range = match self {
+                MaterialParameter::Fuzz => "in [0, 1]",
+                MaterialParameter::Eta => "at least 1",
+            };
+            Err(format!(
+                "{} must be {}, but is {}",
+                self.name(),
+                range,
+                value
+            ))
+        }
+    }
+}
+impl FromStr for MaterialParameter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fuzz" => Ok(MaterialParameter::Fuzz),
+            "eta" => Ok(MaterialParameter::Eta),
+            _ => Err(format!(
+                "unknown material parameter: {} (expected fuzz or eta)",
+                s
+            )),
+        }
+    }
+}
+
+/// The values of a parameter along an axis of contact sheets, written as `<parameter>=<from>..<to>:<steps>`,
+/// e.g. `fuzz=0..1:5` for 0, 0.25, 0.5, 0.75, and 1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SweepAxis {
+    pub parameter: MaterialParameter,
+    pub from: Float,
+    pub to: Float,
+    pub steps: usize,
+}
+impl SweepAxis {
+    /// Returns the values evenly spaced from `from` to `to`, both inclusive, or `from` alone for a single step.
+    pub fn values(&self) -> Vec<Float> {
+        if self.steps == 1 {
+            return vec![self.from];
+        }
+        (0..self.steps)
+            .map(|i| self.from + (self.to - self.from) * (i as Float) / ((self.steps - 1) as Float))
+            .collect()
+    }
+}
+impl FromStr for SweepAxis {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid sweep (expected <parameter>=<from>..<to>:<steps>): {}",
+                s
+            )
+        };
+        let (parameter, range) = s.split_once('=').ok_or_else(invalid)?;
+        let (range, steps) = range.split_once(':').ok_or_else(invalid)?;
+        let (from, to) = range.split_once("..").ok_or_else(invalid)?;
+        let axis = SweepAxis {
+            parameter: parameter.parse()?,
+            from: from.parse().map_err(|_| invalid())?,
+            to: to.parse().map_err(|_| invalid())?,
+            steps: steps.parse().map_err(|_| invalid())?,
+        };
+        if axis.steps == 0 {
+            return Err(format!("a sweep requires at least one step: {}", s));
+        }
+        axis.parameter.validate(axis.from)?;
+        axis.parameter.validate(axis.to)?;
+        Ok(axis)
+    }
+}
+
+/// Returns the values of the parameters of each cell of the contact sheet of `axes`,
+/// row by row from the top left, where the first axis runs along the columns
+/// and the second, if any, along the rows.
+pub fn sweep_cells(axes: &[SweepAxis]) -> Vec<Vec<(MaterialParameter, Float)>> {
+    let mut cells = vec![vec![]];
+    // The cells are built from the last axis so that the first one varies the fastest.
+    for axis in axes.iter().rev() {
+        cells = cells
+            .into_iter()
+            .flat_map(|cell| {
+                axis.values().into_iter().map(move |value| {
+                    let mut cell = cell.clone();
+                    cell.insert(0, (axis.parameter, value));
+                    cell
+                })
+            })
+            .collect();
+    }
+    cells
+}
+
+/// The type for the materials of a cell of contact sheets, which replace those with
+/// the parameters of the cell, e.g. metals with their fuzz, and keep the others as they are.
+/// The replaced materials are made only once for each material of the scene.
+#[derive(Debug)]
+pub struct SweptMaterials {
+    values: Vec<(MaterialParameter, Float)>,
+    /// The replacements of the materials made so far keyed by their addresses,
+    /// which stay the same since the originals are kept here as well.
+    replacements: RwLock<HashMap<usize, (SharedMaterial, SharedMaterial)>>,
+}
+impl SweptMaterials {
+    pub fn new(values: Vec<(MaterialParameter, Float)>) -> Self {
+        Self {
+            values,
+            replacements: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Returns `material` with the parameters of the cell it has.
+    pub fn replace(&self, material: &SharedMaterial) -> SharedMaterial {
+        let key = Arc::as_ptr(material) as *const () as usize;
+        if let Some((_, replacement)) = self
+            .replacements
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&key)
+        {
+            return replacement.clone();
+        }
+        let replacement =
+            self.values
+                .iter()
+                .fold(material.clone(), |material, &(parameter, value)| {
+                    material
+                        .with_parameter(parameter, value)
+                        .unwrap_or(material)
+                });
+        self.replacements
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(key, (material.clone(), replacement.clone()));
+        replacement
+    }
+}
+
+/// The type for objects rendered with the materials of a cell of contact sheets,
+/// which share the objects of the scene among the cells instead of building them again.
+pub struct Swept {
+    pub object: Arc<dyn Hittable>,
+    pub materials: Arc<SweptMaterials>,
+}
+impl Hittable for Swept {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<(HitRecord, SharedMaterial)> {
+        let (hit, material) = self.object.hit(ray, ray_t)?;
+        Some((hit, self.materials.replace(&material)))
+    }
+
+    fn validate(&self) -> Vec<String> {
+        self.object.validate()
+    }
+
+    fn memory_size(&self) -> usize {
+        std::mem::size_of_val(self) + self.object.memory_size()
+    }
+
+    fn bounding_box(&self) -> Option<(Point3, Point3)> {
+        self.object.bounding_box()
+    }
+
+    /// Pushes the lights of the object, whose materials have none of the parameters.
+    fn collect_lights(&self, lights: &mut Vec<Light>) {
+        self.object.collect_lights(lights);
+    }
+
+    fn as_mesh(&self) -> Option<&Mesh> {
+        self.object.as_mesh()
+    }
+}
+
+/// Returns the contact sheet of `films` of the same size laid out in `columns` columns row by row
+/// from the top left, as given by `sweep_cells`.
+pub fn assemble_sheet(films: &[Film], columns: usize) -> Film {
+    let (width, height) = (films[0].width, films[0].height);
+    let rows = films.len().div_ceil(columns);
+    let mut sheet = Film::new(columns * width, rows * height);
+    for (index, film) in films.iter().enumerate() {
+        assert_eq!(
+            (width, height),
+            (film.width, film.height),
+            "the cells must be of the same size"
+        );
+        let (column, row) = (index % columns, index / columns);
+        for y in 0..height {
+            for x in 0..width {
+                sheet.set_pixel(column * width + x, row * height + y, &film.pixel(x, y));
+            }
+        }
+    }
+    sheet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx::assert_approx_eq;
+    use crate::color::{Attenuation, Color};
+    use crate::hittable_object::{Lambertian, Metal};
+
+    #[test]
+    fn sweep_axis_tests() {
+        let axis: SweepAxis = "fuzz=0..1:5".parse().unwrap();
+        assert_eq!(MaterialParameter::Fuzz, axis.parameter);
+        for (expected, value) in [0., 0.25, 0.5, 0.75, 1.].into_iter().zip(axis.values()) {
+            assert_approx_eq!(expected, value);
+        }
+        let axis: SweepAxis = "eta=1.5..2:1".parse().unwrap();
+        assert_eq!(vec![1.5], axis.values());
+        assert!("fuzz=0..2:3".parse::<SweepAxis>().is_err());
+        assert!("eta=0.5..2:3".parse::<SweepAxis>().is_err());
+        assert!("fuzz=0..1:0".parse::<SweepAxis>().is_err());
+        assert!("fuzz=0..1".parse::<SweepAxis>().is_err());
+        assert!("albedo=0..1:2".parse::<SweepAxis>().is_err());
+    }
+
+    #[test]
+    fn sweep_cells_tests() {
+        let axes = [
+            "fuzz=0..1:2".parse().unwrap(),
+            "eta=1..2:3".parse().unwrap(),
+        ];
+        let cells = sweep_cells(&axes);
+        assert_eq!(6, cells.len());
+        // The first axis varies along each row.
+        let (fuzz, eta) = (MaterialParameter::Fuzz, MaterialParameter::Eta);
+        assert_eq!(vec![(fuzz, 0.), (eta, 1.)], cells[0]);
+        assert_eq!(vec![(fuzz, 1.), (eta, 1.)], cells[1]);
+        assert_eq!(vec![(fuzz, 0.), (eta, 1.5)], cells[2]);
+        assert_eq!(vec![(fuzz, 1.), (eta, 2.)], cells[5]);
+    }
+
+    #[test]
+    fn swept_materials_tests() {
+        let albedo = Attenuation {
+            r: 0.8,
+            g: 0.8,
+            b: 0.8,
+        };
+        let metal: SharedMaterial = Arc::new(Metal {
+            albedo: albedo.clone(),
+            fuzz: 0.,
+        });
+        let lambertian: SharedMaterial = Arc::new(Lambertian { albedo });
+        let materials = SweptMaterials::new(vec![(MaterialParameter::Fuzz, 0.5)]);
+        let swept = materials.replace(&metal);
+        assert!(format!("{:?}", swept).contains("fuzz: 0.5"));
+        // The replacement is made once and shared by the later hits.
+        assert!(Arc::ptr_eq(&swept, &materials.replace(&metal)));
+        // The materials without the parameter are kept.
+        assert!(Arc::ptr_eq(&lambertian, &materials.replace(&lambertian)));
+    }
+
+    #[test]
+    fn assemble_sheet_tests() {
+        let film = |value| {
+            let mut film = Film::new(2, 1);
+            for x in 0..2 {
+                film.set_pixel(
+                    x,
+                    0,
+                    &Color {
+                        r: value,
+                        g: value,
+                        b: value,
+                    },
+                );
+            }
+            film
+        };
+        let sheet = assemble_sheet(&[film(0.), film(1.), film(2.)], 2);
+        assert_eq!((4, 2), (sheet.width, sheet.height));
+        assert_approx_eq!(1., sheet.pixel(2, 0).r);
+        assert_approx_eq!(2., sheet.pixel(1, 1).r);
+        // The cells past the last film are black.
+        assert_approx_eq!(0., sheet.pixel(3, 1).r);
+    }
+}
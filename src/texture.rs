@@ -0,0 +1,403 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::color::Attenuation;
+use crate::error::RenderError;
+use crate::geometry::Float;
+
+/// The type for images mapped onto surfaces by the texture coordinates of the hit points,
+/// whose texels are stored in the linear color space row by row from the top.
+#[derive(Clone)]
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    texels: Vec<Attenuation>,
+    /// The mip chain after this image, each level half as large as the previous one down to 1x1,
+    /// which is generated the first time a level is requested.
+    mip_levels: OnceLock<Vec<ImageTexture>>,
+}
+impl ImageTexture {
+    /// Panics if the number of `texels` is not `width * height` or the image is empty.
+    pub fn new(width: usize, height: usize, texels: Vec<Attenuation>) -> Self {
+        assert!(width > 0 && height > 0, "a texture must not be empty");
+        assert_eq!(width * height, texels.len(), "the number of texels");
+        ImageTexture {
+            width,
+            height,
+            texels,
+            mip_levels: OnceLock::new(),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Loads a PNG image, whose colors are decoded by the inverse of the gamma correction of films.
+    /// The alpha channel is ignored. Images whose names end with `.pfm` are loaded by `load_pfm`.
+    pub fn load(path: &Path) -> Result<Self, RenderError> {
+        if path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("pfm"))
+        {
+            return Self::load_pfm(path);
+        }
+        let file = File::open(path).map_err(|e| RenderError::io(path, e))?;
+        let decoding_error = |e: png::DecodingError| match e {
+            png::DecodingError::IoError(e) => RenderError::io(path, e),
+            e => RenderError::SceneParse {
+                path: path.to_path_buf(),
+                message: e.to_string(),
+            },
+        };
+        let mut decoder = png::Decoder::new(file);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(decoding_error)?;
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).map_err(decoding_error)?;
+        let num_channels = info.color_type.samples();
+        let decode = |byte: u8| {
+            let c = byte as Float / 255.;
+            c * c
+        };
+        let texels = data[..info.buffer_size()]
+            .chunks(num_channels)
+            .map(|texel| match texel {
+                [gray] | [gray, _] => {
+                    let c = decode(*gray);
+                    Attenuation { r: c, g: c, b: c }
+                }
+                [r, g, b, ..] => Attenuation {
+                    r: decode(*r),
+                    g: decode(*g),
+                    b: decode(*b),
+                },
+                _ => unreachable!("PNG images have 1 to 4 channels"),
+            })
+            .collect();
+        Ok(Self::new(info.width as usize, info.height as usize, texels))
+    }
+
+    /// Loads a color or grayscale image in the Portable Float Map format as is,
+    /// whose texels are linear and may exceed 1, e.g. for environment maps of high dynamic range.
+    pub fn load_pfm(path: &Path) -> Result<Self, RenderError> {
+        let data = std::fs::read(path).map_err(|e| RenderError::io(path, e))?;
+        let parse_error = |message: &str| RenderError::SceneParse {
+            path: path.to_path_buf(),
+            message: format!("invalid PFM image: {}", message),
+        };
+        let header = || {
+            let (kind, rest) = split_token(&data)?;
+            let (width, rest) = split_token(rest)?;
+            let (height, rest) = split_token(rest)?;
+            let (scale, raster) = split_token(rest)?;
+            Some((
+                kind,
+                width.parse::<usize>().ok()?,
+                height.parse::<usize>().ok()?,
+                scale.parse::<f32>().ok()?,
+                raster,
+            ))
+        };
+        let (kind, width, height, scale, raster) =
+            header().ok_or_else(|| parse_error("malformed header"))?;
+        let num_channels = match kind {
+            "PF" => 3,
+            "Pf" => 1,
+            _ => return Err(parse_error("the header must begin with PF or Pf")),
+        };
+        if width == 0 || height == 0 {
+            return Err(parse_error("the image is empty"));
+        }
+        let row_size = width * num_channels * 4;
+        let raster = raster
+            .get(..row_size * height)
+            .ok_or_else(|| parse_error("the image data is truncated"))?;
+        // Negative scales mean little-endian floats.
+        let read = |bytes: &[u8]| {
+            let bytes = bytes.try_into().unwrap();
+            let value = if scale < 0. {
+                f32::from_le_bytes(bytes)
+            } else {
+                f32::from_be_bytes(bytes)
+            };
+            value as Float
+        };
+        // The rows are stored from the bottom.
+        let texels = raster
+            .chunks_exact(row_size)
+            .rev()
+            .flat_map(|row| row.chunks_exact(num_channels * 4))
+            .map(|texel| {
+                let values: Vec<Float> = texel.chunks_exact(4).map(read).collect();
+                match values[..] {
+                    [gray] => Attenuation {
+                        r: gray,
+                        g: gray,
+                        b: gray,
+                    },
+                    [r, g, b] => Attenuation { r, g, b },
+                    _ => unreachable!("PFM images have 1 or 3 channels"),
+                }
+            })
+            .collect();
+        Ok(Self::new(width, height, texels))
+    }
+
+    pub fn texels(&self) -> &[Attenuation] {
+        &self.texels
+    }
+
+    /// Returns the texel nearest to the texture coordinates `(u, v)`,
+    /// where `(0, 0)` is the bottom-left corner of the image and the image repeats outside [0, 1].
+    pub fn value(&self, u: Float, v: Float) -> &Attenuation {
+        // Keeps 1 as is so that it is the right (resp. top) edge rather than the left (resp. bottom) one.
+        let wrap = |t: Float| {
+            if (0. ..=1.).contains(&t) {
+                t
+            } else {
+                t.rem_euclid(1.)
+            }
+        };
+        let x = (wrap(u) * self.width as Float) as usize;
+        let y = ((1. - wrap(v)) * self.height as Float) as usize;
+        let x = x.min(self.width - 1);
+        let y = y.min(self.height - 1);
+        &self.texels[y * self.width + x]
+    }
+
+    /// Returns the `level`-th level of the mip chain, where the level 0 is the image itself
+    /// and each level is the average of the blocks of 2x2 texels of the previous one.
+    /// Levels beyond the 1x1 one are clamped to it.
+    pub fn mip_level(&self, level: usize) -> &ImageTexture {
+        if level == 0 {
+            return self;
+        }
+        let mip_levels = self.mip_levels.get_or_init(|| {
+            let mut mip_levels: Vec<ImageTexture> = vec![];
+            let mut last = self;
+            while last.width > 1 || last.height > 1 {
+                let next = last.half();
+                mip_levels.push(next);
+                last = mip_levels.last().unwrap();
+            }
+            mip_levels
+        });
+        match mip_levels.get(level - 1).or(mip_levels.last()) {
+            Some(texture) => texture,
+            None => self,
+        }
+    }
+
+    /// Returns the image half as large, where the blocks at the right and bottom edges of
+    /// odd-sized images have fewer texels.
+    fn half(&self) -> ImageTexture {
+        let width = self.width.div_ceil(2);
+        let height = self.height.div_ceil(2);
+        let mut texels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = Attenuation {
+                    r: 0.,
+                    g: 0.,
+                    b: 0.,
+                };
+                let mut num_texels = 0;
+                for sy in (2 * y)..(2 * y + 2).min(self.height) {
+                    for sx in (2 * x)..(2 * x + 2).min(self.width) {
+                        let texel = &self.texels[sy * self.width + sx];
+                        sum.r += texel.r;
+                        sum.g += texel.g;
+                        sum.b += texel.b;
+                        num_texels += 1;
+                    }
+                }
+                let scale = 1. / num_texels as Float;
+                texels.push(Attenuation {
+                    r: sum.r * scale,
+                    g: sum.g * scale,
+                    b: sum.b * scale,
+                });
+            }
+        }
+        ImageTexture::new(width, height, texels)
+    }
+
+    /// Returns the estimated number of bytes the texture occupies, including the mip chain if generated.
+    pub fn memory_size(&self) -> usize {
+        let mip_levels_size: usize = self.mip_levels.get().map_or(0, |mip_levels| {
+            mip_levels.iter().map(|level| level.memory_size()).sum()
+        });
+        std::mem::size_of_val(self)
+            + self.texels.capacity() * std::mem::size_of::<Attenuation>()
+            + mip_levels_size
+    }
+}
+
+/// Splits the token after the leading whitespace of `bytes` from the rest after the single
+/// whitespace character ending it, as in the headers of PFM images.
+fn split_token(bytes: &[u8]) -> Option<(&str, &[u8])> {
+    let start = bytes.iter().position(|b| !b.is_ascii_whitespace())?;
+    let length = bytes[start..]
+        .iter()
+        .position(|b| b.is_ascii_whitespace())?;
+    let token = std::str::from_utf8(&bytes[start..start + length]).ok()?;
+    Some((token, &bytes[start + length + 1..]))
+}
+
+/// Compares the images, regardless of whether their mip chains are generated.
+impl PartialEq for ImageTexture {
+    fn eq(&self, other: &Self) -> bool {
+        (self.width, self.height, &self.texels) == (other.width, other.height, &other.texels)
+    }
+}
+
+/// Prints only the size so that materials with textures can be printed for debugging.
+impl fmt::Debug for ImageTexture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ImageTexture")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The type for caches of textures keyed by their paths, which load each image only the first time
+/// it is requested and share it among all the materials using it, e.g. to avoid keeping
+/// as many copies of a large texture as the models referring to it.
+#[derive(Debug, Default)]
+pub struct TextureCache {
+    textures: Mutex<HashMap<PathBuf, Arc<ImageTexture>>>,
+}
+impl TextureCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the texture loaded from the image at `path`, loading it if it is not cached yet.
+    /// Paths are compared after being made absolute, so different relative paths to the same file
+    /// share the texture. Failures are not cached.
+    pub fn get(&self, path: &Path) -> Result<Arc<ImageTexture>, RenderError> {
+        let key = std::fs::canonicalize(path).map_err(|e| RenderError::io(path, e))?;
+        let mut textures = self.textures.lock().unwrap();
+        if let Some(texture) = textures.get(&key) {
+            return Ok(Arc::clone(texture));
+        }
+        let texture = Arc::new(ImageTexture::load(path)?);
+        textures.insert(key, Arc::clone(&texture));
+        Ok(texture)
+    }
+
+    /// Returns the number of the textures loaded so far.
+    pub fn len(&self) -> usize {
+        self.textures.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn value_tests() {
+        let texel = |r: Float| Attenuation { r, g: 0., b: 0. };
+        // 0 1
+        // 2 3
+        let texture = ImageTexture::new(2, 2, (0..4).map(|i| texel(i as Float)).collect());
+        assert_eq!(&texel(2.), texture.value(0., 0.));
+        assert_eq!(&texel(3.), texture.value(0.75, 0.25));
+        assert_eq!(&texel(1.), texture.value(0.75, 0.75));
+        assert_eq!(&texel(1.), texture.value(-0.25, 0.75));
+        assert_eq!(&texel(0.), texture.value(0.25, 1.));
+    }
+
+    #[test]
+    fn mip_level_tests() {
+        let texel = |r: Float| Attenuation { r, g: 0., b: 0. };
+        // 0 1 2
+        // 3 4 5
+        let texture = ImageTexture::new(3, 2, (0..6).map(|i| texel(i as Float)).collect());
+        let size_without_mip_levels = texture.memory_size();
+        assert_eq!(&texture, texture.mip_level(0));
+
+        let level1 = texture.mip_level(1);
+        assert_eq!((2, 1), (level1.width(), level1.height()));
+        assert_eq!(&[texel(2.), texel(3.5)], level1.texels());
+        let level2 = texture.mip_level(2);
+        assert_eq!((1, 1), (level2.width(), level2.height()));
+        assert_eq!(&[texel(2.75)], level2.texels());
+        // The levels beyond 1x1 are the 1x1 one.
+        assert_eq!(level2, texture.mip_level(10));
+        assert!(texture.memory_size() > size_without_mip_levels);
+
+        let single = ImageTexture::new(1, 1, vec![texel(1.)]);
+        assert_eq!(&single, single.mip_level(3));
+    }
+
+    #[test]
+    fn load_pfm_tests() {
+        let directory = std::env::temp_dir().join(format!("load_pfm_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        // The bottom row is stored first, and the texels may exceed 1.
+        let path = directory.join("bright.pfm");
+        let mut data = b"PF\n1 2\n-1.0\n".to_vec();
+        for value in [1., 2., 3., 40., 50., 60.] {
+            data.extend_from_slice(&(value as f32).to_le_bytes());
+        }
+        std::fs::write(&path, &data).unwrap();
+        let texture = ImageTexture::load(&path).unwrap();
+        assert_eq!((1, 2), (texture.width(), texture.height()));
+        let texel = |r: Float, g: Float, b: Float| Attenuation { r, g, b };
+        assert_eq!(&[texel(40., 50., 60.), texel(1., 2., 3.)], texture.texels());
+
+        let path = directory.join("gray.pfm");
+        let mut data = b"Pf\n1 1\n1.0\n".to_vec();
+        data.extend_from_slice(&0.5f32.to_be_bytes());
+        std::fs::write(&path, &data).unwrap();
+        assert_eq!(
+            &[texel(0.5, 0.5, 0.5)],
+            ImageTexture::load(&path).unwrap().texels()
+        );
+
+        std::fs::write(&path, b"Pf\n2 2\n-1.0\n\0\0\0\0").unwrap();
+        assert!(ImageTexture::load(&path).is_err());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn texture_cache_tests() {
+        let directory = std::env::temp_dir().join(format!("texture_cache_{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("sub")).unwrap();
+        let path = directory.join("gray.png");
+        let file = File::create(&path).unwrap();
+        let mut encoder = png::Encoder::new(file, 2, 1);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[0, 255]).unwrap();
+        writer.finish().unwrap();
+
+        let cache = TextureCache::new();
+        assert!(cache.is_empty());
+        let texture = cache.get(&path).unwrap();
+        assert_eq!((2, 1), (texture.width(), texture.height()));
+        // Another path to the same file shares the texture.
+        let other = cache.get(&directory.join("sub/../gray.png")).unwrap();
+        assert!(Arc::ptr_eq(&texture, &other));
+        assert_eq!(1, cache.len());
+        assert!(cache.get(&directory.join("missing.png")).is_err());
+        assert_eq!(1, cache.len());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
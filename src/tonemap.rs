@@ -0,0 +1,368 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::color::Color;
+use crate::film::Film;
+use crate::geometry::Float;
+use crate::lut::Lut;
+
+/// The curves that map the linear colors of films, which may be brighter than white, to those of images.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ToneCurve {
+    /// Keeps the colors as they are, which the 8-bit formats clip at white.
+    #[default]
+    Linear,
+    /// `c / (1 + c)`, which brings any brightness below white but dims the midtones.
+    Reinhard,
+    /// The fit of the ACES filmic curve by Narkowicz, which keeps more contrast in the midtones.
+    Aces,
+}
+impl ToneCurve {
+    fn map(&self, c: Float) -> Float {
+        match self {
+            ToneCurve::Linear => c,
+            ToneCurve::Reinhard => c / (1. + c),
+            ToneCurve::Aces => {
+                let mapped = c * (2.51 * c + 0.03) / (c * (2.43 * c + 0.59) + 0.14);
+                // Unlike `clamp`, keeps NaN so that invalid samples are not hidden.
+                if mapped > 1. {
+                    1.
+                } else {
+                    mapped
+                }
+            }
+        }
+    }
+
+    /// Returns the name of the curve, which `from_str` parses back.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ToneCurve::Linear => "linear",
+            ToneCurve::Reinhard => "reinhard",
+            ToneCurve::Aces => "aces",
+        }
+    }
+}
+impl FromStr for ToneCurve {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(ToneCurve::Linear),
+            "reinhard" => Ok(ToneCurve::Reinhard),
+            "aces" => Ok(ToneCurve::Aces),
+            _ => Err(format!(
+                "unknown tone curve: {} (expected linear, reinhard, or aces)",
+                s
+            )),
+        }
+    }
+}
+
+/// The transfer functions, i.e., the opto-electronic transfer functions, which encode the linear colors
+/// of images into the values stored in files, e.g. to spend more of the 8 bits on the dark colors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransferFunction {
+    /// Stores the colors as they are, e.g. for the PFM format read by compositing tools.
+    Linear,
+    /// The square root, i.e., gamma 2.0, which is the default of the 8-bit formats.
+    Gamma2,
+    /// The power of 1 / 2.2, which approximates the response of most displays.
+    Gamma22,
+    /// The curve of sRGB, which image viewers and browsers assume for PNG files.
+    Srgb,
+    /// The curve of ITU-R BT.709, which video encoders assume.
+    Rec709,
+    /// The LogC3 curve of ARRI cameras at EI 800, which keeps about 14 stops above black
+    /// for grading, e.g. by the LUTs made for the footage. The primaries are not converted.
+    LogC3,
+    /// The S-Log3 curve of Sony cameras, which is similar to LogC3.
+    SLog3,
+}
+impl TransferFunction {
+    fn encode_value(&self, c: Float) -> Float {
+        match self {
+            TransferFunction::Linear => c,
+            TransferFunction::Gamma2 => c.sqrt(),
+            TransferFunction::Gamma22 => c.powf(1. / 2.2),
+            TransferFunction::Srgb => {
+                if c <= 0.0031308 {
+                    12.92 * c
+                } else {
+                    1.055 * c.powf(1. / 2.4) - 0.055
+                }
+            }
+            TransferFunction::Rec709 => {
+                if c < 0.018 {
+                    4.5 * c
+                } else {
+                    1.099 * c.powf(0.45) - 0.099
+                }
+            }
+            TransferFunction::LogC3 => {
+                if c > 0.010591 {
+                    0.247190 * (5.555556 * c + 0.052272).log10() + 0.385537
+                } else {
+                    5.367655 * c + 0.092809
+                }
+            }
+            TransferFunction::SLog3 => {
+                if c >= 0.01125 {
+                    (420. + ((c + 0.01) / (0.18 + 0.01)).log10() * 261.5) / 1023.
+                } else {
+                    (c * (171.2103 - 95.) / 0.01125 + 95.) / 1023.
+                }
+            }
+        }
+    }
+
+    /// Returns the encoded values of the linear `color`.
+    pub fn encode(&self, color: &Color) -> Color {
+        Color {
+            r: self.encode_value(color.r),
+            g: self.encode_value(color.g),
+            b: self.encode_value(color.b),
+        }
+    }
+
+    /// Returns the name of the function, which `from_str` parses back.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransferFunction::Linear => "linear",
+            TransferFunction::Gamma2 => "gamma2",
+            TransferFunction::Gamma22 => "gamma2.2",
+            TransferFunction::Srgb => "srgb",
+            TransferFunction::Rec709 => "rec709",
+            TransferFunction::LogC3 => "logc3",
+            TransferFunction::SLog3 => "slog3",
+        }
+    }
+}
+impl FromStr for TransferFunction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(TransferFunction::Linear),
+            "gamma2" => Ok(TransferFunction::Gamma2),
+            "gamma2.2" => Ok(TransferFunction::Gamma22),
+            "srgb" => Ok(TransferFunction::Srgb),
+            "rec709" => Ok(TransferFunction::Rec709),
+            "logc3" => Ok(TransferFunction::LogC3),
+            "slog3" => Ok(TransferFunction::SLog3),
+            _ => Err(format!(
+                "unknown transfer function: {} (expected linear, gamma2, gamma2.2, srgb, rec709, logc3, or slog3)",
+                s
+            )),
+        }
+    }
+}
+
+/// The type for the ways of turning rendered films into images.
+/// They are applied to copies of films, so that a single rendering, which is the expensive part,
+/// gives images of several exposures or curves.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ToneMapping {
+    /// The exposure value added to the film, where each step doubles the brightness.
+    pub exposure: Float,
+    pub curve: ToneCurve,
+    /// The transfer function of the image files, or `None` for the default of each format,
+    /// which is `ImageFormat::default_transfer`.
+    pub transfer: Option<TransferFunction>,
+    /// The LUT applied to the colors encoded by `transfer`, e.g. the look of the footage the images
+    /// are matched to, whose results are written as they are.
+    pub lut: Option<Arc<Lut>>,
+}
+impl ToneMapping {
+    /// Returns an error message if the parameters are out of range.
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.exposure.is_finite() {
+            return Err("the exposure must be finite".to_string());
+        }
+        if self.lut.is_some() && self.transfer.is_none() {
+            return Err("the LUT requires the transfer function of its input".to_string());
+        }
+        Ok(())
+    }
+
+    /// Returns the film of the mapped colors of `film`, which has none of its records
+    /// such as invalid samples.
+    pub fn apply(&self, film: &Film) -> Film {
+        let mut mapped = Film::new(film.width, film.height);
+        for y in 0..film.height {
+            for x in 0..film.width {
+                mapped.set_pixel(x, y, &self.map_color(&film.pixel(x, y)));
+            }
+        }
+        mapped
+    }
+
+    /// Returns the mapped color of a pixel of the linear `color`,
+    /// which is already encoded if the mapping has a LUT.
+    pub fn map_color(&self, color: &Color) -> Color {
+        let color = color.scale(self.exposure.exp2());
+        let mapped = Color {
+            r: self.curve.map(color.r),
+            g: self.curve.map(color.g),
+            b: self.curve.map(color.b),
+        };
+        match (&self.lut, self.transfer) {
+            (Some(lut), Some(transfer)) => lut.apply(&transfer.encode(&mapped)),
+            _ => mapped,
+        }
+    }
+
+    /// Returns the transfer function with which the mapped films are written,
+    /// which is linear if the LUT has already encoded them.
+    pub fn file_transfer(&self) -> Option<TransferFunction> {
+        match self.lut {
+            Some(_) => Some(TransferFunction::Linear),
+            None => self.transfer,
+        }
+    }
+
+    /// Returns a short name of the mapping for file names, e.g. `ev+1_aces`, or `ev+1_aces_srgb`
+    /// with a transfer function.
+    pub fn name(&self) -> String {
+        let name = format!("ev{:+}_{}", self.exposure, self.curve.name());
+        match self.transfer {
+            Some(transfer) => format!("{}_{}", name, transfer.name()),
+            None => name,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx::assert_approx_eq;
+
+    #[test]
+    fn tone_mapping_tests() {
+        let mut film = Film::new(3, 1);
+        for (x, c) in [0., 0.25, 4.].into_iter().enumerate() {
+            film.set_pixel(x, 0, &Color { r: c, g: c, b: c });
+        }
+        let mapping = |exposure: Float, curve: ToneCurve| ToneMapping {
+            exposure,
+            curve,
+            transfer: None,
+            lut: None,
+        };
+
+        // The default mapping keeps the colors, including those brighter than white.
+        let kept = ToneMapping::default().apply(&film);
+        for x in 0..3 {
+            assert_eq!(film.pixel(x, 0), kept.pixel(x, 0));
+        }
+        // Each step of the exposure doubles the brightness.
+        let brighter = mapping(2., ToneCurve::Linear).apply(&film);
+        assert_approx_eq!(1., brighter.pixel(1, 0).r);
+        let darker = mapping(-1., ToneCurve::Linear).apply(&film);
+        assert_approx_eq!(2., darker.pixel(2, 0).g);
+        // The curves bring any brightness to white at most, keeping black and the order.
+        for curve in [ToneCurve::Reinhard, ToneCurve::Aces] {
+            let mapped = mapping(0., curve).apply(&film);
+            let values: Vec<Float> = (0..3).map(|x| mapped.pixel(x, 0).b).collect();
+            assert!(values[0].abs() < 0.01, "{:?}", values);
+            assert!(
+                values[0] < values[1] && values[1] < values[2],
+                "{:?}",
+                values
+            );
+            assert!(values[2] <= 1., "{:?}", values);
+        }
+        assert_approx_eq!(
+            0.8,
+            mapping(0., ToneCurve::Reinhard).apply(&film).pixel(2, 0).r
+        );
+
+        for curve in [ToneCurve::Linear, ToneCurve::Reinhard, ToneCurve::Aces] {
+            assert_eq!(Ok(curve), curve.name().parse());
+        }
+        assert!("filmic".parse::<ToneCurve>().is_err());
+        assert_eq!("ev+1_aces", mapping(1., ToneCurve::Aces).name());
+        assert_eq!("ev-0.5_linear", mapping(-0.5, ToneCurve::Linear).name());
+        assert!(mapping(Float::NAN, ToneCurve::Linear).validate().is_err());
+        let srgb = ToneMapping {
+            transfer: Some(TransferFunction::Srgb),
+            ..mapping(0., ToneCurve::Linear)
+        };
+        assert_eq!("ev+0_linear_srgb", srgb.name());
+    }
+
+    #[test]
+    fn transfer_function_tests() {
+        let gray = |c: Float| Color { r: c, g: c, b: c };
+        for transfer in [
+            TransferFunction::Linear,
+            TransferFunction::Gamma2,
+            TransferFunction::Gamma22,
+            TransferFunction::Srgb,
+            TransferFunction::Rec709,
+        ] {
+            assert_eq!(Ok(transfer), transfer.name().parse());
+            // Black and white are kept, and the curves brighten the midtones.
+            assert_approx_eq!(0., transfer.encode(&gray(0.)).r);
+            assert_approx_eq!(1., transfer.encode(&gray(1.)).g, 1e-3);
+            assert!(transfer.encode(&gray(0.2)).b >= 0.2);
+        }
+        assert_eq!(gray(0.5), TransferFunction::Gamma2.encode(&gray(0.25)));
+        // The middle gray of 18% is encoded as about 46% by sRGB, and the linear segment is continuous.
+        assert_approx_eq!(0.4614, TransferFunction::Srgb.encode(&gray(0.18)).r, 1e-4);
+        let below = TransferFunction::Srgb.encode(&gray(0.0031308 - 1e-9)).r;
+        let above = TransferFunction::Srgb.encode(&gray(0.0031308 + 1e-9)).r;
+        assert_approx_eq!(below, above, 1e-6);
+        assert_approx_eq!(0.409, TransferFunction::Rec709.encode(&gray(0.18)).r, 1e-3);
+        assert!("gamma1.8".parse::<TransferFunction>().is_err());
+
+        // The log curves lift black and put the middle gray at the values in their specifications,
+        // continuously across their linear segments.
+        for (transfer, black, middle_gray, cut) in [
+            (TransferFunction::LogC3, 0.0928, 0.3910, 0.010591),
+            (TransferFunction::SLog3, 0.0929, 0.4106, 0.01125),
+        ] {
+            assert_eq!(Ok(transfer), transfer.name().parse());
+            assert_approx_eq!(black, transfer.encode(&gray(0.)).r, 1e-4);
+            assert_approx_eq!(middle_gray, transfer.encode(&gray(0.18)).r, 1e-4);
+            let below = transfer.encode(&gray(cut - 1e-7)).r;
+            let above = transfer.encode(&gray(cut + 1e-7)).r;
+            assert_approx_eq!(below, above, 1e-4);
+            assert!(transfer.encode(&gray(16.)).r < 1.);
+        }
+    }
+
+    #[test]
+    fn lut_tests() {
+        // The LUT inverting the colors.
+        let mut text = "LUT_3D_SIZE 2\n".to_string();
+        for index in 0..8 {
+            let [r, g, b] = [index % 2, index / 2 % 2, index / 4].map(|c| 1 - c);
+            text += &format!("{} {} {}\n", r, g, b);
+        }
+        let lut = Arc::new(crate::lut::parse_cube(&text).unwrap());
+        let mapping = ToneMapping {
+            transfer: Some(TransferFunction::Gamma2),
+            lut: Some(lut),
+            ..ToneMapping::default()
+        };
+        assert_eq!(Ok(()), mapping.validate());
+        let color = Color {
+            r: 0.25,
+            g: 1.,
+            b: 4.,
+        };
+        let mapped = mapping.map_color(&color);
+        assert_approx_eq!(0.5, mapped.r);
+        assert_approx_eq!(0., mapped.g);
+        assert_approx_eq!(0., mapped.b);
+        // The LUT has encoded the colors already.
+        assert_eq!(Some(TransferFunction::Linear), mapping.file_transfer());
+        assert_eq!(None, ToneMapping::default().file_transfer());
+        let unencoded = ToneMapping {
+            transfer: None,
+            ..mapping
+        };
+        assert!(unencoded.validate().is_err());
+    }
+}
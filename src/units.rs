@@ -0,0 +1,83 @@
+use std::str::FromStr;
+
+use crate::geometry::Float;
+
+/// The units of length in which scenes and imported files are measured.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LengthUnit {
+    Millimeter,
+    Centimeter,
+    #[default]
+    Meter,
+    Inch,
+    Foot,
+}
+impl LengthUnit {
+    /// Returns the length of the unit in meters.
+    pub fn meters(&self) -> Float {
+        match self {
+            LengthUnit::Millimeter => 0.001,
+            LengthUnit::Centimeter => 0.01,
+            LengthUnit::Meter => 1.,
+            LengthUnit::Inch => 0.0254,
+            LengthUnit::Foot => 0.3048,
+        }
+    }
+
+    /// Returns the factor converting lengths in this unit into lengths in `unit`.
+    pub fn factor_to(&self, unit: LengthUnit) -> Float {
+        self.meters() / unit.meters()
+    }
+
+    /// Returns the symbol of the unit, which `from_str` parses back.
+    pub fn name(&self) -> &'static str {
+        match self {
+            LengthUnit::Millimeter => "mm",
+            LengthUnit::Centimeter => "cm",
+            LengthUnit::Meter => "m",
+            LengthUnit::Inch => "in",
+            LengthUnit::Foot => "ft",
+        }
+    }
+}
+impl FromStr for LengthUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mm" => Ok(LengthUnit::Millimeter),
+            "cm" => Ok(LengthUnit::Centimeter),
+            "m" => Ok(LengthUnit::Meter),
+            "in" => Ok(LengthUnit::Inch),
+            "ft" => Ok(LengthUnit::Foot),
+            _ => Err(format!(
+                "unknown unit: {} (expected mm, cm, m, in, or ft)",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx::assert_approx_eq;
+
+    #[test]
+    fn length_unit_tests() {
+        for unit in [
+            LengthUnit::Millimeter,
+            LengthUnit::Centimeter,
+            LengthUnit::Meter,
+            LengthUnit::Inch,
+            LengthUnit::Foot,
+        ] {
+            assert_eq!(Ok(unit), unit.name().parse());
+            assert_approx_eq!(1., unit.factor_to(unit));
+        }
+        assert_approx_eq!(1000., LengthUnit::Meter.factor_to(LengthUnit::Millimeter));
+        assert_approx_eq!(2.54, LengthUnit::Inch.factor_to(LengthUnit::Centimeter));
+        assert_approx_eq!(12., LengthUnit::Foot.factor_to(LengthUnit::Inch));
+        assert!("km".parse::<LengthUnit>().is_err());
+    }
+}
@@ -0,0 +1,1153 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::color::{Attenuation, Color};
+use crate::error::RenderError;
+use crate::geometry::{consts, Float, Mat4, Point3, Quat, Vec3};
+use crate::hittable_object::{DiffuseLight, Glass, Lambertian, Mesh, Metal, SharedMaterial};
+
+/// The frame rate of stages that specify neither `timeCodesPerSecond` nor `framesPerSecond`.
+const DEFAULT_TIME_CODES_PER_SECOND: Float = 24.;
+
+/// The color of meshes without `primvars:displayColor`.
+const DEFAULT_DISPLAY_COLOR: Attenuation = Attenuation {
+    r: 0.7,
+    g: 0.7,
+    b: 0.7,
+};
+
+/// The defaults of the inputs of `UsdPreviewSurface`.
+const DEFAULT_DIFFUSE_COLOR: Attenuation = Attenuation {
+    r: 0.18,
+    g: 0.18,
+    b: 0.18,
+};
+const DEFAULT_ROUGHNESS: Float = 0.5;
+const DEFAULT_IOR: Float = 1.5;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    /// Keywords, type names, and attribute names, e.g. `def`, `point3f`, or `xformOp:translate`.
+    Identifier(String),
+    Number(Float),
+    /// Strings and asset paths such as `@model.usda@`.
+    String(String),
+    /// Prim paths such as `</World/Cube>`.
+    Path(String),
+    Punctuation(char),
+}
+
+/// Splits `text` into tokens paired with their line numbers, skipping `#` comments.
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, String> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    while let Some(&c) = chars.peek() {
+        let token_line = line;
+        if c == '\n' {
+            line += 1;
+            chars.next();
+        } else if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' {
+            while chars.next_if(|&c| c != '\n').is_some() {}
+        } else if c == '"' || c == '\'' || c == '@' {
+            chars.next();
+            // Triple-quoted strings may span lines.
+            let is_triple = c != '@' && chars.clone().take(2).all(|next| next == c);
+            if is_triple {
+                chars.next();
+                chars.next();
+            }
+            let mut string = String::new();
+            loop {
+                match chars.next() {
+                    None => return Err(format!("line {}: unterminated string", token_line)),
+                    Some('\\') if c != '@' => string.extend(chars.next()),
+                    Some(next) if next == c => {
+                        if !is_triple {
+                            break;
+                        }
+                        if chars.clone().take(2).all(|next| next == c) {
+                            chars.next();
+                            chars.next();
+                            break;
+                        }
+                        string.push(next);
+                    }
+                    Some(next) => {
+                        if next == '\n' {
+                            line += 1;
+                        }
+                        string.push(next);
+                    }
+                }
+            }
+            tokens.push((Token::String(string), token_line));
+        } else if c == '<' {
+            chars.next();
+            let mut path = String::new();
+            loop {
+                match chars.next() {
+                    Some('>') => break,
+                    Some('\n') | None => {
+                        return Err(format!("line {}: unterminated path", token_line))
+                    }
+                    Some(next) => path.push(next),
+                }
+            }
+            tokens.push((Token::Path(path), token_line));
+        } else if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' {
+            let mut number = String::new();
+            while let Some(next) = chars
+                .next_if(|&next| next.is_ascii_alphanumeric() || matches!(next, '-' | '+' | '.'))
+            {
+                number.push(next);
+            }
+            let value = match number.as_str() {
+                "inf" | "+inf" => Float::INFINITY,
+                "-inf" => Float::NEG_INFINITY,
+                _ => number
+                    .parse()
+                    .map_err(|_| format!("line {}: invalid number: {}", token_line, number))?,
+            };
+            tokens.push((Token::Number(value), token_line));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut identifier = String::new();
+            while let Some(next) =
+                chars.next_if(|&next| next.is_alphanumeric() || matches!(next, '_' | ':' | '.'))
+            {
+                identifier.push(next);
+            }
+            tokens.push((Token::Identifier(identifier), token_line));
+        } else if "()[]{}=,:;".contains(c) {
+            chars.next();
+            tokens.push((Token::Punctuation(c), token_line));
+        } else {
+            return Err(format!(
+                "line {}: unexpected character: {:?}",
+                token_line, c
+            ));
+        }
+    }
+    Ok(tokens)
+}
+
+/// The type for values of attributes, where tuples and arrays are both lists.
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Number(Float),
+    /// Strings, tokens such as `"xformOp:translate"`, and identifiers such as `None`.
+    String(String),
+    List(Vec<Value>),
+}
+impl Value {
+    fn as_number(&self) -> Option<Float> {
+        match self {
+            Value::Number(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    fn as_numbers(&self) -> Option<Vec<Float>> {
+        self.as_list()?.iter().map(Value::as_number).collect()
+    }
+
+    fn as_vec3(&self) -> Option<Vec3> {
+        match self.as_numbers()?.as_slice() {
+            &[x, y, z] => Some(Vec3 { x, y, z }),
+            _ => None,
+        }
+    }
+
+    /// Interpolates numbers and lists of the same shape linearly, and holds the other values.
+    fn lerp(&self, other: &Value, t: Float) -> Value {
+        match (self, other) {
+            (Value::Number(x), Value::Number(y)) => Value::Number((1. - t) * x + t * y),
+            (Value::List(xs), Value::List(ys)) if xs.len() == ys.len() => {
+                Value::List(xs.iter().zip(ys).map(|(x, y)| x.lerp(y, t)).collect())
+            }
+            _ => self.clone(),
+        }
+    }
+}
+
+/// The type for attributes, which have a default value and/or values sampled at time codes.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Attribute {
+    default: Option<Value>,
+    time_samples: Vec<(Float, Value)>,
+}
+impl Attribute {
+    /// Returns the value at `time_code`, interpolating the time samples if any.
+    fn value_at(&self, time_code: Float) -> Option<Value> {
+        let samples = &self.time_samples;
+        let Some((first_time, first)) = samples.first() else {
+            return self.default.clone();
+        };
+        if time_code <= *first_time {
+            return Some(first.clone());
+        }
+        for window in samples.windows(2) {
+            let ((t1, v1), (t2, v2)) = (&window[0], &window[1]);
+            if time_code <= *t2 {
+                return Some(v1.lerp(v2, (time_code - t1) / (t2 - t1)));
+            }
+        }
+        samples.last().map(|(_, value)| value.clone())
+    }
+}
+
+/// The type for prims defined by `def`, i.e., the nodes of the scene hierarchy.
+#[derive(Clone, Debug, PartialEq)]
+struct Prim {
+    /// The schema, e.g. `Xform` or `Mesh`, which is empty for typeless prims.
+    type_name: String,
+    name: String,
+    attributes: HashMap<String, Attribute>,
+    children: Vec<Prim>,
+}
+
+/// The qualifiers that may precede attributes and metadata, which are all ignored.
+const QUALIFIERS: [&str; 8] = [
+    "custom", "uniform", "varying", "prepend", "append", "add", "delete", "reorder",
+];
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+}
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    fn error(&self, message: &str) -> String {
+        match self.tokens.get(self.position) {
+            Some((_, line)) => format!("line {}: {}", line, message),
+            None => format!("end of file: {}", message),
+        }
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self
+            .peek()
+            .cloned()
+            .ok_or_else(|| self.error("unexpected end of file"))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    /// Consumes the punctuation `c` if it comes next.
+    fn eat(&mut self, c: char) -> bool {
+        let is_next = self.peek() == Some(&Token::Punctuation(c));
+        if is_next {
+            self.position += 1;
+        }
+        is_next
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected `{}`", c)))
+        }
+    }
+
+    fn identifier(&mut self) -> Result<String, String> {
+        match self.next()? {
+            Token::Identifier(identifier) => Ok(identifier),
+            _ => {
+                self.position -= 1;
+                Err(self.error("expected a name"))
+            }
+        }
+    }
+
+    /// Skips a group enclosed by the brackets whose opening one comes next,
+    /// e.g. metadata that does not affect the geometry.
+    fn skip_group(&mut self) -> Result<(), String> {
+        let mut depth = 0;
+        loop {
+            match self.next()? {
+                Token::Punctuation('(' | '[' | '{') => depth += 1,
+                Token::Punctuation(')' | ']' | '}') => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.next()? {
+            Token::Number(x) => Ok(Value::Number(x)),
+            Token::String(s) | Token::Path(s) | Token::Identifier(s) => Ok(Value::String(s)),
+            Token::Punctuation(open @ ('(' | '[')) => {
+                let close = if open == '(' { ')' } else { ']' };
+                let mut values = vec![];
+                while !self.eat(close) {
+                    values.push(self.value()?);
+                    if !self.eat(',') {
+                        self.expect(close)?;
+                        break;
+                    }
+                }
+                Ok(Value::List(values))
+            }
+            Token::Punctuation('{') => {
+                // Dictionaries are not used for geometry.
+                self.position -= 1;
+                self.skip_group()?;
+                Ok(Value::List(vec![]))
+            }
+            _ => {
+                self.position -= 1;
+                Err(self.error("expected a value"))
+            }
+        }
+    }
+
+    /// Parses `{ <time code>: <value>, ... }`.
+    fn time_samples(&mut self) -> Result<Vec<(Float, Value)>, String> {
+        self.expect('{')?;
+        let mut samples = vec![];
+        while !self.eat('}') {
+            let Token::Number(time_code) = self.next()? else {
+                self.position -= 1;
+                return Err(self.error("expected a time code"));
+            };
+            self.expect(':')?;
+            samples.push((time_code, self.value()?));
+            if !self.eat(',') {
+                self.expect('}')?;
+                break;
+            }
+        }
+        samples.sort_by(|(t1, _), (t2, _)| t1.total_cmp(t2));
+        Ok(samples)
+    }
+
+    /// Parses the layer metadata, i.e., `(...)` at the top of files.
+    fn layer_metadata(&mut self) -> Result<HashMap<String, Value>, String> {
+        let mut metadata = HashMap::new();
+        self.expect('(')?;
+        while !self.eat(')') {
+            match self.next()? {
+                // The documentation of the layer.
+                Token::String(_) => {}
+                Token::Identifier(mut name) => {
+                    while QUALIFIERS.contains(&name.as_str()) {
+                        name = self.identifier()?;
+                    }
+                    self.expect('=')?;
+                    metadata.insert(name, self.value()?);
+                }
+                _ => {
+                    self.position -= 1;
+                    return Err(self.error("expected layer metadata"));
+                }
+            }
+        }
+        Ok(metadata)
+    }
+
+    /// Parses the prims and the properties up to `}` or the end of the file,
+    /// where prims defined by `over` and `class` are skipped since composition is not supported.
+    fn prim_body(&mut self, prim: &mut Prim) -> Result<(), String> {
+        loop {
+            match self.peek() {
+                None | Some(Token::Punctuation('}')) => return Ok(()),
+                Some(Token::Punctuation(';')) => {
+                    self.position += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            let keyword = self.identifier()?;
+            match keyword.as_str() {
+                "def" | "over" | "class" => {
+                    let type_name = match self.peek() {
+                        Some(Token::Identifier(_)) => self.identifier()?,
+                        _ => String::new(),
+                    };
+                    let Token::String(name) = self.next()? else {
+                        self.position -= 1;
+                        return Err(self.error("expected the name of the prim"));
+                    };
+                    if self.peek() == Some(&Token::Punctuation('(')) {
+                        self.skip_group()?;
+                    }
+                    let mut child = Prim {
+                        type_name,
+                        name,
+                        attributes: HashMap::new(),
+                        children: vec![],
+                    };
+                    self.expect('{')?;
+                    self.prim_body(&mut child)?;
+                    self.expect('}')?;
+                    if keyword == "def" {
+                        prim.children.push(child);
+                    }
+                }
+                "variantSet" => {
+                    self.next()?;
+                    self.expect('=')?;
+                    self.skip_group()?;
+                }
+                _ => self.property(prim, keyword)?,
+            }
+        }
+    }
+
+    /// Parses an attribute or a relationship after its first word `keyword`.
+    fn property(&mut self, prim: &mut Prim, mut keyword: String) -> Result<(), String> {
+        while QUALIFIERS.contains(&keyword.as_str()) {
+            keyword = self.identifier()?;
+        }
+        // `keyword` is the type name unless the property is something like `reorder nameChildren = [...]`.
+        let name = if self.peek() == Some(&Token::Punctuation('=')) {
+            keyword
+        } else {
+            if self.eat('[') {
+                self.expect(']')?;
+            }
+            self.identifier()?
+        };
+        if self.eat('=') {
+            if let Some(base_name) = name.strip_suffix(".timeSamples") {
+                let samples = self.time_samples()?;
+                let attribute = prim.attributes.entry(base_name.to_string()).or_default();
+                attribute.time_samples = samples;
+            } else {
+                let value = self.value()?;
+                if !name.ends_with(".connect") {
+                    let attribute = prim.attributes.entry(name).or_default();
+                    attribute.default = Some(value);
+                }
+            }
+        }
+        if self.peek() == Some(&Token::Punctuation('(')) {
+            self.skip_group()?;
+        }
+        Ok(())
+    }
+}
+
+/// The type for USD stages in the text format (`*.usda`),
+/// of which only the meshes and their transformations are rendered.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UsdStage {
+    pub time_codes_per_second: Float,
+    /// The time code of the first frame, which is rendered at time 0.
+    pub start_time_code: Float,
+    /// Whether the stage is Z-up, in which case it is rotated to be Y-up like the renderer.
+    pub is_z_up: bool,
+    /// The length of the unit of the stage in meters given by `metersPerUnit`,
+    /// by which the meshes can be converted into the units of the scene.
+    pub meters_per_unit: Option<Float>,
+    root: Prim,
+}
+impl UsdStage {
+    pub fn load(path: &Path) -> Result<Self, RenderError> {
+        let text = std::fs::read_to_string(path).map_err(|e| RenderError::io(path, e))?;
+        Self::parse(&text).map_err(|message| RenderError::SceneParse {
+            path: path.to_path_buf(),
+            message,
+        })
+    }
+
+    /// Parses a subset of the USD text format:
+    /// prims defined by `def`, their attributes including time samples, and the layer metadata.
+    /// Composition such as references, payloads, and variants is ignored.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        if !text.starts_with("#usda ") {
+            return Err("expected the header `#usda 1.0`".to_string());
+        }
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            position: 0,
+        };
+        let metadata = if parser.peek() == Some(&Token::Punctuation('(')) {
+            parser.layer_metadata()?
+        } else {
+            HashMap::new()
+        };
+        let mut root = Prim {
+            type_name: String::new(),
+            name: String::new(),
+            attributes: HashMap::new(),
+            children: vec![],
+        };
+        parser.prim_body(&mut root)?;
+        if parser.peek().is_some() {
+            return Err(parser.error("unexpected `}`"));
+        }
+
+        let number = |name: &str| metadata.get(name).and_then(Value::as_number);
+        let time_codes_per_second = number("timeCodesPerSecond")
+            .or_else(|| number("framesPerSecond"))
+            .unwrap_or(DEFAULT_TIME_CODES_PER_SECOND);
+        if !time_codes_per_second.is_finite() || time_codes_per_second <= 0. {
+            return Err(format!(
+                "timeCodesPerSecond must be positive, but is {}",
+                time_codes_per_second
+            ));
+        }
+        let meters_per_unit = number("metersPerUnit");
+        if let Some(meters_per_unit) = meters_per_unit {
+            if !meters_per_unit.is_finite() || meters_per_unit <= 0. {
+                return Err(format!(
+                    "metersPerUnit must be positive, but is {}",
+                    meters_per_unit
+                ));
+            }
+        }
+        Ok(UsdStage {
+            time_codes_per_second,
+            start_time_code: number("startTimeCode").unwrap_or(0.),
+            is_z_up: metadata.get("upAxis") == Some(&Value::String("Z".to_string())),
+            meters_per_unit,
+            root,
+        })
+    }
+
+    /// Returns the meshes placed as they are at `time` in seconds from the first frame,
+    /// where polygons are split into triangles. The meshes are made of the material bound by
+    /// `material:binding` to them or their ancestors, or colored by their `primvars:displayColor`
+    /// otherwise, and those with the same material or color share it.
+    pub fn meshes_at(&self, time: Float) -> Result<Vec<Mesh>, String> {
+        let time_code = self.start_time_code + time * self.time_codes_per_second;
+        let up_axis = if self.is_z_up {
+            let x_axis = Vec3 {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            };
+            Mat4::rotation(&Quat::from_axis_angle(
+                &x_axis.unit_vector(),
+                -consts::PI / 2.,
+            ))
+        } else {
+            Mat4::identity()
+        };
+        let mut materials = MaterialRegistry::default();
+        for child in self.root.children.iter() {
+            materials.define_all(child, "", time_code)?;
+        }
+        let mut meshes = vec![];
+        for child in self.root.children.iter() {
+            let parent = Parent {
+                path: "",
+                transform: &up_axis,
+                binding: None,
+            };
+            collect_meshes(child, &parent, time_code, &mut materials, &mut meshes)?;
+        }
+        Ok(meshes)
+    }
+}
+
+/// The type for the materials shared among the meshes of a stage.
+#[derive(Default)]
+struct MaterialRegistry {
+    /// The materials defined by `Material` prims, by their paths.
+    defined: HashMap<String, SharedMaterial>,
+    /// The materials made of `primvars:displayColor`, by the bits of the colors.
+    display_colors: HashMap<[u64; 3], SharedMaterial>,
+}
+impl MaterialRegistry {
+    /// Registers the `Material` prims in `prim` and its descendants,
+    /// where `parent_path` is the path of the parent prim.
+    fn define_all(
+        &mut self,
+        prim: &Prim,
+        parent_path: &str,
+        time_code: Float,
+    ) -> Result<(), String> {
+        let path = format!("{}/{}", parent_path, prim.name);
+        if prim.type_name == "Material" {
+            let material = to_material(prim, time_code).map_err(|e| format!("{}: {}", path, e))?;
+            self.defined.insert(path.clone(), material);
+        }
+        for child in prim.children.iter() {
+            self.define_all(child, &path, time_code)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the material bound to a mesh by `binding`, i.e., the path of a `Material` prim.
+    fn bound(&self, binding: &str) -> Result<SharedMaterial, String> {
+        self.defined.get(binding).cloned().ok_or_else(|| {
+            format!(
+                "material:binding refers to an unknown material <{}>",
+                binding
+            )
+        })
+    }
+
+    /// Returns the Lambertian material of `color`, which is made once for each color.
+    // The casts are no-ops unless the `f32` feature is enabled.
+    #[allow(clippy::unnecessary_cast)]
+    fn display_color(&mut self, color: Attenuation) -> SharedMaterial {
+        let key = [color.r, color.g, color.b].map(|x| x.to_bits() as u64);
+        self.display_colors
+            .entry(key)
+            .or_insert_with(|| Arc::new(Lambertian { albedo: color }))
+            .clone()
+    }
+}
+
+/// Converts the `UsdPreviewSurface` shader of the `Material` prim `prim`
+/// to the material of the renderer closest to it:
+/// - `DiffuseLight` if `emissiveColor` is not black,
+/// - `Glass` if `opacity` is less than 1, whose refractive index is `ior`,
+/// - `Metal` if `metallic` is at least 0.5, whose fuzz is `roughness`,
+/// - and `Lambertian` of `diffuseColor` otherwise.
+///
+/// Textures connected to the inputs are ignored.
+fn to_material(prim: &Prim, time_code: Float) -> Result<SharedMaterial, String> {
+    let is_preview_surface = |child: &&Prim| {
+        child.type_name == "Shader"
+            && child
+                .attributes
+                .get("info:id")
+                .and_then(|attribute| attribute.default.as_ref())
+                == Some(&Value::String("UsdPreviewSurface".to_string()))
+    };
+    let shader = prim
+        .children
+        .iter()
+        .find(is_preview_surface)
+        .ok_or("no UsdPreviewSurface shader")?;
+    let input = |name: &str| {
+        shader
+            .attributes
+            .get(&format!("inputs:{}", name))
+            .and_then(|attribute| attribute.value_at(time_code))
+    };
+    let number = |name: &str| -> Result<Option<Float>, String> {
+        input(name)
+            .map(|value| {
+                value
+                    .as_number()
+                    .ok_or_else(|| format!("inputs:{} must be a number", name))
+            })
+            .transpose()
+    };
+    let color = |name: &str| -> Result<Option<Vec3>, String> {
+        input(name)
+            .map(|value| {
+                value
+                    .as_vec3()
+                    .ok_or_else(|| format!("inputs:{} must be a color", name))
+            })
+            .transpose()
+    };
+
+    if let Some(emission) = color("emissiveColor")? {
+        if emission.x > 0. || emission.y > 0. || emission.z > 0. {
+            return Ok(Arc::new(DiffuseLight {
+                emit: Color {
+                    r: emission.x,
+                    g: emission.y,
+                    b: emission.z,
+                },
+            }));
+        }
+    }
+    if number("opacity")?.is_some_and(|opacity| opacity < 1.) {
+        return Ok(Arc::new(Glass {
+            eta: number("ior")?
+                .filter(|&eta| eta >= 1.)
+                .unwrap_or(DEFAULT_IOR),
+            albedo: Attenuation {
+                r: 1.,
+                g: 1.,
+                b: 1.,
+            },
+            priority: 0,
+        }));
+    }
+    let albedo = color("diffuseColor")?.map_or(DEFAULT_DIFFUSE_COLOR, |c| Attenuation {
+        r: c.x.clamp(0., 1.),
+        g: c.y.clamp(0., 1.),
+        b: c.z.clamp(0., 1.),
+    });
+    if number("metallic")?.is_some_and(|metallic| metallic >= 0.5) {
+        return Ok(Arc::new(Metal {
+            albedo,
+            fuzz: number("roughness")?
+                .unwrap_or(DEFAULT_ROUGHNESS)
+                .clamp(0., 1.),
+        }));
+    }
+    Ok(Arc::new(Lambertian { albedo }))
+}
+
+/// The type for what prims inherit from their parent.
+struct Parent<'a> {
+    path: &'a str,
+    transform: &'a Mat4,
+    /// The path of the material bound to the parent or its ancestors.
+    binding: Option<&'a str>,
+}
+
+/// Appends the meshes in `prim` and its descendants to `meshes`, taking their materials from `materials`.
+fn collect_meshes(
+    prim: &Prim,
+    parent: &Parent,
+    time_code: Float,
+    materials: &mut MaterialRegistry,
+    meshes: &mut Vec<Mesh>,
+) -> Result<(), String> {
+    let path = format!("{}/{}", parent.path, prim.name);
+    let in_prim = |message: String| format!("{}: {}", path, message);
+    let transform = local_transform(prim, parent.transform, time_code).map_err(in_prim)?;
+    let binding = match prim
+        .attributes
+        .get("material:binding")
+        .and_then(|attribute| attribute.default.as_ref())
+    {
+        Some(Value::String(binding)) => Some(binding.as_str()),
+        Some(_) => return Err(in_prim("material:binding must be a path".to_string())),
+        None => parent.binding,
+    };
+    if prim.type_name == "Mesh" {
+        let material = match binding {
+            Some(binding) => materials.bound(binding).map_err(in_prim)?,
+            None => materials.display_color(display_color(prim, time_code)),
+        };
+        meshes.push(to_mesh(prim, &transform, time_code, material).map_err(in_prim)?);
+    }
+    let parent = Parent {
+        path: &path,
+        transform: &transform,
+        binding,
+    };
+    for child in prim.children.iter() {
+        collect_meshes(child, &parent, time_code, materials, meshes)?;
+    }
+    Ok(())
+}
+
+/// Returns the `primvars:displayColor` of `prim`, where only the first color is used
+/// even if the colors vary over the surface.
+fn display_color(prim: &Prim, time_code: Float) -> Attenuation {
+    prim.attributes
+        .get("primvars:displayColor")
+        .and_then(|attribute| attribute.value_at(time_code))
+        .and_then(|colors| colors.as_list()?.first()?.as_vec3())
+        .map_or(DEFAULT_DISPLAY_COLOR, |color| Attenuation {
+            r: color.x,
+            g: color.y,
+            b: color.z,
+        })
+}
+
+/// Composes `parent_transform` and the operations in the `xformOpOrder` of `prim`,
+/// which are applied from the last one.
+fn local_transform(prim: &Prim, parent_transform: &Mat4, time_code: Float) -> Result<Mat4, String> {
+    let Some(order) = prim
+        .attributes
+        .get("xformOpOrder")
+        .and_then(|attribute| attribute.value_at(time_code))
+    else {
+        return Ok(parent_transform.clone());
+    };
+    let mut transform = parent_transform.clone();
+    for op in order.as_list().ok_or("xformOpOrder must be a list")? {
+        let Value::String(op) = op else {
+            return Err("xformOpOrder must be a list of names".to_string());
+        };
+        if op == "!resetXformStack!" {
+            transform = Mat4::identity();
+            continue;
+        }
+        let (is_inverted, name) = match op.strip_prefix("!invert!") {
+            Some(name) => (true, name),
+            None => (false, op.as_str()),
+        };
+        let value = prim
+            .attributes
+            .get(name)
+            .and_then(|attribute| attribute.value_at(time_code))
+            .ok_or_else(|| format!("{} has no value", name))?;
+        let mut matrix = xform_op_matrix(name, &value)?;
+        if is_inverted {
+            matrix = matrix
+                .inverse()
+                .ok_or_else(|| format!("{} is not invertible", name))?;
+        }
+        transform = &transform * &matrix;
+    }
+    Ok(transform)
+}
+
+/// Converts the value of the transformation operation `name`, e.g. `xformOp:rotateXYZ:pivot`, to a matrix.
+fn xform_op_matrix(name: &str, value: &Value) -> Result<Mat4, String> {
+    let invalid = || format!("invalid value for {}: {:?}", name, value);
+    let kind = name
+        .strip_prefix("xformOp:")
+        .and_then(|suffix| suffix.split(':').next())
+        .ok_or_else(|| format!("unknown transformation: {}", name))?;
+    let axis = |c: char| {
+        let mut v = Vec3 {
+            x: 0.,
+            y: 0.,
+            z: 0.,
+        };
+        v[(c as u8 - b'X') as usize] = 1.;
+        v.unit_vector()
+    };
+    match kind {
+        "translate" => Ok(Mat4::translation(&value.as_vec3().ok_or_else(invalid)?)),
+        "scale" => Ok(Mat4::scaling(&value.as_vec3().ok_or_else(invalid)?)),
+        "transform" => {
+            let rows: Option<Vec<Vec<Float>>> = value
+                .as_list()
+                .and_then(|rows| rows.iter().map(Value::as_numbers).collect());
+            let rows = rows.filter(|rows| rows.len() == 4 && rows.iter().all(|row| row.len() == 4));
+            let rows = rows.ok_or_else(invalid)?;
+            // USD multiplies row vectors by matrices, whose translations are in the last row.
+            let mut m = Mat4::identity();
+            for (i, row) in rows.iter().enumerate() {
+                m.rows[i].copy_from_slice(row);
+            }
+            Ok(m.transpose())
+        }
+        "orient" => match value.as_numbers().as_deref() {
+            Some(&[w, x, y, z]) => Ok(Mat4::rotation(&Quat { w, x, y, z })),
+            _ => Err(invalid()),
+        },
+        "rotateX" | "rotateY" | "rotateZ" => {
+            let degree = value.as_number().ok_or_else(invalid)?;
+            let c = kind.chars().last().unwrap();
+            Ok(Mat4::rotation(&Quat::from_axis_angle(
+                &axis(c),
+                degree.to_radians(),
+            )))
+        }
+        _ => {
+            // `rotateXYZ`, `rotateZYX`, etc., which rotate around the axes in the order of the letters.
+            let axes = kind
+                .strip_prefix("rotate")
+                .filter(|axes| axes.len() == 3 && axes.chars().all(|c| "XYZ".contains(c)))
+                .ok_or_else(|| format!("unknown transformation: {}", name))?;
+            let degrees = value.as_vec3().ok_or_else(invalid)?;
+            let mut q = Quat {
+                w: 1.,
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            };
+            for c in axes.chars() {
+                let index = (c as u8 - b'X') as usize;
+                q = &Quat::from_axis_angle(&axis(c), degrees[index].to_radians()) * &q;
+            }
+            Ok(Mat4::rotation(&q))
+        }
+    }
+}
+
+fn to_mesh(
+    prim: &Prim,
+    transform: &Mat4,
+    time_code: Float,
+    material: SharedMaterial,
+) -> Result<Mesh, String> {
+    let value = |name: &str| {
+        prim.attributes
+            .get(name)
+            .and_then(|attribute| attribute.value_at(time_code))
+    };
+    let indices = |name: &str| -> Result<Vec<usize>, String> {
+        let Some(value) = value(name) else {
+            return Ok(vec![]);
+        };
+        let numbers = value
+            .as_numbers()
+            .ok_or_else(|| format!("{} must be a list of integers", name))?;
+        numbers
+            .into_iter()
+            .map(|x| {
+                if x >= 0. && x.fract() == 0. {
+                    Ok(x as usize)
+                } else {
+                    Err(format!(
+                        "{} must be a list of integers, but has {}",
+                        name, x
+                    ))
+                }
+            })
+            .collect()
+    };
+
+    let points = match value("points") {
+        Some(points) => {
+            let points = points.as_list().ok_or("points must be a list")?;
+            let to_point = |point: &Value| {
+                let v = point.as_vec3()?;
+                Some(transform.transform_point(&Point3 {
+                    x: v.x,
+                    y: v.y,
+                    z: v.z,
+                }))
+            };
+            points
+                .iter()
+                .map(to_point)
+                .collect::<Option<Vec<_>>>()
+                .ok_or("points must be a list of 3D points")?
+        }
+        None => vec![],
+    };
+    let counts = indices("faceVertexCounts")?;
+    let face_vertex_indices = indices("faceVertexIndices")?;
+    if counts.iter().sum::<usize>() != face_vertex_indices.len() {
+        return Err(format!(
+            "faceVertexCounts sums to {}, but faceVertexIndices has {} indices",
+            counts.iter().sum::<usize>(),
+            face_vertex_indices.len()
+        ));
+    }
+    if let Some(&index) = face_vertex_indices.iter().find(|&&i| i >= points.len()) {
+        return Err(format!(
+            "faceVertexIndices refers to point #{}, but there are {} points",
+            index,
+            points.len()
+        ));
+    }
+
+    // Splits each polygon into a fan of triangles around its first vertex.
+    let mut triangles = vec![];
+    let mut rest = face_vertex_indices.as_slice();
+    for count in counts {
+        let (face, next) = rest.split_at(count);
+        for i in 2..count {
+            triangles.push([face[0], face[i - 1], face[i]]);
+        }
+        rest = next;
+    }
+    Ok(Mesh::new(points, triangles, material))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx::assert_approx_eq;
+    use crate::hittable_object::Hittable;
+
+    /// A unit square that moves along the x axis from frame 1 to frame 25,
+    /// under a transformed parent.
+    const ANIMATED_USDA: &str = r#"#usda 1.0
+(
+    "Exported for the tests"
+    defaultPrim = "World"
+    startTimeCode = 1
+    endTimeCode = 25
+    timeCodesPerSecond = 24
+    upAxis = "Y"
+    metersPerUnit = 0.01
+)
+
+def Xform "World" (
+    kind = "assembly"
+)
+{
+    double3 xformOp:translate = (0, 0, -3)
+    uniform token[] xformOpOrder = ["xformOp:translate"]
+
+    def Mesh "Square"
+    {
+        int[] faceVertexCounts = [4]
+        int[] faceVertexIndices = [0, 1, 2, 3]
+        point3f[] points = [(-0.5, -0.5, 0), (0.5, -0.5, 0), (0.5, 0.5, 0), (-0.5, 0.5, 0)] (
+            interpolation = "vertex"
+        )
+        color3f[] primvars:displayColor = [(1, 0, 0)]
+        float3 xformOp:translate.timeSamples = {
+            1: (0, 0, 0),
+            25: (2, 0, 0),
+        }
+        float3 xformOp:scale = (2, 2, 2)
+        uniform token[] xformOpOrder = ["xformOp:translate", "xformOp:scale"]
+    }
+
+    over "Ignored"
+    {
+        int[] faceVertexCounts = [3]
+    }
+}
+"#;
+
+    #[test]
+    fn parse_tests() {
+        let stage = UsdStage::parse(ANIMATED_USDA).unwrap();
+        assert_eq!(24., stage.time_codes_per_second);
+        assert_eq!(1., stage.start_time_code);
+        assert!(!stage.is_z_up);
+        assert_eq!(Some(0.01), stage.meters_per_unit);
+
+        let point = |x: Float, y: Float, z: Float| Point3 { x, y, z };
+        // Frame 13 is half a second after the first frame.
+        for (time, x) in [(0., 0.), (0.5, 1.), (10., 2.)] {
+            let meshes = stage.meshes_at(time).unwrap();
+            assert_eq!(1, meshes.len());
+            assert_eq!(&[[0, 1, 2], [0, 2, 3]], meshes[0].triangles());
+            assert_approx_eq!(point(x - 1., -1., -3.), meshes[0].vertices()[0].clone());
+            assert_approx_eq!(point(x + 1., 1., -3.), meshes[0].vertices()[2].clone());
+            assert!(meshes[0].validate().is_empty());
+        }
+
+        assert!(UsdStage::parse("#usda 1.0\ndef Mesh \"M\" {").is_err());
+        assert!(UsdStage::parse("def Mesh \"M\" {}").is_err());
+        assert!(UsdStage::parse("#usda 1.0\n(\n    metersPerUnit = 0\n)\n").is_err());
+        let broken = "#usda 1.0\ndef Mesh \"M\" {\n    int[] faceVertexCounts = [3]\n    int[] faceVertexIndices = [0, 1, 2]\n    point3f[] points = [(0, 0, 0)]\n}\n";
+        let stage = UsdStage::parse(broken).unwrap();
+        assert_eq!(None, stage.meters_per_unit);
+        assert_eq!(
+            Err("/M: faceVertexIndices refers to point #1, but there are 1 points".to_string()),
+            stage.meshes_at(0.).map(|meshes| meshes.len())
+        );
+    }
+
+    /// Three triangles, two of which are bound to a material through their parent,
+    /// and two more colored by the same display color.
+    const MATERIALS_USDA: &str = r#"#usda 1.0
+
+def Scope "Looks"
+{
+    def Material "Gold"
+    {
+        token outputs:surface.connect = </Looks/Gold/Surface.outputs:surface>
+
+        def Shader "Surface"
+        {
+            uniform token info:id = "UsdPreviewSurface"
+            color3f inputs:diffuseColor = (1, 0.8, 0.3)
+            float inputs:metallic = 1
+            float inputs:roughness = 0.2
+            token outputs:surface
+        }
+    }
+}
+
+def Xform "Bound"
+{
+    rel material:binding = </Looks/Gold>
+
+    def Mesh "A"
+    {
+        int[] faceVertexCounts = [3]
+        int[] faceVertexIndices = [0, 1, 2]
+        point3f[] points = [(0, 0, 0), (1, 0, 0), (0, 1, 0)]
+    }
+
+    def Mesh "B"
+    {
+        int[] faceVertexCounts = [3]
+        int[] faceVertexIndices = [0, 1, 2]
+        point3f[] points = [(0, 0, 1), (1, 0, 1), (0, 1, 1)]
+        color3f[] primvars:displayColor = [(0, 0, 1)]
+    }
+}
+
+def Mesh "C"
+{
+    int[] faceVertexCounts = [3]
+    int[] faceVertexIndices = [0, 1, 2]
+    point3f[] points = [(0, 0, 2), (1, 0, 2), (0, 1, 2)]
+    color3f[] primvars:displayColor = [(0, 0, 1)]
+}
+
+def Mesh "D"
+{
+    int[] faceVertexCounts = [3]
+    int[] faceVertexIndices = [0, 1, 2]
+    point3f[] points = [(0, 0, 3), (1, 0, 3), (0, 1, 3)]
+    color3f[] primvars:displayColor = [(0, 0, 1)]
+}
+"#;
+
+    #[test]
+    fn material_tests() {
+        let stage = UsdStage::parse(MATERIALS_USDA).unwrap();
+        let meshes = stage.meshes_at(0.).unwrap();
+        assert_eq!(4, meshes.len());
+        let materials: Vec<&SharedMaterial> = meshes.iter().map(Mesh::material).collect();
+        assert!(Arc::ptr_eq(materials[0], materials[1]));
+        assert!(Arc::ptr_eq(materials[2], materials[3]));
+        assert!(!Arc::ptr_eq(materials[0], materials[2]));
+        assert_eq!(
+            "Metal { albedo: Spectrum { r: 1.0, g: 0.8, b: 0.3 }, fuzz: 0.2 }",
+            format!("{:?}", materials[0])
+        );
+
+        let unknown = MATERIALS_USDA.replace("</Looks/Gold>", "</Looks/Silver>");
+        assert_eq!(
+            Err(
+                "/Bound/A: material:binding refers to an unknown material </Looks/Silver>"
+                    .to_string()
+            ),
+            UsdStage::parse(&unknown)
+                .unwrap()
+                .meshes_at(0.)
+                .map(|meshes| meshes.len())
+        );
+    }
+
+    #[test]
+    fn xform_op_tests() {
+        let point = Point3 {
+            x: 1.,
+            y: 0.,
+            z: 0.,
+        };
+        let rotation = xform_op_matrix(
+            "xformOp:rotateXYZ",
+            &Value::List(vec![
+                Value::Number(0.),
+                Value::Number(0.),
+                Value::Number(90.),
+            ]),
+        )
+        .unwrap();
+        assert_approx_eq!(
+            Point3 {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            },
+            rotation.transform_point(&point)
+        );
+        let row = |numbers: [Float; 4]| Value::List(numbers.map(Value::Number).to_vec());
+        let matrix = Value::List(vec![
+            row([1., 0., 0., 0.]),
+            row([0., 1., 0., 0.]),
+            row([0., 0., 1., 0.]),
+            row([5., 6., 7., 1.]),
+        ]);
+        let translation = xform_op_matrix("xformOp:transform", &matrix).unwrap();
+        assert_approx_eq!(
+            Point3 {
+                x: 6.,
+                y: 6.,
+                z: 7.,
+            },
+            translation.transform_point(&point)
+        );
+        assert!(xform_op_matrix("xformOp:shear", &matrix).is_err());
+    }
+}
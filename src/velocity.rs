@@ -0,0 +1,147 @@
+use std::io::{self, Write};
+
+use crate::animation::Animation;
+use crate::bvh::TopLevelBvh;
+use crate::geometry::Float;
+use crate::render::RAY_T;
+use crate::scenes::Scene;
+
+/// The type for the motions on the image of the surfaces seen at the pixels from one frame of
+/// an animation to the next, e.g. for compositors to add or remove motion blur.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VelocityMap {
+    pub width: usize,
+    pub height: usize,
+    /// The motion of the surface seen at the center of each pixel in row-major order from the top left,
+    /// in pixels per frame to the right and downward, which is `None` if the pixel sees nothing
+    /// or the surface goes behind the camera.
+    pub velocities: Vec<Option<(Float, Float)>>,
+}
+impl VelocityMap {
+    /// Renders the motions in `scene`, which `animation` has placed as it is at `time`,
+    /// until `frame_duration` later, with a single ray per pixel and without shading.
+    /// Only the objects and the camera moved by `animation` are considered to move.
+    pub fn render(
+        scene: &Scene,
+        animation: &Animation,
+        time: Float,
+        frame_duration: Float,
+        aspect_ratio: Float,
+        width: usize,
+        height: usize,
+    ) -> Self {
+        let next_time = time + frame_duration;
+        let next_camera = animation.camera_at(next_time, aspect_ratio);
+        let next_camera = next_camera.as_ref().unwrap_or(&scene.camera);
+        let bvh = TopLevelBvh::new(&scene.world);
+        let (x_scale, y_scale) = ((width - 1).max(1) as Float, (height - 1).max(1) as Float);
+        let velocities = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                // The centers of the pixels are at the same coordinates as `Renderer::render` samples.
+                let u = x as Float / x_scale;
+                let v = (height - 1 - y) as Float / y_scale;
+                let ray = scene.camera.get_ray(u, v);
+                let mut hit_index = None;
+                let (hit, _) = bvh.hit_members(&ray, &RAY_T, |index, ray_t| {
+                    let pair = scene.world.members[index].hit(&ray, ray_t)?;
+                    // Each hit found is nearer than the previous ones.
+                    hit_index = Some(index);
+                    Some(pair)
+                })?;
+                let point = ray.at(hit.t);
+                let moved = match hit_index.and_then(|index| animation.object(index)) {
+                    Some(object) => {
+                        let object_point = object.transform_at(time).point_to_object(&point);
+                        object.transform_at(next_time).point_to_world(&object_point)
+                    }
+                    None => point,
+                };
+                let (next_u, next_v) = next_camera.project(&moved)?;
+                Some(((next_u - u) * x_scale, (v - next_v) * y_scale))
+            })
+            .collect();
+        VelocityMap {
+            width,
+            height,
+            velocities,
+        }
+    }
+
+    /// Writes the motions in the Portable Float Map format with three channels, the motions to the right
+    /// and downward and zero, where the pixels without motions are 0.
+    // The cast to `f32` is a no-op only if the `f32` feature is enabled.
+    #[allow(clippy::unnecessary_cast)]
+    pub fn write_pfm(&self, out: &mut impl Write) -> io::Result<()> {
+        // The negative scale means little endian.
+        write!(out, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        // The scan lines go from the bottom to the top.
+        for row in self.velocities.chunks(self.width).rev() {
+            for velocity in row {
+                let (dx, dy) = velocity.unwrap_or((0., 0.));
+                for value in [dx, dy, 0.] {
+                    out.write_all(&(value as f32).to_le_bytes())?;
+                }
+            }
+        }
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx::assert_approx_eq;
+    use crate::hittable_object::Hittable;
+    use crate::scenes::{self, SceneParams};
+
+    #[test]
+    fn velocity_map_tests() {
+        // The glass sphere in the middle of `three-spheres` bounces up and down,
+        // while the camera and the ground stay still.
+        let params = SceneParams {
+            seed: 0,
+            density: 1.,
+            ..SceneParams::default()
+        };
+        let animation = scenes::animation_by_name("three-spheres");
+        let (time, frame_duration) = (0.1, 0.1);
+        let scene = animation.apply(
+            scenes::by_name("three-spheres", 1., &params).unwrap(),
+            time,
+            1.,
+        );
+        let (width, height) = (41, 41);
+        let map = VelocityMap::render(&scene, &animation, time, frame_duration, 1., width, height);
+        let velocity_at = |x: usize, y: usize| map.velocities[y * width + x];
+
+        // The point of the sphere seen at the center moves on the image as it rises.
+        let rise = |time: Float| animation.objects[1].transform.value_at(time).translation;
+        let ray = scene.camera.get_ray(0.5, 0.5);
+        let (hit, _) = scene.world.hit(&ray, &RAY_T).unwrap();
+        let point = ray.at(hit.t);
+        let moved = &point + &(&rise(time + frame_duration) - &rise(time));
+        let (_, v) = scene.camera.project(&point).unwrap();
+        let (_, next_v) = scene.camera.project(&moved).unwrap();
+        let (dx, dy) = velocity_at(20, 20).unwrap();
+        assert_approx_eq!(0., dx);
+        assert!(dy < -1., "{}", dy);
+        assert_approx_eq!((v - next_v) * (height - 1) as Float, dy, 1e-3);
+        // The ground at the bottom does not move.
+        let (dx, dy) = velocity_at(20, height - 1).unwrap();
+        assert_approx_eq!(0., dx);
+        assert_approx_eq!(0., dy);
+        // Nothing moves in scenes without animations.
+        let still = VelocityMap::render(&scene, &Animation::default(), time, 0.1, 1., 5, 5);
+        for (dx, dy) in still.velocities.iter().flatten() {
+            assert_approx_eq!(0., *dx);
+            assert_approx_eq!(0., *dy);
+        }
+
+        let mut pfm = vec![];
+        map.write_pfm(&mut pfm).unwrap();
+        let header = format!("PF\n{} {}\n-1.0\n", width, height);
+        assert_eq!(header.as_bytes(), &pfm[..header.len()]);
+        assert_eq!(header.len() + width * height * 3 * 4, pfm.len());
+    }
+}
@@ -0,0 +1,94 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+use try_ray_tracing::error::RenderError;
+use try_ray_tracing::film::Film;
+use try_ray_tracing::tonemap::TransferFunction;
+
+/// The type for videos being encoded by an `ffmpeg` subprocess,
+/// which receives the frames as raw RGB data through its standard input.
+pub struct VideoEncoder {
+    path: PathBuf,
+    child: Child,
+    stdin: ChildStdin,
+}
+impl VideoEncoder {
+    /// Starts encoding a video of `width` x `height` pixels into `path`,
+    /// whose format is determined by the extension (e.g. `.mp4` or `.gif`).
+    pub fn start(path: &Path, width: usize, height: usize, fps: f64) -> Result<Self, RenderError> {
+        let mut command = Command::new("ffmpeg");
+        command
+            .args(["-loglevel", "error", "-y"])
+            .args(["-f", "rawvideo", "-pix_fmt", "rgb24"])
+            .args(["-s", &format!("{}x{}", width, height)])
+            .args(["-framerate", &fps.to_string()])
+            .args(["-i", "-"]);
+        if is_gif(path) {
+            // Generates a palette from the frames themselves for better colors.
+            command.args([
+                "-vf",
+                "split[a][b];[a]palettegen[palette];[b][palette]paletteuse",
+            ]);
+        } else {
+            // Most players support only the 4:2:0 chroma subsampling.
+            command.args(["-pix_fmt", "yuv420p"]);
+        }
+        let mut child = command
+            .arg(path)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| RenderError::Encoding {
+                path: path.to_path_buf(),
+                message: format!("cannot run ffmpeg: {}", e),
+            })?;
+        let stdin = child
+            .stdin
+            .take()
+            .expect("the standard input should be piped");
+        Ok(Self {
+            path: path.to_path_buf(),
+            child,
+            stdin,
+        })
+    }
+
+    /// Adds the frame of `film` encoded by `transfer`.
+    pub fn add_frame(
+        &mut self,
+        film: &Film,
+        transfer: TransferFunction,
+    ) -> Result<(), RenderError> {
+        self.stdin
+            .write_all(&film.to_rgb8(transfer))
+            .map_err(|e| RenderError::Encoding {
+                path: self.path.clone(),
+                message: format!("ffmpeg stopped: {}", e),
+            })
+    }
+
+    /// Waits for `ffmpeg` to finish writing the video.
+    pub fn finish(self) -> Result<(), RenderError> {
+        let Self {
+            path,
+            mut child,
+            stdin,
+        } = self;
+        // Closing the standard input tells ffmpeg that there are no more frames.
+        drop(stdin);
+        let status = child.wait().map_err(|e| RenderError::io(&path, e))?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(RenderError::Encoding {
+                message: format!("ffmpeg failed ({})", status),
+                path,
+            })
+        }
+    }
+}
+
+fn is_gif(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("gif"))
+}
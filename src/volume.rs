@@ -0,0 +1,480 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::error::RenderError;
+use crate::geometry::{random_double, Float, Point3, Ray};
+use crate::hittable_object::{HitRecord, Hittable, SharedMaterial};
+use crate::interval::Interval;
+
+/// The type for densities sampled on regular 3D grids, e.g. of smoke simulations,
+/// which are interpolated trilinearly between the centers of the voxels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DensityGrid {
+    resolution: [usize; 3],
+    /// The densities of the voxels, where x varies fastest and z slowest.
+    densities: Vec<Float>,
+    max_density: Float,
+}
+impl DensityGrid {
+    /// Panics if the number of `densities` is not the product of `resolution`, the grid is empty,
+    /// or some of the densities are negative or not finite.
+    pub fn new(resolution: [usize; 3], densities: Vec<Float>) -> Self {
+        assert!(
+            resolution.iter().all(|&n| n > 0),
+            "a density grid must not be empty"
+        );
+        assert_eq!(
+            resolution.iter().product::<usize>(),
+            densities.len(),
+            "the number of densities"
+        );
+        assert!(
+            densities.iter().all(|d| d.is_finite() && *d >= 0.),
+            "densities must be finite and non-negative"
+        );
+        let max_density = densities.iter().cloned().fold(0., Float::max);
+        DensityGrid {
+            resolution,
+            densities,
+            max_density,
+        }
+    }
+
+    /// Returns the grid whose density at `(x, y, z)` in [0, 1]^3 is `density`,
+    /// sampled at the centers of the voxels.
+    pub fn from_fn(resolution: [usize; 3], density: impl Fn(Float, Float, Float) -> Float) -> Self {
+        let [nx, ny, nz] = resolution;
+        let center = |i: usize, n: usize| (i as Float + 0.5) / n as Float;
+        let mut densities = Vec::with_capacity(nx * ny * nz);
+        for k in 0..nz {
+            for j in 0..ny {
+                for i in 0..nx {
+                    densities.push(density(center(i, nx), center(j, ny), center(k, nz)));
+                }
+            }
+        }
+        Self::new(resolution, densities)
+    }
+
+    pub fn resolution(&self) -> [usize; 3] {
+        self.resolution
+    }
+
+    /// Returns the maximum density, which no interpolated density exceeds.
+    pub fn max_density(&self) -> Float {
+        self.max_density
+    }
+
+    /// Returns the density at `(x, y, z)` in [0, 1]^3, where the grid spans the unit cube,
+    /// and the densities outside the outermost voxel centers are those of the nearest ones.
+    pub fn density(&self, x: Float, y: Float, z: Float) -> Float {
+        // The integer parts of the positions and their fractions along each axis.
+        let mut cells = [(0, 0, 0.); 3];
+        for (axis, position) in [x, y, z].into_iter().enumerate() {
+            let n = self.resolution[axis];
+            let p = (position * n as Float - 0.5).clamp(0., (n - 1) as Float);
+            let i = (p as usize).min(n - 1);
+            cells[axis] = (i, (i + 1).min(n - 1), p - i as Float);
+        }
+        let [(i0, i1, fx), (j0, j1, fy), (k0, k1, fz)] = cells;
+        let [nx, ny, _] = self.resolution;
+        let at = |i: usize, j: usize, k: usize| self.densities[(k * ny + j) * nx + i];
+        let lerp = |a: Float, b: Float, t: Float| a + (b - a) * t;
+        let along_x = |j, k| lerp(at(i0, j, k), at(i1, j, k), fx);
+        let along_y = |k| lerp(along_x(j0, k), along_x(j1, k), fy);
+        lerp(along_y(k0), along_y(k1), fz)
+    }
+
+    /// Returns the estimated number of bytes the grid occupies.
+    pub fn memory_size(&self) -> usize {
+        std::mem::size_of_val(self) + self.densities.capacity() * std::mem::size_of::<Float>()
+    }
+}
+
+/// The type for density grids read from files, placed in the box from `min` to `max`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VolumeFile {
+    pub grid: DensityGrid,
+    pub min: Point3,
+    pub max: Point3,
+}
+impl VolumeFile {
+    /// Loads a grid volume file in the binary format of Mitsuba (`*.vol`).
+    pub fn load(path: &Path) -> Result<Self, RenderError> {
+        let bytes = std::fs::read(path).map_err(|e| RenderError::io(path, e))?;
+        parse_vol(&bytes).map_err(|message| RenderError::SceneParse {
+            path: path.to_path_buf(),
+            message,
+        })
+    }
+}
+
+/// The size of the header of grid volume files before the densities.
+const VOL_HEADER_SIZE: usize = 48;
+
+/// Parses a grid volume file in the binary format of Mitsuba: `VOL` and the version 3,
+/// followed by the little-endian 32-bit integers of the encoding (1 for 32-bit floats),
+/// the resolution along x, y, and z, and the number of channels (which must be 1),
+/// and then the 32-bit floats of the bounding box (the minimum x, y, and z and then the maximum)
+/// and of the densities, where x varies fastest and z slowest.
+pub fn parse_vol(bytes: &[u8]) -> Result<VolumeFile, String> {
+    if bytes.len() < VOL_HEADER_SIZE || &bytes[0..3] != b"VOL" {
+        return Err("not a grid volume file".to_string());
+    }
+    if bytes[3] != 3 {
+        return Err(format!("unsupported version: {}", bytes[3]));
+    }
+    let word = |index: usize| {
+        let offset = 4 + 4 * index;
+        [
+            bytes[offset],
+            bytes[offset + 1],
+            bytes[offset + 2],
+            bytes[offset + 3],
+        ]
+    };
+    let integer = |index: usize| i32::from_le_bytes(word(index));
+    let encoding = integer(0);
+    if encoding != 1 {
+        return Err(format!(
+            "unsupported encoding: {} (only 32-bit floats are supported)",
+            encoding
+        ));
+    }
+    let mut resolution = [0; 3];
+    for (axis, n) in resolution.iter_mut().enumerate() {
+        *n = usize::try_from(integer(1 + axis))
+            .ok()
+            .filter(|&n| n > 0)
+            .ok_or_else(|| format!("invalid resolution: {}", integer(1 + axis)))?;
+    }
+    let num_channels = integer(4);
+    if num_channels != 1 {
+        return Err(format!(
+            "unsupported number of channels: {} (only densities are supported)",
+            num_channels
+        ));
+    }
+    let bounds: Vec<Float> = (5..11)
+        .map(|index| f32::from_le_bytes(word(index)) as Float)
+        .collect();
+    let min = Point3 {
+        x: bounds[0],
+        y: bounds[1],
+        z: bounds[2],
+    };
+    let max = Point3 {
+        x: bounds[3],
+        y: bounds[4],
+        z: bounds[5],
+    };
+    let num_voxels = resolution
+        .iter()
+        .try_fold(1usize, |product, &n| product.checked_mul(n))
+        .ok_or("the resolution is too large")?;
+    let data = &bytes[VOL_HEADER_SIZE..];
+    if data.len() != num_voxels * 4 {
+        return Err(format!(
+            "expected {} densities, but the file has {} bytes of them",
+            num_voxels,
+            data.len()
+        ));
+    }
+    let densities: Vec<Float> = data
+        .chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as Float)
+        .collect();
+    if let Some(density) = densities.iter().find(|d| !(d.is_finite() && **d >= 0.)) {
+        return Err(format!(
+            "densities must be finite and non-negative, but one is {}",
+            density
+        ));
+    }
+    Ok(VolumeFile {
+        grid: DensityGrid::new(resolution, densities),
+        min,
+        max,
+    })
+}
+
+/// The type for participating media whose density varies in space, e.g. clouds and smoke,
+/// filling the axis-aligned box from `min` to `max` over which `grid` is stretched.
+/// The extinction coefficient, i.e., the probability of hitting a particle per unit length,
+/// is `extinction` times the density, and the particles scatter rays by `material`.
+///
+/// Rays are scattered at the distances drawn by delta tracking, which treats the medium as
+/// one of the maximum density with fictitious particles that let rays go through,
+/// so the light through the medium is unbiased however the density varies.
+pub struct HeterogeneousMedium {
+    pub min: Point3,
+    pub max: Point3,
+    pub grid: Arc<DensityGrid>,
+    pub extinction: Float,
+    pub material: SharedMaterial,
+}
+impl HeterogeneousMedium {
+    /// Returns the range of `t` in `ray_t` in which `ray` is in the box.
+    fn clip(&self, ray: &Ray, ray_t: &Interval) -> Option<Interval> {
+        let direction = ray.direction.inject();
+        let mut t = ray_t.clone();
+        for axis in 0..3 {
+            let inverse = 1. / direction[axis];
+            let t0 = (self.min[axis] - ray.origin[axis]) * inverse;
+            let t1 = (self.max[axis] - ray.origin[axis]) * inverse;
+            let (t0, t1) = if inverse < 0. { (t1, t0) } else { (t0, t1) };
+            // `max` and `min` ignore the NaN of rays parallel to the faces.
+            t.min = t.min.max(t0);
+            t.max = t.max.min(t1);
+            if t.max <= t.min {
+                return None;
+            }
+        }
+        Some(t)
+    }
+
+    fn density_at(&self, point: &Point3) -> Float {
+        let size = &self.max - &self.min;
+        let local = point - &self.min;
+        self.grid
+            .density(local.x / size.x, local.y / size.y, local.z / size.z)
+    }
+}
+impl Hittable for HeterogeneousMedium {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<(HitRecord, SharedMaterial)> {
+        let majorant = self.grid.max_density() * self.extinction;
+        if majorant <= 0. {
+            return None;
+        }
+        let inside = self.clip(ray, ray_t)?;
+        let mut t = inside.min;
+        loop {
+            // `random_double` returns numbers in [-0.5, 0.5).
+            t -= (0.5 - random_double()).ln() / majorant;
+            if t >= inside.max {
+                return None;
+            }
+            let extinction = self.density_at(&ray.at(t)) * self.extinction;
+            if random_double() + 0.5 < extinction / majorant {
+                let hit = HitRecord {
+                    t,
+                    // Isotropic particles have no surfaces, so the normal only faces the ray.
+                    surface_normal: (-&ray.direction.inject()).unit_vector(),
+                    u: 0.,
+                    v: 0.,
+                };
+                return Some((hit, self.material.clone()));
+            }
+        }
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if !(self.min.is_finite() && self.max.is_finite()) {
+            problems.push(format!(
+                "bounds must be finite, but are {:?} and {:?}",
+                self.min, self.max
+            ));
+        } else if !(self.min.x < self.max.x && self.min.y < self.max.y && self.min.z < self.max.z) {
+            problems.push(format!(
+                "bounds must span a non-empty box, but are {:?} and {:?}",
+                self.min, self.max
+            ));
+        }
+        if !(self.extinction.is_finite() && self.extinction >= 0.) {
+            problems.push(format!(
+                "extinction must be non-negative, but is {}",
+                self.extinction
+            ));
+        }
+        problems.extend(self.material.validate());
+        problems
+    }
+
+    fn bounding_box(&self) -> Option<(Point3, Point3)> {
+        Some((self.min.clone(), self.max.clone()))
+    }
+
+    fn memory_size(&self) -> usize {
+        let grid_size = self.grid.memory_size() / Arc::strong_count(&self.grid);
+        let material_size = self.material.memory_size() / Arc::strong_count(&self.material);
+        std::mem::size_of_val(self) + grid_size + material_size
+    }
+}
+
+/// Returns the bytes of a grid volume file of `grid` placed from `min` to `max`, which `parse_vol` reads.
+// The casts to `f32` are no-ops only if the `f32` feature is enabled.
+#[allow(clippy::unnecessary_cast)]
+pub fn write_vol(grid: &DensityGrid, min: &Point3, max: &Point3) -> Vec<u8> {
+    let mut bytes = b"VOL\x03".to_vec();
+    let integers = [
+        1,
+        grid.resolution[0],
+        grid.resolution[1],
+        grid.resolution[2],
+        1,
+    ];
+    for integer in integers {
+        bytes.extend((integer as i32).to_le_bytes());
+    }
+    let floats = [min.x, min.y, min.z, max.x, max.y, max.z]
+        .into_iter()
+        .chain(grid.densities.iter().cloned());
+    for float in floats {
+        bytes.extend((float as f32).to_le_bytes());
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx::assert_approx_eq;
+    use crate::color::Attenuation;
+    use crate::geometry::{seed_random, Vec3};
+    use crate::hittable_object::Isotropic;
+
+    #[test]
+    fn density_grid_tests() {
+        let grid = DensityGrid::new([2, 1, 1], vec![1., 3.]);
+        assert_eq!(3., grid.max_density());
+        // The densities are those of the voxels at their centers and interpolated between them.
+        assert_approx_eq!(1., grid.density(0.25, 0.5, 0.5));
+        assert_approx_eq!(2., grid.density(0.5, 0.5, 0.5));
+        assert_approx_eq!(3., grid.density(0.75, 0.9, 0.1));
+        // Outside the centers, the densities of the nearest voxels are taken.
+        assert_approx_eq!(1., grid.density(0., 0.5, 0.5));
+        assert_approx_eq!(3., grid.density(1., 0.5, 0.5));
+
+        let grid = DensityGrid::from_fn([4, 3, 2], |x, y, z| x + 10. * y + 100. * z);
+        assert_eq!([4, 3, 2], grid.resolution());
+        assert_approx_eq!(0.125 + 10. / 6. + 25., grid.density(0.125, 1. / 6., 0.25));
+        // Trilinear interpolation reproduces linear functions between the centers.
+        assert_approx_eq!(0.4 + 5. + 50., grid.density(0.4, 0.5, 0.5));
+    }
+
+    #[test]
+    fn parse_vol_tests() {
+        let grid = DensityGrid::from_fn([3, 2, 2], |x, y, z| x * y + z);
+        let min = Point3 {
+            x: -1.,
+            y: 0.,
+            z: 2.,
+        };
+        let max = Point3 {
+            x: 1.,
+            y: 0.5,
+            z: 3.,
+        };
+        let bytes = write_vol(&grid, &min, &max);
+        let file = parse_vol(&bytes).unwrap();
+        assert_eq!([3, 2, 2], file.grid.resolution());
+        assert_eq!((min.clone(), max.clone()), (file.min, file.max));
+        assert_approx_eq!(
+            grid.density(0.3, 0.6, 0.7),
+            file.grid.density(0.3, 0.6, 0.7),
+            1e-6
+        );
+
+        assert_eq!(
+            Err("not a grid volume file".to_string()),
+            parse_vol(b"PNG").map(|_| ())
+        );
+        let mut truncated = bytes.clone();
+        truncated.truncate(bytes.len() - 4);
+        assert_eq!(
+            Err("expected 12 densities, but the file has 44 bytes of them".to_string()),
+            parse_vol(&truncated).map(|_| ())
+        );
+        let mut multichannel = bytes.clone();
+        multichannel[20] = 3;
+        assert!(parse_vol(&multichannel).is_err());
+        let mut negative = bytes;
+        let last = negative.len() - 4;
+        negative[last..].copy_from_slice(&(-1f32).to_le_bytes());
+        assert_eq!(
+            Err("densities must be finite and non-negative, but one is -1".to_string()),
+            parse_vol(&negative).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn heterogeneous_medium_tests() {
+        // Along x, the density rises linearly from 0 to 2 across the unit cube.
+        let grid = DensityGrid::from_fn([16, 1, 1], |x, _, _| 2. * x);
+        let medium = HeterogeneousMedium {
+            min: Point3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            max: Point3 {
+                x: 1.,
+                y: 1.,
+                z: 1.,
+            },
+            grid: Arc::new(grid),
+            extinction: 1.,
+            material: Arc::new(Isotropic {
+                albedo: Attenuation {
+                    r: 0.5,
+                    g: 0.5,
+                    b: 0.5,
+                },
+            }),
+        };
+        assert!(medium.validate().is_empty());
+        let ray_t = Interval {
+            min: 0.,
+            max: Float::INFINITY,
+        };
+        let ray = Ray {
+            origin: Point3 {
+                x: -1.,
+                y: 0.5,
+                z: 0.5,
+            },
+            direction: Vec3 {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            }
+            .unit_vector(),
+        };
+        seed_random(0);
+        let num_rays = 20000;
+        let mut num_through = 0;
+        let mut num_in_first_half = 0;
+        for _ in 0..num_rays {
+            match medium.hit(&ray, &ray_t) {
+                None => num_through += 1,
+                Some((hit, _)) => {
+                    assert!((1. ..2.).contains(&hit.t), "{}", hit.t);
+                    if hit.t < 1.5 {
+                        num_in_first_half += 1;
+                    }
+                }
+            }
+        }
+        // The optical depth across the cube is the integral of the density, which is about 1,
+        // while the outermost voxels keep their densities to the faces.
+        let optical_depth = 1.;
+        let transmittance = num_through as Float / num_rays as Float;
+        assert_approx_eq!((-optical_depth as Float).exp(), transmittance, 0.03);
+        // Only a quarter of the optical depth is in the first half, where fewer rays are scattered.
+        let first_half = num_in_first_half as Float / num_rays as Float;
+        assert_approx_eq!(1. - (-0.25 as Float).exp(), first_half, 0.03);
+
+        // Rays missing the box or ending before it go through.
+        let missing = Ray {
+            origin: Point3 {
+                x: -1.,
+                y: 2.,
+                z: 0.5,
+            },
+            direction: ray.direction.clone(),
+        };
+        assert!(medium.hit(&missing, &ray_t).is_none());
+        let short = Interval { min: 0., max: 1. };
+        assert!(medium.hit(&ray, &short).is_none());
+    }
+}